@@ -6,8 +6,13 @@ use bevy::{
 };
 use bevy_atmosphere::prelude::*;
 use bevy_terrain::{debug::DebugTerrain, prelude::*};
-use std::{f32::consts::TAU, time::Instant};
-use terrain_settings::load_settings;
+use std::{f32::consts::TAU, path::Path};
+use terrain_settings::Settings;
+use wizard::{AppState, SetupWizard, SetupWizardPlugin};
+
+mod wizard;
+
+const WINDOW_TITLE: &str = "Saxony Terrain Renderer";
 
 const TERRAIN_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 24380770943559);
@@ -35,7 +40,7 @@ impl Plugin for AppPlugin {
                 width: 1920.,
                 height: 1080.,
                 // position: WindowPosition::At(Vec2::new(3600.0, 220.0)),
-                title: "Saxony Terrain Renderer".into(),
+                title: WINDOW_TITLE.into(),
                 present_mode: PresentMode::AutoVsync,
                 ..default()
             },
@@ -48,14 +53,16 @@ impl Plugin for AppPlugin {
         })
         .insert_resource(AtmosphereSettings { resolution: 64 })
         .add_plugin(AtmospherePlugin {})
-        .add_plugin(TerrainPlugin {
-            attachment_count: 3,
-        })
+        .add_plugin(TerrainPlugin)
         .add_plugin(TerrainDebugPlugin)
         .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
+        .add_plugin(SetupWizardPlugin)
         .add_startup_system(setup)
-        .add_system(daylight_cycle)
-        .add_system(toggle_camera_and_height_data);
+        .add_system_set(SystemSet::on_enter(AppState::Running).with_system(spawn_terrain))
+        .add_system_set(
+            SystemSet::on_update(AppState::Running).with_system(toggle_camera_and_height_data),
+        )
+        .add_system(daylight_cycle);
 
         app.world.resource_mut::<Assets<_>>().set_untracked(
             TERRAIN_SHADER,
@@ -67,14 +74,18 @@ impl Plugin for AppPlugin {
     }
 }
 
-fn setup(
-    mut commands: Commands,
-    mut materials: ResMut<Assets<TerrainMaterial>>,
-    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
-    mut terrain_view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
-) {
-    let settings = load_settings().unwrap();
+/// Returns the path of the source data of the attachment.
+/// Relative terrain paths are resolved inside the assets directory.
+fn source_path(terrain_path: &str, name: &str) -> String {
+    if Path::new(terrain_path).is_absolute() {
+        format!("{terrain_path}/source/{name}")
+    } else {
+        format!("assets/{terrain_path}/source/{name}")
+    }
+}
 
+/// Configures the attachments of the terrain described by the settings.
+fn terrain_config(settings: &Settings) -> (TerrainConfig, Preprocessor, AttachmentFromDiskLoader) {
     let mut preprocessor = Preprocessor::default();
     let mut loader = AttachmentFromDiskLoader::default();
 
@@ -97,9 +108,15 @@ fn setup(
             AttachmentFormat::R16,
         ),
         TileConfig {
-            path: format!("{}/source/dtm", &settings.terrain_path),
+            path: source_path(&settings.terrain_path, "dtm"),
             size: settings.tile_size,
             file_format: FileFormat::DTM,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
         },
     );
     if settings.enable_dsm {
@@ -114,9 +131,15 @@ fn setup(
                 AttachmentFormat::R16,
             ),
             TileConfig {
-                path: format!("{}/source/dsm", &settings.terrain_path),
+                path: source_path(&settings.terrain_path, "dsm"),
                 size: settings.tile_size,
                 file_format: FileFormat::DTM,
+                detail: None,
+                water: None,
+                void_fill: None,
+                georeference: None,
+                sources: vec![],
+                feather_width: 0,
             },
         );
     } else {
@@ -133,9 +156,15 @@ fn setup(
                 AttachmentFormat::R16,
             ),
             TileConfig {
-                path: format!("{}/source/dtm", &settings.terrain_path),
+                path: source_path(&settings.terrain_path, "dtm"),
                 size: settings.tile_size,
                 file_format: FileFormat::DTM,
+                detail: None,
+                water: None,
+                void_fill: None,
+                georeference: None,
+                sources: vec![],
+                feather_width: 0,
             },
         );
     }
@@ -151,21 +180,49 @@ fn setup(
             AttachmentFormat::Rgb8,
         ),
         TileConfig {
-            path: format!("{}/source/dop", &settings.terrain_path),
+            path: source_path(&settings.terrain_path, "dop"),
             size: settings.tile_size,
             file_format: FileFormat::QOI,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
         },
     );
 
-    if settings.preprocess {
-        println!("Started preprocessing the terrain data. This might take a while ...");
-        let start = Instant::now();
-        preprocessor.preprocess(&config);
-        let duration = start.elapsed();
-        println!("Time elapsed during preprocessing is: {:?}.", duration);
-    }
+    (config, preprocessor, loader)
+}
 
-    load_node_config(&mut config);
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: 15000.0,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 4.0, -1.0).looking_at(Vec3::ZERO, Vec3::Y),
+            ..default()
+        },
+        Sun::default(),
+    ));
+    commands.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    });
+}
+
+fn spawn_terrain(
+    mut commands: Commands,
+    mut wizard: ResMut<SetupWizard>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut terrain_view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let (settings, mut config, loader) = wizard.take_terrain();
+
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
 
     let terrain = commands
         .spawn((
@@ -201,22 +258,6 @@ fn setup(
 
     terrain_view_configs.insert((terrain, view), view_config);
     quadtrees.insert((terrain, view), quadtree);
-
-    commands.spawn((
-        DirectionalLightBundle {
-            directional_light: DirectionalLight {
-                illuminance: 15000.0,
-                ..default()
-            },
-            transform: Transform::from_xyz(0.0, 4.0, -1.0).looking_at(Vec3::ZERO, Vec3::Y),
-            ..default()
-        },
-        Sun::default(),
-    ));
-    commands.insert_resource(AmbientLight {
-        brightness: 0.2,
-        ..default()
-    });
 }
 
 #[derive(Component)]
@@ -241,6 +282,7 @@ impl Default for Sun {
 fn daylight_cycle(
     input: Res<Input<KeyCode>>,
     mut atmosphere: ResMut<Atmosphere>,
+    mut terrain_atmosphere: ResMut<TerrainAtmosphere>,
     mut query: Query<(&mut Transform, &mut DirectionalLight, &mut Sun)>,
     time: Res<Time>,
 ) {
@@ -271,6 +313,7 @@ fn daylight_cycle(
     if sun.rotating {
         sun.angle += TAU * time.delta_seconds() / sun.period_duration;
         atmosphere.sun_position = Vec3::new(sun.angle.cos(), sun.angle.sin(), 0.0);
+        terrain_atmosphere.sun_direction = atmosphere.sun_position;
 
         *transform = Transform::from_xyz(-sun.angle.cos(), sun.angle.sin(), 0.0)
             .looking_at(Vec3::ZERO, Vec3::Y);
@@ -283,7 +326,12 @@ fn toggle_camera_and_height_data(
     mut camera_query: Query<&mut DebugCamera>,
     mut debug: ResMut<DebugTerrain>,
 ) {
-    let mut camera = camera_query.single_mut();
+    // the camera is spawned together with the terrain
+    let mut camera = match camera_query.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+
     if input.just_pressed(KeyCode::T) {
         camera.active = !camera.active;
         println!(
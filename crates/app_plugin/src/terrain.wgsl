@@ -28,8 +28,16 @@ struct TerrainConfig {
 var<uniform> view_config: TerrainViewConfig;
 @group(1) @binding(1)
 var quadtree: texture_2d_array<u32>;
+#ifndef DOWNLEVEL
 @group(1) @binding(2)
 var<storage> tiles: TileList;
+#endif
+@group(1) @binding(8)
+var color_ramp_texture: texture_2d<f32>;
+@group(1) @binding(9)
+var color_ramp_sampler: sampler;
+@group(1) @binding(10)
+var<uniform> atmosphere: TerrainAtmosphere;
 
 // terrain bindings
 @group(2) @binding(0)
@@ -63,6 +71,7 @@ var albedo_atlas: texture_2d_array<f32>;
 #import bevy_terrain::node
 #import bevy_terrain::functions
 #import bevy_terrain::debug
+#import bevy_terrain::atmosphere
 
 struct FragmentData {
     world_normal: vec3<f32>,
@@ -82,7 +91,7 @@ fn vertex_height(lookup: NodeLookup) -> f32 {
     }
 #endif
 
-    return height * config.height;
+    return height * config.height + view_config.elevation_offset;
 }
 
 fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>, ddy: vec2<f32>) -> FragmentData {
@@ -153,6 +162,9 @@ fn process_fragment(input: FragmentInput, data: FragmentData) -> Fragment {
     color = tone_mapping(pbr(pbr_input));
 #endif
 
+    color = apply_atmosphere(color, input.world_position);
+    color = show_analysis(color, data.world_normal, (input.world_position.y - view_config.elevation_offset) / config.height);
+
     return Fragment(color, false);
 }
 
@@ -0,0 +1,286 @@
+//! A small startup state machine, which lets the user pick the terrain to be rendered.
+//!
+//! All datasets inside `assets/terrains/*` are scanned and listed in the console.
+//! The selected dataset is preprocessed if necessary, before the terrain is spawned.
+//! If no dataset is found, the terrain configured in the `config.toml` is used instead.
+
+use crate::{terrain_config, WINDOW_TITLE};
+use bevy::{app::AppExit, prelude::*};
+use bevy_terrain::prelude::*;
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+use terrain_settings::{load_dataset_settings, load_settings, Settings};
+
+/// The directory inside the assets, which is scanned for datasets.
+const TERRAIN_DIRECTORY: &str = "terrains";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum AppState {
+    /// Searches for available datasets.
+    Scanning,
+    /// Waits for the user to select a dataset.
+    Selecting,
+    /// Preprocesses the selected dataset, if necessary.
+    Preprocessing,
+    /// Renders the selected terrain.
+    Running,
+}
+
+/// A dataset found inside the terrain directory.
+struct TerrainDataset {
+    name: String,
+    preprocessed: bool,
+}
+
+/// The preprocessing running in the background.
+struct PreprocessingTask {
+    step: Arc<AtomicUsize>,
+    step_count: usize,
+    handle: JoinHandle<Result<(), TerrainError>>,
+    start: Instant,
+}
+
+/// The state of the setup, which is shared between the steps of the wizard.
+#[derive(Resource, Default)]
+pub(crate) struct SetupWizard {
+    datasets: Vec<TerrainDataset>,
+    selected: usize,
+    /// Whether the selected dataset is preprocessed, even if it has been preprocessed already.
+    force_preprocess: bool,
+    settings: Option<Settings>,
+    terrain: Option<(TerrainConfig, AttachmentFromDiskLoader)>,
+    task: Option<PreprocessingTask>,
+}
+
+impl SetupWizard {
+    /// Takes the settings, config and loader of the prepared terrain.
+    pub(crate) fn take_terrain(&mut self) -> (Settings, TerrainConfig, AttachmentFromDiskLoader) {
+        let settings = self.settings.take().unwrap();
+        let (config, loader) = self.terrain.take().unwrap();
+
+        (settings, config, loader)
+    }
+
+    fn print_datasets(&self) {
+        println!("Select the terrain using the up and down keys and confirm with enter.");
+        println!("Press backspace instead, to preprocess the selected terrain again.");
+
+        for (index, dataset) in self.datasets.iter().enumerate() {
+            println!(
+                "{} {}{}",
+                if index == self.selected { ">" } else { " " },
+                dataset.name,
+                if dataset.preprocessed {
+                    ""
+                } else {
+                    " (not preprocessed)"
+                }
+            );
+        }
+    }
+}
+
+pub(crate) struct SetupWizardPlugin;
+
+impl Plugin for SetupWizardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SetupWizard>()
+            .add_state(AppState::Scanning)
+            .add_system_set(SystemSet::on_enter(AppState::Scanning).with_system(scan_datasets))
+            .add_system_set(SystemSet::on_update(AppState::Selecting).with_system(select_dataset))
+            .add_system_set(
+                SystemSet::on_enter(AppState::Preprocessing).with_system(start_preprocessing),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Preprocessing).with_system(update_preprocessing),
+            );
+    }
+}
+
+/// Returns whether the terrain at the path has been preprocessed already.
+/// Relative terrain paths are resolved inside the assets directory.
+fn is_preprocessed(terrain_path: &str) -> bool {
+    if Path::new(terrain_path).is_absolute() {
+        Path::new(&format!("{terrain_path}/data/config.tc")).exists()
+    } else {
+        Path::new(&format!("assets/{terrain_path}/data/config.tc")).exists()
+    }
+}
+
+/// Collects all datasets, which are configured in the `config.toml` and
+/// either have been preprocessed already or contain the source data.
+fn scan_datasets(mut wizard: ResMut<SetupWizard>, mut state: ResMut<State<AppState>>) {
+    let directory = format!("assets/{TERRAIN_DIRECTORY}");
+
+    let mut datasets: Vec<TerrainDataset> = fs::read_dir(&directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            // the dataset has to be configured, in order to know its dimensions
+            load_dataset_settings(TERRAIN_DIRECTORY, &name).ok()?;
+
+            let preprocessed = is_preprocessed(&format!("{TERRAIN_DIRECTORY}/{name}"));
+            let source = entry.path().join("source/dtm").exists();
+
+            (preprocessed || source).then_some(TerrainDataset { name, preprocessed })
+        })
+        .collect();
+
+    datasets.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if datasets.is_empty() {
+        println!(
+            "Found no datasets in {directory}, using the terrain configured in the config.toml."
+        );
+
+        let settings = load_settings().unwrap();
+        wizard.force_preprocess = settings.preprocess;
+        wizard.settings = Some(settings);
+
+        state.set(AppState::Preprocessing).unwrap();
+        return;
+    }
+
+    wizard.datasets = datasets;
+    wizard.print_datasets();
+
+    state.set(AppState::Selecting).unwrap();
+}
+
+fn select_dataset(
+    input: Res<Input<KeyCode>>,
+    mut wizard: ResMut<SetupWizard>,
+    mut state: ResMut<State<AppState>>,
+) {
+    let count = wizard.datasets.len();
+
+    if count == 0 {
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Up) {
+        wizard.selected = (wizard.selected + count - 1) % count;
+        wizard.print_datasets();
+    }
+    if input.just_pressed(KeyCode::Down) {
+        wizard.selected = (wizard.selected + 1) % count;
+        wizard.print_datasets();
+    }
+
+    let confirm = input.just_pressed(KeyCode::Return);
+    let force_preprocess = input.just_pressed(KeyCode::Back);
+
+    if confirm || force_preprocess {
+        let name = wizard.datasets[wizard.selected].name.clone();
+
+        match load_dataset_settings(TERRAIN_DIRECTORY, &name) {
+            Ok(settings) => {
+                wizard.settings = Some(settings);
+                wizard.force_preprocess = force_preprocess;
+                state.set(AppState::Preprocessing).unwrap();
+            }
+            Err(error) => println!("Could not load the settings of {name}: {error}"),
+        }
+    }
+}
+
+/// Configures the selected terrain and starts preprocessing it in the background, if necessary.
+fn start_preprocessing(mut wizard: ResMut<SetupWizard>, mut state: ResMut<State<AppState>>) {
+    let settings = wizard.settings.as_ref().unwrap();
+    let (config, preprocessor, loader) = terrain_config(settings);
+
+    if wizard.force_preprocess || !is_preprocessed(&settings.terrain_path) {
+        println!("Started preprocessing the terrain data. This might take a while ...");
+
+        let step = Arc::new(AtomicUsize::new(0));
+        let step_count = preprocessor.step_count();
+
+        let handle = {
+            let step = step.clone();
+            let config = config.clone();
+
+            thread::spawn(move || {
+                preprocessor.preprocess_with_progress(&config, |finished| {
+                    step.store(finished, Ordering::Relaxed)
+                })
+            })
+        };
+
+        wizard.task = Some(PreprocessingTask {
+            step,
+            step_count,
+            handle,
+            start: Instant::now(),
+        });
+    } else {
+        state.set(AppState::Running).unwrap();
+    }
+
+    wizard.terrain = Some((config, loader));
+}
+
+/// Displays the progress of the preprocessing and continues, once it has finished.
+fn update_preprocessing(
+    mut wizard: ResMut<SetupWizard>,
+    mut state: ResMut<State<AppState>>,
+    mut windows: ResMut<Windows>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let window = windows.primary_mut();
+
+    let finished = match &wizard.task {
+        Some(task) => {
+            window.set_title(format!(
+                "{WINDOW_TITLE} - Preprocessing ({}/{})",
+                task.step.load(Ordering::Relaxed),
+                task.step_count
+            ));
+
+            task.handle.is_finished()
+        }
+        None => return,
+    };
+
+    if finished {
+        let task = wizard.task.take().unwrap();
+
+        match task.handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => {
+                println!("Failed to preprocess the terrain data: {error}");
+                window.set_title(WINDOW_TITLE.to_string());
+                wizard.terrain = None;
+
+                // the terrain of the config.toml is used, if there is no dataset to select instead
+                if wizard.datasets.is_empty() {
+                    exit.send(AppExit);
+                } else {
+                    state.set(AppState::Selecting).unwrap();
+                }
+
+                return;
+            }
+            Err(_) => panic!("Failed to preprocess the terrain data."),
+        }
+
+        println!(
+            "Time elapsed during preprocessing is: {:?}.",
+            task.start.elapsed()
+        );
+
+        window.set_title(WINDOW_TITLE.to_string());
+        state.set(AppState::Running).unwrap();
+    }
+}
@@ -30,8 +30,16 @@ struct TerrainConfig {
 var<uniform> view_config: TerrainViewConfig;
 @group(1) @binding(1)
 var quadtree: texture_2d_array<u32>;
+#ifndef DOWNLEVEL
 @group(1) @binding(2)
 var<storage> tiles: TileList;
+#endif
+@group(1) @binding(8)
+var color_ramp_texture: texture_2d<f32>;
+@group(1) @binding(9)
+var color_ramp_sampler: sampler;
+@group(1) @binding(10)
+var<uniform> atmosphere: TerrainAtmosphere;
 
 // terrain bindings
 @group(2) @binding(0)
@@ -46,6 +54,9 @@ var minmax_atlas: texture_2d_array<f32>;
 #ifdef ALBEDO
 @group(2) @binding(4)
 var albedo_atlas: texture_2d_array<f32>;
+// The sampler of each attachment is bound nine slots after its texture.
+@group(2) @binding(13)
+var albedo_sampler: sampler;
 #endif
 
 // Customize your material data here.
@@ -53,6 +64,10 @@ var albedo_atlas: texture_2d_array<f32>;
 var array_texture: texture_2d_array<f32>;
 @group(3) @binding(1)
 var array_sampler: sampler;
+@group(3) @binding(2)
+var snow_cover: texture_2d<f32>;
+@group(3) @binding(3)
+var snow_sampler: sampler;
 
 #import bevy_pbr::mesh_types
 #import bevy_pbr::pbr_types
@@ -65,6 +80,7 @@ var array_sampler: sampler;
 #import bevy_terrain::node
 #import bevy_terrain::functions
 #import bevy_terrain::debug
+#import bevy_terrain::atmosphere
 
 // The terrain data required by your `fragment_color` function.
 // This data will be fetched from the atlases by means of the `AtlasLookup`.
@@ -96,10 +112,10 @@ fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>
 
 #ifdef ALBEDO
 #ifdef SAMPLE_GRAD
-    var color = textureSampleGrad(albedo_atlas, atlas_sampler, albedo_coords, atlas_index, albedo_ddx, albedo_ddy);
+    var color = textureSampleGrad(albedo_atlas, albedo_sampler, albedo_coords, atlas_index, albedo_ddx, albedo_ddy);
 #else
-    var color = textureSample(albedo_atlas, atlas_sampler, albedo_coords, atlas_index);
-    // var color = textureSampleLevel(albedo_atlas, atlas_sampler, albedo_coords, atlas_index, 0.0);
+    var color = textureSample(albedo_atlas, albedo_sampler, albedo_coords, atlas_index);
+    // var color = textureSampleLevel(albedo_atlas, albedo_sampler, albedo_coords, atlas_index, 0.0);
 #endif
 
 #else
@@ -149,6 +165,10 @@ fn process_fragment(in: FragmentInput, data: FragmentData) -> Fragment {
     color = mix(mix(sand, rock, slope_weight), snow, height_weight);
 #endif
 
+    // Blend in the simulated snow cover.
+    let cover = textureSample(snow_cover, snow_sampler, in.local_position / f32(config.terrain_size)).x;
+    color = mix(color, vec4<f32>(0.95, 0.95, 1.0, 1.0), cover);
+
 #ifdef LIGHTING
     // Finally assemble the pbr input and calculate the lighting.
     var pbr_input: PbrInput = pbr_input_new();
@@ -165,6 +185,9 @@ fn process_fragment(in: FragmentInput, data: FragmentData) -> Fragment {
     color = tone_mapping(pbr(pbr_input));
 #endif
 
+    color = apply_atmosphere(color, in.world_position);
+    color = show_analysis(color, world_normal, (in.world_position.y - view_config.elevation_offset) / config.height);
+
     return Fragment(color, false);
 }
 
@@ -20,6 +20,9 @@ pub struct TerrainMaterial {
     #[texture(0, dimension = "2d_array")]
     #[sampler(1)]
     array_texture: Handle<Image>,
+    #[texture(2)]
+    #[sampler(3)]
+    snow_cover: Handle<Image>,
 }
 
 impl Material for TerrainMaterial {
@@ -34,20 +37,20 @@ fn main() {
             watch_for_changes: true, // enable hot reloading for shader easy customization
             ..default()
         }))
-        .add_plugin(TerrainPlugin {
-            attachment_count: 3, // has to match the attachments of the terrain
-        })
+        .add_plugin(TerrainPlugin)
         .add_plugin(TerrainDebugPlugin)
         .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
         .add_system(create_array_texture)
         .add_startup_system(setup)
         .add_system(toggle_camera)
+        .add_system(toggle_snowfall)
         .run();
 }
 
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
     mut materials: ResMut<Assets<TerrainMaterial>>,
     mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
     mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
@@ -78,6 +81,12 @@ fn setup(
             path: "assets/terrain/source/height".to_string(),
             size: TERRAIN_SIZE,
             file_format: FileFormat::PNG,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
         },
     );
 
@@ -95,14 +104,26 @@ fn setup(
             path: "assets/terrain/source/albedo.png".to_string(),
             size: TERRAIN_SIZE,
             file_format: FileFormat::PNG,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
         },
     );
 
     // Preprocesses the terrain data.
     // Todo: Should be commented out after the first run.
-    preprocessor.preprocess(&config);
+    preprocessor
+        .preprocess(&config)
+        .expect("Could not preprocess the terrain.");
+
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
 
-    load_node_config(&mut config);
+    // Simulate the snow cover of the upper half of the terrain.
+    let mut snow = SnowSimulation::new(&mut images, 256);
+    snow.snow_line = HEIGHT / 2.0;
 
     // Create the terrain.
     let terrain = commands
@@ -111,7 +132,9 @@ fn setup(
             loader,
             materials.add(TerrainMaterial {
                 array_texture: texture,
+                snow_cover: snow.image.clone(),
             }),
+            snow,
         ))
         .id();
 
@@ -162,6 +185,21 @@ fn toggle_camera(input: Res<Input<KeyCode>>, mut camera_query: Query<&mut DebugC
     }
 }
 
+fn toggle_snowfall(input: Res<Input<KeyCode>>, mut snow_query: Query<&mut SnowSimulation>) {
+    let mut snow = snow_query.single_mut();
+    if input.just_pressed(KeyCode::N) {
+        snow.weather = if snow.weather > 0.0 { -1.0 } else { 1.0 };
+        println!(
+            "{} the snow.",
+            if snow.weather > 0.0 {
+                "Started"
+            } else {
+                "Melting"
+            }
+        );
+    }
+}
+
 #[derive(Resource)]
 struct LoadingTexture {
     is_loaded: bool,
@@ -18,9 +18,7 @@ impl Material for TerrainMaterial {}
 fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
-        .add_plugin(TerrainPlugin {
-            attachment_count: 2, // has to match the attachments of the terrain
-        })
+        .add_plugin(TerrainPlugin)
         .add_plugin(TerrainDebugPlugin) // enable debug settings and controls
         .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
         .add_startup_system(setup)
@@ -54,14 +52,22 @@ fn setup(
             path: "assets/terrain/source/height".to_string(),
             size: TERRAIN_SIZE,
             file_format: FileFormat::PNG,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
         },
     );
 
     // Preprocesses the terrain data.
     // Todo: Should be commented out after the first run.
-    preprocessor.preprocess(&config);
+    preprocessor
+        .preprocess(&config)
+        .expect("Could not preprocess the terrain.");
 
-    load_node_config(&mut config);
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
 
     // Create the terrain.
     let terrain = commands
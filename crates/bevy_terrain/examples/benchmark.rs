@@ -0,0 +1,138 @@
+use bevy::{
+    app::ScheduleRunnerPlugin, prelude::*, reflect::TypeUuid, render::render_resource::*,
+    window::WindowPlugin, winit::WinitPlugin,
+};
+use bevy_terrain::prelude::*;
+
+const TERRAIN_SIZE: u32 = 1024;
+const TEXTURE_SIZE: u32 = 512;
+const MIP_LEVEL_COUNT: u32 = 1;
+const LOD_COUNT: u32 = 4;
+const HEIGHT: f32 = 200.0;
+const NODE_ATLAS_SIZE: u32 = 100;
+const PATH: &str = "terrain";
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "003e1d5d-241c-45a6-8c25-731dee22d820"]
+pub struct TerrainMaterial {}
+
+impl Material for TerrainMaterial {}
+
+/// Whether or not the benchmark renders into an image instead of a window.
+#[derive(Resource)]
+struct Headless(bool);
+
+// Run with `cargo run --release --example benchmark -- --headless` to benchmark without a window.
+fn main() {
+    let headless = std::env::args().any(|arg| arg == "--headless");
+
+    let mut app = App::new();
+
+    if headless {
+        app.add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    add_primary_window: false,
+                    exit_on_all_closed: false,
+                    ..default()
+                })
+                .disable::<WinitPlugin>(),
+        )
+        .add_plugin(ScheduleRunnerPlugin);
+    } else {
+        app.add_plugins(DefaultPlugins);
+    }
+
+    app.insert_resource(Headless(headless))
+        .add_plugin(TerrainPlugin)
+        .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
+        .add_plugin(TerrainBenchmarkPlugin {
+            camera_path: "assets/benchmark/camera_path.ron".to_string(),
+            output: "target/benchmark.csv".to_string(),
+            ..default()
+        })
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    headless: Res<Headless>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let mut preprocessor = Preprocessor::default();
+    let mut loader = AttachmentFromDiskLoader::default();
+
+    let mut config = TerrainConfig::new(
+        TERRAIN_SIZE,
+        LOD_COUNT,
+        HEIGHT,
+        NODE_ATLAS_SIZE,
+        PATH.to_string(),
+    );
+
+    config.add_base_attachment_from_disk(
+        &mut preprocessor,
+        &mut loader,
+        BaseConfig::new(TEXTURE_SIZE, MIP_LEVEL_COUNT),
+        TileConfig {
+            path: "assets/terrain/source/height".to_string(),
+            size: TERRAIN_SIZE,
+            file_format: FileFormat::PNG,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
+        },
+    );
+
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
+
+    let terrain = commands
+        .spawn((
+            TerrainBundle::new(config.clone()),
+            loader,
+            materials.add(TerrainMaterial {}),
+        ))
+        .id();
+
+    // Keep these settings fixed, while comparing code changes.
+    let view_config = TerrainViewConfig {
+        tile_scale: 4.0,
+        grid_size: 4,
+        node_count: 10,
+        load_distance: 5.0,
+        view_distance: 4.0,
+        ..default()
+    };
+
+    let mut camera = Camera3dBundle::default();
+
+    if headless.0 {
+        camera.camera.target = headless_render_target(&mut images, 1920, 1080);
+    }
+
+    let view = commands.spawn((TerrainView, BenchmarkCamera, camera)).id();
+
+    let quadtree = Quadtree::from_configs(&config, &view_config);
+    view_configs.insert((terrain, view), view_config);
+    quadtrees.insert((terrain, view), quadtree);
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    });
+}
@@ -0,0 +1,254 @@
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{camera::Viewport, render_resource::*},
+    window::WindowResized,
+};
+use bevy_terrain::prelude::*;
+
+const TERRAIN_SIZE: u32 = 1024;
+const TEXTURE_SIZE: u32 = 512;
+const MIP_LEVEL_COUNT: u32 = 1;
+const LOD_COUNT: u32 = 4;
+const HEIGHT: f32 = 200.0;
+const NODE_ATLAS_SIZE: u32 = 100;
+const PATH: &str = "terrain";
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "003e1d5d-241c-45a6-8c25-731dee22d820"]
+pub struct TerrainMaterial {}
+
+impl Material for TerrainMaterial {}
+
+/// Marks the camera of the left or the right half of the window.
+#[derive(Component)]
+struct SplitScreen {
+    left: bool,
+}
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugin(TerrainPlugin)
+        .add_plugin(TerrainDebugPlugin) // enable debug settings and controls
+        // one plugin per material type, each of which queues only the terrains of its type
+        .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
+        .add_plugin(TerrainMaterialPlugin::<StandardTerrainMaterial>::default())
+        .add_startup_system(setup)
+        .add_system(set_camera_viewports)
+        .add_system(toggle_camera)
+        .run();
+}
+
+fn base_tile() -> TileConfig {
+    TileConfig {
+        path: "assets/terrain/source/height".to_string(),
+        size: TERRAIN_SIZE,
+        file_format: FileFormat::PNG,
+        detail: None,
+        water: None,
+        void_fill: None,
+        georeference: None,
+        sources: vec![],
+        feather_width: 0,
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut custom_materials: ResMut<Assets<TerrainMaterial>>,
+    mut standard_materials: ResMut<Assets<StandardTerrainMaterial>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let mut preprocessor = Preprocessor::default();
+    let mut albedo_loader = AttachmentFromDiskLoader::default();
+    let mut base_loader = AttachmentFromDiskLoader::default();
+
+    // The first terrain uses the base attachment and an albedo attachment.
+    let mut albedo_config = TerrainConfig::new(
+        TERRAIN_SIZE,
+        LOD_COUNT,
+        HEIGHT,
+        NODE_ATLAS_SIZE,
+        PATH.to_string(),
+    );
+
+    albedo_config.add_base_attachment_from_disk(
+        &mut preprocessor,
+        &mut albedo_loader,
+        BaseConfig::new(TEXTURE_SIZE, MIP_LEVEL_COUNT),
+        base_tile(),
+    );
+
+    albedo_config.add_attachment_from_disk(
+        &mut preprocessor,
+        &mut albedo_loader,
+        AttachmentConfig::new(
+            "albedo".to_string(),
+            TEXTURE_SIZE,
+            1,
+            MIP_LEVEL_COUNT,
+            AttachmentFormat::Rgb8,
+        ),
+        TileConfig {
+            path: "assets/terrain/source/albedo.png".to_string(),
+            ..base_tile()
+        },
+    );
+
+    // Preprocesses the terrain data.
+    // Todo: Should be commented out after the first run.
+    preprocessor
+        .preprocess(&albedo_config)
+        .expect("Could not preprocess the terrain.");
+
+    load_node_config(&mut albedo_config).expect("Could not load the node config of the terrain.");
+
+    // The second terrain only loads the base attachment of the same data.
+    let mut base_config = TerrainConfig::new(
+        TERRAIN_SIZE,
+        LOD_COUNT,
+        HEIGHT,
+        NODE_ATLAS_SIZE,
+        PATH.to_string(),
+    );
+
+    base_config.load_base_attachment_from_disk(
+        &mut base_loader,
+        BaseConfig::new(TEXTURE_SIZE, MIP_LEVEL_COUNT),
+    );
+
+    load_node_config(&mut base_config).expect("Could not load the node config of the terrain.");
+
+    // Each terrain has its own material type and thus its own pipelines.
+    let albedo_terrain = commands
+        .spawn((
+            TerrainBundle::new(albedo_config.clone()),
+            albedo_loader,
+            custom_materials.add(TerrainMaterial {}),
+        ))
+        .id();
+
+    let base_terrain = commands
+        .spawn((
+            TerrainBundle::new(base_config.clone()),
+            base_loader,
+            standard_materials.add(StandardTerrainMaterial {
+                base_color: Color::rgb(0.4, 0.5, 0.3),
+                snow_line: Some(SnowLine {
+                    summer_altitude: HEIGHT * 0.6,
+                    winter_altitude: HEIGHT * 0.6,
+                    ..default()
+                }),
+                ..default()
+            }),
+        ))
+        .id();
+
+    let view_config = TerrainViewConfig {
+        tile_scale: 4.0,
+        grid_size: 4,
+        node_count: 10,
+        load_distance: 5.0,
+        view_distance: 4.0,
+        ..default()
+    };
+
+    let left = commands
+        .spawn((
+            TerrainView,
+            SplitScreen { left: true },
+            DebugCamera::new(Vec3::new(300.0, 200.0, 300.0), -135.0, -20.0),
+            Camera3dBundle::default(),
+        ))
+        .id();
+
+    let right = commands
+        .spawn((
+            TerrainView,
+            SplitScreen { left: false },
+            DebugCamera::new(Vec3::new(300.0, 200.0, 300.0), -135.0, -20.0),
+            Camera3dBundle {
+                camera: Camera {
+                    priority: 1, // render after the left camera
+                    ..default()
+                },
+                camera_3d: Camera3d {
+                    // do not clear the image rendered by the left camera
+                    clear_color: ClearColorConfig::None,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .id();
+
+    // A terrain is only rendered into the views, which it has a view config for.
+    for (terrain, config, view) in [
+        (albedo_terrain, &albedo_config, left),
+        (base_terrain, &base_config, right),
+    ] {
+        quadtrees.insert(
+            (terrain, view),
+            Quadtree::from_configs(config, &view_config),
+        );
+        view_configs.insert((terrain, view), view_config.clone());
+    }
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    });
+}
+
+/// Splits the window between the two cameras, whenever it is resized.
+fn set_camera_viewports(
+    windows: Res<Windows>,
+    mut resize_events: EventReader<WindowResized>,
+    mut camera_query: Query<(&SplitScreen, &mut Camera)>,
+) {
+    for resize_event in resize_events.iter() {
+        if resize_event.id != WindowId::primary() {
+            continue;
+        }
+
+        let window = windows.primary();
+        let size = UVec2::new(window.physical_width() / 2, window.physical_height());
+
+        for (split_screen, mut camera) in &mut camera_query {
+            camera.viewport = Some(Viewport {
+                physical_position: UVec2::new(if split_screen.left { 0 } else { size.x }, 0),
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
+
+/// Toggles the movement of the left (T) and right (Y) camera.
+fn toggle_camera(
+    input: Res<Input<KeyCode>>,
+    mut camera_query: Query<(&SplitScreen, &mut DebugCamera)>,
+) {
+    let left = if input.just_pressed(KeyCode::T) {
+        true
+    } else if input.just_pressed(KeyCode::Y) {
+        false
+    } else {
+        return;
+    };
+
+    for (split_screen, mut camera) in &mut camera_query {
+        camera.active = split_screen.left == left && !camera.active;
+    }
+}
@@ -0,0 +1,201 @@
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{camera::Viewport, render_resource::*},
+    window::WindowResized,
+};
+use bevy_terrain::prelude::*;
+
+const TERRAIN_SIZE: u32 = 1024;
+const TEXTURE_SIZE: u32 = 512;
+const MIP_LEVEL_COUNT: u32 = 1;
+const LOD_COUNT: u32 = 4;
+const HEIGHT: f32 = 200.0;
+const NODE_ATLAS_SIZE: u32 = 200; // has to hold the nodes of both views
+const PATH: &str = "terrain";
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "003e1d5d-241c-45a6-8c25-731dee22d820"]
+pub struct TerrainMaterial {}
+
+impl Material for TerrainMaterial {}
+
+/// Marks the camera of the left or the right half of the window.
+#[derive(Component)]
+struct SplitScreen {
+    left: bool,
+}
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugin(TerrainPlugin)
+        .add_plugin(TerrainDebugPlugin) // enable debug settings and controls
+        .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
+        .add_startup_system(setup)
+        .add_system(set_camera_viewports)
+        .add_system(toggle_camera)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let mut preprocessor = Preprocessor::default();
+    let mut loader = AttachmentFromDiskLoader::default();
+
+    let mut config = TerrainConfig::new(
+        TERRAIN_SIZE,
+        LOD_COUNT,
+        HEIGHT,
+        NODE_ATLAS_SIZE,
+        PATH.to_string(),
+    );
+
+    config.add_base_attachment_from_disk(
+        &mut preprocessor,
+        &mut loader,
+        BaseConfig::new(TEXTURE_SIZE, MIP_LEVEL_COUNT),
+        TileConfig {
+            path: "assets/terrain/source/height".to_string(),
+            size: TERRAIN_SIZE,
+            file_format: FileFormat::PNG,
+            detail: None,
+            water: None,
+            void_fill: None,
+            georeference: None,
+            sources: vec![],
+            feather_width: 0,
+        },
+    );
+
+    // Preprocesses the terrain data.
+    // Todo: Should be commented out after the first run.
+    preprocessor
+        .preprocess(&config)
+        .expect("Could not preprocess the terrain.");
+
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
+
+    let terrain = commands
+        .spawn((
+            TerrainBundle::new(config.clone()),
+            loader,
+            materials.add(TerrainMaterial {}),
+        ))
+        .id();
+
+    // Each view has its own quality settings, the right one renders the terrain more coarsely.
+    let left_config = TerrainViewConfig {
+        tile_scale: 4.0,
+        grid_size: 4,
+        node_count: 10,
+        load_distance: 5.0,
+        view_distance: 4.0,
+        ..default()
+    };
+    let right_config = TerrainViewConfig {
+        tile_scale: 16.0,
+        grid_size: 4,
+        node_count: 6,
+        load_distance: 3.0,
+        view_distance: 2.0,
+        ..default()
+    };
+
+    let left = commands
+        .spawn((
+            TerrainView,
+            SplitScreen { left: true },
+            DebugCamera::new(Vec3::new(300.0, 200.0, 300.0), -135.0, -20.0),
+            Camera3dBundle::default(),
+        ))
+        .id();
+
+    let right = commands
+        .spawn((
+            TerrainView,
+            SplitScreen { left: false },
+            DebugCamera::new(Vec3::new(700.0, 400.0, 700.0), 45.0, -30.0),
+            Camera3dBundle {
+                camera: Camera {
+                    priority: 1, // render after the left camera
+                    ..default()
+                },
+                camera_3d: Camera3d {
+                    // do not clear the image rendered by the left camera
+                    clear_color: ClearColorConfig::None,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .id();
+
+    for (view, view_config) in [(left, left_config), (right, right_config)] {
+        quadtrees.insert(
+            (terrain, view),
+            Quadtree::from_configs(&config, &view_config),
+        );
+        view_configs.insert((terrain, view), view_config);
+    }
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    });
+}
+
+/// Splits the window between the two cameras, whenever it is resized.
+fn set_camera_viewports(
+    windows: Res<Windows>,
+    mut resize_events: EventReader<WindowResized>,
+    mut camera_query: Query<(&SplitScreen, &mut Camera)>,
+) {
+    for resize_event in resize_events.iter() {
+        if resize_event.id != WindowId::primary() {
+            continue;
+        }
+
+        let window = windows.primary();
+        let size = UVec2::new(window.physical_width() / 2, window.physical_height());
+
+        for (split_screen, mut camera) in &mut camera_query {
+            camera.viewport = Some(Viewport {
+                physical_position: UVec2::new(if split_screen.left { 0 } else { size.x }, 0),
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
+
+/// Toggles the movement of the left (T) and right (Y) camera.
+fn toggle_camera(
+    input: Res<Input<KeyCode>>,
+    mut camera_query: Query<(&SplitScreen, &mut DebugCamera)>,
+) {
+    let left = if input.just_pressed(KeyCode::T) {
+        true
+    } else if input.just_pressed(KeyCode::Y) {
+        false
+    } else {
+        return;
+    };
+
+    for (split_screen, mut camera) in &mut camera_query {
+        camera.active = split_screen.left == left && !camera.active;
+    }
+}
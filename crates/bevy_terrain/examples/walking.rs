@@ -0,0 +1,249 @@
+use bevy::{input::mouse::MouseMotion, prelude::*, reflect::TypeUuid, render::render_resource::*};
+use bevy_terrain::prelude::*;
+
+const TERRAIN_SIZE: u32 = 1024;
+const TEXTURE_SIZE: u32 = 512;
+const MIP_LEVEL_COUNT: u32 = 1;
+const LOD_COUNT: u32 = 4;
+const HEIGHT: f32 = 200.0;
+const NODE_ATLAS_SIZE: u32 = 100;
+const PATH: &str = "terrain";
+
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "003e1d5d-241c-45a6-8c25-731dee22d820"]
+pub struct TerrainMaterial {}
+
+impl Material for TerrainMaterial {}
+
+/// A gravity based first person controller, which walks on the terrain.
+///
+/// Use `W`, `A`, `S` and `D` to walk, `Space` to jump and the mouse to look around.
+#[derive(Component)]
+pub struct WalkingController {
+    /// The horizontal speed in units per second.
+    pub walk_speed: f32,
+    /// The initial vertical speed of a jump.
+    pub jump_speed: f32,
+    /// The downward acceleration.
+    pub gravity: f32,
+    /// The height of the camera above the ground.
+    pub eye_height: f32,
+    /// The steepest slope, which can be walked up.
+    pub max_slope_degrees: f32,
+    /// The rotation speed of the mouse look.
+    pub sensitivity: f32,
+    pub velocity: Vec3,
+    pub grounded: bool,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Default for WalkingController {
+    fn default() -> Self {
+        Self {
+            walk_speed: 5.0,
+            jump_speed: 5.0,
+            gravity: 9.81,
+            eye_height: 1.8,
+            max_slope_degrees: 40.0,
+            sensitivity: 0.002,
+            velocity: Vec3::ZERO,
+            grounded: false,
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+}
+
+/// Moves all [`WalkingController`]s, which have to be terrain views themselves.
+pub struct WalkingControllerPlugin;
+
+impl Plugin for WalkingControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(walk);
+    }
+}
+
+fn walk(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut motion_events: EventReader<MouseMotion>,
+    images: Res<Assets<Image>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
+    mut controller_query: Query<(Entity, &mut Transform, &mut WalkingController)>,
+) {
+    let delta_time = time.delta_seconds();
+    let mouse_delta: Vec2 = motion_events.iter().map(|motion| motion.delta).sum();
+
+    for (view, mut transform, mut controller) in &mut controller_query {
+        controller.yaw -= mouse_delta.x * controller.sensitivity;
+        controller.pitch =
+            (controller.pitch - mouse_delta.y * controller.sensitivity).clamp(-1.5, 1.5);
+        transform.rotation = Quat::from_euler(EulerRot::YXZ, controller.yaw, controller.pitch, 0.0);
+
+        let ground = |position: Vec3| {
+            terrain_query.iter().find_map(|(terrain, node_atlas)| {
+                let quadtree = quadtrees.get(&(terrain, view))?;
+                let height = quadtree.sample_height(node_atlas, &images, position.xz())?;
+                let normal = quadtree.sample_normal(node_atlas, &images, position.xz())?;
+
+                Some((height, normal))
+            })
+        };
+
+        // wait until the terrain below the controller is loaded
+        let (_, normal) = match ground(transform.translation) {
+            Some(ground) => ground,
+            None => continue,
+        };
+
+        let mut direction = Vec3::ZERO;
+
+        if keys.pressed(KeyCode::W) {
+            direction.z -= 1.0;
+        }
+        if keys.pressed(KeyCode::S) {
+            direction.z += 1.0;
+        }
+        if keys.pressed(KeyCode::A) {
+            direction.x -= 1.0;
+        }
+        if keys.pressed(KeyCode::D) {
+            direction.x += 1.0;
+        }
+
+        let mut walk_velocity = Quat::from_rotation_y(controller.yaw)
+            * direction.normalize_or_zero()
+            * controller.walk_speed;
+
+        // slopes that are too steep can not be walked up
+        let slope = normal.angle_between(Vec3::Y);
+        let uphill = -Vec3::new(normal.x, 0.0, normal.z).normalize_or_zero();
+
+        if slope > controller.max_slope_degrees.to_radians() && walk_velocity.dot(uphill) > 0.0 {
+            walk_velocity -= uphill * walk_velocity.dot(uphill);
+        }
+
+        controller.velocity.x = walk_velocity.x;
+        controller.velocity.z = walk_velocity.z;
+
+        if controller.grounded && keys.just_pressed(KeyCode::Space) {
+            controller.velocity.y = controller.jump_speed;
+        }
+
+        controller.velocity.y -= controller.gravity * delta_time;
+        transform.translation += controller.velocity * delta_time;
+
+        // snap onto the ground
+        let (height, _) = match ground(transform.translation) {
+            Some(ground) => ground,
+            None => continue,
+        };
+
+        let eye_height = height + controller.eye_height;
+        controller.grounded = transform.translation.y <= eye_height;
+
+        if controller.grounded {
+            transform.translation.y = eye_height;
+            controller.velocity.y = 0.0;
+        }
+    }
+}
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugin(TerrainPlugin)
+        .add_plugin(TerrainMaterialPlugin::<TerrainMaterial>::default())
+        .add_plugin(WalkingControllerPlugin)
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let mut preprocessor = Preprocessor::default();
+    let mut loader = AttachmentFromDiskLoader::default();
+
+    let mut config = TerrainConfig::new(
+        TERRAIN_SIZE,
+        LOD_COUNT,
+        HEIGHT,
+        NODE_ATLAS_SIZE,
+        PATH.to_string(),
+    );
+
+    config.add_base_attachment_from_disk(
+        &mut preprocessor,
+        &mut loader,
+        BaseConfig::new(TEXTURE_SIZE, MIP_LEVEL_COUNT),
+        TileConfig {
+            path: "assets/terrain/source/height".to_string(),
+            size: TERRAIN_SIZE,
+            file_format: FileFormat::PNG,
+            ..default()
+        },
+    );
+
+    // Preprocesses the terrain data.
+    // Todo: Should be commented out after the first run.
+    preprocessor
+        .preprocess(&config)
+        .expect("Could not preprocess the terrain.");
+
+    load_node_config(&mut config).expect("Could not load the node config of the terrain.");
+
+    let terrain = commands
+        .spawn((
+            TerrainBundle::new(config.clone()),
+            loader,
+            materials.add(TerrainMaterial {}),
+        ))
+        .id();
+
+    // A walker only needs high detail close by.
+    let view_config = TerrainViewConfig {
+        tile_scale: 4.0,
+        grid_size: 4,
+        node_count: 8,
+        load_distance: 3.0,
+        view_distance: 3.0,
+        ..default()
+    };
+
+    // Spawn the walker above the center of the terrain.
+    // It drops down as soon as the ground below it is loaded.
+    let center = TERRAIN_SIZE as f32 / 2.0;
+    let view = commands
+        .spawn((
+            TerrainView,
+            WalkingController::default(),
+            Camera3dBundle {
+                transform: Transform::from_xyz(center, HEIGHT, center),
+                ..default()
+            },
+        ))
+        .id();
+
+    let quadtree = Quadtree::from_configs(&config, &view_config);
+    view_configs.insert((terrain, view), view_config);
+    quadtrees.insert((terrain, view), quadtree);
+
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            illuminance: 20000.0,
+            ..default()
+        },
+        transform: Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.insert_resource(AmbientLight {
+        brightness: 0.2,
+        ..default()
+    });
+}
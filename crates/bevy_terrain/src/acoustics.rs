@@ -0,0 +1,91 @@
+//! Spatial queries, which let audio middleware drive occlusion and reverb from the shape of the
+//! terrain.
+//!
+//! The [`TerrainAcoustics`] tests the line of sight between a sound source and a listener
+//! against the currently loaded heights, the same way the viewshed marches its lines of sight on
+//! the GPU, and estimates how open the surroundings of a position are from the elevation of the
+//! horizon around it (e.g. a plain is open, while a canyon or a valley is enclosed).
+//! Both queries are evaluated on the CPU and are cheap enough to run every frame for a handful
+//! of sound sources.
+
+use crate::terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree, sampler::TerrainSampler};
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+/// Answers acoustic queries against the nodes currently loaded by a quadtree.
+///
+/// Heights, which are not loaded yet or which lie inside holes of the mask, are ignored,
+/// thus they neither occlude sounds nor enclose positions.
+pub struct TerrainAcoustics<'a> {
+    sampler: TerrainSampler<'a>,
+    /// The number of heights sampled along each line of sight.
+    pub sample_count: u32,
+    /// The depth of the line of sight below the terrain, at which a sound is fully occluded.
+    /// Shallower depths (e.g. sounds grazing a ridge) are partially occluded.
+    pub occlusion_depth: f32,
+    /// The number of directions, in which the horizon is searched for the openness.
+    pub horizon_directions: u32,
+}
+
+impl<'a> TerrainAcoustics<'a> {
+    pub fn new(
+        quadtree: &'a Quadtree,
+        node_atlas: &'a NodeAtlas,
+        images: &'a Assets<Image>,
+    ) -> Self {
+        Self {
+            sampler: TerrainSampler::new(quadtree, node_atlas, images),
+            sample_count: 64,
+            occlusion_depth: 4.0,
+            horizon_directions: 8,
+        }
+    }
+
+    /// Returns how strongly the terrain occludes the line of sight from the source to the
+    /// listener, from zero (unobstructed) to one (fully occluded).
+    pub fn occlusion(&self, from: Vec3, to: Vec3) -> f32 {
+        let sample_count = self.sample_count.max(1);
+
+        // the end points themselves rest on the terrain and are excluded
+        let depth = (1..sample_count)
+            .filter_map(|index| {
+                let position = from.lerp(to, index as f32 / sample_count as f32);
+                let height = self.sampler.height(position.xz())?;
+
+                Some(height - position.y)
+            })
+            .fold(0.0, f32::max);
+
+        (depth / self.occlusion_depth.max(f32::EPSILON)).min(1.0)
+    }
+
+    /// Returns how open the surroundings of the position are within the radius, from zero
+    /// (fully enclosed) to one (a flat plain or a peak).
+    ///
+    /// The openness is the average over all directions of one minus the sine of the elevation
+    /// angle of the horizon, which is the highest terrain seen from the position in that direction.
+    pub fn openness(&self, position: Vec3, radius: f32) -> f32 {
+        let direction_count = self.horizon_directions.max(1);
+        let sample_count = self.sample_count.max(1);
+
+        let openness: f32 = (0..direction_count)
+            .map(|direction| {
+                let angle = direction as f32 / direction_count as f32 * TAU;
+                let direction = Vec2::new(angle.cos(), angle.sin());
+
+                let horizon = (1..=sample_count)
+                    .filter_map(|index| {
+                        let distance = index as f32 / sample_count as f32 * radius;
+                        let height = self.sampler.height(position.xz() + direction * distance)?;
+
+                        Some((height - position.y).atan2(distance))
+                    })
+                    .fold(0.0, f32::max);
+
+                1.0 - horizon.sin()
+            })
+            .sum();
+
+        openness / direction_count as f32
+    }
+}
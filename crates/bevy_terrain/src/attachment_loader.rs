@@ -1,7 +1,18 @@
 //! The default attachment loader, which loads node data from disk.
+//!
+//! The node data is read through the [`AssetServer`], so the byte source is determined by its
+//! [`AssetIo`](bevy::asset::AssetIo). On native platforms the nodes are read from the file system,
+//! while on the web (wasm32) they are fetched over HTTP relative to the page,
+//! so the assets directory has to be served alongside the app.
 
-use crate::terrain_data::{
-    node_atlas::NodeAtlas, AttachmentConfig, AttachmentIndex, FileFormat, NodeId,
+use crate::{
+    error::{TerrainError, TerrainLoadError},
+    formats::tc::TC,
+    terrain::TerrainConfig,
+    terrain_data::{
+        node_atlas::NodeAtlas, shift_attachment_index, AttachmentConfig, AttachmentIndex,
+        FileFormat, NodeId,
+    },
 };
 use bevy::{
     asset::{AssetServer, HandleId, LoadState},
@@ -9,6 +20,7 @@ use bevy::{
     render::render_resource::*,
     utils::HashMap,
 };
+use std::mem;
 
 pub(crate) struct AttachmentFromDisk {
     pub(crate) path: String,
@@ -34,6 +46,38 @@ pub struct AttachmentFromDiskLoader {
     handle_mapping: HashMap<HandleId, (NodeId, AttachmentIndex)>,
 }
 
+impl AttachmentFromDiskLoader {
+    /// Loads the attachment at the index from the data directory of the terrain.
+    pub(crate) fn insert_attachment(
+        &mut self,
+        attachment_index: AttachmentIndex,
+        attachment: &AttachmentConfig,
+        path: &str,
+    ) {
+        self.attachments
+            .insert(attachment_index, AttachmentFromDisk::new(attachment, path));
+    }
+
+    /// Stops loading the attachment and shifts the indices of the following attachments.
+    pub(crate) fn remove_attachment(&mut self, removed_index: AttachmentIndex) {
+        self.attachments = mem::take(&mut self.attachments)
+            .into_iter()
+            .filter_map(|(index, attachment)| {
+                shift_attachment_index(index, removed_index).map(|index| (index, attachment))
+            })
+            .collect();
+        self.handle_mapping.retain(|_, (_, index)| {
+            match shift_attachment_index(*index, removed_index) {
+                Some(shifted) => {
+                    *index = shifted;
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+}
+
 pub(crate) fn start_loading_attachment_from_disk(
     asset_server: Res<AssetServer>,
     mut terrain_query: Query<(&mut NodeAtlas, &mut AttachmentFromDiskLoader)>,
@@ -77,14 +121,50 @@ pub(crate) fn start_loading_attachment_from_disk(
     }
 }
 
+/// Returns the path of the asset for error messages.
+fn asset_path(asset_server: &AssetServer, id: HandleId) -> String {
+    asset_server.get_handle_path(id).map_or_else(
+        || format!("{id:?}"),
+        |path| path.path().display().to_string(),
+    )
+}
+
+/// Marks the attachments of nodes, which have finished loading, as loaded.
+///
+/// Nodes with attachments, which failed to load, are removed from the node atlas,
+/// so that the quadtrees fall back to their parents, and a [`TerrainLoadError`] is sent.
 pub(crate) fn finish_loading_attachment_from_disk(
+    asset_server: Res<AssetServer>,
     mut asset_events: EventReader<AssetEvent<Image>>,
+    mut load_errors: EventWriter<TerrainLoadError>,
     mut images: ResMut<Assets<Image>>,
-    mut terrain_query: Query<(&mut NodeAtlas, &mut AttachmentFromDiskLoader)>,
+    mut terrain_query: Query<(Entity, &mut NodeAtlas, &mut AttachmentFromDiskLoader)>,
 ) {
+    for (terrain, mut node_atlas, mut config) in terrain_query.iter_mut() {
+        let failed_handles: Vec<HandleId> = config
+            .handle_mapping
+            .keys()
+            .filter(|&&id| asset_server.get_load_state(id) == LoadState::Failed)
+            .copied()
+            .collect();
+
+        for id in failed_handles {
+            let (node_id, _) = config.handle_mapping.remove(&id).unwrap();
+
+            node_atlas.remove_missing_node(node_id);
+
+            load_errors.send(TerrainLoadError {
+                terrain,
+                error: TerrainError::NodeLoadFailed {
+                    path: asset_path(&asset_server, id),
+                },
+            });
+        }
+    }
+
     for event in asset_events.iter() {
         if let AssetEvent::Created { handle } = event {
-            for (mut node_atlas, mut config) in terrain_query.iter_mut() {
+            for (_, mut node_atlas, mut config) in terrain_query.iter_mut() {
                 if let Some((node_id, attachment_index)) =
                     config.handle_mapping.remove(&handle.id())
                 {
@@ -94,11 +174,76 @@ pub(crate) fn finish_loading_attachment_from_disk(
                     image.texture_descriptor.format = attachment.format;
                     image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
 
-                    let node = node_atlas.loading_nodes.get_mut(&node_id).unwrap();
-                    node.loaded(attachment_index);
+                    // the node may have been removed, since another attachment is missing
+                    if let Some(node) = node_atlas.loading_nodes.get_mut(&node_id) {
+                        node.loaded(attachment_index);
+                    }
                     break;
                 }
             }
         }
     }
 }
+
+/// This component loads the node configuration of the terrain through the [`AssetServer`].
+///
+/// It is an asynchronous alternative to
+/// `load_node_config`, which also works on the web.
+/// Until the configuration has loaded, no nodes of the terrain are requested.
+#[derive(Component)]
+pub struct NodeConfigLoader {
+    handle: Handle<TC>,
+}
+
+impl NodeConfigLoader {
+    /// Starts loading the node configuration from the terrain folder inside the assets directory.
+    pub fn new(config: &TerrainConfig, asset_server: &AssetServer) -> Self {
+        Self {
+            handle: asset_server.load(&format!("{}/config.tc", config.path)),
+        }
+    }
+}
+
+/// Inserts the loaded node configuration into the terrain config and the node atlas.
+///
+/// Configurations, which do not match the terrain config or use an outdated node layout,
+/// are rejected with a [`TerrainLoadError`], since they can only be migrated
+/// by `load_node_config` natively.
+pub(crate) fn finish_loading_node_config(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut load_errors: EventWriter<TerrainLoadError>,
+    node_configs: Res<Assets<TC>>,
+    mut terrain_query: Query<(
+        Entity,
+        &NodeConfigLoader,
+        &mut TerrainConfig,
+        &mut NodeAtlas,
+    )>,
+) {
+    for (terrain, loader, mut config, mut node_atlas) in terrain_query.iter_mut() {
+        if asset_server.get_load_state(&loader.handle) == LoadState::Failed {
+            commands.entity(terrain).remove::<NodeConfigLoader>();
+
+            load_errors.send(TerrainLoadError {
+                terrain,
+                error: TerrainError::NodeLoadFailed {
+                    path: asset_path(&asset_server, loader.handle.id()),
+                },
+            });
+        }
+
+        if let Some(tc) = node_configs.get(&loader.handle) {
+            commands.entity(terrain).remove::<NodeConfigLoader>();
+
+            if let Err(error) = tc.validate(&config) {
+                error!("{error}");
+                load_errors.send(TerrainLoadError { terrain, error });
+                continue;
+            }
+
+            config.nodes = tc.nodes.iter().copied().collect();
+            node_atlas.existing_nodes = config.nodes.clone();
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! A deterministic benchmark, which replays a scripted camera path and records per frame
+//! timings and statistics into a CSV file.
+//!
+//! The camera path is a [`CameraPath`] stored in a RON file, which can be written by hand or
+//! recorded with the [`CameraPathRecorder`](crate::debug::camera_path::CameraPathRecorder).
+//!
+//! The path is advanced by a fixed time step each frame, instead of the elapsed real time,
+//! thus each run renders the exact same sequence of camera positions, independent of the
+//! frame rate. Only the loading of the nodes, which happens asynchronously, may differ between runs.
+
+use crate::{
+    debug::camera_path::CameraPath,
+    render::diagnostics::{
+        TerrainDiagnosticsPlugin, TERRAIN_DRAW_MS, TERRAIN_PATCHES_DRAWN, TERRAIN_REFINE_MS,
+        TERRAIN_VERTICES,
+    },
+    terrain::Terrain,
+    terrain_data::node_atlas::{update_node_atlas, NodeAtlas},
+};
+use bevy::{
+    app::AppExit,
+    diagnostic::{DiagnosticId, Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+    render::{camera::RenderTarget, render_resource::*},
+};
+use std::{fmt::Write, fs, path::Path};
+
+/// Marks the camera, which is moved along the camera path of the benchmark.
+///
+/// The camera should not be controlled by an active [`DebugCamera`](crate::debug::camera::DebugCamera)
+/// at the same time.
+#[derive(Component)]
+pub struct BenchmarkCamera;
+
+/// Replays the camera path and writes the timings and statistics of each frame into a CSV file.
+///
+/// Each row contains the CPU frame time, the latest available GPU timings of the
+/// [`TerrainDiagnosticsPlugin`] (which lag a few frames behind), the drawn patches and vertices,
+/// the nodes that have started and finished loading and the nodes evicted from the node atlases.
+pub struct TerrainBenchmarkPlugin {
+    /// The path of the RON file containing the [`CameraPath`].
+    pub camera_path: String,
+    /// The path of the CSV file, into which the results are written.
+    pub output: String,
+    /// The amount of frames rendered at the start of the path, before recording begins.
+    pub warmup_frames: u32,
+    /// The time, by which the camera path is advanced each frame.
+    pub time_step: f32,
+    /// Whether or not to exit the app, once the benchmark has finished.
+    pub exit_when_finished: bool,
+}
+
+impl Default for TerrainBenchmarkPlugin {
+    fn default() -> Self {
+        Self {
+            camera_path: "assets/benchmark/camera_path.ron".to_string(),
+            output: "benchmark.csv".to_string(),
+            warmup_frames: 60,
+            time_step: 1.0 / 60.0,
+            exit_when_finished: true,
+        }
+    }
+}
+
+impl Plugin for TerrainBenchmarkPlugin {
+    fn build(&self, app: &mut App) {
+        let camera_path =
+            CameraPath::load(&self.camera_path).expect("Could not load the camera path.");
+
+        if !app.is_plugin_added::<TerrainDiagnosticsPlugin>() {
+            app.add_plugin(TerrainDiagnosticsPlugin);
+        }
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugin(FrameTimeDiagnosticsPlugin);
+        }
+
+        app.insert_resource(TerrainBenchmark {
+            camera_path,
+            output: self.output.clone(),
+            warmup_frames: self.warmup_frames,
+            time_step: self.time_step,
+            exit_when_finished: self.exit_when_finished,
+            frame: 0,
+            rows: Vec::new(),
+            finished: false,
+        })
+        .add_system_to_stage(CoreStage::PreUpdate, advance_benchmark_camera)
+        .add_system_to_stage(
+            CoreStage::Last,
+            record_benchmark_frame.after(update_node_atlas),
+        );
+    }
+}
+
+/// A row of the benchmark results.
+struct BenchmarkRow {
+    frame: u32,
+    time: f32,
+    cpu_ms: Option<f64>,
+    refine_ms: Option<f64>,
+    draw_ms: Option<f64>,
+    patches_drawn: Option<f64>,
+    vertices: Option<f64>,
+    nodes_started: u32,
+    nodes_finished: u32,
+    nodes_evicted: u32,
+    atlas_used: u32,
+}
+
+/// The state of the running benchmark.
+#[derive(Resource)]
+pub struct TerrainBenchmark {
+    camera_path: CameraPath,
+    output: String,
+    warmup_frames: u32,
+    time_step: f32,
+    exit_when_finished: bool,
+    /// The current frame, including the warmup frames.
+    frame: u32,
+    rows: Vec<BenchmarkRow>,
+    finished: bool,
+}
+
+impl TerrainBenchmark {
+    /// Returns whether or not the benchmark has finished and written its results.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the time along the camera path of the current frame.
+    fn time(&self) -> f32 {
+        self.frame.saturating_sub(self.warmup_frames) as f32 * self.time_step
+    }
+
+    fn write_csv(&self, path: &Path) -> anyhow::Result<()> {
+        fn field(value: Option<f64>) -> String {
+            value.map_or_else(String::new, |value| format!("{value:.4}"))
+        }
+
+        let mut csv = String::from(
+            "frame,time,cpu_ms,refine_ms,draw_ms,patches_drawn,vertices,\
+             nodes_started,nodes_finished,nodes_evicted,atlas_used\n",
+        );
+
+        for row in &self.rows {
+            writeln!(
+                csv,
+                "{},{:.4},{},{},{},{},{},{},{},{},{}",
+                row.frame,
+                row.time,
+                field(row.cpu_ms),
+                field(row.refine_ms),
+                field(row.draw_ms),
+                field(row.patches_drawn),
+                field(row.vertices),
+                row.nodes_started,
+                row.nodes_finished,
+                row.nodes_evicted,
+                row.atlas_used
+            )?;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, csv)?;
+
+        Ok(())
+    }
+}
+
+/// Creates an image, which can be used as the render target of the benchmark camera,
+/// when running without a window.
+pub fn headless_render_target(images: &mut Assets<Image>, width: u32, height: u32) -> RenderTarget {
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: "benchmark_render_target".into(),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+        },
+        ..default()
+    };
+
+    image.resize(size);
+
+    RenderTarget::Image(images.add(image))
+}
+
+fn advance_benchmark_camera(
+    benchmark: Res<TerrainBenchmark>,
+    mut camera_query: Query<&mut Transform, With<BenchmarkCamera>>,
+) {
+    if benchmark.finished {
+        return;
+    }
+
+    let transform = benchmark.camera_path.sample(benchmark.time());
+
+    for mut camera_transform in camera_query.iter_mut() {
+        *camera_transform = transform;
+    }
+}
+
+fn record_benchmark_frame(
+    mut benchmark: ResMut<TerrainBenchmark>,
+    mut exit: EventWriter<AppExit>,
+    diagnostics: Res<Diagnostics>,
+    terrain_query: Query<&NodeAtlas, With<Terrain>>,
+) {
+    if benchmark.finished {
+        return;
+    }
+
+    let frame = benchmark.frame;
+    let time = benchmark.time();
+    benchmark.frame += 1;
+
+    if frame < benchmark.warmup_frames {
+        return;
+    }
+
+    let value = |id: DiagnosticId| {
+        diagnostics
+            .get(id)
+            .and_then(|diagnostic| diagnostic.value())
+    };
+
+    let mut row = BenchmarkRow {
+        frame: frame - benchmark.warmup_frames,
+        time,
+        cpu_ms: value(FrameTimeDiagnosticsPlugin::FRAME_TIME).map(|frame_time| frame_time * 1000.0),
+        refine_ms: value(TERRAIN_REFINE_MS),
+        draw_ms: value(TERRAIN_DRAW_MS),
+        patches_drawn: value(TERRAIN_PATCHES_DRAWN),
+        vertices: value(TERRAIN_VERTICES),
+        nodes_started: 0,
+        nodes_finished: 0,
+        nodes_evicted: 0,
+        atlas_used: 0,
+    };
+
+    for node_atlas in terrain_query.iter() {
+        let statistics = node_atlas.statistics();
+
+        row.nodes_started += statistics.started;
+        row.nodes_finished += statistics.finished;
+        row.nodes_evicted += statistics.evicted;
+        row.atlas_used += statistics.used;
+    }
+
+    benchmark.rows.push(row);
+
+    if time < benchmark.camera_path.duration() {
+        return;
+    }
+
+    benchmark.finished = true;
+
+    let output = benchmark.output.clone();
+
+    match benchmark.write_csv(Path::new(&output)) {
+        Ok(()) => info!(
+            "The benchmark recorded {} frames into {output}.",
+            benchmark.rows.len()
+        ),
+        Err(error) => error!("Could not write the benchmark results into {output}: {error}"),
+    }
+
+    if benchmark.exit_when_finished {
+        exit.send(AppExit);
+    }
+}
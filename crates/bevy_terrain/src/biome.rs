@@ -0,0 +1,139 @@
+//! A biome classification of the terrain (e.g. forest, meadow, rock or snow), which drives both
+//! the coloring of the terrain and gameplay rules (e.g. spawning tables or music zones).
+//!
+//! The preprocessor classifies each pixel of the terrain into the first [`Biome`], whose rules
+//! match its height, its slope and optionally the class of a landcover attachment, and stores the
+//! biome id in the biome attachment. The id zero marks pixels, which match no biome.
+//!
+//! The default shader colors the terrain with the colors of the biomes and blends them across the
+//! borders between the biomes. On the CPU, [`TerrainSampler::biome_at`] returns the biome at a
+//! position of the loaded terrain.
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    preprocess::BaseConfig,
+    terrain_data::{AttachmentConfig, AttachmentFormat},
+};
+use crate::{terrain::TerrainConfig, terrain_data::sampler::TerrainSampler};
+use bevy::prelude::*;
+
+/// The maximum number of biomes per terrain, whose colors are available in shaders.
+pub const MAX_BIOMES: usize = 16;
+
+/// The id of a biome, which is its index in the classification plus one.
+/// The id zero marks pixels, which match no biome.
+pub type BiomeId = u16;
+
+/// A biome and the rules, under which the terrain belongs to it.
+#[derive(Clone, Debug)]
+pub struct Biome {
+    /// The name of the biome.
+    pub name: String,
+    /// The color of the terrain inside the biome.
+    pub color: Color,
+    /// The minimum height of the terrain in world units.
+    pub min_height: f32,
+    /// The maximum height of the terrain in world units.
+    pub max_height: f32,
+    /// The minimum slope of the terrain in degrees.
+    pub min_slope: f32,
+    /// The maximum slope of the terrain in degrees.
+    pub max_slope: f32,
+    /// The classes of the landcover attachment, in which the biome occurs.
+    /// An empty list matches all classes and terrains without a landcover attachment.
+    pub landcover_classes: Vec<u16>,
+}
+
+impl Biome {
+    /// Creates a biome, which matches the whole terrain.
+    pub fn new(name: &str, color: Color) -> Self {
+        Self {
+            name: name.to_string(),
+            color,
+            min_height: f32::MIN,
+            max_height: f32::MAX,
+            min_slope: 0.0,
+            max_slope: 90.0,
+            landcover_classes: Vec::new(),
+        }
+    }
+
+    /// Whether the terrain with the height, the slope (in degrees) and the landcover class
+    /// belongs to the biome.
+    pub fn matches(&self, height: f32, slope: f32, landcover_class: Option<u16>) -> bool {
+        let landcover = self.landcover_classes.is_empty()
+            || landcover_class.map_or(false, |class| self.landcover_classes.contains(&class));
+
+        (self.min_height..=self.max_height).contains(&height)
+            && (self.min_slope..=self.max_slope).contains(&slope)
+            && landcover
+    }
+}
+
+/// The biomes of a terrain in the order of their priority.
+#[derive(Clone, Debug, Default)]
+pub struct BiomeClassification {
+    /// The biomes, where the first matching one is assigned to each pixel.
+    pub biomes: Vec<Biome>,
+    /// The name of an attachment, whose first channel stores the class of a landcover raster,
+    /// if any. It has to be preprocessed from its source tiles by the same preprocessor.
+    pub landcover_attachment: Option<String>,
+}
+
+impl BiomeClassification {
+    pub fn new(biomes: Vec<Biome>) -> Self {
+        if biomes.len() > MAX_BIOMES {
+            warn!(
+                "The classification has {} biomes, but only the first {MAX_BIOMES} are colored by the default shader.",
+                biomes.len()
+            );
+        }
+
+        Self {
+            biomes,
+            landcover_attachment: None,
+        }
+    }
+
+    /// Returns the id of the first biome, which matches the terrain, or zero if none does.
+    pub fn classify(&self, height: f32, slope: f32, landcover_class: Option<u16>) -> BiomeId {
+        self.biomes
+            .iter()
+            .position(|biome| biome.matches(height, slope, landcover_class))
+            .map_or(0, |index| index as BiomeId + 1)
+    }
+
+    /// Returns the attachment, which stores the biome ids.
+    ///
+    /// The ids are stored without mip maps, since they must not be interpolated.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn attachment(&self, base: &BaseConfig) -> AttachmentConfig {
+        let mut attachment = AttachmentConfig::new(
+            "biome".to_string(),
+            base.texture_size,
+            base.border_size,
+            1,
+            AttachmentFormat::R16,
+        );
+
+        attachment.file_format = base.file_format;
+        attachment
+    }
+}
+
+/// Returns the biome with the id, if any.
+pub fn biome(biomes: &[Biome], id: BiomeId) -> Option<&Biome> {
+    biomes.get((id as usize).checked_sub(1)?)
+}
+
+impl<'a> TerrainSampler<'a> {
+    /// Returns the biome of the terrain at the position in the horizontal (x, z) plane.
+    ///
+    /// Returns `None`, if the terrain has no biome attachment, if its node is not loaded yet
+    /// or if the position matches no biome.
+    pub fn biome_at<'c>(&self, config: &'c TerrainConfig, position: Vec2) -> Option<&'c Biome> {
+        let value = self.attachment(config.biome_attachment?, position)?;
+
+        biome(&config.biomes, (value * u16::MAX as f32).round() as BiomeId)
+    }
+}
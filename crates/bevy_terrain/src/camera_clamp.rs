@@ -0,0 +1,102 @@
+//! Keeps cameras above the surface of the terrain.
+//!
+//! Add the [`CameraTerrainClamp`] component to any camera (fly cameras, cameras following
+//! a [`CameraPath`](crate::debug::camera_path::CameraPath), etc.) to prevent it from clipping
+//! through the terrain.
+//! The height is sampled from the currently loaded nodes, so the clamp is only as accurate
+//! as the level of detail resident around the camera.
+
+use crate::{
+    debug::camera::DebugCamera,
+    terrain::Terrain,
+    terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree},
+    TerrainViewComponents,
+};
+use bevy::prelude::*;
+use dolly::prelude::*;
+
+/// Keeps the camera a minimum distance above the sampled terrain height.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct CameraTerrainClamp {
+    /// The minimum distance between the camera and the terrain surface.
+    pub min_height: f32,
+}
+
+impl Default for CameraTerrainClamp {
+    fn default() -> Self {
+        Self { min_height: 2.0 }
+    }
+}
+
+impl CameraTerrainClamp {
+    pub fn new(min_height: f32) -> Self {
+        Self { min_height }
+    }
+}
+
+/// Returns the height of the highest terrain at the position, or `None` if no terrain
+/// has loaded data there.
+///
+/// The quadtree of the view is preferred, since its nodes are refined around the view.
+/// Cameras, that are not a terrain view themselves, use the quadtree of any other view.
+fn terrain_height(
+    quadtrees: &TerrainViewComponents<Quadtree>,
+    terrain_query: &Query<(Entity, &NodeAtlas), With<Terrain>>,
+    images: &Assets<Image>,
+    view: Entity,
+    position: Vec2,
+) -> Option<f32> {
+    terrain_query
+        .iter()
+        .filter_map(|(terrain, node_atlas)| {
+            let quadtree = quadtrees.get(&(terrain, view)).or_else(|| {
+                quadtrees
+                    .0
+                    .iter()
+                    .find(|(&(quadtree_terrain, _), _)| quadtree_terrain == terrain)
+                    .map(|(_, quadtree)| quadtree)
+            })?;
+
+            quadtree.sample_height(node_atlas, images, position)
+        })
+        .reduce(f32::max)
+}
+
+/// Lifts all cameras with a [`CameraTerrainClamp`], that are too close to the terrain.
+///
+/// The rig of a [`DebugCamera`] is lifted as well, so that it does not keep moving underground.
+pub(crate) fn clamp_cameras_to_terrain(
+    images: Res<Assets<Image>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
+    mut camera_query: Query<(
+        Entity,
+        &CameraTerrainClamp,
+        &mut Transform,
+        Option<&mut DebugCamera>,
+    )>,
+) {
+    for (view, clamp, mut transform, camera) in &mut camera_query {
+        let height = match terrain_height(
+            &quadtrees,
+            &terrain_query,
+            &images,
+            view,
+            transform.translation.xz(),
+        ) {
+            Some(height) => height,
+            None => continue,
+        };
+
+        let min_height = height + clamp.min_height;
+
+        if transform.translation.y < min_height {
+            transform.translation.y = min_height;
+        }
+
+        if let Some(mut camera) = camera {
+            let position = &mut camera.rig.driver_mut::<Position>().position;
+            position.y = position.y.max(min_height);
+        }
+    }
+}
@@ -1,11 +1,127 @@
-use bevy::{input::mouse::MouseMotion, prelude::*};
+use crate::{georeference::Georeference, TerrainViewComponents, TerrainViewConfig};
+use bevy::{input::mouse::MouseMotion, math::DVec2, prelude::*};
 use dolly::prelude::*;
 
 // Todo: unify dolly glam and bevy glam
 
+/// The keys controlling a [`DebugCamera`].
+#[derive(Clone, Debug)]
+pub struct DebugCameraBindings {
+    pub forward: KeyCode,
+    pub backward: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    /// Multiplies the translation speed by the acceleration, while pressed.
+    pub faster: KeyCode,
+    /// Divides the translation speed by the acceleration, while pressed.
+    pub slower: KeyCode,
+    /// Switches between the fly and the orbit mode.
+    pub toggle_mode: KeyCode,
+}
+
+impl Default for DebugCameraBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::Up,
+            backward: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            up: KeyCode::PageUp,
+            down: KeyCode::PageDown,
+            faster: KeyCode::End,
+            slower: KeyCode::Home,
+            toggle_mode: KeyCode::Tab,
+        }
+    }
+}
+
+/// The way the [`DebugCamera`] is controlled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugCameraMode {
+    /// Flies freely through the scene.
+    #[default]
+    Fly,
+    /// Orbits around a target point, e.g. for presentations and screenshots.
+    Orbit,
+}
+
+/// The state of the [`DebugCamera`] in the orbit mode.
+///
+/// The mouse rotates the camera around the target, the horizontal movement keys pan the target
+/// and the vertical movement keys zoom in and out.
+#[derive(Clone, Debug)]
+pub struct OrbitController {
+    /// The point the camera orbits around.
+    pub target: Vec3,
+    /// The distance between the camera and the target.
+    pub distance: f32,
+    /// The horizontal angle of the camera around the target.
+    pub azimuth_degrees: f32,
+    /// The vertical angle of the camera above the target.
+    pub elevation_degrees: f32,
+    /// The current rotation speed of the azimuth and the elevation in degrees per second.
+    pub angular_velocity: Vec2,
+    /// The rate, at which the angular velocity decays.
+    /// Smaller values keep the camera spinning longer after the mouse stopped.
+    pub damping: f32,
+    /// The constant rotation of the azimuth in degrees per second, which turns the camera
+    /// into a turntable.
+    pub turntable_speed: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self {
+            target: Vec3::ZERO,
+            distance: 500.0,
+            azimuth_degrees: 0.0,
+            elevation_degrees: 30.0,
+            angular_velocity: Vec2::ZERO,
+            damping: 4.0,
+            turntable_speed: 0.0,
+        }
+    }
+}
+
+impl OrbitController {
+    /// Returns the position of the camera.
+    pub fn position(&self) -> Vec3 {
+        let (azimuth, elevation) = (
+            self.azimuth_degrees.to_radians(),
+            self.elevation_degrees.to_radians(),
+        );
+
+        self.target
+            + self.distance
+                * Vec3::new(
+                    elevation.cos() * azimuth.sin(),
+                    elevation.sin(),
+                    elevation.cos() * azimuth.cos(),
+                )
+    }
+
+    fn update(&mut self, rotation_delta: Vec2, pan: Vec3, zoom: f32, delta_time: f32) {
+        self.angular_velocity += rotation_delta * self.damping;
+        self.angular_velocity *= (-self.damping * delta_time).exp();
+
+        self.azimuth_degrees = (self.azimuth_degrees
+            + (self.angular_velocity.x + self.turntable_speed) * delta_time)
+            .rem_euclid(360.0);
+        self.elevation_degrees =
+            (self.elevation_degrees - self.angular_velocity.y * delta_time).clamp(-89.0, 89.0);
+
+        let rotation = Quat::from_rotation_y(self.azimuth_degrees.to_radians());
+        self.target += rotation * Vec3::new(pan.x, 0.0, pan.z);
+        self.distance = (self.distance * (-zoom * delta_time).exp()).max(1.0);
+    }
+}
+
 /// A fly camera used to navigate and debug the terrain.
 ///
-/// It is controlled using the arrow keys, and the mouse.
+/// It is controlled using the arrow keys (see [`DebugCameraBindings`]), and the mouse.
+/// Alternatively it orbits around a target point (see [`DebugCameraMode`]).
 #[derive(Component)]
 pub struct DebugCamera {
     pub rig: CameraRig<RightHanded>,
@@ -13,59 +129,177 @@ pub struct DebugCamera {
     pub translation_speed: f32,
     pub rotation_speed: f32,
     pub acceleration: f32,
+    /// The keys controlling the camera.
+    pub bindings: DebugCameraBindings,
+    /// The height above the terrain, at which the camera moves with the translation speed.
+    ///
+    /// The speed is scaled proportionally to the height above the terrain (sampled under the
+    /// terrain view of the camera), so that it grows exponentially while climbing at full speed.
+    /// `None` disables the scaling.
+    pub reference_altitude: Option<f32>,
+    /// The smallest factor, by which the speed is scaled close to the ground.
+    pub min_altitude_factor: f32,
+    /// The damping of the movement. Larger values smooth the movement more.
+    pub position_smoothness: f32,
+    /// The damping of the rotation. Larger values smooth the rotation more.
+    pub rotation_smoothness: f32,
+    /// The way the camera is controlled.
+    pub mode: DebugCameraMode,
+    /// The state of the camera in the orbit mode.
+    pub orbit: OrbitController,
 }
 
 impl Default for DebugCamera {
     fn default() -> Self {
-        Self {
-            rig: CameraRig::builder()
-                .with(Position::new(dolly::glam::Vec3::new(0.0, 100.0, 0.0)))
-                .with(YawPitch {
-                    yaw_degrees: -135.0,
-                    pitch_degrees: 0.0,
-                })
-                .with(Smooth::new_position_rotation(3.0, 1.5))
-                .build(),
-            active: false,
-            translation_speed: 100.0,
-            rotation_speed: 8.0,
-            acceleration: 1.03,
-        }
+        Self::new(Vec3::new(0.0, 100.0, 0.0), -135.0, 0.0)
     }
 }
 
 impl DebugCamera {
     pub fn new(position: Vec3, yaw_degrees: f32, pitch_degrees: f32) -> Self {
+        let position_smoothness = 3.0;
+        let rotation_smoothness = 1.5;
+
         Self {
-            rig: CameraRig::builder()
-                .with(Position::new(dolly::glam::Vec3::new(
-                    position.x, position.y, position.z,
-                )))
-                .with(YawPitch {
-                    yaw_degrees,
-                    pitch_degrees,
-                })
-                .with(Smooth::new_position_rotation(3.0, 1.5))
-                .build(),
+            rig: build_rig(
+                position,
+                yaw_degrees,
+                pitch_degrees,
+                position_smoothness,
+                rotation_smoothness,
+            ),
             active: false,
             translation_speed: 100.0,
             rotation_speed: 8.0,
             acceleration: 1.03,
+            bindings: default(),
+            reference_altitude: None,
+            min_altitude_factor: 0.05,
+            position_smoothness,
+            rotation_smoothness,
+            mode: default(),
+            orbit: default(),
+        }
+    }
+
+    /// Switches the way the camera is controlled.
+    ///
+    /// When switching to the orbit mode, the target is placed in front of the camera,
+    /// so that the camera keeps its current position and orientation.
+    pub fn set_mode(&mut self, mode: DebugCameraMode) {
+        if mode == DebugCameraMode::Orbit && self.mode != DebugCameraMode::Orbit {
+            let position = self.rig.driver::<Position>().position;
+            let yaw_pitch = self.rig.driver::<YawPitch>();
+            let (yaw, pitch) = (
+                yaw_pitch.yaw_degrees.to_radians(),
+                yaw_pitch.pitch_degrees.to_radians(),
+            );
+
+            let forward = Vec3::new(
+                -pitch.cos() * yaw.sin(),
+                pitch.sin(),
+                -pitch.cos() * yaw.cos(),
+            );
+
+            self.orbit.azimuth_degrees = yaw_pitch.yaw_degrees;
+            self.orbit.elevation_degrees = -yaw_pitch.pitch_degrees;
+            self.orbit.angular_velocity = Vec2::ZERO;
+            self.orbit.target =
+                Vec3::new(position.x, position.y, position.z) + forward * self.orbit.distance;
+        }
+
+        self.mode = mode;
+    }
+
+    /// Switches between the fly and the orbit mode.
+    pub fn toggle_mode(&mut self) {
+        self.set_mode(match self.mode {
+            DebugCameraMode::Fly => DebugCameraMode::Orbit,
+            DebugCameraMode::Orbit => DebugCameraMode::Fly,
+        });
+    }
+
+    /// Moves the camera to the position instantly, without any smoothing.
+    ///
+    /// The orientation of the camera is kept.
+    pub fn teleport(&mut self, position: Vec3) {
+        let yaw_pitch = self.rig.driver::<YawPitch>();
+        let (yaw_degrees, pitch_degrees) = (yaw_pitch.yaw_degrees, yaw_pitch.pitch_degrees);
+
+        self.rig = build_rig(
+            position,
+            yaw_degrees,
+            pitch_degrees,
+            self.position_smoothness,
+            self.rotation_smoothness,
+        );
+    }
+
+    /// Moves the camera above the latitude and longitude (in degrees) of the georeferenced terrain,
+    /// at the height in world units.
+    ///
+    /// Returns whether or not the coordinates could be converted into the coordinate reference
+    /// system of the terrain.
+    pub fn teleport_to_geographic(
+        &mut self,
+        georeference: &Georeference,
+        geographic: DVec2,
+        height: f32,
+    ) -> bool {
+        match georeference.geo_to_world(geographic) {
+            Some(position) => {
+                self.teleport(Vec3::new(position.x, height, position.y));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the factor, by which the translation speed is scaled at the height above the terrain.
+    fn altitude_factor(&self, height_above_ground: Option<f32>) -> f32 {
+        match (self.reference_altitude, height_above_ground) {
+            (Some(reference_altitude), Some(height_above_ground)) => {
+                (height_above_ground / reference_altitude).max(self.min_altitude_factor)
+            }
+            _ => 1.0,
         }
     }
 }
 
+fn build_rig(
+    position: Vec3,
+    yaw_degrees: f32,
+    pitch_degrees: f32,
+    position_smoothness: f32,
+    rotation_smoothness: f32,
+) -> CameraRig<RightHanded> {
+    CameraRig::builder()
+        .with(Position::new(dolly::glam::Vec3::new(
+            position.x, position.y, position.z,
+        )))
+        .with(YawPitch {
+            yaw_degrees,
+            pitch_degrees,
+        })
+        .with(Smooth::new_position_rotation(
+            position_smoothness,
+            rotation_smoothness,
+        ))
+        .build()
+}
+
 pub(crate) fn debug_camera_control(
     time: Res<Time>,
     mut motion_events: EventReader<MouseMotion>,
     keys: Res<Input<KeyCode>>,
-    mut camera_rig_query: Query<(&mut Transform, &mut DebugCamera)>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+    mut camera_rig_query: Query<(Entity, &mut Transform, &mut DebugCamera)>,
 ) {
     let delta_time = time.delta_seconds();
 
-    if let Some((_, mut camera)) = camera_rig_query
+    if let Some((view, transform, mut camera)) = camera_rig_query
         .iter_mut()
-        .find(|(_, camera)| camera.active)
+        .find(|(_, _, camera)| camera.active)
     {
         let mut speed_factor = 1.0;
         let mut rotation_delta = Vec2::ZERO;
@@ -75,62 +309,107 @@ pub(crate) fn debug_camera_control(
             rotation_delta += -motion.delta;
         }
 
-        if keys.pressed(KeyCode::Left) {
+        if keys.just_pressed(camera.bindings.toggle_mode) {
+            camera.toggle_mode();
+        }
+
+        let bindings = &camera.bindings;
+
+        if keys.pressed(bindings.left) {
             translation_delta.x -= 1.0;
         }
-        if keys.pressed(KeyCode::Right) {
+        if keys.pressed(bindings.right) {
             translation_delta.x += 1.0;
         }
-        if keys.pressed(KeyCode::PageUp) {
+        if keys.pressed(bindings.up) {
             translation_delta.y += 1.0;
         }
-        if keys.pressed(KeyCode::PageDown) {
+        if keys.pressed(bindings.down) {
             translation_delta.y -= 1.0;
         }
-        if keys.pressed(KeyCode::Up) {
+        if keys.pressed(bindings.forward) {
             translation_delta.z -= 1.0;
         }
-        if keys.pressed(KeyCode::Down) {
+        if keys.pressed(bindings.backward) {
             translation_delta.z += 1.0;
         }
-        if keys.pressed(KeyCode::Home) {
+        if keys.pressed(bindings.slower) {
             speed_factor = 1.0 / camera.acceleration;
         }
-        if keys.pressed(KeyCode::End) {
+        if keys.pressed(bindings.faster) {
             speed_factor = camera.acceleration / 1.0;
         }
 
         camera.translation_speed *= speed_factor;
 
+        // the height above the highest terrain under the camera
+        let height_above_ground = view_configs
+            .0
+            .iter()
+            .filter(|(&(_, config_view), _)| config_view == view)
+            .map(|(_, view_config)| transform.translation.y - view_config.height_under_viewer)
+            .reduce(f32::min);
+        let altitude_factor = camera.altitude_factor(height_above_ground);
+
         if translation_delta != dolly::glam::Vec3::ZERO {
             translation_delta = translation_delta.normalize();
         }
 
-        let euler = camera
-            .rig
-            .final_transform
-            .rotation
-            .to_euler(dolly::glam::EulerRot::YXZ);
-        translation_delta =
-            dolly::glam::Quat::from_euler(dolly::glam::EulerRot::YXZ, euler.0, 0.0, 0.0)
-                * translation_delta;
-
-        translation_delta = translation_delta * camera.translation_speed * delta_time;
-        rotation_delta = rotation_delta * camera.rotation_speed * delta_time;
-
-        camera
-            .rig
-            .driver_mut::<YawPitch>()
-            .rotate_yaw_pitch(rotation_delta.x, rotation_delta.y);
-        camera
-            .rig
-            .driver_mut::<Position>()
-            .translate(translation_delta);
+        if camera.mode == DebugCameraMode::Orbit {
+            let pan = Vec3::new(translation_delta.x, 0.0, translation_delta.z)
+                * camera.translation_speed
+                * altitude_factor
+                * delta_time;
+            let zoom = translation_delta.y;
+            let rotation_delta = rotation_delta * camera.rotation_speed * delta_time;
+
+            camera.orbit.update(rotation_delta, pan, zoom, delta_time);
+
+            // move the rig, so that the fly mode continues from the orbit
+            let position = camera.orbit.position();
+            let (azimuth, elevation) =
+                (camera.orbit.azimuth_degrees, camera.orbit.elevation_degrees);
+
+            camera.rig.driver_mut::<Position>().position =
+                dolly::glam::Vec3::new(position.x, position.y, position.z);
+
+            let yaw_pitch = camera.rig.driver_mut::<YawPitch>();
+            yaw_pitch.yaw_degrees = azimuth;
+            yaw_pitch.pitch_degrees = -elevation;
+        } else {
+            let euler = camera
+                .rig
+                .final_transform
+                .rotation
+                .to_euler(dolly::glam::EulerRot::YXZ);
+            translation_delta =
+                dolly::glam::Quat::from_euler(dolly::glam::EulerRot::YXZ, euler.0, 0.0, 0.0)
+                    * translation_delta;
+
+            translation_delta =
+                translation_delta * camera.translation_speed * altitude_factor * delta_time;
+            rotation_delta = rotation_delta * camera.rotation_speed * delta_time;
+
+            camera
+                .rig
+                .driver_mut::<YawPitch>()
+                .rotate_yaw_pitch(rotation_delta.x, rotation_delta.y);
+            camera
+                .rig
+                .driver_mut::<Position>()
+                .translate(translation_delta);
+        }
     } else {
         for _ in motion_events.iter() {}
     }
 
-    for (mut transform, mut camera) in &mut camera_rig_query {
+    for (_, mut transform, mut camera) in &mut camera_rig_query {
+        let (position_smoothness, rotation_smoothness) =
+            (camera.position_smoothness, camera.rotation_smoothness);
+        let smooth = camera.rig.driver_mut::<Smooth>();
+        smooth.position_smoothness = position_smoothness;
+        smooth.rotation_smoothness = rotation_smoothness;
+
         let (translation, rotation) = camera.rig.update(delta_time).into_position_rotation();
         transform.translation = Vec3::new(translation.x, translation.y, translation.z);
         transform.rotation = Quat::from_array(rotation.to_array());
@@ -0,0 +1,346 @@
+//! Records camera paths from the [`DebugCamera`] and replays them.
+//!
+//! A camera path is a list of keyframes stored in a RON file, e.g.:
+//! ```ron
+//! (
+//!     keyframes: [
+//!         (time: 0.0, position: (0.0, 300.0, 0.0), yaw_degrees: -135.0, pitch_degrees: -20.0),
+//!         (time: 10.0, position: (800.0, 150.0, 800.0), yaw_degrees: -90.0, pitch_degrees: -10.0),
+//!     ],
+//!     easing: EaseInOut,
+//! )
+//! ```
+//!
+//! Paths recorded with the [`CameraPathRecorder`] can be replayed by the recorder for demo videos
+//! or by the [`TerrainBenchmarkPlugin`](crate::benchmark::TerrainBenchmarkPlugin),
+//! so that both use the identical motion.
+
+use crate::debug::camera::DebugCamera;
+use anyhow::Result;
+use bevy::prelude::*;
+use dolly::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+
+/// A keyframe of the [`CameraPath`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraKeyframe {
+    /// The time of the keyframe in seconds since the start of the path.
+    pub time: f32,
+    /// The position of the camera.
+    pub position: [f32; 3],
+    /// The rotation of the camera around the vertical axis.
+    pub yaw_degrees: f32,
+    /// The rotation of the camera around the horizontal axis.
+    pub pitch_degrees: f32,
+}
+
+impl CameraKeyframe {
+    /// Creates a keyframe from the transform of a camera.
+    pub fn from_transform(time: f32, transform: &Transform) -> Self {
+        let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+
+        Self {
+            time,
+            position: transform.translation.into(),
+            yaw_degrees: yaw.to_degrees(),
+            pitch_degrees: pitch.to_degrees(),
+        }
+    }
+
+    fn yaw_pitch(&self) -> Vec3 {
+        Vec3::new(self.yaw_degrees, self.pitch_degrees, 0.0)
+    }
+}
+
+/// The interpolation between the keyframes of a [`CameraPath`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraEasing {
+    /// Moves with a constant speed between two keyframes.
+    #[default]
+    Linear,
+    /// Accelerates after and decelerates before each keyframe.
+    EaseInOut,
+    /// Moves along a smooth Catmull-Rom spline through all keyframes.
+    Spline,
+}
+
+/// A camera path, which interpolates between its keyframes.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// The keyframes sorted by their time.
+    pub keyframes: Vec<CameraKeyframe>,
+    /// The interpolation between the keyframes.
+    #[serde(default)]
+    pub easing: CameraEasing,
+}
+
+impl CameraPath {
+    /// Loads the camera path from a RON file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self> {
+        let mut camera_path: Self = ron::from_str(&fs::read_to_string(path)?)?;
+
+        if camera_path.keyframes.is_empty() {
+            anyhow::bail!("The camera path {path} does not contain any keyframes.");
+        }
+
+        camera_path
+            .keyframes
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(camera_path)
+    }
+
+    /// Saves the camera path into a RON file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(
+            path,
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Camera paths can not be loaded on the web, since there is no file system.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(path: &str) -> Result<Self> {
+        anyhow::bail!(
+            "Can not load the camera path {path}, since there is no file system on the web."
+        )
+    }
+
+    /// Camera paths can not be saved on the web, since there is no file system.
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self, path: &str) -> Result<()> {
+        anyhow::bail!(
+            "Can not save the camera path {path}, since there is no file system on the web."
+        )
+    }
+
+    /// Returns the time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// Returns the transform of the camera at the time.
+    pub fn sample(&self, time: f32) -> Transform {
+        let last = self.keyframes.len() - 1;
+        let next = self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+            .unwrap_or(last);
+        let previous = next.saturating_sub(1);
+
+        let (a, b) = (&self.keyframes[previous], &self.keyframes[next]);
+        let t = if b.time > a.time {
+            ((time - a.time) / (b.time - a.time)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let (position, yaw_pitch) = match self.easing {
+            CameraEasing::Linear => (
+                Vec3::from(a.position).lerp(Vec3::from(b.position), t),
+                a.yaw_pitch().lerp(b.yaw_pitch(), t),
+            ),
+            CameraEasing::EaseInOut => {
+                let t = t * t * (3.0 - 2.0 * t);
+
+                (
+                    Vec3::from(a.position).lerp(Vec3::from(b.position), t),
+                    a.yaw_pitch().lerp(b.yaw_pitch(), t),
+                )
+            }
+            CameraEasing::Spline => {
+                let before = &self.keyframes[previous.saturating_sub(1)];
+                let after = &self.keyframes[(next + 1).min(last)];
+
+                (
+                    catmull_rom(
+                        before.position.into(),
+                        a.position.into(),
+                        b.position.into(),
+                        after.position.into(),
+                        t,
+                    ),
+                    catmull_rom(
+                        before.yaw_pitch(),
+                        a.yaw_pitch(),
+                        b.yaw_pitch(),
+                        after.yaw_pitch(),
+                        t,
+                    ),
+                )
+            }
+        };
+
+        Transform::from_translation(position).with_rotation(Quat::from_euler(
+            EulerRot::YXZ,
+            yaw_pitch.x.to_radians(),
+            yaw_pitch.y.to_radians(),
+            0.0,
+        ))
+    }
+}
+
+/// Evaluates the uniform Catmull-Rom spline between `b` and `c`.
+fn catmull_rom(a: Vec3, b: Vec3, c: Vec3, d: Vec3, t: f32) -> Vec3 {
+    0.5 * (2.0 * b
+        + (c - a) * t
+        + (2.0 * a - 5.0 * b + 4.0 * c - d) * t * t
+        + (3.0 * b - a - 3.0 * c + d) * t * t * t)
+}
+
+/// Records keyframes of the active [`DebugCamera`] and replays the recorded path.
+///
+/// Press the `record_key` to add a keyframe at the current camera transform, the `save_key`
+/// to save the path into the `output` file and the `replay_key` to start or stop replaying it.
+/// The keyframe times are measured from the first keyframe of the recording.
+#[derive(Clone, Resource)]
+pub struct CameraPathRecorder {
+    /// The recorded path.
+    pub path: CameraPath,
+    /// The path of the RON file, into which the recorded path is saved.
+    pub output: String,
+    /// The key, which adds a keyframe.
+    pub record_key: KeyCode,
+    /// The key, which saves the recorded path.
+    pub save_key: KeyCode,
+    /// The key, which starts or stops replaying the recorded path.
+    pub replay_key: KeyCode,
+    /// The time, by which the path is advanced each frame while replaying,
+    /// or `None` to use the elapsed real time.
+    /// A fixed time step reproduces the exact same motion, independent of the frame rate.
+    pub time_step: Option<f32>,
+    /// The time since the startup of the app, when the first keyframe was recorded.
+    start_time: Option<f32>,
+    /// The time along the replayed path.
+    replay_time: Option<f32>,
+}
+
+impl Default for CameraPathRecorder {
+    fn default() -> Self {
+        Self {
+            path: default(),
+            output: "camera_path.ron".to_string(),
+            record_key: KeyCode::R,
+            save_key: KeyCode::F5,
+            replay_key: KeyCode::F6,
+            time_step: None,
+            start_time: None,
+            replay_time: None,
+        }
+    }
+}
+
+impl CameraPathRecorder {
+    /// Discards the recorded keyframes and starts a new recording.
+    pub fn clear(&mut self) {
+        self.path.keyframes.clear();
+        self.start_time = None;
+        self.replay_time = None;
+    }
+
+    /// Adds a keyframe at the time since the startup of the app.
+    pub fn record(&mut self, elapsed_time: f32, transform: &Transform) {
+        let start_time = *self.start_time.get_or_insert(elapsed_time);
+
+        self.path.keyframes.push(CameraKeyframe::from_transform(
+            elapsed_time - start_time,
+            transform,
+        ));
+    }
+
+    /// Starts replaying the path from the beginning.
+    pub fn replay(&mut self, path: CameraPath) {
+        self.path = path;
+        self.replay_time = (!self.path.keyframes.is_empty()).then_some(0.0);
+    }
+
+    /// Stops replaying the path.
+    pub fn stop_replay(&mut self) {
+        self.replay_time = None;
+    }
+
+    /// Returns whether or not the path is being replayed.
+    pub fn is_replaying(&self) -> bool {
+        self.replay_time.is_some()
+    }
+}
+
+/// Records, saves and replays the camera path according to the keys of the recorder.
+pub(crate) fn record_camera_path(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut recorder: ResMut<CameraPathRecorder>,
+    camera_query: Query<(&Transform, &DebugCamera)>,
+) {
+    if keys.just_pressed(recorder.record_key) {
+        if let Some((transform, _)) = camera_query.iter().find(|(_, camera)| camera.active) {
+            recorder.record(time.elapsed_seconds(), transform);
+            info!(
+                "Recorded the keyframe {} of the camera path.",
+                recorder.path.keyframes.len()
+            );
+        }
+    }
+
+    if keys.just_pressed(recorder.save_key) {
+        match recorder.path.save(&recorder.output) {
+            Ok(()) => info!("Saved the camera path into {}.", recorder.output),
+            Err(error) => error!(
+                "Could not save the camera path into {}: {error}",
+                recorder.output
+            ),
+        }
+    }
+
+    if keys.just_pressed(recorder.replay_key) {
+        if recorder.is_replaying() {
+            recorder.stop_replay();
+        } else {
+            let path = recorder.path.clone();
+            recorder.replay(path);
+        }
+    }
+}
+
+/// Moves the active [`DebugCamera`] along the replayed path.
+///
+/// The rig of the camera is moved as well, so that the camera continues from the end of the path.
+pub(crate) fn replay_camera_path(
+    time: Res<Time>,
+    mut recorder: ResMut<CameraPathRecorder>,
+    mut camera_query: Query<(&mut Transform, &mut DebugCamera)>,
+) {
+    let replay_time = match recorder.replay_time {
+        Some(replay_time) => replay_time,
+        None => return,
+    };
+
+    let transform = recorder.path.sample(replay_time);
+    let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+
+    if let Some((mut camera_transform, mut camera)) =
+        camera_query.iter_mut().find(|(_, camera)| camera.active)
+    {
+        *camera_transform = transform;
+
+        let translation = transform.translation;
+        camera.rig.driver_mut::<Position>().position =
+            dolly::glam::Vec3::new(translation.x, translation.y, translation.z);
+
+        let yaw_pitch = camera.rig.driver_mut::<YawPitch>();
+        yaw_pitch.yaw_degrees = yaw.to_degrees();
+        yaw_pitch.pitch_degrees = pitch.to_degrees();
+    }
+
+    let time_step = recorder.time_step.unwrap_or_else(|| time.delta_seconds());
+
+    recorder.replay_time =
+        (replay_time < recorder.path.duration()).then_some(replay_time + time_step);
+}
@@ -0,0 +1,547 @@
+//! Captures the refinement of a single frame for offline analysis and replays it.
+//!
+//! A capture contains the tile list produced by the GPU refinement (read back), the state of
+//! the quadtree, the view config parameters and the camera transform of each terrain view.
+//! It is stored as a RON file, which can be inspected or diffed with any text editor.
+//!
+//! While a capture is replayed, the camera transforms and the view config parameters are
+//! restored each frame and the captured tiles are drawn instead of refining new ones.
+//! Thus the captured geometry is re-rendered deterministically, while the debug flags
+//! can be toggled freely to investigate it. The attachments are still sampled from the
+//! currently loaded nodes.
+
+use crate::{
+    debug::{camera::debug_camera_control, command::VIEW_PARAMETERS},
+    render::terrain_view_data::{TerrainViewConfigUniform, TerrainViewData},
+    skip_none,
+    terrain_data::{
+        node_atlas::update_node_atlas,
+        quadtree::{adjust_quadtree, Quadtree},
+    },
+    terrain_view::{TerrainViewComponents, TerrainViewConfig},
+};
+use anyhow::Result;
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract, RenderApp, RenderStage,
+    },
+    utils::HashMap,
+};
+use bytemuck::cast_slice;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use wgpu::Maintain;
+
+/// The size of a tile in the tile buffer (`vec2<u32>` coordinates and a `u32` size,
+/// padded to the alignment of the coordinates).
+const TILE_STRIDE: BufferAddress = 4 * 4;
+
+/// A tile of the tile list produced by the refinement.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CapturedTile {
+    /// The coordinates of the tile in units of its size.
+    pub coords: [u32; 2],
+    /// The size of the tile.
+    pub size: u32,
+}
+
+/// The state of a quadtree node.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CapturedNode {
+    pub lod: u32,
+    pub x: u32,
+    pub y: u32,
+    /// Whether or not the node is requested by the quadtree.
+    pub requested: bool,
+    /// The lod of the best loaded node covering this one.
+    pub loaded_lod: Option<u32>,
+}
+
+/// The captured state of a single terrain view.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapturedView {
+    /// The translation of the view.
+    pub translation: [f32; 3],
+    /// The rotation of the view as a quaternion.
+    pub rotation: [f32; 4],
+    /// The view config parameters by name.
+    pub parameters: Vec<(String, String)>,
+    /// The position of the viewer used for the refinement.
+    pub viewer_position: [f32; 3],
+    /// The nodes of all lods of the quadtree.
+    pub quadtree: Vec<CapturedNode>,
+    /// The tiles drawn by the view.
+    pub tiles: Vec<CapturedTile>,
+}
+
+/// The captured state of all terrain views in a frame.
+///
+/// The views are stored in the order of their (terrain, view) entities.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CapturedFrame {
+    /// The time since the startup of the app, when the frame was captured.
+    pub time: f32,
+    pub views: Vec<CapturedView>,
+}
+
+impl CapturedFrame {
+    /// Loads a capture from a RON file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self> {
+        Ok(ron::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Saves the capture into a RON file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(
+            path,
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Captures can not be loaded on the web, since there is no file system.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(path: &str) -> Result<Self> {
+        anyhow::bail!("Can not load the capture {path}, since there is no file system on the web.")
+    }
+
+    /// Captures can not be saved on the web, since there is no file system.
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self, path: &str) -> Result<()> {
+        anyhow::bail!("Can not save the capture {path}, since there is no file system on the web.")
+    }
+}
+
+/// The tile lists read back by the render world.
+type CapturedTiles = HashMap<(Entity, Entity), Vec<CapturedTile>>;
+
+/// Controls the capturing and replaying of frames.
+#[derive(Default, Resource)]
+pub struct FrameCapture {
+    /// The path, into which the current frame will be captured.
+    requested: Option<String>,
+    /// The capture, that is waiting for its tiles to be read back.
+    pending: Option<(String, CapturedFrame)>,
+    /// Whether or not the tiles should be read back this frame.
+    read_back: bool,
+    /// The capture being replayed and the (terrain, view) pairs it is applied to.
+    replay: Option<(Arc<CapturedFrame>, Vec<(Entity, Entity)>)>,
+    /// Shared with the render world, which stores the read back tiles.
+    finished: Arc<Mutex<Option<CapturedTiles>>>,
+}
+
+impl FrameCapture {
+    /// Captures the current frame into the RON file at the path.
+    pub fn capture(&mut self, path: impl Into<String>) {
+        self.requested = Some(path.into());
+    }
+
+    /// Starts replaying the capture stored in the RON file at the path.
+    pub fn replay(&mut self, path: &str) -> Result<()> {
+        self.replay = Some((Arc::new(CapturedFrame::load(path)?), Vec::new()));
+
+        Ok(())
+    }
+
+    /// Stops replaying the capture and resumes the refinement.
+    pub fn stop_replay(&mut self) {
+        self.replay = None;
+    }
+
+    /// Returns whether or not a capture is being replayed.
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+}
+
+/// Adds the [`FrameCapture`] resource, which captures and replays the refinement of a frame.
+///
+/// Has to be added after the [`TerrainPlugin`](crate::TerrainPlugin).
+pub struct TerrainCapturePlugin;
+
+impl Plugin for TerrainCapturePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameCapture>()
+            .add_system(apply_replay.after(debug_camera_control))
+            .add_system_to_stage(
+                CoreStage::Last,
+                start_frame_capture
+                    .after(update_node_atlas)
+                    .after(adjust_quadtree),
+            )
+            .add_system_to_stage(CoreStage::Last, finish_frame_capture);
+
+        app.sub_app_mut(RenderApp)
+            .init_resource::<GpuFrameCapture>()
+            .add_system_to_stage(RenderStage::Extract, extract_frame_capture)
+            .add_system_to_stage(RenderStage::Queue, queue_frame_capture);
+    }
+}
+
+/// Restores the camera transforms and view config parameters of the replayed capture.
+fn apply_replay(
+    mut frame_capture: ResMut<FrameCapture>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    mut view_query: Query<&mut Transform>,
+) {
+    let (frame, keys) = match &mut frame_capture.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+
+    if keys.is_empty() {
+        let mut view_keys: Vec<_> = view_configs.0.keys().copied().collect();
+        view_keys.sort();
+
+        if view_keys.len() != frame.views.len() {
+            warn!(
+                "The capture contains {} views, but there are {} terrain views. Only the first ones are replayed.",
+                frame.views.len(),
+                view_keys.len()
+            );
+        }
+
+        view_keys.truncate(frame.views.len());
+        *keys = view_keys;
+    }
+
+    for (key, captured_view) in keys.iter().zip(&frame.views) {
+        if let Ok(mut transform) = view_query.get_mut(key.1) {
+            transform.translation = captured_view.translation.into();
+            transform.rotation = Quat::from_array(captured_view.rotation);
+        }
+
+        if let Some(view_config) = view_configs.get_mut(key) {
+            for (name, value) in &captured_view.parameters {
+                if view_config.parameter(name).as_ref() != Some(value) {
+                    view_config.set_parameter(name, value).unwrap();
+                }
+            }
+        }
+    }
+}
+
+/// Records the CPU side state of the requested capture and requests the readback of the tiles.
+fn start_frame_capture(
+    time: Res<Time>,
+    mut frame_capture: ResMut<FrameCapture>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+    view_query: Query<&Transform>,
+) {
+    frame_capture.read_back = false;
+
+    if frame_capture.pending.is_some() {
+        return; // only one capture is read back at a time
+    }
+
+    let path = match frame_capture.requested.take() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let mut keys: Vec<_> = view_configs.0.keys().copied().collect();
+    keys.sort();
+
+    let views = keys
+        .iter()
+        .map(|key| {
+            let view_config = view_configs.get(key).unwrap();
+            let transform = view_query.get(key.1).copied().unwrap_or_default();
+
+            let quadtree = quadtrees.get(key).map_or_else(Vec::new, |quadtree| {
+                (0..quadtree.lod_count)
+                    .flat_map(|lod| {
+                        quadtree
+                            .layer_states(lod)
+                            .map(move |(node, requested, loaded_lod)| CapturedNode {
+                                lod,
+                                x: node.x,
+                                y: node.y,
+                                requested,
+                                loaded_lod,
+                            })
+                    })
+                    .collect()
+            });
+
+            CapturedView {
+                translation: transform.translation.into(),
+                rotation: transform.rotation.to_array(),
+                parameters: VIEW_PARAMETERS
+                    .iter()
+                    .map(|&name| (name.to_string(), view_config.parameter(name).unwrap()))
+                    .collect(),
+                viewer_position: view_config.viewer_position.into(),
+                quadtree,
+                tiles: Vec::new(),
+            }
+        })
+        .collect();
+
+    frame_capture.pending = Some((
+        path,
+        CapturedFrame {
+            time: time.elapsed_seconds(),
+            views,
+        },
+    ));
+    frame_capture.read_back = true;
+}
+
+/// Completes the pending capture with the read back tiles and saves it.
+fn finish_frame_capture(
+    mut frame_capture: ResMut<FrameCapture>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    let mut tiles = match frame_capture.finished.lock().unwrap().take() {
+        Some(tiles) => tiles,
+        None => return,
+    };
+
+    let (path, mut frame) = match frame_capture.pending.take() {
+        Some(pending) => pending,
+        None => return,
+    };
+
+    let mut keys: Vec<_> = view_configs.0.keys().copied().collect();
+    keys.sort();
+
+    for (key, view) in keys.iter().zip(&mut frame.views) {
+        view.tiles = tiles.remove(key).unwrap_or_default();
+    }
+
+    match frame.save(&path) {
+        Ok(()) => info!("Captured the frame into {path}."),
+        Err(error) => error!("Could not save the capture into {path}: {error}"),
+    }
+}
+
+/// The staging buffers of a single view.
+struct ViewReadback {
+    key: (Entity, Entity),
+    tile_buffer: Buffer,
+    tile_buffer_size: BufferAddress,
+    vertex_count_buffer: Buffer,
+}
+
+/// The staging buffers, into which the tile lists of all views are copied.
+struct CaptureReadback {
+    views: Vec<ViewReadback>,
+    /// Counts the buffers, that have been mapped.
+    mapped: Arc<AtomicUsize>,
+}
+
+impl CaptureReadback {
+    /// Starts mapping the staging buffers, after the copies have been submitted.
+    fn map(&self) {
+        for view in &self.views {
+            for buffer in [&view.tile_buffer, &view.vertex_count_buffer] {
+                let mapped = self.mapped.clone();
+
+                buffer.slice(..).map_async(MapMode::Read, move |result| {
+                    if result.is_ok() {
+                        mapped.fetch_add(1, Ordering::Release);
+                    }
+                });
+            }
+        }
+    }
+
+    fn is_mapped(&self) -> bool {
+        self.mapped.load(Ordering::Acquire) == 2 * self.views.len()
+    }
+}
+
+/// Stores the render world side of the [`FrameCapture`].
+#[derive(Default, Resource)]
+pub struct GpuFrameCapture {
+    /// Whether or not the tiles should be read back this frame.
+    read_back: bool,
+    /// The readback, whose copies are recorded this frame.
+    recording: Option<CaptureReadback>,
+    /// The readback, whose copies have been submitted and which is being mapped.
+    in_flight: Option<CaptureReadback>,
+    /// The tiles of the replayed capture by (terrain, view).
+    replay: Vec<((Entity, Entity), Arc<CapturedFrame>, usize)>,
+    finished: Arc<Mutex<Option<CapturedTiles>>>,
+}
+
+impl GpuFrameCapture {
+    /// Returns whether or not the tiles of the view are replayed instead of refined.
+    pub(crate) fn is_replayed(&self, key: &(Entity, Entity)) -> bool {
+        self.replay.iter().any(|(replay_key, ..)| replay_key == key)
+    }
+
+    /// Copies the tile lists of all views into the staging buffers of the readback,
+    /// after the refinement has finished.
+    pub(crate) fn copy(
+        &self,
+        command_encoder: &mut CommandEncoder,
+        terrain_view_data: &TerrainViewComponents<TerrainViewData>,
+    ) {
+        let readback = match &self.recording {
+            Some(readback) => readback,
+            None => return,
+        };
+
+        for view in &readback.views {
+            if let Some(view_data) = terrain_view_data.get(&view.key) {
+                command_encoder.copy_buffer_to_buffer(
+                    &view_data.final_tile_buffer,
+                    0,
+                    &view.tile_buffer,
+                    0,
+                    view.tile_buffer_size,
+                );
+                // the first two entries of the indirect buffer are the vertex (or index) count
+                // and the instance count of the draw
+                command_encoder.copy_buffer_to_buffer(
+                    &view_data.indirect_buffer,
+                    view_data.indirect_offset,
+                    &view.vertex_count_buffer,
+                    0,
+                    8,
+                );
+            }
+        }
+    }
+
+    /// Moves the tiles of the mapped readback into the shared tiles.
+    fn finish(&mut self, view_config_uniforms: &TerrainViewComponents<TerrainViewConfigUniform>) {
+        let readback = match &self.in_flight {
+            Some(readback) if readback.is_mapped() => readback,
+            _ => return,
+        };
+
+        let mut tiles = CapturedTiles::default();
+
+        for view in &readback.views {
+            let [count, instance_count] =
+                cast_slice::<u8, [u32; 2]>(&view.vertex_count_buffer.slice(..).get_mapped_range())
+                    [0];
+            let vertex_count = count * instance_count;
+            let vertices_per_tile = view_config_uniforms
+                .get(&view.key)
+                .map_or(1, |view_config| view_config.vertices_per_tile.max(1));
+            let tile_count = (vertex_count / vertices_per_tile) as usize;
+
+            let view_tiles =
+                cast_slice::<u8, [u32; 4]>(&view.tile_buffer.slice(..).get_mapped_range())
+                    .iter()
+                    .take(tile_count)
+                    .map(|&[x, y, size, _]| CapturedTile {
+                        coords: [x, y],
+                        size,
+                    })
+                    .collect();
+
+            tiles.insert(view.key, view_tiles);
+
+            view.tile_buffer.unmap();
+            view.vertex_count_buffer.unmap();
+        }
+
+        *self.finished.lock().unwrap() = Some(tiles);
+        self.in_flight = None;
+    }
+}
+
+fn extract_frame_capture(
+    mut gpu_frame_capture: ResMut<GpuFrameCapture>,
+    frame_capture: Extract<Res<FrameCapture>>,
+) {
+    gpu_frame_capture.read_back = frame_capture.read_back;
+    gpu_frame_capture.finished = frame_capture.finished.clone();
+    gpu_frame_capture.replay = match &frame_capture.replay {
+        Some((frame, keys)) => keys
+            .iter()
+            .enumerate()
+            .map(|(index, &key)| (key, frame.clone(), index))
+            .collect(),
+        None => Vec::new(),
+    };
+}
+
+/// Writes the replayed tiles into the tile buffers and manages the readback of the tiles.
+fn queue_frame_capture(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    view_config_uniforms: Res<TerrainViewComponents<TerrainViewConfigUniform>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    mut gpu_frame_capture: ResMut<GpuFrameCapture>,
+) {
+    for (key, frame, index) in &gpu_frame_capture.replay {
+        let view_data = skip_none!(terrain_view_data.get(key));
+        let view_config = skip_none!(view_config_uniforms.get(key));
+
+        let tiles: Vec<[u32; 4]> = frame.views[*index]
+            .tiles
+            .iter()
+            .take(view_config.tile_count as usize)
+            .map(|tile| [tile.coords[0], tile.coords[1], tile.size, 0])
+            .collect();
+        let draw_arguments = view_config.draw_arguments(tiles.len() as u32);
+
+        queue.write_buffer(&view_data.final_tile_buffer, 0, cast_slice(&tiles));
+        queue.write_buffer(
+            &view_data.indirect_buffer,
+            view_data.indirect_offset,
+            cast_slice(&draw_arguments),
+        );
+    }
+
+    device.wgpu_device().poll(Maintain::Poll);
+
+    gpu_frame_capture.finish(&view_config_uniforms);
+
+    // the readback recorded last frame has been submitted by now
+    if let Some(readback) = gpu_frame_capture.recording.take() {
+        readback.map();
+        gpu_frame_capture.in_flight = Some(readback);
+    }
+
+    if !gpu_frame_capture.read_back {
+        return;
+    }
+
+    let create_buffer = |label: &str, size: BufferAddress| {
+        device.create_buffer(&BufferDescriptor {
+            label: label.into(),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    };
+
+    let views = view_config_uniforms
+        .0
+        .iter()
+        .map(|(&key, view_config)| {
+            let tile_buffer_size = TILE_STRIDE * view_config.tile_count as BufferAddress;
+
+            ViewReadback {
+                key,
+                tile_buffer: create_buffer("capture_tile_staging_buffer", tile_buffer_size),
+                tile_buffer_size,
+                vertex_count_buffer: create_buffer("capture_vertex_count_staging_buffer", 8),
+            }
+        })
+        .collect();
+
+    gpu_frame_capture.recording = Some(CaptureReadback {
+        views,
+        mapped: default(),
+    });
+}
@@ -0,0 +1,234 @@
+//! A simple string based command interface for the debug flags and the view config parameters.
+//!
+//! Commands can either be sent as [`TerrainCommand`] events or be executed directly using
+//! [`execute_command`], e.g. from the command handler of a console like `bevy_console`.
+//!
+//! The following commands are supported:
+//! * `toggle <flag>` - toggles a debug flag
+//! * `set <flag> <on|off>` - enables or disables a debug flag
+//! * `set <parameter> <value>` - sets a view config parameter of all terrain views
+//! * `get <flag|parameter>` - prints the current value
+//! * `help` - lists all flags and parameters
+
+use crate::{debug::DebugTerrain, TerrainViewComponents, TerrainViewConfig};
+use anyhow::{anyhow, Result};
+use bevy::prelude::*;
+
+/// The names of all debug flags.
+pub const DEBUG_FLAGS: [&str; 25] = [
+    "wireframe",
+    "show_tiles",
+    "show_lod",
+    "show_uv",
+    "show_nodes",
+    "show_minmax_error",
+    "minmax",
+    "mesh_morph",
+    "albedo",
+    "bright",
+    "lighting",
+    "sample_grad",
+    "freeze",
+    "freeze_view",
+    "test1",
+    "test2",
+    "test3",
+    "show_slope",
+    "show_aspect",
+    "show_hillshade",
+    "show_height_difference",
+    "show_height",
+    "show_flow",
+    "show_watersheds",
+    "show_node_bounds",
+];
+
+/// The names of all view config parameters.
+pub const VIEW_PARAMETERS: [&str; 12] = [
+    "load_distance",
+    "refinement_count",
+    "additional_refinement",
+    "tile_scale",
+    "grid_size",
+    "view_distance",
+    "morph_range",
+    "blend_range",
+    "min_triangle_size",
+    "sun_azimuth",
+    "sun_elevation",
+    "difference_range",
+];
+
+/// An event, which executes a debug command.
+pub struct TerrainCommand(pub String);
+
+impl DebugTerrain {
+    /// Returns the debug flag with the name.
+    pub fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "wireframe" => &mut self.wireframe,
+            "show_tiles" => &mut self.show_tiles,
+            "show_lod" => &mut self.show_lod,
+            "show_uv" => &mut self.show_uv,
+            "show_nodes" => &mut self.show_nodes,
+            "show_minmax_error" => &mut self.show_minmax_error,
+            "minmax" => &mut self.minmax,
+            "mesh_morph" => &mut self.mesh_morph,
+            "albedo" => &mut self.albedo,
+            "bright" => &mut self.bright,
+            "lighting" => &mut self.lighting,
+            "sample_grad" => &mut self.sample_grad,
+            "freeze" => &mut self.freeze,
+            "freeze_view" => &mut self.freeze_view,
+            "test1" => &mut self.test1,
+            "test2" => &mut self.test2,
+            "test3" => &mut self.test3,
+            "show_slope" => &mut self.show_slope,
+            "show_aspect" => &mut self.show_aspect,
+            "show_hillshade" => &mut self.show_hillshade,
+            "show_height_difference" => &mut self.show_height_difference,
+            "show_height" => &mut self.show_height,
+            "show_flow" => &mut self.show_flow,
+            "show_watersheds" => &mut self.show_watersheds,
+            "show_node_bounds" => &mut self.show_node_bounds,
+            _ => return None,
+        })
+    }
+}
+
+impl TerrainViewConfig {
+    /// Sets the view config parameter with the name to the value.
+    pub fn set_parameter(&mut self, name: &str, value: &str) -> Result<()> {
+        let parse_f32 = |value: &str| {
+            value
+                .parse::<f32>()
+                .map_err(|_| anyhow!("Expected a number, got {value}."))
+        };
+        let parse_u32 = |value: &str| {
+            value
+                .parse::<u32>()
+                .map_err(|_| anyhow!("Expected a positive integer, got {value}."))
+        };
+
+        match name {
+            "load_distance" => self.load_distance = parse_f32(value)?,
+            "refinement_count" => self.refinement_count = parse_u32(value)?,
+            "additional_refinement" => self.additional_refinement = parse_u32(value)?,
+            "tile_scale" => self.tile_scale = parse_f32(value)?,
+            "grid_size" => self.grid_size = parse_u32(value)?,
+            "view_distance" => self.view_distance = parse_f32(value)?,
+            "morph_range" => self.morph_range = parse_f32(value)?,
+            "blend_range" => self.blend_range = parse_f32(value)?,
+            "min_triangle_size" => self.min_triangle_size = parse_f32(value)?,
+            "sun_azimuth" => self.sun_azimuth = parse_f32(value)?,
+            "sun_elevation" => self.sun_elevation = parse_f32(value)?,
+            "difference_range" => self.difference_range = parse_f32(value)?,
+            _ => return Err(anyhow!("Unknown parameter {name}.")),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of the view config parameter with the name.
+    pub fn parameter(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "load_distance" => self.load_distance.to_string(),
+            "refinement_count" => self.refinement_count.to_string(),
+            "additional_refinement" => self.additional_refinement.to_string(),
+            "tile_scale" => self.tile_scale.to_string(),
+            "grid_size" => self.grid_size.to_string(),
+            "view_distance" => self.view_distance.to_string(),
+            "morph_range" => self.morph_range.to_string(),
+            "blend_range" => self.blend_range.to_string(),
+            "min_triangle_size" => self.min_triangle_size.to_string(),
+            "sun_azimuth" => self.sun_azimuth.to_string(),
+            "sun_elevation" => self.sun_elevation.to_string(),
+            "difference_range" => self.difference_range.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool> {
+    match value {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(anyhow!("Expected on or off, got {value}.")),
+    }
+}
+
+/// Executes the command and returns a message describing the result.
+pub fn execute_command(
+    command: &str,
+    debug: &mut DebugTerrain,
+    view_configs: &mut TerrainViewComponents<TerrainViewConfig>,
+) -> Result<String> {
+    let arguments: Vec<&str> = command.split_whitespace().collect();
+
+    match arguments.as_slice() {
+        ["help"] => Ok(format!(
+            "Flags: {}\nParameters: {}",
+            DEBUG_FLAGS.join(", "),
+            VIEW_PARAMETERS.join(", ")
+        )),
+        ["toggle", name] => {
+            let flag = debug
+                .flag_mut(name)
+                .ok_or(anyhow!("Unknown flag {name}."))?;
+            *flag = !*flag;
+
+            Ok(format!("Set {name} to {flag}."))
+        }
+        ["set", name, value] => {
+            if let Some(flag) = debug.flag_mut(name) {
+                *flag = parse_switch(value)?;
+
+                return Ok(format!("Set {name} to {flag}."));
+            }
+
+            // validated up front, since there may be no views to reject the name
+            if !VIEW_PARAMETERS.contains(name) {
+                return Err(anyhow!("Unknown flag or parameter {name}."));
+            }
+
+            for view_config in view_configs.0.values_mut() {
+                view_config.set_parameter(name, value)?;
+            }
+
+            Ok(format!("Set {name} to {value}."))
+        }
+        ["get", name] => {
+            if let Some(flag) = debug.flag_mut(name) {
+                return Ok(format!("{name} is {flag}."));
+            }
+
+            let values: Vec<String> = view_configs
+                .0
+                .values()
+                .filter_map(|view_config| view_config.parameter(name))
+                .collect();
+
+            if values.is_empty() && !VIEW_PARAMETERS.contains(name) {
+                return Err(anyhow!("Unknown flag or parameter {name}."));
+            }
+
+            Ok(format!("{name} is {}.", values.join(", ")))
+        }
+        _ => Err(anyhow!(
+            "Invalid command {command}. Type help for a list of flags and parameters."
+        )),
+    }
+}
+
+pub(crate) fn handle_terrain_commands(
+    mut commands: EventReader<TerrainCommand>,
+    mut debug: ResMut<DebugTerrain>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    for TerrainCommand(command) in commands.iter() {
+        match execute_command(command, &mut debug, &mut view_configs) {
+            Ok(message) => println!("{message}"),
+            Err(error) => println!("{error}"),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! The key bindings of the [`DebugAction`]s.
+
+use crate::debug::DebugAction;
+use bevy::prelude::*;
+
+/// Maps keys to [`DebugAction`]s.
+///
+/// Modify this resource to rebind the actions, or replace it with [`DebugInputMap::disabled`]
+/// to ignore the keyboard altogether.
+#[derive(Clone, Debug, Resource)]
+pub struct DebugInputMap {
+    /// Whether or not the key bindings are active.
+    pub enabled: bool,
+    /// The key bindings. Multiple keys may trigger the same action.
+    pub bindings: Vec<(KeyCode, DebugAction)>,
+}
+
+impl Default for DebugInputMap {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bindings: vec![
+                (KeyCode::W, DebugAction::Toggle("wireframe")),
+                (KeyCode::P, DebugAction::Toggle("show_tiles")),
+                (KeyCode::L, DebugAction::Toggle("show_lod")),
+                (KeyCode::U, DebugAction::Toggle("show_uv")),
+                (KeyCode::C, DebugAction::Toggle("show_nodes")),
+                (KeyCode::Y, DebugAction::Toggle("show_minmax_error")),
+                (KeyCode::M, DebugAction::Toggle("minmax")),
+                (KeyCode::D, DebugAction::Toggle("mesh_morph")),
+                (KeyCode::A, DebugAction::Toggle("albedo")),
+                (KeyCode::B, DebugAction::Toggle("bright")),
+                (KeyCode::S, DebugAction::Toggle("lighting")),
+                (KeyCode::G, DebugAction::Toggle("sample_grad")),
+                (KeyCode::F, DebugAction::Toggle("freeze")),
+                (KeyCode::K, DebugAction::Toggle("freeze_view")),
+                (KeyCode::Key1, DebugAction::Toggle("test1")),
+                (KeyCode::Key2, DebugAction::Toggle("test2")),
+                (KeyCode::Key3, DebugAction::Toggle("test3")),
+                (KeyCode::Key4, DebugAction::Toggle("show_slope")),
+                (KeyCode::Key5, DebugAction::Toggle("show_aspect")),
+                (KeyCode::Key6, DebugAction::Toggle("show_hillshade")),
+                (KeyCode::Key7, DebugAction::Toggle("show_height_difference")),
+                (KeyCode::Key8, DebugAction::Toggle("show_height")),
+                (KeyCode::Key0, DebugAction::Toggle("show_flow")),
+                (KeyCode::Minus, DebugAction::Toggle("show_watersheds")),
+                (KeyCode::Equals, DebugAction::Toggle("show_node_bounds")),
+                (KeyCode::H, DebugAction::DecreaseTileScale),
+                (KeyCode::J, DebugAction::IncreaseTileScale),
+                (KeyCode::I, DebugAction::DecreaseViewDistance),
+                (KeyCode::O, DebugAction::IncreaseViewDistance),
+                (KeyCode::N, DebugAction::DecreaseGridSize),
+                (KeyCode::E, DebugAction::IncreaseGridSize),
+                (KeyCode::Comma, DebugAction::RotateSunLeft),
+                (KeyCode::Period, DebugAction::RotateSunRight),
+                (KeyCode::Key9, DebugAction::ToggleBathymetry),
+            ],
+        }
+    }
+}
+
+impl DebugInputMap {
+    /// Creates an input map without any key bindings.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Binds the key to the action, replacing its previous binding.
+    pub fn bind(&mut self, key: KeyCode, action: DebugAction) {
+        self.unbind(key);
+        self.bindings.push((key, action));
+    }
+
+    /// Removes the binding of the key.
+    pub fn unbind(&mut self, key: KeyCode) {
+        self.bindings.retain(|&(bound_key, _)| bound_key != key);
+    }
+}
+
+/// Sends the actions of the pressed keys.
+pub(crate) fn map_debug_input(
+    input: Res<Input<KeyCode>>,
+    input_map: Res<DebugInputMap>,
+    mut actions: EventWriter<DebugAction>,
+) {
+    if !input_map.enabled {
+        return;
+    }
+
+    for &(key, action) in &input_map.bindings {
+        if input.just_pressed(key) {
+            actions.send(action);
+        }
+    }
+}
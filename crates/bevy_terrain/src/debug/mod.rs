@@ -1,22 +1,51 @@
 //! Contains a debug resource and systems controlling it to visualize different internal
 //! data of the plugin.
-use crate::{debug::camera::debug_camera_control, TerrainViewComponents, TerrainViewConfig};
+use crate::{
+    debug::{
+        camera::debug_camera_control,
+        camera_path::{record_camera_path, replay_camera_path, CameraPathRecorder},
+        command::{handle_terrain_commands, TerrainCommand},
+        input::{map_debug_input, DebugInputMap},
+        node_bounds::draw_node_bounds,
+    },
+    terrain_view::BathymetryMode,
+    TerrainViewComponents, TerrainViewConfig,
+};
 use bevy::{
     prelude::*,
     render::{Extract, RenderApp, RenderStage},
 };
 
 pub mod camera;
+pub mod camera_path;
+pub mod capture;
+pub mod command;
+pub mod input;
+pub mod node_bounds;
+#[cfg(feature = "debug_ui")]
+pub mod ui;
 
-/// Adds a terrain debug config, a debug camera and debug control systems.
+/// Adds a terrain debug config, a debug camera, a camera path recorder and debug control systems.
+///
+/// The debug flags are controlled by [`DebugAction`] events, which are sent by the
+/// [`DebugInputMap`]. Insert [`DebugInputMap::disabled`] before adding the plugin,
+/// to remove all key bindings, e.g. in end-user builds.
 pub struct TerrainDebugPlugin;
 
 impl Plugin for TerrainDebugPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<DebugTerrain>()
+            .init_resource::<DebugInputMap>()
+            .init_resource::<CameraPathRecorder>()
+            .add_event::<TerrainCommand>()
+            .add_event::<DebugAction>()
             .add_system(debug_camera_control)
-            .add_system(toggle_debug)
-            .add_system(change_config)
+            .add_system(record_camera_path.before(replay_camera_path))
+            .add_system(replay_camera_path.after(debug_camera_control))
+            .add_system(handle_terrain_commands)
+            .add_system(map_debug_input.before(handle_debug_actions))
+            .add_system(handle_debug_actions)
+            .add_system_to_stage(CoreStage::PostUpdate, draw_node_bounds)
             .sub_app_mut(RenderApp)
             .init_resource::<DebugTerrain>()
             .add_system_to_stage(RenderStage::Extract, extract_debug);
@@ -38,9 +67,18 @@ pub struct DebugTerrain {
     pub lighting: bool,
     pub sample_grad: bool,
     pub freeze: bool,
+    pub freeze_view: bool,
     pub test1: bool,
     pub test2: bool,
     pub test3: bool,
+    pub show_slope: bool,
+    pub show_aspect: bool,
+    pub show_hillshade: bool,
+    pub show_height_difference: bool,
+    pub show_height: bool,
+    pub show_flow: bool,
+    pub show_watersheds: bool,
+    pub show_node_bounds: bool,
 }
 
 impl Default for DebugTerrain {
@@ -59,9 +97,18 @@ impl Default for DebugTerrain {
             lighting: true,
             sample_grad: true,
             freeze: false,
+            freeze_view: false,
             test1: false,
             test2: false,
             test3: true,
+            show_slope: false,
+            show_aspect: false,
+            show_hillshade: false,
+            show_height_difference: false,
+            show_height: false,
+            show_flow: false,
+            show_watersheds: false,
+            show_node_bounds: false,
         }
     }
 }
@@ -70,157 +117,113 @@ pub fn extract_debug(mut debug: ResMut<DebugTerrain>, extracted_debug: Extract<R
     *debug = extracted_debug.clone();
 }
 
-pub fn toggle_debug(input: Res<Input<KeyCode>>, mut debug: ResMut<DebugTerrain>) {
-    if input.just_pressed(KeyCode::W) {
-        debug.wireframe = !debug.wireframe;
-        println!(
-            "Toggled the wireframe view {}.",
-            if debug.wireframe { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::P) {
-        debug.show_tiles = !debug.show_tiles;
-        println!(
-            "Toggled the tile view {}.",
-            if debug.show_tiles { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::L) {
-        debug.show_lod = !debug.show_lod;
-        println!(
-            "Toggled the lod view {}.",
-            if debug.show_lod { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::U) {
-        debug.show_uv = !debug.show_uv;
-        println!(
-            "Toggled the uv view {}.",
-            if debug.show_uv { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::C) {
-        debug.show_nodes = !debug.show_nodes;
-        println!(
-            "Toggled the node view {}.",
-            if debug.show_nodes { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::Y) {
-        debug.show_minmax_error = !debug.show_minmax_error;
-        println!(
-            "Toggled the minmax error view {}.",
-            if debug.show_minmax_error { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::M) {
-        debug.minmax = !debug.minmax;
-        println!(
-            "Toggled the minmax view {}.",
-            if debug.minmax { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::D) {
-        debug.mesh_morph = !debug.mesh_morph;
-        println!(
-            "Toggled the mesh morph {}.",
-            if debug.mesh_morph { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::A) {
-        debug.albedo = !debug.albedo;
-        println!(
-            "Toggled the albedo {}.",
-            if debug.albedo { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::B) {
-        debug.bright = !debug.bright;
-        println!(
-            "Toggled the base color to {}.",
-            if debug.bright { "white" } else { "black" }
-        )
-    }
-    if input.just_pressed(KeyCode::S) {
-        debug.lighting = !debug.lighting;
-        println!(
-            "Toggled the lighting {}.",
-            if debug.lighting { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::G) {
-        debug.sample_grad = !debug.sample_grad;
-        println!(
-            "Toggled the texture sampling using gradients {}.",
-            if debug.sample_grad { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::F) {
-        debug.freeze = !debug.freeze;
-        println!(
-            "{} the view frustum.",
-            if debug.freeze { "Froze" } else { "Unfroze" }
-        )
-    }
-    if input.just_pressed(KeyCode::Key1) {
-        debug.test1 = !debug.test1;
-        println!(
-            "Toggled the debug flag 1 {}.",
-            if debug.test1 { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::Key2) {
-        debug.test2 = !debug.test2;
-        println!(
-            "Toggled the debug flag 2 {}.",
-            if debug.test2 { "on" } else { "off" }
-        )
-    }
-    if input.just_pressed(KeyCode::Key3) {
-        debug.test3 = !debug.test3;
-        println!(
-            "Toggled the debug flag 3 {}.",
-            if debug.test3 { "on" } else { "off" }
-        )
-    }
+/// An action, which changes the debug flags or the view config parameters of all terrain views.
+///
+/// Send it as an event to apply it, e.g. from a custom input handler or UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Toggles the debug flag with the name (see [`DEBUG_FLAGS`](command::DEBUG_FLAGS)).
+    Toggle(&'static str),
+    /// Halves the tile scale.
+    DecreaseTileScale,
+    /// Doubles the tile scale.
+    IncreaseTileScale,
+    /// Decreases the view distance by a quarter node.
+    DecreaseViewDistance,
+    /// Increases the view distance by a quarter node.
+    IncreaseViewDistance,
+    /// Decreases the grid size by two.
+    DecreaseGridSize,
+    /// Increases the grid size by two.
+    IncreaseGridSize,
+    /// Rotates the sun azimuth of the analysis views by -15 degrees.
+    RotateSunLeft,
+    /// Rotates the sun azimuth of the analysis views by 15 degrees.
+    RotateSunRight,
+    /// Switches between rendering the topography and the topography with the seabed.
+    ToggleBathymetry,
 }
 
-pub fn change_config(
-    input: Res<Input<KeyCode>>,
-    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
-) {
-    for mut view_config in &mut view_configs.0.values_mut() {
-        if input.just_pressed(KeyCode::H) && view_config.tile_scale > 0.25 {
-            view_config.tile_scale /= 2.0;
-            println!("Decreased the tile scale to {}.", view_config.tile_scale);
-        }
-        if input.just_pressed(KeyCode::J) {
-            view_config.tile_scale *= 2.0;
-            println!("Increased the tile scale to {}.", view_config.tile_scale)
+impl DebugAction {
+    /// Applies the action and returns a message describing the result.
+    pub fn apply(
+        self,
+        debug: &mut DebugTerrain,
+        view_configs: &mut TerrainViewComponents<TerrainViewConfig>,
+    ) -> String {
+        if let DebugAction::Toggle(name) = self {
+            return match debug.flag_mut(name) {
+                Some(flag) => {
+                    *flag = !*flag;
+                    format!("Toggled {name} {}.", if *flag { "on" } else { "off" })
+                }
+                None => format!("Unknown flag {name}."),
+            };
         }
 
-        if input.just_pressed(KeyCode::I) {
-            view_config.view_distance -= 0.25;
-            println!(
-                "Decreased the view distance to {}.",
-                view_config.view_distance
-            );
-        }
-        if input.just_pressed(KeyCode::O) {
-            view_config.view_distance += 0.25;
-            println!(
-                "Increased the view distance to {}.",
-                view_config.view_distance
-            );
-        }
+        let mut message = String::new();
 
-        if input.just_pressed(KeyCode::N) && view_config.grid_size > 2 {
-            view_config.grid_size -= 2;
-            println!("Decreased the grid size to {}.", view_config.grid_size);
-        }
-        if input.just_pressed(KeyCode::E) {
-            view_config.grid_size += 2;
-            println!("Increased the grid size to {}.", view_config.grid_size);
+        for view_config in view_configs.0.values_mut() {
+            message = match self {
+                DebugAction::Toggle(_) => unreachable!(),
+                DebugAction::DecreaseTileScale => {
+                    view_config.tile_scale = (view_config.tile_scale / 2.0).max(0.25);
+                    format!("Decreased the tile scale to {}.", view_config.tile_scale)
+                }
+                DebugAction::IncreaseTileScale => {
+                    view_config.tile_scale *= 2.0;
+                    format!("Increased the tile scale to {}.", view_config.tile_scale)
+                }
+                DebugAction::DecreaseViewDistance => {
+                    view_config.view_distance -= 0.25;
+                    format!(
+                        "Decreased the view distance to {}.",
+                        view_config.view_distance
+                    )
+                }
+                DebugAction::IncreaseViewDistance => {
+                    view_config.view_distance += 0.25;
+                    format!(
+                        "Increased the view distance to {}.",
+                        view_config.view_distance
+                    )
+                }
+                DebugAction::DecreaseGridSize => {
+                    view_config.grid_size = view_config.grid_size.saturating_sub(2).max(2);
+                    format!("Decreased the grid size to {}.", view_config.grid_size)
+                }
+                DebugAction::IncreaseGridSize => {
+                    view_config.grid_size += 2;
+                    format!("Increased the grid size to {}.", view_config.grid_size)
+                }
+                DebugAction::RotateSunLeft => {
+                    view_config.sun_azimuth = (view_config.sun_azimuth - 15.0).rem_euclid(360.0);
+                    format!("Rotated the sun azimuth to {}.", view_config.sun_azimuth)
+                }
+                DebugAction::RotateSunRight => {
+                    view_config.sun_azimuth = (view_config.sun_azimuth + 15.0).rem_euclid(360.0);
+                    format!("Rotated the sun azimuth to {}.", view_config.sun_azimuth)
+                }
+                DebugAction::ToggleBathymetry => {
+                    view_config.bathymetry_mode = match view_config.bathymetry_mode {
+                        BathymetryMode::Topography => BathymetryMode::TopoBathy,
+                        BathymetryMode::TopoBathy => BathymetryMode::Topography,
+                    };
+                    format!("Switched to the {:?} mode.", view_config.bathymetry_mode)
+                }
+            };
         }
+
+        message
+    }
+}
+
+pub(crate) fn handle_debug_actions(
+    mut actions: EventReader<DebugAction>,
+    mut debug: ResMut<DebugTerrain>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    for &action in actions.iter() {
+        println!("{}", action.apply(&mut debug, &mut view_configs));
     }
 }
@@ -0,0 +1,125 @@
+//! Draws the bounding boxes of the nodes, which are currently requested by the quadtrees,
+//! color-coded by their lod, to visualize the refinement decisions in the scene.
+
+use crate::{debug::DebugTerrain, terrain_data::quadtree::Quadtree, TerrainViewComponents};
+use bevy::{
+    math::DVec3,
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{mesh::PrimitiveTopology, view::NoFrustumCulling},
+};
+
+/// The edges of a box as pairs of corner indices, where the bits of an index select
+/// the maximum (1) or minimum (0) of the x, y and z coordinate.
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0b000, 0b001),
+    (0b010, 0b011),
+    (0b100, 0b101),
+    (0b110, 0b111),
+    (0b000, 0b010),
+    (0b001, 0b011),
+    (0b100, 0b110),
+    (0b101, 0b111),
+    (0b000, 0b100),
+    (0b001, 0b101),
+    (0b010, 0b110),
+    (0b011, 0b111),
+];
+
+/// The fraction of the node size, by which the boxes are shrunk,
+/// so that the boxes of nested nodes do not overlap.
+const INSET: f64 = 0.005;
+
+/// Marks the line mesh, which draws the node bounds of all terrain views.
+#[derive(Component)]
+pub(crate) struct NodeBoundsGizmo;
+
+/// The same colors as the lod debug view of the shaders.
+fn lod_color(lod: u32) -> Color {
+    match lod % 6 {
+        0 => Color::RED,
+        1 => Color::GREEN,
+        2 => Color::BLUE,
+        3 => Color::YELLOW,
+        4 => Color::FUCHSIA,
+        _ => Color::CYAN,
+    }
+}
+
+fn node_bounds_mesh(quadtrees: &TerrainViewComponents<Quadtree>) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut colors: Vec<[f32; 4]> = Vec::new();
+
+    for quadtree in quadtrees.0.values() {
+        for (lod, min, max) in quadtree.requested_node_bounds() {
+            let inset = (max.x - min.x) * INSET;
+            let (min, max) = (min + inset, max - inset);
+
+            let corner = |index: usize| {
+                let mask = BVec3::new(index & 0b100 != 0, index & 0b010 != 0, index & 0b001 != 0);
+
+                DVec3::select(mask, max, min).as_vec3().to_array()
+            };
+
+            for (start, end) in BOX_EDGES {
+                positions.extend([corner(start), corner(end)]);
+                colors.extend([lod_color(lod).as_linear_rgba_f32(); 2]);
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        vec![[0.0, 1.0, 0.0]; positions.len()],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+/// Draws the bounding boxes of the requested nodes of all quadtrees,
+/// while the `show_node_bounds` debug flag is enabled.
+pub(crate) fn draw_node_bounds(
+    mut commands: Commands,
+    debug: Res<DebugTerrain>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    gizmo_query: Query<(Entity, &Handle<Mesh>), With<NodeBoundsGizmo>>,
+) {
+    if !debug.show_node_bounds {
+        for (gizmo, _) in gizmo_query.iter() {
+            commands.entity(gizmo).despawn();
+        }
+
+        return;
+    }
+
+    let mesh = node_bounds_mesh(&quadtrees);
+
+    match gizmo_query.get_single() {
+        Ok((_, handle)) => {
+            if let Some(gizmo_mesh) = meshes.get_mut(handle) {
+                *gizmo_mesh = mesh;
+            }
+        }
+        Err(_) => {
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(StandardMaterial {
+                        base_color: Color::WHITE,
+                        unlit: true,
+                        ..default()
+                    }),
+                    ..default()
+                },
+                NodeBoundsGizmo,
+                NotShadowCaster,
+                // the mesh changes every frame, so its bounding box would be outdated
+                NoFrustumCulling,
+            ));
+        }
+    }
+}
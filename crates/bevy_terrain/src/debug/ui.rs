@@ -0,0 +1,201 @@
+//! An egui window, which displays the internal state of the terrains and controls the debug flags
+//! and view config parameters.
+//!
+//! The window shows the occupancy of the node atlas of each terrain, as well as the quadtree of
+//! each view, where each slot is colored according to the state of its node:
+//! * gray - the node is not requested
+//! * red - the node is requested, but neither it nor any of its ancestors are loaded
+//! * yellow to green - the node is requested and the best loaded node is coarser by up to
+//!   three lods (yellow) or the node itself (green)
+//!
+//! Besides the frame time, the GPU timings and statistics of the [`TerrainDiagnosticsPlugin`]
+//! are displayed, if it has been added.
+//!
+//! [`TerrainDiagnosticsPlugin`]: crate::render::diagnostics::TerrainDiagnosticsPlugin
+
+use crate::{
+    debug::{
+        command::{DEBUG_FLAGS, VIEW_PARAMETERS},
+        DebugTerrain, TerrainDebugPlugin,
+    },
+    render::diagnostics::{
+        TERRAIN_DRAW_MS, TERRAIN_PATCHES_DRAWN, TERRAIN_REFINE_MS, TERRAIN_VERTICES,
+    },
+    terrain::Terrain,
+    terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree},
+    TerrainViewComponents, TerrainViewConfig,
+};
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use bevy_egui::{
+    egui::{self, Color32},
+    EguiContext, EguiPlugin,
+};
+
+/// The view config parameters, which only accept integer values.
+const INTEGER_PARAMETERS: [&str; 3] = ["refinement_count", "additional_refinement", "grid_size"];
+
+/// The size of a quadtree slot in the window.
+const SLOT_SIZE: f32 = 6.0;
+
+/// Adds an egui window, which visualizes the quadtrees and node atlases and controls
+/// the debug flags.
+///
+/// Requires the `debug_ui` feature. Adds the [`TerrainDebugPlugin`] as well.
+pub struct TerrainDebugUiPlugin;
+
+impl Plugin for TerrainDebugUiPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<TerrainDebugPlugin>() {
+            app.add_plugin(TerrainDebugPlugin);
+        }
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugin(EguiPlugin);
+        }
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugin(FrameTimeDiagnosticsPlugin);
+        }
+
+        app.add_system(debug_ui);
+    }
+}
+
+fn debug_ui(
+    mut egui_context: ResMut<EguiContext>,
+    diagnostics: Res<Diagnostics>,
+    mut debug: ResMut<DebugTerrain>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
+) {
+    egui::Window::new("Terrain Debug").show(egui_context.ctx_mut(), |ui| {
+        if let Some(frame_time) = diagnostics
+            .get(FrameTimeDiagnosticsPlugin::FRAME_TIME)
+            .and_then(|frame_time| frame_time.smoothed())
+        {
+            ui.label(format!("Frame time: {:.2} ms", frame_time * 1000.0));
+        }
+
+        for id in [
+            TERRAIN_REFINE_MS,
+            TERRAIN_DRAW_MS,
+            TERRAIN_PATCHES_DRAWN,
+            TERRAIN_VERTICES,
+        ] {
+            if let Some(diagnostic) = diagnostics.get(id) {
+                if let Some(value) = diagnostic.smoothed() {
+                    ui.label(format!("{}: {value:.2}", diagnostic.name));
+                }
+            }
+        }
+
+        egui::CollapsingHeader::new("Debug Flags").show(ui, |ui| {
+            for name in DEBUG_FLAGS {
+                let flag = debug.flag_mut(name).unwrap();
+                ui.checkbox(flag, name);
+            }
+        });
+
+        egui::CollapsingHeader::new("View Parameters").show(ui, |ui| {
+            parameter_ui(ui, &mut view_configs);
+        });
+
+        for (terrain, node_atlas) in terrain_query.iter() {
+            egui::CollapsingHeader::new(format!("Terrain {terrain:?}")).show(ui, |ui| {
+                node_atlas_ui(ui, node_atlas);
+
+                for (&(_, view), quadtree) in quadtrees
+                    .0
+                    .iter()
+                    .filter(|(&(quadtree_terrain, _), _)| quadtree_terrain == terrain)
+                {
+                    egui::CollapsingHeader::new(format!("View {view:?}")).show(ui, |ui| {
+                        quadtree_ui(ui, quadtree);
+                    });
+                }
+            });
+        }
+    });
+}
+
+/// Edits the view config parameters of all terrain views, starting from the values of the first one.
+fn parameter_ui(ui: &mut egui::Ui, view_configs: &mut TerrainViewComponents<TerrainViewConfig>) {
+    let first = match view_configs.0.values().next() {
+        Some(view_config) => view_config.clone(),
+        None => return,
+    };
+
+    for name in VIEW_PARAMETERS {
+        let mut value: f64 = first.parameter(name).unwrap().parse().unwrap();
+        let integer = INTEGER_PARAMETERS.contains(&name);
+
+        ui.horizontal(|ui| {
+            let drag_value =
+                egui::DragValue::new(&mut value).speed(if integer { 1.0 } else { 0.1 });
+            let changed = ui.add(drag_value).changed();
+            ui.label(name);
+
+            if changed {
+                let value = if integer {
+                    (value.max(0.0).round() as u32).to_string()
+                } else {
+                    value.to_string()
+                };
+
+                for view_config in view_configs.0.values_mut() {
+                    view_config.set_parameter(name, &value).unwrap();
+                }
+            }
+        });
+    }
+}
+
+fn node_atlas_ui(ui: &mut egui::Ui, node_atlas: &NodeAtlas) {
+    let statistics = node_atlas.statistics();
+
+    ui.add(
+        egui::ProgressBar::new(statistics.used as f32 / statistics.size as f32).text(format!(
+            "{} / {} slots used",
+            statistics.used, statistics.size
+        )),
+    );
+    ui.label(format!(
+        "cached: {}, free: {}, loading: {}",
+        statistics.cached, statistics.free, statistics.loading
+    ));
+}
+
+fn quadtree_ui(ui: &mut egui::Ui, quadtree: &Quadtree) {
+    let grid_size = quadtree.node_count as f32 * SLOT_SIZE;
+
+    ui.horizontal_wrapped(|ui| {
+        for lod in 0..quadtree.lod_count {
+            ui.vertical(|ui| {
+                ui.label(format!("LOD {lod}"));
+
+                let (response, painter) =
+                    ui.allocate_painter(egui::Vec2::splat(grid_size), egui::Sense::hover());
+                let origin = response.rect.min;
+
+                for (slot, requested, loaded_lod) in quadtree.layer_states(lod) {
+                    let color = match (requested, loaded_lod) {
+                        (false, _) => Color32::DARK_GRAY,
+                        (true, None) => Color32::RED,
+                        (true, Some(loaded_lod)) => {
+                            let ratio = ((loaded_lod - lod) as f32 / 3.0).min(1.0);
+                            Color32::from_rgb((255.0 * ratio) as u8, 200, 0)
+                        }
+                    };
+
+                    // the slots are wrapping, thus the grid is not centered on the viewer
+                    let min = origin + egui::vec2(slot.x as f32, slot.y as f32) * SLOT_SIZE;
+                    let rect = egui::Rect::from_min_size(min, egui::Vec2::splat(SLOT_SIZE - 1.0));
+
+                    painter.rect_filled(rect, 0.0, color);
+                }
+            });
+        }
+    });
+}
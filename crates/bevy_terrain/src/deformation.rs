@@ -0,0 +1,566 @@
+//! Deformation of the terrain at runtime, e.g. craters left by explosions or impacts in
+//! destructible-terrain games.
+//!
+//! Each [`TerrainDeformEvent`] is applied in-place to the height and minmax attachments of all
+//! resident nodes it overlaps, including their coarser mip levels and the borders shared with
+//! neighbouring nodes, by rendering the deformed heights directly into the node atlas.
+//! All deformations are recorded by the [`TerrainDeformation`] and applied again to nodes,
+//! which are loaded afterwards, so that they persist while the terrain is streamed.
+//!
+//! The deformation only affects the GPU data of the terrain. Heights sampled on the CPU
+//! (e.g. via [`Quadtree::sample_height`](crate::terrain_data::quadtree::Quadtree::sample_height))
+//! still return the original terrain, use a
+//! [`TerrainHeightQuery`](crate::terrain_data::height_query::TerrainHeightQuery) instead.
+
+use crate::{
+    render::{decals::TerrainDecal, shaders::DEFORMATION_SHADER},
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_data::{
+        gpu_node_atlas::GpuNodeAtlas,
+        node_atlas::{NodeAtlas, TerrainEvent},
+        AtlasAttachment, AtlasIndex, NodeCoordinate, NodeId,
+    },
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        MainWorld,
+    },
+};
+use std::{f32::consts::TAU, mem, num::NonZeroU32};
+
+/// The shape of a deformation.
+///
+/// All heights are measured in world units.
+#[derive(Clone, Copy, Debug)]
+pub enum DeformationProfile {
+    /// A bowl shaped crater, which is surrounded by a raised rim extending half the radius
+    /// beyond the crater.
+    Crater { depth: f32, rim_height: f32 },
+    /// A smooth mound, which digs a smooth pit for negative heights.
+    Mound { height: f32 },
+}
+
+impl DeformationProfile {
+    /// Returns the distance from the center, up to which the terrain is affected,
+    /// relative to the radius.
+    fn extent(&self) -> f32 {
+        match self {
+            Self::Crater { .. } => 1.5,
+            Self::Mound { .. } => 1.0,
+        }
+    }
+}
+
+/// Deforms the terrain around the center.
+///
+/// The terrain has to have a [`TerrainDeformation`] component.
+#[derive(Clone, Debug)]
+pub struct TerrainDeformEvent {
+    /// The deformed terrain.
+    pub terrain: Entity,
+    /// The center of the deformation in the horizontal (x, z) plane of the terrain.
+    pub center: Vec2,
+    /// The radius of the deformation.
+    pub radius: f32,
+    /// The shape of the deformation.
+    pub profile: DeformationProfile,
+    /// The texture of a [`TerrainDecal`], which is painted over the affected area
+    /// (e.g. scorch marks and debris).
+    pub debris: Option<Handle<Image>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Deformation {
+    center: Vec2,
+    radius: f32,
+    profile: DeformationProfile,
+}
+
+impl Deformation {
+    /// Tests whether the deformation overlaps the data of the node, including its border.
+    fn overlaps(
+        &self,
+        config: &TerrainConfig,
+        attachment: &AtlasAttachment,
+        node_id: NodeId,
+    ) -> bool {
+        let coordinate = NodeCoordinate::from(node_id);
+        let node_size = (config.leaf_node_size << coordinate.lod) as f32;
+        let pixel_size = node_size / attachment.center_size as f32;
+
+        let min = Vec2::new(coordinate.x as f32, coordinate.y as f32) * node_size
+            - attachment.border_size as f32 * pixel_size;
+        let max = min + attachment.texture_size as f32 * pixel_size;
+
+        let extent = self.radius * self.profile.extent();
+        let closest = self.center.clamp(min, max);
+
+        closest.distance(self.center) < extent
+    }
+}
+
+/// A deformation, which has to be applied to a node of the atlas.
+#[derive(Clone, Copy)]
+pub(crate) struct NodeDeformation {
+    atlas_index: AtlasIndex,
+    node_id: NodeId,
+    deformation: Deformation,
+}
+
+/// Records the deformations of a terrain.
+///
+/// Add this component to a terrain entity, to deform it using [`TerrainDeformEvent`]s.
+#[derive(Default, Component)]
+pub struct TerrainDeformation {
+    /// All deformations applied so far, in order.
+    deformations: Vec<Deformation>,
+    /// The deformations, which have to be applied to the atlas this frame.
+    pending: Vec<NodeDeformation>,
+}
+
+impl TerrainDeformation {
+    /// Returns the number of deformations applied so far.
+    pub fn deformation_count(&self) -> usize {
+        self.deformations.len()
+    }
+}
+
+/// Applies the new deformations to all resident nodes and all previous deformations to the
+/// nodes, that have finished loading this frame.
+pub(crate) fn deform_terrain(
+    mut commands: Commands,
+    mut deform_events: EventReader<TerrainDeformEvent>,
+    mut terrain_events: EventReader<TerrainEvent>,
+    mut terrain_query: Query<
+        (Entity, &TerrainConfig, &NodeAtlas, &mut TerrainDeformation),
+        With<Terrain>,
+    >,
+) {
+    let deform_events: Vec<&TerrainDeformEvent> = deform_events.iter().collect();
+    let loaded_nodes: Vec<(Entity, NodeId)> = terrain_events
+        .iter()
+        .filter_map(|event| match event {
+            &TerrainEvent::NodeLoaded { terrain, node_id } => Some((terrain, node_id)),
+            _ => None,
+        })
+        .collect();
+
+    for event in &deform_events {
+        if !terrain_query.contains(event.terrain) {
+            warn!("Only terrains with a TerrainDeformation component can be deformed.");
+        }
+
+        if let Some(texture) = &event.debris {
+            let extent = event.radius * event.profile.extent();
+
+            commands.spawn(TerrainDecal {
+                position: event.center,
+                size: Vec2::splat(2.0 * extent),
+                rotation: fastrand::f32() * TAU,
+                texture: texture.clone(),
+                color: Color::WHITE,
+            });
+        }
+    }
+
+    for (terrain, config, node_atlas, mut terrain_deformation) in terrain_query.iter_mut() {
+        let attachment = skip_none!(node_atlas.attachments.first());
+        let terrain_deformation = &mut *terrain_deformation;
+
+        let loaded_nodes: Vec<NodeId> = loaded_nodes
+            .iter()
+            .filter(|&&(loaded_terrain, _)| loaded_terrain == terrain)
+            .map(|&(_, node_id)| node_id)
+            .collect();
+
+        let new_deformations: Vec<Deformation> = deform_events
+            .iter()
+            .filter(|event| event.terrain == terrain)
+            .map(|event| Deformation {
+                center: event.center,
+                radius: event.radius,
+                profile: event.profile,
+            })
+            .collect();
+
+        // the nodes loaded this frame receive the new deformations alongside the previous ones
+        for (&node_id, node) in &node_atlas.nodes {
+            if !node_atlas.is_loaded(node_id) || loaded_nodes.contains(&node_id) {
+                continue;
+            }
+
+            terrain_deformation.pending.extend(
+                new_deformations
+                    .iter()
+                    .filter(|deformation| deformation.overlaps(config, attachment, node_id))
+                    .map(|&deformation| NodeDeformation {
+                        atlas_index: node.atlas_index,
+                        node_id,
+                        deformation,
+                    }),
+            );
+        }
+
+        terrain_deformation.deformations.extend(new_deformations);
+
+        for node_id in loaded_nodes {
+            let node = skip_none!(node_atlas.nodes.get(&node_id));
+
+            terrain_deformation.pending.extend(
+                terrain_deformation
+                    .deformations
+                    .iter()
+                    .filter(|deformation| deformation.overlaps(config, attachment, node_id))
+                    .map(|&deformation| NodeDeformation {
+                        atlas_index: node.atlas_index,
+                        node_id,
+                        deformation,
+                    }),
+            );
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct DeformationUniform {
+    center: Vec2,
+    /// The position of the corner of the first texel of the mip level.
+    origin: Vec2,
+    radius: f32,
+    /// The size of a texel of the mip level.
+    texel_size: f32,
+    /// 0 for craters and 1 for mounds.
+    profile: u32,
+    /// The normalized depth of a crater or the normalized height of a mound.
+    amplitude: f32,
+    /// The normalized height of the rim of a crater.
+    rim_height: f32,
+}
+
+/// The pipelines used to render the deformed attachments into the node atlas.
+#[derive(Resource)]
+pub struct DeformationPipeline {
+    deformation_layout: BindGroupLayout,
+}
+
+impl FromWorld for DeformationPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let deformation_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "deformation_layout".into(),
+            entries: &[
+                // deformation
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(DeformationUniform::min_size()),
+                    },
+                    count: None,
+                },
+                // original attachment
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        Self { deformation_layout }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeformationPipelineKey {
+    /// The format of the deformed attachment.
+    format: TextureFormat,
+    /// Whether the deformed attachment stores the minimum and maximum height.
+    minmax: bool,
+}
+
+impl SpecializedRenderPipeline for DeformationPipeline {
+    type Key = DeformationPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let shader_defs = if key.minmax {
+            vec!["MINMAX".to_string()]
+        } else {
+            Vec::new()
+        };
+
+        RenderPipelineDescriptor {
+            label: "deformation_pipeline".into(),
+            layout: Some(vec![self.deformation_layout.clone()]),
+            vertex: VertexState {
+                shader: DEFORMATION_SHADER.typed(),
+                entry_point: "vertex".into(),
+                shader_defs: shader_defs.clone(),
+                buffers: Vec::new(),
+            },
+            primitive: PrimitiveState::default(),
+            fragment: Some(FragmentState {
+                shader: DEFORMATION_SHADER.typed(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: key.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+        }
+    }
+}
+
+/// Stores the render world side of the [`TerrainDeformation`].
+pub struct GpuTerrainDeformation {
+    leaf_node_size: u32,
+    height: f32,
+    pending: Vec<NodeDeformation>,
+}
+
+impl GpuTerrainDeformation {
+    /// Records the deformation of all mip levels of the attachment of the node.
+    fn deform(
+        &self,
+        device: &RenderDevice,
+        command_encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        deformation_layout: &BindGroupLayout,
+        (attachment, atlas_texture): (&AtlasAttachment, &Texture),
+        node_deformation: &NodeDeformation,
+    ) {
+        let NodeDeformation {
+            atlas_index,
+            node_id,
+            deformation,
+        } = *node_deformation;
+
+        // the original data is copied out of the atlas, because it is overwritten in-place
+        let original_texture = device.create_texture(&TextureDescriptor {
+            label: "deformation_original_texture".into(),
+            size: Extent3d {
+                width: attachment.texture_size,
+                height: attachment.texture_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: attachment.mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: attachment.format,
+            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+        });
+
+        let coordinate = NodeCoordinate::from(node_id);
+        let node_size = (self.leaf_node_size << coordinate.lod) as f32;
+        let pixel_size = node_size / attachment.center_size as f32;
+        let origin = Vec2::new(coordinate.x as f32, coordinate.y as f32) * node_size
+            - attachment.border_size as f32 * pixel_size;
+
+        let (profile, amplitude, rim_height) = match deformation.profile {
+            DeformationProfile::Crater { depth, rim_height } => (0, depth, rim_height),
+            DeformationProfile::Mound { height } => (1, height, 0.0),
+        };
+
+        for mip_level in 0..attachment.mip_level_count {
+            let size = attachment.texture_size >> mip_level;
+
+            command_encoder.copy_texture_to_texture(
+                ImageCopyTexture {
+                    texture: atlas_texture,
+                    mip_level,
+                    origin: Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: atlas_index as u32,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyTexture {
+                    texture: &original_texture,
+                    mip_level,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            let mut uniform = encase::UniformBuffer::new(Vec::new());
+            uniform
+                .write(&DeformationUniform {
+                    center: deformation.center,
+                    origin,
+                    radius: deformation.radius,
+                    texel_size: pixel_size * (1 << mip_level) as f32,
+                    profile,
+                    amplitude: amplitude / self.height,
+                    rim_height: rim_height / self.height,
+                })
+                .unwrap();
+
+            let uniform_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+                label: "deformation_uniform_buffer".into(),
+                contents: &uniform.into_inner(),
+                usage: BufferUsages::UNIFORM,
+            });
+
+            let original_view = original_texture.create_view(&TextureViewDescriptor {
+                base_mip_level: mip_level,
+                mip_level_count: NonZeroU32::new(1),
+                ..default()
+            });
+
+            let atlas_view = atlas_texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: NonZeroU32::new(1),
+                base_array_layer: atlas_index as u32,
+                array_layer_count: NonZeroU32::new(1),
+                ..default()
+            });
+
+            let deformation_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&original_view),
+                    },
+                ],
+                layout: deformation_layout,
+            });
+
+            let mut pass = command_encoder.begin_render_pass(&RenderPassDescriptor {
+                label: "deformation_pass".into(),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &atlas_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &deformation_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+/// Extracts the pending deformations into the [`GpuTerrainDeformation`]s.
+pub(crate) fn extract_terrain_deformation(
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_deformations: ResMut<TerrainComponents<GpuTerrainDeformation>>,
+) {
+    let mut terrain_query = main_world.query::<(Entity, &TerrainConfig, &mut TerrainDeformation)>();
+
+    for (terrain, config, mut terrain_deformation) in terrain_query.iter_mut(&mut main_world) {
+        let pending = mem::take(&mut terrain_deformation.pending);
+
+        gpu_deformations
+            .0
+            .entry(terrain)
+            .or_insert_with(|| GpuTerrainDeformation {
+                leaf_node_size: config.leaf_node_size,
+                height: config.height,
+                pending: default(),
+            })
+            .pending
+            .extend(pending);
+    }
+}
+
+/// Queues the deformation of the atlas, after the nodes of this frame have been uploaded.
+pub(crate) fn queue_terrain_deformation(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    deformation_pipeline: Res<DeformationPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<DeformationPipeline>>,
+    images: Res<RenderAssets<Image>>,
+    gpu_node_atlases: Res<TerrainComponents<GpuNodeAtlas>>,
+    mut gpu_deformations: ResMut<TerrainComponents<GpuTerrainDeformation>>,
+) {
+    for (terrain, gpu_deformation) in &mut gpu_deformations.0 {
+        if gpu_deformation.pending.is_empty() {
+            continue;
+        }
+
+        let gpu_node_atlas = skip_none!(gpu_node_atlases.get(terrain));
+
+        // only the heights and their bounds are deformed
+        let attachments: Vec<_> = gpu_node_atlas
+            .attachments
+            .iter()
+            .filter(|(attachment, _)| attachment.name == "height" || attachment.name == "minmax")
+            .map(|(attachment, handle)| {
+                let key = DeformationPipelineKey {
+                    format: attachment.format,
+                    minmax: attachment.name == "minmax",
+                };
+                let pipeline_id = pipelines.specialize(&pipeline_cache, &deformation_pipeline, key);
+
+                (
+                    attachment,
+                    pipeline_cache.get_render_pipeline(pipeline_id),
+                    images.get(handle),
+                )
+            })
+            .collect();
+
+        // keep the deformations pending, until all pipelines and textures are available
+        if attachments
+            .iter()
+            .any(|(_, pipeline, image)| pipeline.is_none() || image.is_none())
+        {
+            continue;
+        }
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        for node_deformation in &gpu_deformation.pending {
+            for &(attachment, pipeline, image) in &attachments {
+                gpu_deformation.deform(
+                    &device,
+                    &mut command_encoder,
+                    pipeline.unwrap(),
+                    &deformation_pipeline.deformation_layout,
+                    (attachment, &image.unwrap().texture),
+                    node_deformation,
+                );
+            }
+        }
+
+        queue.submit(vec![command_encoder.finish()]);
+
+        gpu_deformation.pending.clear();
+    }
+}
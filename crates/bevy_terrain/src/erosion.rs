@@ -0,0 +1,641 @@
+//! Hydraulic and thermal erosion of procedurally generated terrain, which is simulated on the GPU.
+//!
+//! Layered noise lacks the valleys and sediment fans carved by flowing water.
+//! The erosion simulates many rain droplets, which erode the terrain while flowing downhill and
+//! deposit the sediment once they slow down, followed by thermal erosion, which lets slopes
+//! steeper than the angle of repose collapse.
+//!
+//! A region of the terrain is sampled from the noise on the CPU, eroded on the GPU and read back.
+//! The resulting height difference is stored in the [`ProceduralAttachmentLoader`] and applied
+//! to all nodes generated afterwards. Nodes that are already loaded are generated again.
+
+use crate::{
+    procedural_loader::{sample_height, ProceduralAttachmentLoader},
+    render::shaders::EROSION_SHADER,
+    skip_none,
+    terrain::{TerrainComponents, TerrainConfig},
+    terrain_data::{node_atlas::NodeAtlas, NodeCoordinate, NodeId},
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        MainWorld,
+    },
+    tasks::AsyncComputeTaskPool,
+};
+use bytemuck::cast_slice;
+use itertools::iproduct;
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use wgpu::Maintain;
+
+/// The scale of the fixed point heights used by the simulation. Has to match the shader.
+const FIXED_POINT_SCALE: f32 = 16777216.0;
+/// The width in pixels, over which the erosion fades out towards the border of a region.
+const FADE_WIDTH: f32 = 16.0;
+
+/// The parameters of the erosion simulation.
+///
+/// The droplet parameters follow the common particle based approach,
+/// all heights are measured in world units.
+#[derive(Clone, Debug)]
+pub struct ErosionConfig {
+    /// The seed used to place the droplets.
+    pub seed: u32,
+    /// The number of simulated rain droplets.
+    pub droplet_count: u32,
+    /// The maximum number of steps a droplet moves, before it evaporates.
+    pub droplet_lifetime: u32,
+    /// How much a droplet keeps its direction (0 to 1), instead of following the slope.
+    pub inertia: f32,
+    /// Scales the amount of sediment a droplet can carry.
+    pub sediment_capacity: f32,
+    /// The amount of sediment a droplet can carry, even on flat terrain.
+    pub min_sediment_capacity: f32,
+    /// The fraction (0 to 1) of the remaining capacity, that is eroded each step.
+    pub erosion_rate: f32,
+    /// The fraction (0 to 1) of the excess sediment, that is deposited each step.
+    pub deposition_rate: f32,
+    /// The fraction (0 to 1) of the water, that evaporates each step.
+    pub evaporation_rate: f32,
+    /// The acceleration of the droplets when flowing downhill.
+    pub gravity: f32,
+    /// The number of thermal erosion iterations, which run after the hydraulic erosion.
+    pub thermal_iterations: u32,
+    /// The angle of repose in degrees. Steeper slopes collapse during thermal erosion.
+    pub talus_angle: f32,
+    /// The fraction (0 to 1) of the unstable material, that slides down each iteration.
+    pub thermal_rate: f32,
+}
+
+impl Default for ErosionConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            droplet_count: 200_000,
+            droplet_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erosion_rate: 0.3,
+            deposition_rate: 0.3,
+            evaporation_rate: 0.01,
+            gravity: 4.0,
+            thermal_iterations: 50,
+            talus_angle: 35.0,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+pub(crate) struct ErosionParameters {
+    size: UVec2,
+    height: f32,
+    seed: u32,
+    droplet_count: u32,
+    droplet_lifetime: u32,
+    inertia: f32,
+    sediment_capacity: f32,
+    min_sediment_capacity: f32,
+    erosion_rate: f32,
+    deposition_rate: f32,
+    evaporation_rate: f32,
+    gravity: f32,
+    talus: f32,
+    thermal_rate: f32,
+}
+
+impl ErosionParameters {
+    fn new(config: &ErosionConfig, size: UVec2, height: f32) -> Self {
+        Self {
+            size,
+            height,
+            seed: config.seed,
+            droplet_count: config.droplet_count,
+            droplet_lifetime: config.droplet_lifetime,
+            inertia: config.inertia,
+            sediment_capacity: config.sediment_capacity,
+            min_sediment_capacity: config.min_sediment_capacity,
+            erosion_rate: config.erosion_rate,
+            deposition_rate: config.deposition_rate,
+            evaporation_rate: config.evaporation_rate,
+            gravity: config.gravity,
+            talus: config.talus_angle.to_radians().tan(),
+            thermal_rate: config.thermal_rate,
+        }
+    }
+}
+
+/// The height difference caused by the erosion of a region of the terrain.
+#[derive(Clone)]
+pub(crate) struct ErodedRegion {
+    /// The first pixel of the region (at lod zero).
+    min: UVec2,
+    /// The size of the region in pixels (at lod zero).
+    size: UVec2,
+    /// The normalized height difference of each pixel.
+    delta: Vec<f32>,
+}
+
+impl ErodedRegion {
+    /// Computes the height difference, which fades out towards the border of the region,
+    /// to avoid seams with the surrounding terrain.
+    fn new(min: UVec2, size: UVec2, original: &[f32], eroded: &[f32]) -> Self {
+        let delta = iproduct!(0..size.y, 0..size.x)
+            .zip(original.iter().zip(eroded))
+            .map(|((y, x), (original, eroded))| {
+                let distance = x.min(y).min(size.x - 1 - x).min(size.y - 1 - y) as f32;
+                let fade = (distance / FADE_WIDTH).min(1.0);
+                let fade = fade * fade * (3.0 - 2.0 * fade);
+
+                (eroded - original) * fade
+            })
+            .collect();
+
+        Self { min, size, delta }
+    }
+
+    /// Tests whether the region overlaps the rectangle (in pixels at lod zero).
+    fn overlaps(&self, min: Vec2, max: Vec2) -> bool {
+        let region_min = self.min.as_vec2();
+        let region_max = (self.min + self.size).as_vec2();
+
+        min.cmplt(region_max).all() && max.cmpgt(region_min).all()
+    }
+
+    /// Samples the height difference at the position (in pixels at lod zero) bilinearly.
+    pub(crate) fn sample(&self, position: Vec2) -> f32 {
+        let local = position - self.min.as_vec2();
+        let last = (self.size - 1).as_vec2();
+
+        if local.cmplt(Vec2::ZERO).any() || local.cmpgt(last).any() {
+            return 0.0;
+        }
+
+        let coordinate = local.floor().min(last - 1.0).max(Vec2::ZERO);
+        let fraction = (local - coordinate).min(Vec2::ONE);
+        let (x, y) = (coordinate.x as u32, coordinate.y as u32);
+
+        let delta = |x: u32, y: u32| {
+            let (x, y) = (x.min(self.size.x - 1), y.min(self.size.y - 1));
+            self.delta[(y * self.size.x + x) as usize]
+        };
+
+        let top = delta(x, y) + (delta(x + 1, y) - delta(x, y)) * fraction.x;
+        let bottom = delta(x, y + 1) + (delta(x + 1, y + 1) - delta(x, y + 1)) * fraction.x;
+
+        top + (bottom - top) * fraction.y
+    }
+}
+
+/// The heights of a region, which are waiting to be eroded on the GPU.
+pub(crate) struct ErosionBatch {
+    min: UVec2,
+    size: UVec2,
+    heights: Vec<f32>,
+    parameters: ErosionParameters,
+    thermal_iterations: u32,
+}
+
+/// Erodes regions of a procedurally generated terrain.
+///
+/// Add this component to a terrain entity with a [`ProceduralAttachmentLoader`] and
+/// request regions using [`erode`](Self::erode).
+/// Regions added using [`with_region`](Self::with_region) are eroded as soon as the terrain
+/// has been spawned, comparable to a preprocessing step.
+/// Regions which are eroded simultaneously do not take each others erosion into account.
+#[derive(Component)]
+pub struct TerrainErosion {
+    /// The parameters of the simulation, which are used for all subsequent regions.
+    pub config: ErosionConfig,
+    /// The regions (first and last pixel at lod zero), which will be eroded next.
+    requested_regions: Vec<(UVec2, UVec2)>,
+    /// The number of regions, which are currently being eroded.
+    running_count: usize,
+    /// Shared with the render world, which erodes the prepared regions.
+    prepared: Arc<Mutex<Vec<ErosionBatch>>>,
+    /// Shared with the render world, which stores the eroded regions.
+    finished: Arc<Mutex<Vec<ErodedRegion>>>,
+}
+
+impl TerrainErosion {
+    pub fn new(config: ErosionConfig) -> Self {
+        Self {
+            config,
+            requested_regions: default(),
+            running_count: 0,
+            prepared: default(),
+            finished: default(),
+        }
+    }
+
+    /// Adds a region, which will be eroded once the terrain is spawned.
+    pub fn with_region(mut self, min: UVec2, max: UVec2) -> Self {
+        self.erode(min, max);
+        self
+    }
+
+    /// Erodes the region between the first (inclusive) and the last (exclusive) pixel at lod zero.
+    pub fn erode(&mut self, min: UVec2, max: UVec2) {
+        self.requested_regions.push((min, max));
+    }
+
+    /// Returns whether any requested region has not finished eroding yet.
+    pub fn is_running(&self) -> bool {
+        !self.requested_regions.is_empty() || self.running_count > 0
+    }
+}
+
+/// Samples the heights of the requested regions on a background thread.
+pub(crate) fn start_erosion(
+    mut terrain_query: Query<(
+        &TerrainConfig,
+        &ProceduralAttachmentLoader,
+        &mut TerrainErosion,
+    )>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for (config, loader, mut erosion) in terrain_query.iter_mut() {
+        let erosion = &mut *erosion;
+
+        for (min, max) in erosion.requested_regions.drain(..) {
+            let max = max.min(UVec2::splat(config.terrain_size));
+
+            if max.cmple(min + 1).any() {
+                warn!("The eroded region has to be at least two pixels wide.");
+                continue;
+            }
+
+            let size = max - min;
+            let parameters = ErosionParameters::new(&erosion.config, size, config.height);
+            let thermal_iterations = erosion.config.thermal_iterations;
+            let layers = loader.layers.clone();
+            let eroded_regions = loader.eroded_regions.clone();
+            let prepared = erosion.prepared.clone();
+
+            erosion.running_count += 1;
+
+            task_pool
+                .spawn(async move {
+                    let heights = iproduct!(0..size.y, 0..size.x)
+                        .map(|(y, x)| {
+                            let position = (min + UVec2::new(x, y)).as_vec2();
+                            sample_height(&layers, &eroded_regions, position)
+                        })
+                        .collect();
+
+                    prepared.lock().unwrap().push(ErosionBatch {
+                        min,
+                        size,
+                        heights,
+                        parameters,
+                        thermal_iterations,
+                    });
+                })
+                .detach();
+        }
+    }
+}
+
+/// Hands the eroded regions over to the [`ProceduralAttachmentLoader`] and
+/// regenerates all present nodes, that are affected by them.
+pub(crate) fn finish_erosion(
+    mut terrain_query: Query<(
+        &mut NodeAtlas,
+        &mut ProceduralAttachmentLoader,
+        &mut TerrainErosion,
+    )>,
+) {
+    for (mut node_atlas, mut loader, mut erosion) in terrain_query.iter_mut() {
+        let finished = mem::take(&mut *erosion.finished.lock().unwrap());
+
+        for region in finished {
+            erosion.running_count -= 1;
+
+            let attachment = skip_none!(loader.attachments.values().next())
+                .config
+                .clone();
+            let region_count = loader.eroded_regions.len() + 1;
+
+            let affected_nodes: Vec<NodeId> = node_atlas
+                .nodes
+                .keys()
+                .copied()
+                .filter(|&node_id| {
+                    let coordinate = NodeCoordinate::from(node_id);
+                    let scale = (1 << coordinate.lod) as f32;
+                    let origin = Vec2::new(coordinate.x as f32, coordinate.y as f32)
+                        * attachment.center_size as f32
+                        - attachment.border_size as f32;
+
+                    let min = origin * scale;
+                    let max = (origin + attachment.texture_size as f32) * scale;
+
+                    region.overlaps(min, max)
+                })
+                .collect();
+
+            for node_id in affected_nodes {
+                loader.required_regions.insert(node_id, region_count);
+                node_atlas.reload_node(node_id);
+            }
+
+            Arc::make_mut(&mut loader.eroded_regions).push(region);
+        }
+    }
+}
+
+fn erosion_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: "erosion_layout".into(),
+        entries: &[
+            // parameters
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(ErosionParameters::min_size()),
+                },
+                count: None,
+            },
+            // heights
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(4),
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// The pipelines used to simulate the erosion.
+#[derive(Resource)]
+pub struct ErosionPipeline {
+    erosion_layout: BindGroupLayout,
+    hydraulic_pipeline_id: CachedComputePipelineId,
+    thermal_pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for ErosionPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let erosion_layout = erosion_bind_group_layout(device);
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let mut queue_pipeline = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some(format!("{entry_point}_pipeline").into()),
+                layout: Some(vec![erosion_layout.clone()]),
+                shader: EROSION_SHADER.typed(),
+                shader_defs: vec![],
+                entry_point: entry_point.into(),
+            })
+        };
+
+        let hydraulic_pipeline_id = queue_pipeline("hydraulic_erosion");
+        let thermal_pipeline_id = queue_pipeline("thermal_erosion");
+
+        Self {
+            erosion_layout,
+            hydraulic_pipeline_id,
+            thermal_pipeline_id,
+        }
+    }
+}
+
+/// A region, whose eroded heights are waiting to be mapped.
+struct RunningErosion {
+    min: UVec2,
+    size: UVec2,
+    original: Vec<f32>,
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+}
+
+impl RunningErosion {
+    /// Starts mapping the staging buffer, after the simulation has been submitted.
+    fn map(&self) {
+        let mapped = self.mapped.clone();
+
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+/// Stores the render world side of the [`TerrainErosion`].
+pub struct GpuErosion {
+    pending: Vec<ErosionBatch>,
+    running: Vec<RunningErosion>,
+    finished: Arc<Mutex<Vec<ErodedRegion>>>,
+}
+
+impl GpuErosion {
+    /// Records the simulation of the batch and the copy of the eroded heights
+    /// into a staging buffer.
+    fn erode(
+        device: &RenderDevice,
+        command_encoder: &mut CommandEncoder,
+        pipelines: (&ComputePipeline, &ComputePipeline),
+        erosion_layout: &BindGroupLayout,
+        batch: ErosionBatch,
+    ) -> RunningErosion {
+        let (hydraulic_pipeline, thermal_pipeline) = pipelines;
+
+        let mut parameters = encase::UniformBuffer::new(Vec::new());
+        parameters.write(&batch.parameters).unwrap();
+
+        let parameter_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "erosion_parameter_buffer".into(),
+            contents: &parameters.into_inner(),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let heights: Vec<i32> = batch
+            .heights
+            .iter()
+            .map(|&height| (height * FIXED_POINT_SCALE) as i32)
+            .collect();
+
+        let height_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "erosion_height_buffer".into(),
+            contents: cast_slice(&heights),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        });
+
+        let size = height_buffer.size();
+
+        let staging_buffer = device.create_buffer(&BufferDescriptor {
+            label: "erosion_staging_buffer".into(),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let erosion_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: parameter_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: height_buffer.as_entire_binding(),
+                },
+            ],
+            layout: erosion_layout,
+        });
+
+        {
+            let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+            pass.set_bind_group(0, &erosion_bind_group, &[]);
+
+            // the droplets are spread over two dimensions, to exceed the workgroup count limit
+            let workgroup_count = (batch.parameters.droplet_count + 63) / 64;
+
+            if workgroup_count > 0 {
+                let x = workgroup_count.min(u16::MAX as u32);
+                let y = (workgroup_count + x - 1) / x;
+
+                pass.set_pipeline(hydraulic_pipeline);
+                pass.dispatch_workgroups(x, y, 1);
+            }
+
+            pass.set_pipeline(thermal_pipeline);
+
+            for _ in 0..batch.thermal_iterations {
+                pass.dispatch_workgroups((batch.size.x + 7) / 8, (batch.size.y + 7) / 8, 1);
+            }
+        }
+
+        command_encoder.copy_buffer_to_buffer(&height_buffer, 0, &staging_buffer, 0, size);
+
+        RunningErosion {
+            min: batch.min,
+            size: batch.size,
+            original: batch.heights,
+            buffer: staging_buffer,
+            mapped: default(),
+        }
+    }
+
+    /// Moves the regions, whose eroded heights have been mapped, into the finished regions.
+    fn finish(&mut self) {
+        let (mapped, running): (Vec<_>, Vec<_>) = mem::take(&mut self.running)
+            .into_iter()
+            .partition(|erosion| erosion.mapped.load(Ordering::Acquire));
+
+        self.running = running;
+
+        for erosion in mapped {
+            let eroded: Vec<f32> =
+                cast_slice::<u8, i32>(&erosion.buffer.slice(..).get_mapped_range())
+                    .iter()
+                    .map(|&height| height as f32 / FIXED_POINT_SCALE)
+                    .collect();
+
+            erosion.buffer.unmap();
+
+            let region = ErodedRegion::new(erosion.min, erosion.size, &erosion.original, &eroded);
+            self.finished.lock().unwrap().push(region);
+        }
+    }
+}
+
+/// Extracts the prepared regions into the [`GpuErosion`]s.
+pub(crate) fn extract_erosion(
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_erosions: ResMut<TerrainComponents<GpuErosion>>,
+) {
+    let mut terrain_query = main_world.query::<(Entity, &TerrainErosion)>();
+
+    for (terrain, erosion) in terrain_query.iter(&main_world) {
+        let batches = mem::take(&mut *erosion.prepared.lock().unwrap());
+
+        gpu_erosions
+            .0
+            .entry(terrain)
+            .or_insert_with(|| GpuErosion {
+                pending: default(),
+                running: default(),
+                finished: erosion.finished.clone(),
+            })
+            .pending
+            .extend(batches);
+    }
+}
+
+/// Queues the simulation of the pending regions and collects the eroded ones.
+pub(crate) fn queue_erosion(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    erosion_pipeline: Res<ErosionPipeline>,
+    mut gpu_erosions: ResMut<TerrainComponents<GpuErosion>>,
+) {
+    device.wgpu_device().poll(Maintain::Poll);
+
+    let pipelines = match (
+        pipeline_cache.get_compute_pipeline(erosion_pipeline.hydraulic_pipeline_id),
+        pipeline_cache.get_compute_pipeline(erosion_pipeline.thermal_pipeline_id),
+    ) {
+        (Some(hydraulic_pipeline), Some(thermal_pipeline)) => {
+            (hydraulic_pipeline, thermal_pipeline)
+        }
+        _ => return, // the pipelines are not loaded yet
+    };
+
+    for gpu_erosion in gpu_erosions.0.values_mut() {
+        gpu_erosion.finish();
+
+        if gpu_erosion.pending.is_empty() {
+            continue;
+        }
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        let running: Vec<RunningErosion> = gpu_erosion
+            .pending
+            .drain(..)
+            .map(|batch| {
+                GpuErosion::erode(
+                    &device,
+                    &mut command_encoder,
+                    pipelines,
+                    &erosion_pipeline.erosion_layout,
+                    batch,
+                )
+            })
+            .collect();
+
+        queue.submit(vec![command_encoder.finish()]);
+
+        for erosion in running {
+            erosion.map();
+            gpu_erosion.running.push(erosion);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! The errors, which can occur while preprocessing and loading the terrain data.
+//!
+//! The preprocessor and [`load_node_config`](crate::preprocess::config::load_node_config)
+//! return them directly, while errors of the loaders, which run inside the app,
+//! are sent as [`TerrainLoadError`] events.
+
+use crate::terrain_data::{AttachmentFormat, FileFormat};
+use bevy::prelude::*;
+use std::{error::Error, fmt, io};
+
+pub type TerrainResult<T> = Result<T, TerrainError>;
+
+/// An error, which occurred while preprocessing or loading the terrain data.
+#[derive(Debug)]
+pub enum TerrainError {
+    /// A file or directory could not be read or written.
+    Io { path: String, source: io::Error },
+    /// A source tile does not exist.
+    MissingTile { path: String },
+    /// An image or file could not be decoded or does not have the expected layout.
+    MalformedFile { path: String, reason: String },
+    /// The attachment format can not be stored in the file format.
+    UnsupportedFormat {
+        format: AttachmentFormat,
+        file_format: FileFormat,
+    },
+    /// The configuration of the terrain is inconsistent with itself or the preprocessed data.
+    InvalidConfig(String),
+    /// A node of an attachment could not be loaded by the asset server.
+    NodeLoadFailed { path: String },
+}
+
+impl TerrainError {
+    pub(crate) fn io(path: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Io { path, source }
+    }
+
+    pub(crate) fn malformed(path: impl Into<String>, reason: impl fmt::Display) -> Self {
+        Self::MalformedFile {
+            path: path.into(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "Could not access {path}: {source}"),
+            Self::MissingTile { path } => write!(f, "The source tile {path} does not exist."),
+            Self::MalformedFile { path, reason } => {
+                write!(f, "The file {path} is malformed: {reason}")
+            }
+            Self::UnsupportedFormat {
+                format,
+                file_format,
+            } => write!(
+                f,
+                "The attachment format {format:?} can not be stored as {file_format:?}."
+            ),
+            Self::InvalidConfig(reason) => write!(f, "The terrain config is invalid: {reason}"),
+            Self::NodeLoadFailed { path } => write!(f, "The node {path} could not be loaded."),
+        }
+    }
+}
+
+impl Error for TerrainError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Sent, whenever loading the data of a terrain failed.
+///
+/// The terrain keeps rendering with the data, that has been loaded successfully.
+pub struct TerrainLoadError {
+    /// The terrain, whose data could not be loaded.
+    pub terrain: Entity,
+    pub error: TerrainError,
+}
@@ -0,0 +1,348 @@
+//! A coarse mesh of the whole terrain, which continues the terrain beyond the quadtrees of the
+//! views up to the far plane.
+//!
+//! The quadtree of a view only covers `node_count` x `node_count` nodes of each lod around the
+//! viewer, thus large terrains are cut off beyond its coarsest layer, leaving a visible horizon.
+//! The [`TerrainFarField`] is built from the coarsest lod of the height attachment, which is
+//! loaded once and stays resident, and is extended by a flat skirt beyond the border of the terrain.
+//! The detailed terrain discards its fragments beyond the coarsest quadtree layer, so that the far
+//! field is visible there, while it is drawn in front of the far field everywhere else.
+//!
+//! The far field is rendered as a regular mesh with a [`StandardMaterial`], so it is not shaded
+//! by the terrain material. It requires the [`TerrainFarFieldPlugin`] and an
+//! [`AttachmentFromDiskLoader`], which loads the height attachment of the terrain.
+
+use crate::{
+    attachment_loader::{start_loading_attachment_from_disk, AttachmentFromDiskLoader},
+    skip_none,
+    terrain::{CoverageFill, TerrainConfig},
+    terrain_data::{quadtree::sample_image, NodeCoordinate},
+};
+use bevy::{
+    asset::LoadState,
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_resource::TextureUsages,
+    },
+    utils::HashMap,
+};
+
+/// Renders a coarse mesh of the terrain beyond the quadtrees of its views.
+///
+/// Add this component to a terrain entity, which loads its attachments from disk.
+/// The mesh is rebuilt, whenever the component changes.
+#[derive(Clone, Component)]
+pub struct TerrainFarField {
+    /// The number of quads per side of the mesh, which covers the terrain.
+    pub resolution: u32,
+    /// The distance, by which the mesh is extended beyond the border of the terrain.
+    /// It should match the far plane of the cameras.
+    pub far_distance: f32,
+    /// The distance, by which the mesh is lowered, so that the detailed terrain is drawn in front
+    /// of it, where both overlap.
+    pub depth_offset: f32,
+    /// The color of the mesh.
+    pub color: Color,
+}
+
+impl Default for TerrainFarField {
+    fn default() -> Self {
+        Self {
+            resolution: 128,
+            far_distance: 1000.0,
+            depth_offset: 1.0,
+            color: Color::rgb(0.35, 0.38, 0.3),
+        }
+    }
+}
+
+/// The coarsest nodes of the height attachment and the mesh of a [`TerrainFarField`].
+#[derive(Component)]
+struct FarFieldNodes {
+    /// The images of the coarsest nodes keyed by their coordinate.
+    nodes: HashMap<UVec2, Handle<Image>>,
+    mesh: Option<Entity>,
+}
+
+impl FarFieldNodes {
+    /// Samples the height of the terrain at the position, or `None` if the position lies
+    /// outside of the coverage of the terrain.
+    fn sample_height(
+        &self,
+        config: &TerrainConfig,
+        images: &Assets<Image>,
+        position: Vec2,
+    ) -> Option<f32> {
+        let attachment = &config.attachments[0];
+        let node_size = (config.leaf_node_size << (config.lod_count - 1)) as f32;
+        let coordinate = (position / node_size).floor();
+
+        let image = images.get(self.nodes.get(&coordinate.as_uvec2())?)?;
+
+        let node_coords = position / node_size - coordinate;
+        let coords = (node_coords * attachment.center_size as f32 + attachment.border_size as f32)
+            / attachment.texture_size as f32;
+
+        Some(sample_image(image, coords) * config.height + config.elevation_offset())
+    }
+
+    /// Returns the lowest height of the terrain inside of each cell of the grid,
+    /// or `None` for cells outside of the coverage of the terrain.
+    fn cell_heights(
+        &self,
+        config: &TerrainConfig,
+        images: &Assets<Image>,
+        resolution: u32,
+    ) -> Vec<Option<f32>> {
+        let attachment = &config.attachments[0];
+        let node_size = (config.leaf_node_size << (config.lod_count - 1)) as f32;
+        let pixel_size = node_size / attachment.center_size as f32;
+        let cell_size = config.terrain_size as f32 / resolution as f32;
+
+        // each pixel of the coarsest lod is sampled at least once
+        let sample_count = (cell_size / pixel_size).ceil().max(1.0) as u32;
+
+        (0..resolution)
+            .flat_map(|y| (0..resolution).map(move |x| UVec2::new(x, y)))
+            .map(|cell| {
+                (0..sample_count)
+                    .flat_map(|y| (0..sample_count).map(move |x| UVec2::new(x, y)))
+                    .filter_map(|sample| {
+                        let offset = (sample.as_vec2() + 0.5) / sample_count as f32;
+                        let position = (cell.as_vec2() + offset) * cell_size;
+
+                        self.sample_height(config, images, position)
+                    })
+                    .reduce(f32::min)
+            })
+            .collect()
+    }
+
+    /// Builds the mesh of the terrain and its skirt, which extends beyond the border.
+    ///
+    /// The height of each vertex is the lowest height of the adjacent cells, so that the mesh
+    /// stays below the detailed terrain, where both overlap.
+    fn build_mesh(
+        &self,
+        config: &TerrainConfig,
+        far_field: &TerrainFarField,
+        images: &Assets<Image>,
+    ) -> Mesh {
+        let terrain_size = config.terrain_size as f32;
+        let resolution = far_field.resolution.max(1);
+        let cell_heights = self.cell_heights(config, images, resolution);
+
+        let fill_height = match config.coverage_fill {
+            CoverageFill::Skip => config.elevation_offset(),
+            CoverageFill::Fill { height } => height,
+        };
+
+        // the outermost coordinates belong to the skirt
+        let coordinates: Vec<f32> = [-far_field.far_distance]
+            .into_iter()
+            .chain((0..=resolution).map(|i| i as f32 / resolution as f32 * terrain_size))
+            .chain([terrain_size + far_field.far_distance])
+            .collect();
+        let count = coordinates.len();
+
+        let heights: Vec<f32> = (0..count)
+            .flat_map(|z| (0..count).map(move |x| IVec2::new(x as i32, z as i32)))
+            .map(|vertex| {
+                // the skirt continues the height of the border
+                let vertex = (vertex - 1).clamp(IVec2::ZERO, IVec2::splat(resolution as i32));
+
+                let height = [(-1, -1), (0, -1), (-1, 0), (0, 0)]
+                    .into_iter()
+                    .map(|(x, y)| vertex + IVec2::new(x, y))
+                    .filter(|cell| {
+                        cell.min_element() >= 0 && cell.max_element() < resolution as i32
+                    })
+                    .filter_map(|cell| cell_heights[(cell.y * resolution as i32 + cell.x) as usize])
+                    .reduce(f32::min);
+
+                height.unwrap_or(fill_height) - far_field.depth_offset
+            })
+            .collect();
+
+        let mut positions = Vec::with_capacity(count * count);
+        let mut normals = Vec::with_capacity(count * count);
+
+        for (z, &z_coordinate) in coordinates.iter().enumerate() {
+            for (x, &x_coordinate) in coordinates.iter().enumerate() {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(count - 1));
+                let (back, front) = (z.saturating_sub(1), (z + 1).min(count - 1));
+
+                let dx = (heights[z * count + right] - heights[z * count + left])
+                    / (coordinates[right] - coordinates[left]);
+                let dz = (heights[front * count + x] - heights[back * count + x])
+                    / (coordinates[front] - coordinates[back]);
+
+                positions.push([x_coordinate, heights[z * count + x], z_coordinate]);
+                normals.push(Vec3::new(-dx, 1.0, -dz).normalize().to_array());
+            }
+        }
+
+        let indices = (0..count as u32 - 1)
+            .flat_map(|z| (0..count as u32 - 1).map(move |x| (x, z)))
+            .flat_map(|(x, z)| {
+                let index = z * count as u32 + x;
+                let front = index + count as u32;
+
+                [index, front, index + 1, index + 1, front, front + 1]
+            })
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+/// Starts loading the coarsest nodes of the height attachment of newly added far fields.
+///
+/// The nodes are loaded once the node config of the terrain is available.
+fn start_loading_far_field(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    terrain_query: Query<
+        (Entity, &TerrainConfig, &AttachmentFromDiskLoader),
+        (With<TerrainFarField>, Without<FarFieldNodes>),
+    >,
+) {
+    for (terrain, config, loader) in terrain_query.iter() {
+        let attachment = skip_none!(loader.attachments.get(&0));
+
+        if config.nodes.is_empty() {
+            continue;
+        }
+
+        let nodes = config
+            .nodes
+            .iter()
+            .filter(|&&node_id| NodeCoordinate::from(node_id).lod == config.lod_count - 1)
+            .map(|&node_id| {
+                let coordinate = NodeCoordinate::from(node_id);
+                let handle = asset_server.load(&format!(
+                    "{}/{node_id}.{}",
+                    attachment.path,
+                    attachment.file_format.extension()
+                ));
+
+                (UVec2::new(coordinate.x, coordinate.y), handle)
+            })
+            .collect();
+
+        commands
+            .entity(terrain)
+            .insert(FarFieldNodes { nodes, mesh: None });
+    }
+}
+
+/// Builds the meshes of the far fields, whose nodes have finished loading or which have changed.
+fn build_far_field(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut terrain_query: Query<(
+        Entity,
+        &TerrainConfig,
+        &AttachmentFromDiskLoader,
+        &TerrainFarField,
+        ChangeTrackers<TerrainFarField>,
+        &mut FarFieldNodes,
+    )>,
+) {
+    for (terrain, config, loader, far_field, far_field_trackers, mut nodes) in
+        terrain_query.iter_mut()
+    {
+        if nodes.mesh.is_some() && !far_field_trackers.is_changed() {
+            continue;
+        }
+
+        // nodes, which failed to load, are treated as uncovered
+        let loading = nodes.nodes.values().any(|handle| {
+            matches!(
+                asset_server.get_load_state(handle),
+                LoadState::NotLoaded | LoadState::Loading
+            )
+        });
+
+        if loading {
+            continue;
+        }
+
+        let attachment = skip_none!(loader.attachments.get(&0));
+
+        // the node atlas reuses the loaded images, thus they have to be prepared like its own
+        for handle in nodes.nodes.values() {
+            let image = skip_none!(images.get(handle));
+
+            if image.texture_descriptor.format != attachment.format {
+                let image = images.get_mut(handle).unwrap();
+                image.texture_descriptor.format = attachment.format;
+                image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+            }
+        }
+
+        if let Some(mesh) = nodes.mesh.take() {
+            commands.entity(mesh).despawn_recursive();
+        }
+
+        let mesh = nodes.build_mesh(config, far_field, &images);
+
+        let mesh = commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(StandardMaterial {
+                        base_color: far_field.color,
+                        perceptual_roughness: 1.0,
+                        ..default()
+                    }),
+                    ..default()
+                },
+                NotShadowCaster,
+            ))
+            .id();
+
+        commands.entity(terrain).add_child(mesh);
+        nodes.mesh = Some(mesh);
+    }
+}
+
+/// Removes the meshes of far fields, which have been removed from their terrain.
+fn remove_far_field(
+    mut commands: Commands,
+    removed_far_fields: RemovedComponents<TerrainFarField>,
+    terrain_query: Query<&FarFieldNodes>,
+) {
+    for terrain in removed_far_fields.iter() {
+        let nodes = skip_none!(terrain_query.get(terrain).ok());
+
+        if let Some(mesh) = nodes.mesh {
+            commands.entity(mesh).despawn_recursive();
+        }
+
+        commands.entity(terrain).remove::<FarFieldNodes>();
+    }
+}
+
+/// Builds and renders the [`TerrainFarField`]s of the terrains.
+pub struct TerrainFarFieldPlugin;
+
+impl Plugin for TerrainFarFieldPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(CoreStage::Last, start_loading_far_field)
+            .add_system_to_stage(
+                CoreStage::Last,
+                build_far_field.before(start_loading_attachment_from_disk),
+            )
+            .add_system_to_stage(CoreStage::Last, remove_far_field);
+    }
+}
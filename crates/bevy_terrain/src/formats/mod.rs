@@ -2,10 +2,11 @@
 //!
 //! It is based on the DTM and QOI format internally.
 
+pub mod streaming_state;
 pub mod tc;
 pub mod tdf;
 
-use crate::formats::tdf::TDF;
+use crate::formats::{tc::TC, tdf::TDF};
 use bevy::{
     asset::{AssetLoader, Error, LoadedAsset},
     prelude::*,
@@ -21,7 +22,12 @@ impl AssetLoader for TDFAssetLoader {
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<(), Error>> {
         Box::pin(async move {
-            let (descriptor, mut data) = TDF::decode_alloc(bytes, true).unwrap();
+            let (descriptor, mut data) = TDF::decode_alloc(bytes, true)?;
+
+            // single precision floats are not filterable on all devices
+            if descriptor.float && descriptor.pixel_size == 4 {
+                data = tdf::to_half_float(&data, descriptor.channel_count);
+            }
 
             // extend alpha channel
             if descriptor.channel_count == 3 && descriptor.pixel_size == 1 {
@@ -61,11 +67,35 @@ impl AssetLoader for TDFAssetLoader {
     }
 }
 
-/// Plugin that registers the `TDFAssetLoader`.
+struct TCAssetLoader;
+
+impl AssetLoader for TCAssetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy::asset::LoadContext,
+    ) -> bevy::utils::BoxedFuture<'a, Result<(), Error>> {
+        Box::pin(async move {
+            let tc = TC::decode_alloc(bytes)?;
+
+            load_context.set_default_asset(LoadedAsset::new(tc));
+
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tc"]
+    }
+}
+
+/// Plugin that registers the `TDFAssetLoader` and the `TCAssetLoader`.
 pub struct TDFPlugin;
 
 impl Plugin for TDFPlugin {
     fn build(&self, app: &mut App) {
-        app.add_asset_loader(TDFAssetLoader);
+        app.add_asset::<TC>()
+            .add_asset_loader(TDFAssetLoader)
+            .add_asset_loader(TCAssetLoader);
     }
 }
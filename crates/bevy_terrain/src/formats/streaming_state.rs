@@ -0,0 +1,52 @@
+//! The streaming state of a terrain view, which allows resuming the streaming instantly.
+
+use crate::terrain_data::NodeId;
+use anyhow::{anyhow, Result};
+use bincode::{config, Decode, Encode};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, path::Path};
+
+/// Identifies streaming state files.
+const STREAMING_STATE_MAGIC: [u8; 4] = *b"BTSS";
+
+/// The set of nodes, which were resident for a terrain view.
+///
+/// Save it (e.g. when the game exits or before a level is unloaded) via
+/// [`Quadtree::streaming_state`](crate::terrain_data::quadtree::Quadtree::streaming_state)
+/// and pass it to
+/// [`Quadtree::warm_start`](crate::terrain_data::quadtree::Quadtree::warm_start) on the next run,
+/// so that all of these nodes are loaded at once, instead of being refined step by step.
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamingState {
+    /// The resident nodes in ascending order.
+    pub nodes: Vec<NodeId>,
+}
+
+impl StreamingState {
+    pub fn decode_alloc(encoded: &[u8]) -> Result<Self> {
+        let encoded = encoded
+            .strip_prefix(&STREAMING_STATE_MAGIC)
+            .ok_or_else(|| anyhow!("The data is not a streaming state."))?;
+
+        Ok(bincode::decode_from_slice(encoded, config::standard())?.0)
+    }
+
+    pub fn encode_alloc(&self) -> Result<Vec<u8>> {
+        let mut encoded = STREAMING_STATE_MAGIC.to_vec();
+        encoded.extend(bincode::encode_to_vec(self, config::standard())?);
+        Ok(encoded)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let encoded = fs::read(path)?;
+        Self::decode_alloc(&encoded)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let encoded = self.encode_alloc()?;
+        fs::write(path, encoded)?;
+        Ok(())
+    }
+}
@@ -1,34 +1,142 @@
-use crate::terrain_data::NodeId;
+//! The terrain config (TC) manifest, which describes the preprocessed data of a terrain.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    terrain::TerrainConfig,
+    terrain_data::{AttachmentConfig, NodeId},
+};
 use anyhow::Result;
+use bevy::{log::warn, reflect::TypeUuid, render::render_resource::TextureFormat};
 use bincode::{config, Decode, Encode};
+#[cfg(not(target_arch = "wasm32"))]
 use std::{fs, path::Path};
 
-#[derive(Encode, Decode, Debug)]
+/// Identifies versioned manifests. Manifests without it predate the versioning (version 1).
+const TC_MAGIC: [u8; 4] = *b"BTTC";
+
+/// The version of the on-disk node layout, which is increased whenever it changes.
+///
+/// * 1 - 32 bit node ids (6 bit lod, 13 bit x and y), no manifest header
+/// * 2 - 64 bit node ids (6 bit lod, 29 bit x and y)
+pub const TC_VERSION: u32 = 2;
+
+/// The manifest of the preprocessed data of a terrain, which is written by the preprocessor.
+///
+/// It stores the version of the node layout, the layout of the preprocessed attachments and
+/// the [`NodeId`]s of all the nodes of the terrain.
+#[derive(Encode, Decode, Debug, TypeUuid)]
+#[uuid = "1b4c2d8e-3f9a-4e6b-9c7d-5a8f0e2b6d41"]
 pub struct TC {
+    pub version: u32,
+    /// The count of level of detail layers, the data was preprocessed with.
+    pub lod_count: u32,
+    /// The preprocessed attachments.
+    pub attachments: Vec<AttachmentConfig>,
     pub nodes: Vec<NodeId>,
 }
 
+/// The manifest before the versioning, which only stored the node ids.
+#[derive(Decode)]
+struct LegacyTC {
+    nodes: Vec<u32>,
+}
+
 impl TC {
     pub fn decode_alloc(encoded: &[u8]) -> Result<Self> {
         let config = config::standard();
-        let decoded = bincode::decode_from_slice(encoded, config)?;
-        Ok(decoded.0)
+
+        match encoded.strip_prefix(&TC_MAGIC) {
+            Some(encoded) => Ok(bincode::decode_from_slice(encoded, config)?.0),
+            None => {
+                let legacy: LegacyTC = bincode::decode_from_slice(encoded, config)?.0;
+
+                Ok(Self {
+                    version: 1,
+                    lod_count: 0,
+                    attachments: vec![],
+                    nodes: legacy.nodes.into_iter().map(NodeId::from).collect(),
+                })
+            }
+        }
     }
 
     pub fn encode_alloc(&self) -> Result<Vec<u8>> {
         let config = config::standard();
-        let encoded = bincode::encode_to_vec(self, config)?;
+        let mut encoded = TC_MAGIC.to_vec();
+        encoded.extend(bincode::encode_to_vec(self, config)?);
         Ok(encoded)
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let encoded = fs::read(path)?;
         Self::decode_alloc(&encoded)
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let encoded = self.encode_alloc()?;
         fs::write(path, encoded)?;
         Ok(())
     }
+
+    /// Checks, whether the preprocessed data matches the config of the terrain.
+    ///
+    /// Attachments, which have not been preprocessed (e.g. procedural ones), are skipped.
+    /// Manifests migrated from the legacy version do not list any attachments.
+    pub fn validate(&self, config: &TerrainConfig) -> TerrainResult<()> {
+        if self.version > TC_VERSION {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The terrain {} was preprocessed with a newer node layout (version {}), than is supported (version {TC_VERSION}).",
+                config.path,
+                self.version
+            )));
+        }
+
+        if self.version < TC_VERSION {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The terrain {} was preprocessed with an outdated node layout (version {}) and has to be migrated by `load_node_config` or preprocessed again.",
+                config.path,
+                self.version
+            )));
+        }
+
+        if self.lod_count != config.lod_count {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The terrain {} was preprocessed with {} lods, but is configured with {}.",
+                config.path, self.lod_count, config.lod_count
+            )));
+        }
+
+        if self.attachments.is_empty() {
+            return Ok(());
+        }
+
+        for attachment in &config.attachments {
+            let preprocessed = match self.attachments.iter().find(|a| a.name == attachment.name) {
+                Some(preprocessed) => preprocessed,
+                None => {
+                    warn!(
+                        "The attachment {} of the terrain {} has not been preprocessed.",
+                        attachment.name, config.path
+                    );
+                    continue;
+                }
+            };
+
+            if preprocessed.texture_size != attachment.texture_size
+                || preprocessed.border_size != attachment.border_size
+                || preprocessed.mip_level_count != attachment.mip_level_count
+                || TextureFormat::from(preprocessed.format) != attachment.format
+            {
+                return Err(TerrainError::InvalidConfig(format!(
+                    "The attachment {} of the terrain {} was preprocessed as {preprocessed:?}, which does not match its config.",
+                    attachment.name,
+                    config.path
+                )));
+            }
+        }
+
+        Ok(())
+    }
 }
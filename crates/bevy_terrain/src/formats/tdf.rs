@@ -1,17 +1,28 @@
 use anyhow::{anyhow, Result};
 use dtm::DTM;
+use half::f16;
 use itertools::iproduct;
 use rapid_qoi::{Colors, Qoi};
+#[cfg(not(target_arch = "wasm32"))]
 use std::{fs, path::Path};
 
 const TDF_HEADER_SIZE: usize = 7;
 
+/// The bit of the pixel size byte, which marks floating point data.
+/// Floating point data is stored uncompressed.
+const TDF_FLOAT_FLAG: u8 = 0x80;
+/// The bit of the pixel size byte, which marks categorical data.
+/// The mip maps of categorical data are down sampled by the majority instead of the average.
+const TDF_CATEGORICAL_FLAG: u8 = 0x40;
+
 #[derive(Debug)]
 pub struct TDF {
     pub pixel_size: u32,
     pub channel_count: u32,
     pub mip_level_count: u32,
     pub size: u32,
+    pub float: bool,
+    pub categorical: bool,
 }
 
 impl TDF {
@@ -22,11 +33,17 @@ impl TDF {
     }
 
     pub fn decode_alloc(encoded: &[u8], mip_maps: bool) -> Result<(Self, Vec<u8>)> {
+        if encoded.len() < TDF_HEADER_SIZE {
+            return Err(anyhow!("The TDF header is incomplete."));
+        }
+
         let mut descriptor = TDF {
-            pixel_size: encoded[0] as u32,
+            pixel_size: (encoded[0] & !(TDF_FLOAT_FLAG | TDF_CATEGORICAL_FLAG)) as u32,
             channel_count: encoded[1] as u32,
             mip_level_count: encoded[2] as u32,
             size: u32::from_be_bytes(encoded[3..7].try_into().unwrap()),
+            float: encoded[0] & TDF_FLOAT_FLAG != 0,
+            categorical: encoded[0] & TDF_CATEGORICAL_FLAG != 0,
         };
 
         if !mip_maps {
@@ -52,6 +69,8 @@ impl TDF {
 
             if encoded_size == decoded_size {
                 decoded.copy_from_slice(encoded);
+            } else if descriptor.float {
+                return Err(anyhow!("The floating point data is incomplete."));
             } else {
                 if descriptor.pixel_size == 1 {
                     Qoi::decode(encoded, decoded)?;
@@ -72,6 +91,25 @@ impl TDF {
             let c_start = decoded_start + decoded_size;
 
             match (descriptor.channel_count, descriptor.pixel_size) {
+                (1, 1) if descriptor.categorical => {
+                    generate_majority_mipmap(&mut decoded, p_size, c_size, p_start, c_start)
+                }
+                (c, 2) if descriptor.float => generate_float_mipmap::<2>(
+                    &mut decoded,
+                    c as usize,
+                    p_size,
+                    c_size,
+                    p_start,
+                    c_start,
+                ),
+                (c, 4) if descriptor.float => generate_float_mipmap::<4>(
+                    &mut decoded,
+                    c as usize,
+                    p_size,
+                    c_size,
+                    p_start,
+                    c_start,
+                ),
                 (1, 2) => generate_mipmap::<1, 2>(&mut decoded, p_size, c_size, p_start, c_start),
                 (2, 2) => generate_mipmap::<2, 2>(&mut decoded, p_size, c_size, p_start, c_start),
                 (3, 1) => generate_mipmap::<3, 1>(&mut decoded, p_size, c_size, p_start, c_start),
@@ -94,7 +132,13 @@ impl TDF {
 
         let mut encoded = vec![0; TDF_HEADER_SIZE + decoded_size];
 
-        encoded[0] = self.pixel_size as u8;
+        encoded[0] = self.pixel_size as u8
+            | if self.float { TDF_FLOAT_FLAG } else { 0 }
+            | if self.categorical {
+                TDF_CATEGORICAL_FLAG
+            } else {
+                0
+            };
         encoded[1] = self.channel_count as u8;
         encoded[2] = self.mip_level_count as u8;
         encoded[3..7].copy_from_slice(&self.size.to_be_bytes());
@@ -107,7 +151,8 @@ impl TDF {
 
         let decoded = &decoded[decoded_start..decoded_start + decoded_size];
 
-        if self.pixel_size == 1 {
+        // floating point and single channel 8 bit data is stored uncompressed
+        if self.pixel_size == 1 && self.channel_count > 1 && !self.float {
             let colors = match self.channel_count {
                 3 => Ok(Colors::Rgb),
                 4 => Ok(Colors::Rgba),
@@ -126,7 +171,7 @@ impl TDF {
                 encoded_size = qoi_encoded.len();
                 encoded[encoded_start..encoded_start + encoded_size].copy_from_slice(&qoi_encoded);
             }
-        } else if self.pixel_size == 2 {
+        } else if self.pixel_size == 2 && !self.float {
             let descriptor = DTM {
                 pixel_size: self.pixel_size,
                 channel_count: self.channel_count,
@@ -151,11 +196,13 @@ impl TDF {
         Ok(encoded)
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn load_file<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<u8>)> {
         let encoded = fs::read(path)?;
         Self::decode_alloc(&encoded, false)
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn save_file<P: AsRef<Path>>(&self, path: P, decoded: &[u8]) -> Result<()> {
         let encoded = self.encode_alloc(decoded)?;
 
@@ -194,3 +241,132 @@ fn generate_mipmap<const C: usize, const P: usize>(
         }
     }
 }
+
+/// Generates the mip map of single channel 8 bit categorical data, where each pixel takes the
+/// most frequent value of its four parent pixels. Ties are resolved in favor of the first one.
+fn generate_majority_mipmap(
+    decoded: &mut [u8],
+    p_size: usize,
+    c_size: usize,
+    p_start: usize,
+    c_start: usize,
+) {
+    for (c_y, c_x) in iproduct!(0..c_size, 0..c_size) {
+        let values: [u8; 4] = std::array::from_fn(|i| {
+            let p_x = (c_x << 1) + (i >> 1);
+            let p_y = (c_y << 1) + (i & 1);
+
+            decoded[p_start + p_y * p_size + p_x]
+        });
+
+        decoded[c_start + c_y * c_size + c_x] = most_frequent(values);
+    }
+}
+
+/// Returns the most frequent of the four values, preferring the earlier ones on ties.
+pub(crate) fn most_frequent(values: [u8; 4]) -> u8 {
+    let count = |value: u8| values.iter().filter(|&&other| other == value).count();
+
+    values
+        .into_iter()
+        .rev()
+        .max_by_key(|&value| count(value))
+        .unwrap()
+}
+
+/// Converts single precision data, which is not filterable on all devices, into filterable
+/// half precision data with two channels.
+///
+/// Data with one channel (e.g. heights) is split into the half precision value and the half
+/// precision remainder, whose sum restores the value with almost single precision.
+/// Data with two channels (the minimum and the maximum height) is rounded outwards,
+/// so that the range still contains the exact one.
+pub fn to_half_float(data: &[u8], channel_count: u32) -> Vec<u8> {
+    let values = data
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()));
+
+    let halfs: Vec<f16> = if channel_count == 1 {
+        values
+            .flat_map(|value| {
+                let high = f16::from_f32(value);
+                [high, f16::from_f32(value - high.to_f32())]
+            })
+            .collect()
+    } else {
+        values
+            .enumerate()
+            .map(|(i, value)| {
+                if i % 2 == 0 {
+                    round_down(value)
+                } else {
+                    round_up(value)
+                }
+            })
+            .collect()
+    };
+
+    halfs.iter().flat_map(|half| half.to_le_bytes()).collect()
+}
+
+/// Converts the value to the largest half precision value not above it.
+fn round_down(value: f32) -> f16 {
+    let half = f16::from_f32(value);
+
+    if half.to_f32() <= value {
+        half
+    } else if half.to_bits() == 0 {
+        f16::from_bits(0x8001)
+    } else if half.is_sign_negative() {
+        f16::from_bits(half.to_bits() + 1)
+    } else {
+        f16::from_bits(half.to_bits() - 1)
+    }
+}
+
+/// Converts the value to the smallest half precision value not below it.
+fn round_up(value: f32) -> f16 {
+    -round_down(-value)
+}
+
+/// Reads the floating point value (half or single precision) from its little endian bytes.
+fn read_float<const P: usize>(bytes: &[u8]) -> f32 {
+    match P {
+        2 => f16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+        _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    }
+}
+
+/// Writes the floating point value (half or single precision) as little endian bytes.
+fn write_float<const P: usize>(bytes: &mut [u8], value: f32) {
+    match P {
+        2 => bytes.copy_from_slice(&f16::from_f32(value).to_le_bytes()),
+        _ => bytes.copy_from_slice(&value.to_le_bytes()),
+    }
+}
+
+fn generate_float_mipmap<const P: usize>(
+    decoded: &mut [u8],
+    channel_count: usize,
+    p_size: usize,
+    c_size: usize,
+    p_start: usize,
+    c_start: usize,
+) {
+    for (c_y, c_x, c) in iproduct!(0..c_size, 0..c_size, 0..channel_count) {
+        let mut value = 0.0;
+
+        for i in 0..4 {
+            let p_x = (c_x << 1) + (i >> 1);
+            let p_y = (c_y << 1) + (i & 1);
+
+            let index = p_start + P * (channel_count * (p_y * p_size + p_x) + c);
+
+            value += read_float::<P>(&decoded[index..index + P]);
+        }
+
+        let index = c_start + P * (channel_count * (c_y * c_size + c_x) + c);
+
+        write_float::<P>(&mut decoded[index..index + P], value / 4.0);
+    }
+}
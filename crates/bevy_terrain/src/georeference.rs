@@ -0,0 +1,343 @@
+//! Types for relating the terrain to real-world coordinate reference systems (CRS).
+//!
+//! UTM based coordinate reference systems are supported natively.
+//! Converting between arbitrary coordinate reference systems requires the `proj` feature.
+
+use crate::terrain::TerrainConfig;
+use bevy::{math::DVec2, prelude::*};
+
+/// The EPSG code of WGS 84, whose coordinates are the longitude and latitude in degrees.
+const WGS84: u32 = 4326;
+
+/// Describes how the terrain is placed inside a projected coordinate reference system.
+///
+/// The terrain is assumed to be axis aligned with its x axis pointing east and
+/// its z axis pointing south.
+///
+/// It can also describe the placement of a source tile, where one world unit corresponds to
+/// one pixel of the tile.
+#[derive(Clone, Copy, Debug)]
+pub struct Georeference {
+    /// The EPSG code of the projected coordinate reference system (e.g. 25833 for ETRS89 / UTM 33N).
+    pub epsg: u32,
+    /// The easting and northing of the terrain origin (its north-west corner).
+    pub origin: DVec2,
+    /// The size of one world unit in meters.
+    pub unit_size: f64,
+}
+
+impl Georeference {
+    pub fn new(epsg: u32, origin: DVec2, unit_size: f64) -> Self {
+        Self {
+            epsg,
+            origin,
+            unit_size,
+        }
+    }
+
+    /// Converts the world position into the easting and northing of the projected CRS.
+    pub fn to_projected(&self, position: Vec3) -> DVec2 {
+        self.origin + DVec2::new(position.x as f64, -position.z as f64) * self.unit_size
+    }
+
+    /// Converts the easting and northing of the projected CRS into the horizontal (x, z)
+    /// position of the terrain.
+    pub fn to_local(&self, projected: DVec2) -> Vec2 {
+        let local = (projected - self.origin) / self.unit_size;
+        Vec2::new(local.x as f32, -local.y as f32)
+    }
+
+    /// Returns the content of a world file (e.g. `.pgw` or `.tfw`), which places a raster inside
+    /// the projected CRS, so that it can be loaded by GIS applications.
+    ///
+    /// * `origin` - The horizontal (x, z) position of the center of the upper left pixel.
+    /// * `pixel_size` - The size of a pixel in world units.
+    pub fn world_file(&self, origin: Vec2, pixel_size: f32) -> String {
+        let origin = self.to_projected(Vec3::new(origin.x, 0.0, origin.y));
+        let pixel_size = pixel_size as f64 * self.unit_size;
+
+        format!(
+            "{pixel_size}\n0.0\n0.0\n{}\n{}\n{}\n",
+            -pixel_size, origin.x, origin.y
+        )
+    }
+
+    /// Converts the world position into the latitude and longitude (in degrees).
+    ///
+    /// Coordinate reference systems other than UTM return `None`, unless the `proj` feature is enabled.
+    pub fn world_to_geo(&self, position: Vec3) -> Option<DVec2> {
+        let projected = self.to_projected(position);
+
+        match utm_zone(self.epsg) {
+            Some((zone, north)) => Some(utm_to_geographic(projected, zone, north)),
+            None => {
+                let geographic = Transformation::new(self.epsg, WGS84)?.convert(projected)?;
+                Some(DVec2::new(geographic.y, geographic.x))
+            }
+        }
+    }
+
+    /// Converts the latitude and longitude (in degrees) into the horizontal (x, z) position
+    /// of the terrain.
+    ///
+    /// Coordinate reference systems other than UTM return `None`, unless the `proj` feature is enabled.
+    pub fn geo_to_world(&self, geographic: DVec2) -> Option<Vec2> {
+        let projected = match utm_zone(self.epsg) {
+            Some((zone, north)) => geographic_to_utm(geographic, zone, north),
+            None => Transformation::new(WGS84, self.epsg)?
+                .convert(DVec2::new(geographic.y, geographic.x))?,
+        };
+
+        Some(self.to_local(projected))
+    }
+}
+
+/// The reference surface, relative to which the heights of a terrain are measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VerticalDatum {
+    /// The heights are measured relative to the reference surface of the world (y = 0),
+    /// e.g. the WGS 84 ellipsoid.
+    #[default]
+    World,
+    /// The heights are measured relative to a geoid (e.g. EGM2008) or a national height system
+    /// (e.g. NAVD88), whose surface lies at the undulation (in meters) above the reference
+    /// surface of the world inside the area of the terrain.
+    Geoid { undulation: f64 },
+}
+
+impl VerticalDatum {
+    /// Returns the height of the datum above the reference surface of the world in meters.
+    pub fn offset(&self) -> f64 {
+        match self {
+            Self::World => 0.0,
+            Self::Geoid { undulation } => *undulation,
+        }
+    }
+}
+
+/// Places an entity on a georeferenced terrain by its real-world coordinates.
+///
+/// The entity has to be a child of the terrain. Its [`Transform`] is computed from the
+/// [`Georeference`] of the terrain, whenever either of them changes.
+/// Since the transform is relative to the terrain, the entity follows the terrain,
+/// when it is moved (e.g. by a floating origin).
+#[derive(Clone, Copy, Debug, Component)]
+pub struct GeoTransform {
+    /// The latitude in degrees.
+    pub lat: f64,
+    /// The longitude in degrees.
+    pub lon: f64,
+    /// The altitude in meters.
+    pub alt: f64,
+}
+
+impl GeoTransform {
+    pub fn new(lat: f64, lon: f64, alt: f64) -> Self {
+        Self { lat, lon, alt }
+    }
+
+    /// Computes the position of the entity relative to the terrain.
+    pub fn local_position(&self, georeference: &Georeference) -> Option<Vec3> {
+        let position = georeference.geo_to_world(DVec2::new(self.lat, self.lon))?;
+        let height = (self.alt / georeference.unit_size) as f32;
+
+        Some(Vec3::new(position.x, height, position.y))
+    }
+}
+
+/// Updates the transforms of all entities placed by a [`GeoTransform`].
+pub(crate) fn update_geo_transforms(
+    terrain_query: Query<(&TerrainConfig, ChangeTrackers<TerrainConfig>)>,
+    mut geo_query: Query<(
+        Entity,
+        &GeoTransform,
+        ChangeTrackers<GeoTransform>,
+        &Parent,
+        &mut Transform,
+    )>,
+) {
+    for (entity, geo_transform, geo_tracker, parent, mut transform) in geo_query.iter_mut() {
+        let (config, config_tracker) = match terrain_query.get(parent.get()) {
+            Ok(terrain) => terrain,
+            Err(_) => {
+                if geo_tracker.is_changed() {
+                    warn!("The GeoTransform of {entity:?} requires it to be a child of a terrain.");
+                }
+                continue;
+            }
+        };
+
+        if !geo_tracker.is_changed() && !config_tracker.is_changed() {
+            continue;
+        }
+
+        let georeference = match &config.georeference {
+            Some(georeference) => georeference,
+            None => {
+                warn!(
+                    "The GeoTransform of {entity:?} requires the terrain to have a georeference."
+                );
+                continue;
+            }
+        };
+
+        match geo_transform.local_position(georeference) {
+            Some(position) => transform.translation = position,
+            None => warn!(
+                "Can not place {entity:?}, since EPSG:{} is not supported without the `proj` feature.",
+                georeference.epsg
+            ),
+        }
+    }
+}
+
+/// Converts projected coordinates from one coordinate reference system into another.
+#[cfg(feature = "proj")]
+pub(crate) struct Transformation(Option<proj::Proj>);
+
+#[cfg(feature = "proj")]
+impl Transformation {
+    /// Creates the transformation between the EPSG codes, if it is supported.
+    pub(crate) fn new(from: u32, to: u32) -> Option<Self> {
+        if from == to {
+            return Some(Self(None));
+        }
+
+        match proj::Proj::new_known_crs(&format!("EPSG:{from}"), &format!("EPSG:{to}"), None) {
+            Ok(proj) => Some(Self(Some(proj))),
+            Err(error) => {
+                warn!("Can not transform from EPSG:{from} to EPSG:{to}: {error}");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn convert(&self, position: DVec2) -> Option<DVec2> {
+        match &self.0 {
+            Some(proj) => {
+                let (x, y) = proj.convert((position.x, position.y)).ok()?;
+                Some(DVec2::new(x, y))
+            }
+            None => Some(position),
+        }
+    }
+}
+
+/// Converts projected coordinates from one coordinate reference system into another.
+///
+/// Without the `proj` feature, only identical coordinate reference systems are supported.
+#[cfg(not(feature = "proj"))]
+pub(crate) struct Transformation;
+
+#[cfg(not(feature = "proj"))]
+impl Transformation {
+    /// Creates the transformation between the EPSG codes, if it is supported.
+    pub(crate) fn new(from: u32, to: u32) -> Option<Self> {
+        (from == to).then_some(Self)
+    }
+
+    pub(crate) fn convert(&self, position: DVec2) -> Option<DVec2> {
+        Some(position)
+    }
+}
+
+/// Returns the zone and hemisphere of UTM based coordinate reference systems.
+fn utm_zone(epsg: u32) -> Option<(u32, bool)> {
+    match epsg {
+        25828..=25838 => Some((epsg - 25800, true)), // ETRS89 / UTM
+        32601..=32660 => Some((epsg - 32600, true)), // WGS 84 / UTM north
+        32701..=32760 => Some((epsg - 32700, false)), // WGS 84 / UTM south
+        _ => None,
+    }
+}
+
+/// Projects the latitude and longitude (in degrees) with the transverse mercator projection of
+/// the UTM zone (Snyder, 1987).
+fn geographic_to_utm(geographic: DVec2, zone: u32, north: bool) -> DVec2 {
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    const K0: f64 = 0.9996;
+
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+
+    let phi = geographic.x.to_radians();
+    let central_meridian = (zone as f64 * 6.0 - 183.0).to_radians();
+
+    let (sin, cos, tan) = (phi.sin(), phi.cos(), phi.tan());
+    let n = A / (1.0 - e2 * sin * sin).sqrt();
+    let t = tan * tan;
+    let c = ep2 * cos * cos;
+    let a = cos * (geographic.y.to_radians() - central_meridian);
+
+    let m = A
+        * ((1.0 - e2 / 4.0 - 3.0 * e2.powi(2) / 64.0 - 5.0 * e2.powi(3) / 256.0) * phi
+            - (3.0 * e2 / 8.0 + 3.0 * e2.powi(2) / 32.0 + 45.0 * e2.powi(3) / 1024.0)
+                * (2.0 * phi).sin()
+            + (15.0 * e2.powi(2) / 256.0 + 45.0 * e2.powi(3) / 1024.0) * (4.0 * phi).sin()
+            - (35.0 * e2.powi(3) / 3072.0) * (6.0 * phi).sin());
+
+    let x = K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0)
+        + 500_000.0;
+    let y = K0
+        * (m + n
+            * tan
+            * (a.powi(2) / 2.0
+                + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+
+    DVec2::new(x, if north { y } else { y + 10_000_000.0 })
+}
+
+/// Inverts the transverse mercator projection of the UTM zone (Snyder, 1987).
+fn utm_to_geographic(projected: DVec2, zone: u32, north: bool) -> DVec2 {
+    const A: f64 = 6_378_137.0;
+    const F: f64 = 1.0 / 298.257_223_563;
+    const K0: f64 = 0.9996;
+
+    let e2 = F * (2.0 - F);
+    let ep2 = e2 / (1.0 - e2);
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+
+    let x = projected.x - 500_000.0;
+    let y = if north {
+        projected.y
+    } else {
+        projected.y - 10_000_000.0
+    };
+
+    let mu = y / K0 / (A * (1.0 - e2 / 4.0 - 3.0 * e2.powi(2) / 64.0 - 5.0 * e2.powi(3) / 256.0));
+    let phi = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1.powi(2) / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let (sin, cos, tan) = (phi.sin(), phi.cos(), phi.tan());
+    let n = A / (1.0 - e2 * sin * sin).sqrt();
+    let r = A * (1.0 - e2) / (1.0 - e2 * sin * sin).powf(1.5);
+    let t = tan * tan;
+    let c = ep2 * cos * cos;
+    let d = x / (n * K0);
+
+    let latitude = phi
+        - (n * tan / r)
+            * (d.powi(2) / 2.0
+                - (5.0 + 3.0 * t + 10.0 * c - 4.0 * c * c - 9.0 * ep2) * d.powi(4) / 24.0
+                + (61.0 + 90.0 * t + 298.0 * c + 45.0 * t * t - 252.0 * ep2 - 3.0 * c * c)
+                    * d.powi(6)
+                    / 720.0);
+    let longitude = (d - (1.0 + 2.0 * t + c) * d.powi(3) / 6.0
+        + (5.0 - 2.0 * c + 28.0 * t - 3.0 * c * c + 8.0 * ep2 + 24.0 * t * t) * d.powi(5) / 120.0)
+        / cos;
+
+    let central_meridian = (zone as f64 * 6.0 - 183.0).to_radians();
+
+    DVec2::new(
+        latitude.to_degrees(),
+        (central_meridian + longitude).to_degrees(),
+    )
+}
@@ -46,22 +46,77 @@
 extern crate core;
 
 use crate::{
-    attachment_loader::{finish_loading_attachment_from_disk, start_loading_attachment_from_disk},
+    attachment_loader::{
+        finish_loading_attachment_from_disk, finish_loading_node_config,
+        start_loading_attachment_from_disk,
+    },
+    camera_clamp::clamp_cameras_to_terrain,
     debug::DebugTerrain,
+    deformation::{
+        deform_terrain, extract_terrain_deformation, queue_terrain_deformation,
+        DeformationPipeline, GpuTerrainDeformation, TerrainDeformEvent,
+    },
+    erosion::{
+        extract_erosion, finish_erosion, queue_erosion, start_erosion, ErosionPipeline, GpuErosion,
+    },
+    error::TerrainLoadError,
     formats::TDFPlugin,
+    georeference::update_geo_transforms,
+    mesh_insert::{
+        extract_mesh_inserts, queue_mesh_inserts, update_mesh_inserts, GpuTerrainMeshInserts,
+    },
+    minimap::update_minimap,
+    node_entities::update_node_entities,
+    procedural_loader::{finish_generating_attachments, start_generating_attachments},
     render::{
+        atmosphere::{
+            extract_terrain_atmosphere, queue_terrain_atmosphere, GpuTerrainAtmosphere,
+            TerrainAtmosphere,
+        },
+        color_ramp::{extract_color_ramp, queue_color_ramp, ColorRamp, GpuColorRamp},
         compute_pipelines::{TerrainComputeNode, TerrainComputePipelines},
         culling::{queue_terrain_culling_bind_group, CullingBindGroup},
-        render_pipeline::TerrainPipelineConfig,
+        decals::{
+            extract_terrain_decals, queue_terrain_decals, ExtractedTerrainDecals, GpuDecalAtlas,
+        },
+        downlevel::{
+            extract_downlevel_terrains, queue_downlevel_tiles, DownlevelTerrain, TerrainRenderMode,
+        },
         shaders::add_shader,
-        terrain_data::{initialize_terrain_data, TerrainData},
+        soft_intersection::{
+            extract_soft_intersections, queue_soft_intersections, DrawSoftIntersection,
+            SoftIntersectionPipeline,
+        },
+        standard_material::{apply_season, Season},
+        terrain_data::{initialize_terrain_data, PlaceholderAttachment, TerrainData},
+        terrain_textures::{extract_terrain_textures, TerrainBindGroupLayout, TerrainTextures},
         terrain_view_data::TerrainViewConfigUniform,
         terrain_view_data::{
             extract_terrain_view_config, initialize_terrain_view_data, queue_terrain_view_config,
-            TerrainViewData,
+            SharedTerrainViewResources, TerrainViewData,
+        },
+        tint::{extract_terrain_tint, queue_terrain_tint, GpuTerrainTint},
+        vector_layer::{
+            queue_vector_layers, DrapedVectorLayer, DrawVectorLayer, TerrainVectorLayer,
+            VectorLayerPipeline,
         },
+        vegetation::{
+            extract_vegetation, initialize_vegetation_view_data, queue_vegetation, DrawVegetation,
+            VegetationPipelines, VegetationViewData,
+        },
+        viewshed::{
+            extract_terrain_viewshed, queue_terrain_viewshed, refresh_viewsheds,
+            GpuTerrainViewshed, ViewshedPipeline,
+        },
+        water::{extract_water, queue_water, DrawWater, WaterData, WaterPipeline},
+        weather::{extract_terrain_weather, queue_terrain_weather, GpuTerrainWeather},
+    },
+    shadow_view::{extract_shadow_views, update_shadow_view_configs, update_shadow_views},
+    snow::simulate_snow,
+    terrain::{
+        update_terrain_attachments, AddTerrainAttachment, RemoveTerrainAttachment, Terrain,
+        TerrainComponents, TerrainConfig,
     },
-    terrain::{Terrain, TerrainComponents, TerrainConfig},
     terrain_data::{
         gpu_node_atlas::{
             extract_node_atlas, initialize_gpu_node_atlas, queue_node_atlas_updates, GpuNodeAtlas,
@@ -69,47 +124,171 @@ use crate::{
         gpu_quadtree::{
             extract_quadtree, initialize_gpu_quadtree, queue_quadtree_update, GpuQuadtree,
         },
-        node_atlas::{update_node_atlas, NodeAtlas},
+        gpu_virtual_texture::{
+            extract_virtual_textures, queue_virtual_textures, GpuVirtualTexture,
+        },
+        height_query::{
+            extract_height_query, queue_height_query, update_height_query, GpuHeightQuery,
+            HeightQueryPipeline,
+        },
+        node_atlas::{update_node_atlas, NodeAtlas, TerrainEvent},
+        node_readback::{
+            extract_node_readback, queue_node_readback, update_node_readback, GpuNodeReadback,
+        },
         quadtree::{
-            adjust_quadtree, compute_quadtree_request, update_height_under_viewer, Quadtree,
+            adjust_quadtree, compute_quadtree_request, send_initial_load_events,
+            update_height_under_viewer, update_viewer_position, Quadtree,
         },
+        refinement::TerrainRefinement,
+        virtual_texture::update_virtual_textures,
+    },
+    terrain_view::{
+        remove_terrain_views, update_dependent_views, TerrainView, TerrainViewComponents,
+        TerrainViewConfig,
     },
-    terrain_view::{TerrainView, TerrainViewComponents, TerrainViewConfig},
 };
 use bevy::render::view::NoFrustumCulling;
 use bevy::{
+    core_pipeline::core_3d::{Opaque3d, Transparent3d},
     prelude::*,
     render::{
         extract_component::ExtractComponentPlugin, main_graph::node::CAMERA_DRIVER,
-        render_graph::RenderGraph, render_resource::*, RenderApp, RenderStage,
+        render_asset::RenderAssetPlugin, render_graph::RenderGraph, render_phase::AddRenderCommand,
+        render_resource::*, renderer::RenderDevice, RenderApp, RenderStage,
     },
+    transform::TransformSystem,
 };
 
+pub mod acoustics;
 pub mod attachment_loader;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod benchmark;
+pub mod biome;
+pub mod camera_clamp;
 pub mod debug;
+pub mod deformation;
+pub mod erosion;
+pub mod error;
+pub mod far_field;
 pub mod formats;
+pub mod georeference;
+#[cfg(feature = "measurement")]
+pub mod measurement;
+pub mod mesh_insert;
+pub mod minimap;
+pub mod node_entities;
+pub mod noise;
+#[cfg(feature = "overlay")]
+pub mod overlay;
 pub mod preprocess;
+pub mod procedural_loader;
 pub mod render;
+pub mod shadow_view;
+pub mod snow;
 pub mod terrain;
 pub mod terrain_data;
 pub mod terrain_view;
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+pub mod testing;
 
 pub mod prelude {
     //! `use bevy_terrain::prelude::*;` to import common components, bundles, and plugins.
     // #[doc(hidden)]
     pub use crate::{
-        attachment_loader::AttachmentFromDiskLoader,
-        debug::{camera::DebugCamera, TerrainDebugPlugin},
-        preprocess::{config::load_node_config, BaseConfig, Preprocessor, TileConfig},
-        render::render_pipeline::TerrainMaterialPlugin,
-        terrain::{Terrain, TerrainConfig},
+        acoustics::TerrainAcoustics,
+        attachment_loader::{AttachmentFromDiskLoader, NodeConfigLoader},
+        biome::{Biome, BiomeClassification, BiomeId},
+        camera_clamp::CameraTerrainClamp,
+        debug::{
+            camera::{DebugCamera, DebugCameraBindings, DebugCameraMode, OrbitController},
+            camera_path::{CameraEasing, CameraKeyframe, CameraPath, CameraPathRecorder},
+            capture::{CapturedFrame, FrameCapture, TerrainCapturePlugin},
+            command::{execute_command, TerrainCommand},
+            input::DebugInputMap,
+            DebugAction, TerrainDebugPlugin,
+        },
+        deformation::{DeformationProfile, TerrainDeformEvent, TerrainDeformation},
+        erosion::{ErosionConfig, TerrainErosion},
+        error::{TerrainError, TerrainLoadError},
+        far_field::{TerrainFarField, TerrainFarFieldPlugin},
+        formats::streaming_state::StreamingState,
+        georeference::{GeoTransform, Georeference, VerticalDatum},
+        mesh_insert::TerrainMeshInsert,
+        minimap::{spawn_terrain_minimap, MinimapSettings, TerrainMinimap},
+        node_entities::{TerrainNode, TerrainNodeEntities},
+        noise::{NoiseLayer, NoiseType},
+        preprocess::BaseConfig,
+        procedural_loader::ProceduralAttachmentLoader,
+        render::{
+            atmosphere::TerrainAtmosphere,
+            color_ramp::{ColorRamp, RampInterpolation},
+            decals::TerrainDecal,
+            diagnostics::TerrainDiagnosticsPlugin,
+            downlevel::TerrainRenderMode,
+            render_pipeline::TerrainMaterialPlugin,
+            soft_intersection::TerrainSoftIntersection,
+            standard_material::{
+                DetailLayer, HorizonShadows, Season, SnowLine, StandardTerrainMaterial,
+            },
+            terrain_textures::{TerrainBindGroupLayout, TerrainTextures},
+            tint::TerrainTint,
+            vector_layer::{DrapedVectorLayer, TerrainVectorLayer},
+            vegetation::TerrainVegetation,
+            viewshed::TerrainViewshed,
+            water::TerrainWater,
+            weather::TerrainWeather,
+        },
+        shadow_view::{spawn_terrain_shadow_view, ShadowViewSettings, TerrainShadowView},
+        snow::SnowSimulation,
+        terrain::{
+            AddTerrainAttachment, BorderSkirt, CoverageFill, HeightFilter, RemoveTerrainAttachment,
+            Terrain, TerrainConfig,
+        },
         terrain_data::{
-            node_atlas::NodeAtlas, quadtree::Quadtree, AttachmentConfig, AttachmentFormat,
-            FileFormat,
+            height_query::{HeightQueryResult, TerrainHeightQuery},
+            node_atlas::{NodeAtlas, TerrainEvent},
+            node_readback::NodeReadback,
+            quadtree::{NodeRequests, Quadtree},
+            refinement::{
+                AdaptiveRefinement, AdaptiveRefinementPlugin, DistanceHeuristic, RefinementContext,
+                RefinementHeuristic, RefinementHeuristicPlugin,
+            },
+            sampler::{ScatterRules, TerrainSampler},
+            virtual_texture::{TerrainVirtualTexture, VirtualTextureConfig},
+            AttachmentConfig, AttachmentFormat, AttachmentSampler, FileFormat,
+        },
+        terrain_view::{
+            BathymetryMode, DependentRefinement, DependentTerrainView, PatchTopology, TerrainView,
+            TerrainViewComponents, TerrainViewConfig,
         },
-        terrain_view::{TerrainView, TerrainViewComponents, TerrainViewConfig},
         TerrainBundle, TerrainPlugin,
     };
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use crate::{
+        benchmark::{headless_render_target, BenchmarkCamera, TerrainBenchmarkPlugin},
+        preprocess::{
+            amplify::DetailAmplification,
+            config::load_node_config,
+            cut_fill::{cut_fill_volume, CutFill},
+            derivative::{export_derivative_images, Derivative},
+            export::{export_heightmap, export_mesh, MeshExport, MeshFormat},
+            flow::FlowAnalysis,
+            road::{carve_road, Road, RoadSplat},
+            tin::{load_tin_meshes, TinMesh},
+            water::{WaterBodies, WaterFlattening},
+            Preprocessor, TileConfig,
+        },
+    };
+
+    #[cfg(feature = "debug_ui")]
+    pub use crate::debug::ui::TerrainDebugUiPlugin;
+    #[cfg(feature = "measurement")]
+    pub use crate::measurement::{
+        Measurement, ProfileSample, TerrainMeasurement, TerrainMeasurementPlugin,
+    };
+    #[cfg(feature = "overlay")]
+    pub use crate::overlay::TerrainOverlayPlugin;
 }
 
 /// The components of a terrain.
@@ -143,70 +322,198 @@ impl TerrainBundle {
 }
 
 /// The plugin for the terrain renderer.
-pub struct TerrainPlugin {
-    /// The number of terrain attachments.
-    pub attachment_count: usize,
-}
-
-impl Default for TerrainPlugin {
-    fn default() -> Self {
-        Self {
-            attachment_count: 2,
-        }
-    }
-}
+///
+/// Each terrain may use up to [`MAX_ATTACHMENTS`](render::terrain_data::MAX_ATTACHMENTS) attachments,
+/// devices with fewer than 19 sampled textures per shader stage bind fewer attachment slots.
+#[derive(Default)]
+pub struct TerrainPlugin;
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
         add_shader(app);
 
+        let mode = app
+            .world
+            .get_resource::<RenderDevice>()
+            .map(TerrainRenderMode::from_device)
+            .unwrap_or_default();
+
         app.add_plugin(TDFPlugin)
+            .insert_resource(mode)
             .add_plugin(ExtractComponentPlugin::<Terrain>::default())
             .add_plugin(ExtractComponentPlugin::<TerrainView>::default())
+            .add_plugin(ExtractComponentPlugin::<DrapedVectorLayer>::default())
+            .add_asset::<TerrainVectorLayer>()
+            .add_plugin(RenderAssetPlugin::<TerrainVectorLayer>::default())
             .init_resource::<TerrainViewComponents<Quadtree>>()
             .init_resource::<TerrainViewComponents<TerrainViewConfig>>()
+            .init_resource::<TerrainRefinement>()
+            .init_resource::<ColorRamp>()
+            .init_resource::<TerrainAtmosphere>()
+            .init_resource::<Season>()
+            .add_event::<AddTerrainAttachment>()
+            .add_event::<RemoveTerrainAttachment>()
+            .add_event::<TerrainLoadError>()
+            .add_event::<TerrainEvent>()
+            .add_event::<TerrainDeformEvent>()
+            .add_system_to_stage(
+                CoreStage::Last,
+                finish_loading_node_config.before(update_node_atlas),
+            )
             .add_system_to_stage(
                 CoreStage::Last,
                 finish_loading_attachment_from_disk.before(update_node_atlas),
             )
+            .add_system_to_stage(
+                CoreStage::Last,
+                finish_generating_attachments.before(update_node_atlas),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                remove_terrain_views.before(update_viewer_position),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_viewer_position.before(compute_quadtree_request),
+            )
             .add_system_to_stage(
                 CoreStage::Last,
                 compute_quadtree_request.before(update_node_atlas),
             )
             .add_system_to_stage(CoreStage::Last, update_node_atlas)
             .add_system_to_stage(CoreStage::Last, adjust_quadtree.after(update_node_atlas))
+            .add_system_to_stage(
+                CoreStage::Last,
+                send_initial_load_events.after(update_node_atlas),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_terrain_attachments
+                    .after(update_node_atlas)
+                    .before(start_loading_attachment_from_disk)
+                    .before(start_generating_attachments),
+            )
             .add_system_to_stage(
                 CoreStage::Last,
                 start_loading_attachment_from_disk.after(update_node_atlas),
             )
+            .add_system_to_stage(
+                CoreStage::Last,
+                start_generating_attachments.after(update_node_atlas),
+            )
+            .add_system_to_stage(CoreStage::Last, start_erosion)
+            .add_system_to_stage(
+                CoreStage::Last,
+                finish_erosion
+                    .after(update_node_atlas)
+                    .before(start_generating_attachments),
+            )
             .add_system_to_stage(
                 CoreStage::Last,
                 update_height_under_viewer.after(adjust_quadtree),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_dependent_views.after(update_height_under_viewer),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_shadow_view_configs.after(update_dependent_views),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_node_readback.after(update_node_atlas),
+            )
+            .add_system(update_minimap)
+            .add_system(update_shadow_views)
+            .add_system(update_geo_transforms)
+            .add_system(apply_season)
+            .add_system_to_stage(CoreStage::Last, update_height_query)
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_virtual_textures.after(update_viewer_position),
+            )
+            .add_system_to_stage(CoreStage::Last, refresh_viewsheds.after(update_node_atlas))
+            .add_system_to_stage(CoreStage::Last, deform_terrain.after(update_node_atlas))
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_mesh_inserts.after(update_node_atlas),
+            )
+            .add_system_to_stage(
+                CoreStage::Last,
+                update_node_entities.after(update_node_atlas),
+            )
+            .add_system_to_stage(CoreStage::Last, simulate_snow.after(adjust_quadtree))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                clamp_cameras_to_terrain.before(TransformSystem::TransformPropagate),
             );
 
-        let render_app = app
-            .sub_app_mut(RenderApp)
-            .insert_resource(TerrainPipelineConfig {
-                attachment_count: self.attachment_count,
-            })
-            .init_resource::<TerrainComputePipelines>()
-            .init_resource::<SpecializedComputePipelines<TerrainComputePipelines>>()
+        // headless apps without a renderer (e.g. tests) only stream the terrain data
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+
+        render_app
+            .insert_resource(mode)
             .init_resource::<TerrainComponents<GpuNodeAtlas>>()
+            .init_resource::<PlaceholderAttachment>()
             .init_resource::<TerrainComponents<TerrainData>>()
+            .init_resource::<TerrainComponents<GpuNodeReadback>>()
             .init_resource::<TerrainViewComponents<GpuQuadtree>>()
+            .init_resource::<SharedTerrainViewResources>()
             .init_resource::<TerrainViewComponents<TerrainViewData>>()
             .init_resource::<TerrainViewComponents<TerrainViewConfigUniform>>()
-            .init_resource::<TerrainViewComponents<CullingBindGroup>>()
+            .init_resource::<GpuDecalAtlas>()
+            .init_resource::<GpuColorRamp>()
+            .init_resource::<GpuTerrainAtmosphere>()
+            .init_resource::<TerrainComponents<GpuTerrainTint>>()
+            .init_resource::<TerrainComponents<GpuTerrainWeather>>()
+            .init_resource::<TerrainComponents<GpuTerrainViewshed>>()
+            .init_resource::<TerrainComponents<GpuVirtualTexture>>()
+            .init_resource::<TerrainComponents<GpuTerrainDeformation>>()
+            .init_resource::<DeformationPipeline>()
+            .init_resource::<SpecializedRenderPipelines<DeformationPipeline>>()
+            .init_resource::<TerrainComponents<GpuTerrainMeshInserts>>()
+            .init_resource::<TerrainBindGroupLayout>()
+            .init_resource::<TerrainTextures>()
+            .init_resource::<SoftIntersectionPipeline>()
+            .init_resource::<SpecializedMeshPipelines<SoftIntersectionPipeline>>()
+            .add_render_command::<Transparent3d, DrawSoftIntersection>()
+            .init_resource::<ExtractedTerrainDecals>()
             .add_system_to_stage(RenderStage::Extract, extract_terrain_view_config)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_decals)
+            .add_system_to_stage(RenderStage::Extract, extract_color_ramp)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_atmosphere)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_tint)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_weather)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_viewshed)
+            .add_system_to_stage(RenderStage::Extract, extract_virtual_textures)
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_deformation)
+            .add_system_to_stage(RenderStage::Extract, extract_mesh_inserts)
+            .add_system_to_stage(RenderStage::Extract, extract_soft_intersections)
+            .add_system_to_stage(RenderStage::Extract, extract_shadow_views)
             .add_system_to_stage(RenderStage::Extract, initialize_gpu_node_atlas)
             .add_system_to_stage(RenderStage::Extract, initialize_gpu_quadtree)
             .add_system_to_stage(
                 RenderStage::Extract,
-                initialize_terrain_data.after(initialize_gpu_node_atlas),
+                initialize_terrain_data.after(extract_node_atlas),
+            )
+            .add_system_to_stage(
+                RenderStage::Extract,
+                initialize_terrain_view_data
+                    .after(initialize_gpu_quadtree)
+                    .after(extract_terrain_tint)
+                    .after(extract_terrain_weather)
+                    .after(extract_terrain_viewshed)
+                    .after(extract_virtual_textures),
             )
             .add_system_to_stage(
                 RenderStage::Extract,
-                initialize_terrain_view_data.after(initialize_gpu_quadtree),
+                extract_terrain_textures
+                    .after(initialize_terrain_data)
+                    .after(initialize_terrain_view_data),
             )
             .add_system_to_stage(
                 RenderStage::Extract,
@@ -216,18 +523,98 @@ impl Plugin for TerrainPlugin {
                 RenderStage::Extract,
                 extract_quadtree.after(initialize_gpu_quadtree),
             )
+            .add_system_to_stage(
+                RenderStage::Extract,
+                extract_node_readback.after(extract_node_atlas),
+            )
             .add_system_to_stage(RenderStage::Queue, queue_quadtree_update)
             .add_system_to_stage(RenderStage::Queue, queue_node_atlas_updates)
-            .add_system_to_stage(RenderStage::Queue, queue_terrain_culling_bind_group)
-            .add_system_to_stage(RenderStage::Queue, queue_terrain_view_config);
+            .add_system_to_stage(
+                RenderStage::Queue,
+                queue_node_readback.after(queue_node_atlas_updates),
+            )
+            .add_system_to_stage(
+                RenderStage::Queue,
+                queue_terrain_deformation
+                    .after(queue_node_atlas_updates)
+                    .before(queue_node_readback),
+            )
+            .add_system_to_stage(
+                RenderStage::Queue,
+                queue_mesh_inserts.after(queue_node_atlas_updates),
+            )
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_view_config)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_decals)
+            .add_system_to_stage(RenderStage::Queue, queue_color_ramp)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_atmosphere)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_tint)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_weather)
+            .add_system_to_stage(RenderStage::Queue, queue_soft_intersections)
+            .add_system_to_stage(RenderStage::Queue, queue_virtual_textures);
 
-        let compute_node = TerrainComputeNode::from_world(&mut render_app.world);
+        match mode {
+            TerrainRenderMode::Compute => {
+                render_app
+                    .init_resource::<TerrainComputePipelines>()
+                    .init_resource::<SpecializedComputePipelines<TerrainComputePipelines>>()
+                    .init_resource::<TerrainComponents<GpuHeightQuery>>()
+                    .init_resource::<HeightQueryPipeline>()
+                    .init_resource::<TerrainComponents<GpuErosion>>()
+                    .init_resource::<ErosionPipeline>()
+                    .init_resource::<ViewshedPipeline>()
+                    .init_resource::<TerrainViewComponents<CullingBindGroup>>()
+                    .init_resource::<VectorLayerPipeline>()
+                    .init_resource::<SpecializedRenderPipelines<VectorLayerPipeline>>()
+                    .add_render_command::<Transparent3d, DrawVectorLayer>()
+                    .init_resource::<TerrainViewComponents<VegetationViewData>>()
+                    .init_resource::<VegetationPipelines>()
+                    .init_resource::<SpecializedComputePipelines<VegetationPipelines>>()
+                    .init_resource::<SpecializedRenderPipelines<VegetationPipelines>>()
+                    .add_render_command::<Opaque3d, DrawVegetation>()
+                    .init_resource::<TerrainComponents<WaterData>>()
+                    .init_resource::<WaterPipeline>()
+                    .init_resource::<SpecializedRenderPipelines<WaterPipeline>>()
+                    .add_render_command::<Transparent3d, DrawWater>()
+                    .add_system_to_stage(RenderStage::Extract, extract_water)
+                    .add_system_to_stage(RenderStage::Extract, initialize_vegetation_view_data)
+                    .add_system_to_stage(
+                        RenderStage::Extract,
+                        extract_vegetation.after(initialize_vegetation_view_data),
+                    )
+                    .add_system_to_stage(RenderStage::Extract, extract_height_query)
+                    .add_system_to_stage(RenderStage::Extract, extract_erosion)
+                    .add_system_to_stage(RenderStage::Queue, queue_terrain_culling_bind_group)
+                    .add_system_to_stage(
+                        RenderStage::Queue,
+                        queue_height_query.after(queue_terrain_culling_bind_group),
+                    )
+                    .add_system_to_stage(RenderStage::Queue, queue_vector_layers)
+                    .add_system_to_stage(RenderStage::Queue, queue_vegetation)
+                    .add_system_to_stage(RenderStage::Queue, queue_water)
+                    .add_system_to_stage(RenderStage::Queue, queue_erosion)
+                    .add_system_to_stage(
+                        RenderStage::Queue,
+                        queue_terrain_viewshed.after(queue_terrain_culling_bind_group),
+                    );
 
-        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-        render_graph.add_node("terrain_compute", compute_node);
+                let compute_node = TerrainComputeNode::from_world(&mut render_app.world);
 
-        render_graph
-            .add_node_edge("terrain_compute", CAMERA_DRIVER)
-            .unwrap();
+                let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+                render_graph.add_node("terrain_compute", compute_node);
+
+                render_graph
+                    .add_node_edge("terrain_compute", CAMERA_DRIVER)
+                    .unwrap();
+            }
+            TerrainRenderMode::Downlevel => {
+                render_app
+                    .init_resource::<TerrainComponents<DownlevelTerrain>>()
+                    .add_system_to_stage(RenderStage::Extract, extract_downlevel_terrains)
+                    .add_system_to_stage(
+                        RenderStage::Queue,
+                        queue_downlevel_tiles.after(queue_terrain_view_config),
+                    );
+            }
+        }
     }
 }
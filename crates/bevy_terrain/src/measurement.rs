@@ -0,0 +1,416 @@
+//! Interactive measurements of distances, areas and elevation profiles on the terrain.
+//!
+//! While the [`TerrainMeasurement`] is enabled, clicking on the terrain with the add button
+//! appends a point to the measured path and the clear button removes all points.
+//! The terrain is picked by marching the ray of the cursor against the heights of the
+//! currently loaded nodes.
+//!
+//! Whenever the points change, a [`Measurement`] event is sent, which contains the distance
+//! along the path, the area enclosed by it and its elevation profile, so that apps can display
+//! them in their own UI.
+//! On georeferenced terrains the distance and the area are measured on the surface of the
+//! earth (approximated by a sphere), otherwise in the plane of the terrain.
+
+use crate::{
+    terrain::{Terrain, TerrainConfig},
+    terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree},
+    terrain_view::{TerrainView, TerrainViewComponents},
+};
+use bevy::{math::DVec2, prelude::*};
+
+/// The mean radius of the earth in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+/// The number of steps the ray is marched with, before the hit is refined.
+const MARCH_STEPS: u32 = 512;
+/// The number of bisection steps, which refine the hit.
+const REFINE_STEPS: u32 = 16;
+
+/// Adds the [`TerrainMeasurement`] resource and sends [`Measurement`] events.
+pub struct TerrainMeasurementPlugin;
+
+impl Plugin for TerrainMeasurementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainMeasurement>()
+            .add_event::<Measurement>()
+            .add_system(pick_measurement_points)
+            .add_system(send_measurement.after(pick_measurement_points));
+    }
+}
+
+/// The points of the measured path and the controls of the measurement.
+#[derive(Clone, Debug, Resource)]
+pub struct TerrainMeasurement {
+    /// Whether or not clicking on the terrain adds points.
+    pub enabled: bool,
+    /// The mouse button, which adds the point under the cursor.
+    pub add_button: MouseButton,
+    /// The mouse button, which removes all points.
+    pub clear_button: MouseButton,
+    /// The number of heights sampled along the whole path for the elevation profile.
+    pub profile_samples: u32,
+    terrain: Option<Entity>,
+    points: Vec<Vec3>,
+}
+
+impl Default for TerrainMeasurement {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            add_button: MouseButton::Left,
+            clear_button: MouseButton::Right,
+            profile_samples: 256,
+            terrain: None,
+            points: Vec::new(),
+        }
+    }
+}
+
+impl TerrainMeasurement {
+    /// Returns the points of the measured path.
+    pub fn points(&self) -> &[Vec3] {
+        &self.points
+    }
+
+    /// Appends the point on the surface of the terrain to the path.
+    ///
+    /// Points on another terrain start a new path.
+    pub fn add_point(&mut self, terrain: Entity, point: Vec3) {
+        if self.terrain != Some(terrain) {
+            self.points.clear();
+        }
+
+        self.terrain = Some(terrain);
+        self.points.push(point);
+    }
+
+    /// Removes all points.
+    pub fn clear(&mut self) {
+        self.terrain = None;
+        self.points.clear();
+    }
+}
+
+/// A height of the elevation profile.
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileSample {
+    /// The horizontal distance from the start of the path in meters.
+    pub distance: f64,
+    /// The position on the path, whose height is sampled.
+    pub position: Vec2,
+    /// The height of the terrain, if it is loaded and covered at the position.
+    pub height: Option<f32>,
+}
+
+/// The result of a measurement, which is sent as an event, whenever the points change.
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    /// The measured terrain.
+    pub terrain: Entity,
+    /// The points of the path.
+    pub points: Vec<Vec3>,
+    /// The horizontal (geodesic) length of the path in meters.
+    pub distance: f64,
+    /// The length of the path along the surface of the terrain in meters,
+    /// approximated by the elevation profile.
+    pub surface_distance: f64,
+    /// The area enclosed by the path in square meters, which is closed into a polygon.
+    /// This is zero for less than three points.
+    pub area: f64,
+    /// The heights sampled evenly along the path.
+    pub profile: Vec<ProfileSample>,
+}
+
+/// Returns the great circle distance between two geographic coordinates (latitude and
+/// longitude in degrees) in meters.
+fn haversine_distance(start: DVec2, end: DVec2) -> f64 {
+    let (start, end) = (start * 1f64.to_radians(), end * 1f64.to_radians());
+    let delta = end - start;
+
+    let a =
+        (delta.x / 2.0).sin().powi(2) + start.x.cos() * end.x.cos() * (delta.y / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Returns the area of the polygon of geographic coordinates (latitude and longitude in degrees)
+/// on the sphere in square meters.
+fn spherical_area(polygon: &[DVec2]) -> f64 {
+    let area: f64 = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(start, end)| {
+            let (start, end) = (*start * 1f64.to_radians(), *end * 1f64.to_radians());
+            (end.y - start.y) * (2.0 + start.x.sin() + end.x.sin())
+        })
+        .sum();
+
+    (area * EARTH_RADIUS * EARTH_RADIUS / 2.0).abs()
+}
+
+/// Returns the area of the polygon in the plane.
+fn planar_area(polygon: &[DVec2]) -> f64 {
+    let area: f64 = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(start, end)| start.perp_dot(*end))
+        .sum();
+
+    (area / 2.0).abs()
+}
+
+impl Measurement {
+    /// Measures the path on the terrain.
+    pub fn new(
+        terrain: Entity,
+        points: &[Vec3],
+        config: &TerrainConfig,
+        sample_height: impl Fn(Vec2) -> Option<f32>,
+        profile_samples: u32,
+    ) -> Self {
+        let georeference = config.georeference;
+        let unit_size = georeference.map_or(1.0, |georeference| georeference.unit_size);
+        let geographic =
+            |point: Vec3| georeference.and_then(|georeference| georeference.world_to_geo(point));
+
+        let horizontal_distance =
+            |start: Vec3, end: Vec3| match (geographic(start), geographic(end)) {
+                (Some(start), Some(end)) => haversine_distance(start, end),
+                _ => start.xz().distance(end.xz()) as f64 * unit_size,
+            };
+
+        let segment_lengths: Vec<f64> = points
+            .windows(2)
+            .map(|segment| horizontal_distance(segment[0], segment[1]))
+            .collect();
+        let distance: f64 = segment_lengths.iter().sum();
+
+        let area = if points.len() < 3 {
+            0.0
+        } else {
+            match points.iter().map(|&point| geographic(point)).collect() {
+                Some(polygon) => spherical_area(&polygon),
+                None => {
+                    let polygon: Vec<DVec2> =
+                        points.iter().map(|point| point.xz().as_dvec2()).collect();
+                    planar_area(&polygon) * unit_size * unit_size
+                }
+            }
+        };
+
+        // distribute the samples along the path by the length of the segments
+        let mut profile = Vec::new();
+        let mut start_distance = 0.0;
+
+        for (segment, &length) in points.windows(2).zip(&segment_lengths) {
+            let count = if distance > 0.0 {
+                ((length / distance * profile_samples as f64).round() as u32).max(1)
+            } else {
+                1
+            };
+
+            for index in 0..count {
+                let t = index as f32 / count as f32;
+                let position = segment[0].xz().lerp(segment[1].xz(), t);
+
+                profile.push(ProfileSample {
+                    distance: start_distance + length * t as f64,
+                    position,
+                    height: sample_height(position),
+                });
+            }
+
+            start_distance += length;
+        }
+
+        if let Some(&last) = points.last() {
+            profile.push(ProfileSample {
+                distance,
+                position: last.xz(),
+                height: sample_height(last.xz()),
+            });
+        }
+
+        let surface_distance = profile
+            .windows(2)
+            .map(|samples| {
+                let horizontal = samples[1].distance - samples[0].distance;
+                let vertical = match (samples[0].height, samples[1].height) {
+                    (Some(start), Some(end)) => (end - start) as f64 * unit_size,
+                    _ => 0.0,
+                };
+
+                horizontal.hypot(vertical)
+            })
+            .sum();
+
+        Self {
+            terrain,
+            points: points.to_vec(),
+            distance,
+            surface_distance,
+            area,
+            profile,
+        }
+    }
+}
+
+/// Returns the first intersection of the ray with the loaded terrain within the maximum distance.
+///
+/// The ray is marched in even steps, so features smaller than a step may be missed.
+pub fn raycast_terrain(
+    sample_height: impl Fn(Vec2) -> Option<f32>,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+) -> Option<Vec3> {
+    let below = |t: f32| {
+        let position = origin + direction * t;
+        sample_height(position.xz()).map_or(false, |height| position.y <= height)
+    };
+
+    let step = max_distance / MARCH_STEPS as f32;
+    let hit = (1..=MARCH_STEPS).find(|&index| below(index as f32 * step))?;
+
+    let (mut near, mut far) = ((hit - 1) as f32 * step, hit as f32 * step);
+
+    for _ in 0..REFINE_STEPS {
+        let middle = (near + far) / 2.0;
+
+        if below(middle) {
+            far = middle;
+        } else {
+            near = middle;
+        }
+    }
+
+    Some(origin + direction * far)
+}
+
+/// Returns the origin and the direction of the ray through the cursor position (in pixels,
+/// from the bottom left corner of the window).
+fn cursor_ray(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    cursor_position: Vec2,
+    window_size: Vec2,
+) -> (Vec3, Vec3) {
+    let ndc = cursor_position / window_size * 2.0 - 1.0;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+
+    // the near plane has a depth of one due to the reversed z
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
+
+    (near, (far - near).normalize())
+}
+
+/// Adds the point under the cursor to the measurement or clears it.
+fn pick_measurement_points(
+    mouse: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    images: Res<Assets<Image>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    mut measurement: ResMut<TerrainMeasurement>,
+    view_query: Query<(Entity, &Camera, &GlobalTransform), With<TerrainView>>,
+    terrain_query: Query<(Entity, &NodeAtlas, &TerrainConfig), With<Terrain>>,
+) {
+    if !measurement.enabled {
+        return;
+    }
+
+    if mouse.just_pressed(measurement.clear_button) {
+        measurement.clear();
+        return;
+    }
+
+    if !mouse.just_pressed(measurement.add_button) {
+        return;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let cursor_position = match window.cursor_position() {
+        Some(cursor_position) => cursor_position,
+        None => return,
+    };
+
+    let window_size = Vec2::new(window.width(), window.height());
+
+    let (images, quadtrees, terrain_query) = (&*images, &*quadtrees, &terrain_query);
+
+    // pick the closest hit of all terrains
+    let hit = view_query
+        .iter()
+        .filter(|(_, camera, _)| camera.is_active)
+        .flat_map(|(view, camera, camera_transform)| {
+            let (origin, direction) =
+                cursor_ray(camera, camera_transform, cursor_position, window_size);
+
+            terrain_query
+                .iter()
+                .filter_map(move |(terrain, node_atlas, config)| {
+                    let quadtree = quadtrees.get(&(terrain, view))?;
+                    let sample_height =
+                        |position| quadtree.sample_height(node_atlas, images, position);
+
+                    let point = raycast_terrain(
+                        sample_height,
+                        origin,
+                        direction,
+                        2.0 * config.terrain_size as f32,
+                    )?;
+
+                    Some((terrain, point, point.distance(origin)))
+                })
+                .collect::<Vec<_>>()
+        })
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+
+    if let Some((terrain, point, _)) = hit {
+        measurement.add_point(terrain, point);
+    }
+}
+
+/// Sends a [`Measurement`] event, whenever the points of the measurement change.
+fn send_measurement(
+    images: Res<Assets<Image>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    measurement: Res<TerrainMeasurement>,
+    mut measurements: EventWriter<Measurement>,
+    terrain_query: Query<(&NodeAtlas, &TerrainConfig), With<Terrain>>,
+) {
+    if !measurement.is_changed() {
+        return;
+    }
+
+    let terrain = match measurement.terrain {
+        Some(terrain) => terrain,
+        None => return,
+    };
+
+    let (node_atlas, config) = match terrain_query.get(terrain) {
+        Ok(terrain) => terrain,
+        Err(_) => return,
+    };
+
+    // the heights are sampled from any view of the terrain
+    let quadtree = match quadtrees
+        .0
+        .iter()
+        .find(|(&(quadtree_terrain, _), _)| quadtree_terrain == terrain)
+    {
+        Some((_, quadtree)) => quadtree,
+        None => return,
+    };
+
+    measurements.send(Measurement::new(
+        terrain,
+        &measurement.points,
+        config,
+        |position| quadtree.sample_height(node_atlas, &images, position),
+        measurement.profile_samples,
+    ));
+}
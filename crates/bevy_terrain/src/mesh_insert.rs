@@ -0,0 +1,252 @@
+//! Local replacement meshes for regions of the terrain, which can not be expressed by the
+//! heightfield (e.g. a hand-modeled quarry, stadium or voxel structure).
+//!
+//! A [`TerrainMeshInsert`] covers a rectangular footprint of the terrain. The footprint is
+//! carved into the hole mask of all leaf nodes it overlaps, as soon as they have been loaded,
+//! so that the heightfield is not rendered beneath the mesh at the highest level of detail.
+//! The mesh itself is only visible, while any of these leaf nodes is requested by a quadtree,
+//! thus it streams in and out with the same lod logic as the terrain.
+//!
+//! The carving only affects the GPU data of the terrain and requires a mask attachment.
+//! Once an insert is removed, the holes persist until the nodes are loaded again.
+
+use crate::{
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_data::{
+        gpu_node_atlas::GpuNodeAtlas,
+        node_atlas::{NodeAtlas, TerrainEvent},
+        AtlasAttachment, AtlasIndex, AttachmentIndex, NodeCoordinate, NodeId,
+    },
+};
+use bevy::{
+    prelude::*,
+    render::{render_asset::RenderAssets, render_resource::*, renderer::RenderQueue, MainWorld},
+};
+use std::{mem, num::NonZeroU32};
+
+/// Replaces the terrain inside of its footprint with the mesh of the entity.
+///
+/// Add this component to an entity with a mesh (e.g. a [`PbrBundle`]), which is positioned
+/// in the coordinate system of the terrain. The terrain has to have a mask attachment.
+#[derive(Component)]
+pub struct TerrainMeshInsert {
+    /// The terrain, which is replaced by the mesh.
+    pub terrain: Entity,
+    /// The minimum corner of the footprint in the horizontal (x, z) plane of the terrain.
+    pub min: Vec2,
+    /// The maximum corner of the footprint in the horizontal (x, z) plane of the terrain.
+    pub max: Vec2,
+    /// The leaf nodes, whose mask has to be carved this frame.
+    pending: Vec<NodeCarving>,
+}
+
+impl TerrainMeshInsert {
+    pub fn new(terrain: Entity, min: Vec2, max: Vec2) -> Self {
+        Self {
+            terrain,
+            min,
+            max,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the texels of the node data (including its border), which are covered by the
+    /// footprint, or `None` if the footprint does not overlap the node.
+    fn covered_texels(
+        &self,
+        config: &TerrainConfig,
+        attachment: &AtlasAttachment,
+        node_id: NodeId,
+    ) -> Option<(UVec2, UVec2)> {
+        let coordinate = NodeCoordinate::from(node_id);
+        let node_size = (config.leaf_node_size << coordinate.lod) as f32;
+        let pixel_size = node_size / attachment.center_size as f32;
+
+        let origin = Vec2::new(coordinate.x as f32, coordinate.y as f32) * node_size
+            - attachment.border_size as f32 * pixel_size;
+        let size = Vec2::splat(attachment.texture_size as f32);
+
+        let min = ((self.min - origin) / pixel_size)
+            .floor()
+            .clamp(Vec2::ZERO, size);
+        let max = ((self.max - origin) / pixel_size)
+            .ceil()
+            .clamp(Vec2::ZERO, size);
+
+        (min.cmplt(max).all()).then(|| (min.as_uvec2(), max.as_uvec2()))
+    }
+}
+
+/// The texels of a leaf node, which are carved out of the mask.
+#[derive(Clone, Copy)]
+pub(crate) struct NodeCarving {
+    atlas_index: AtlasIndex,
+    min: UVec2,
+    max: UVec2,
+}
+
+/// Carves the footprints of the inserts into the leaf nodes, which have finished loading
+/// (or all resident leaf nodes for new inserts), and shows the inserts, while their leaf nodes
+/// are requested.
+pub(crate) fn update_mesh_inserts(
+    mut terrain_events: EventReader<TerrainEvent>,
+    mut insert_query: Query<(&mut TerrainMeshInsert, &mut Visibility)>,
+    terrain_query: Query<(&TerrainConfig, &NodeAtlas), With<Terrain>>,
+) {
+    let loaded_nodes: Vec<(Entity, NodeId)> = terrain_events
+        .iter()
+        .filter_map(|event| match event {
+            &TerrainEvent::NodeLoaded { terrain, node_id } => Some((terrain, node_id)),
+            _ => None,
+        })
+        .collect();
+
+    for (mut insert, mut visibility) in insert_query.iter_mut() {
+        let (config, node_atlas) = skip_none!(terrain_query.get(insert.terrain).ok());
+
+        let mask_attachment = match config.mask_attachment {
+            Some(mask_attachment) => &node_atlas.attachments[mask_attachment],
+            None => {
+                warn!("Only terrains with a mask attachment can be replaced by mesh inserts.");
+                continue;
+            }
+        };
+
+        let is_added = insert.is_added();
+        let mut is_visible = false;
+        let mut pending = Vec::new();
+
+        for (&node_id, node) in &node_atlas.nodes {
+            if NodeCoordinate::from(node_id).lod != 0 || !node_atlas.is_loaded(node_id) {
+                continue;
+            }
+
+            let (min, max) = skip_none!(insert.covered_texels(config, mask_attachment, node_id));
+
+            is_visible |= node_atlas.is_requested(node_id);
+
+            if is_added || loaded_nodes.contains(&(insert.terrain, node_id)) {
+                pending.push(NodeCarving {
+                    atlas_index: node.atlas_index,
+                    min,
+                    max,
+                });
+            }
+        }
+
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+
+        if !pending.is_empty() {
+            insert.pending.extend(pending);
+        }
+    }
+}
+
+/// Stores the carvings of the masks of a terrain, which have to be applied this frame.
+pub struct GpuTerrainMeshInserts {
+    mask_attachment: AttachmentIndex,
+    pending: Vec<NodeCarving>,
+}
+
+/// Extracts the pending carvings of all inserts into the [`GpuTerrainMeshInserts`].
+pub(crate) fn extract_mesh_inserts(
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_inserts: ResMut<TerrainComponents<GpuTerrainMeshInserts>>,
+) {
+    let mut insert_query = main_world.query::<&mut TerrainMeshInsert>();
+
+    let pending: Vec<(Entity, Vec<NodeCarving>)> = insert_query
+        .iter_mut(&mut main_world)
+        .filter(|insert| !insert.pending.is_empty())
+        .map(|mut insert| (insert.terrain, mem::take(&mut insert.pending)))
+        .collect();
+
+    for (terrain, carvings) in pending {
+        let config = skip_none!(main_world.get::<TerrainConfig>(terrain));
+        let mask_attachment = skip_none!(config.mask_attachment);
+
+        gpu_inserts
+            .0
+            .entry(terrain)
+            .or_insert_with(|| GpuTerrainMeshInserts {
+                mask_attachment,
+                pending: Vec::new(),
+            })
+            .pending
+            .extend(carvings);
+    }
+}
+
+/// Returns the data of a texel, which marks a hole in a mask of the format.
+fn hole_texel(format: TextureFormat) -> Vec<u8> {
+    match format {
+        TextureFormat::R16Float => vec![0x00, 0x3c],
+        // a 32 bit float mask is split into the value and its remainder
+        TextureFormat::Rg16Float => vec![0x00, 0x3c, 0x00, 0x00],
+        // all channels of the normalized formats are set to one
+        format => vec![u8::MAX; format.describe().block_size as usize],
+    }
+}
+
+/// Writes the carvings into all mip levels of the mask attachment,
+/// after the nodes of this frame have been uploaded.
+pub(crate) fn queue_mesh_inserts(
+    queue: Res<RenderQueue>,
+    images: Res<RenderAssets<Image>>,
+    gpu_node_atlases: Res<TerrainComponents<GpuNodeAtlas>>,
+    mut gpu_inserts: ResMut<TerrainComponents<GpuTerrainMeshInserts>>,
+) {
+    for (terrain, gpu_insert) in &mut gpu_inserts.0 {
+        if gpu_insert.pending.is_empty() {
+            continue;
+        }
+
+        let gpu_node_atlas = skip_none!(gpu_node_atlases.get(terrain));
+        let (attachment, handle) =
+            skip_none!(gpu_node_atlas.attachments.get(gpu_insert.mask_attachment));
+
+        // keep the carvings pending, until the mask is available
+        let texture = &skip_none!(images.get(handle)).texture;
+        let texel = hole_texel(attachment.format);
+
+        for carving in gpu_insert.pending.drain(..) {
+            for mip_level in 0..attachment.mip_level_count {
+                let mip_size = UVec2::splat((attachment.texture_size >> mip_level).max(1));
+                let min = carving.min >> mip_level;
+                let max = ((carving.max + (1 << mip_level) - 1) >> mip_level).min(mip_size);
+                let size = max - min;
+
+                if size.cmpeq(UVec2::ZERO).any() {
+                    continue;
+                }
+
+                queue.write_texture(
+                    ImageCopyTexture {
+                        texture,
+                        mip_level,
+                        origin: Origin3d {
+                            x: min.x,
+                            y: min.y,
+                            z: carving.atlas_index as u32,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    &texel.repeat((size.x * size.y) as usize),
+                    ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: NonZeroU32::new(size.x * texel.len() as u32),
+                        rows_per_image: None,
+                    },
+                    Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+        }
+    }
+}
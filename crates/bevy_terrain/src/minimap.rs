@@ -0,0 +1,186 @@
+//! A top-down overview of the terrain, which is rendered into an image, e.g. to be displayed
+//! as a minimap by the UI.
+//!
+//! The minimap is an additional [`TerrainView`] with its own orthographic camera and a coarser
+//! [`TerrainViewConfig`]. The camera only renders the terrain, because it is placed on a separate
+//! render layer, which regular meshes are not part of.
+
+use crate::{
+    terrain::TerrainConfig, terrain_data::quadtree::Quadtree, terrain_view::TerrainView,
+    TerrainViewComponents, TerrainViewConfig,
+};
+use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
+    prelude::*,
+    render::{
+        camera::{RenderTarget, ScalingMode},
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        view::RenderLayers,
+    },
+};
+
+/// The render layer of the minimap cameras.
+pub const MINIMAP_LAYER: u8 = 31;
+
+/// The settings of a [`TerrainMinimap`].
+#[derive(Clone)]
+pub struct MinimapSettings {
+    /// The resolution of the minimap image in pixels.
+    pub resolution: UVec2,
+    /// The side length of the (vertically) displayed region in world units.
+    /// Defaults to the size of the terrain.
+    pub extent: Option<f32>,
+    /// The entity, whose horizontal position the minimap is centered on.
+    /// Defaults to the center of the terrain.
+    pub follow: Option<Entity>,
+    /// The order in which the minimap is rendered relative to the other cameras.
+    pub priority: isize,
+    /// The background color of the minimap image.
+    pub clear_color: Color,
+    /// The quality settings of the minimap terrain view.
+    pub view_config: TerrainViewConfig,
+}
+
+impl Default for MinimapSettings {
+    fn default() -> Self {
+        Self {
+            resolution: UVec2::splat(256),
+            extent: None,
+            follow: None,
+            priority: -1,
+            clear_color: Color::NONE,
+            view_config: TerrainViewConfig {
+                load_distance: 2.0,
+                node_count: 4,
+                grid_size: 4,
+                view_distance: 1.0,
+                ..default()
+            },
+        }
+    }
+}
+
+/// A minimap camera, which renders a terrain top down into the image.
+///
+/// Use [`spawn_terrain_minimap`] to create it.
+#[derive(Clone, Component)]
+pub struct TerrainMinimap {
+    /// The image the minimap is rendered into.
+    pub image: Handle<Image>,
+    /// The terrain displayed by the minimap.
+    pub terrain: Entity,
+    /// The entity, whose horizontal position the minimap is centered on, if any.
+    pub follow: Option<Entity>,
+}
+
+/// Spawns a minimap of the terrain and returns the minimap camera and the image it renders into.
+///
+/// Keep in mind, that the node atlas of the terrain has to be large enough to hold the nodes
+/// requested by all views, including the minimap.
+pub fn spawn_terrain_minimap(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    view_configs: &mut TerrainViewComponents<TerrainViewConfig>,
+    quadtrees: &mut TerrainViewComponents<Quadtree>,
+    terrain: Entity,
+    config: &TerrainConfig,
+    settings: MinimapSettings,
+) -> (Entity, Handle<Image>) {
+    let size = Extent3d {
+        width: settings.resolution.x,
+        height: settings.resolution.y,
+        ..default()
+    };
+
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        },
+        ..default()
+    };
+    image.resize(size);
+
+    let image = images.add(image);
+
+    let extent = settings.extent.unwrap_or(config.terrain_size as f32);
+    let center = Vec2::splat(config.terrain_size as f32 / 2.0);
+    // place the camera above the highest point of the terrain
+    let altitude = config.elevation_offset() + 2.0 * config.height + 1.0;
+
+    let view = commands
+        .spawn((
+            Camera3dBundle {
+                camera: Camera {
+                    target: RenderTarget::Image(image.clone()),
+                    priority: settings.priority,
+                    ..default()
+                },
+                camera_3d: Camera3d {
+                    clear_color: ClearColorConfig::Custom(settings.clear_color),
+                    ..default()
+                },
+                projection: OrthographicProjection {
+                    near: 0.0,
+                    far: 2.0 * altitude,
+                    scaling_mode: ScalingMode::FixedVertical(extent),
+                    ..default()
+                }
+                .into(),
+                transform: minimap_transform(center, altitude),
+                ..default()
+            },
+            RenderLayers::layer(MINIMAP_LAYER),
+            UiCameraConfig { show_ui: false },
+            TerrainView,
+            TerrainMinimap {
+                image: image.clone(),
+                terrain,
+                follow: settings.follow,
+            },
+        ))
+        .id();
+
+    quadtrees.insert(
+        (terrain, view),
+        Quadtree::from_configs(config, &settings.view_config),
+    );
+    view_configs.insert((terrain, view), settings.view_config);
+
+    (view, image)
+}
+
+/// Returns the transform of a camera looking straight down, with north (negative z) facing up.
+fn minimap_transform(center: Vec2, altitude: f32) -> Transform {
+    Transform::from_xyz(center.x, altitude, center.y)
+        .looking_at(Vec3::new(center.x, 0.0, center.y), Vec3::NEG_Z)
+}
+
+/// Centers the minimaps on the entities they follow.
+pub(crate) fn update_minimap(
+    mut minimap_query: Query<(&TerrainMinimap, &mut Transform)>,
+    follow_query: Query<&GlobalTransform>,
+) {
+    for (minimap, mut transform) in &mut minimap_query {
+        let follow = match minimap.follow {
+            Some(follow) => follow,
+            None => continue,
+        };
+
+        if let Ok(follow) = follow_query.get(follow) {
+            let position = follow.translation();
+            let center = Vec2::new(position.x, position.z);
+
+            *transform = minimap_transform(center, transform.translation.y);
+        }
+    }
+}
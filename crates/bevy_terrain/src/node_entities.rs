@@ -0,0 +1,137 @@
+//! Lightweight entities mirroring the leaf nodes of a terrain, for attaching game data
+//! (e.g. resources or territory) to regions of the terrain.
+//!
+//! Adding [`TerrainNodeEntities`] to a terrain opts into this mode. Then a [`TerrainNode`]
+//! marker entity is spawned for each leaf node, which is loaded and requested by any quadtree,
+//! and despawned again, once the node is released. Thus the entities follow the quadtrees of
+//! the views with the same lod logic as the terrain data.
+//! The rendering does not depend on these entities in any way.
+
+use crate::{
+    terrain::{Terrain, TerrainConfig},
+    terrain_data::{
+        node_atlas::NodeAtlas, quadtree::sample_image, AtlasIndex, NodeCoordinate, NodeId,
+    },
+};
+use bevy::{prelude::*, render::primitives::Aabb, utils::HashMap};
+
+/// Spawns a [`TerrainNode`] entity for each requested leaf node of the terrain.
+///
+/// Add this component to a terrain entity. Removing it leaves the spawned entities behind.
+#[derive(Default, Component)]
+pub struct TerrainNodeEntities {
+    entities: HashMap<NodeId, Entity>,
+}
+
+impl TerrainNodeEntities {
+    /// Returns the entity of the leaf node, if it is currently requested.
+    pub fn get(&self, node_id: NodeId) -> Option<Entity> {
+        self.entities.get(&node_id).copied()
+    }
+
+    /// Iterates over the requested leaf nodes and their entities.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, Entity)> + '_ {
+        self.entities
+            .iter()
+            .map(|(&node_id, &entity)| (node_id, entity))
+    }
+}
+
+/// A marker entity of a leaf node, which is currently requested by a quadtree.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct TerrainNode {
+    /// The terrain of the node.
+    pub terrain: Entity,
+    /// The id of the node.
+    pub node_id: NodeId,
+    /// The coordinate of the node in leaf node sizes.
+    pub coordinate: UVec2,
+    /// The bounding box of the node in world space, spanning the heights of its data.
+    pub aabb: Aabb,
+}
+
+/// Returns the bounding box of the leaf node, whose vertical extent is derived from its height
+/// data or covers the whole height range of the terrain, if the data is not available on the CPU.
+fn node_aabb(
+    config: &TerrainConfig,
+    node_atlas: &NodeAtlas,
+    images: &Assets<Image>,
+    atlas_index: AtlasIndex,
+    coordinate: UVec2,
+) -> Aabb {
+    let min = coordinate.as_vec2() * config.leaf_node_size as f32;
+    let max = min + config.leaf_node_size as f32;
+
+    let image = node_atlas.data[atlas_index as usize]
+        ._attachments
+        .get(&0)
+        .and_then(|handle| images.get(handle));
+
+    let (min_height, max_height) = match image {
+        Some(image) => {
+            let size = image.size();
+
+            (0..size.y as u32)
+                .flat_map(|y| (0..size.x as u32).map(move |x| Vec2::new(x as f32, y as f32)))
+                .map(|position| sample_image(image, (position + 0.5) / size))
+                .fold((f32::MAX, f32::MIN), |(min, max), height| {
+                    (min.min(height), max.max(height))
+                })
+        }
+        None => (0.0, 1.0),
+    };
+
+    let offset = config.elevation_offset();
+
+    Aabb::from_min_max(
+        Vec3::new(min.x, min_height * config.height + offset, min.y),
+        Vec3::new(max.x, max_height * config.height + offset, max.y),
+    )
+}
+
+/// Spawns the entities of newly requested leaf nodes and despawns those of released ones.
+pub(crate) fn update_node_entities(
+    mut commands: Commands,
+    images: Res<Assets<Image>>,
+    mut terrain_query: Query<
+        (Entity, &TerrainConfig, &NodeAtlas, &mut TerrainNodeEntities),
+        With<Terrain>,
+    >,
+) {
+    for (terrain, config, node_atlas, mut node_entities) in terrain_query.iter_mut() {
+        node_entities.entities.retain(|&node_id, &mut entity| {
+            let retain = node_atlas.is_requested(node_id);
+
+            if !retain {
+                commands.entity(entity).despawn();
+            }
+
+            retain
+        });
+
+        for (&node_id, node) in &node_atlas.nodes {
+            let coordinate = NodeCoordinate::from(node_id);
+
+            if coordinate.lod != 0
+                || !node_atlas.is_requested(node_id)
+                || node_entities.entities.contains_key(&node_id)
+            {
+                continue;
+            }
+
+            let coordinate = UVec2::new(coordinate.x, coordinate.y);
+            let aabb = node_aabb(config, node_atlas, &images, node.atlas_index, coordinate);
+
+            let entity = commands
+                .spawn(TerrainNode {
+                    terrain,
+                    node_id,
+                    coordinate,
+                    aabb,
+                })
+                .id();
+
+            node_entities.entities.insert(node_id, entity);
+        }
+    }
+}
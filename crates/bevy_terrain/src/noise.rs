@@ -0,0 +1,157 @@
+//! Simple layered gradient noise functions used to synthesize terrain data.
+
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+/// The type of fractal noise of a [`NoiseLayer`].
+#[derive(Clone, Copy, Debug)]
+pub enum NoiseType {
+    /// Fractal brownian motion, which sums multiple octaves of gradient noise.
+    Fbm,
+    /// Ridged multifractal noise, which creates sharp mountain ridges.
+    Ridged,
+    /// Fractal brownian motion, whose input is distorted by another fbm.
+    DomainWarped,
+}
+
+/// A single layer of noise. Multiple layers are combined into the final height.
+#[derive(Clone, Debug)]
+pub struct NoiseLayer {
+    /// The type of fractal noise.
+    pub noise_type: NoiseType,
+    /// The seed of the layer.
+    pub seed: u32,
+    /// The frequency of the first octave in world units.
+    pub frequency: f32,
+    /// The amplitude of the layer relative to the other layers.
+    pub amplitude: f32,
+    /// The number of octaves.
+    pub octaves: u32,
+    /// The frequency multiplier between two successive octaves.
+    pub lacunarity: f32,
+    /// The amplitude multiplier between two successive octaves.
+    pub gain: f32,
+}
+
+impl Default for NoiseLayer {
+    fn default() -> Self {
+        Self {
+            noise_type: NoiseType::Fbm,
+            seed: 0,
+            frequency: 0.001,
+            amplitude: 1.0,
+            octaves: 8,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl NoiseLayer {
+    /// Evaluates the layer at the world position. The result is in the range of [-1, 1].
+    pub fn sample(&self, position: Vec2) -> f32 {
+        let position = position * self.frequency;
+
+        match self.noise_type {
+            NoiseType::Fbm => self.fbm(position, self.seed),
+            NoiseType::Ridged => self.ridged(position),
+            NoiseType::DomainWarped => {
+                let warp = Vec2::new(
+                    self.fbm(position + Vec2::new(17.3, 9.2), self.seed.wrapping_add(1)),
+                    self.fbm(position + Vec2::new(5.7, 31.4), self.seed.wrapping_add(2)),
+                );
+
+                self.fbm(position + 4.0 * warp, self.seed)
+            }
+        }
+    }
+
+    fn fbm(&self, position: Vec2, seed: u32) -> f32 {
+        let mut value = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+
+        for octave in 0..self.octaves {
+            value += amplitude * gradient_noise(position * frequency, seed.wrapping_add(octave));
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+
+        value / total
+    }
+
+    fn ridged(&self, position: Vec2) -> f32 {
+        let mut value = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        let mut weight = 1.0;
+        let mut total = 0.0;
+
+        for octave in 0..self.octaves {
+            let signal =
+                1.0 - gradient_noise(position * frequency, self.seed.wrapping_add(octave)).abs();
+            let signal = signal * signal * weight;
+
+            weight = (signal * 2.0).clamp(0.0, 1.0);
+            value += amplitude * signal;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+
+        2.0 * value / total - 1.0
+    }
+}
+
+/// Evaluates all layers at the world position and combines them into a height in the range of [0, 1].
+pub fn sample_layers(layers: &[NoiseLayer], position: Vec2) -> f32 {
+    let total: f32 = layers.iter().map(|layer| layer.amplitude).sum();
+
+    if total == 0.0 {
+        return 0.5;
+    }
+
+    let value: f32 = layers
+        .iter()
+        .map(|layer| layer.amplitude * layer.sample(position))
+        .sum();
+
+    (0.5 * value / total + 0.5).clamp(0.0, 1.0)
+}
+
+fn hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut hash =
+        seed ^ (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+fn gradient(x: i32, y: i32, seed: u32) -> Vec2 {
+    let angle = hash(x, y, seed) as f32 / u32::MAX as f32 * TAU;
+
+    Vec2::new(angle.cos(), angle.sin())
+}
+
+/// Two dimensional gradient noise in the range of [-1, 1].
+pub fn gradient_noise(position: Vec2, seed: u32) -> f32 {
+    let cell = position.floor();
+    let local = position - cell;
+    let (x, y) = (cell.x as i32, cell.y as i32);
+
+    // quintic interpolation curve
+    let fade = local * local * local * (local * (local * 6.0 - 15.0) + 10.0);
+
+    let a = gradient(x, y, seed).dot(local);
+    let b = gradient(x + 1, y, seed).dot(local - Vec2::new(1.0, 0.0));
+    let c = gradient(x, y + 1, seed).dot(local - Vec2::new(0.0, 1.0));
+    let d = gradient(x + 1, y + 1, seed).dot(local - Vec2::new(1.0, 1.0));
+
+    let value = a + (b - a) * fade.x + (c - a) * fade.y + (a - b - c + d) * fade.x * fade.y;
+
+    (value * std::f32::consts::SQRT_2).clamp(-1.0, 1.0)
+}
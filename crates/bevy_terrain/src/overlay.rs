@@ -0,0 +1,238 @@
+//! An on-screen overlay, which helps to orient oneself on the terrain.
+//!
+//! It consists of a north compass, a dynamically scaled distance bar and a readout
+//! of the coordinates of the viewer.
+//! If the terrain has a [`Georeference`](crate::georeference::Georeference) the coordinates
+//! are displayed in its coordinate reference system as well.
+
+use crate::{
+    terrain::{Terrain, TerrainConfig},
+    terrain_view::{TerrainView, TerrainViewComponents, TerrainViewConfig},
+};
+use bevy::prelude::*;
+
+/// The maximum width of the scale bar in pixels.
+const SCALE_BAR_WIDTH: f32 = 150.0;
+const COMPASS_SIZE: f32 = 48.0;
+const FONT_SIZE: f32 = 16.0;
+
+/// Adds the orientation overlay for the first terrain view.
+///
+/// Bevy does not ship with a default font, so the path to one inside the assets
+/// directory has to be provided.
+pub struct TerrainOverlayPlugin {
+    pub font: String,
+}
+
+impl Plugin for TerrainOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        let font = app.world.resource::<AssetServer>().load(&self.font);
+
+        app.insert_resource(OverlayFont(font))
+            .add_startup_system(spawn_overlay)
+            .add_system(update_compass)
+            .add_system(update_scale_bar)
+            .add_system(update_coordinates);
+    }
+}
+
+#[derive(Resource)]
+struct OverlayFont(Handle<Font>);
+
+#[derive(Component)]
+struct Compass;
+
+#[derive(Component)]
+struct ScaleBar;
+
+#[derive(Component)]
+struct ScaleLabel;
+
+#[derive(Component)]
+struct CoordinateLabel;
+
+fn spawn_overlay(mut commands: Commands, font: Res<OverlayFont>) {
+    let text_style = TextStyle {
+        font: font.0.clone(),
+        font_size: FONT_SIZE,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    left: Val::Px(10.0),
+                    bottom: Val::Px(10.0),
+                    ..default()
+                },
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexStart,
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.4).into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                CoordinateLabel,
+            ));
+
+            parent.spawn((TextBundle::from_section("", text_style.clone()), ScaleLabel));
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(SCALE_BAR_WIDTH), Val::Px(4.0)),
+                        margin: UiRect::all(Val::Px(4.0)),
+                        ..default()
+                    },
+                    background_color: Color::WHITE.into(),
+                    ..default()
+                },
+                ScaleBar,
+            ));
+
+            parent
+                .spawn((
+                    NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(COMPASS_SIZE), Val::Px(COMPASS_SIZE)),
+                            margin: UiRect::all(Val::Px(4.0)),
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::SpaceBetween,
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    Compass,
+                ))
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        "N",
+                        TextStyle {
+                            color: Color::RED,
+                            ..text_style
+                        },
+                    ));
+                    parent.spawn(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(4.0), Val::Px(COMPASS_SIZE / 2.0)),
+                            ..default()
+                        },
+                        background_color: Color::RED.into(),
+                        ..default()
+                    });
+                });
+        });
+}
+
+/// Rotates the compass, so that it points north (negative z) relative to the view direction.
+fn update_compass(
+    view_query: Query<&GlobalTransform, With<TerrainView>>,
+    mut compass_query: Query<&mut Transform, With<Compass>>,
+) {
+    let view_transform = match view_query.iter().next() {
+        Some(view_transform) => view_transform,
+        None => return,
+    };
+
+    let forward = view_transform.forward();
+    let heading = forward.x.atan2(-forward.z);
+
+    for mut transform in compass_query.iter_mut() {
+        transform.rotation = Quat::from_rotation_z(heading);
+    }
+}
+
+/// Resizes the scale bar to a round distance, based on the size of a pixel at the center of the screen.
+fn update_scale_bar(
+    windows: Res<Windows>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+    view_query: Query<(Entity, &GlobalTransform, &Projection), With<TerrainView>>,
+    terrain_query: Query<(Entity, &TerrainConfig), With<Terrain>>,
+    mut bar_query: Query<&mut Style, With<ScaleBar>>,
+    mut label_query: Query<&mut Text, With<ScaleLabel>>,
+) {
+    let (view, view_transform, projection) = match view_query.iter().next() {
+        Some(view) => view,
+        None => return,
+    };
+    let (terrain, config) = match terrain_query.iter().next() {
+        Some(terrain) => terrain,
+        None => return,
+    };
+    let (window, fov) = match (windows.get_primary(), projection) {
+        (Some(window), Projection::Perspective(projection)) => (window, projection.fov),
+        _ => return,
+    };
+
+    let height_under_viewer = view_configs
+        .get(&(terrain, view))
+        .map_or(0.0, |view_config| view_config.height_under_viewer);
+    let altitude = (view_transform.translation().y - height_under_viewer).max(1.0);
+    // distance along the view direction until the ground, assuming a flat terrain
+    let distance = altitude / (-view_transform.forward().y).max(0.2);
+    let unit_size = config
+        .georeference
+        .map_or(1.0, |georeference| georeference.unit_size as f32);
+    let meters_per_pixel = 2.0 * distance * (fov / 2.0).tan() / window.height() * unit_size;
+
+    let max_length = meters_per_pixel * SCALE_BAR_WIDTH;
+    let magnitude = 10.0_f32.powf(max_length.log10().floor());
+    let length = [5.0, 2.0, 1.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&length| length <= max_length)
+        .unwrap_or(magnitude);
+
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Px(length / meters_per_pixel);
+    }
+
+    for mut text in label_query.iter_mut() {
+        text.sections[0].value = if length >= 1000.0 {
+            format!("{} km", length / 1000.0)
+        } else {
+            format!("{length} m")
+        };
+    }
+}
+
+/// Displays the terrain coordinates of the viewer and its georeferenced coordinates if available.
+fn update_coordinates(
+    view_query: Query<&GlobalTransform, With<TerrainView>>,
+    terrain_query: Query<&TerrainConfig, With<Terrain>>,
+    mut label_query: Query<&mut Text, With<CoordinateLabel>>,
+) {
+    let (view_transform, config) = match (view_query.iter().next(), terrain_query.iter().next()) {
+        (Some(view_transform), Some(config)) => (view_transform, config),
+        _ => return,
+    };
+
+    let position = view_transform.translation();
+
+    let mut readout = format!(
+        "x: {:.1} z: {:.1} height: {:.1}",
+        position.x, position.z, position.y
+    );
+
+    if let Some(georeference) = config.georeference {
+        let projected = georeference.to_projected(position);
+
+        readout += &format!(
+            "\nEPSG:{} E: {:.1} N: {:.1}",
+            georeference.epsg, projected.x, projected.y
+        );
+
+        if let Some(geographic) = georeference.world_to_geo(position) {
+            readout += &format!("\nlat: {:.6} lon: {:.6}", geographic.x, geographic.y);
+        }
+    }
+
+    for mut text in label_query.iter_mut() {
+        text.sections[0].value = readout.clone();
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    error::TerrainResult,
+    noise::NoiseLayer,
+    preprocess::{
+        file_io::{format_node_path, load_image, save_image},
+        R16Image, R8Image, Rg16Image, Rgb32FImage, Rgb8Image, Rgba8Image, UVec2Utils,
+    },
+    skip_none,
+    terrain_data::{AttachmentConfig, AttachmentFormat},
+};
+use bevy::prelude::*;
+use image::{DynamicImage, Rgb};
+use itertools::iproduct;
+
+/// Configures the procedural refinement of the lods, which are finer than the source data.
+///
+/// The source tiles are split into nodes of lod `lod_count` and all finer lods are generated
+/// by upsampling their parent and adding noise-based micro-relief, which is scaled by the
+/// local slope of the parent.
+/// All attachments of a terrain have to use the same `lod_count`, but the noise can be disabled
+/// per attachment by setting the `strength` to zero (e.g. for albedo data).
+#[derive(Clone, Debug)]
+pub struct DetailAmplification {
+    /// The count of lods below the resolution of the source data, which are generated.
+    pub lod_count: u32,
+    /// The noise, which is added as micro-relief.
+    pub noise: NoiseLayer,
+    /// The strength of the micro-relief relative to the slope of the parent.
+    pub strength: f32,
+}
+
+pub(crate) fn channel_count(format: AttachmentFormat) -> usize {
+    match format {
+        AttachmentFormat::Rgb8 => 3,
+        AttachmentFormat::Rgba8 => 4,
+        AttachmentFormat::R16 => 1,
+        AttachmentFormat::Rg16 => 2,
+        AttachmentFormat::R16F | AttachmentFormat::R32F => 1,
+        AttachmentFormat::Rg32F => 2,
+        AttachmentFormat::R8Uint => 1,
+    }
+}
+
+/// Converts the image into normalized floating point channels.
+///
+/// The channels of floating point formats and the categories of categorical formats
+/// are passed through unchanged.
+pub(crate) fn to_channels(image: &DynamicImage, format: AttachmentFormat) -> Vec<f32> {
+    match format {
+        AttachmentFormat::Rgb8 => image
+            .as_rgb8()
+            .unwrap()
+            .as_raw()
+            .iter()
+            .map(|&v| v as f32 / u8::MAX as f32)
+            .collect(),
+        AttachmentFormat::Rgba8 => image
+            .as_rgba8()
+            .unwrap()
+            .as_raw()
+            .iter()
+            .map(|&v| v as f32 / u8::MAX as f32)
+            .collect(),
+        AttachmentFormat::R16 => image
+            .as_luma16()
+            .unwrap()
+            .as_raw()
+            .iter()
+            .map(|&v| v as f32 / u16::MAX as f32)
+            .collect(),
+        AttachmentFormat::Rg16 => image
+            .as_luma_alpha16()
+            .unwrap()
+            .as_raw()
+            .iter()
+            .map(|&v| v as f32 / u16::MAX as f32)
+            .collect(),
+        AttachmentFormat::R8Uint => image
+            .as_luma8()
+            .unwrap()
+            .as_raw()
+            .iter()
+            .map(|&v| v as f32)
+            .collect(),
+        format => image
+            .as_rgb32f()
+            .unwrap()
+            .pixels()
+            .flat_map(|pixel| pixel.0[..channel_count(format)].to_vec())
+            .collect(),
+    }
+}
+
+/// Converts the normalized floating point channels back into an image.
+pub(crate) fn from_channels(data: &[f32], size: UVec2, format: AttachmentFormat) -> DynamicImage {
+    let to_u8 = |&v: &f32| (v.clamp(0.0, 1.0) * u8::MAX as f32) as u8;
+    let to_u16 = |&v: &f32| (v.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+
+    match format {
+        AttachmentFormat::Rgb8 => DynamicImage::from(
+            Rgb8Image::from_raw(size.x, size.y, data.iter().map(to_u8).collect()).unwrap(),
+        ),
+        AttachmentFormat::Rgba8 => DynamicImage::from(
+            Rgba8Image::from_raw(size.x, size.y, data.iter().map(to_u8).collect()).unwrap(),
+        ),
+        AttachmentFormat::R16 => DynamicImage::from(
+            R16Image::from_raw(size.x, size.y, data.iter().map(to_u16).collect()).unwrap(),
+        ),
+        AttachmentFormat::Rg16 => DynamicImage::from(
+            Rg16Image::from_raw(size.x, size.y, data.iter().map(to_u16).collect()).unwrap(),
+        ),
+        AttachmentFormat::R8Uint => DynamicImage::from(
+            R8Image::from_raw(
+                size.x,
+                size.y,
+                data.iter()
+                    .map(|&v| v.round().clamp(0.0, u8::MAX as f32) as u8)
+                    .collect(),
+            )
+            .unwrap(),
+        ),
+        format => {
+            let channels = channel_count(format);
+
+            DynamicImage::from(Rgb32FImage::from_fn(size.x, size.y, |x, y| {
+                let index = (y * size.x + x) as usize * channels;
+                let mut pixel = Rgb([0.0; 3]);
+                pixel.0[..channels].copy_from_slice(&data[index..index + channels]);
+                pixel
+            }))
+        }
+    }
+}
+
+/// Bilinearly samples the channel of the image at the pixel coordinate.
+fn sample(data: &[f32], size: u32, channels: usize, channel: usize, position: Vec2) -> f32 {
+    let position = position.clamp(Vec2::ZERO, Vec2::splat((size - 1) as f32));
+    let first = position.floor().as_uvec2();
+    let last = (first + 1).min(UVec2::splat(size - 1));
+    let ratio = position - first.as_vec2();
+
+    let value = |x: u32, y: u32| data[(y * size + x) as usize * channels + channel];
+
+    let top = value(first.x, first.y) * (1.0 - ratio.x) + value(last.x, first.y) * ratio.x;
+    let bottom = value(first.x, last.y) * (1.0 - ratio.x) + value(last.x, last.y) * ratio.x;
+
+    top * (1.0 - ratio.y) + bottom * ratio.y
+}
+
+/// Generates the child node by upsampling its parent and adding slope dependent noise.
+fn amplify_node(
+    parent: &[f32],
+    attachment: &AttachmentConfig,
+    detail: &DetailAmplification,
+    lod: u32,
+    coord: UVec2,
+) -> Vec<f32> {
+    let size = attachment.texture_size;
+    let channels = channel_count(attachment.format);
+    let parent_origin = (coord >> 1).as_vec2() * attachment.center_size as f32;
+    let border = attachment.border_size as f32;
+
+    let mut data = vec![0.0; (size * size) as usize * channels];
+
+    for (y, x) in iproduct!(0..size, 0..size) {
+        // the position of the pixel in pixels of this lod
+        let global = coord.as_vec2() * attachment.center_size as f32
+            + Vec2::new(x as f32, y as f32)
+            - border;
+        let parent_position = (global + 0.5) / 2.0 - 0.5 - parent_origin + border;
+        let noise = detail.noise.sample(global * (1 << lod) as f32);
+
+        // categories are neither interpolated nor perturbed by the micro-relief
+        if attachment.format.is_categorical() {
+            let parent_position = parent_position.round();
+
+            for channel in 0..channels {
+                data[(y * size + x) as usize * channels + channel] =
+                    sample(parent, size, channels, channel, parent_position);
+            }
+
+            continue;
+        }
+
+        for channel in 0..channels {
+            let value = sample(parent, size, channels, channel, parent_position);
+
+            let slope = Vec2::new(
+                sample(parent, size, channels, channel, parent_position + Vec2::X)
+                    - sample(parent, size, channels, channel, parent_position - Vec2::X),
+                sample(parent, size, channels, channel, parent_position + Vec2::Y)
+                    - sample(parent, size, channels, channel, parent_position - Vec2::Y),
+            )
+            .length()
+                / 2.0;
+
+            data[(y * size + x) as usize * channels + channel] =
+                value + detail.strength * slope * noise;
+        }
+    }
+
+    data
+}
+
+/// Generates all nodes of the lod, by refining the nodes of the next coarser lod.
+pub(crate) fn amplify_layer(
+    directory: &str,
+    attachment: &AttachmentConfig,
+    detail: &DetailAmplification,
+    lod: u32,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<()> {
+    for (x, y) in first.div_floor(2).product(last.div_ceil(2)) {
+        let parent_path = format_node_path(directory, lod + 1, x, y);
+        let parent_image = skip_none!(load_image(&parent_path, attachment.file_format)?);
+        let parent = to_channels(&parent_image, attachment.format);
+
+        for (cx, cy) in iproduct!(0..2, 0..2) {
+            let coord = UVec2::new((x << 1) + cx, (y << 1) + cy);
+
+            if coord.cmplt(first).any() || coord.cmpge(last).any() {
+                continue;
+            }
+
+            let data = amplify_node(&parent, attachment, detail, lod, coord);
+            let node_image = from_channels(
+                &data,
+                UVec2::splat(attachment.texture_size),
+                attachment.format,
+            );
+            let node_path = format_node_path(directory, lod, coord.x, coord.y);
+
+            save_image(&node_path, &node_image, attachment)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,27 +1,60 @@
 use crate::{
+    error::TerrainResult,
     preprocess::{
-        down_sample::{down_sample_layer, linear, minmax},
+        amplify::amplify_layer,
+        down_sample::{down_sample_layer, linear, majority, minmax, Filter},
         file_io::{
             format_directory, format_node_path, iterate_directory, load_image, reset_directory,
             save_image,
         },
         split::split_tiles,
         stitch::stitch_layer,
+        water::{flatten_water, flatten_water_layer},
         BaseConfig, TileConfig, UVec2Utils,
     },
+    skip_none,
     terrain_data::{AttachmentConfig, NodeCoordinate, NodeId},
     TerrainConfig,
 };
-use image::{DynamicImage, ImageBuffer, LumaA};
+use bevy::prelude::*;
+use image::{DynamicImage, ImageBuffer, LumaA, Rgb};
+
+/// Converts a height node into a minmax node of the same lod, whose minimum and maximum
+/// are both set to the height.
+pub(crate) fn height_node_to_minmax(height_image: &DynamicImage) -> DynamicImage {
+    if let Some(height_image) = height_image.as_rgb32f() {
+        return DynamicImage::from(ImageBuffer::from_fn(
+            height_image.width(),
+            height_image.height(),
+            |x, y| {
+                let value = height_image.get_pixel(x, y).0[0];
+
+                Rgb([value, value, 0.0])
+            },
+        ));
+    }
+
+    let height_image = height_image.as_luma16().unwrap();
+
+    DynamicImage::from(ImageBuffer::from_fn(
+        height_image.width(),
+        height_image.height(),
+        |x, y| {
+            let value = height_image.get_pixel(x, y).0[0];
+
+            LumaA([value, value])
+        },
+    ))
+}
 
 fn height_to_minmax(
     height_directory: &str,
     minmax_directory: &str,
     height_attachment: &AttachmentConfig,
     minmax_attachment: &AttachmentConfig,
-) {
-    for (height_name, height_path) in iterate_directory(height_directory) {
-        let coord = NodeCoordinate::from(height_name.parse::<NodeId>().unwrap());
+) -> TerrainResult<()> {
+    for (height_name, height_path) in iterate_directory(height_directory)? {
+        let coord = NodeCoordinate::from(skip_none!(height_name.parse::<NodeId>().ok()));
 
         if coord.lod != 0 {
             continue;
@@ -29,41 +62,133 @@ fn height_to_minmax(
 
         let minmax_path = format_node_path(minmax_directory, coord.lod, coord.x, coord.y);
 
-        let height_image = load_image(&height_path, height_attachment.file_format).unwrap();
-        let height_image = height_image.as_luma16().unwrap();
+        let height_image = skip_none!(load_image(&height_path, height_attachment.file_format)?);
+        let minmax_image = height_node_to_minmax(&height_image);
 
-        let minmax_image = DynamicImage::from(ImageBuffer::from_fn(
-            height_image.width(),
-            height_image.height(),
-            |x, y| {
-                let value = height_image.get_pixel(x, y).0[0];
+        save_image(&minmax_path, &minmax_image, minmax_attachment)?;
+    }
 
-                LumaA([value, value])
-            },
-        ));
+    Ok(())
+}
+
+/// Packs the floating point height nodes into `Rg16` nodes, whose first channel stores the
+/// quantized height and whose second channel stores the residual of the quantization.
+pub(crate) fn pack_height_residual(config: &TerrainConfig, base: &BaseConfig) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let stored_attachment = base.stored_height_attachment();
+
+    let height_directory = format_directory(&config.path, "height");
+
+    for (_, height_path) in iterate_directory(&height_directory)? {
+        let height_image = skip_none!(load_image(&height_path, height_attachment.file_format)?);
+        let height_image = skip_none!(height_image.as_rgb32f());
+
+        let packed_image =
+            ImageBuffer::from_fn(height_image.width(), height_image.height(), |x, y| {
+                let value = height_image.get_pixel(x, y).0[0].clamp(0.0, 1.0) * u16::MAX as f32;
+                let height = value.floor();
+                let residual = (value - height) * u16::MAX as f32;
+
+                LumaA([height as u16, residual.round() as u16])
+            });
 
-        save_image(&minmax_path, &minmax_image, minmax_attachment);
+        save_image(
+            &height_path,
+            &DynamicImage::from(packed_image),
+            &stored_attachment,
+        )?;
     }
+
+    Ok(())
+}
+
+/// The lod at which the source data is split into nodes.
+fn source_lod(tile: &TileConfig) -> u32 {
+    tile.detail.as_ref().map_or(0, |detail| detail.lod_count)
 }
 
-pub(crate) fn preprocess_base(config: &TerrainConfig, tile: &TileConfig, base: &BaseConfig) {
+/// Splits the source data into nodes and generates the finer lods procedurally if configured.
+/// Flattens the water bodies if configured.
+/// Returns the first and last node coordinate of lod zero.
+fn split_and_amplify(
+    config: &TerrainConfig,
+    directory: &str,
+    tile: &TileConfig,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<(UVec2, UVec2)> {
+    let source_lod = source_lod(tile);
+    let (first, last) = split_tiles(config, directory, tile, attachment, source_lod)?;
+
+    let water_levels = match &tile.water {
+        Some(water) => flatten_water(
+            config, directory, attachment, water, source_lod, first, last,
+        )?,
+        None => None,
+    };
+
+    if let Some(detail) = &tile.detail {
+        stitch_layer(directory, attachment, source_lod, first, last)?;
+
+        for lod in (0..source_lod).rev() {
+            let scale = 1 << (source_lod - lod);
+
+            amplify_layer(
+                directory,
+                attachment,
+                detail,
+                lod,
+                first * scale,
+                last * scale,
+            )?;
+
+            // the amplified micro-relief would make the water bumpy again
+            if let Some(water_levels) = &water_levels {
+                flatten_water_layer(
+                    directory,
+                    attachment,
+                    water_levels,
+                    lod,
+                    first * scale,
+                    last * scale,
+                )?;
+            }
+
+            stitch_layer(directory, attachment, lod, first * scale, last * scale)?;
+        }
+    }
+
+    let scale = 1 << source_lod;
+
+    Ok((first * scale, last * scale))
+}
+
+pub(crate) fn preprocess_base(
+    config: &TerrainConfig,
+    tile: &TileConfig,
+    base: &BaseConfig,
+) -> TerrainResult<()> {
     let height_attachment = base.height_attachment();
     let minmax_attachment = base.minmax_attachment();
 
     let height_directory = format_directory(&config.path, "height");
     let minmax_directory = format_directory(&config.path, "minmax");
 
-    reset_directory(&height_directory);
-    reset_directory(&minmax_directory);
+    reset_directory(&height_directory)?;
+    reset_directory(&minmax_directory)?;
 
-    let temp = split_tiles(&height_directory, tile, &height_attachment);
+    let temp = split_and_amplify(config, &height_directory, tile, &height_attachment)?;
 
     let (mut first, mut last) = temp;
+    let source_lod = source_lod(tile);
 
     for lod in 1..config.lod_count {
         first = first.div_floor(2);
         last = last.div_ceil(2);
 
+        if lod <= source_lod {
+            continue;
+        }
+
         down_sample_layer(
             linear,
             &height_directory,
@@ -71,8 +196,8 @@ pub(crate) fn preprocess_base(config: &TerrainConfig, tile: &TileConfig, base: &
             lod,
             first,
             last,
-        );
-        stitch_layer(&height_directory, &height_attachment, lod, first, last);
+        )?;
+        stitch_layer(&height_directory, &height_attachment, lod, first, last)?;
     }
 
     height_to_minmax(
@@ -80,7 +205,7 @@ pub(crate) fn preprocess_base(config: &TerrainConfig, tile: &TileConfig, base: &
         &minmax_directory,
         &height_attachment,
         &minmax_attachment,
-    );
+    )?;
 
     let (mut first, mut last) = temp;
 
@@ -95,27 +220,42 @@ pub(crate) fn preprocess_base(config: &TerrainConfig, tile: &TileConfig, base: &
             lod,
             first,
             last,
-        );
-        stitch_layer(&minmax_directory, &minmax_attachment, lod, first, last);
+        )?;
+        stitch_layer(&minmax_directory, &minmax_attachment, lod, first, last)?;
     }
+
+    Ok(())
 }
 
 pub(crate) fn preprocess_attachment(
     config: &TerrainConfig,
     tile: &TileConfig,
     attachment: &AttachmentConfig,
-) {
+) -> TerrainResult<()> {
     let directory = format_directory(&config.path, &attachment.name);
 
-    reset_directory(&directory);
+    reset_directory(&directory)?;
 
-    let (mut first, mut last) = split_tiles(&directory, tile, attachment);
+    let (mut first, mut last) = split_and_amplify(config, &directory, tile, attachment)?;
+    let source_lod = source_lod(tile);
+
+    let filter: Filter = if attachment.format.is_categorical() {
+        majority
+    } else {
+        linear
+    };
 
     for lod in 1..config.lod_count {
         first = first.div_floor(2);
         last = last.div_ceil(2);
 
-        down_sample_layer(linear, &directory, attachment, lod, first, last);
-        stitch_layer(&directory, attachment, lod, first, last);
+        if lod <= source_lod {
+            continue;
+        }
+
+        down_sample_layer(filter, &directory, attachment, lod, first, last)?;
+        stitch_layer(&directory, attachment, lod, first, last)?;
     }
+
+    Ok(())
 }
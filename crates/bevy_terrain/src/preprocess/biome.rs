@@ -0,0 +1,106 @@
+//! Classifies the preprocessed height data (and optionally a landcover attachment) into the
+//! biome attachment.
+
+use crate::{
+    biome::BiomeClassification,
+    error::TerrainResult,
+    preprocess::{
+        derivative::height_nodes,
+        file_io::{format_directory, format_node_path, load_image, reset_directory, save_image},
+        BaseConfig, R16Image,
+    },
+    terrain_data::AttachmentConfig,
+    TerrainConfig,
+};
+use bevy::prelude::*;
+use image::{DynamicImage, GenericImageView};
+use itertools::iproduct;
+
+/// Returns the class of the landcover raster at the pixel, which is stored in its first channel.
+fn landcover_class(image: &DynamicImage, x: u32, y: u32) -> u16 {
+    match image {
+        DynamicImage::ImageLuma16(image) => image.get_pixel(x, y).0[0],
+        image => image.get_pixel(x, y).0[0] as u16,
+    }
+}
+
+/// Generates the biome attachment for all lods of the terrain.
+///
+/// Like the derivatives, each lod is classified from the height (and landcover) of the same lod,
+/// so that the biomes match the geometry, which is rendered.
+pub(crate) fn preprocess_biomes(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    classification: &BiomeClassification,
+    landcover_attachment: Option<&AttachmentConfig>,
+) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let attachment = classification.attachment(base);
+    let directory = format_directory(&config.path, &attachment.name);
+    let offset = config.elevation_offset();
+
+    reset_directory(&directory)?;
+
+    if classification.landcover_attachment.is_some() && landcover_attachment.is_none() {
+        warn!("The landcover attachment of the biomes has not been preprocessed.");
+    }
+
+    for lod in 0..config.lod_count {
+        // the distance between two pixels in world units
+        let pixel_size =
+            config.leaf_node_size as f32 / height_attachment.center_size as f32 * (1 << lod) as f32;
+
+        for node in height_nodes(config, &height_attachment, lod)? {
+            let (coordinate, height_image) = node?;
+            let size = height_image.width();
+
+            let landcover_image = match landcover_attachment {
+                Some(landcover_attachment) => {
+                    let landcover_directory =
+                        format_directory(&config.path, &landcover_attachment.name);
+                    let path =
+                        format_node_path(&landcover_directory, lod, coordinate.x, coordinate.y);
+
+                    load_image(&path, landcover_attachment.file_format)?
+                }
+                None => None,
+            };
+
+            let value = |x: i32, y: i32| {
+                let x = x.clamp(0, size as i32 - 1) as u32;
+                let y = y.clamp(0, size as i32 - 1) as u32;
+
+                height_image.get_pixel(x, y).0[0] as f32 / u16::MAX as f32 * config.height
+            };
+
+            let data = iproduct!(0..size as i32, 0..size as i32)
+                .map(|(y, x)| {
+                    let gradient = Vec2::new(
+                        value(x + 1, y) - value(x - 1, y),
+                        value(x, y + 1) - value(x, y - 1),
+                    ) / (2.0 * pixel_size);
+
+                    let height = value(x, y) + offset;
+                    let slope = gradient.length().atan().to_degrees();
+
+                    // the landcover may have a different resolution than the height
+                    let landcover = landcover_image.as_ref().map(|image| {
+                        let lx = (x as u32 * image.width() / size).min(image.width() - 1);
+                        let ly = (y as u32 * image.height() / size).min(image.height() - 1);
+
+                        landcover_class(image, lx, ly)
+                    });
+
+                    classification.classify(height, slope, landcover)
+                })
+                .collect();
+
+            let node_image = R16Image::from_raw(size, size, data).unwrap().into();
+            let node_path = format_node_path(&directory, lod, coordinate.x, coordinate.y);
+
+            save_image(&node_path, &node_image, &attachment)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,28 +1,157 @@
 use crate::{
-    formats::tc::TC,
+    error::{TerrainError, TerrainResult},
+    formats::tc::{TC, TC_VERSION},
     preprocess::file_io::{format_directory, iterate_directory},
-    terrain_data::NodeId,
+    terrain_data::{calc_node_id, AttachmentConfig, NodeId},
     TerrainConfig,
 };
+use bevy::log::info;
+use std::{collections::HashSet, fs, path::Path};
 
-/// Saves. s the node configuration of the terrain, which stores the [`NodeId`]s of all the nodes
-/// of the terrain.
-pub fn save_config(config: &TerrainConfig) {
-    let mut tc = TC { nodes: vec![] };
-    let attachment_directory = format_directory(&config.path, &config.attachments[0].name);
+/// Saves the manifest of the terrain, which stores the format version, the preprocessed
+/// attachments and the [`NodeId`]s of all the nodes of the terrain.
+///
+/// Only nodes, for which all preprocessed attachments exist, are listed as present.
+///
+/// Attachments preprocessed previously, which are not part of the preprocessed attachments,
+/// are kept in the manifest.
+pub fn save_config(config: &TerrainConfig, attachments: &[AttachmentConfig]) -> TerrainResult<()> {
+    let path = format_directory(&config.path, "../config.tc");
 
-    for (name, _) in iterate_directory(&attachment_directory) {
-        let node_id = name.parse::<NodeId>().unwrap();
-        tc.nodes.push(node_id);
+    let mut tc = TC {
+        version: TC_VERSION,
+        lod_count: config.lod_count,
+        attachments: attachments.to_vec(),
+        nodes: vec![],
+    };
+
+    if let Ok(previous) = TC::load_file(&path) {
+        if previous.version == TC_VERSION && previous.lod_count == config.lod_count {
+            for attachment in previous.attachments {
+                if !tc.attachments.iter().any(|a| a.name == attachment.name) {
+                    tc.attachments.push(attachment);
+                }
+            }
+        }
+    }
+
+    let mut names: Vec<&str> = tc.attachments.iter().map(|a| a.name.as_str()).collect();
+
+    if names.is_empty() {
+        let attachment = config.attachments.first().ok_or_else(|| {
+            TerrainError::InvalidConfig("The terrain does not have any attachments.".to_string())
+        })?;
+        names.push(&attachment.name);
+    }
+
+    // a node is only present, if all of its preprocessed attachments exist,
+    // missing nodes (e.g. no-data areas of sparse datasets) fall back to their parents
+    let mut nodes: Option<HashSet<NodeId>> = None;
+
+    for name in names {
+        let attachment_directory = format_directory(&config.path, name);
+
+        if !Path::new(&attachment_directory).is_dir() {
+            continue;
+        }
+
+        let attachment_nodes: HashSet<NodeId> = iterate_directory(&attachment_directory)?
+            .filter_map(|(name, _)| name.parse::<NodeId>().ok())
+            .collect();
+
+        nodes = Some(match nodes {
+            Some(nodes) => nodes.intersection(&attachment_nodes).copied().collect(),
+            None => attachment_nodes,
+        });
     }
 
-    tc.save_file(format_directory(&config.path, "../config.tc"))
-        .unwrap();
+    tc.nodes = nodes.unwrap_or_default().into_iter().collect();
+    tc.nodes.sort_unstable();
+
+    tc.save_file(&path)
+        .map_err(|error| TerrainError::malformed(path, error))
 }
 
-/// Loads the node configuration of the terrain, which stores the [`NodeId`]s of all the nodes
-/// of the terrain.
-pub fn load_node_config(config: &mut TerrainConfig) {
-    let tc = TC::load_file(format_directory(&config.path, "../config.tc")).unwrap();
+/// Loads the manifest of the terrain, which stores the [`NodeId`]s of all the nodes
+/// of the terrain, and validates it against the config.
+///
+/// Terrains preprocessed with the legacy node layout (version 1) are migrated on disk.
+pub fn load_node_config(config: &mut TerrainConfig) -> TerrainResult<()> {
+    let path = format_directory(&config.path, "../config.tc");
+    let mut tc = TC::load_file(&path).map_err(|error| TerrainError::malformed(&path, error))?;
+
+    if tc.version == 1 {
+        info!(
+            "Migrating the terrain {} to the node layout version {TC_VERSION}.",
+            config.path
+        );
+
+        tc = migrate_v1(config, tc)?;
+        tc.save_file(&path)
+            .map_err(|error| TerrainError::malformed(&path, error))?;
+    }
+
+    tc.validate(config)?;
+
     config.nodes = tc.nodes.into_iter().collect();
+
+    Ok(())
+}
+
+/// Converts a node id of the legacy node layout (6 bit lod, 13 bit x and y) to a [`NodeId`].
+fn convert_v1_node_id(id: NodeId) -> NodeId {
+    let lod = (id >> 26) & 0x3F;
+    let x = (id >> 13) & 0x1FFF;
+    let y = id & 0x1FFF;
+
+    calc_node_id(lod as u32, x as u32, y as u32)
+}
+
+/// Renames the node files of all attachments to the current node layout.
+///
+/// The legacy manifest does not store the layout of the attachments, so the migrated one
+/// does neither and only the lod count is validated.
+fn migrate_v1(config: &TerrainConfig, tc: TC) -> TerrainResult<TC> {
+    let data_directory = format_directory(&config.path, "");
+    let io = |path: &Path| TerrainError::io(path.to_string_lossy());
+
+    for entry in fs::read_dir(&data_directory).map_err(TerrainError::io(&data_directory))? {
+        let directory = entry.map_err(TerrainError::io(&data_directory))?.path();
+
+        if !directory.is_dir() {
+            continue;
+        }
+
+        // move the files into a new directory, so that new and old ids can not collide
+        let legacy_directory = directory.with_extension("v1");
+        fs::rename(&directory, &legacy_directory).map_err(io(&directory))?;
+        fs::create_dir(&directory).map_err(io(&directory))?;
+
+        for file in fs::read_dir(&legacy_directory).map_err(io(&legacy_directory))? {
+            let file = file.map_err(io(&legacy_directory))?.path();
+            let node_id = file
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<NodeId>().ok());
+
+            let name = match node_id {
+                Some(node_id) => match file.extension().and_then(|extension| extension.to_str()) {
+                    Some(extension) => format!("{}.{extension}", convert_v1_node_id(node_id)),
+                    None => convert_v1_node_id(node_id).to_string(),
+                },
+                None => file.file_name().unwrap().to_string_lossy().into_owned(),
+            };
+
+            fs::rename(&file, directory.join(name)).map_err(io(&file))?;
+        }
+
+        fs::remove_dir(&legacy_directory).map_err(io(&legacy_directory))?;
+    }
+
+    Ok(TC {
+        version: TC_VERSION,
+        lod_count: config.lod_count,
+        attachments: vec![],
+        nodes: tc.nodes.into_iter().map(convert_v1_node_id).collect(),
+    })
 }
@@ -0,0 +1,75 @@
+//! Compares the preprocessed height data against a reference height attachment, e.g. to compute
+//! the earthworks of an edit (current heights minus original heights) or the height of buildings
+//! and vegetation (DSM minus DTM).
+
+use crate::{preprocess::export::NodeSampler, terrain_data::AttachmentConfig, TerrainConfig};
+use bevy::prelude::*;
+use itertools::iproduct;
+
+/// The volumes of material, which have been removed or added compared to the reference.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CutFill {
+    /// The volume (in cubic world units) where the height lies below the reference.
+    pub cut: f64,
+    /// The volume (in cubic world units) where the height lies above the reference.
+    pub fill: f64,
+    /// The area (in square world units), where both the height and the reference exist.
+    pub area: f64,
+}
+
+impl CutFill {
+    /// Returns the net volume, which is positive if more material has been added than removed.
+    pub fn net(&self) -> f64 {
+        self.fill - self.cut
+    }
+}
+
+/// Computes the total cut and fill volume of the region between the min and max
+/// (exclusive) position in the horizontal (x, z) plane.
+///
+/// Both attachments have to store normalized `R16` heights of the same resolution and
+/// be preprocessed already.
+/// The heights are sampled at the lod, which trades precision for speed (one sample per
+/// 2^lod world units). Pixels, whose nodes do not exist in either attachment, are skipped.
+pub fn cut_fill_volume(
+    config: &TerrainConfig,
+    height_attachment: &AttachmentConfig,
+    reference_attachment: &AttachmentConfig,
+    min: UVec2,
+    max: UVec2,
+    lod: u32,
+) -> CutFill {
+    let mut heights = NodeSampler::new(config, height_attachment, lod);
+    let mut references = NodeSampler::new(config, reference_attachment, lod);
+
+    // the size of a pixel of the lod in world units
+    let pixel_size =
+        config.leaf_node_size as f32 / height_attachment.center_size as f32 * (1 << lod) as f32;
+    let pixel_area = (pixel_size * pixel_size) as f64;
+
+    let first = (min.as_vec2() / pixel_size).floor().as_uvec2();
+    let last = (max.as_vec2() / pixel_size).ceil().as_uvec2();
+
+    let mut cut_fill = CutFill::default();
+
+    for (y, x) in iproduct!(first.y..last.y, first.x..last.x) {
+        let pixel = UVec2::new(x, y);
+
+        let (height, reference) = match (heights.height(pixel), references.height(pixel)) {
+            (Some(height), Some(reference)) => (height, reference),
+            _ => continue,
+        };
+
+        let volume = (height - reference) as f64 * config.height as f64 * pixel_area;
+
+        if volume < 0.0 {
+            cut_fill.cut -= volume;
+        } else {
+            cut_fill.fill += volume;
+        }
+
+        cut_fill.area += pixel_area;
+    }
+
+    cut_fill
+}
@@ -0,0 +1,362 @@
+//! Derives rasters like hillshade, slope, aspect, ambient occlusion and horizon maps from the
+//! preprocessed height data.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        file_io::{
+            format_directory, format_node_path, iterate_directory, load_image, reset_directory,
+            save_image,
+        },
+        BaseConfig, R16Image, Rgba8Image,
+    },
+    terrain_data::{AttachmentConfig, AttachmentFormat, NodeCoordinate, NodeId},
+    TerrainConfig,
+};
+use bevy::prelude::*;
+use image::DynamicImage;
+use itertools::iproduct;
+use std::{f32::consts::TAU, fs};
+
+/// A raster, which is derived from the height of the terrain.
+#[derive(Clone, Copy, Debug)]
+pub enum Derivative {
+    /// The illumination of the terrain by a directional light (0 to 1).
+    Hillshade {
+        /// The direction of the light in degrees, measured clockwise from north.
+        azimuth: f32,
+        /// The angle of the light above the horizon in degrees.
+        altitude: f32,
+    },
+    /// The steepness of the terrain in degrees (0 to 90).
+    Slope,
+    /// The downhill direction of the terrain in degrees, measured clockwise from north (0 to 360).
+    Aspect,
+    /// The fraction of the sky, which is visible above the terrain (0 occluded to 1 open).
+    ///
+    /// The horizon is searched within the radius around each pixel in multiple directions,
+    /// which darkens valleys and creases. Since the search is limited to the node
+    /// (including its border), coarser lods capture the occlusion of larger features.
+    AmbientOcclusion {
+        /// The search radius in pixels.
+        radius: u32,
+        /// The number of directions, in which the horizon is searched.
+        direction_count: u32,
+    },
+    /// The sine of the horizon angle in the four directions east (+x), south (+z), west (-x)
+    /// and north (-z), stored in the red, green, blue and alpha channel (0 flat to 1 vertical).
+    ///
+    /// The horizon map lets the
+    /// [`StandardTerrainMaterial`](crate::render::standard_material::StandardTerrainMaterial)
+    /// cast soft self-shadows from the sun, which are much cheaper and smoother than shadow maps
+    /// for distant mountains. Like the ambient occlusion, the search is limited to the node.
+    Horizon {
+        /// The search radius in pixels.
+        radius: u32,
+    },
+}
+
+/// The directions of the [`Derivative::Horizon`] in the order of the channels.
+const HORIZON_DIRECTIONS: [Vec2; 4] = [
+    Vec2::new(1.0, 0.0),
+    Vec2::new(0.0, 1.0),
+    Vec2::new(-1.0, 0.0),
+    Vec2::new(0.0, -1.0),
+];
+
+impl Derivative {
+    /// The name of the derived attachment.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Derivative::Hillshade { .. } => "hillshade",
+            Derivative::Slope => "slope",
+            Derivative::Aspect => "aspect",
+            Derivative::AmbientOcclusion { .. } => "ambient_occlusion",
+            Derivative::Horizon { .. } => "horizon",
+        }
+    }
+
+    /// The number of values, which are derived per pixel.
+    fn channel_count(&self) -> usize {
+        match self {
+            Derivative::Horizon { .. } => HORIZON_DIRECTIONS.len(),
+            _ => 1,
+        }
+    }
+
+    /// Evaluates the derivative for the height gradient and maps it into the range of [0, 1].
+    fn evaluate(&self, gradient: Vec2) -> f32 {
+        match *self {
+            Derivative::Hillshade { azimuth, altitude } => {
+                let (azimuth, altitude) = (azimuth.to_radians(), altitude.to_radians());
+                let normal = Vec3::new(-gradient.x, 1.0, -gradient.y).normalize();
+                let light = Vec3::new(
+                    azimuth.sin() * altitude.cos(),
+                    altitude.sin(),
+                    -azimuth.cos() * altitude.cos(),
+                );
+
+                normal.dot(light).max(0.0)
+            }
+            Derivative::Slope => gradient.length().atan() / (TAU / 4.0),
+            Derivative::Aspect => {
+                if gradient == Vec2::ZERO {
+                    return 0.0;
+                }
+
+                // the x axis points east and the y axis (world z) points south
+                (-gradient.x).atan2(gradient.y).rem_euclid(TAU) / TAU
+            }
+            Derivative::AmbientOcclusion { .. } | Derivative::Horizon { .. } => {
+                unreachable!("The horizon does not depend on the gradient alone.")
+            }
+        }
+    }
+
+    /// Returns the attachment, which stores the derivative.
+    pub(crate) fn attachment(&self, base: &BaseConfig) -> AttachmentConfig {
+        let mut attachment = AttachmentConfig::new(
+            self.name().to_string(),
+            base.texture_size,
+            base.border_size,
+            base.mip_level_count,
+            match self.channel_count() {
+                1 => AttachmentFormat::R16,
+                _ => AttachmentFormat::Rgba8,
+            },
+        );
+
+        attachment.file_format = base.file_format;
+        attachment
+    }
+}
+
+/// Computes the derivative for every pixel of the height node.
+///
+/// The height gradient is approximated using central differences.
+/// Derivatives with multiple channels store the values of each pixel next to each other.
+fn derive_node(height_image: &R16Image, derivative: Derivative, lod: u32, height: f32) -> Vec<f32> {
+    let size = height_image.width();
+    // the distance between two pixels in world units
+    let pixel_size = (1 << lod) as f32;
+
+    let value = |x: i32, y: i32| {
+        let x = x.clamp(0, size as i32 - 1) as u32;
+        let y = y.clamp(0, size as i32 - 1) as u32;
+
+        height_image.get_pixel(x, y).0[0] as f32 / u16::MAX as f32 * height
+    };
+
+    if let Derivative::AmbientOcclusion {
+        radius,
+        direction_count,
+    } = derivative
+    {
+        return ambient_occlusion(size, pixel_size, radius, direction_count, value);
+    }
+
+    if let Derivative::Horizon { radius } = derivative {
+        return iproduct!(0..size as i32, 0..size as i32)
+            .flat_map(|(y, x)| {
+                HORIZON_DIRECTIONS
+                    .map(|direction| horizon(x, y, direction, pixel_size, radius, &value))
+            })
+            .collect();
+    }
+
+    iproduct!(0..size as i32, 0..size as i32)
+        .map(|(y, x)| {
+            let gradient = Vec2::new(
+                value(x + 1, y) - value(x - 1, y),
+                value(x, y + 1) - value(x, y - 1),
+            ) / (2.0 * pixel_size);
+
+            derivative.evaluate(gradient)
+        })
+        .collect()
+}
+
+/// Returns the sine of the horizon angle of the pixel in the direction.
+///
+/// The horizon angle is the steepest elevation angle towards the terrain within the radius.
+fn horizon(
+    x: i32,
+    y: i32,
+    direction: Vec2,
+    pixel_size: f32,
+    radius: u32,
+    value: impl Fn(i32, i32) -> f32,
+) -> f32 {
+    let center = value(x, y);
+
+    let max_slope = (1..=radius)
+        .map(|step| {
+            let offset = (direction * step as f32).round();
+            let height = value(x + offset.x as i32, y + offset.y as i32);
+
+            (height - center) / (offset.length().max(1.0) * pixel_size)
+        })
+        .fold(0.0, f32::max);
+
+    max_slope / (1.0 + max_slope * max_slope).sqrt()
+}
+
+/// Computes the horizon based ambient occlusion for every pixel of the height node.
+///
+/// For each direction the horizon angle is determined,
+/// and its sine is averaged over all directions.
+fn ambient_occlusion(
+    size: u32,
+    pixel_size: f32,
+    radius: u32,
+    direction_count: u32,
+    value: impl Fn(i32, i32) -> f32,
+) -> Vec<f32> {
+    let directions: Vec<Vec2> = (0..direction_count.max(1))
+        .map(|index| {
+            let angle = index as f32 / direction_count.max(1) as f32 * TAU;
+            Vec2::new(angle.cos(), angle.sin())
+        })
+        .collect();
+
+    iproduct!(0..size as i32, 0..size as i32)
+        .map(|(y, x)| {
+            let occlusion: f32 = directions
+                .iter()
+                .map(|&direction| horizon(x, y, direction, pixel_size, radius, &value))
+                .sum();
+
+            1.0 - occlusion / directions.len() as f32
+        })
+        .collect()
+}
+
+/// Converts the derived values into a 16 bit image, or an 8 bit RGBA image for four channels.
+fn to_image(data: &[f32], size: u32, channel_count: usize) -> DynamicImage {
+    if channel_count == 1 {
+        let data = data
+            .iter()
+            .map(|value| (value.clamp(0.0, 1.0) * u16::MAX as f32) as u16)
+            .collect();
+
+        return R16Image::from_raw(size, size, data).unwrap().into();
+    }
+
+    let data = data
+        .iter()
+        .map(|value| (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+        .collect();
+
+    Rgba8Image::from_raw(size, size, data).unwrap().into()
+}
+
+/// Iterates over all height nodes of the lod.
+pub(crate) fn height_nodes(
+    config: &TerrainConfig,
+    height_attachment: &AttachmentConfig,
+    lod: u32,
+) -> TerrainResult<impl Iterator<Item = TerrainResult<(NodeCoordinate, R16Image)>>> {
+    let height_directory = format_directory(&config.path, "height");
+    let file_format = height_attachment.file_format;
+
+    Ok(iterate_directory(&height_directory)?
+        .filter_map(|(name, _)| name.parse::<NodeId>().ok())
+        .map(NodeCoordinate::from)
+        .filter(move |coordinate| coordinate.lod == lod)
+        .filter_map(move |coordinate| {
+            let path = format_node_path(&height_directory, lod, coordinate.x, coordinate.y);
+
+            load_image(&path, file_format)
+                .transpose()
+                .map(|height_image| Ok((coordinate, height_image?.into_luma16())))
+        }))
+}
+
+/// Generates the derivative attachment for all lods of the terrain.
+///
+/// Each lod is derived from the height of the same lod, instead of down sampling the finest one,
+/// so that the derivative matches the geometry, which is rendered.
+pub(crate) fn preprocess_derivative(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    derivative: Derivative,
+) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let attachment = derivative.attachment(base);
+    let directory = format_directory(&config.path, &attachment.name);
+
+    reset_directory(&directory)?;
+
+    for lod in 0..config.lod_count {
+        for node in height_nodes(config, &height_attachment, lod)? {
+            let (coordinate, height_image) = node?;
+            let data = derive_node(&height_image, derivative, lod, config.height);
+            let node_image = to_image(&data, attachment.texture_size, derivative.channel_count());
+            let node_path = format_node_path(&directory, lod, coordinate.x, coordinate.y);
+
+            save_image(&node_path, &node_image, &attachment)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Exports the derivative of all nodes of the lod as PNG images into the directory.
+///
+/// Each image covers the center of one node (without its border).
+/// If the terrain has a [`Georeference`](crate::georeference::Georeference), a world file
+/// (`.pgw`) is written alongside each image, so that it can be placed by GIS applications.
+/// The terrain has to be preprocessed already.
+pub fn export_derivative_images(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    derivative: Derivative,
+    lod: u32,
+    directory: &str,
+) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let (border_size, center_size) = (height_attachment.border_size, height_attachment.center_size);
+    let pixel_size = (1 << lod) as f32;
+
+    reset_directory(directory)?;
+
+    for node in height_nodes(config, &height_attachment, lod)? {
+        let (coordinate, height_image) = node?;
+        let data = derive_node(&height_image, derivative, lod, config.height);
+        let image = to_image(
+            &data,
+            height_attachment.texture_size,
+            derivative.channel_count(),
+        )
+        .crop_imm(border_size, border_size, center_size, center_size);
+
+        let path = format!(
+            "{directory}/{}_{}_{}_{}",
+            derivative.name(),
+            lod,
+            coordinate.x,
+            coordinate.y
+        );
+
+        let image_path = format!("{path}.png");
+        image
+            .save(&image_path)
+            .map_err(|error| TerrainError::malformed(image_path, error))?;
+
+        if let Some(georeference) = config.georeference {
+            // the world position of the center of the upper left pixel
+            let origin = Vec2::new(coordinate.x as f32, coordinate.y as f32)
+                * (center_size as f32 * pixel_size)
+                + pixel_size / 2.0;
+
+            let world_file_path = format!("{path}.pgw");
+            fs::write(
+                &world_file_path,
+                georeference.world_file(origin, pixel_size),
+            )
+            .map_err(TerrainError::io(world_file_path))?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
 use crate::{
+    error::TerrainResult,
+    formats::tdf::most_frequent,
     preprocess::{
         file_io::{format_node_path, load_image, load_or_create_node, save_image},
         UVec2Utils,
@@ -54,7 +56,16 @@ impl AveragePixel for LumaA<u16> {
     }
 }
 
-type Filter = fn(&mut DynamicImage, &DynamicImage, &AttachmentConfig, UVec2);
+impl AveragePixel for Rgb<f32> {
+    fn average(a: Self, b: Self, c: Self, d: Self) -> Self {
+        let mut value = Rgb([0.0; 3]);
+        izip!(&mut value.0, &a.0, &b.0, &c.0, &d.0)
+            .for_each(|(out, &a, &b, &c, &d)| *out = (a + b + c + d) / 4.0);
+        value
+    }
+}
+
+pub(crate) type Filter = fn(&mut DynamicImage, &DynamicImage, &AttachmentConfig, UVec2);
 
 pub(crate) fn imageops_linear<I, J>(
     parent_image: &mut I,
@@ -134,6 +145,16 @@ pub(crate) fn linear(
                 attachment.border_size,
             );
         }
+        _ => {
+            imageops_linear(
+                parent_image.as_mut_rgb32f().unwrap(),
+                child_image.as_rgb32f().unwrap(),
+                child_size,
+                node_x,
+                node_y,
+                attachment.border_size,
+            );
+        }
     }
 }
 
@@ -143,6 +164,10 @@ pub(crate) fn minmax(
     attachment: &AttachmentConfig,
     offset: UVec2,
 ) {
+    if attachment.format.is_float() {
+        return minmax_float(parent_image, child_image, attachment, offset);
+    }
+
     let parent_image = parent_image.as_mut_luma_alpha16().unwrap();
     let child_image = child_image.as_luma_alpha16().unwrap();
 
@@ -171,6 +196,72 @@ pub(crate) fn minmax(
     }
 }
 
+/// Down samples categorical attachments (`R8Uint`), where each pixel takes the most frequent
+/// category of its four child pixels.
+pub(crate) fn majority(
+    parent_image: &mut DynamicImage,
+    child_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+    offset: UVec2,
+) {
+    let parent_image = parent_image.as_mut_luma8().unwrap();
+    let child_image = child_image.as_luma8().unwrap();
+
+    let child_size = attachment.center_size >> 1;
+
+    let node_x = offset.x * child_size + attachment.border_size;
+    let node_y = offset.y * child_size + attachment.border_size;
+
+    for (x, y) in iproduct!(0..child_size, 0..child_size) {
+        let values = [(0, 0), (0, 1), (1, 0), (1, 1)].map(|(cx, cy)| {
+            child_image
+                .get_pixel(
+                    (x << 1) + cx + attachment.border_size,
+                    (y << 1) + cy + attachment.border_size,
+                )
+                .0[0]
+        });
+
+        let value = Luma([most_frequent(values)]);
+        parent_image.put_pixel(node_x + x, node_y + y, value);
+    }
+}
+
+/// Down samples the minmax attachment of floating point heights (`Rg32F`).
+fn minmax_float(
+    parent_image: &mut DynamicImage,
+    child_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+    offset: UVec2,
+) {
+    let parent_image = parent_image.as_mut_rgb32f().unwrap();
+    let child_image = child_image.as_rgb32f().unwrap();
+
+    let child_size = attachment.center_size >> 1;
+
+    let node_x = offset.x * child_size + attachment.border_size;
+    let node_y = offset.y * child_size + attachment.border_size;
+
+    for (x, y) in iproduct!(0..child_size, 0..child_size) {
+        let mut min = f32::MAX;
+        let mut max = f32::MIN;
+
+        for (cx, cy) in iproduct!(0..2, 0..2) {
+            let value = child_image
+                .get_pixel(
+                    (x << 1) + cx + attachment.border_size,
+                    (y << 1) + cy + attachment.border_size,
+                )
+                .0;
+            min = min.min(value[0]);
+            max = max.max(value[1]);
+        }
+
+        let value = Rgb([min, max, 0.0]);
+        parent_image.put_pixel(node_x + x, node_y + y, value);
+    }
+}
+
 pub(crate) fn down_sample_layer(
     filter: Filter,
     directory: &str,
@@ -178,25 +269,33 @@ pub(crate) fn down_sample_layer(
     lod: u32,
     first: UVec2,
     last: UVec2,
-) {
-    first.product(last).into_iter().for_each(|(x, y)| {
-        let node_path = format_node_path(directory, lod, x, y);
-        let mut node_image = load_or_create_node(&node_path, attachment);
+) -> TerrainResult<()> {
+    for (x, y) in first.product(last) {
+        let mut children = Vec::new();
 
         for (cx, cy) in iproduct!(0..2, 0..2) {
             let child_path = format_node_path(directory, lod - 1, (x << 1) + cx, (y << 1) + cy);
-            let child_image = skip_none!(load_image(&child_path, attachment.file_format));
+            let child_image = skip_none!(load_image(&child_path, attachment.file_format)?);
             // Todo: if a child node is not available, we should fill the gap in the parent one
             // maybe this should not even be possible
 
-            filter(
-                &mut node_image,
-                &child_image,
-                attachment,
-                UVec2::new(cx, cy),
-            );
+            children.push((UVec2::new(cx, cy), child_image));
+        }
+
+        // empty branches of sparse terrains are not stored, so that they are never requested
+        if children.is_empty() {
+            continue;
         }
 
-        save_image(&node_path, &node_image, attachment);
-    });
+        let node_path = format_node_path(directory, lod, x, y);
+        let mut node_image = load_or_create_node(&node_path, attachment)?;
+
+        for (child, child_image) in children {
+            filter(&mut node_image, &child_image, attachment, child);
+        }
+
+        save_image(&node_path, &node_image, attachment)?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,500 @@
+//! Exports regions of the preprocessed terrain as static meshes, e.g. for inspecting them in
+//! Blender or importing them into other engines, or as single height rasters.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        file_io::{format_directory, format_node_path, load_image},
+        BaseConfig, R16Image,
+    },
+    terrain_data::AttachmentConfig,
+    TerrainConfig,
+};
+use bevy::{prelude::*, utils::HashMap};
+use bytemuck::cast_slice;
+use image::{DynamicImage, Luma, Rgba, RgbaImage};
+use itertools::iproduct;
+use serde_json::json;
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The file format of an exported mesh.
+#[derive(Clone, Copy, Debug)]
+pub enum MeshFormat {
+    /// A glTF 2.0 file (`.gltf`) with a separate binary buffer (`.bin`).
+    Gltf,
+    /// A Wavefront OBJ file (`.obj`) with a material library (`.mtl`).
+    Obj,
+}
+
+/// The region of the terrain, which is exported as a mesh.
+#[derive(Clone, Debug)]
+pub struct MeshExport {
+    /// The first position of the region in the horizontal (x, z) plane.
+    pub min: UVec2,
+    /// The last position (exclusive) of the region in the horizontal (x, z) plane.
+    pub max: UVec2,
+    /// The lod of the height data, which determines the vertex spacing (2^lod world units).
+    pub lod: u32,
+    /// The file format of the mesh.
+    pub format: MeshFormat,
+    /// The name of the attachment, which is baked into the albedo texture of the mesh, if any.
+    /// Only `Rgb8` and `Rgba8` attachments are supported.
+    pub albedo: Option<String>,
+}
+
+impl MeshExport {
+    pub fn new(min: UVec2, max: UVec2, lod: u32, format: MeshFormat) -> Self {
+        Self {
+            min,
+            max,
+            lod,
+            format,
+            albedo: None,
+        }
+    }
+
+    /// Bakes the attachment into the albedo texture of the mesh.
+    pub fn with_albedo(mut self, attachment: &str) -> Self {
+        self.albedo = Some(attachment.to_string());
+        self
+    }
+}
+
+/// Samples the pixels of the nodes of a single lod, which are loaded on demand.
+pub(crate) struct NodeSampler<'a> {
+    directory: String,
+    attachment: &'a AttachmentConfig,
+    lod: u32,
+    nodes: HashMap<UVec2, Option<DynamicImage>>,
+}
+
+impl<'a> NodeSampler<'a> {
+    pub(crate) fn new(config: &TerrainConfig, attachment: &'a AttachmentConfig, lod: u32) -> Self {
+        Self {
+            directory: format_directory(&config.path, &attachment.name),
+            attachment,
+            lod,
+            nodes: default(),
+        }
+    }
+
+    /// Returns the pixel of the lod, if its node exists.
+    fn pixel(&mut self, pixel: UVec2) -> Option<&DynamicImage> {
+        let NodeSampler {
+            directory,
+            attachment,
+            lod,
+            nodes,
+        } = self;
+
+        let node = pixel / attachment.center_size;
+
+        nodes
+            .entry(node)
+            .or_insert_with(|| {
+                let node_path = format_node_path(directory, *lod, node.x, node.y);
+                load_image(&node_path, attachment.file_format).unwrap_or_else(|error| {
+                    warn!("{error}");
+                    None
+                })
+            })
+            .as_ref()
+    }
+
+    /// Returns the coordinate of the pixel inside of its node.
+    fn local(&self, pixel: UVec2) -> (u32, u32) {
+        let local = pixel % self.attachment.center_size + self.attachment.border_size;
+
+        (local.x, local.y)
+    }
+
+    /// Returns the normalized height of the pixel.
+    pub(crate) fn height(&mut self, pixel: UVec2) -> Option<f32> {
+        let (x, y) = self.local(pixel);
+
+        match self.pixel(pixel)? {
+            DynamicImage::ImageLuma16(image) => {
+                Some(image.get_pixel(x, y).0[0] as f32 / u16::MAX as f32)
+            }
+            // the second channel stores the residual of the quantized height
+            DynamicImage::ImageLumaA16(image) => {
+                let [height, residual] = image.get_pixel(x, y).0;
+                Some((height as f32 + residual as f32 / u16::MAX as f32) / u16::MAX as f32)
+            }
+            DynamicImage::ImageRgb32F(image) => Some(image.get_pixel(x, y).0[0]),
+            _ => None,
+        }
+    }
+
+    /// Returns the color of the pixel.
+    fn color(&mut self, pixel: UVec2) -> Option<Rgba<u8>> {
+        let (x, y) = self.local(pixel);
+
+        match self.pixel(pixel)? {
+            DynamicImage::ImageRgb8(image) => {
+                let [r, g, b] = image.get_pixel(x, y).0;
+                Some(Rgba([r, g, b, 255]))
+            }
+            DynamicImage::ImageRgba8(image) => Some(*image.get_pixel(x, y)),
+            _ => None,
+        }
+    }
+}
+
+/// The vertices and triangles of the exported region.
+struct TerrainMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl TerrainMesh {
+    /// Triangulates the height data of the region, with one vertex per pixel of the lod.
+    fn new(config: &TerrainConfig, base: &BaseConfig, export: &MeshExport) -> Self {
+        let height_attachment = base.stored_height_attachment();
+        let mut heights = NodeSampler::new(config, &height_attachment, export.lod);
+
+        let pixel_size = (1 << export.lod) as f32;
+        let pixel_count = UVec2::splat((config.terrain_size >> export.lod).max(1));
+
+        let scale = 1 << export.lod;
+        let first = (export.min / scale).min(pixel_count - 1);
+        let last = ((export.max + scale - 1) / scale).min(pixel_count - 1);
+        let size = (last - first + 1).max(UVec2::splat(2));
+
+        let grid: Vec<f32> = iproduct!(0..size.y, 0..size.x)
+            .map(|(y, x)| {
+                let pixel = (first + UVec2::new(x, y)).min(pixel_count - 1);
+                heights.height(pixel).unwrap_or(0.0) * config.height
+            })
+            .collect();
+
+        let height = |x: i32, y: i32| {
+            let x = x.clamp(0, size.x as i32 - 1) as u32;
+            let y = y.clamp(0, size.y as i32 - 1) as u32;
+
+            grid[(y * size.x + x) as usize]
+        };
+
+        let mut mesh = Self {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        for (y, x) in iproduct!(0..size.y as i32, 0..size.x as i32) {
+            let position = (first.as_vec2() + Vec2::new(x as f32, y as f32)) * pixel_size;
+
+            // central differences, like the normals of the terrain shader
+            let normal = Vec3::new(
+                height(x - 1, y) - height(x + 1, y),
+                2.0 * pixel_size,
+                height(x, y - 1) - height(x, y + 1),
+            )
+            .normalize();
+
+            mesh.positions.push([position.x, height(x, y), position.y]);
+            mesh.normals.push(normal.to_array());
+            mesh.uvs.push([
+                x as f32 / (size.x - 1) as f32,
+                y as f32 / (size.y - 1) as f32,
+            ]);
+        }
+
+        for (y, x) in iproduct!(0..size.y - 1, 0..size.x - 1) {
+            let a = y * size.x + x;
+            let b = a + size.x;
+
+            mesh.indices.extend([a, b, a + 1, a + 1, b, b + 1]);
+        }
+
+        mesh
+    }
+
+    fn bounds(&self) -> (Vec3, Vec3) {
+        self.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), &position| {
+                let position = Vec3::from(position);
+                (min.min(position), max.max(position))
+            },
+        )
+    }
+}
+
+/// Bakes the albedo attachment of the region into a single texture.
+fn bake_albedo(
+    config: &TerrainConfig,
+    attachment: &AttachmentConfig,
+    export: &MeshExport,
+) -> RgbaImage {
+    let mut colors = NodeSampler::new(config, attachment, export.lod);
+
+    // the size of a pixel of the attachment in world units
+    let pixel_size =
+        config.leaf_node_size as f32 / attachment.center_size as f32 * (1 << export.lod) as f32;
+    let pixel_count = (config.terrain_size as f32 / pixel_size).ceil().max(1.0) as u32;
+
+    let first = (export.min.as_vec2() / pixel_size).floor().as_uvec2();
+    let last = (export.max.as_vec2() / pixel_size).ceil().as_uvec2();
+    let size = (last - first.min(last)).max(UVec2::ONE);
+
+    RgbaImage::from_fn(size.x, size.y, |x, y| {
+        let pixel = (first + UVec2::new(x, y)).min(UVec2::splat(pixel_count - 1));
+        colors.color(pixel).unwrap_or(Rgba([0, 0, 0, 255]))
+    })
+}
+
+/// Writes the file and converts the error.
+fn write_file(path: PathBuf, contents: impl AsRef<[u8]>) -> TerrainResult<()> {
+    fs::write(&path, contents).map_err(TerrainError::io(path.to_string_lossy()))
+}
+
+fn write_gltf(path: &Path, mesh: &TerrainMesh, texture: Option<&str>) -> TerrainResult<()> {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let mut buffer = Vec::new();
+    let mut views = Vec::new();
+
+    for (data, target) in [
+        (cast_slice::<_, u8>(&mesh.positions), 34962),
+        (cast_slice(&mesh.normals), 34962),
+        (cast_slice(&mesh.uvs), 34962),
+        (cast_slice(&mesh.indices), 34963),
+    ] {
+        views.push(json!({
+            "buffer": 0,
+            "byteOffset": buffer.len(),
+            "byteLength": data.len(),
+            "target": target,
+        }));
+        buffer.extend_from_slice(data);
+    }
+
+    let (min, max) = mesh.bounds();
+    let vertex_count = mesh.positions.len();
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "bevy_terrain" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": name }],
+        "meshes": [{
+            "primitives": [{
+                "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                "indices": 3,
+                "material": 0,
+            }],
+        }],
+        "materials": [{
+            "pbrMetallicRoughness": { "metallicFactor": 0.0, "roughnessFactor": 1.0 },
+        }],
+        "accessors": [
+            {
+                "bufferView": 0, "componentType": 5126, "count": vertex_count, "type": "VEC3",
+                "min": min.to_array(), "max": max.to_array(),
+            },
+            { "bufferView": 1, "componentType": 5126, "count": vertex_count, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5126, "count": vertex_count, "type": "VEC2" },
+            {
+                "bufferView": 3, "componentType": 5125, "count": mesh.indices.len(),
+                "type": "SCALAR",
+            },
+        ],
+        "bufferViews": views,
+        "buffers": [{ "uri": format!("{name}.bin"), "byteLength": buffer.len() }],
+    });
+
+    if let Some(texture) = texture {
+        gltf["images"] = json!([{ "uri": texture }]);
+        gltf["textures"] = json!([{ "source": 0 }]);
+        gltf["materials"][0]["pbrMetallicRoughness"]["baseColorTexture"] = json!({ "index": 0 });
+    }
+
+    write_file(path.with_extension("bin"), buffer)?;
+    let gltf_path = path.with_extension("gltf");
+    let gltf = serde_json::to_string_pretty(&gltf)
+        .map_err(|error| TerrainError::malformed(gltf_path.to_string_lossy(), error))?;
+
+    write_file(gltf_path, gltf)
+}
+
+fn write_obj(path: &Path, mesh: &TerrainMesh, texture: Option<&str>) -> TerrainResult<()> {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let mut material = String::from("newmtl terrain\nKd 1.0 1.0 1.0\nKs 0.0 0.0 0.0\n");
+
+    if let Some(texture) = texture {
+        material.push_str(&format!("map_Kd {texture}\n"));
+    }
+
+    write_file(path.with_extension("mtl"), material)?;
+
+    let mut obj = Vec::new();
+
+    writeln!(obj, "mtllib {name}.mtl\no {name}\nusemtl terrain").unwrap();
+
+    for [x, y, z] in &mesh.positions {
+        writeln!(obj, "v {x} {y} {z}").unwrap();
+    }
+    for [x, y, z] in &mesh.normals {
+        writeln!(obj, "vn {x} {y} {z}").unwrap();
+    }
+    // the texture coordinates of OBJ files start at the bottom of the image
+    for [u, v] in &mesh.uvs {
+        writeln!(obj, "vt {u} {}", 1.0 - v).unwrap();
+    }
+    for triangle in mesh.indices.chunks(3) {
+        let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+        writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}").unwrap();
+    }
+
+    write_file(path.with_extension("obj"), obj)
+}
+
+/// Exports a region of the terrain at the lod as a static mesh (positions, normals and UVs)
+/// to the path (without extension).
+///
+/// If an albedo attachment is configured, it is baked into a PNG texture next to the mesh.
+/// The positions are stored in world units, so that adjacent regions line up.
+/// The terrain has to be preprocessed already.
+pub fn export_mesh(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    attachments: &[AttachmentConfig],
+    export: &MeshExport,
+    path: &str,
+) -> TerrainResult<()> {
+    let path = Path::new(path);
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(TerrainError::io(directory.to_string_lossy()))?;
+    }
+
+    let mesh = TerrainMesh::new(config, base, export);
+
+    let attachment = export.albedo.as_ref().and_then(|name| {
+        let attachment = attachments
+            .iter()
+            .find(|attachment| &attachment.name == name);
+
+        if attachment.is_none() {
+            warn!("The albedo attachment {name} does not exist.");
+        }
+
+        attachment
+    });
+
+    let texture = match attachment {
+        Some(attachment) => {
+            let texture = bake_albedo(config, attachment, export);
+            let texture_path = path.with_extension("png");
+            texture
+                .save(&texture_path)
+                .map_err(|error| TerrainError::malformed(texture_path.to_string_lossy(), error))?;
+
+            texture_path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        }
+        None => None,
+    };
+
+    match export.format {
+        MeshFormat::Gltf => write_gltf(path, &mesh, texture.as_deref()),
+        MeshFormat::Obj => write_obj(path, &mesh, texture.as_deref()),
+    }
+}
+
+/// Stitches the height nodes of a region at the lod back into a single 16 bit raster and
+/// saves it to the path. The file format is deduced from the extension (e.g. `.tif` or `.png`).
+///
+/// This is the inverse of the preprocessing, which is useful for validating edits and
+/// sharing the processed data. The heights are normalized, a value of `u16::MAX` corresponds to
+/// the height of the terrain.
+/// If the terrain has a [`Georeference`](crate::georeference::Georeference), a world file and
+/// a GDAL auxiliary file (storing the EPSG code) are written alongside the raster.
+/// The terrain has to be preprocessed already.
+pub fn export_heightmap(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    min: UVec2,
+    max: UVec2,
+    lod: u32,
+    path: &str,
+) -> TerrainResult<()> {
+    let path = Path::new(path);
+
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(TerrainError::io(directory.to_string_lossy()))?;
+    }
+
+    let height_attachment = base.stored_height_attachment();
+    let mut heights = NodeSampler::new(config, &height_attachment, lod);
+
+    // the size of a pixel of the lod in world units
+    let pixel_size =
+        config.leaf_node_size as f32 / height_attachment.center_size as f32 * (1 << lod) as f32;
+    let pixel_count = (config.terrain_size as f32 / pixel_size).ceil().max(1.0) as u32;
+
+    let first = (min.as_vec2() / pixel_size)
+        .floor()
+        .as_uvec2()
+        .min(UVec2::splat(pixel_count - 1));
+    let last = (max.as_vec2() / pixel_size)
+        .ceil()
+        .as_uvec2()
+        .min(UVec2::splat(pixel_count));
+    let size = (last - first.min(last)).max(UVec2::ONE);
+
+    let raster = R16Image::from_fn(size.x, size.y, |x, y| {
+        let height = heights.height(first + UVec2::new(x, y)).unwrap_or(0.0);
+        Luma([(height * u16::MAX as f32) as u16])
+    });
+
+    raster
+        .save(path)
+        .map_err(|error| TerrainError::malformed(path.to_string_lossy(), error))?;
+
+    if let Some(georeference) = config.georeference {
+        let extension = path.extension().map_or(String::new(), |extension| {
+            extension.to_string_lossy().to_lowercase()
+        });
+
+        // e.g. tif -> tfw and png -> pgw
+        let world_extension = match extension.as_str() {
+            "tif" | "tiff" => "tfw".to_string(),
+            extension if extension.len() >= 2 => {
+                let mut chars = extension.chars();
+                format!("{}{}w", chars.next().unwrap(), chars.last().unwrap())
+            }
+            _ => "wld".to_string(),
+        };
+
+        let origin = first.as_vec2() * pixel_size + pixel_size / 2.0;
+
+        write_file(
+            path.with_extension(world_extension),
+            georeference.world_file(origin, pixel_size),
+        )?;
+
+        let mut aux_path = path.as_os_str().to_owned();
+        aux_path.push(".aux.xml");
+
+        write_file(
+            aux_path.into(),
+            format!(
+                "<PAMDataset>\n  <SRS>EPSG:{}</SRS>\n</PAMDataset>\n",
+                georeference.epsg
+            ),
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use crate::{
+    preprocess::amplify::{channel_count, from_channels, to_channels},
+    skip_none,
+    terrain_data::AttachmentFormat,
+};
+use bevy::{prelude::*, utils::HashMap};
+use image::DynamicImage;
+use itertools::iproduct;
+
+/// The outermost pixels of a source tile along its left, right, top and bottom edge.
+pub(crate) struct TileEdges([Vec<f32>; 4]);
+
+impl TileEdges {
+    pub(crate) fn new(tile_image: &DynamicImage, format: AttachmentFormat) -> Self {
+        let data = to_channels(tile_image, format);
+        let channels = channel_count(format);
+        let (width, height) = (tile_image.width(), tile_image.height());
+
+        let pixel = |x: u32, y: u32| {
+            let index = (y * width + x) as usize * channels;
+            data[index..index + channels].to_vec()
+        };
+
+        let column = |x: u32| -> Vec<f32> { (0..height).flat_map(|y| pixel(x, y)).collect() };
+        let row = |y: u32| -> Vec<f32> { (0..width).flat_map(|x| pixel(x, y)).collect() };
+
+        Self([column(0), column(width - 1), row(0), row(height - 1)])
+    }
+}
+
+/// Cross-blends the source tile with its adjacent tiles (e.g. from a different acquisition
+/// campaign), by distributing the steps along their shared edges linearly over the feather
+/// width on both sides of the seams.
+///
+/// Since the adjacent tiles are feathered towards the same values, the seams close up
+/// without any of the tiles having to be loaded twice.
+pub(crate) fn feather_tile(
+    tile_image: &DynamicImage,
+    format: AttachmentFormat,
+    coord: UVec2,
+    edges: &HashMap<UVec2, TileEdges>,
+    feather_width: u32,
+) -> DynamicImage {
+    let channels = channel_count(format);
+    let (width, height) = (tile_image.width(), tile_image.height());
+    let own_edges = &edges[&coord];
+
+    let mut data = to_channels(tile_image, format);
+
+    // the direction of the adjacent tile, the edge of this tile and the opposite edge of the adjacent tile
+    let seams = [
+        (IVec2::NEG_X, 0, 1),
+        (IVec2::X, 1, 0),
+        (IVec2::NEG_Y, 2, 3),
+        (IVec2::Y, 3, 2),
+    ];
+
+    for (direction, edge, opposite) in seams {
+        let adjacent = coord.as_ivec2() + direction;
+
+        if adjacent.min_element() < 0 {
+            continue;
+        }
+
+        let own_edge = &own_edges.0[edge];
+        let adjacent_edge = &skip_none!(edges.get(&adjacent.as_uvec2())).0[opposite];
+
+        // the tiles do not share the whole edge
+        if own_edge.len() != adjacent_edge.len() {
+            continue;
+        }
+
+        let (length, depth) = if edge < 2 {
+            (height, feather_width.min(width / 2))
+        } else {
+            (width, feather_width.min(height / 2))
+        };
+
+        for (i, k) in iproduct!(0..length, 0..depth) {
+            // both tiles meet half way at the seam
+            let weight = 0.5 * (1.0 - (k as f32 + 0.5) / feather_width as f32);
+
+            let (x, y) = match edge {
+                0 => (k, i),
+                1 => (width - 1 - k, i),
+                2 => (i, k),
+                _ => (i, height - 1 - k),
+            };
+
+            for channel in 0..channels {
+                let edge_index = i as usize * channels + channel;
+                let step = adjacent_edge[edge_index] - own_edge[edge_index];
+
+                data[(y * width + x) as usize * channels + channel] += weight * step;
+            }
+        }
+    }
+
+    from_channels(&data, UVec2::new(width, height), format)
+}
@@ -1,45 +1,39 @@
 use crate::{
+    error::{TerrainError, TerrainResult},
     formats::tdf::TDF,
-    preprocess::{R16Image, Rg16Image, Rgb8Image, Rgba8Image},
+    preprocess::{R16Image, R8Image, Rg16Image, Rgb32FImage, Rgb8Image, Rgba8Image},
     terrain_data::{calc_node_id, AttachmentConfig, AttachmentFormat, FileFormat},
 };
 use bytemuck::cast_slice;
 use dtm::DTM;
+use half::f16;
 use image::{io::Reader, DynamicImage};
 use rapid_qoi::{Colors, Qoi};
-use std::{
-    fs::{self, DirEntry, ReadDir},
-    iter::FilterMap,
-    path::Path,
-};
+use std::{fs, path::Path};
 
+/// Iterates over the names and paths of all visible files in the directory.
 pub(crate) fn iterate_directory(
     directory: &str,
-) -> FilterMap<ReadDir, fn(std::io::Result<DirEntry>) -> Option<(String, String)>> {
-    fs::read_dir(directory).unwrap().filter_map(|path| {
-        let path = path.unwrap().path();
+) -> TerrainResult<impl Iterator<Item = (String, String)>> {
+    let entries = fs::read_dir(directory).map_err(TerrainError::io(directory))?;
 
-        let name = path
-            .with_extension("")
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
+    Ok(entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
 
-        let path = path.into_os_string().into_string().unwrap();
+        let name = path.with_extension("").file_name()?.to_str()?.to_string();
+        let path = path.into_os_string().into_string().ok()?;
 
-        if name.starts_with(".") {
+        if name.starts_with('.') {
             None
         } else {
             Some((name, path))
         }
-    })
+    }))
 }
 
-pub fn reset_directory(directory: &str) {
+pub fn reset_directory(directory: &str) -> TerrainResult<()> {
     let _ = fs::remove_dir_all(directory);
-    fs::create_dir_all(directory).unwrap();
+    fs::create_dir_all(directory).map_err(TerrainError::io(directory))
 }
 
 pub(crate) fn format_directory(path: &str, name: &str) -> String {
@@ -56,38 +50,58 @@ pub(crate) fn format_node_path(directory: &str, lod: u32, x: u32, y: u32) -> Str
     format!("{directory}/{node_id}",)
 }
 
-pub fn load_image(path: &str, file_format: FileFormat) -> Option<DynamicImage> {
-    let path = Path::new(path);
-    let path = path.with_extension(file_format.extension());
-    let path = path.to_str().unwrap();
+/// Loads the image at the path, if it exists.
+pub fn load_image(path: &str, file_format: FileFormat) -> TerrainResult<Option<DynamicImage>> {
+    let path = Path::new(path).with_extension(file_format.extension());
 
-    match file_format {
-        FileFormat::TDF => load_tdf(path),
-        FileFormat::PNG | FileFormat::TIF => load_image_rs(path),
-        FileFormat::QOI => load_qoi(path),
-        FileFormat::DTM => load_dtm(path),
+    if !path.exists() {
+        return Ok(None);
     }
+
+    let path = path.to_string_lossy();
+
+    let image = match file_format {
+        FileFormat::TDF => load_tdf(&path),
+        FileFormat::PNG | FileFormat::TIF => load_image_rs(&path),
+        FileFormat::QOI => load_qoi(&path),
+        FileFormat::DTM => load_dtm(&path),
+    }?;
+
+    Ok(Some(image))
 }
 
-pub(crate) fn load_or_create_node(path: &str, attachment: &AttachmentConfig) -> DynamicImage {
-    if let Some(node_image) = load_image(path, attachment.file_format) {
-        node_image
+pub(crate) fn load_or_create_node(
+    path: &str,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<DynamicImage> {
+    if let Some(node_image) = load_image(path, attachment.file_format)? {
+        Ok(node_image)
     } else {
-        let size = attachment.texture_size;
+        Ok(create_node(attachment))
+    }
+}
 
-        match attachment.format {
-            AttachmentFormat::Rgb8 => DynamicImage::from(Rgb8Image::new(size, size)),
-            AttachmentFormat::Rgba8 => DynamicImage::from(Rgba8Image::new(size, size)),
-            AttachmentFormat::R16 => DynamicImage::from(R16Image::new(size, size)),
-            AttachmentFormat::Rg16 => DynamicImage::from(Rg16Image::new(size, size)),
-        }
+/// Creates an empty node image of the attachment.
+pub(crate) fn create_node(attachment: &AttachmentConfig) -> DynamicImage {
+    let size = attachment.texture_size;
+
+    match attachment.format {
+        AttachmentFormat::Rgb8 => DynamicImage::from(Rgb8Image::new(size, size)),
+        AttachmentFormat::Rgba8 => DynamicImage::from(Rgba8Image::new(size, size)),
+        AttachmentFormat::R16 => DynamicImage::from(R16Image::new(size, size)),
+        AttachmentFormat::Rg16 => DynamicImage::from(Rg16Image::new(size, size)),
+        AttachmentFormat::R8Uint => DynamicImage::from(R8Image::new(size, size)),
+        _ => DynamicImage::from(Rgb32FImage::new(size, size)),
     }
 }
 
-pub fn save_image(path: &str, node_image: &DynamicImage, attachment: &AttachmentConfig) {
-    let path = Path::new(path);
-    let path = path.with_extension(attachment.file_format.extension());
-    let path = path.to_str().unwrap();
+pub fn save_image(
+    path: &str,
+    node_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<()> {
+    let path = Path::new(path).with_extension(attachment.file_format.extension());
+    let path = path.to_string_lossy();
 
     match attachment.file_format {
         FileFormat::TDF => save_tdf(&path, node_image, attachment),
@@ -97,103 +111,153 @@ pub fn save_image(path: &str, node_image: &DynamicImage, attachment: &Attachment
     }
 }
 
-fn load_tdf(path: &str) -> Option<DynamicImage> {
-    let (descriptor, data) = TDF::load_file(path).ok()?;
+/// Converts the little endian bytes into 16 bit pixels.
+fn to_u16(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|pixel| u16::from_le_bytes([pixel[0], pixel[1]]))
+        .collect()
+}
+
+/// Converts the little endian bytes of half (2) or single (4) precision floats
+/// into the channels of a floating point image.
+fn to_rgb32f(data: &[u8], pixel_size: u32, channel_count: u32) -> Vec<f32> {
+    let values: Vec<f32> = match pixel_size {
+        2 => data
+            .chunks_exact(2)
+            .map(|value| f16::from_le_bytes([value[0], value[1]]).to_f32())
+            .collect(),
+        _ => data
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect(),
+    };
+
+    values
+        .chunks_exact(channel_count as usize)
+        .flat_map(|pixel| (0..3).map(|channel| pixel.get(channel).copied().unwrap_or(0.0)))
+        .collect()
+}
+
+/// Converts the channels of a floating point image into the little endian bytes of
+/// half (2) or single (4) precision floats.
+fn from_rgb32f(image: &DynamicImage, pixel_size: u32, channel_count: u32) -> Vec<u8> {
+    image
+        .as_rgb32f()
+        .unwrap()
+        .pixels()
+        .flat_map(|pixel| pixel.0[..channel_count as usize].to_vec())
+        .flat_map(|value| match pixel_size {
+            2 => f16::from_f32(value).to_le_bytes().to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        })
+        .collect()
+}
+
+fn load_tdf(path: &str) -> TerrainResult<DynamicImage> {
+    let (descriptor, data) =
+        TDF::load_file(path).map_err(|error| TerrainError::malformed(path, error))?;
     let size = descriptor.size;
+    let invalid = || TerrainError::malformed(path, "The data does not match the header.");
+
+    if descriptor.float {
+        let data = to_rgb32f(&data, descriptor.pixel_size, descriptor.channel_count);
+        let image = Rgb32FImage::from_raw(size, size, data).ok_or_else(invalid)?;
+        return Ok(DynamicImage::from(image));
+    }
 
     match (descriptor.pixel_size, descriptor.channel_count) {
+        (1, 1) => {
+            let image = R8Image::from_raw(size, size, data).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
+        }
         (1, 3) => {
-            let image = Rgb8Image::from_raw(size, size, data).unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rgb8Image::from_raw(size, size, data).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
         (1, 4) => {
-            let image = Rgba8Image::from_raw(size, size, data).unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rgba8Image::from_raw(size, size, data).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
         (2, 1) => {
-            let data: Vec<u16> = data
-                .chunks_exact(2)
-                .into_iter()
-                .map(|pixel| u16::from_ne_bytes(pixel.try_into().unwrap()))
-                .collect();
-
-            let image = R16Image::from_raw(size, size, data).unwrap();
-            Some(DynamicImage::from(image))
+            let image = R16Image::from_raw(size, size, to_u16(&data)).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
         (2, 2) => {
-            let data: Vec<u16> = data
-                .chunks_exact(2)
-                .into_iter()
-                .map(|pixel| u16::from_le_bytes(pixel.try_into().unwrap()))
-                .collect();
-
-            let image = Rg16Image::from_raw(size, size, data).unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rg16Image::from_raw(size, size, to_u16(&data)).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
-        _ => None,
+        (pixel_size, channel_count) => Err(TerrainError::malformed(
+            path,
+            format!("Unsupported pixel size {pixel_size} with {channel_count} channels."),
+        )),
     }
 }
 
-fn load_image_rs(path: &str) -> Option<DynamicImage> {
-    let mut reader = Reader::open(path).ok()?;
+fn load_image_rs(path: &str) -> TerrainResult<DynamicImage> {
+    let mut reader = Reader::open(path).map_err(TerrainError::io(path))?;
     reader.no_limits();
-    Some(reader.decode().unwrap())
+    reader
+        .decode()
+        .map_err(|error| TerrainError::malformed(path, error))
 }
 
-fn load_dtm(path: &str) -> Option<DynamicImage> {
-    let (descriptor, data) = DTM::decode_file(path).ok()?;
+fn load_dtm(path: &str) -> TerrainResult<DynamicImage> {
+    let (descriptor, data) =
+        DTM::decode_file(path).map_err(|error| TerrainError::malformed(path, error))?;
+    let (width, height) = (descriptor.width as u32, descriptor.height as u32);
+    let invalid = || TerrainError::malformed(path, "The data does not match the header.");
 
     match descriptor.channel_count {
         1 => {
-            let data: Vec<u16> = data
-                .chunks_exact(2)
-                .into_iter()
-                .map(|pixel| u16::from_le_bytes(pixel.try_into().unwrap()))
-                .collect();
-
-            let image = R16Image::from_raw(descriptor.width as u32, descriptor.height as u32, data)
-                .unwrap();
-            Some(DynamicImage::from(image))
+            let image = R16Image::from_raw(width, height, to_u16(&data)).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
         2 => {
-            let data: Vec<u16> = data
-                .chunks_exact(2)
-                .into_iter()
-                .map(|pixel| u16::from_le_bytes(pixel.try_into().unwrap()))
-                .collect();
-
-            let image =
-                Rg16Image::from_raw(descriptor.width as u32, descriptor.height as u32, data)
-                    .unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rg16Image::from_raw(width, height, to_u16(&data)).ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
-        _ => None,
+        channel_count => Err(TerrainError::malformed(
+            path,
+            format!("Unsupported channel count {channel_count}."),
+        )),
     }
 }
 
-fn load_qoi(path: &str) -> Option<DynamicImage> {
-    let bytes = fs::read(path).ok()?;
-    let (descriptor, pixels) = Qoi::decode_alloc(&bytes).unwrap();
+fn load_qoi(path: &str) -> TerrainResult<DynamicImage> {
+    let bytes = fs::read(path).map_err(TerrainError::io(path))?;
+    let (descriptor, pixels) =
+        Qoi::decode_alloc(&bytes).map_err(|error| TerrainError::malformed(path, error))?;
+    let invalid = || TerrainError::malformed(path, "The data does not match the header.");
 
     match descriptor.colors {
         Colors::Rgb => {
-            let image = Rgb8Image::from_raw(descriptor.width, descriptor.height, pixels).unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rgb8Image::from_raw(descriptor.width, descriptor.height, pixels)
+                .ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
         Colors::Rgba => {
-            let image = Rgba8Image::from_raw(descriptor.width, descriptor.height, pixels).unwrap();
-            Some(DynamicImage::from(image))
+            let image = Rgba8Image::from_raw(descriptor.width, descriptor.height, pixels)
+                .ok_or_else(invalid)?;
+            Ok(DynamicImage::from(image))
         }
-        _ => None,
+        _ => Err(TerrainError::malformed(path, "Unsupported color space.")),
     }
 }
 
-fn save_tdf(path: &str, node_image: &DynamicImage, attachment: &AttachmentConfig) {
+fn save_tdf(
+    path: &str,
+    node_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<()> {
     let (pixel_size, channel_count) = match attachment.format {
         AttachmentFormat::Rgb8 => (1, 3),
         AttachmentFormat::Rgba8 => (1, 4),
         AttachmentFormat::R16 => (2, 1),
         AttachmentFormat::Rg16 => (2, 2),
+        AttachmentFormat::R16F => (2, 1),
+        AttachmentFormat::R32F => (4, 1),
+        AttachmentFormat::Rg32F => (4, 2),
+        AttachmentFormat::R8Uint => (1, 1),
     };
 
     let descriptor = TDF {
@@ -201,48 +265,82 @@ fn save_tdf(path: &str, node_image: &DynamicImage, attachment: &AttachmentConfig
         channel_count,
         size: attachment.texture_size,
         mip_level_count: attachment.mip_level_count,
+        float: attachment.format.is_float(),
+        categorical: attachment.format.is_categorical(),
+    };
+
+    let result = if descriptor.float {
+        descriptor.save_file(path, &from_rgb32f(node_image, pixel_size, channel_count))
+    } else {
+        descriptor.save_file(path, node_image.as_bytes())
     };
 
-    descriptor.save_file(path, node_image.as_bytes()).unwrap();
+    result.map_err(|error| TerrainError::malformed(path, error))
 }
 
-fn save_image_rs(path: &str, node_image: &DynamicImage, _attachment: &AttachmentConfig) {
-    node_image.save(path).expect("Could not save node.");
+fn save_image_rs(
+    path: &str,
+    node_image: &DynamicImage,
+    _attachment: &AttachmentConfig,
+) -> TerrainResult<()> {
+    node_image
+        .save(path)
+        .map_err(|error| TerrainError::malformed(path, error))
 }
 
-fn save_dtm(path: &str, node_image: &DynamicImage, attachment: &AttachmentConfig) {
+fn save_dtm(
+    path: &str,
+    node_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<()> {
+    let channel_count = match attachment.format {
+        AttachmentFormat::R16 => 1,
+        AttachmentFormat::Rg16 => 2,
+        format => {
+            return Err(TerrainError::UnsupportedFormat {
+                format,
+                file_format: FileFormat::DTM,
+            })
+        }
+    };
+
     let descriptor = DTM {
         pixel_size: 2,
-        channel_count: match attachment.format {
-            AttachmentFormat::Rgb8 => panic!("Can not save Rgb8 as DTM."),
-            AttachmentFormat::Rgba8 => panic!("Can not save Rgba8 as DTM."),
-            AttachmentFormat::R16 => 1,
-            AttachmentFormat::Rg16 => 2,
-        },
+        channel_count,
         width: node_image.width(),
         height: node_image.height(),
     };
 
     descriptor
         .encode_file(path, cast_slice(node_image.as_bytes()))
-        .expect("Could not save node.");
+        .map_err(|error| TerrainError::malformed(path, error))
 }
 
-fn save_qoi(path: &str, node_image: &DynamicImage, attachment: &AttachmentConfig) {
+fn save_qoi(
+    path: &str,
+    node_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<()> {
+    let colors = match attachment.format {
+        AttachmentFormat::Rgb8 => Colors::Rgb,
+        AttachmentFormat::Rgba8 => Colors::Rgba,
+        format => {
+            return Err(TerrainError::UnsupportedFormat {
+                format,
+                file_format: FileFormat::QOI,
+            })
+        }
+    };
+
     let descriptor = Qoi {
         width: node_image.width(),
         height: node_image.height(),
-        colors: match attachment.format {
-            AttachmentFormat::Rgb8 => Colors::Rgb,
-            AttachmentFormat::Rgba8 => Colors::Rgba,
-            AttachmentFormat::R16 => panic!("Can not save R16 as QOI."),
-            AttachmentFormat::Rg16 => panic!("Can not save Rg16 as QOI."),
-        },
+        colors,
     };
 
     let bytes = descriptor
         .encode_alloc(cast_slice(node_image.as_bytes()))
-        .unwrap();
+        .map_err(|error| TerrainError::malformed(path, error))?;
 
-    fs::write(path, &bytes).expect("Could not save node.");
+    fs::write(path, bytes).map_err(TerrainError::io(path))
 }
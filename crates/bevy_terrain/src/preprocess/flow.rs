@@ -0,0 +1,274 @@
+//! Analyzes the drainage of the terrain, by computing the flow accumulation and the watersheds
+//! from the preprocessed height data (e.g. for hydrology-aware gameplay or GIS analysis).
+
+use crate::{
+    error::TerrainResult,
+    preprocess::{
+        export::NodeSampler,
+        file_io::{
+            format_directory, format_node_path, iterate_directory, reset_directory, save_image,
+        },
+        BaseConfig, Rg16Image,
+    },
+    skip_none,
+    terrain_data::{AttachmentConfig, AttachmentFormat, NodeCoordinate, NodeId},
+    TerrainConfig,
+};
+use bevy::prelude::*;
+use image::{DynamicImage, LumaA};
+use itertools::iproduct;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
+
+/// The drainage analysis of the terrain, which is stored in the flow attachment.
+///
+/// The red channel stores the flow accumulation (the number of upstream pixels) on a
+/// logarithmic scale (0 for ridges to 1 for the pixel, which drains the whole terrain).
+/// The green channel stores the id of the watershed, which the pixel drains into,
+/// divided by `u16::MAX`. Pixels without height data have the id zero.
+///
+/// Depressions are filled, so that all water drains towards the border of the terrain
+/// or towards its nodata areas, which act as outlets.
+#[derive(Clone, Copy, Debug)]
+pub struct FlowAnalysis {
+    /// The lod of the height data, which is analyzed as a whole.
+    /// Finer lods capture smaller streams, but the whole lod has to fit into memory.
+    pub lod: u32,
+}
+
+impl FlowAnalysis {
+    pub fn new(lod: u32) -> Self {
+        Self { lod }
+    }
+
+    /// Returns the attachment, which stores the flow accumulation and the watersheds.
+    pub(crate) fn attachment(&self, base: &BaseConfig) -> AttachmentConfig {
+        let mut attachment = AttachmentConfig::new(
+            "flow".to_string(),
+            base.texture_size,
+            base.border_size,
+            base.mip_level_count,
+            AttachmentFormat::Rg16,
+        );
+
+        attachment.file_format = base.file_format;
+        attachment
+    }
+}
+
+/// A pixel in the queue of the depression filling, ordered by its filled height.
+struct FloodPixel {
+    height: f32,
+    index: usize,
+}
+
+impl PartialEq for FloodPixel {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FloodPixel {}
+
+impl PartialOrd for FloodPixel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FloodPixel {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.height
+            .total_cmp(&other.height)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+/// The flow accumulation and the watershed id of each pixel of a raster.
+struct FlowRaster {
+    size: u32,
+    pixels: Vec<(f32, u32)>,
+}
+
+impl FlowRaster {
+    fn get(&self, pixel: IVec2) -> (f32, u32) {
+        let pixel = pixel.clamp(IVec2::ZERO, IVec2::splat(self.size as i32 - 1));
+
+        self.pixels[(pixel.y as u32 * self.size + pixel.x as u32) as usize]
+    }
+
+    /// Halves the resolution, each pixel keeps the strongest flow of the four finer pixels,
+    /// so that the main streams remain visible on coarser lods.
+    fn down_sample(&self) -> Self {
+        let size = (self.size + 1) / 2;
+
+        let pixels = iproduct!(0..size as i32, 0..size as i32)
+            .map(|(y, x)| {
+                iproduct!(0..2, 0..2)
+                    .map(|(dy, dx)| self.get(IVec2::new(2 * x + dx, 2 * y + dy)))
+                    .max_by(|a, b| a.0.total_cmp(&b.0))
+                    .unwrap()
+            })
+            .collect();
+
+        Self { size, pixels }
+    }
+}
+
+/// Routes the water across the raster and returns the flow accumulation and watershed ids.
+///
+/// The depressions are filled with the priority flood algorithm, which processes the pixels
+/// from the outlets upwards. Each pixel drains into the neighbour, from which it was reached,
+/// thus the order of the flood is a valid drainage order as well.
+fn analyze(heights: &[Option<f32>], size: u32) -> FlowRaster {
+    let pixel_count = heights.len();
+    let neighbours = move |index: usize| {
+        let (x, y) = ((index as u32 % size) as i32, (index as u32 / size) as i32);
+
+        iproduct!(-1..=1, -1..=1)
+            .filter(|&offset| offset != (0, 0))
+            .map(move |(dy, dx)| (x + dx, y + dy))
+            .filter(move |&(x, y)| x >= 0 && y >= 0 && x < size as i32 && y < size as i32)
+            .map(move |(x, y)| (y as u32 * size + x as u32) as usize)
+    };
+
+    let mut queue = BinaryHeap::new();
+    let mut visited = vec![false; pixel_count];
+    let mut downstream: Vec<usize> = (0..pixel_count).collect();
+    let mut watersheds = vec![0; pixel_count];
+    let mut order = Vec::with_capacity(pixel_count);
+    let mut watershed_count = 0;
+
+    // the outlets are the pixels at the border of the raster or next to nodata pixels
+    for (index, height) in heights.iter().enumerate() {
+        let height = skip_none!(*height);
+        let is_outlet = neighbours(index).count() < 8
+            || neighbours(index).any(|neighbour| heights[neighbour].is_none());
+
+        if is_outlet {
+            visited[index] = true;
+            watershed_count += 1;
+            watersheds[index] = watershed_count % u16::MAX as u32 + 1;
+            queue.push(Reverse(FloodPixel { height, index }));
+        }
+    }
+
+    while let Some(Reverse(FloodPixel { height, index })) = queue.pop() {
+        order.push(index);
+
+        for neighbour in neighbours(index) {
+            if visited[neighbour] {
+                continue;
+            }
+
+            let neighbour_height = skip_none!(heights[neighbour]);
+
+            visited[neighbour] = true;
+            downstream[neighbour] = index;
+            watersheds[neighbour] = watersheds[index];
+            queue.push(Reverse(FloodPixel {
+                height: neighbour_height.max(height),
+                index: neighbour,
+            }));
+        }
+    }
+
+    // accumulate the flow from the upstream pixels, which were flooded last
+    let mut accumulation: Vec<f32> = vec![0.0; pixel_count];
+
+    for &index in order.iter().rev() {
+        accumulation[index] += 1.0;
+
+        if downstream[index] != index {
+            accumulation[downstream[index]] += accumulation[index];
+        }
+    }
+
+    let max_accumulation = (order.len() as f32).max(2.0).ln();
+
+    let pixels = accumulation
+        .iter()
+        .zip(watersheds)
+        .map(|(&accumulation, watershed)| {
+            let flow = if accumulation > 0.0 {
+                accumulation.ln() / max_accumulation
+            } else {
+                0.0
+            };
+
+            (flow, watershed)
+        })
+        .collect();
+
+    FlowRaster { size, pixels }
+}
+
+/// Generates the flow attachment for all lods of the terrain.
+///
+/// The terrain has to be preprocessed already.
+/// The whole lod of the analysis is loaded and analyzed at once, then each node of the
+/// flow attachment is sampled from the analysis or its down sampled versions.
+pub(crate) fn preprocess_flow(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    analysis: &FlowAnalysis,
+) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let attachment = analysis.attachment(base);
+    let height_directory = format_directory(&config.path, "height");
+    let directory = format_directory(&config.path, &attachment.name);
+
+    reset_directory(&directory)?;
+
+    let mut heights = NodeSampler::new(config, &height_attachment, analysis.lod);
+
+    let pixel_size = config.leaf_node_size as f32 / height_attachment.center_size as f32
+        * (1 << analysis.lod) as f32;
+    let size = (config.terrain_size as f32 / pixel_size).ceil().max(1.0) as u32;
+
+    let height_raster: Vec<Option<f32>> = iproduct!(0..size, 0..size)
+        .map(|(y, x)| heights.height(UVec2::new(x, y)))
+        .collect();
+
+    // the analysis at its lod, followed by its down sampled versions for the coarser lods
+    let mut rasters = vec![analyze(&height_raster, size)];
+
+    for lod in 0..config.lod_count {
+        let level = lod.saturating_sub(analysis.lod) as usize;
+
+        while rasters.len() <= level {
+            let raster = rasters.last().unwrap().down_sample();
+            rasters.push(raster);
+        }
+
+        let raster = &rasters[level];
+        // finer lods than the analysis are up sampled
+        let scale = (1 << analysis.lod.saturating_sub(lod)) as f32;
+
+        let coordinates = iterate_directory(&height_directory)?
+            .filter_map(|(name, _)| name.parse::<NodeId>().ok())
+            .map(NodeCoordinate::from)
+            .filter(|coordinate| coordinate.lod == lod);
+
+        for coordinate in coordinates {
+            let origin = UVec2::new(coordinate.x, coordinate.y) * attachment.center_size;
+
+            let node_image =
+                Rg16Image::from_fn(attachment.texture_size, attachment.texture_size, |x, y| {
+                    let pixel =
+                        (origin + UVec2::new(x, y)).as_ivec2() - attachment.border_size as i32;
+                    let pixel = ((pixel.as_vec2() + 0.5) / scale).floor().as_ivec2();
+                    let (flow, watershed) = raster.get(pixel);
+
+                    LumaA([(flow * u16::MAX as f32) as u16, watershed as u16])
+                });
+
+            let node_path = format_node_path(&directory, lod, coordinate.x, coordinate.y);
+            save_image(&node_path, &DynamicImage::from(node_image), &attachment)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,23 +1,80 @@
 //! Contains the implementation for preprocessing source tiles into streamable nodes.
+//!
+//! The preprocessing reads and writes the file system directly and is thus not available on the
+//! web (wasm32). There, the terrain data has to be preprocessed beforehand and served alongside
+//! the app, while only the [`BaseConfig`] is used to describe the base attachment.
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod amplify;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod attachment;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod biome;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cut_fill;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod derivative;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod down_sample;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod export;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod feather;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod file_io;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod flow;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod mosaic;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod reproject;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod road;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod split;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod stitch;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tin;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod virtual_texture;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod void_fill;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod water;
 
+use crate::terrain_data::{AttachmentConfig, AttachmentFormat, AttachmentSampler, FileFormat};
+#[cfg(not(target_arch = "wasm32"))]
 use crate::{
+    biome::BiomeClassification,
+    error::TerrainResult,
+    georeference::Georeference,
     preprocess::{
-        attachment::{preprocess_attachment, preprocess_base},
+        amplify::DetailAmplification,
+        attachment::{pack_height_residual, preprocess_attachment, preprocess_base},
+        biome::preprocess_biomes,
         config::save_config,
+        derivative::{preprocess_derivative, Derivative},
+        flow::{preprocess_flow, FlowAnalysis},
+        mosaic::MosaicSource,
+        road::{carve_road, Road},
+        tin::preprocess_tin,
+        virtual_texture::preprocess_virtual_texture,
+        void_fill::VoidFilling,
+        water::WaterFlattening,
     },
-    terrain_data::{AttachmentConfig, AttachmentFormat, FileFormat},
+    terrain_data::virtual_texture::VirtualTextureConfig,
     TerrainConfig,
 };
+#[cfg(not(target_arch = "wasm32"))]
 use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
 use image::{ImageBuffer, Luma, LumaA, Rgb, Rgba};
+#[cfg(not(target_arch = "wasm32"))]
 use itertools::{Itertools, Product};
+#[cfg(not(target_arch = "wasm32"))]
 use std::ops::Range;
 
 #[macro_export]
@@ -39,6 +96,18 @@ pub struct BaseConfig {
     pub border_size: u32,
     pub mip_level_count: u32,
     pub file_format: FileFormat,
+    /// The format of the height attachment (`R16`, `R16F` or `R32F`).
+    /// The minmax attachment of floating point heights uses the `Rg32F` format.
+    pub height_format: AttachmentFormat,
+    /// Whether the residual of the quantized `R16` heights is stored in a second channel,
+    /// which turns the height attachment into an `Rg16` attachment with 32 bit fixed point
+    /// precision. This removes the terracing of flat areas, which are lit at a low angle.
+    ///
+    /// The heights are processed as 32 bit floats and packed after all other attachments
+    /// have been preprocessed, thus the source heights have to be normalized.
+    /// Only the shaders of the terrain use the residual, while the CPU-side sampling and
+    /// the compute passes keep using the first channel.
+    pub height_residual: bool,
 }
 
 impl BaseConfig {
@@ -48,35 +117,72 @@ impl BaseConfig {
             border_size: 2,
             mip_level_count,
             file_format: FileFormat::TDF,
+            height_format: AttachmentFormat::R16,
+            height_residual: false,
         }
     }
 
+    /// Whether the residual of the heights is stored, which only applies to the `R16` format.
+    pub(crate) fn has_residual(&self) -> bool {
+        self.height_residual && matches!(self.height_format, AttachmentFormat::R16)
+    }
+
+    /// The format, in which the heights are processed.
+    fn processing_format(&self) -> AttachmentFormat {
+        if self.has_residual() {
+            AttachmentFormat::R32F
+        } else {
+            self.height_format
+        }
+    }
+
+    /// The height attachment, as it is processed by the preprocessor.
     pub(crate) fn height_attachment(&self) -> AttachmentConfig {
         let mut attachment = AttachmentConfig::new(
             "height".to_string(),
             self.texture_size,
             self.border_size,
             self.mip_level_count,
-            AttachmentFormat::R16,
+            self.processing_format(),
         );
 
         attachment.file_format = self.file_format;
+        attachment.sampler = AttachmentSampler::linear();
+        attachment
+    }
+
+    /// The height attachment, as it is stored after the preprocessing and loaded into the
+    /// node atlas.
+    pub(crate) fn stored_height_attachment(&self) -> AttachmentConfig {
+        let mut attachment = self.height_attachment();
+
+        if self.has_residual() {
+            attachment.format = AttachmentFormat::Rg16;
+        }
+
         attachment
     }
+
     pub(crate) fn minmax_attachment(&self) -> AttachmentConfig {
         let mut attachment = AttachmentConfig::new(
             "minmax".to_string(),
             self.texture_size,
             self.border_size,
             self.mip_level_count,
-            AttachmentFormat::Rg16,
+            if self.processing_format().is_float() {
+                AttachmentFormat::Rg32F
+            } else {
+                AttachmentFormat::Rg16
+            },
         );
 
         attachment.file_format = self.file_format;
+        attachment.sampler = AttachmentSampler::linear();
         attachment
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 /// The configuration of the source tile(s) of an attachment.
 #[derive(Default, Debug)]
 pub struct TileConfig {
@@ -86,8 +192,28 @@ pub struct TileConfig {
     pub size: u32,
     /// The file format of the tile.
     pub file_format: FileFormat,
+    /// Optionally refines the lods, which are finer than the tile, procedurally.
+    pub detail: Option<DetailAmplification>,
+    /// Optionally flattens the height data inside of water bodies.
+    pub water: Option<WaterFlattening>,
+    /// Optionally fills the nodata voids of the source data.
+    pub void_fill: Option<VoidFilling>,
+    /// The placement of the tile(s) inside their coordinate reference system, if any,
+    /// where one world unit corresponds to one pixel.
+    ///
+    /// The tile(s) are then reprojected into the grid of the terrain, which requires the terrain
+    /// to have a georeference as well.
+    /// Tiles in a different coordinate reference system than the terrain require the `proj` feature.
+    pub georeference: Option<Georeference>,
+    /// Additional sources with different resolutions, which are mosaicked with the tile(s).
+    pub sources: Vec<MosaicSource>,
+    /// The width in pixels, over which the seams between adjacent tiles of a directory are
+    /// cross-blended (e.g. to hide steps between different acquisition campaigns).
+    /// Zero disables the feathering.
+    pub feather_width: u32,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 /// The preprocessor converts attachments from source data to streamable nodes.
 ///
 /// It gathers all configurations of the attachments and then optionally processes them.
@@ -95,29 +221,172 @@ pub struct TileConfig {
 pub struct Preprocessor {
     pub(crate) base: Option<(TileConfig, BaseConfig)>,
     pub(crate) attachments: Vec<(TileConfig, AttachmentConfig)>,
+    pub(crate) virtual_textures: Vec<(TileConfig, VirtualTextureConfig)>,
+    pub(crate) derivatives: Vec<(BaseConfig, Derivative)>,
+    pub(crate) flow: Option<(BaseConfig, FlowAnalysis)>,
+    pub(crate) biomes: Option<(BaseConfig, BiomeClassification)>,
+    pub(crate) roads: Vec<Road>,
+    pub(crate) tin: Option<(BaseConfig, f32)>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Preprocessor {
     /// Preprocesses all attachments of the terrain.
-    pub fn preprocess(self, config: &TerrainConfig) {
-        if let Some(base) = self.base {
-            preprocess_base(config, &base.0, &base.1);
+    ///
+    /// Stops at the first missing or malformed source tile and returns the error.
+    pub fn preprocess(self, config: &TerrainConfig) -> TerrainResult<()> {
+        self.preprocess_with_progress(config, |_| {})
+    }
+
+    /// Adds a road, which is carved into the terrain after all attachments have been preprocessed.
+    ///
+    /// The derivatives are generated afterwards, thus they include the road.
+    pub fn add_road(&mut self, road: Road) {
+        self.roads.push(road);
+    }
+
+    /// Adds the imagery attachment of a virtual texture, which is cut into pages
+    /// after the other attachments.
+    ///
+    /// The pages are streamed by a
+    /// [`TerrainVirtualTexture`](crate::terrain_data::virtual_texture::TerrainVirtualTexture)
+    /// instead of the node atlas.
+    pub fn add_virtual_texture(&mut self, tile: TileConfig, config: VirtualTextureConfig) {
+        self.virtual_textures.push((tile, config));
+    }
+
+    /// Generates an experimental simplified mesh for every height node, after the derivatives.
+    ///
+    /// The maximum error is the allowed height difference in world units at lod 0,
+    /// which doubles with each coarser lod.
+    /// The meshes can be loaded with [`load_tin_meshes`](tin::load_tin_meshes).
+    pub fn add_tin(&mut self, base: BaseConfig, max_error: f32) {
+        self.tin = Some((base, max_error));
+    }
+
+    /// Returns the number of steps (one per attachment, one per virtual texture and one for all
+    /// roads), that the preprocessing consists of.
+    pub fn step_count(&self) -> usize {
+        self.base.iter().count()
+            + self.attachments.len()
+            + self.virtual_textures.len()
+            + usize::from(!self.roads.is_empty())
+            + self.derivatives.len()
+            + usize::from(self.flow.is_some())
+            + usize::from(self.biomes.is_some())
+            + usize::from(self.tin.is_some())
+    }
+
+    /// Preprocesses all attachments of the terrain and reports the number of finished
+    /// steps after each attachment.
+    pub fn preprocess_with_progress(
+        self,
+        config: &TerrainConfig,
+        mut progress: impl FnMut(usize),
+    ) -> TerrainResult<()> {
+        let mut step = 0;
+        let mut attachments = vec![];
+
+        if let Some((tile, base)) = &self.base {
+            preprocess_base(config, tile, base)?;
+            attachments.push(base.stored_height_attachment());
+            attachments.push(base.minmax_attachment());
+            step += 1;
+            progress(step);
+        }
+
+        for (tile, attachment) in &self.attachments {
+            preprocess_attachment(config, tile, attachment)?;
+            attachments.push(attachment.clone());
+            step += 1;
+            progress(step);
+        }
+
+        // the pages are not loaded into the node atlas, thus they are not part of the config
+        for (tile, virtual_texture) in &self.virtual_textures {
+            preprocess_virtual_texture(config, tile, virtual_texture)?;
+            step += 1;
+            progress(step);
+        }
+
+        if !self.roads.is_empty() {
+            match &self.base {
+                Some((_, base)) => {
+                    for road in &self.roads {
+                        let splat_attachment = road.splat.as_ref().and_then(|splat| {
+                            self.attachments
+                                .iter()
+                                .map(|(_, attachment)| attachment)
+                                .find(|attachment| attachment.name == splat.attachment)
+                        });
+
+                        carve_road(config, base, splat_attachment, road)?;
+                    }
+                }
+                None => warn!("Roads can only be carved into terrains with a base attachment."),
+            }
+
+            step += 1;
+            progress(step);
+        }
+
+        for (base, derivative) in self.derivatives {
+            attachments.push(derivative.attachment(&base));
+            preprocess_derivative(config, &base, derivative)?;
+            step += 1;
+            progress(step);
+        }
+
+        if let Some((base, analysis)) = &self.flow {
+            attachments.push(analysis.attachment(base));
+            preprocess_flow(config, base, analysis)?;
+            step += 1;
+            progress(step);
+        }
+
+        if let Some((base, classification)) = &self.biomes {
+            let landcover_attachment =
+                classification
+                    .landcover_attachment
+                    .as_ref()
+                    .and_then(|landcover| {
+                        self.attachments
+                            .iter()
+                            .map(|(_, attachment)| attachment)
+                            .find(|attachment| &attachment.name == landcover)
+                    });
+
+            attachments.push(classification.attachment(base));
+            preprocess_biomes(config, base, classification, landcover_attachment)?;
+            step += 1;
+            progress(step);
+        }
+
+        if let Some((base, max_error)) = &self.tin {
+            preprocess_tin(config, base, *max_error)?;
+            step += 1;
+            progress(step);
         }
 
-        for (tile, attachment) in self.attachments {
-            preprocess_attachment(config, &tile, &attachment);
+        // all other steps read the heights in the format they were processed in
+        if let Some((_, base)) = &self.base {
+            if base.has_residual() {
+                pack_height_residual(config, base)?;
+            }
         }
 
-        save_config(config);
+        save_config(config, &attachments)
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub(crate) trait UVec2Utils {
     fn div_floor(self, rhs: u32) -> Self;
     fn div_ceil(self, rhs: u32) -> Self;
     fn product(self, other: Self) -> Product<Range<u32>, Range<u32>>;
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl UVec2Utils for UVec2 {
     fn div_floor(self, rhs: u32) -> Self {
         self / rhs
@@ -132,7 +401,16 @@ impl UVec2Utils for UVec2 {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub type R8Image = ImageBuffer<Luma<u8>, Vec<u8>>;
+#[cfg(not(target_arch = "wasm32"))]
 pub type Rgb8Image = ImageBuffer<Rgb<u8>, Vec<u8>>;
+#[cfg(not(target_arch = "wasm32"))]
 pub type Rgba8Image = ImageBuffer<Rgba<u8>, Vec<u8>>;
+#[cfg(not(target_arch = "wasm32"))]
 pub type R16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+#[cfg(not(target_arch = "wasm32"))]
 pub type Rg16Image = ImageBuffer<LumaA<u16>, Vec<u16>>;
+/// Stores the channels of all floating point attachments, the unused ones are zero.
+#[cfg(not(target_arch = "wasm32"))]
+pub type Rgb32FImage = ImageBuffer<Rgb<f32>, Vec<f32>>;
@@ -0,0 +1,281 @@
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        amplify::{channel_count, from_channels, to_channels},
+        file_io::{create_node, format_node_path, iterate_directory, load_image, save_image},
+        split::{load_filled_tile, tile_coordinate},
+        TileConfig, UVec2Utils,
+    },
+    terrain_data::{AttachmentConfig, FileFormat},
+};
+use bevy::{prelude::*, utils::HashSet};
+use image::DynamicImage;
+use itertools::iproduct;
+use std::{fs, iter};
+
+/// A source of an attachment, which is mosaicked with the tile(s) of its [`TileConfig`]
+/// (e.g. high-resolution lidar data of the cities on top of a coarse DEM of the surroundings).
+///
+/// The sources are applied in the order of their priority, so that sources with a higher priority
+/// overwrite the ones below. Sources with the same priority are applied from the coarsest to the
+/// finest resolution, so that each node uses the best available data.
+#[derive(Clone, Debug)]
+pub struct MosaicSource {
+    /// The path to the tile/directory of tiles (named `name_x_y`).
+    pub path: String,
+    /// The size of the tile(s) in pixels.
+    pub size: u32,
+    /// The file format of the tile(s).
+    pub file_format: FileFormat,
+    /// The size of a pixel of the source measured in pixels of the tile(s) of the [`TileConfig`]
+    /// (e.g. 20 for a 20 m DEM mosaicked with 1 m lidar data).
+    pub resolution: f32,
+    /// The priority of the source. The tile(s) of the [`TileConfig`] have a priority of zero.
+    pub priority: i32,
+    /// The width of the band along the outer edges of the source, measured in pixels of the
+    /// tile(s) of the [`TileConfig`], in which it is blended with the sources below.
+    pub blend_width: f32,
+}
+
+impl MosaicSource {
+    /// Returns the paths and coordinates of the tiles of the source.
+    fn tiles(&self) -> TerrainResult<Vec<(String, UVec2)>> {
+        let metadata = fs::metadata(&self.path).map_err(|_| TerrainError::MissingTile {
+            path: self.path.clone(),
+        })?;
+
+        if !metadata.is_dir() {
+            return Ok(vec![(self.path.clone(), UVec2::ZERO)]);
+        }
+
+        iterate_directory(&self.path)?
+            .map(|(tile_name, tile_path)| {
+                let coord = tile_coordinate(&tile_name).ok_or_else(|| {
+                    TerrainError::InvalidConfig(format!(
+                        "The tile {tile_path} is not named after its coordinate (name_x_y)."
+                    ))
+                })?;
+
+                Ok((tile_path, coord))
+            })
+            .collect()
+    }
+}
+
+/// Bilinearly samples the channel of the tile at the pixel coordinate.
+fn sample(data: &[f32], size: UVec2, channels: usize, channel: usize, position: Vec2) -> f32 {
+    let position = position.clamp(Vec2::ZERO, (size - 1).as_vec2());
+    let first = position.floor().as_uvec2();
+    let last = (first + 1).min(size - 1);
+    let ratio = position - first.as_vec2();
+
+    let value = |x: u32, y: u32| data[(y * size.x + x) as usize * channels + channel];
+
+    let top = value(first.x, first.y) * (1.0 - ratio.x) + value(last.x, first.y) * ratio.x;
+    let bottom = value(first.x, last.y) * (1.0 - ratio.x) + value(last.x, last.y) * ratio.x;
+
+    top * (1.0 - ratio.y) + bottom * ratio.y
+}
+
+/// Returns an ordered dither threshold (4x4 Bayer matrix) of the pixel in the range of (0, 1).
+fn dither(x: u32, y: u32) -> f32 {
+    const BAYER: [u32; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+
+    (BAYER[((y % 4) * 4 + x % 4) as usize] as f32 + 0.5) / 16.0
+}
+
+/// Resamples the tile of the source into all nodes of the lod it overlaps.
+///
+/// Along the outer edges of the source the tile is blended with the data of the sources below.
+/// Voids of the tile, which are passed to be inherited, keep the data of the sources below.
+#[allow(clippy::too_many_arguments)]
+fn mosaic_tile(
+    directory: &str,
+    tile_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+    lod: u32,
+    source: &MosaicSource,
+    coord: UVec2,
+    coords: &HashSet<UVec2>,
+    voids: Option<&[bool]>,
+) -> TerrainResult<()> {
+    let channels = channel_count(attachment.format);
+    let size = attachment.texture_size;
+    let border = attachment.border_size as f32;
+
+    let tile_size = UVec2::new(tile_image.width(), tile_image.height());
+    let tile_data = to_channels(tile_image, attachment.format);
+
+    // the offset of the tile in pixels of the source
+    let offset = coord * source.size;
+    let tile_min = offset.as_vec2() * source.resolution;
+    let tile_max = (offset + tile_size).as_vec2() * source.resolution;
+
+    // the edges of the tile, which are not adjacent to another tile of the source
+    let outer = |x: Option<u32>, y: Option<u32>| match (x, y) {
+        (Some(x), Some(y)) => !coords.contains(&UVec2::new(x, y)),
+        _ => true,
+    };
+    let outer_edges = [
+        outer(coord.x.checked_sub(1), Some(coord.y)),
+        outer(Some(coord.x + 1), Some(coord.y)),
+        outer(Some(coord.x), coord.y.checked_sub(1)),
+        outer(Some(coord.x), Some(coord.y + 1)),
+    ];
+
+    // first and last node coordinate
+    let first = (tile_min - border)
+        .max(Vec2::ZERO)
+        .as_uvec2()
+        .div_floor(attachment.center_size);
+    let last = (tile_max + border)
+        .ceil()
+        .as_uvec2()
+        .div_ceil(attachment.center_size);
+
+    for (x, y) in first.product(last) {
+        let node_path = format_node_path(directory, lod, x, y);
+
+        // only existing nodes contain data of the sources below, which can be blended with
+        let (node_image, existing) = match load_image(&node_path, attachment.file_format)? {
+            Some(node_image) => (node_image, true),
+            None => (create_node(attachment), false),
+        };
+        let blend = existing && source.blend_width > 0.0;
+
+        let mut node_data = to_channels(&node_image, attachment.format);
+        let node_offset = (UVec2::new(x, y) * attachment.center_size).as_vec2() - border;
+
+        for (py, px) in iproduct!(0..size, 0..size) {
+            // the center of the pixel in pixels of the tile(s) of the tile config
+            let position = node_offset + Vec2::new(px as f32, py as f32) + 0.5;
+
+            if position.cmplt(tile_min).any() || position.cmpge(tile_max).any() {
+                continue;
+            }
+
+            let mut tile_position = position / source.resolution - offset.as_vec2() - 0.5;
+
+            // categories are resampled with the nearest neighbor
+            if attachment.format.is_categorical() {
+                tile_position = tile_position.round();
+            }
+
+            if let (true, Some(voids)) = (existing, voids) {
+                let pixel = tile_position.round().as_uvec2().min(tile_size - 1);
+
+                if voids[(pixel.y * tile_size.x + pixel.x) as usize] {
+                    continue;
+                }
+            }
+
+            let mut weight = if blend {
+                let distances = [
+                    position.x - tile_min.x,
+                    tile_max.x - position.x,
+                    position.y - tile_min.y,
+                    tile_max.y - position.y,
+                ];
+
+                let distance = distances
+                    .into_iter()
+                    .zip(outer_edges)
+                    .filter(|&(_, outer)| outer)
+                    .map(|(distance, _)| distance)
+                    .fold(f32::MAX, f32::min);
+
+                (distance / source.blend_width).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+
+            // the blend of categories is dithered instead
+            if attachment.format.is_categorical() {
+                weight = f32::from(weight > dither(px, py));
+            }
+
+            for channel in 0..channels {
+                let value = sample(&tile_data, tile_size, channels, channel, tile_position);
+                let index = (py * size + px) as usize * channels + channel;
+
+                node_data[index] = node_data[index] * (1.0 - weight) + value * weight;
+            }
+        }
+
+        let node_image = from_channels(&node_data, UVec2::splat(size), attachment.format);
+        save_image(&node_path, &node_image, attachment)?;
+    }
+
+    Ok(())
+}
+
+/// Mosaics the tile(s) of the tile config and its sources into nodes of the lod.
+///
+/// Returns the first and last node coordinate of the lod.
+pub(crate) fn mosaic_tiles(
+    directory: &str,
+    tile: &TileConfig,
+    attachment: &AttachmentConfig,
+    lod: u32,
+) -> TerrainResult<(UVec2, UVec2)> {
+    if tile.georeference.is_some() {
+        return Err(TerrainError::InvalidConfig(format!(
+            "The georeferenced tile {} can not be mosaicked with other sources.",
+            tile.path
+        )));
+    }
+
+    let primary = MosaicSource {
+        path: tile.path.clone(),
+        size: tile.size,
+        file_format: tile.file_format,
+        resolution: 1.0,
+        priority: 0,
+        blend_width: 0.0,
+    };
+
+    let mut sources: Vec<&MosaicSource> = iter::once(&primary).chain(&tile.sources).collect();
+    sources.sort_by(|a, b| {
+        a.priority
+            .cmp(&b.priority)
+            .then(b.resolution.total_cmp(&a.resolution))
+    });
+
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+
+    for source in sources {
+        let tiles = source.tiles()?;
+        let coords: HashSet<UVec2> = tiles.iter().map(|&(_, coord)| coord).collect();
+
+        for (tile_path, coord) in tiles {
+            let (tile_image, voids) = load_filled_tile(
+                &tile_path,
+                source.file_format,
+                attachment,
+                tile.void_fill.as_ref(),
+            )?;
+            let voids = voids.filter(|_| matches!(&tile.void_fill, Some(fill) if fill.inherit));
+
+            mosaic_tile(
+                directory,
+                &tile_image,
+                attachment,
+                lod,
+                source,
+                coord,
+                &coords,
+                voids.as_deref(),
+            )?;
+
+            let tile_size = UVec2::new(tile_image.width(), tile_image.height());
+            min = min.min((coord * source.size).as_vec2() * source.resolution);
+            max = max.max((coord * source.size + tile_size).as_vec2() * source.resolution);
+        }
+    }
+
+    let first = min.floor().as_uvec2().div_floor(attachment.center_size);
+    let last = max.ceil().as_uvec2().div_ceil(attachment.center_size);
+
+    Ok((first, last))
+}
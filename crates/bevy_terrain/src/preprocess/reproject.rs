@@ -0,0 +1,221 @@
+//! Resamples source tiles from their own coordinate reference system (CRS) into the grid of the
+//! terrain, so that data in different CRSs (e.g. a UTM DTM and Web Mercator orthophotos) lines up.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    georeference::{Georeference, Transformation},
+    preprocess::{
+        file_io::{iterate_directory, load_image},
+        split::{tile_coordinate, to_categories},
+        void_fill::fill_voids,
+        TileConfig,
+    },
+    terrain_data::{AttachmentConfig, AttachmentFormat},
+    TerrainConfig,
+};
+use bevy::{math::DVec2, prelude::*, utils::HashMap};
+use image::{DynamicImage, ImageBuffer, Pixel};
+use itertools::iproduct;
+use std::fs;
+
+/// The number of samples along each edge of the source, used to determine its extent in the terrain.
+const EDGE_SAMPLES: u32 = 16;
+
+/// The source tile(s) of an attachment, which can be sampled at any source pixel.
+struct SourceTiles<P: Pixel> {
+    tiles: HashMap<UVec2, ImageBuffer<P, Vec<P::Subpixel>>>,
+    tile_size: u32,
+    size: UVec2,
+}
+
+impl<P: Pixel> SourceTiles<P> {
+    fn load(
+        tile: &TileConfig,
+        attachment: &AttachmentConfig,
+        convert: fn(DynamicImage) -> ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> TerrainResult<Self> {
+        let mut tiles = HashMap::default();
+
+        let load = |path: &str| {
+            let mut image =
+                load_image(path, tile.file_format)?.ok_or_else(|| TerrainError::MissingTile {
+                    path: path.to_string(),
+                })?;
+
+            if let Some(void_fill) = &tile.void_fill {
+                fill_voids(&mut image, void_fill, path)?;
+            }
+
+            if attachment.format.is_categorical() {
+                image = to_categories(image, path)?;
+            }
+
+            Ok(image)
+        };
+
+        let metadata = fs::metadata(&tile.path).map_err(|_| TerrainError::MissingTile {
+            path: tile.path.clone(),
+        })?;
+
+        if metadata.is_dir() {
+            for (tile_name, tile_path) in iterate_directory(&tile.path)? {
+                let coord = tile_coordinate(&tile_name).ok_or_else(|| {
+                    TerrainError::InvalidConfig(format!(
+                        "The tile {tile_path} is not named after its coordinate (name_x_y)."
+                    ))
+                })?;
+
+                tiles.insert(coord, convert(load(&tile_path)?));
+            }
+        } else {
+            tiles.insert(UVec2::ZERO, convert(load(&tile.path)?));
+        }
+
+        let size = tiles
+            .keys()
+            .fold(UVec2::ZERO, |size, &coord| size.max(coord + 1))
+            * tile.size;
+
+        Ok(Self {
+            tiles,
+            tile_size: tile.size,
+            size,
+        })
+    }
+
+    /// Returns the nearest pixel of the source tile(s), if any.
+    fn sample(&self, pixel: Vec2) -> Option<P> {
+        if pixel.x < 0.0 || pixel.y < 0.0 {
+            return None;
+        }
+
+        let pixel = pixel.as_uvec2();
+        let image = self.tiles.get(&(pixel / self.tile_size))?;
+        let local = pixel % self.tile_size;
+
+        image.get_pixel_checked(local.x, local.y).copied()
+    }
+}
+
+/// Resamples the source tile(s) into the pixel grid of the terrain at the lod.
+///
+/// Returns the resampled image and its offset in pixels of the lod.
+fn reproject<P: Pixel>(
+    config: &TerrainConfig,
+    target: &Georeference,
+    source: &Georeference,
+    tiles: &SourceTiles<P>,
+    pixel_size: f64,
+) -> TerrainResult<(ImageBuffer<P, Vec<P::Subpixel>>, UVec2)> {
+    let transformation = |from: u32, to: u32| {
+        Transformation::new(from, to).ok_or_else(|| {
+            TerrainError::InvalidConfig(format!(
+                "Can not reproject from EPSG:{from} to EPSG:{to} without the `proj` feature."
+            ))
+        })
+    };
+
+    let to_target = transformation(source.epsg, target.epsg)?;
+    let to_source = transformation(target.epsg, source.epsg)?;
+
+    // the extent of the source inside the pixel grid of the terrain
+    let edge = |t: f64| {
+        let size = tiles.size.as_dvec2();
+
+        [
+            DVec2::new(t * size.x, 0.0),
+            DVec2::new(t * size.x, size.y),
+            DVec2::new(0.0, t * size.y),
+            DVec2::new(size.x, t * size.y),
+        ]
+    };
+
+    let (min, max) = (0..=EDGE_SAMPLES)
+        .flat_map(|i| edge(i as f64 / EDGE_SAMPLES as f64))
+        .filter_map(|pixel| {
+            let projected = source.to_projected(Vec3::new(pixel.x as f32, 0.0, pixel.y as f32));
+            let local = target.to_local(to_target.convert(projected)?);
+            Some(local.as_dvec2() / pixel_size)
+        })
+        .fold(
+            (DVec2::splat(f64::MAX), DVec2::splat(f64::MIN)),
+            |(min, max), pixel| (min.min(pixel), max.max(pixel)),
+        );
+
+    let terrain_pixels = DVec2::splat(config.terrain_size as f64 / pixel_size);
+    let offset = min.clamp(DVec2::ZERO, terrain_pixels).floor().as_uvec2();
+    let end = max.clamp(DVec2::ZERO, terrain_pixels).ceil().as_uvec2();
+    let size = end.max(offset) - offset;
+
+    let mut image = ImageBuffer::new(size.x, size.y);
+
+    for (y, x) in iproduct!(0..size.y, 0..size.x) {
+        let world = ((offset + UVec2::new(x, y)).as_dvec2() + 0.5) * pixel_size;
+        let projected = target.to_projected(Vec3::new(world.x as f32, 0.0, world.y as f32));
+
+        let value = to_source
+            .convert(projected)
+            .and_then(|projected| tiles.sample(source.to_local(projected)));
+
+        if let Some(value) = value {
+            image.put_pixel(x, y, value);
+        }
+    }
+
+    Ok((image, offset))
+}
+
+/// Resamples the source tile(s) from the CRS of the tile into the pixel grid of the terrain
+/// at the lod.
+///
+/// Returns the resampled image and its offset in pixels of the lod.
+pub(crate) fn reproject_tiles(
+    config: &TerrainConfig,
+    tile: &TileConfig,
+    source: &Georeference,
+    attachment: &AttachmentConfig,
+    lod: u32,
+) -> TerrainResult<(DynamicImage, UVec2)> {
+    let target = config.georeference.as_ref().ok_or_else(|| {
+        TerrainError::InvalidConfig(
+            "Reprojecting a tile requires the terrain to have a georeference.".to_string(),
+        )
+    })?;
+
+    // the size of a pixel of the lod in world units
+    let pixel_size =
+        config.leaf_node_size as f64 / attachment.center_size as f64 * (1u64 << lod) as f64;
+
+    match attachment.format {
+        AttachmentFormat::Rgb8 => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_rgb8())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+        AttachmentFormat::Rgba8 => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_rgba8())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+        AttachmentFormat::R16 => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_luma16())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+        AttachmentFormat::Rg16 => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_luma_alpha16())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+        AttachmentFormat::R8Uint => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_luma8())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+        _ => {
+            let tiles = SourceTiles::load(tile, attachment, |image| image.to_rgb32f())?;
+            let (image, offset) = reproject(config, target, source, &tiles, pixel_size)?;
+            Ok((DynamicImage::from(image), offset))
+        }
+    }
+}
@@ -0,0 +1,466 @@
+//! Carves roads and paths into the preprocessed terrain data.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        attachment::height_node_to_minmax,
+        down_sample::{down_sample_layer, linear, minmax, Filter},
+        file_io::{format_directory, format_node_path, load_image, save_image},
+        stitch::stitch_layer,
+        BaseConfig, UVec2Utils,
+    },
+    skip_none,
+    terrain_data::AttachmentConfig,
+    TerrainConfig,
+};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use image::{DynamicImage, Luma};
+
+/// Paints a road into a splat attachment.
+#[derive(Clone, Debug)]
+pub struct RoadSplat {
+    /// The name of the splat attachment (`Rgb8`, `Rgba8` or `R16`).
+    pub attachment: String,
+    /// The channel, which stores the weight of the road layer.
+    /// The weights of the other channels are reduced accordingly.
+    /// Single channel attachments are treated as a road mask instead.
+    pub channel: usize,
+}
+
+/// A road (or path), which is carved into the terrain.
+///
+/// The surface of the road follows the smoothed terrain along its center line,
+/// without exceeding the maximum grade. Beside the road, banks are cut into or filled onto
+/// the terrain, so that the road blends into its surroundings.
+#[derive(Clone, Debug)]
+pub struct Road {
+    /// The center line of the road in the horizontal (x, z) plane.
+    pub path: Vec<Vec2>,
+    /// The width of the road surface.
+    pub width: f32,
+    /// The maximum grade (rise over run) of the road surface (e.g. 0.08 for 8 %).
+    pub max_grade: f32,
+    /// The slope (rise over run) of the cut and fill banks beside the road.
+    pub bank_slope: f32,
+    /// The maximum width of the banks on each side of the road.
+    pub bank_width: f32,
+    /// The splat attachment, which the road is painted into, if any.
+    pub splat: Option<RoadSplat>,
+}
+
+impl Road {
+    pub fn new(path: Vec<Vec2>, width: f32) -> Self {
+        Self {
+            path,
+            width,
+            max_grade: 0.1,
+            bank_slope: 1.0,
+            bank_width: width,
+            splat: None,
+        }
+    }
+
+    /// Paints the road into the channel of the splat attachment.
+    pub fn with_splat(mut self, attachment: &str, channel: usize) -> Self {
+        self.splat = Some(RoadSplat {
+            attachment: attachment.to_string(),
+            channel,
+        });
+        self
+    }
+
+    /// Returns the length of the center line.
+    fn length(&self) -> f32 {
+        self.path
+            .windows(2)
+            .map(|line| line[0].distance(line[1]))
+            .sum()
+    }
+
+    /// Returns the position on the center line at the distance from its start.
+    fn position(&self, arc_length: f32) -> Vec2 {
+        let mut remaining = arc_length;
+
+        for line in self.path.windows(2) {
+            let length = line[0].distance(line[1]);
+
+            if remaining <= length && length > 0.0 {
+                return line[0].lerp(line[1], remaining / length);
+            }
+
+            remaining -= length;
+        }
+
+        *self.path.last().unwrap()
+    }
+}
+
+/// The closest point on the center line of a road to a pixel.
+#[derive(Clone, Copy)]
+struct ClosestPoint {
+    /// The distance between the pixel and the center line.
+    distance: f32,
+    /// The distance from the start of the center line.
+    arc_length: f32,
+}
+
+/// Finds the closest point on the center line for all pixels within the extent of it.
+fn closest_points(
+    path: &[Vec2],
+    extent: f32,
+    pixel_size: f32,
+    pixel_count: u32,
+) -> HashMap<UVec2, ClosestPoint> {
+    let mut points: HashMap<UVec2, ClosestPoint> = HashMap::default();
+    let mut arc_length = 0.0;
+
+    for line in path.windows(2) {
+        let (start, end) = (line[0], line[1]);
+        let direction = end - start;
+        let length = direction.length();
+
+        let first = ((start.min(end) - extent) / pixel_size).floor().as_uvec2();
+        let last = ((start.max(end) + extent) / pixel_size)
+            .ceil()
+            .as_uvec2()
+            .min(UVec2::splat(pixel_count));
+
+        for (x, y) in first.product(last) {
+            let position = (Vec2::new(x as f32, y as f32) + 0.5) * pixel_size;
+            let t = if length > 0.0 {
+                ((position - start).dot(direction) / (length * length)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+
+            let point = ClosestPoint {
+                distance: position.distance(start + t * direction),
+                arc_length: arc_length + t * length,
+            };
+
+            if point.distance > extent {
+                continue;
+            }
+
+            points
+                .entry(UVec2::new(x, y))
+                .and_modify(|closest| {
+                    if point.distance < closest.distance {
+                        *closest = point;
+                    }
+                })
+                .or_insert(point);
+        }
+
+        arc_length += length;
+    }
+
+    points
+}
+
+/// Lazily loads the nodes of lod zero, in order to modify them pixel by pixel.
+struct NodeCache<'a> {
+    directory: String,
+    attachment: &'a AttachmentConfig,
+    nodes: HashMap<UVec2, Option<DynamicImage>>,
+    changed_nodes: HashSet<UVec2>,
+    /// The first error, which occurred while loading a node.
+    error: Option<TerrainError>,
+}
+
+impl<'a> NodeCache<'a> {
+    fn new(directory: String, attachment: &'a AttachmentConfig) -> Self {
+        Self {
+            directory,
+            attachment,
+            nodes: default(),
+            changed_nodes: default(),
+            error: None,
+        }
+    }
+
+    /// Returns the node containing the pixel (of lod zero) and the coordinate inside of it.
+    fn node(&mut self, pixel: UVec2) -> Option<(UVec2, &mut DynamicImage, u32, u32)> {
+        let NodeCache {
+            directory,
+            attachment,
+            nodes,
+            error,
+            ..
+        } = self;
+
+        let node = pixel / attachment.center_size;
+        let local = pixel % attachment.center_size + attachment.border_size;
+
+        let node_image = nodes
+            .entry(node)
+            .or_insert_with(|| {
+                let node_path = format_node_path(directory, 0, node.x, node.y);
+                load_image(&node_path, attachment.file_format).unwrap_or_else(|load_error| {
+                    error.get_or_insert(load_error);
+                    None
+                })
+            })
+            .as_mut()?;
+
+        Some((node, node_image, local.x, local.y))
+    }
+
+    /// Returns the normalized height of the pixel.
+    fn height(&mut self, pixel: UVec2) -> Option<f32> {
+        let (_, node_image, x, y) = self.node(pixel)?;
+        let value = node_image.as_luma16()?.get_pixel(x, y).0[0];
+
+        Some(value as f32 / u16::MAX as f32)
+    }
+
+    /// Sets the normalized height of the pixel.
+    fn set_height(&mut self, pixel: UVec2, height: f32) {
+        if let Some((node, node_image, x, y)) = self.node(pixel) {
+            let value = (height.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+
+            node_image
+                .as_mut_luma16()
+                .unwrap()
+                .put_pixel(x, y, Luma([value]));
+            self.changed_nodes.insert(node);
+        }
+    }
+
+    /// Blends the weight of the road layer into the splat weights of the pixel.
+    fn paint(&mut self, pixel: UVec2, channel: usize, weight: f32) {
+        let blend = |value: &mut u8, target: f32| {
+            *value = (*value as f32 * (1.0 - weight) + target * weight) as u8;
+        };
+
+        if let Some((node, node_image, x, y)) = self.node(pixel) {
+            match node_image {
+                DynamicImage::ImageRgb8(image) => {
+                    for (index, value) in image.get_pixel_mut(x, y).0.iter_mut().enumerate() {
+                        blend(value, if index == channel { 255.0 } else { 0.0 });
+                    }
+                }
+                DynamicImage::ImageRgba8(image) => {
+                    for (index, value) in image.get_pixel_mut(x, y).0.iter_mut().enumerate() {
+                        blend(value, if index == channel { 255.0 } else { 0.0 });
+                    }
+                }
+                DynamicImage::ImageLuma16(image) => {
+                    let value = &mut image.get_pixel_mut(x, y).0[0];
+                    *value = (*value).max((weight * u16::MAX as f32) as u16);
+                }
+                _ => return,
+            }
+
+            self.changed_nodes.insert(node);
+        }
+    }
+
+    /// Saves all modified nodes and returns the first and last (exclusive) of them.
+    ///
+    /// Fails without saving, if any node could not be loaded.
+    fn save(self) -> TerrainResult<Option<(UVec2, UVec2)>> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut first = UVec2::splat(u32::MAX);
+        let mut last = UVec2::ZERO;
+
+        for node in &self.changed_nodes {
+            let node_image = self.nodes[node].as_ref().unwrap();
+            let node_path = format_node_path(&self.directory, 0, node.x, node.y);
+
+            save_image(&node_path, node_image, self.attachment)?;
+
+            first = first.min(*node);
+            last = last.max(*node + 1);
+        }
+
+        Ok((!self.changed_nodes.is_empty()).then_some((first, last)))
+    }
+}
+
+/// Stitches the modified nodes of lod zero and regenerates the coarser lods above them.
+fn update_lods(
+    config: &TerrainConfig,
+    directory: &str,
+    attachment: &AttachmentConfig,
+    filter: Filter,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<()> {
+    // the borders of the adjacent nodes have to be stitched as well
+    let expand = |first: UVec2, last: UVec2| (first.max(UVec2::ONE) - 1, last + 1);
+
+    let (stitch_first, stitch_last) = expand(first, last);
+    stitch_layer(directory, attachment, 0, stitch_first, stitch_last)?;
+
+    let (mut first, mut last) = (first, last);
+
+    for lod in 1..config.lod_count {
+        first = first.div_floor(2);
+        last = last.div_ceil(2);
+
+        down_sample_layer(filter, directory, attachment, lod, first, last)?;
+
+        let (stitch_first, stitch_last) = expand(first, last);
+        stitch_layer(directory, attachment, lod, stitch_first, stitch_last)?;
+    }
+
+    Ok(())
+}
+
+/// Computes the height of the road surface along the center line, sampled every world unit.
+///
+/// The terrain height is smoothed over the width of the road and
+/// then limited by the maximum grade in both directions.
+fn road_profile(road: &Road, heights: &mut NodeCache, pixel_size: f32, height: f32) -> Vec<f32> {
+    let sample_count = road.length().ceil() as usize + 1;
+
+    let mut previous = 0.0;
+    let samples: Vec<f32> = (0..sample_count)
+        .map(|index| {
+            let pixel = (road.position(index as f32) / pixel_size).as_uvec2();
+            previous = heights
+                .height(pixel)
+                .map_or(previous, |value| value * height);
+            previous
+        })
+        .collect();
+
+    let radius = (0.5 * road.width).ceil() as usize;
+
+    let mut profile: Vec<f32> = (0..sample_count)
+        .map(|index| {
+            let window =
+                &samples[index.saturating_sub(radius)..(index + radius + 1).min(sample_count)];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect();
+
+    for index in 1..sample_count {
+        let previous = profile[index - 1];
+        profile[index] = profile[index].clamp(previous - road.max_grade, previous + road.max_grade);
+    }
+
+    for index in (0..sample_count - 1).rev() {
+        let next = profile[index + 1];
+        profile[index] = profile[index].clamp(next - road.max_grade, next + road.max_grade);
+    }
+
+    profile
+}
+
+/// Linearly interpolates the profile at the distance from the start of the center line.
+fn sample_profile(profile: &[f32], arc_length: f32) -> f32 {
+    let index = (arc_length.floor() as usize).min(profile.len() - 1);
+    let next = (index + 1).min(profile.len() - 1);
+
+    profile[index] + (profile[next] - profile[index]) * arc_length.fract()
+}
+
+/// Carves the road into the height data and paints it into the splat attachment, if configured.
+///
+/// The terrain has to be preprocessed already. All lods of the height, minmax and splat
+/// attachments, which are affected by the road, are updated on disk.
+pub fn carve_road(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    splat_attachment: Option<&AttachmentConfig>,
+    road: &Road,
+) -> TerrainResult<()> {
+    if road.path.len() < 2 {
+        warn!("A road requires at least two points.");
+        return Ok(());
+    }
+
+    let height_attachment = base.height_attachment();
+    let minmax_attachment = base.minmax_attachment();
+    let height_directory = format_directory(&config.path, "height");
+    let minmax_directory = format_directory(&config.path, "minmax");
+
+    let pixel_size = config.leaf_node_size as f32 / height_attachment.center_size as f32;
+    let pixel_count = (config.terrain_size as f32 / pixel_size).ceil() as u32;
+
+    let mut heights = NodeCache::new(height_directory.clone(), &height_attachment);
+    let profile = road_profile(road, &mut heights, pixel_size, config.height);
+
+    let extent = 0.5 * road.width + road.bank_width;
+
+    for (pixel, closest) in closest_points(&road.path, extent, pixel_size, pixel_count) {
+        let terrain_height = skip_none!(heights.height(pixel)) * config.height;
+        let road_height = sample_profile(&profile, closest.arc_length);
+        let edge_distance = closest.distance - 0.5 * road.width;
+
+        let height = if edge_distance <= 0.0 {
+            road_height
+        } else {
+            // cut into higher and fill up lower terrain, up to the slope of the banks
+            let limit = edge_distance * road.bank_slope;
+            terrain_height.clamp(road_height - limit, road_height + limit)
+        };
+
+        heights.set_height(pixel, height / config.height);
+    }
+
+    if let Some((first, last)) = heights.save()? {
+        update_lods(
+            config,
+            &height_directory,
+            &height_attachment,
+            linear,
+            first,
+            last,
+        )?;
+
+        // the minmax of lod zero is regenerated including the borders of the adjacent nodes
+        for (x, y) in (first.max(UVec2::ONE) - 1).product(last + 1) {
+            let height_path = format_node_path(&height_directory, 0, x, y);
+            let height_image = skip_none!(load_image(&height_path, height_attachment.file_format)?);
+            let minmax_path = format_node_path(&minmax_directory, 0, x, y);
+
+            save_image(
+                &minmax_path,
+                &height_node_to_minmax(&height_image),
+                &minmax_attachment,
+            )?;
+        }
+
+        update_lods(
+            config,
+            &minmax_directory,
+            &minmax_attachment,
+            minmax,
+            first,
+            last,
+        )?;
+    }
+
+    if let (Some(splat), Some(attachment)) = (&road.splat, splat_attachment) {
+        let directory = format_directory(&config.path, &attachment.name);
+        let pixel_size = config.leaf_node_size as f32 / attachment.center_size as f32;
+        let pixel_count = (config.terrain_size as f32 / pixel_size).ceil() as u32;
+
+        let mut splat_nodes = NodeCache::new(directory.clone(), attachment);
+        let extent = 0.5 * road.width + pixel_size;
+
+        for (pixel, closest) in closest_points(&road.path, extent, pixel_size, pixel_count) {
+            // anti-aliases the edge of the road
+            let weight = ((0.5 * road.width - closest.distance) / pixel_size + 0.5).clamp(0.0, 1.0);
+
+            if weight > 0.0 {
+                splat_nodes.paint(pixel, splat.channel, weight);
+            }
+        }
+
+        if let Some((first, last)) = splat_nodes.save()? {
+            update_lods(config, &directory, attachment, linear, first, last)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,13 +1,19 @@
 use crate::{
+    error::{TerrainError, TerrainResult},
     preprocess::{
+        feather::{feather_tile, TileEdges},
         file_io::{
             format_node_path, iterate_directory, load_image, load_or_create_node, save_image,
         },
-        TileConfig, UVec2Utils,
+        mosaic::mosaic_tiles,
+        reproject::reproject_tiles,
+        void_fill::{fill_voids, VoidFilling},
+        R8Image, TileConfig, UVec2Utils,
     },
-    terrain_data::{AttachmentConfig, AttachmentFormat},
+    terrain_data::{AttachmentConfig, AttachmentFormat, FileFormat},
+    TerrainConfig,
 };
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashMap};
 use image::{
     imageops::{self},
     DynamicImage,
@@ -21,8 +27,10 @@ fn tile_to_node(
     coord: UVec2,
     offset: UVec2,
 ) {
-    let x = (offset.x + attachment.border_size) as i64 - (coord.x * attachment.center_size) as i64;
-    let y = (offset.y + attachment.border_size) as i64 - (coord.y * attachment.center_size) as i64;
+    let x =
+        (offset.x + attachment.border_size) as i64 - coord.x as i64 * attachment.center_size as i64;
+    let y =
+        (offset.y + attachment.border_size) as i64 - coord.y as i64 * attachment.center_size as i64;
 
     match attachment.format {
         AttachmentFormat::Rgb8 => imageops::replace(
@@ -49,57 +57,250 @@ fn tile_to_node(
             x,
             y,
         ),
+        AttachmentFormat::R8Uint => imageops::replace(
+            node_image.as_mut_luma8().unwrap(),
+            tile_image.as_luma8().unwrap(),
+            x,
+            y,
+        ),
+        _ => imageops::replace(
+            node_image.as_mut_rgb32f().unwrap(),
+            tile_image.as_rgb32f().unwrap(),
+            x,
+            y,
+        ),
+    };
+}
+
+/// Loads the source tile and checks, whether it has the format of the attachment.
+///
+/// Tiles of floating point attachments are converted, so that they can be stored in any
+/// format (e.g. 32 bit float TIFFs or normalized 16 bit PNGs).
+/// Tiles of categorical attachments are converted as well (see [`to_categories`]).
+pub(crate) fn load_tile(
+    path: &str,
+    file_format: FileFormat,
+    attachment: &AttachmentConfig,
+) -> TerrainResult<DynamicImage> {
+    let mut tile_image =
+        load_image(path, file_format)?.ok_or_else(|| TerrainError::MissingTile {
+            path: path.to_string(),
+        })?;
+
+    if attachment.format.is_float() {
+        tile_image = DynamicImage::from(tile_image.to_rgb32f());
+    }
+
+    if attachment.format.is_categorical() {
+        tile_image = to_categories(tile_image, path)?;
+    }
+
+    check_format(&tile_image, attachment, path)?;
+
+    Ok(tile_image)
+}
+
+/// Converts the single channel source tile of a categorical attachment (e.g. a CORINE
+/// landcover raster) into an 8 bit image of its categories.
+///
+/// Unlike the conversions of the image crate, the values are kept as they are instead of being
+/// rescaled, thus 16 bit and floating point rasters may store categories up to 255.
+pub(crate) fn to_categories(tile_image: DynamicImage, path: &str) -> TerrainResult<DynamicImage> {
+    if tile_image.as_luma8().is_some() {
+        return Ok(tile_image);
+    }
+
+    let values: Vec<f32> = match &tile_image {
+        DynamicImage::ImageLuma16(image) => image.pixels().map(|p| p.0[0] as f32).collect(),
+        DynamicImage::ImageRgb32F(image) => image.pixels().map(|p| p.0[0]).collect(),
+        _ => {
+            return Err(TerrainError::malformed(
+                path,
+                "The categories of the tile have to be stored in a single channel.",
+            ))
+        }
+    };
+
+    if values
+        .iter()
+        .any(|value| !(0.0..=u8::MAX as f32).contains(value))
+    {
+        return Err(TerrainError::malformed(
+            path,
+            "The categories of the tile have to be in the range of 0 to 255.",
+        ));
+    }
+
+    let data = values
+        .into_iter()
+        .map(|value| value.round() as u8)
+        .collect();
+
+    Ok(DynamicImage::from(
+        R8Image::from_raw(tile_image.width(), tile_image.height(), data).unwrap(),
+    ))
+}
+
+/// Loads the source tile and fills its voids, if configured.
+///
+/// Returns the pixels, which were void, as well.
+pub(crate) fn load_filled_tile(
+    path: &str,
+    file_format: FileFormat,
+    attachment: &AttachmentConfig,
+    void_fill: Option<&VoidFilling>,
+) -> TerrainResult<(DynamicImage, Option<Vec<bool>>)> {
+    let mut tile_image = load_tile(path, file_format, attachment)?;
+
+    let voids = match void_fill {
+        Some(void_fill) => Some(fill_voids(&mut tile_image, void_fill, path)?),
+        None => None,
+    };
+
+    Ok((tile_image, voids))
+}
+
+/// Checks, whether the source tile has the format of the attachment.
+fn check_format(
+    tile_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+    path: &str,
+) -> TerrainResult<()> {
+    let matches_format = match attachment.format {
+        AttachmentFormat::Rgb8 => tile_image.as_rgb8().is_some(),
+        AttachmentFormat::Rgba8 => tile_image.as_rgba8().is_some(),
+        AttachmentFormat::R16 => tile_image.as_luma16().is_some(),
+        AttachmentFormat::Rg16 => tile_image.as_luma_alpha16().is_some(),
+        AttachmentFormat::R8Uint => tile_image.as_luma8().is_some(),
+        _ => tile_image.as_rgb32f().is_some(),
     };
+
+    if !matches_format {
+        return Err(TerrainError::malformed(
+            path,
+            format!(
+                "The tile does not have the {:?} format of the attachment {}.",
+                attachment.format, attachment.name
+            ),
+        ));
+    }
+
+    Ok(())
 }
 
-fn split_tile(directory: &str, tile: &TileConfig, attachment: &AttachmentConfig, offset: UVec2) {
-    let tile_image = load_image(&tile.path, tile.file_format).expect("Could not load tile.");
+fn split_image(
+    directory: &str,
+    tile_image: &DynamicImage,
+    attachment: &AttachmentConfig,
+    lod: u32,
+    offset: UVec2,
+) -> TerrainResult<()> {
+    let size = UVec2::new(tile_image.width(), tile_image.height());
 
     // first and last node coordinate
     let first = offset.div_floor(attachment.center_size);
-    let last = (offset + tile.size + attachment.border_size).div_ceil(attachment.center_size);
+    let last = (offset + size + attachment.border_size).div_ceil(attachment.center_size);
 
     for (x, y) in first.product(last) {
-        let node_path = format_node_path(directory, 0, x, y);
+        let node_path = format_node_path(directory, lod, x, y);
 
-        let mut node_image = load_or_create_node(&node_path, attachment);
+        let mut node_image = load_or_create_node(&node_path, attachment)?;
 
         tile_to_node(
             &mut node_image,
-            &tile_image,
+            tile_image,
             attachment,
             UVec2::new(x, y),
             offset,
         );
 
-        save_image(&node_path, &node_image, attachment);
+        save_image(&node_path, &node_image, attachment)?;
     }
+
+    Ok(())
 }
 
+/// Splits the source tile(s) into nodes of the lod.
+///
+/// Tiles with a georeference are reprojected into the grid of the terrain beforehand.
+/// Tiles with additional sources are mosaicked with them.
 pub(crate) fn split_tiles(
+    config: &TerrainConfig,
     directory: &str,
     tile: &TileConfig,
     attachment: &AttachmentConfig,
-) -> (UVec2, UVec2) {
-    let (offset, size) = if fs::metadata(&tile.path).unwrap().is_dir() {
+    lod: u32,
+) -> TerrainResult<(UVec2, UVec2)> {
+    if !tile.sources.is_empty() {
+        return mosaic_tiles(directory, tile, attachment, lod);
+    }
+
+    let metadata = fs::metadata(&tile.path).map_err(|_| TerrainError::MissingTile {
+        path: tile.path.clone(),
+    })?;
+
+    let (offset, size) = if let Some(georeference) = &tile.georeference {
+        let (tile_image, offset) = reproject_tiles(config, tile, georeference, attachment, lod)?;
+        split_image(directory, &tile_image, attachment, lod, offset)?;
+
+        (offset, UVec2::new(tile_image.width(), tile_image.height()))
+    } else if metadata.is_dir() {
         let mut min_pos = UVec2::splat(u32::MAX);
         let mut max_pos = UVec2::splat(u32::MIN);
 
-        for (tile_name, tile_path) in iterate_directory(&tile.path) {
-            let mut parts = tile_name.split('_');
-            parts.next();
+        let tiles = iterate_directory(&tile.path)?
+            .map(|(tile_name, tile_path)| {
+                let coord = tile_coordinate(&tile_name).ok_or_else(|| {
+                    TerrainError::InvalidConfig(format!(
+                        "The tile {tile_path} is not named after its coordinate (name_x_y)."
+                    ))
+                })?;
+
+                Ok((tile_path, coord))
+            })
+            .collect::<TerrainResult<Vec<_>>>()?;
 
-            let coord = UVec2::new(
-                parts.next().unwrap().parse::<u32>().unwrap(),
-                parts.next().unwrap().parse::<u32>().unwrap(),
-            );
+        // categories must not be cross-blended
+        let feather = tile.feather_width > 0 && !attachment.format.is_categorical();
 
-            let tile = TileConfig {
-                path: tile_path,
-                ..*tile
-            };
+        // the edges of all tiles are gathered beforehand, so that the seams are feathered from both sides
+        let edges = if feather {
+            tiles
+                .iter()
+                .map(|(tile_path, coord)| {
+                    let (tile_image, _) = load_filled_tile(
+                        tile_path,
+                        tile.file_format,
+                        attachment,
+                        tile.void_fill.as_ref(),
+                    )?;
 
-            split_tile(directory, &tile, attachment, coord * tile.size);
+                    Ok((*coord, TileEdges::new(&tile_image, attachment.format)))
+                })
+                .collect::<TerrainResult<HashMap<_, _>>>()?
+        } else {
+            HashMap::default()
+        };
+
+        for (tile_path, coord) in tiles {
+            let (mut tile_image, _) = load_filled_tile(
+                &tile_path,
+                tile.file_format,
+                attachment,
+                tile.void_fill.as_ref(),
+            )?;
+
+            if feather {
+                tile_image = feather_tile(
+                    &tile_image,
+                    attachment.format,
+                    coord,
+                    &edges,
+                    tile.feather_width,
+                );
+            }
+
+            split_image(directory, &tile_image, attachment, lod, coord * tile.size)?;
 
             min_pos = min_pos.min(coord);
             max_pos = max_pos.max(coord);
@@ -110,7 +311,13 @@ pub(crate) fn split_tiles(
 
         (offset, size)
     } else {
-        split_tile(directory, tile, attachment, UVec2::splat(0));
+        let (tile_image, _) = load_filled_tile(
+            &tile.path,
+            tile.file_format,
+            attachment,
+            tile.void_fill.as_ref(),
+        )?;
+        split_image(directory, &tile_image, attachment, lod, UVec2::splat(0))?;
 
         (UVec2::splat(0), UVec2::splat(tile.size))
     };
@@ -118,5 +325,16 @@ pub(crate) fn split_tiles(
     let first = offset.div_floor(attachment.center_size);
     let last = (offset + size).div_ceil(attachment.center_size);
 
-    (first, last)
+    Ok((first, last))
+}
+
+/// Parses the coordinate of a source tile from its name (`name_x_y`).
+pub(crate) fn tile_coordinate(tile_name: &str) -> Option<UVec2> {
+    let mut parts = tile_name.split('_');
+    parts.next();
+
+    Some(UVec2::new(
+        parts.next()?.parse::<u32>().ok()?,
+        parts.next()?.parse::<u32>().ok()?,
+    ))
 }
@@ -1,4 +1,5 @@
 use crate::{
+    error::TerrainResult,
     preprocess::{
         file_io::{format_node_path, load_image, save_image},
         UVec2Utils,
@@ -65,6 +66,22 @@ fn stitch(
             let node_image = node_image.as_mut_luma_alpha16().unwrap();
             let adjacent_image = adjacent_image.as_luma_alpha16().unwrap();
 
+            for (x1, y1, x2, y2) in iter {
+                node_image.put_pixel(x1, y1, *adjacent_image.get_pixel(x2, y2));
+            }
+        }
+        AttachmentFormat::R8Uint => {
+            let node_image = node_image.as_mut_luma8().unwrap();
+            let adjacent_image = adjacent_image.as_luma8().unwrap();
+
+            for (x1, y1, x2, y2) in iter {
+                node_image.put_pixel(x1, y1, *adjacent_image.get_pixel(x2, y2));
+            }
+        }
+        _ => {
+            let node_image = node_image.as_mut_rgb32f().unwrap();
+            let adjacent_image = adjacent_image.as_rgb32f().unwrap();
+
             for (x1, y1, x2, y2) in iter {
                 node_image.put_pixel(x1, y1, *adjacent_image.get_pixel(x2, y2));
             }
@@ -118,6 +135,20 @@ fn extend(node_image: &mut DynamicImage, attachment: &AttachmentConfig, directio
         AttachmentFormat::Rg16 => {
             let node_image = node_image.as_mut_luma_alpha16().unwrap();
 
+            for (x1, y1, x2, y2) in iter {
+                node_image.put_pixel(x1, y1, *node_image.get_pixel(x2, y2));
+            }
+        }
+        AttachmentFormat::R8Uint => {
+            let node_image = node_image.as_mut_luma8().unwrap();
+
+            for (x1, y1, x2, y2) in iter {
+                node_image.put_pixel(x1, y1, *node_image.get_pixel(x2, y2));
+            }
+        }
+        _ => {
+            let node_image = node_image.as_mut_rgb32f().unwrap();
+
             for (x1, y1, x2, y2) in iter {
                 node_image.put_pixel(x1, y1, *node_image.get_pixel(x2, y2));
             }
@@ -131,14 +162,14 @@ pub(crate) fn stitch_layer(
     lod: u32,
     first: UVec2,
     last: UVec2,
-) {
+) -> TerrainResult<()> {
     if attachment.border_size == 0 {
-        return;
+        return Ok(());
     }
 
     for (x, y) in first.product(last) {
         let node_path = format_node_path(directory, lod, x, y);
-        let mut node_image = skip_none!(load_image(&node_path, attachment.file_format));
+        let mut node_image = skip_none!(load_image(&node_path, attachment.file_format)?);
 
         for direction in iproduct!(-1..=1, -1..=1) {
             if direction == (0, 0) {
@@ -150,13 +181,15 @@ pub(crate) fn stitch_layer(
 
             let adjacent_path = format_node_path(directory, lod, x as u32, y as u32);
 
-            if let Some(adjacent_image) = load_image(&adjacent_path, attachment.file_format) {
+            if let Some(adjacent_image) = load_image(&adjacent_path, attachment.file_format)? {
                 stitch(&mut node_image, &adjacent_image, attachment, direction);
             } else {
                 extend(&mut node_image, attachment, direction);
             }
         }
 
-        save_image(&node_path, &node_image, attachment);
+        save_image(&node_path, &node_image, attachment)?;
     }
+
+    Ok(())
 }
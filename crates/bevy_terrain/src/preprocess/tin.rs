@@ -0,0 +1,287 @@
+//! Generates pre-simplified triangulated irregular networks (TINs) from the height nodes.
+//!
+//! This is an experimental alternative to the uniform tile grid for memory-constrained targets.
+//! Instead of streaming the height data into the node atlas and displacing a dense grid,
+//! each node is stored as a small mesh, which is simplified up to a maximum height error using
+//! the right-triangulated irregular network (RTIN) algorithm.
+//! The meshes of different lods are not stitched together, which may leave small cracks
+//! between adjacent nodes of different lods.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        derivative::height_nodes,
+        file_io::{format_directory, format_node_path, iterate_directory, reset_directory},
+        BaseConfig, R16Image,
+    },
+    terrain_data::{NodeCoordinate, NodeId},
+    TerrainConfig,
+};
+use anyhow::Result;
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+use bincode::{config, Decode, Encode};
+use std::{collections::HashMap, fs};
+
+/// The simplified mesh of a single node.
+#[derive(Encode, Decode, Clone, Debug, Default)]
+pub struct TinMesh {
+    /// The positions of the vertices relative to the origin of the terrain.
+    pub positions: Vec<[f32; 3]>,
+    /// The counter-clockwise triangle list.
+    pub indices: Vec<u32>,
+}
+
+impl TinMesh {
+    pub fn decode_alloc(encoded: &[u8]) -> Result<Self> {
+        let decoded = bincode::decode_from_slice(encoded, config::standard())?;
+        Ok(decoded.0)
+    }
+
+    pub fn encode_alloc(&self) -> Result<Vec<u8>> {
+        let encoded = bincode::encode_to_vec(self, config::standard())?;
+        Ok(encoded)
+    }
+
+    /// Converts the node into a mesh with smooth normals, which can be rendered by the pbr pipeline.
+    pub fn to_mesh(&self) -> Mesh {
+        let mut normals = vec![Vec3::ZERO; self.positions.len()];
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.positions[triangle[i] as usize]));
+            let normal = (b - a).cross(c - a);
+
+            for &index in triangle {
+                normals[index as usize] += normal;
+            }
+        }
+
+        let normals: Vec<[f32; 3]> = normals
+            .into_iter()
+            .map(|normal| normal.normalize_or_zero().into())
+            .collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions.clone());
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_indices(Some(Indices::U32(self.indices.clone())));
+        mesh
+    }
+}
+
+/// Loads the simplified meshes of all nodes of the lod.
+/// The terrain has to be preprocessed with [`Preprocessor::add_tin`](super::Preprocessor::add_tin).
+pub fn load_tin_meshes(config: &TerrainConfig, lod: u32) -> Vec<(NodeId, TinMesh)> {
+    let directory = format_directory(&config.path, "tin");
+
+    iterate_directory(&directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, path)| {
+            let node_id = name.parse::<NodeId>().ok()?;
+
+            if NodeCoordinate::from(node_id).lod != lod {
+                return None;
+            }
+
+            let mesh = TinMesh::decode_alloc(&fs::read(path).ok()?).ok()?;
+
+            Some((node_id, mesh))
+        })
+        .collect()
+}
+
+/// Stores the maximum error of each vertex of a grid with the size of a power of two plus one.
+///
+/// The error of a vertex is the height difference between the grid and the edge of the parent
+/// triangle it splits, propagated upwards so that the error of a parent covers its children.
+struct RtinErrors {
+    size: usize,
+    errors: Vec<f32>,
+}
+
+impl RtinErrors {
+    fn new(heights: &[f32], size: usize) -> Self {
+        let tile = size - 1;
+        let triangle_count = tile * tile * 2 - 2;
+        let parent_count = triangle_count - tile * tile;
+
+        let mut errors = vec![0.0; size * size];
+
+        // iterate all triangles from the smallest to the largest
+        for index in (0..triangle_count).rev() {
+            let mut id = index + 2;
+
+            let (mut ax, mut ay, mut bx, mut by, mut cx, mut cy) = if id & 1 == 1 {
+                (0, 0, tile, tile, tile, 0)
+            } else {
+                (tile, tile, 0, 0, 0, tile)
+            };
+
+            loop {
+                id >>= 1;
+
+                if id <= 1 {
+                    break;
+                }
+
+                let (mx, my) = ((ax + bx) >> 1, (ay + by) >> 1);
+
+                if id & 1 == 1 {
+                    (bx, by, ax, ay) = (ax, ay, cx, cy);
+                } else {
+                    (ax, ay, bx, by) = (bx, by, cx, cy);
+                }
+
+                (cx, cy) = (mx, my);
+            }
+
+            let (mx, my) = ((ax + bx) >> 1, (ay + by) >> 1);
+            let middle = my * size + mx;
+
+            let interpolated = (heights[ay * size + ax] + heights[by * size + bx]) / 2.0;
+            let mut error = (interpolated - heights[middle]).abs().max(errors[middle]);
+
+            if index < parent_count {
+                let left = ((ay + cy) >> 1) * size + ((ax + cx) >> 1);
+                let right = ((by + cy) >> 1) * size + ((bx + cx) >> 1);
+
+                error = error.max(errors[left]).max(errors[right]);
+            }
+
+            errors[middle] = error;
+        }
+
+        Self { size, errors }
+    }
+
+    /// Collects the triangles, whose error is below the maximum error.
+    fn triangulate(&self, max_error: f32) -> (Vec<(usize, usize)>, Vec<u32>) {
+        let tile = self.size - 1;
+
+        let mut vertices = Vec::new();
+        let mut vertex_indices = HashMap::new();
+        let mut indices = Vec::new();
+
+        let mut stack = vec![(0, 0, tile, tile, tile, 0), (tile, tile, 0, 0, 0, tile)];
+
+        while let Some((ax, ay, bx, by, cx, cy)) = stack.pop() {
+            let (mx, my) = ((ax + bx) >> 1, (ay + by) >> 1);
+
+            if ax.abs_diff(cx) + ay.abs_diff(cy) > 1 && self.errors[my * self.size + mx] > max_error
+            {
+                stack.push((cx, cy, ax, ay, mx, my));
+                stack.push((bx, by, cx, cy, mx, my));
+            } else {
+                for vertex in [(ax, ay), (bx, by), (cx, cy)] {
+                    let index = *vertex_indices.entry(vertex).or_insert_with(|| {
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    });
+
+                    indices.push(index);
+                }
+            }
+        }
+
+        (vertices, indices)
+    }
+}
+
+/// Bilinearly samples the height of the node at the pixel position.
+fn sample_height(height_image: &R16Image, position: Vec2) -> f32 {
+    let max = height_image.width() as f32 - 1.0;
+    let position = position.clamp(Vec2::ZERO, Vec2::splat(max));
+    let (origin, t) = (position.floor(), position.fract());
+
+    let value = |x: f32, y: f32| {
+        let (x, y) = (
+            (origin.x + x).min(max) as u32,
+            (origin.y + y).min(max) as u32,
+        );
+        height_image.get_pixel(x, y).0[0] as f32 / u16::MAX as f32
+    };
+
+    let top = value(0.0, 0.0) * (1.0 - t.x) + value(1.0, 0.0) * t.x;
+    let bottom = value(0.0, 1.0) * (1.0 - t.x) + value(1.0, 1.0) * t.x;
+
+    top * (1.0 - t.y) + bottom * t.y
+}
+
+/// Simplifies the center of the height node into a mesh.
+fn simplify_node(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    coordinate: &NodeCoordinate,
+    height_image: &R16Image,
+    max_error: f32,
+) -> TinMesh {
+    let height_attachment = base.height_attachment();
+    let (border_size, center_size) = (height_attachment.border_size, height_attachment.center_size);
+
+    // the rtin requires a grid with a size of a power of two plus one
+    let tile: u32 = 1 << (31 - center_size.leading_zeros());
+    let size = tile as usize + 1;
+
+    let node_size = (center_size << coordinate.lod) as f32;
+    let node_origin = Vec2::new(coordinate.x as f32, coordinate.y as f32) * node_size;
+
+    let heights: Vec<f32> = (0..size * size)
+        .map(|index| {
+            let uv = Vec2::new((index % size) as f32, (index / size) as f32) / tile as f32;
+            let position = border_size as f32 + uv * center_size as f32 - 0.5;
+
+            sample_height(height_image, position) * config.height
+        })
+        .collect();
+
+    let (vertices, indices) = RtinErrors::new(&heights, size).triangulate(max_error);
+
+    let positions = vertices
+        .into_iter()
+        .map(|(x, y)| {
+            let local_position = Vec2::new(x as f32, y as f32) / tile as f32 * node_size;
+            let world_position = node_origin + local_position;
+
+            [world_position.x, heights[y * size + x], world_position.y]
+        })
+        .collect();
+
+    TinMesh { positions, indices }
+}
+
+/// Generates the simplified meshes of all nodes of the terrain.
+///
+/// The maximum error is measured in world units.
+pub(crate) fn preprocess_tin(
+    config: &TerrainConfig,
+    base: &BaseConfig,
+    max_error: f32,
+) -> TerrainResult<()> {
+    let height_attachment = base.height_attachment();
+    let directory = format_directory(&config.path, "tin");
+
+    reset_directory(&directory)?;
+
+    for lod in 0..config.lod_count {
+        // coarser lods tolerate larger errors, since they are only visible from further away
+        let max_error = max_error * (1 << lod) as f32;
+
+        for node in height_nodes(config, &height_attachment, lod)? {
+            let (coordinate, height_image) = node?;
+            let mesh = simplify_node(config, base, &coordinate, &height_image, max_error);
+            let path = format_node_path(&directory, lod, coordinate.x, coordinate.y);
+
+            let path = format!("{path}.bin");
+            let encoded = mesh
+                .encode_alloc()
+                .map_err(|error| TerrainError::malformed(&path, error))?;
+
+            fs::write(&path, encoded).map_err(TerrainError::io(path))?;
+        }
+    }
+
+    Ok(())
+}
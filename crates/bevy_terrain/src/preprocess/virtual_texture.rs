@@ -0,0 +1,64 @@
+//! Cuts the nodes of an imagery attachment into the pages of a
+//! [`TerrainVirtualTexture`](crate::terrain_data::virtual_texture::TerrainVirtualTexture).
+
+use crate::{
+    error::TerrainResult,
+    preprocess::{
+        attachment::preprocess_attachment,
+        file_io::{
+            format_directory, format_node_path, iterate_directory, load_image, reset_directory,
+            save_image,
+        },
+        TileConfig,
+    },
+    skip_none,
+    terrain_data::{virtual_texture::VirtualTextureConfig, NodeCoordinate, NodeId},
+    TerrainConfig,
+};
+use itertools::iproduct;
+
+/// Preprocesses the attachment of the virtual texture like any other attachment and
+/// then cuts each node into pages, which share the border of the node.
+pub(crate) fn preprocess_virtual_texture(
+    config: &TerrainConfig,
+    tile: &TileConfig,
+    virtual_texture: &VirtualTextureConfig,
+) -> TerrainResult<()> {
+    let attachment = &virtual_texture.attachment;
+    let page_attachment = virtual_texture.page_attachment();
+
+    preprocess_attachment(config, tile, attachment)?;
+
+    let node_directory = format_directory(&config.path, &attachment.name);
+    let page_directory = format_directory(&config.path, &page_attachment.name);
+
+    reset_directory(&page_directory)?;
+
+    let page_size = virtual_texture.page_size;
+    let pages_per_node = virtual_texture.pages_per_node();
+
+    for (node_name, node_path) in iterate_directory(&node_directory)? {
+        let node = NodeCoordinate::from(skip_none!(node_name.parse::<NodeId>().ok()));
+        let node_image = skip_none!(load_image(&node_path, attachment.file_format)?);
+
+        for (x, y) in iproduct!(0..pages_per_node, 0..pages_per_node) {
+            let page_image = node_image.crop_imm(
+                x * page_size,
+                y * page_size,
+                page_attachment.texture_size,
+                page_attachment.texture_size,
+            );
+
+            let page_path = format_node_path(
+                &page_directory,
+                node.lod,
+                node.x * pages_per_node + x,
+                node.y * pages_per_node + y,
+            );
+
+            save_image(&page_path, &page_image, &page_attachment)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::R16Image,
+};
+use bevy::prelude::*;
+use image::{DynamicImage, Luma};
+use itertools::iproduct;
+use std::{fs, path::Path};
+
+/// The interpolation, which fills the voids of the source data.
+#[derive(Clone, Copy, Debug)]
+pub enum VoidFillMethod {
+    /// Weights the valid pixels inside the radius (in pixels) by the inverse of their distance
+    /// raised to the power.
+    /// Voids, which are wider than the radius, are filled by the multigrid interpolation.
+    InverseDistance { radius: u32, power: f32 },
+    /// Averages the valid pixels on successively coarser grids until all voids are closed
+    /// and interpolates the result back, smoothing it with the iterations of relaxation per grid.
+    Multigrid { iterations: u32 },
+}
+
+/// Configures the filling of the nodata voids (e.g. below water or buildings in lidar-derived
+/// DTMs) of height sources, so that they do not turn into spikes.
+///
+/// Only single channel 16 bit (R16) attachments can be filled.
+#[derive(Clone, Debug)]
+pub struct VoidFilling {
+    /// The value of the source data, which marks voids.
+    pub nodata: u16,
+    /// The interpolation, which fills the voids.
+    pub method: VoidFillMethod,
+    /// Lets voids inherit the values of the mosaic sources below (e.g. a coarser DEM),
+    /// instead of interpolating them. Voids without any data below are interpolated nonetheless.
+    pub inherit: bool,
+    /// The directory, to which a mask of the filled pixels is written as source tiles (R16 PNGs
+    /// named like the source tiles), so that it can be preprocessed as an attachment as well.
+    pub mask_path: Option<String>,
+}
+
+/// Fills the voids by inverse distance weighting and returns the pixels, which are still void.
+fn inverse_distance(
+    values: &mut [f32],
+    voids: &[bool],
+    size: UVec2,
+    radius: u32,
+    power: f32,
+) -> Vec<bool> {
+    let source = values.to_vec();
+    let mut remaining = voids.to_vec();
+    let radius = radius as i32;
+
+    for (y, x) in iproduct!(0..size.y as i32, 0..size.x as i32) {
+        let index = (y as u32 * size.x + x as u32) as usize;
+
+        if !voids[index] {
+            continue;
+        }
+
+        let mut value = 0.0;
+        let mut weight = 0.0;
+
+        for (dy, dx) in iproduct!(-radius..=radius, -radius..=radius) {
+            let (sx, sy) = (x + dx, y + dy);
+            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+
+            if sx < 0
+                || sy < 0
+                || sx >= size.x as i32
+                || sy >= size.y as i32
+                || distance > radius as f32
+            {
+                continue;
+            }
+
+            let sample = (sy as u32 * size.x + sx as u32) as usize;
+
+            if !voids[sample] {
+                let w = 1.0 / distance.powf(power);
+                value += w * source[sample];
+                weight += w;
+            }
+        }
+
+        if weight > 0.0 {
+            values[index] = value / weight;
+            remaining[index] = false;
+        }
+    }
+
+    remaining
+}
+
+/// Fills the voids by recursively averaging the valid pixels on coarser grids.
+fn multigrid(values: &mut [f32], voids: &[bool], size: UVec2, iterations: u32) {
+    if !voids.contains(&true) {
+        return;
+    }
+
+    if size.x <= 1 && size.y <= 1 {
+        // the whole tile is void
+        values.fill(0.0);
+        return;
+    }
+
+    // restrict the valid pixels to the coarser grid
+    let coarse_size = (size + 1) / 2;
+    let mut coarse_values = vec![0.0; (coarse_size.x * coarse_size.y) as usize];
+    let mut coarse_voids = vec![true; (coarse_size.x * coarse_size.y) as usize];
+
+    for (y, x) in iproduct!(0..coarse_size.y, 0..coarse_size.x) {
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for (cy, cx) in iproduct!(
+            2 * y..(2 * y + 2).min(size.y),
+            2 * x..(2 * x + 2).min(size.x)
+        ) {
+            let index = (cy * size.x + cx) as usize;
+
+            if !voids[index] {
+                sum += values[index];
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            let index = (y * coarse_size.x + x) as usize;
+            coarse_values[index] = sum / count as f32;
+            coarse_voids[index] = false;
+        }
+    }
+
+    multigrid(&mut coarse_values, &coarse_voids, coarse_size, iterations);
+
+    // prolongate the coarse values into the voids
+    for (y, x) in iproduct!(0..size.y, 0..size.x) {
+        let index = (y * size.x + x) as usize;
+
+        if voids[index] {
+            values[index] = coarse_values[((y / 2) * coarse_size.x + x / 2) as usize];
+        }
+    }
+
+    // relax the voids towards the average of their neighbours
+    for _ in 0..iterations {
+        for (y, x) in iproduct!(0..size.y, 0..size.x) {
+            let index = (y * size.x + x) as usize;
+
+            if !voids[index] {
+                continue;
+            }
+
+            let neighbours = [
+                (x.saturating_sub(1), y),
+                ((x + 1).min(size.x - 1), y),
+                (x, y.saturating_sub(1)),
+                (x, (y + 1).min(size.y - 1)),
+            ];
+
+            let average = neighbours
+                .iter()
+                .map(|&(nx, ny)| values[(ny * size.x + nx) as usize])
+                .sum::<f32>()
+                / 4.0;
+
+            values[index] = average;
+        }
+    }
+}
+
+/// Fills the voids of the source tile in place and returns, which pixels were void.
+///
+/// The mask of the filled pixels is written to the mask directory, if configured.
+pub(crate) fn fill_voids(
+    tile_image: &mut DynamicImage,
+    void_fill: &VoidFilling,
+    path: &str,
+) -> TerrainResult<Vec<bool>> {
+    let image = tile_image.as_mut_luma16().ok_or_else(|| {
+        TerrainError::InvalidConfig(format!(
+            "The voids of the tile {path} can not be filled, since it is not a R16 image."
+        ))
+    })?;
+
+    let size = UVec2::new(image.width(), image.height());
+    let voids: Vec<bool> = image.pixels().map(|p| p.0[0] == void_fill.nodata).collect();
+
+    if voids.contains(&true) {
+        let mut values: Vec<f32> = image.pixels().map(|p| p.0[0] as f32).collect();
+
+        match void_fill.method {
+            VoidFillMethod::InverseDistance { radius, power } => {
+                let remaining = inverse_distance(&mut values, &voids, size, radius, power);
+                multigrid(&mut values, &remaining, size, 4);
+            }
+            VoidFillMethod::Multigrid { iterations } => {
+                multigrid(&mut values, &voids, size, iterations)
+            }
+        }
+
+        for (pixel, value) in image.pixels_mut().zip(values) {
+            pixel.0[0] = value.round().clamp(0.0, u16::MAX as f32) as u16;
+        }
+    }
+
+    if let Some(mask_path) = &void_fill.mask_path {
+        fs::create_dir_all(mask_path).map_err(TerrainError::io(mask_path))?;
+
+        let name = Path::new(path).file_stem().unwrap().to_string_lossy();
+        let mask_path = format!("{mask_path}/{name}.png");
+
+        let mask = R16Image::from_fn(size.x, size.y, |x, y| {
+            let void = voids[(y * size.x + x) as usize];
+            Luma([if void { u16::MAX } else { 0 }])
+        });
+
+        mask.save(&mask_path)
+            .map_err(|error| TerrainError::malformed(&mask_path, error))?;
+    }
+
+    Ok(voids)
+}
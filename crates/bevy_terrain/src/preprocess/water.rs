@@ -0,0 +1,380 @@
+use crate::{
+    error::{TerrainError, TerrainResult},
+    preprocess::{
+        file_io::{format_node_path, load_image, save_image},
+        UVec2Utils,
+    },
+    skip_none,
+    terrain_data::{AttachmentConfig, AttachmentFormat, FileFormat},
+    TerrainConfig,
+};
+use bevy::{math::DVec2, prelude::*, utils::HashMap};
+use image::{ImageBuffer, Luma};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs};
+
+/// The source of the water bodies, whose height is flattened.
+#[derive(Clone, Debug)]
+pub enum WaterBodies {
+    /// The outer rings of polygons in the horizontal (x, z) plane of the terrain.
+    Polygons(Vec<Vec<Vec2>>),
+    /// The path to a GeoJSON file with `Polygon` or `MultiPolygon` geometries.
+    ///
+    /// The coordinates are converted using the georeference of the terrain, if it has one.
+    /// Otherwise they are interpreted as positions in the horizontal (x, z) plane.
+    GeoJson(String),
+    /// A single raster covering the entire terrain, whose pixel values identify the water body.
+    /// Zero represents land.
+    Raster {
+        /// The path to the raster.
+        path: String,
+        /// The file format of the raster.
+        file_format: FileFormat,
+    },
+}
+
+/// Flattens the height data inside of water bodies (e.g. lakes and rivers) to a consistent level.
+///
+/// Real DTMs often contain noisy water surfaces, which look bumpy when rendered.
+/// The level of each water body is chosen as a percentile of all heights inside of it, which
+/// makes it robust against outliers. The height attachment has to use the `R16` format.
+#[derive(Clone, Debug)]
+pub struct WaterFlattening {
+    /// The water bodies, which are flattened.
+    pub bodies: WaterBodies,
+    /// The percentile (0 to 1) of the heights inside of a water body, which is used as its level.
+    /// Zero selects the minimum and one half the median height.
+    pub percentile: f32,
+}
+
+impl WaterFlattening {
+    pub fn new(bodies: WaterBodies) -> Self {
+        Self {
+            bodies,
+            percentile: 0.5,
+        }
+    }
+}
+
+/// A polygon consisting of an outer ring and optional holes.
+struct Polygon {
+    rings: Vec<Vec<Vec2>>,
+    min: Vec2,
+    max: Vec2,
+}
+
+impl Polygon {
+    fn new(rings: Vec<Vec<Vec2>>) -> Self {
+        let points = rings.iter().flatten();
+        let min = points.clone().fold(Vec2::splat(f32::MAX), |a, &b| a.min(b));
+        let max = points.fold(Vec2::splat(f32::MIN), |a, &b| a.max(b));
+
+        Self { rings, min, max }
+    }
+
+    /// Tests whether the position lies inside, using the even-odd rule over all rings.
+    fn contains(&self, position: Vec2) -> bool {
+        if position.cmplt(self.min).any() || position.cmpgt(self.max).any() {
+            return false;
+        }
+
+        let mut inside = false;
+
+        for ring in &self.rings {
+            for (&a, &b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+                if (a.y > position.y) != (b.y > position.y)
+                    && position.x < a.x + (position.y - a.y) / (b.y - a.y) * (b.x - a.x)
+                {
+                    inside = !inside;
+                }
+            }
+        }
+
+        inside
+    }
+}
+
+/// Assigns positions of the terrain to water bodies.
+enum WaterMask {
+    Polygons(Vec<Polygon>),
+    Raster {
+        labels: ImageBuffer<Luma<u16>, Vec<u16>>,
+        scale: Vec2,
+    },
+}
+
+impl WaterMask {
+    fn new(bodies: &WaterBodies, config: &TerrainConfig) -> TerrainResult<Self> {
+        Ok(match bodies {
+            WaterBodies::Polygons(polygons) => Self::Polygons(
+                polygons
+                    .iter()
+                    .map(|ring| Polygon::new(vec![ring.clone()]))
+                    .collect(),
+            ),
+            WaterBodies::GeoJson(path) => {
+                let contents = fs::read_to_string(path).map_err(TerrainError::io(path))?;
+                let value: Value = serde_json::from_str(&contents)
+                    .map_err(|error| TerrainError::malformed(path, error))?;
+
+                let mut polygons = Vec::new();
+                collect_polygons(&value, &mut polygons);
+
+                let to_local = |position: DVec2| match &config.georeference {
+                    Some(georeference) => georeference.to_local(position),
+                    None => position.as_vec2(),
+                };
+
+                Self::Polygons(
+                    polygons
+                        .into_iter()
+                        .map(|rings| {
+                            Polygon::new(
+                                rings
+                                    .into_iter()
+                                    .map(|ring| ring.into_iter().map(to_local).collect())
+                                    .collect(),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+            WaterBodies::Raster { path, file_format } => {
+                let labels = load_image(path, *file_format)?
+                    .ok_or_else(|| TerrainError::MissingTile { path: path.clone() })?
+                    .to_luma16();
+                let scale = Vec2::new(labels.width() as f32, labels.height() as f32)
+                    / config.terrain_size as f32;
+
+                Self::Raster { labels, scale }
+            }
+        })
+    }
+
+    /// Returns the water body at the position, if any.
+    fn body(&self, position: Vec2) -> Option<usize> {
+        match self {
+            Self::Polygons(polygons) => polygons
+                .iter()
+                .position(|polygon| polygon.contains(position)),
+            Self::Raster { labels, scale } => {
+                let coord = (position * *scale).floor();
+
+                if coord.cmplt(Vec2::ZERO).any() {
+                    return None;
+                }
+
+                let (x, y) = (coord.x as u32, coord.y as u32);
+
+                if x >= labels.width() || y >= labels.height() {
+                    return None;
+                }
+
+                match labels.get_pixel(x, y).0[0] {
+                    0 => None,
+                    label => Some(label as usize),
+                }
+            }
+        }
+    }
+}
+
+/// Collects the rings of all polygons inside of the GeoJSON value.
+fn collect_polygons(value: &Value, polygons: &mut Vec<Vec<Vec<DVec2>>>) {
+    let rings = |value: &Value| -> Vec<Vec<DVec2>> {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|ring| {
+                ring.as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|point| {
+                        Some(DVec2::new(point.get(0)?.as_f64()?, point.get(1)?.as_f64()?))
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                collect_polygons(feature, polygons);
+            }
+        }
+        Some("Feature") => collect_polygons(&value["geometry"], polygons),
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                collect_polygons(geometry, polygons);
+            }
+        }
+        Some("Polygon") => polygons.push(rings(&value["coordinates"])),
+        Some("MultiPolygon") => {
+            for polygon in value["coordinates"].as_array().into_iter().flatten() {
+                polygons.push(rings(polygon));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Calls the function for each pixel of the node with its position in the horizontal plane.
+fn for_each_pixel(
+    attachment: &AttachmentConfig,
+    lod: u32,
+    coord: UVec2,
+    mut f: impl FnMut(u32, u32, Vec2),
+) {
+    let pixel_size = (1 << lod) as f32;
+    let origin = (coord * attachment.center_size).as_vec2() - attachment.border_size as f32;
+
+    for y in 0..attachment.texture_size {
+        for x in 0..attachment.texture_size {
+            let position = (origin + Vec2::new(x as f32, y as f32) + 0.5) * pixel_size;
+            f(x, y, position);
+        }
+    }
+}
+
+/// Computes the level of each water body from the nodes of the lod.
+fn water_levels(
+    directory: &str,
+    attachment: &AttachmentConfig,
+    mask: &WaterMask,
+    percentile: f32,
+    lod: u32,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<HashMap<usize, u16>> {
+    let mut histograms: HashMap<usize, BTreeMap<u16, u64>> = HashMap::default();
+
+    for (x, y) in first.product(last) {
+        let node_path = format_node_path(directory, lod, x, y);
+        let node_image = skip_none!(load_image(&node_path, attachment.file_format)?);
+        let node_image = node_image.as_luma16().unwrap();
+
+        for_each_pixel(attachment, lod, UVec2::new(x, y), |px, py, position| {
+            if let Some(body) = mask.body(position) {
+                let height = node_image.get_pixel(px, py).0[0];
+                *histograms
+                    .entry(body)
+                    .or_default()
+                    .entry(height)
+                    .or_default() += 1;
+            }
+        });
+    }
+
+    Ok(histograms
+        .into_iter()
+        .map(|(body, histogram)| {
+            let count: u64 = histogram.values().sum();
+            let target = (percentile.clamp(0.0, 1.0) * (count - 1) as f32) as u64;
+
+            let mut accumulated = 0;
+            let level = histogram
+                .into_iter()
+                .find(|&(_, n)| {
+                    accumulated += n;
+                    accumulated > target
+                })
+                .unwrap()
+                .0;
+
+            (body, level)
+        })
+        .collect())
+}
+
+/// Sets the height inside of the water bodies of all nodes of the lod to their level.
+fn flatten_layer(
+    directory: &str,
+    attachment: &AttachmentConfig,
+    mask: &WaterMask,
+    levels: &HashMap<usize, u16>,
+    lod: u32,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<()> {
+    for (x, y) in first.product(last) {
+        let node_path = format_node_path(directory, lod, x, y);
+        let mut node_image = skip_none!(load_image(&node_path, attachment.file_format)?);
+        let node_data = node_image.as_mut_luma16().unwrap();
+        let mut changed = false;
+
+        for_each_pixel(attachment, lod, UVec2::new(x, y), |px, py, position| {
+            if let Some(&level) = mask.body(position).and_then(|body| levels.get(&body)) {
+                node_data.put_pixel(px, py, Luma([level]));
+                changed = true;
+            }
+        });
+
+        if changed {
+            save_image(&node_path, &node_image, attachment)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The water bodies and their levels, which have been computed from the source lod.
+pub(crate) struct WaterLevels {
+    mask: WaterMask,
+    levels: HashMap<usize, u16>,
+}
+
+/// Flattens the water bodies in the nodes of the source lod.
+///
+/// Returns the levels of the water bodies, which have to be applied to the finer lods
+/// (generated afterwards) using [`flatten_water_layer`].
+pub(crate) fn flatten_water(
+    config: &TerrainConfig,
+    directory: &str,
+    attachment: &AttachmentConfig,
+    water: &WaterFlattening,
+    lod: u32,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<Option<WaterLevels>> {
+    if !matches!(attachment.format, AttachmentFormat::R16) {
+        warn!("Only attachments with the R16 format can be flattened.");
+        return Ok(None);
+    }
+
+    let mask = WaterMask::new(&water.bodies, config)?;
+    let levels = water_levels(
+        directory,
+        attachment,
+        &mask,
+        water.percentile,
+        lod,
+        first,
+        last,
+    )?;
+
+    flatten_layer(directory, attachment, &mask, &levels, lod, first, last)?;
+
+    Ok(Some(WaterLevels { mask, levels }))
+}
+
+/// Flattens the water bodies in the nodes of a finer lod, using the levels of the source lod.
+pub(crate) fn flatten_water_layer(
+    directory: &str,
+    attachment: &AttachmentConfig,
+    water_levels: &WaterLevels,
+    lod: u32,
+    first: UVec2,
+    last: UVec2,
+) -> TerrainResult<()> {
+    flatten_layer(
+        directory,
+        attachment,
+        &water_levels.mask,
+        &water_levels.levels,
+        lod,
+        first,
+        last,
+    )
+}
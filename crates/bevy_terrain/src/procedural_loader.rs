@@ -0,0 +1,275 @@
+//! An attachment loader, which synthesizes node data from layered noise instead of loading it from disk.
+
+use crate::{
+    erosion::ErodedRegion,
+    formats::tdf::to_half_float,
+    noise::{sample_layers, NoiseLayer},
+    preprocess::BaseConfig,
+    terrain::TerrainConfig,
+    terrain_data::{
+        calc_node_id, node_atlas::NodeAtlas, shift_attachment_index, AttachmentConfig,
+        AttachmentFormat, AttachmentIndex, NodeCoordinate, NodeId,
+    },
+};
+use bevy::{prelude::*, render::render_resource::*, tasks::AsyncComputeTaskPool, utils::HashMap};
+use half::f16;
+use itertools::iproduct;
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+/// The kind of data a procedural attachment contains.
+#[derive(Clone, Copy, Debug)]
+pub enum ProceduralData {
+    /// The normalized height of the terrain (R16, R16F or R32F).
+    Height,
+    /// The minimum and maximum height inside each pixel (Rg16 or Rg32F).
+    MinMax,
+}
+
+#[derive(Clone)]
+pub(crate) struct ProceduralAttachment {
+    pub(crate) config: AttachmentConfig,
+    pub(crate) data: ProceduralData,
+}
+
+/// This component is used to generate attachments of the [`NodeAtlas`] procedurally on demand.
+///
+/// Each requested node is synthesized from the noise layers on a background thread.
+#[derive(Component)]
+pub struct ProceduralAttachmentLoader {
+    /// The noise layers, which are combined into the terrain height.
+    pub layers: Arc<Vec<NoiseLayer>>,
+    pub(crate) attachments: HashMap<AttachmentIndex, ProceduralAttachment>,
+    /// The regions, which have been eroded, in the order they have been eroded in.
+    pub(crate) eroded_regions: Arc<Vec<ErodedRegion>>,
+    /// The number of eroded regions, that a node has to be generated with, after it has been
+    /// reloaded due to an erosion. Older results are discarded.
+    pub(crate) required_regions: HashMap<NodeId, usize>,
+    /// Nodes that have finished generating on a background thread,
+    /// alongside the number of eroded regions they have been generated with.
+    finished: Arc<Mutex<Vec<(NodeId, AttachmentIndex, Image, usize)>>>,
+}
+
+impl ProceduralAttachmentLoader {
+    pub fn new(layers: Vec<NoiseLayer>) -> Self {
+        Self {
+            layers: Arc::new(layers),
+            attachments: default(),
+            eroded_regions: default(),
+            required_regions: default(),
+            finished: default(),
+        }
+    }
+
+    /// Stops generating the attachment and shifts the indices of the following attachments.
+    pub(crate) fn remove_attachment(&mut self, removed_index: AttachmentIndex) {
+        self.attachments = mem::take(&mut self.attachments)
+            .into_iter()
+            .filter_map(|(index, attachment)| {
+                shift_attachment_index(index, removed_index).map(|index| (index, attachment))
+            })
+            .collect();
+
+        let mut finished = self.finished.lock().unwrap();
+        *finished = mem::take(&mut *finished)
+            .into_iter()
+            .filter_map(|(node_id, index, image, region_count)| {
+                let index = shift_attachment_index(index, removed_index)?;
+                Some((node_id, index, image, region_count))
+            })
+            .collect();
+    }
+}
+
+impl TerrainConfig {
+    /// Adds the base attachment, which will be generated procedurally.
+    ///
+    /// Since there is no source data, all nodes of the terrain are marked as existing.
+    pub fn add_base_attachment_procedural(
+        &mut self,
+        loader: &mut ProceduralAttachmentLoader,
+        base: BaseConfig,
+    ) {
+        // the residual of the heights is only stored by the preprocessor
+        let base = BaseConfig {
+            height_residual: false,
+            ..base
+        };
+
+        self.leaf_node_size = base.texture_size - 2 * base.border_size;
+
+        loader.attachments.insert(
+            self.attachments.len(),
+            ProceduralAttachment {
+                config: base.height_attachment(),
+                data: ProceduralData::Height,
+            },
+        );
+        loader.attachments.insert(
+            self.attachments.len() + 1,
+            ProceduralAttachment {
+                config: base.minmax_attachment(),
+                data: ProceduralData::MinMax,
+            },
+        );
+
+        self.add_base_attachment(base);
+
+        for lod in 0..self.lod_count {
+            let node_size = self.leaf_node_size << lod;
+            let node_count = (self.terrain_size + node_size - 1) / node_size;
+
+            self.nodes.extend(
+                iproduct!(0..node_count, 0..node_count).map(|(x, y)| calc_node_id(lod, x, y)),
+            );
+        }
+    }
+}
+
+/// Evaluates the noise layers at the world position and applies the erosion of all eroded regions.
+pub(crate) fn sample_height(
+    layers: &[NoiseLayer],
+    eroded_regions: &[ErodedRegion],
+    position: Vec2,
+) -> f32 {
+    let erosion: f32 = eroded_regions
+        .iter()
+        .map(|region| region.sample(position))
+        .sum();
+
+    (sample_layers(layers, position) + erosion).clamp(0.0, 1.0)
+}
+
+/// Synthesizes the data of a single node attachment, including all of its mip levels.
+pub(crate) fn generate_node(
+    layers: &[NoiseLayer],
+    eroded_regions: &[ErodedRegion],
+    node_id: NodeId,
+    attachment: &ProceduralAttachment,
+) -> Image {
+    let coordinate = NodeCoordinate::from(node_id);
+    let config = &attachment.config;
+    let scale = (1 << coordinate.lod) as f32;
+    let origin = Vec2::new(coordinate.x as f32, coordinate.y as f32) * config.center_size as f32;
+
+    let mut data = Vec::new();
+    let mut push = |value: f32| match config.format {
+        AttachmentFormat::R16F => data.extend_from_slice(&f16::from_f32(value).to_le_bytes()),
+        AttachmentFormat::R32F | AttachmentFormat::Rg32F => {
+            data.extend_from_slice(&value.to_le_bytes())
+        }
+        _ => data.extend_from_slice(&((value * u16::MAX as f32) as u16).to_le_bytes()),
+    };
+
+    for mip_level in 0..config.mip_level_count {
+        let size = config.texture_size >> mip_level;
+        let step = (1 << mip_level) as f32;
+
+        for (y, x) in iproduct!(0..size, 0..size) {
+            let pixel = Vec2::new(x as f32, y as f32) * step - config.border_size as f32;
+            let position = (origin + pixel) * scale;
+
+            match attachment.data {
+                ProceduralData::Height => push(sample_height(layers, eroded_regions, position)),
+                ProceduralData::MinMax => {
+                    let extent = step * scale;
+                    let (min, max) = iproduct!(0..2, 0..2)
+                        .map(|(cx, cy)| {
+                            let offset = Vec2::new(cx as f32, cy as f32) * extent;
+                            sample_height(layers, eroded_regions, position + offset)
+                        })
+                        .fold((f32::MAX, f32::MIN), |(min, max), height| {
+                            (min.min(height), max.max(height))
+                        });
+
+                    push(min);
+                    push(max);
+                }
+            }
+        }
+    }
+
+    if let AttachmentFormat::R32F | AttachmentFormat::Rg32F = config.format {
+        let channel_count = match attachment.data {
+            ProceduralData::Height => 1,
+            ProceduralData::MinMax => 2,
+        };
+
+        data = to_half_float(&data, channel_count);
+    }
+
+    Image {
+        data,
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: config.texture_size,
+                height: config.texture_size,
+                ..default()
+            },
+            mip_level_count: config.mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: config.format.into(),
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
+        },
+        sampler_descriptor: default(),
+        texture_view_descriptor: None,
+    }
+}
+
+/// Starts generating the attachments of all nodes that have been requested this frame.
+pub(crate) fn start_generating_attachments(
+    terrain_query: Query<(&NodeAtlas, &ProceduralAttachmentLoader)>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for (node_atlas, loader) in terrain_query.iter() {
+        for (&node_id, (&attachment_index, attachment)) in
+            iproduct!(node_atlas.load_events.iter(), loader.attachments.iter())
+        {
+            let layers = loader.layers.clone();
+            let eroded_regions = loader.eroded_regions.clone();
+            let attachment = attachment.clone();
+            let finished = loader.finished.clone();
+
+            task_pool
+                .spawn(async move {
+                    let image = generate_node(&layers, &eroded_regions, node_id, &attachment);
+                    finished.lock().unwrap().push((
+                        node_id,
+                        attachment_index,
+                        image,
+                        eroded_regions.len(),
+                    ));
+                })
+                .detach();
+        }
+    }
+}
+
+/// Hands the attachments, that have finished generating, over to the [`NodeAtlas`].
+pub(crate) fn finish_generating_attachments(
+    mut images: ResMut<Assets<Image>>,
+    mut terrain_query: Query<(&mut NodeAtlas, &ProceduralAttachmentLoader)>,
+) {
+    for (mut node_atlas, loader) in terrain_query.iter_mut() {
+        for (node_id, attachment_index, image, region_count) in
+            loader.finished.lock().unwrap().drain(..)
+        {
+            // the node has been reloaded since, because it is affected by a newer erosion
+            if region_count < loader.required_regions.get(&node_id).copied().unwrap_or(0) {
+                continue;
+            }
+
+            if let Some(node) = node_atlas.loading_nodes.get_mut(&node_id) {
+                node.set_attachment(attachment_index, images.add(image));
+                node.loaded(attachment_index);
+            }
+        }
+    }
+}
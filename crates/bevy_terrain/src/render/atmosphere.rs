@@ -0,0 +1,114 @@
+//! Terrain specific atmospherics, which are applied in the fragment shader of the terrain.
+//!
+//! The fog combines a distance fog with an exponential height falloff, which is measured relative
+//! to the height range of the terrain. Looking towards the sun tints the fog with the sun color,
+//! which approximates the aerial perspective of the scattered sunlight.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+
+/// The fog and aerial perspective applied to all terrains.
+///
+/// The fog is disabled, as long as the `fog_density` is zero.
+#[derive(Clone, Debug, Resource)]
+pub struct TerrainAtmosphere {
+    /// The color of the fog.
+    pub fog_color: Color,
+    /// The density of the fog at zero height per world unit.
+    pub fog_density: f32,
+    /// The rate at which the fog density decreases with the height.
+    /// The density falls off by a factor of e over the height of the terrain.
+    pub height_falloff: f32,
+    /// The direction towards the sun.
+    pub sun_direction: Vec3,
+    /// The color of the fog when looking towards the sun.
+    pub sun_color: Color,
+    /// The exponent of the glow around the sun. Larger values result in a tighter glow.
+    pub sun_exponent: f32,
+}
+
+impl Default for TerrainAtmosphere {
+    fn default() -> Self {
+        Self {
+            fog_color: Color::rgb(0.5, 0.6, 0.7),
+            fog_density: 0.0,
+            height_falloff: 1.0,
+            sun_direction: Vec3::new(1.0, 1.0, 0.0).normalize(),
+            sun_color: Color::rgb(1.0, 0.9, 0.7),
+            sun_exponent: 8.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct TerrainAtmosphereUniform {
+    fog_color: Vec4,
+    sun_color: Vec4,
+    sun_direction: Vec4,
+    fog_density: f32,
+    height_falloff: f32,
+    sun_exponent: f32,
+}
+
+impl From<&TerrainAtmosphere> for TerrainAtmosphereUniform {
+    fn from(atmosphere: &TerrainAtmosphere) -> Self {
+        Self {
+            fog_color: atmosphere.fog_color.as_linear_rgba_f32().into(),
+            sun_color: atmosphere.sun_color.as_linear_rgba_f32().into(),
+            sun_direction: atmosphere.sun_direction.normalize_or_zero().extend(0.0),
+            fog_density: atmosphere.fog_density,
+            height_falloff: atmosphere.height_falloff,
+            sun_exponent: atmosphere.sun_exponent,
+        }
+    }
+}
+
+/// Stores the uniform buffer of the [`TerrainAtmosphere`].
+#[derive(Resource)]
+pub struct GpuTerrainAtmosphere {
+    pub(crate) buffer: Buffer,
+    uniform: TerrainAtmosphereUniform,
+}
+
+impl FromWorld for GpuTerrainAtmosphere {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: "terrain_atmosphere_buffer".into(),
+            size: TerrainAtmosphereUniform::min_size().get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            uniform: default(),
+        }
+    }
+}
+
+pub(crate) fn extract_terrain_atmosphere(
+    mut gpu_atmosphere: ResMut<GpuTerrainAtmosphere>,
+    atmosphere: Extract<Option<Res<TerrainAtmosphere>>>,
+) {
+    gpu_atmosphere.uniform = match atmosphere.as_deref() {
+        Some(atmosphere) => atmosphere.into(),
+        None => default(),
+    };
+}
+
+pub(crate) fn queue_terrain_atmosphere(
+    queue: Res<RenderQueue>,
+    gpu_atmosphere: Res<GpuTerrainAtmosphere>,
+) {
+    let mut buffer = encase::UniformBuffer::new(Vec::new());
+    buffer.write(&gpu_atmosphere.uniform).unwrap();
+    queue.write_buffer(&gpu_atmosphere.buffer, 0, &buffer.into_inner());
+}
@@ -0,0 +1,198 @@
+//! A configurable color ramp, which maps normalized values (e.g. heights or slopes) to colors
+//! in the debug and analysis views.
+//!
+//! The ramp is baked into a small lookup texture, which is updated, whenever the
+//! [`ColorRamp`] resource changes.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use std::num::NonZeroU32;
+
+/// The number of texels of the color ramp lookup texture.
+pub const COLOR_RAMP_SIZE: u32 = 256;
+
+/// How the colors between the stops of a [`ColorRamp`] are computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RampInterpolation {
+    /// The colors are blended linearly between the neighbouring stops.
+    Linear,
+    /// The color of the previous stop is used until the next one, resulting in discrete bands.
+    Constant,
+}
+
+/// The color ramp used by the height and slope views of the default shader.
+///
+/// Modify this resource at runtime to change the colors of the views.
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub struct ColorRamp {
+    /// The stops of the ramp, consisting of a position in the range of [0, 1] and a color.
+    /// Positions outside of the first and last stop are clamped.
+    pub stops: Vec<(f32, Color)>,
+    /// The interpolation between the stops.
+    pub interpolation: RampInterpolation,
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        Self::new(RampInterpolation::Linear)
+            .with_stop(0.0, Color::rgb(0.0, 0.6, 0.0))
+            .with_stop(0.167, Color::rgb(1.0, 1.0, 0.0))
+            .with_stop(0.389, Color::rgb(1.0, 0.0, 0.0))
+            .with_stop(0.667, Color::rgb(0.5, 0.0, 0.5))
+    }
+}
+
+impl ColorRamp {
+    /// Creates a new color ramp without any stops.
+    pub fn new(interpolation: RampInterpolation) -> Self {
+        Self {
+            stops: Vec::new(),
+            interpolation,
+        }
+    }
+
+    /// Adds a stop to the ramp, keeping the stops sorted by their position.
+    pub fn with_stop(mut self, position: f32, color: Color) -> Self {
+        let index = self.stops.partition_point(|&(stop, _)| stop <= position);
+        self.stops.insert(index, (position, color));
+        self
+    }
+
+    /// Returns the color of the ramp at the position.
+    pub fn sample(&self, position: f32) -> Color {
+        let next = self.stops.partition_point(|&(stop, _)| stop <= position);
+
+        if next == 0 {
+            return self.stops.first().map_or(Color::BLACK, |&(_, color)| color);
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+
+        let (start, start_color) = self.stops[next - 1];
+        let (end, end_color) = self.stops[next];
+
+        match self.interpolation {
+            RampInterpolation::Constant => start_color,
+            RampInterpolation::Linear => {
+                let ratio = (position - start) / (end - start);
+                let start_color = Vec4::from(start_color.as_rgba_f32());
+                let end_color = Vec4::from(end_color.as_rgba_f32());
+
+                Color::from(start_color.lerp(end_color, ratio))
+            }
+        }
+    }
+
+    /// Bakes the ramp into the texels of the lookup texture.
+    fn bake(&self) -> Vec<u8> {
+        (0..COLOR_RAMP_SIZE)
+            .flat_map(|texel| {
+                let position = texel as f32 / (COLOR_RAMP_SIZE - 1) as f32;
+                let [r, g, b, a] = self.sample(position).as_rgba_f32();
+
+                [r, g, b, a].map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    }
+}
+
+/// Stores the lookup texture of the [`ColorRamp`].
+#[derive(Resource)]
+pub struct GpuColorRamp {
+    pub(crate) texture_view: TextureView,
+    pub(crate) sampler: Sampler,
+    texture: Texture,
+    /// The ramp, which has been written into the texture last.
+    ramp: Option<ColorRamp>,
+    /// The texels, which have been extracted this frame, but not yet written into the texture.
+    pending_texels: Option<Vec<u8>>,
+}
+
+impl FromWorld for GpuColorRamp {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: "color_ramp".into(),
+            size: Extent3d {
+                width: COLOR_RAMP_SIZE,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let texture_view = texture.create_view(&default());
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        Self {
+            texture_view,
+            sampler,
+            texture,
+            ramp: None,
+            pending_texels: None,
+        }
+    }
+}
+
+pub(crate) fn extract_color_ramp(
+    mut gpu_ramp: ResMut<GpuColorRamp>,
+    ramp: Extract<Option<Res<ColorRamp>>>,
+) {
+    let ramp = match ramp.as_deref() {
+        Some(ramp) => ramp,
+        None => return,
+    };
+
+    if gpu_ramp.ramp.as_ref() != Some(ramp) {
+        gpu_ramp.pending_texels = Some(ramp.bake());
+        gpu_ramp.ramp = Some(ramp.clone());
+    }
+}
+
+pub(crate) fn queue_color_ramp(queue: Res<RenderQueue>, mut gpu_ramp: ResMut<GpuColorRamp>) {
+    let GpuColorRamp {
+        ref texture,
+        ref mut pending_texels,
+        ..
+    } = gpu_ramp.as_mut();
+
+    if let Some(texels) = pending_texels.take() {
+        queue.write_texture(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &texels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(COLOR_RAMP_SIZE * 4),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: COLOR_RAMP_SIZE,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
@@ -1,13 +1,16 @@
 use crate::{
+    debug::capture::GpuFrameCapture,
     render::{
         culling::CullingBindGroup,
-        render_pipeline::TerrainPipelineConfig,
+        diagnostics::GpuTerrainDiagnostics,
         shaders::{PREPARE_INDIRECT_SHADER, REFINE_TILES_SHADER},
         terrain_data::terrain_bind_group_layout,
         terrain_view_data::TerrainViewConfigUniform,
         terrain_view_data::TerrainViewData,
+        vegetation::{VegetationComputePipelineId, VegetationViewData},
         CULL_DATA_LAYOUT, PREPARE_INDIRECT_LAYOUT, REFINE_TILES_LAYOUT,
     },
+    skip_none,
     terrain::Terrain,
     DebugTerrain, TerrainComponents, TerrainData, TerrainView, TerrainViewComponents,
 };
@@ -34,6 +37,9 @@ pub enum TerrainComputePipelineId {
     PrepareRoot,
     PrepareNext,
     PrepareRender,
+    CullTiles,
+    PrepareCull,
+    PrepareDependentRender,
 }
 
 bitflags::bitflags! {
@@ -79,12 +85,11 @@ pub struct TerrainComputePipelines {
 impl FromWorld for TerrainComputePipelines {
     fn from_world(world: &mut World) -> Self {
         let device = world.resource::<RenderDevice>();
-        let config = world.resource::<TerrainPipelineConfig>();
 
         let prepare_indirect_layout = device.create_bind_group_layout(&PREPARE_INDIRECT_LAYOUT);
         let refine_tiles_layout = device.create_bind_group_layout(&REFINE_TILES_LAYOUT);
         let cull_data_layout = device.create_bind_group_layout(&CULL_DATA_LAYOUT);
-        let terrain_layout = terrain_bind_group_layout(&device, config.attachment_count);
+        let terrain_layout = terrain_bind_group_layout(&device);
 
         let prepare_indirect_shader = PREPARE_INDIRECT_SHADER.typed();
         let refine_tiles_shader = REFINE_TILES_SHADER.typed();
@@ -150,6 +155,35 @@ impl SpecializedComputePipeline for TerrainComputePipelines {
                 shader = self.prepare_indirect_shader.clone();
                 entry_point = "prepare_render".into();
             }
+            TerrainComputePipelineId::CullTiles => {
+                layout = Some(vec![
+                    self.refine_tiles_layout.clone(),
+                    self.cull_data_layout.clone(),
+                    self.terrain_layout.clone(),
+                ]);
+                shader = self.refine_tiles_shader.clone();
+                entry_point = "cull_tiles".into();
+            }
+            TerrainComputePipelineId::PrepareCull => {
+                layout = Some(vec![
+                    self.refine_tiles_layout.clone(),
+                    self.cull_data_layout.clone(),
+                    self.terrain_layout.clone(),
+                    self.prepare_indirect_layout.clone(),
+                ]);
+                shader = self.prepare_indirect_shader.clone();
+                entry_point = "prepare_cull".into();
+            }
+            TerrainComputePipelineId::PrepareDependentRender => {
+                layout = Some(vec![
+                    self.refine_tiles_layout.clone(),
+                    self.cull_data_layout.clone(),
+                    self.terrain_layout.clone(),
+                    self.prepare_indirect_layout.clone(),
+                ]);
+                shader = self.prepare_indirect_shader.clone();
+                entry_point = "prepare_dependent_render".into();
+            }
         }
 
         ComputePipelineDescriptor {
@@ -162,6 +196,13 @@ impl SpecializedComputePipeline for TerrainComputePipelines {
     }
 }
 
+/// The render graph node, which refines the quadtree of each terrain view and prepares the
+/// indirect draw arguments.
+///
+/// The dispatches are recorded into the command encoder of the render graph and therefore run
+/// on the main queue before the camera passes.
+/// Todo: schedule the refinement on an async compute queue, once wgpu exposes more than one
+/// queue per device, so that it can overlap the shadow and prepass work.
 pub struct TerrainComputeNode {
     terrain_query: QueryState<Entity, With<Terrain>>,
     view_query: QueryState<Entity, With<TerrainView>>,
@@ -186,6 +227,8 @@ impl FromWorld for TerrainComputeNode {
 }
 
 impl TerrainComputeNode {
+    /// Refines the tiles of a primary view and culls them for each of its dependent views,
+    /// before the draw of the primary view is prepared.
     fn tessellate_terrain<'a>(
         pass: &mut ComputePass<'a>,
         pipelines: &'a Vec<&'a ComputePipeline>,
@@ -193,68 +236,135 @@ impl TerrainComputeNode {
         terrain_data: &'a TerrainData,
         culling_bind_group: &'a BindGroup,
         refinement_count: u32,
+        dependents: &[(&'a TerrainViewData, &'a BindGroup)],
     ) {
-        pass.set_bind_group(0, &view_data.refine_tiles_bind_group, &[]);
+        let (refine_tiles_bind_group, prepare_indirect_bind_group) = match (
+            &view_data.refine_tiles_bind_group,
+            &view_data.prepare_indirect_bind_group,
+        ) {
+            (Some(refine_tiles), Some(prepare_indirect)) => (refine_tiles, prepare_indirect),
+            _ => return, // the view is refined on the CPU
+        };
+
+        pass.set_bind_group(0, refine_tiles_bind_group, &[]);
         pass.set_bind_group(1, culling_bind_group, &[]);
         pass.set_bind_group(2, &terrain_data.terrain_bind_group, &[]);
-        pass.set_bind_group(3, &view_data.prepare_indirect_bind_group, &[]);
+        pass.set_bind_group(3, prepare_indirect_bind_group, &[]);
 
         pass.set_pipeline(pipelines[TerrainComputePipelineId::PrepareRoot as usize]);
         pass.dispatch_workgroups(1, 1, 1);
 
         for _ in 0..refinement_count {
             pass.set_pipeline(pipelines[TerrainComputePipelineId::RefineTiles as usize]);
-            pass.dispatch_workgroups_indirect(&view_data.indirect_buffer, 0);
+            pass.dispatch_workgroups_indirect(
+                &view_data.indirect_buffer,
+                view_data.indirect_offset,
+            );
 
             pass.set_pipeline(pipelines[TerrainComputePipelineId::PrepareNext as usize]);
             pass.dispatch_workgroups(1, 1, 1);
         }
 
         pass.set_pipeline(pipelines[TerrainComputePipelineId::RefineTiles as usize]);
-        pass.dispatch_workgroups_indirect(&view_data.indirect_buffer, 0);
+        pass.dispatch_workgroups_indirect(&view_data.indirect_buffer, view_data.indirect_offset);
+
+        for &(dependent_data, dependent_culling_bind_group) in dependents {
+            Self::cull_dependent_tiles(
+                pass,
+                pipelines,
+                dependent_data,
+                dependent_culling_bind_group,
+            );
+        }
+
+        if !dependents.is_empty() {
+            pass.set_bind_group(0, refine_tiles_bind_group, &[]);
+            pass.set_bind_group(1, culling_bind_group, &[]);
+            pass.set_bind_group(3, prepare_indirect_bind_group, &[]);
+        }
 
         pass.set_pipeline(pipelines[TerrainComputePipelineId::PrepareRender as usize]);
         pass.dispatch_workgroups(1, 1, 1);
     }
-}
 
-impl render_graph::Node for TerrainComputeNode {
-    fn update(&mut self, world: &mut World) {
-        self.terrain_query.update_archetypes(world);
-        self.view_query.update_archetypes(world);
+    /// Culls the final tiles of the primary view against the frustum of a dependent view.
+    /// Has to be called after the last refinement of the primary view and before its draw is
+    /// prepared, since that resets the final tile count.
+    fn cull_dependent_tiles<'a>(
+        pass: &mut ComputePass<'a>,
+        pipelines: &'a Vec<&'a ComputePipeline>,
+        view_data: &'a TerrainViewData,
+        culling_bind_group: &'a BindGroup,
+    ) {
+        let (refine_tiles_bind_group, prepare_indirect_bind_group) = match (
+            &view_data.refine_tiles_bind_group,
+            &view_data.prepare_indirect_bind_group,
+        ) {
+            (Some(refine_tiles), Some(prepare_indirect)) => (refine_tiles, prepare_indirect),
+            _ => return,
+        };
 
-        let (mut pipeline_cache, mut pipelines, pipeline, debug) = self.system_state.get_mut(world);
+        pass.set_bind_group(0, refine_tiles_bind_group, &[]);
+        pass.set_bind_group(1, culling_bind_group, &[]);
+        pass.set_bind_group(3, prepare_indirect_bind_group, &[]);
 
-        let mut flags = TerrainComputePipelineFlags::NONE;
+        pass.set_pipeline(pipelines[TerrainComputePipelineId::PrepareCull as usize]);
+        pass.dispatch_workgroups(1, 1, 1);
 
-        if let Some(debug) = &debug {
-            flags |= TerrainComputePipelineFlags::from_debug(debug);
-        }
+        pass.set_pipeline(pipelines[TerrainComputePipelineId::CullTiles as usize]);
+        pass.dispatch_workgroups_indirect(&view_data.indirect_buffer, view_data.indirect_offset);
 
-        for id in TerrainComputePipelineId::iter() {
-            self.pipelines[id as usize] =
-                pipelines.specialize(&mut pipeline_cache, &pipeline, (id, flags));
-        }
+        pass.set_pipeline(pipelines[TerrainComputePipelineId::PrepareDependentRender as usize]);
+        pass.dispatch_workgroups(1, 1, 1);
     }
 
-    fn run(
-        &self,
-        _graph: &mut render_graph::RenderGraphContext,
-        context: &mut RenderContext,
-        world: &World,
-    ) -> Result<(), render_graph::NodeRunError> {
+    /// Scatters the vegetation instances of the view. Has to be called after the terrain bind
+    /// groups of the view have been set by [`Self::tessellate_terrain`].
+    fn scatter_vegetation<'a>(
+        pass: &mut ComputePass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        vegetation_data: &'a VegetationViewData,
+    ) {
+        let pipelines = match VegetationComputePipelineId::iter()
+            .map(|id| {
+                pipeline_cache.get_compute_pipeline(vegetation_data.compute_pipelines[id as usize])
+            })
+            .collect::<Option<Vec<_>>>()
+        {
+            None => return, // some pipelines are not loaded yet
+            Some(pipelines) => pipelines,
+        };
+
+        let workgroup_count = (vegetation_data.grid_size + 7) / 8;
+
+        pass.set_bind_group(3, &vegetation_data.scatter_bind_group, &[]);
+
+        pass.set_pipeline(pipelines[VegetationComputePipelineId::Prepare as usize]);
+        pass.dispatch_workgroups(1, 1, 1);
+
+        pass.set_pipeline(pipelines[VegetationComputePipelineId::Scatter as usize]);
+        pass.dispatch_workgroups(workgroup_count, workgroup_count, 1);
+
+        pass.set_pipeline(pipelines[VegetationComputePipelineId::Finish as usize]);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Refines the tiles of all terrain views and scatters their vegetation.
+    fn refine_terrain(&self, command_encoder: &mut CommandEncoder, world: &World) {
         let pipeline_cache = world.resource::<PipelineCache>();
         let view_config_uniforms =
             world.resource::<TerrainViewComponents<TerrainViewConfigUniform>>();
         let terrain_data = world.resource::<TerrainComponents<TerrainData>>();
         let terrain_view_data = world.resource::<TerrainViewComponents<TerrainViewData>>();
         let culling_bind_groups = world.resource::<TerrainViewComponents<CullingBindGroup>>();
+        let vegetation_view_data = world.resource::<TerrainViewComponents<VegetationViewData>>();
+        let frame_capture = world.get_resource::<GpuFrameCapture>();
 
         let debug = world.get_resource::<DebugTerrain>();
 
         if let Some(debug) = debug {
             if debug.freeze {
-                return Ok(());
+                return;
             }
         }
 
@@ -262,20 +372,45 @@ impl render_graph::Node for TerrainComputeNode {
             .map(|key| pipeline_cache.get_compute_pipeline(self.pipelines[key as usize]))
             .collect::<Option<Vec<_>>>()
         {
-            None => return Ok(()), // some pipelines are not loaded yet
+            None => return, // some pipelines are not loaded yet
             Some(pipelines) => pipelines,
         };
 
-        let pass = &mut context
-            .command_encoder
-            .begin_compute_pass(&ComputePassDescriptor::default());
+        let pass = &mut command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
 
         for terrain in self.terrain_query.iter_manual(world) {
             let terrain_data = terrain_data.get(&terrain).unwrap();
             for view in self.view_query.iter_manual(world) {
-                let view_config = view_config_uniforms.get(&(terrain, view)).unwrap();
-                let view_data = terrain_view_data.get(&(terrain, view)).unwrap();
-                let culling_bind_group = culling_bind_groups.get(&(terrain, view)).unwrap();
+                if frame_capture.map_or(false, |capture| capture.is_replayed(&(terrain, view))) {
+                    continue; // the tiles of the view are replayed from a capture
+                }
+
+                let view_config = skip_none!(view_config_uniforms.get(&(terrain, view)));
+                let view_data = skip_none!(terrain_view_data.get(&(terrain, view)));
+                let culling_bind_group = skip_none!(culling_bind_groups.get(&(terrain, view)));
+
+                if view_data.primary.is_some() {
+                    continue; // the view is culled while its primary view is refined
+                }
+
+                let dependents = self
+                    .view_query
+                    .iter_manual(world)
+                    .filter(|&dependent| {
+                        !frame_capture
+                            .map_or(false, |capture| capture.is_replayed(&(terrain, dependent)))
+                    })
+                    .filter_map(|dependent| {
+                        let dependent_data = terrain_view_data.get(&(terrain, dependent))?;
+                        let culling_bind_group = culling_bind_groups.get(&(terrain, dependent))?;
+
+                        (dependent_data.primary == Some(view)).then_some((
+                            dependent,
+                            dependent_data,
+                            &culling_bind_group.value,
+                        ))
+                    })
+                    .collect::<Vec<_>>();
 
                 TerrainComputeNode::tessellate_terrain(
                     pass,
@@ -284,9 +419,77 @@ impl render_graph::Node for TerrainComputeNode {
                     terrain_data,
                     &culling_bind_group.value,
                     view_config.refinement_count,
+                    &dependents
+                        .iter()
+                        .map(|&(_, data, culling_bind_group)| (data, culling_bind_group))
+                        .collect::<Vec<_>>(),
                 );
+
+                if let Some(vegetation_data) = vegetation_view_data.get(&(terrain, view)) {
+                    TerrainComputeNode::scatter_vegetation(pass, pipeline_cache, vegetation_data);
+                }
+
+                for (dependent, dependent_data, culling_bind_group) in dependents {
+                    if let Some(vegetation_data) = vegetation_view_data.get(&(terrain, dependent)) {
+                        pass.set_bind_group(
+                            0,
+                            skip_none!(&dependent_data.refine_tiles_bind_group),
+                            &[],
+                        );
+                        pass.set_bind_group(1, culling_bind_group, &[]);
+                        TerrainComputeNode::scatter_vegetation(
+                            pass,
+                            pipeline_cache,
+                            vegetation_data,
+                        );
+                    }
+                }
             }
         }
+    }
+}
+
+impl render_graph::Node for TerrainComputeNode {
+    fn update(&mut self, world: &mut World) {
+        self.terrain_query.update_archetypes(world);
+        self.view_query.update_archetypes(world);
+
+        let (mut pipeline_cache, mut pipelines, pipeline, debug) = self.system_state.get_mut(world);
+
+        let mut flags = TerrainComputePipelineFlags::NONE;
+
+        if let Some(debug) = &debug {
+            flags |= TerrainComputePipelineFlags::from_debug(debug);
+        }
+
+        for id in TerrainComputePipelineId::iter() {
+            self.pipelines[id as usize] =
+                pipelines.specialize(&mut pipeline_cache, &pipeline, (id, flags));
+        }
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_diagnostics = world.get_resource::<GpuTerrainDiagnostics>();
+
+        if let Some(gpu_diagnostics) = gpu_diagnostics {
+            gpu_diagnostics.write_refine_begin(&mut context.command_encoder);
+        }
+
+        self.refine_terrain(&mut context.command_encoder, world);
+
+        if let Some(frame_capture) = world.get_resource::<GpuFrameCapture>() {
+            let terrain_view_data = world.resource::<TerrainViewComponents<TerrainViewData>>();
+            frame_capture.copy(&mut context.command_encoder, terrain_view_data);
+        }
+
+        if let Some(gpu_diagnostics) = gpu_diagnostics {
+            gpu_diagnostics.write_refine_end(&mut context.command_encoder);
+        }
 
         Ok(())
     }
@@ -1,8 +1,12 @@
-use crate::{terrain::Terrain, TerrainComputePipelines, TerrainView, TerrainViewComponents};
+use crate::{
+    render::terrain_view_data::TerrainViewConfigUniform, skip_none, terrain::Terrain,
+    terrain_view::ORTHOGRAPHIC_REFERENCE_FOV, DebugTerrain, TerrainComputePipelines, TerrainView,
+    TerrainViewComponents,
+};
 use bevy::{
-    math::Vec3Swizzles,
     prelude::*,
     render::{render_resource::*, renderer::RenderDevice, view::ExtractedView},
+    utils::HashMap,
 };
 
 #[repr(C)]
@@ -12,6 +16,9 @@ pub struct CullingData {
     pub(crate) view_proj: Mat4,
     pub(crate) model: Mat4,
     pub(crate) planes: [Vec4; 5],
+    /// The projected size in pixels of one world unit at a distance of one.
+    /// Orthographic views use the projection of their virtual perspective viewer.
+    pub(crate) pixel_scale: f32,
 }
 
 #[derive(Component)]
@@ -34,25 +41,57 @@ pub fn planes(view_projection: &Mat4) -> [Vec4; 5] {
     planes
 }
 
+/// Returns the view projection and the pixel scale of the view, which are used for culling.
+pub(crate) fn culling_view(extracted_view: &ExtractedView) -> (Mat4, f32) {
+    let view_proj = extracted_view.projection * extracted_view.transform.compute_matrix().inverse();
+
+    let is_orthographic = extracted_view.projection.w_axis.w == 1.0;
+    let focal_length = if is_orthographic {
+        1.0 / (ORTHOGRAPHIC_REFERENCE_FOV / 2.0).tan()
+    } else {
+        extracted_view.projection.y_axis.y
+    };
+    let pixel_scale = extracted_view.viewport.w as f32 / 2.0 * focal_length;
+
+    (view_proj, pixel_scale)
+}
+
 pub(crate) fn queue_terrain_culling_bind_group(
     device: Res<RenderDevice>,
     compute_pipelines: Res<TerrainComputePipelines>,
     mut culling_bind_groups: ResMut<TerrainViewComponents<CullingBindGroup>>,
+    view_config_uniforms: Res<TerrainViewComponents<TerrainViewConfigUniform>>,
     terrain_query: Query<Entity, With<Terrain>>,
     view_query: Query<(Entity, &ExtractedView), With<TerrainView>>,
+    debug: Option<Res<DebugTerrain>>,
+    mut frozen_views: Local<HashMap<Entity, (Mat4, f32)>>,
 ) {
+    let freeze_view = debug.map_or(false, |debug| debug.freeze_view);
+
+    if !freeze_view {
+        frozen_views.clear();
+    }
+
     for (view, extracted_view) in view_query.iter() {
-        let view_proj =
-            extracted_view.projection * extracted_view.transform.compute_matrix().inverse();
+        let (view_proj, pixel_scale) = culling_view(extracted_view);
 
+        // keep culling against the view, which was active when the view was frozen
+        let (view_proj, pixel_scale) = if freeze_view {
+            *frozen_views.entry(view).or_insert((view_proj, pixel_scale))
+        } else {
+            (view_proj, pixel_scale)
+        };
         let planes = planes(&view_proj);
 
         for terrain in terrain_query.iter() {
+            let view_config = skip_none!(view_config_uniforms.get(&(terrain, view)));
+
             let culling_data = CullingData {
-                world_position: extracted_view.transform.translation().xyzx(),
+                world_position: view_config.viewer_position,
                 view_proj,
                 model: default(),
                 planes,
+                pixel_scale,
             };
 
             let mut buffer = encase::UniformBuffer::new(Vec::new());
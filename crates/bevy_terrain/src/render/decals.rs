@@ -0,0 +1,296 @@
+//! Runtime decals, which are projected onto the terrain surface in the fragment shader.
+//!
+//! Instead of spawning meshes that conform to the terrain, all decals are uploaded into a
+//! storage buffer. To avoid testing each fragment against every decal, the terrain is divided
+//! into a uniform grid of clusters, which each store the indices of the decals overlapping them.
+
+use crate::{
+    render::terrain_view_data::TerrainViewData,
+    terrain::{Terrain, TerrainConfig},
+    terrain_view::TerrainViewComponents,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+    utils::HashMap,
+};
+use bytemuck::{cast_slice, Pod, Zeroable};
+use std::num::NonZeroU32;
+
+/// The maximum number of decals, that can be rendered at once.
+pub const MAX_DECALS: usize = 256;
+/// The maximum number of distinct decal textures.
+pub const MAX_DECAL_TEXTURES: u32 = 16;
+/// The required size of the decal textures in pixels.
+pub const DECAL_TEXTURE_SIZE: u32 = 256;
+/// The number of decal clusters in x and y direction. Has to match the shader.
+pub(crate) const DECAL_CLUSTER_COUNT: u32 = 32;
+/// The maximum number of decal references of all clusters combined.
+pub(crate) const MAX_DECAL_INDICES: usize = 4096;
+
+/// A decal, which is projected top down onto the terrain.
+///
+/// The texture has to be a [`DECAL_TEXTURE_SIZE`] squared `Rgba8UnormSrgb` image.
+#[derive(Clone, Component)]
+pub struct TerrainDecal {
+    /// The center of the decal in the horizontal (x, z) plane of the terrain.
+    pub position: Vec2,
+    /// The width and length of the decal.
+    pub size: Vec2,
+    /// The rotation of the decal around the vertical axis in radians.
+    pub rotation: f32,
+    /// The texture of the decal.
+    pub texture: Handle<Image>,
+    /// The tint of the decal, whose alpha scales the opacity.
+    pub color: Color,
+}
+
+impl Default for TerrainDecal {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            size: Vec2::ONE,
+            rotation: 0.0,
+            texture: default(),
+            color: Color::WHITE,
+        }
+    }
+}
+
+/// The representation of a decal in the decal buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+struct GpuDecal {
+    position: [f32; 2],
+    half_size: [f32; 2],
+    rotation: [f32; 2],
+    layer: u32,
+    _padding: u32,
+    color: [f32; 4],
+}
+
+/// Stores the textures of all decals in the layers of a single texture array.
+#[derive(Resource)]
+pub struct GpuDecalAtlas {
+    pub(crate) texture_view: TextureView,
+    pub(crate) sampler: Sampler,
+    texture: Texture,
+    layers: HashMap<Handle<Image>, u32>,
+    /// Textures, which have been extracted this frame, but not yet written into the atlas.
+    pending_textures: Vec<(u32, Vec<u8>)>,
+}
+
+impl FromWorld for GpuDecalAtlas {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: "decal_atlas".into(),
+            size: Extent3d {
+                width: DECAL_TEXTURE_SIZE,
+                height: DECAL_TEXTURE_SIZE,
+                depth_or_array_layers: MAX_DECAL_TEXTURES,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let texture_view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..default()
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..default()
+        });
+
+        Self {
+            texture_view,
+            sampler,
+            texture,
+            layers: default(),
+            pending_textures: default(),
+        }
+    }
+}
+
+impl GpuDecalAtlas {
+    /// Returns the layer of the texture, or assigns a new one if the texture is not in the atlas yet.
+    fn layer(&mut self, handle: &Handle<Image>, images: &Assets<Image>) -> Option<u32> {
+        if let Some(&layer) = self.layers.get(handle) {
+            return Some(layer);
+        }
+
+        let image = images.get(handle)?;
+        let layer = self.layers.len() as u32;
+
+        if layer >= MAX_DECAL_TEXTURES {
+            return None;
+        }
+
+        if image.texture_descriptor.format != TextureFormat::Rgba8UnormSrgb
+            || image.size() != Vec2::splat(DECAL_TEXTURE_SIZE as f32)
+        {
+            warn!("Decal textures have to be {DECAL_TEXTURE_SIZE}x{DECAL_TEXTURE_SIZE} Rgba8UnormSrgb images.");
+            return None;
+        }
+
+        let size = (DECAL_TEXTURE_SIZE * DECAL_TEXTURE_SIZE * 4) as usize;
+
+        self.layers.insert(handle.clone_weak(), layer);
+        self.pending_textures
+            .push((layer, image.data[..size].to_vec()));
+
+        Some(layer)
+    }
+}
+
+/// The decals of all terrains, sorted into clusters.
+#[derive(Default, Resource)]
+pub struct ExtractedTerrainDecals {
+    decals: Vec<GpuDecal>,
+    /// The clusters of each terrain, consisting of the offset and count into the indices.
+    clusters: HashMap<Entity, (Vec<[u32; 2]>, Vec<u32>)>,
+}
+
+impl ExtractedTerrainDecals {
+    /// Sorts the decals into the clusters of a terrain with the size.
+    fn cluster(&self, terrain_size: f32) -> (Vec<[u32; 2]>, Vec<u32>) {
+        let cluster_size = terrain_size / DECAL_CLUSTER_COUNT as f32;
+        let cluster_count = (DECAL_CLUSTER_COUNT * DECAL_CLUSTER_COUNT) as usize;
+
+        let mut cluster_decals = vec![Vec::new(); cluster_count];
+
+        for (index, decal) in self.decals.iter().enumerate() {
+            // the bounding square of the rotated decal
+            let radius = Vec2::from(decal.half_size).length();
+            let position = Vec2::from(decal.position);
+            let max_cluster = (DECAL_CLUSTER_COUNT - 1) as f32;
+
+            let first = ((position - radius) / cluster_size)
+                .clamp(Vec2::ZERO, Vec2::splat(max_cluster))
+                .as_uvec2();
+            let last = ((position + radius) / cluster_size)
+                .clamp(Vec2::ZERO, Vec2::splat(max_cluster))
+                .as_uvec2();
+
+            for y in first.y..=last.y {
+                for x in first.x..=last.x {
+                    cluster_decals[(y * DECAL_CLUSTER_COUNT + x) as usize].push(index as u32);
+                }
+            }
+        }
+
+        let mut clusters = Vec::with_capacity(cluster_count);
+        let mut indices = Vec::new();
+
+        for decals in cluster_decals {
+            let count = decals.len().min(MAX_DECAL_INDICES - indices.len());
+
+            clusters.push([indices.len() as u32, count as u32]);
+            indices.extend_from_slice(&decals[..count]);
+        }
+
+        (clusters, indices)
+    }
+}
+
+pub(crate) fn extract_terrain_decals(
+    mut extracted_decals: ResMut<ExtractedTerrainDecals>,
+    mut decal_atlas: ResMut<GpuDecalAtlas>,
+    images: Extract<Res<Assets<Image>>>,
+    decal_query: Extract<Query<&TerrainDecal>>,
+    terrain_query: Extract<Query<(Entity, &TerrainConfig), With<Terrain>>>,
+) {
+    extracted_decals.decals.clear();
+    extracted_decals.clusters.clear();
+
+    for decal in decal_query.iter().take(MAX_DECALS) {
+        let layer = match decal_atlas.layer(&decal.texture, &images) {
+            Some(layer) => layer,
+            None => continue,
+        };
+
+        extracted_decals.decals.push(GpuDecal {
+            position: decal.position.to_array(),
+            half_size: (decal.size / 2.0).to_array(),
+            rotation: [decal.rotation.cos(), decal.rotation.sin()],
+            layer,
+            _padding: 0,
+            color: decal.color.as_linear_rgba_f32(),
+        });
+    }
+
+    for (terrain, config) in terrain_query.iter() {
+        let clusters = extracted_decals.cluster(config.terrain_size as f32);
+        extracted_decals.clusters.insert(terrain, clusters);
+    }
+}
+
+pub(crate) fn queue_terrain_decals(
+    queue: Res<RenderQueue>,
+    mut decal_atlas: ResMut<GpuDecalAtlas>,
+    extracted_decals: Res<ExtractedTerrainDecals>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+) {
+    let GpuDecalAtlas {
+        ref texture,
+        ref mut pending_textures,
+        ..
+    } = decal_atlas.as_mut();
+
+    for (layer, data) in pending_textures.drain(..) {
+        queue.write_texture(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: TextureAspect::All,
+            },
+            &data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(DECAL_TEXTURE_SIZE * 4),
+                rows_per_image: NonZeroU32::new(DECAL_TEXTURE_SIZE),
+            },
+            Extent3d {
+                width: DECAL_TEXTURE_SIZE,
+                height: DECAL_TEXTURE_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    for (&(terrain, _), data) in &terrain_view_data.0 {
+        if data.primary.is_some() {
+            continue; // dependent views share the decal buffers of their primary view
+        }
+
+        let (clusters, indices) = match extracted_decals.clusters.get(&terrain) {
+            Some(clusters) => clusters,
+            None => continue,
+        };
+
+        if !extracted_decals.decals.is_empty() {
+            queue.write_buffer(&data.decal_buffer, 0, cast_slice(&extracted_decals.decals));
+        }
+        if !indices.is_empty() {
+            queue.write_buffer(&data.decal_index_buffer, 0, cast_slice(indices));
+        }
+
+        queue.write_buffer(&data.decal_cluster_buffer, 0, cast_slice(clusters));
+    }
+}
@@ -0,0 +1,428 @@
+//! GPU timings and statistics of the terrain rendering, exposed as Bevy [`Diagnostics`].
+//!
+//! The duration of the compute refinement and of the main pass, which contains the terrain draws,
+//! is measured using timestamp queries. Additionally the number of drawn patches (tiles) and
+//! vertices of all terrain views is read back from the indirect buffers.
+//! The measurements become available a few frames after they have been recorded.
+//!
+//! Timestamp queries are an optional feature of the GPU and have to be requested explicitly,
+//! by inserting `WgpuSettings { features: WgpuFeatures::TIMESTAMP_QUERY, ..default() }`
+//! before adding the `DefaultPlugins`. Without them only the statistics are measured.
+
+use crate::{
+    render::terrain_view_data::{TerrainViewConfigUniform, TerrainViewData},
+    terrain_view::TerrainViewComponents,
+};
+use bevy::{
+    core_pipeline::core_3d,
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+    render::{
+        main_graph::node::CAMERA_DRIVER,
+        render_graph::{self, RenderGraph},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        RenderApp, RenderStage,
+    },
+};
+use bytemuck::cast_slice;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use wgpu::{Maintain, QuerySet, QuerySetDescriptor, QueryType};
+
+/// The duration of the compute refinement of all terrain views in milliseconds.
+pub const TERRAIN_REFINE_MS: DiagnosticId =
+    DiagnosticId::from_u128(139465280546216340948367015431295837729);
+/// The duration of the main pass, which contains the terrain draws, in milliseconds.
+pub const TERRAIN_DRAW_MS: DiagnosticId =
+    DiagnosticId::from_u128(247916302614806927305125381207417845016);
+/// The number of patches (tiles) drawn for all terrain views.
+pub const TERRAIN_PATCHES_DRAWN: DiagnosticId =
+    DiagnosticId::from_u128(61729043870192357360188290167508423154);
+/// The number of vertices drawn for all terrain views.
+pub const TERRAIN_VERTICES: DiagnosticId =
+    DiagnosticId::from_u128(301485725026693641876049265531180978863);
+
+const TIMESTAMP_COUNT: u32 = 4;
+const TIMESTAMP_SIZE: BufferAddress = 8;
+const REFINE_BEGIN: u32 = 0;
+const REFINE_END: u32 = 1;
+const DRAW_BEGIN: u32 = 2;
+const DRAW_END: u32 = 3;
+
+/// Measures the GPU timings and statistics of the terrain rendering and reports them
+/// as [`Diagnostics`] named `terrain/refine_ms`, `terrain/draw_ms`, `terrain/patches_drawn`
+/// and `terrain/vertices`.
+///
+/// If multiple cameras are rendered, `terrain/draw_ms` measures the main pass of the last one.
+pub struct TerrainDiagnosticsPlugin;
+
+impl Plugin for TerrainDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let finished = FinishedMeasurements::default();
+
+        app.insert_resource(finished.clone())
+            .add_startup_system(setup_terrain_diagnostics)
+            .add_system(update_terrain_diagnostics);
+
+        let render_app = app.sub_app_mut(RenderApp);
+
+        let device = render_app.world.resource::<RenderDevice>();
+        let queue = render_app.world.resource::<RenderQueue>();
+        let gpu_diagnostics = GpuTerrainDiagnostics::new(device, queue, finished);
+
+        render_app
+            .insert_resource(gpu_diagnostics)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_diagnostics);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+
+        render_graph.add_node("terrain_diagnostics", TerrainDiagnosticsNode::default());
+        render_graph
+            .add_node_edge(CAMERA_DRIVER, "terrain_diagnostics")
+            .unwrap();
+
+        let draw_graph = render_graph
+            .get_sub_graph_mut(core_3d::graph::NAME)
+            .unwrap();
+
+        draw_graph.add_node("terrain_draw_begin", TimestampNode(DRAW_BEGIN));
+        draw_graph.add_node("terrain_draw_end", TimestampNode(DRAW_END));
+        draw_graph
+            .add_node_edge("terrain_draw_begin", core_3d::graph::node::MAIN_PASS)
+            .unwrap();
+        draw_graph
+            .add_node_edge(core_3d::graph::node::MAIN_PASS, "terrain_draw_end")
+            .unwrap();
+    }
+}
+
+/// The measurements of a single frame.
+#[derive(Clone, Copy, Default)]
+struct TerrainMeasurements {
+    refine_ms: Option<f64>,
+    draw_ms: Option<f64>,
+    patches_drawn: u32,
+    vertices: u32,
+}
+
+/// Shared with the render world, which stores the most recently read back measurements.
+#[derive(Clone, Default, Resource)]
+struct FinishedMeasurements(Arc<Mutex<Option<TerrainMeasurements>>>);
+
+fn setup_terrain_diagnostics(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(TERRAIN_REFINE_MS, "terrain/refine_ms", 20));
+    diagnostics.add(Diagnostic::new(TERRAIN_DRAW_MS, "terrain/draw_ms", 20));
+    diagnostics.add(Diagnostic::new(
+        TERRAIN_PATCHES_DRAWN,
+        "terrain/patches_drawn",
+        20,
+    ));
+    diagnostics.add(Diagnostic::new(TERRAIN_VERTICES, "terrain/vertices", 20));
+}
+
+/// Adds the measurements, that have been read back in the meantime.
+fn update_terrain_diagnostics(
+    finished: Res<FinishedMeasurements>,
+    mut diagnostics: ResMut<Diagnostics>,
+) {
+    let measurements = match finished.0.lock().unwrap().take() {
+        Some(measurements) => measurements,
+        None => return,
+    };
+
+    if let Some(refine_ms) = measurements.refine_ms {
+        diagnostics.add_measurement(TERRAIN_REFINE_MS, refine_ms);
+    }
+    if let Some(draw_ms) = measurements.draw_ms {
+        diagnostics.add_measurement(TERRAIN_DRAW_MS, draw_ms);
+    }
+
+    diagnostics.add_measurement(TERRAIN_PATCHES_DRAWN, measurements.patches_drawn as f64);
+    diagnostics.add_measurement(TERRAIN_VERTICES, measurements.vertices as f64);
+}
+
+/// The staging buffers, into which the timestamps and vertex counts of a frame are copied.
+struct DiagnosticsReadback {
+    timestamp_buffer: Option<Buffer>,
+    vertex_count_buffer: Option<Buffer>,
+    /// The (terrain, view) pairs, whose vertex counts are copied, and their vertices per tile.
+    views: Vec<((Entity, Entity), u32)>,
+    /// Counts the buffers, that have been mapped.
+    mapped: Arc<AtomicUsize>,
+}
+
+impl DiagnosticsReadback {
+    fn buffers(&self) -> impl Iterator<Item = &Buffer> {
+        self.timestamp_buffer
+            .iter()
+            .chain(self.vertex_count_buffer.iter())
+    }
+
+    /// Starts mapping the staging buffers, after the copies have been submitted.
+    fn map(&self) {
+        for buffer in self.buffers() {
+            let mapped = self.mapped.clone();
+
+            buffer.slice(..).map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.fetch_add(1, Ordering::Release);
+                }
+            });
+        }
+    }
+
+    fn is_mapped(&self) -> bool {
+        self.mapped.load(Ordering::Acquire) == self.buffers().count()
+    }
+}
+
+/// Stores the timestamp queries and the readback of the [`TerrainDiagnosticsPlugin`].
+#[derive(Resource)]
+pub struct GpuTerrainDiagnostics {
+    /// Only available, if the device supports timestamp queries.
+    query_set: Option<QuerySet>,
+    resolve_buffer: Option<Buffer>,
+    /// The number of nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// The readback, whose copies are recorded this frame.
+    recording: Option<DiagnosticsReadback>,
+    /// The readback, whose copies have been submitted and which is being mapped.
+    in_flight: Option<DiagnosticsReadback>,
+    finished: FinishedMeasurements,
+}
+
+impl GpuTerrainDiagnostics {
+    fn new(device: &RenderDevice, queue: &RenderQueue, finished: FinishedMeasurements) -> Self {
+        let timestamps = device.features().contains(WgpuFeatures::TIMESTAMP_QUERY);
+
+        if !timestamps {
+            info!(
+                "Timestamp queries are not enabled, thus the terrain GPU timings are not measured."
+            );
+        }
+
+        let query_set = timestamps.then(|| {
+            device.wgpu_device().create_query_set(&QuerySetDescriptor {
+                label: Some("terrain_timestamp_query_set"),
+                ty: QueryType::Timestamp,
+                count: TIMESTAMP_COUNT,
+            })
+        });
+
+        let resolve_buffer = timestamps.then(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: "terrain_timestamp_resolve_buffer".into(),
+                size: TIMESTAMP_COUNT as BufferAddress * TIMESTAMP_SIZE,
+                usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            timestamp_period: queue.get_timestamp_period(),
+            recording: None,
+            in_flight: None,
+            finished,
+        }
+    }
+
+    /// Records a timestamp, if timestamp queries are supported.
+    fn write_timestamp(&self, command_encoder: &mut CommandEncoder, index: u32) {
+        if let Some(query_set) = &self.query_set {
+            command_encoder.write_timestamp(query_set, index);
+        }
+    }
+
+    pub(crate) fn write_refine_begin(&self, command_encoder: &mut CommandEncoder) {
+        self.write_timestamp(command_encoder, REFINE_BEGIN);
+    }
+
+    pub(crate) fn write_refine_end(&self, command_encoder: &mut CommandEncoder) {
+        self.write_timestamp(command_encoder, REFINE_END);
+    }
+
+    fn duration_ms(&self, timestamps: &[u64], begin: u32, end: u32) -> Option<f64> {
+        let (begin, end) = (timestamps[begin as usize], timestamps[end as usize]);
+
+        // the timestamps have not been written, e.g. because no camera was rendered
+        if begin == 0 || end < begin {
+            return None;
+        }
+
+        Some((end - begin) as f64 * self.timestamp_period as f64 / 1_000_000.0)
+    }
+
+    /// Moves the measurements of the mapped readback into the shared measurements.
+    fn finish(&mut self) {
+        let readback = match &self.in_flight {
+            Some(readback) if readback.is_mapped() => readback,
+            _ => return,
+        };
+
+        let mut measurements = TerrainMeasurements::default();
+
+        if let Some(buffer) = &readback.timestamp_buffer {
+            let timestamps = cast_slice::<u8, u64>(&buffer.slice(..).get_mapped_range()).to_vec();
+
+            measurements.refine_ms = self.duration_ms(&timestamps, REFINE_BEGIN, REFINE_END);
+            measurements.draw_ms = self.duration_ms(&timestamps, DRAW_BEGIN, DRAW_END);
+        }
+
+        if let Some(buffer) = &readback.vertex_count_buffer {
+            for (&[count, instance_count], &(_, vertices_per_tile)) in
+                cast_slice::<u8, [u32; 2]>(&buffer.slice(..).get_mapped_range())
+                    .iter()
+                    .zip(&readback.views)
+            {
+                let vertices = count * instance_count;
+
+                measurements.vertices += vertices;
+                measurements.patches_drawn += vertices / vertices_per_tile.max(1);
+            }
+        }
+
+        for buffer in readback.buffers() {
+            buffer.unmap();
+        }
+
+        *self.finished.0.lock().unwrap() = Some(measurements);
+        self.in_flight = None;
+    }
+}
+
+/// Collects the finished measurements, maps the readback submitted last frame and
+/// prepares a new readback, once the previous one has finished.
+fn queue_terrain_diagnostics(
+    device: Res<RenderDevice>,
+    view_config_uniforms: Res<TerrainViewComponents<TerrainViewConfigUniform>>,
+    mut gpu_diagnostics: ResMut<GpuTerrainDiagnostics>,
+) {
+    device.wgpu_device().poll(Maintain::Poll);
+
+    gpu_diagnostics.finish();
+
+    // only one readback is in flight at a time
+    if gpu_diagnostics.in_flight.is_some() {
+        return;
+    }
+
+    // the readback recorded last frame has been submitted by now
+    if let Some(readback) = gpu_diagnostics.recording.take() {
+        readback.map();
+        gpu_diagnostics.in_flight = Some(readback);
+        return;
+    }
+
+    let views: Vec<_> = view_config_uniforms
+        .0
+        .iter()
+        .map(|(&key, view_config)| (key, view_config.vertices_per_tile))
+        .collect();
+
+    let timestamp_buffer = gpu_diagnostics.query_set.is_some().then(|| {
+        device.create_buffer(&BufferDescriptor {
+            label: "terrain_timestamp_staging_buffer".into(),
+            size: TIMESTAMP_COUNT as BufferAddress * TIMESTAMP_SIZE,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    });
+
+    let vertex_count_buffer = (!views.is_empty()).then(|| {
+        device.create_buffer(&BufferDescriptor {
+            label: "terrain_vertex_count_staging_buffer".into(),
+            size: views.len() as BufferAddress * 8,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    });
+
+    gpu_diagnostics.recording = Some(DiagnosticsReadback {
+        timestamp_buffer,
+        vertex_count_buffer,
+        views,
+        mapped: default(),
+    });
+}
+
+/// Writes a timestamp into the query set.
+struct TimestampNode(u32);
+
+impl render_graph::Node for TimestampNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_diagnostics = world.resource::<GpuTerrainDiagnostics>();
+        gpu_diagnostics.write_timestamp(&mut context.command_encoder, self.0);
+
+        Ok(())
+    }
+}
+
+/// Copies the timestamps and the vertex counts of the terrain views into the staging buffers
+/// of the readback, after all views have been rendered.
+#[derive(Default)]
+struct TerrainDiagnosticsNode;
+
+impl render_graph::Node for TerrainDiagnosticsNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let gpu_diagnostics = world.resource::<GpuTerrainDiagnostics>();
+        let terrain_view_data = world.resource::<TerrainViewComponents<TerrainViewData>>();
+
+        let readback = match &gpu_diagnostics.recording {
+            Some(readback) => readback,
+            None => return Ok(()),
+        };
+
+        if let (Some(query_set), Some(resolve_buffer), Some(timestamp_buffer)) = (
+            &gpu_diagnostics.query_set,
+            &gpu_diagnostics.resolve_buffer,
+            &readback.timestamp_buffer,
+        ) {
+            context.command_encoder.resolve_query_set(
+                query_set,
+                0..TIMESTAMP_COUNT,
+                resolve_buffer,
+                0,
+            );
+            context.command_encoder.copy_buffer_to_buffer(
+                resolve_buffer,
+                0,
+                timestamp_buffer,
+                0,
+                TIMESTAMP_COUNT as BufferAddress * TIMESTAMP_SIZE,
+            );
+        }
+
+        if let Some(vertex_count_buffer) = &readback.vertex_count_buffer {
+            for (index, (key, _)) in readback.views.iter().enumerate() {
+                if let Some(view_data) = terrain_view_data.get(key) {
+                    // the first two entries of the indirect buffer are the vertex (or index)
+                    // count and the instance count of the draw
+                    context.command_encoder.copy_buffer_to_buffer(
+                        &view_data.indirect_buffer,
+                        view_data.indirect_offset,
+                        vertex_count_buffer,
+                        index as BufferAddress * 8,
+                        8,
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
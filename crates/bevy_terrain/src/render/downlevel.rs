@@ -0,0 +1,283 @@
+//! The fallback for downlevel devices (e.g. WebGL2), which support neither compute shaders
+//! nor storage buffers.
+//!
+//! Instead of refining the tiles in a compute shader prepass, the refinement and the culling are
+//! performed on the CPU each frame.
+//! The resulting tiles are uploaded into the tile buffer of the view, which is bound as an
+//! instance buffer, and drawn with a regular draw call.
+//!
+//! Only the default distance based refinement heuristic is supported.
+//! Features, which depend on compute shaders or storage buffers (vegetation, water, vector layers,
+//! decals, height queries, erosion and frame captures), are not available in this mode.
+
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+use crate::terrain_view::TerrainViewConfig;
+use crate::{
+    render::{
+        culling::{culling_view, planes},
+        terrain_view_data::{TerrainViewConfigUniform, TerrainViewData},
+    },
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    DebugTerrain, TerrainView, TerrainViewComponents,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+        Extract,
+    },
+};
+
+/// The bindings of the terrain view layout, which are storage buffers
+/// (tiles, decals, decal clusters, decal indices and page feedback).
+const STORAGE_BINDINGS: [u32; 5] = [2, 3, 4, 5, 17];
+
+/// The way the tiles of the terrain are refined and drawn.
+///
+/// It is selected automatically from the limits of the [`RenderDevice`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Resource)]
+pub enum TerrainRenderMode {
+    /// Refines the tiles in a compute shader and draws them indirectly.
+    #[default]
+    Compute,
+    /// Refines the tiles on the CPU and draws them as instances.
+    Downlevel,
+}
+
+impl TerrainRenderMode {
+    /// Selects the downlevel mode, if the device supports neither compute shaders nor storage buffers.
+    pub fn from_device(device: &RenderDevice) -> Self {
+        let limits = device.limits();
+
+        if limits.max_storage_buffers_per_shader_stage == 0
+            || limits.max_compute_workgroups_per_dimension == 0
+        {
+            Self::Downlevel
+        } else {
+            Self::Compute
+        }
+    }
+
+    /// Returns whether the binding of the terrain view layout is available in this mode.
+    pub(crate) fn supports_binding(self, binding: u32) -> bool {
+        self == Self::Compute || !STORAGE_BINDINGS.contains(&binding)
+    }
+}
+
+/// The parameters of a terrain, which are required by the CPU refinement.
+#[derive(Clone, Copy)]
+pub(crate) struct DownlevelTerrain {
+    terrain_size: f32,
+    height: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Tile {
+    coords: UVec2,
+    size: u32,
+}
+
+impl Tile {
+    fn children(self) -> impl Iterator<Item = Tile> {
+        (0..4).map(move |i| Tile {
+            coords: UVec2::new(
+                (self.coords.x << 1) + (i & 1),
+                (self.coords.y << 1) + (i >> 1 & 1),
+            ),
+            size: self.size >> 1,
+        })
+    }
+}
+
+/// Refines the tiles of a single terrain view, the same way the `refine_tiles` compute shader does.
+struct Refinement<'a> {
+    terrain: DownlevelTerrain,
+    view_config: &'a TerrainViewConfigUniform,
+    planes: [Vec4; 5],
+    pixel_scale: f32,
+}
+
+impl<'a> Refinement<'a> {
+    fn approximate_world_position(&self, local_position: Vec2) -> Vec3 {
+        Vec3::new(
+            local_position.x,
+            self.view_config.height_under_viewer,
+            local_position.y,
+        )
+    }
+
+    fn tile_size(&self, tile: Tile) -> f32 {
+        tile.size as f32 * self.view_config.tile_scale
+    }
+
+    fn frustum_cull(&self, tile: Tile) -> bool {
+        let size = self.tile_size(tile);
+        let tile_min = tile.coords.as_vec2() * size;
+
+        // 2D frustum culling
+        let aabb_min = Vec3::new(tile_min.x, 0.0, tile_min.y);
+        let aabb_max = Vec3::new(tile_min.x + size, self.terrain.height, tile_min.y + size);
+
+        self.planes.iter().any(|plane| {
+            // the corner, which is furthest along the plane normal
+            let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), aabb_max, aabb_min);
+
+            plane.dot(corner.extend(1.0)) < 0.0
+        })
+    }
+
+    fn outside_cull(&self, tile: Tile) -> bool {
+        // cull tiles outside of the terrain
+        let local_position = (tile.coords * tile.size).as_vec2() * self.view_config.tile_scale;
+
+        local_position.x > self.terrain.terrain_size || local_position.y > self.terrain.terrain_size
+    }
+
+    fn cull(&self, tile: Tile) -> bool {
+        self.outside_cull(tile) || self.frustum_cull(tile)
+    }
+
+    fn should_be_divided(&self, tile: Tile) -> bool {
+        if tile.size == 1 {
+            return false;
+        }
+
+        let viewer_position = self.view_config.viewer_position.truncate();
+
+        let dist = (0..4)
+            .map(|i| {
+                let corner_coords = tile.coords + UVec2::new(i & 1, i >> 1 & 1);
+                let local_position =
+                    (corner_coords * tile.size).as_vec2() * self.view_config.tile_scale;
+
+                self.approximate_world_position(local_position)
+                    .distance(viewer_position)
+            })
+            .fold(f32::MAX, f32::min);
+
+        dist < self.view_config.morph_distance * tile.size as f32
+    }
+
+    fn below_min_triangle_size(&self, tile: Tile) -> bool {
+        let size = self.tile_size(tile);
+        let tile_min = tile.coords.as_vec2() * size;
+        let viewer_position = self.view_config.viewer_position.truncate();
+
+        // the closest point of the tile to the viewer
+        let local_position =
+            Vec2::new(viewer_position.x, viewer_position.z).clamp(tile_min, tile_min + size);
+        let world_position = self.approximate_world_position(local_position);
+        let dist = world_position.distance(viewer_position).max(0.0001);
+
+        // the edge length of the triangles after the subdivision
+        let edge_length = size / (2.0 * self.view_config.grid_size);
+
+        edge_length / dist * self.pixel_scale < self.view_config.min_triangle_size
+    }
+
+    /// Returns the final tiles in the layout of the tile buffer.
+    fn refine(&self) -> Vec<[u32; 4]> {
+        let mut tiles = vec![Tile {
+            coords: UVec2::ZERO,
+            size: 1 << self.view_config.refinement_count.saturating_sub(1),
+        }];
+        let mut final_tiles = Vec::new();
+
+        for _ in 0..=self.view_config.refinement_count {
+            let mut children = Vec::new();
+
+            for tile in tiles {
+                if self.should_be_divided(tile) && !self.below_min_triangle_size(tile) {
+                    children.extend(tile.children().filter(|&child| !self.cull(child)));
+                } else {
+                    final_tiles.push(tile);
+                }
+            }
+
+            tiles = children;
+        }
+
+        final_tiles
+            .into_iter()
+            .take(self.view_config.tile_count as usize)
+            .map(|tile| [tile.coords.x, tile.coords.y, tile.size, 0])
+            .collect()
+    }
+}
+
+/// Refines the tiles of a terrain view on the CPU, as seen through the view projection.
+///
+/// This lets the test harness check the tiles without a renderer.
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+pub(crate) fn refine_view(
+    config: &TerrainConfig,
+    view_config: &TerrainViewConfig,
+    view_proj: Mat4,
+    pixel_scale: f32,
+) -> Vec<[u32; 4]> {
+    let view_config = TerrainViewConfigUniform::new(config, view_config, false);
+
+    Refinement {
+        terrain: DownlevelTerrain {
+            terrain_size: config.terrain_size as f32,
+            height: config.height,
+        },
+        view_config: &view_config,
+        planes: planes(&view_proj),
+        pixel_scale,
+    }
+    .refine()
+}
+
+pub(crate) fn extract_downlevel_terrains(
+    mut terrains: ResMut<TerrainComponents<DownlevelTerrain>>,
+    terrain_query: Extract<Query<(Entity, &TerrainConfig), With<Terrain>>>,
+) {
+    terrains.0.clear();
+
+    for (terrain, config) in terrain_query.iter() {
+        terrains.insert(
+            terrain,
+            DownlevelTerrain {
+                terrain_size: config.terrain_size as f32,
+                height: config.height,
+            },
+        );
+    }
+}
+
+/// Refines the tiles of all terrain views on the CPU and uploads them for rendering.
+pub(crate) fn queue_downlevel_tiles(
+    queue: Res<RenderQueue>,
+    terrains: Res<TerrainComponents<DownlevelTerrain>>,
+    view_config_uniforms: Res<TerrainViewComponents<TerrainViewConfigUniform>>,
+    mut terrain_view_data: ResMut<TerrainViewComponents<TerrainViewData>>,
+    view_query: Query<(Entity, &ExtractedView), With<TerrainView>>,
+    debug: Option<Res<DebugTerrain>>,
+) {
+    if debug.map_or(false, |debug| debug.freeze) {
+        return;
+    }
+
+    for (view, extracted_view) in view_query.iter() {
+        let (view_proj, pixel_scale) = culling_view(extracted_view);
+        let planes = planes(&view_proj);
+
+        for (&terrain, &downlevel_terrain) in &terrains.0 {
+            let view_config = skip_none!(view_config_uniforms.get(&(terrain, view)));
+            let view_data = skip_none!(terrain_view_data.get_mut(&(terrain, view)));
+
+            let tiles = Refinement {
+                terrain: downlevel_terrain,
+                view_config,
+                planes,
+                pixel_scale,
+            }
+            .refine();
+
+            view_data.write_downlevel_tiles(&queue, &tiles);
+        }
+    }
+}
@@ -12,12 +12,26 @@ use crate::render::{
 use bevy::render::render_resource::*;
 use std::mem;
 
+pub mod atmosphere;
+pub mod color_ramp;
 pub mod compute_pipelines;
 pub mod culling;
+pub mod decals;
+pub mod diagnostics;
+pub mod downlevel;
 pub mod render_pipeline;
 pub mod shaders;
+pub mod soft_intersection;
+pub mod standard_material;
 pub mod terrain_data;
+pub mod terrain_textures;
 pub mod terrain_view_data;
+pub mod tint;
+pub mod vector_layer;
+pub mod vegetation;
+pub mod viewshed;
+pub mod water;
+pub mod weather;
 
 pub(crate) const TERRAIN_CONFIG_SIZE: BufferAddress =
     mem::size_of::<TerrainConfigUniform>() as BufferAddress;
@@ -26,6 +40,13 @@ pub(crate) const TERRAIN_VIEW_CONFIG_SIZE: BufferAddress =
 pub(crate) const CULL_DATA_BUFFER_SIZE: BufferAddress =
     mem::size_of::<CullingData>() as BufferAddress;
 pub(crate) const TILE_SIZE: BufferAddress = 6 * 4;
+pub(crate) const DECAL_SIZE: BufferAddress = 12 * 4;
+pub(crate) const DECAL_CLUSTER_SIZE: BufferAddress = 2 * 4;
+pub(crate) const DECAL_INDEX_SIZE: BufferAddress = 4;
+pub(crate) const VEGETATION_INSTANCE_SIZE: BufferAddress = 4 * 4;
+pub(crate) const VEGETATION_INDIRECT_BUFFER_SIZE: BufferAddress = 4 * 4;
+pub(crate) const HEIGHT_QUERY_POSITION_SIZE: BufferAddress = 2 * 4;
+pub(crate) const HEIGHT_QUERY_RESULT_SIZE: BufferAddress = 4 * 4;
 pub(crate) const INDIRECT_BUFFER_SIZE: BufferAddress = 5 * 4;
 pub(crate) const PARAMETER_BUFFER_SIZE: BufferAddress = 7 * 4;
 
@@ -160,5 +181,257 @@ pub(crate) const TERRAIN_VIEW_LAYOUT: BindGroupLayoutDescriptor = BindGroupLayou
             },
             count: None,
         },
+        // decals
+        BindGroupLayoutEntry {
+            binding: 3,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(DECAL_SIZE),
+            },
+            count: None,
+        },
+        // decal clusters
+        BindGroupLayoutEntry {
+            binding: 4,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(DECAL_CLUSTER_SIZE),
+            },
+            count: None,
+        },
+        // decal indices
+        BindGroupLayoutEntry {
+            binding: 5,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(DECAL_INDEX_SIZE),
+            },
+            count: None,
+        },
+        // decal atlas
+        BindGroupLayoutEntry {
+            binding: 6,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // decal sampler
+        BindGroupLayoutEntry {
+            binding: 7,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        },
+        // color ramp
+        BindGroupLayoutEntry {
+            binding: 8,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // color ramp sampler
+        BindGroupLayoutEntry {
+            binding: 9,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        },
+        // atmosphere
+        BindGroupLayoutEntry {
+            binding: 10,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // tint
+        BindGroupLayoutEntry {
+            binding: 11,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // weather
+        BindGroupLayoutEntry {
+            binding: 12,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // viewshed
+        BindGroupLayoutEntry {
+            binding: 13,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // virtual texture
+        BindGroupLayoutEntry {
+            binding: 14,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // page table
+        BindGroupLayoutEntry {
+            binding: 15,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Uint,
+                view_dimension: TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // page cache
+        BindGroupLayoutEntry {
+            binding: 16,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        // page feedback
+        BindGroupLayoutEntry {
+            binding: 17,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(4),
+            },
+            count: None,
+        },
+    ],
+};
+
+pub(crate) const SCATTER_VEGETATION_LAYOUT: BindGroupLayoutDescriptor = BindGroupLayoutDescriptor {
+    label: None,
+    entries: &[
+        // vegetation config
+        BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // instances
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(VEGETATION_INSTANCE_SIZE),
+            },
+            count: None,
+        },
+        // indirect buffer
+        BindGroupLayoutEntry {
+            binding: 2,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(VEGETATION_INDIRECT_BUFFER_SIZE),
+            },
+            count: None,
+        },
+    ],
+};
+
+pub(crate) const VEGETATION_LAYOUT: BindGroupLayoutDescriptor = BindGroupLayoutDescriptor {
+    label: None,
+    entries: &[
+        // vegetation config
+        BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // instances
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(VEGETATION_INSTANCE_SIZE),
+            },
+            count: None,
+        },
+    ],
+};
+
+pub(crate) const HEIGHT_QUERY_LAYOUT: BindGroupLayoutDescriptor = BindGroupLayoutDescriptor {
+    label: None,
+    entries: &[
+        // positions
+        BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(HEIGHT_QUERY_POSITION_SIZE),
+            },
+            count: None,
+        },
+        // results
+        BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: BufferSize::new(HEIGHT_QUERY_RESULT_SIZE),
+            },
+            count: None,
+        },
     ],
 };
@@ -1,11 +1,16 @@
 use crate::{
     render::{
-        shaders::DEFAULT_SHADER,
-        terrain_data::{terrain_bind_group_layout, SetTerrainBindGroup},
-        terrain_view_data::{DrawTerrainCommand, SetTerrainViewBindGroup},
-        TERRAIN_VIEW_LAYOUT,
+        downlevel::TerrainRenderMode,
+        shaders::{DEFAULT_SHADER, FALLBACK_SHADER},
+        terrain_data::{terrain_bind_group_layout, SetTerrainBindGroup, TerrainData},
+        terrain_view_data::{
+            terrain_view_layout, DrawTerrainCommand, SetTerrainViewBindGroup, TerrainViewData,
+        },
     },
-    DebugTerrain, Terrain,
+    skip_none,
+    terrain::HeightFilter,
+    terrain_data::gpu_virtual_texture::GpuVirtualTexture,
+    DebugTerrain, Terrain, TerrainComponents, TerrainView, TerrainViewComponents,
 };
 use bevy::{
     core_pipeline::core_3d::Opaque3d,
@@ -21,13 +26,6 @@ use bevy::{
 };
 use std::{hash::Hash, marker::PhantomData};
 
-/// Configures the default terrain pipeline.
-#[derive(Resource)]
-pub struct TerrainPipelineConfig {
-    /// The number of terrain attachments.
-    pub attachment_count: usize,
-}
-
 pub struct TerrainPipelineKey<M: Material> {
     pub flags: TerrainPipelineFlags,
     pub bind_group_data: M::Data,
@@ -68,7 +66,7 @@ where
 
 bitflags::bitflags! {
 #[repr(transparent)]
-pub struct TerrainPipelineFlags: u32 {
+pub struct TerrainPipelineFlags: u64 {
     const NONE               = 0;
     const WIREFRAME          = (1 <<  0);
     const SHOW_TILES         = (1 <<  1);
@@ -85,17 +83,47 @@ pub struct TerrainPipelineFlags: u32 {
     const TEST1              = (1 << 12);
     const TEST2              = (1 << 13);
     const TEST3              = (1 << 14);
+    const MASK               = (1 << 15);
+    const SHOW_SLOPE         = (1 << 16);
+    const SHOW_ASPECT        = (1 << 17);
+    const SHOW_HILLSHADE     = (1 << 18);
+    const SHOW_HEIGHT_DIFFERENCE = (1 << 19);
+    const REFERENCE_ATTACHMENT_2 = (1 << 20);
+    const REFERENCE_ATTACHMENT_3 = (1 << 21);
+    const SHOW_HEIGHT        = (1 << 22);
+    const FALLBACK           = (1 << 23);
+    const DOWNLEVEL          = (1 << 24);
+    const BATHYMETRY_ATTACHMENT_2 = (1 << 25);
+    const BATHYMETRY_ATTACHMENT_3 = (1 << 26);
+    const FLOW_ATTACHMENT_2  = (1 << 27);
+    const FLOW_ATTACHMENT_3  = (1 << 28);
+    const SHOW_FLOW          = (1 << 29);
+    const SHOW_WATERSHEDS    = (1 << 30);
+    const NODE_FADE          = (1 << 31);
+    const CEILING_ATTACHMENT_2 = (1 << 32);
+    const CEILING_ATTACHMENT_3 = (1 << 33);
+    const CEILING_LAYER      = (1 << 34);
+    const BIOME_ATTACHMENT_2 = (1 << 35);
+    const BIOME_ATTACHMENT_3 = (1 << 36);
+    const VIRTUAL_TEXTURE = (1 << 37);
+    const ALPHA_TO_COVERAGE = (1 << 38);
+    const CATMULL_ROM_NORMALS = (1 << 39);
+    const DEBAND_DITHER = (1 << 40);
+    const HEIGHT_RESIDUAL = (1 << 41);
+    const B_SPLINE_NORMALS = (1 << 42);
+    const CATMULL_ROM_VERTICES = (1 << 43);
+    const B_SPLINE_VERTICES = (1 << 44);
 
     const MSAA_RESERVED_BITS = TerrainPipelineFlags::MSAA_MASK_BITS << TerrainPipelineFlags::MSAA_SHIFT_BITS;
 }
 }
 
 impl TerrainPipelineFlags {
-    const MSAA_MASK_BITS: u32 = 0b111111;
-    const MSAA_SHIFT_BITS: u32 = 32 - 6;
+    const MSAA_MASK_BITS: u64 = 0b111111;
+    const MSAA_SHIFT_BITS: u64 = 64 - 6;
 
     pub fn from_msaa_samples(msaa_samples: u32) -> Self {
-        let msaa_bits = ((msaa_samples - 1) & Self::MSAA_MASK_BITS) << Self::MSAA_SHIFT_BITS;
+        let msaa_bits = ((msaa_samples as u64 - 1) & Self::MSAA_MASK_BITS) << Self::MSAA_SHIFT_BITS;
         TerrainPipelineFlags::from_bits(msaa_bits).unwrap()
     }
 
@@ -147,12 +175,46 @@ impl TerrainPipelineFlags {
         if debug.test3 {
             key |= TerrainPipelineFlags::TEST3;
         }
+        if debug.show_slope {
+            key |= TerrainPipelineFlags::SHOW_SLOPE;
+        }
+        if debug.show_aspect {
+            key |= TerrainPipelineFlags::SHOW_ASPECT;
+        }
+        if debug.show_hillshade {
+            key |= TerrainPipelineFlags::SHOW_HILLSHADE;
+        }
+        if debug.show_height_difference {
+            key |= TerrainPipelineFlags::SHOW_HEIGHT_DIFFERENCE;
+        }
+        if debug.show_height {
+            key |= TerrainPipelineFlags::SHOW_HEIGHT;
+        }
+        if debug.show_flow {
+            key |= TerrainPipelineFlags::SHOW_FLOW;
+        }
+        if debug.show_watersheds {
+            key |= TerrainPipelineFlags::SHOW_WATERSHEDS;
+        }
 
         key
     }
 
     pub fn msaa_samples(&self) -> u32 {
-        ((self.bits >> Self::MSAA_SHIFT_BITS) & Self::MSAA_MASK_BITS) + 1
+        (((self.bits >> Self::MSAA_SHIFT_BITS) & Self::MSAA_MASK_BITS) + 1) as u32
+    }
+
+    /// Alpha to coverage is only valid for multisampled pipelines.
+    pub fn alpha_to_coverage(&self) -> bool {
+        (self.bits & TerrainPipelineFlags::ALPHA_TO_COVERAGE.bits) != 0 && self.msaa_samples() > 1
+    }
+
+    pub fn cull_mode(&self) -> Option<Face> {
+        // the ceiling layer is visible from above and below
+        match (self.bits & TerrainPipelineFlags::CEILING_LAYER.bits) != 0 {
+            true => None,
+            false => Some(Face::Back),
+        }
     }
 
     pub fn polygon_mode(&self) -> PolygonMode {
@@ -207,6 +269,107 @@ impl TerrainPipelineFlags {
         if (self.bits & TerrainPipelineFlags::TEST3.bits) != 0 {
             shader_defs.push("TEST3".to_string());
         }
+        if (self.bits & TerrainPipelineFlags::MASK.bits) != 0 {
+            shader_defs.push("MASK".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_SLOPE.bits) != 0 {
+            shader_defs.push("SHOW_SLOPE".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_ASPECT.bits) != 0 {
+            shader_defs.push("SHOW_ASPECT".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_HILLSHADE.bits) != 0 {
+            shader_defs.push("SHOW_HILLSHADE".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_HEIGHT_DIFFERENCE.bits) != 0 {
+            shader_defs.push("SHOW_HEIGHT_DIFFERENCE".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_HEIGHT.bits) != 0 {
+            shader_defs.push("SHOW_HEIGHT".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::DOWNLEVEL.bits) != 0 {
+            shader_defs.push("DOWNLEVEL".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::REFERENCE_ATTACHMENT_2.bits) != 0 {
+            shader_defs.push("REFERENCE".to_string());
+            shader_defs.push("REFERENCE_ATTACHMENT_2".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::REFERENCE_ATTACHMENT_3.bits) != 0 {
+            shader_defs.push("REFERENCE".to_string());
+            shader_defs.push("REFERENCE_ATTACHMENT_3".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::BATHYMETRY_ATTACHMENT_2.bits) != 0 {
+            shader_defs.push("BATHYMETRY".to_string());
+            shader_defs.push("BATHYMETRY_ATTACHMENT_2".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::BATHYMETRY_ATTACHMENT_3.bits) != 0 {
+            shader_defs.push("BATHYMETRY".to_string());
+            shader_defs.push("BATHYMETRY_ATTACHMENT_3".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::FLOW_ATTACHMENT_2.bits) != 0 {
+            shader_defs.push("FLOW".to_string());
+            shader_defs.push("FLOW_ATTACHMENT_2".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::FLOW_ATTACHMENT_3.bits) != 0 {
+            shader_defs.push("FLOW".to_string());
+            shader_defs.push("FLOW_ATTACHMENT_3".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_FLOW.bits) != 0 {
+            shader_defs.push("SHOW_FLOW".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::SHOW_WATERSHEDS.bits) != 0 {
+            shader_defs.push("SHOW_WATERSHEDS".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::NODE_FADE.bits) != 0 {
+            shader_defs.push("NODE_FADE".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::CEILING_ATTACHMENT_2.bits) != 0 {
+            shader_defs.push("CEILING".to_string());
+            shader_defs.push("CEILING_ATTACHMENT_2".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::CEILING_ATTACHMENT_3.bits) != 0 {
+            shader_defs.push("CEILING".to_string());
+            shader_defs.push("CEILING_ATTACHMENT_3".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::CEILING_LAYER.bits) != 0 {
+            shader_defs.push("CEILING_LAYER".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::BIOME_ATTACHMENT_2.bits) != 0 {
+            shader_defs.push("BIOME".to_string());
+            shader_defs.push("BIOME_ATTACHMENT_2".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::BIOME_ATTACHMENT_3.bits) != 0 {
+            shader_defs.push("BIOME".to_string());
+            shader_defs.push("BIOME_ATTACHMENT_3".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::VIRTUAL_TEXTURE.bits) != 0 {
+            shader_defs.push("VIRTUAL_TEXTURE".to_string());
+        }
+        if self.alpha_to_coverage() {
+            shader_defs.push("ALPHA_TO_COVERAGE".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::CATMULL_ROM_NORMALS.bits) != 0 {
+            shader_defs.push("BICUBIC_NORMALS".to_string());
+            shader_defs.push("CATMULL_ROM_NORMALS".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::B_SPLINE_NORMALS.bits) != 0 {
+            shader_defs.push("BICUBIC_NORMALS".to_string());
+            shader_defs.push("B_SPLINE_NORMALS".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::CATMULL_ROM_VERTICES.bits) != 0 {
+            shader_defs.push("BICUBIC_VERTICES".to_string());
+            shader_defs.push("CATMULL_ROM_VERTICES".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::B_SPLINE_VERTICES.bits) != 0 {
+            shader_defs.push("BICUBIC_VERTICES".to_string());
+            shader_defs.push("B_SPLINE_VERTICES".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::DEBAND_DITHER.bits) != 0 {
+            shader_defs.push("DEBAND_DITHER".to_string());
+        }
+        if (self.bits & TerrainPipelineFlags::HEIGHT_RESIDUAL.bits) != 0 {
+            shader_defs.push("HEIGHT_RESIDUAL".to_string());
+        }
 
         shader_defs
     }
@@ -229,11 +392,11 @@ impl<M: Material> FromWorld for TerrainRenderPipeline<M> {
         let device = world.resource::<RenderDevice>();
         let asset_server = world.resource::<AssetServer>();
         let mesh_pipeline = world.resource::<MeshPipeline>();
-        let config = world.resource::<TerrainPipelineConfig>();
+        let mode = *world.resource::<TerrainRenderMode>();
 
         let view_layout = mesh_pipeline.view_layout.clone();
-        let terrain_layout = terrain_bind_group_layout(&device, config.attachment_count);
-        let terrain_view_layout = device.create_bind_group_layout(&TERRAIN_VIEW_LAYOUT);
+        let terrain_layout = terrain_bind_group_layout(&device);
+        let terrain_view_layout = terrain_view_layout(device, mode);
         let material_layout = M::bind_group_layout(device);
 
         let vertex_shader = match M::vertex_shader() {
@@ -267,35 +430,60 @@ where
     type Key = TerrainPipelineKey<M>;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        // the fallback pipeline renders the terrain with a solid color,
+        // whenever the shaders of the material fail to compile
+        let (vertex_shader, fragment_shader) = if key.flags.contains(TerrainPipelineFlags::FALLBACK)
+        {
+            (FALLBACK_SHADER.typed(), FALLBACK_SHADER.typed())
+        } else {
+            (self.vertex_shader.clone(), self.fragment_shader.clone())
+        };
+
         let mut shader_defs = key.flags.shader_defs();
 
         shader_defs.push("TONEMAP_IN_SHADER".to_string());
 
+        // in downlevel mode the tiles are passed as instances instead of a storage buffer
+        let buffers = if key.flags.contains(TerrainPipelineFlags::DOWNLEVEL) {
+            vec![VertexBufferLayout {
+                array_stride: 4 * 4,
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![VertexAttribute {
+                    format: VertexFormat::Uint32x3,
+                    offset: 0,
+                    shader_location: 0,
+                }],
+            }]
+        } else {
+            Vec::new()
+        };
+
         RenderPipelineDescriptor {
             label: None,
             layout: Some(vec![
                 self.view_layout.clone(),
                 self.terrain_view_layout.clone(),
-                self.terrain_layout.clone(), // Todo: do this properly for multiple terrains
+                self.terrain_layout.clone(),
                 self.material_layout.clone(),
             ]),
             vertex: VertexState {
-                shader: self.vertex_shader.clone(),
+                shader: vertex_shader,
                 entry_point: "vertex".into(),
                 shader_defs: shader_defs.clone(),
-                buffers: Vec::new(),
+                buffers,
             },
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back),
+                cull_mode: key.flags.cull_mode(),
                 unclipped_depth: false,
                 polygon_mode: key.flags.polygon_mode(),
                 conservative: false,
                 topology: PrimitiveTopology::TriangleStrip,
-                strip_index_format: None,
+                // the indexed strip topology separates the columns of the grid by primitive restarts
+                strip_index_format: Some(IndexFormat::Uint32),
             },
             fragment: Some(FragmentState {
-                shader: self.fragment_shader.clone(),
+                shader: fragment_shader,
                 shader_defs,
                 entry_point: "fragment".into(),
                 targets: vec![Some(ColorTargetState {
@@ -323,7 +511,7 @@ where
             multisample: MultisampleState {
                 count: key.flags.msaa_samples(),
                 mask: !0,
-                alpha_to_coverage_enabled: false,
+                alpha_to_coverage_enabled: key.flags.alpha_to_coverage(),
             },
         }
     }
@@ -345,38 +533,177 @@ pub(crate) fn queue_terrain<M: Material>(
     terrain_pipeline: Res<TerrainRenderPipeline<M>>,
     draw_functions: Res<DrawFunctions<Opaque3d>>,
     msaa: Res<Msaa>,
+    mode: Res<TerrainRenderMode>,
     debug: Option<Res<DebugTerrain>>,
     render_materials: Res<RenderMaterials<M>>,
+    terrain_data: Res<TerrainComponents<TerrainData>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    virtual_textures: Res<TerrainComponents<GpuVirtualTexture>>,
     mut pipelines: ResMut<SpecializedRenderPipelines<TerrainRenderPipeline<M>>>,
     mut pipeline_cache: ResMut<PipelineCache>,
-    mut view_query: Query<&mut RenderPhase<Opaque3d>>,
+    mut view_query: Query<(Entity, &mut RenderPhase<Opaque3d>), With<TerrainView>>,
     terrain_query: Query<(Entity, &Handle<M>), With<Terrain>>,
 ) where
     M::Data: PartialEq + Eq + Hash + Clone,
 {
     let draw_function = draw_functions.read().get_id::<DrawTerrain<M>>().unwrap();
 
-    for mut opaque_phase in view_query.iter_mut() {
-        for (entity, material) in terrain_query.iter() {
-            if let Some(material) = render_materials.get(material) {
-                let mut flags = TerrainPipelineFlags::from_msaa_samples(msaa.samples);
-
-                if let Some(debug) = &debug {
-                    flags |= TerrainPipelineFlags::from_debug(debug);
-                } else {
-                    flags |= TerrainPipelineFlags::LIGHTING
-                        | TerrainPipelineFlags::SHOW_NODES
-                        | TerrainPipelineFlags::MESH_MORPH
-                        | TerrainPipelineFlags::SAMPLE_GRAD;
-                }
+    // each terrain is queued with the pipelines of its own material, so that terrains with different
+    // materials (and material types, each registered by its own plugin) are rendered side by side
+    for (entity, material) in terrain_query.iter() {
+        let material = skip_none!(render_materials.get(material));
+
+        let mut flags = TerrainPipelineFlags::from_msaa_samples(msaa.samples);
+
+        if let Some(debug) = &debug {
+            flags |= TerrainPipelineFlags::from_debug(debug);
+        } else {
+            flags |= TerrainPipelineFlags::LIGHTING
+                | TerrainPipelineFlags::SHOW_NODES
+                | TerrainPipelineFlags::MESH_MORPH
+                | TerrainPipelineFlags::SAMPLE_GRAD;
+        }
+
+        if *mode == TerrainRenderMode::Downlevel {
+            // the minmax view reads the tiles from the storage buffer
+            flags |= TerrainPipelineFlags::DOWNLEVEL;
+            flags.remove(TerrainPipelineFlags::MINMAX);
+        }
+
+        if let Some(data) = terrain_data.get(&entity) {
+            if data.mask {
+                flags |= TerrainPipelineFlags::MASK;
+            }
+
+            if data.node_fade {
+                flags |= TerrainPipelineFlags::NODE_FADE;
+            }
+
+            match data.normal_filter {
+                HeightFilter::Linear => {}
+                HeightFilter::CatmullRom => flags |= TerrainPipelineFlags::CATMULL_ROM_NORMALS,
+                HeightFilter::BSpline => flags |= TerrainPipelineFlags::B_SPLINE_NORMALS,
+            }
+
+            match data.vertex_filter {
+                HeightFilter::Linear => {}
+                HeightFilter::CatmullRom => flags |= TerrainPipelineFlags::CATMULL_ROM_VERTICES,
+                HeightFilter::BSpline => flags |= TerrainPipelineFlags::B_SPLINE_VERTICES,
+            }
+
+            if data.deband_dither {
+                flags |= TerrainPipelineFlags::DEBAND_DITHER;
+            }
+
+            if data.height_residual {
+                flags |= TerrainPipelineFlags::HEIGHT_RESIDUAL;
+            }
+
+            match data.reference {
+                Some(2) => flags |= TerrainPipelineFlags::REFERENCE_ATTACHMENT_2,
+                Some(3) => flags |= TerrainPipelineFlags::REFERENCE_ATTACHMENT_3,
+                _ => {}
+            }
+
+            match data.bathymetry {
+                Some(2) => flags |= TerrainPipelineFlags::BATHYMETRY_ATTACHMENT_2,
+                Some(3) => flags |= TerrainPipelineFlags::BATHYMETRY_ATTACHMENT_3,
+                _ => {}
+            }
+
+            match data.flow {
+                Some(2) => flags |= TerrainPipelineFlags::FLOW_ATTACHMENT_2,
+                Some(3) => flags |= TerrainPipelineFlags::FLOW_ATTACHMENT_3,
+                _ => {}
+            }
+
+            match data.ceiling {
+                Some(2) => flags |= TerrainPipelineFlags::CEILING_ATTACHMENT_2,
+                Some(3) => flags |= TerrainPipelineFlags::CEILING_ATTACHMENT_3,
+                _ => {}
+            }
+
+            match data.biome {
+                Some(2) => flags |= TerrainPipelineFlags::BIOME_ATTACHMENT_2,
+                Some(3) => flags |= TerrainPipelineFlags::BIOME_ATTACHMENT_3,
+                _ => {}
+            }
+        }
+
+        // the page feedback is written into a storage buffer
+        if let Some(virtual_texture) = virtual_textures.get(&entity) {
+            if virtual_texture.active && *mode == TerrainRenderMode::Compute {
+                flags |= TerrainPipelineFlags::VIRTUAL_TEXTURE;
+            }
+        }
+
+        // masked holes are antialiased by the coverage of the multisampled fragments,
+        // the key changes with the msaa samples, so the pipelines are respecialized
+        if msaa.samples > 1
+            && flags.intersects(
+                TerrainPipelineFlags::MASK
+                    | TerrainPipelineFlags::CEILING_ATTACHMENT_2
+                    | TerrainPipelineFlags::CEILING_ATTACHMENT_3,
+            )
+        {
+            flags |= TerrainPipelineFlags::ALPHA_TO_COVERAGE;
+        }
+
+        let mut layers = vec![flags];
 
+        // the ceiling of overhangs is rendered by a second draw of the same tiles
+        if flags.intersects(
+            TerrainPipelineFlags::CEILING_ATTACHMENT_2 | TerrainPipelineFlags::CEILING_ATTACHMENT_3,
+        ) && !flags.contains(TerrainPipelineFlags::MINMAX)
+        {
+            layers.push(flags | TerrainPipelineFlags::CEILING_LAYER);
+        }
+
+        let layer_pipelines: Vec<_> = layers
+            .into_iter()
+            .map(|flags| {
                 let key = TerrainPipelineKey {
                     flags,
                     bind_group_data: material.key.clone(),
                 };
 
-                let pipeline = pipelines.specialize(&mut pipeline_cache, &terrain_pipeline, key);
+                let mut pipeline =
+                    pipelines.specialize(&mut pipeline_cache, &terrain_pipeline, key.clone());
+
+                // The error has already been logged by the pipeline cache.
+                // Once the shader is fixed and reloaded, the pipeline is recompiled.
+                if let CachedPipelineState::Err(error) =
+                    pipeline_cache.get_render_pipeline_state(pipeline)
+                {
+                    if !matches!(
+                        error,
+                        PipelineCacheError::ShaderNotLoaded(_)
+                            | PipelineCacheError::ShaderImportNotYetAvailable
+                    ) {
+                        let key = TerrainPipelineKey {
+                            flags: TerrainPipelineFlags::from_msaa_samples(msaa.samples)
+                                | (flags
+                                    & (TerrainPipelineFlags::WIREFRAME
+                                        | TerrainPipelineFlags::DOWNLEVEL))
+                                | TerrainPipelineFlags::FALLBACK,
+                            ..key
+                        };
+
+                        pipeline =
+                            pipelines.specialize(&mut pipeline_cache, &terrain_pipeline, key);
+                    }
+                }
+
+                pipeline
+            })
+            .collect();
+
+        for (view, mut opaque_phase) in view_query.iter_mut() {
+            if terrain_view_data.get(&(entity, view)).is_none() {
+                continue;
+            }
 
+            for &pipeline in &layer_pipelines {
                 opaque_phase.add(Opaque3d {
                     entity,
                     pipeline,
@@ -391,6 +718,8 @@ pub(crate) fn queue_terrain<M: Material>(
 /// This plugin adds a custom material for a terrain.
 ///
 /// It can be used to render the terrain using a custom vertex and fragment shader.
+/// Each terrain is rendered with the material of its `Handle<M>`, thus terrains with different
+/// material types can be rendered in the same world by adding one plugin per material type.
 pub struct TerrainMaterialPlugin<M: Material>(PhantomData<M>);
 
 impl<M: Material> Default for TerrainMaterialPlugin<M> {
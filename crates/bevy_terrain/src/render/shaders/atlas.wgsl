@@ -0,0 +1,51 @@
+#define_import_path bevy_terrain::atlas
+
+// Helpers for sampling the node atlas of the terrain from external render passes.
+// They require the bindings of `bevy_terrain::atlas_bindings` and the `bevy_terrain::node` import.
+
+// Selects the finest lod of the quadtree, which still covers the position.
+fn quadtree_lod(local_position: vec2<f32>) -> u32 {
+    var lod = 0u;
+    for (; lod < config.lod_count - 1u; lod = lod + 1u) {
+        let coordinate = local_position / node_size(lod);
+        let grid_coordinate = floor(view_config.viewer_position.xz / node_size(lod) + 0.5 - f32(view_config.node_count >> 1u));
+
+        let grid = step(grid_coordinate, coordinate) * (1.0 - step(grid_coordinate + f32(view_config.node_count), coordinate));
+
+        if (grid.x * grid.y == 1.0) {
+            break;
+        }
+    }
+
+    return lod;
+}
+
+// Looks up the best available node at the position, as currently loaded for the view.
+fn lookup_terrain(local_position: vec2<f32>) -> NodeLookup {
+    return lookup_node(quadtree_lod(local_position), local_position);
+}
+
+// The coordinates of the node inside the atlas of the attachment (with the index zero to three).
+fn attachment_coords(lookup: NodeLookup, attachment: u32) -> vec2<f32> {
+    return lookup.atlas_coords * config.attachment_scales[attachment] + config.attachment_offsets[attachment];
+}
+
+// Samples the height at the node in world units, offset by a number of pixels.
+fn terrain_height(lookup: NodeLookup, offset: vec2<f32>) -> f32 {
+    let coords = attachment_coords(lookup, 0u) + offset / config.attachment_sizes[0];
+
+    return config.height * textureSampleLevel(height_atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).x + view_config.elevation_offset;
+}
+
+// Approximates the normal of the terrain at the node with central differences of the height.
+fn terrain_normal(lookup: NodeLookup) -> vec3<f32> {
+    // the size of a pixel of the height attachment in world units
+    let pixel_size = node_size(lookup.atlas_lod) / (config.attachment_scales[0] * config.attachment_sizes[0]);
+
+    let left  = terrain_height(lookup, vec2<f32>(-1.0,  0.0));
+    let up    = terrain_height(lookup, vec2<f32>( 0.0, -1.0));
+    let right = terrain_height(lookup, vec2<f32>( 1.0,  0.0));
+    let down  = terrain_height(lookup, vec2<f32>( 0.0,  1.0));
+
+    return normalize(vec3<f32>(left - right, 2.0 * pixel_size, up - down));
+}
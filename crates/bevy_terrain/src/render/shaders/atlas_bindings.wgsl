@@ -0,0 +1,27 @@
+#define_import_path bevy_terrain::atlas_bindings
+
+// The leading fields of the terrain config, which are shared by all terrain shaders.
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    attachment_sizes: vec4<f32>,
+    attachment_scales: vec4<f32>,
+    attachment_offsets: vec4<f32>,
+}
+
+// terrain view bindings
+@group(1) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(1) @binding(1)
+var quadtree: texture_2d_array<u32>;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
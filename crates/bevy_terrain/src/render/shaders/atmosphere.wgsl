@@ -0,0 +1,29 @@
+#define_import_path bevy_terrain::atmosphere
+
+// Applies the height fog and the aerial perspective of the sun to the color.
+fn apply_atmosphere(base_color: vec4<f32>, world_position: vec4<f32>) -> vec4<f32> {
+    if (atmosphere.fog_density <= 0.0) {
+        return base_color;
+    }
+
+    let to_fragment = world_position.xyz - view.world_position.xyz;
+    let ray_length = max(length(to_fragment), 0.0001);
+    let direction = to_fragment / ray_length;
+
+    // the density falls off by a factor of e over the height of the terrain
+    let falloff = atmosphere.height_falloff / config.height;
+
+    // integrate the exponential density along the ray from the viewer to the fragment
+    var optical_depth = atmosphere.fog_density * exp(-falloff * view.world_position.y) * ray_length;
+    let height_change = falloff * direction.y * ray_length;
+
+    if (abs(height_change) > 0.0001) {
+        optical_depth = optical_depth * (1.0 - exp(-height_change)) / height_change;
+    }
+
+    let fog_amount = 1.0 - exp(-optical_depth);
+    let sun_amount = pow(max(dot(direction, atmosphere.sun_direction.xyz), 0.0), atmosphere.sun_exponent);
+    let fog_color = mix(atmosphere.fog_color.rgb, atmosphere.sun_color.rgb, sun_amount);
+
+    return vec4<f32>(mix(base_color.rgb, fog_color, fog_amount), base_color.a);
+}
@@ -0,0 +1,209 @@
+struct ErosionParameters {
+    size: vec2<u32>,
+    height: f32,
+    seed: u32,
+    droplet_count: u32,
+    droplet_lifetime: u32,
+    inertia: f32,
+    sediment_capacity: f32,
+    min_sediment_capacity: f32,
+    erosion_rate: f32,
+    deposition_rate: f32,
+    evaporation_rate: f32,
+    gravity: f32,
+    talus: f32,
+    thermal_rate: f32,
+}
+
+// The heights are stored as fixed point numbers, so that droplets can modify them atomically.
+struct Heights {
+    data: array<atomic<i32>>,
+}
+
+@group(0) @binding(0)
+var<uniform> parameters: ErosionParameters;
+@group(0) @binding(1)
+var<storage, read_write> heights: Heights;
+
+let FIXED_POINT_SCALE: f32 = 16777216.0;
+
+var<private> NEIGHBOURS: array<vec2<i32>, 8> = array<vec2<i32>, 8>(
+    vec2<i32>(-1, -1), vec2<i32>( 0, -1), vec2<i32>( 1, -1), vec2<i32>(-1,  0),
+    vec2<i32>( 1,  0), vec2<i32>(-1,  1), vec2<i32>( 0,  1), vec2<i32>( 1,  1)
+);
+
+struct HeightAndGradient {
+    height: f32,
+    gradient: vec2<f32>,
+}
+
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(value: u32) -> f32 {
+    return f32(hash(value)) / 4294967295.0;
+}
+
+fn inside(coordinate: vec2<i32>) -> bool {
+    return all(coordinate >= vec2<i32>(0)) && all(coordinate < vec2<i32>(parameters.size));
+}
+
+fn height_index(coordinate: vec2<i32>) -> i32 {
+    let clamped = clamp(coordinate, vec2<i32>(0), vec2<i32>(parameters.size) - 1);
+    return clamped.y * i32(parameters.size.x) + clamped.x;
+}
+
+// Returns the height in world units.
+fn load_height(coordinate: vec2<i32>) -> f32 {
+    let value = atomicLoad(&heights.data[height_index(coordinate)]);
+    return f32(value) / FIXED_POINT_SCALE * parameters.height;
+}
+
+// Adds the height difference in world units.
+fn add_height(coordinate: vec2<i32>, delta: f32) {
+    if (!inside(coordinate)) {
+        return;
+    }
+
+    let value = i32(delta / parameters.height * FIXED_POINT_SCALE);
+    atomicAdd(&heights.data[height_index(coordinate)], value);
+}
+
+// Distributes the amount of material bilinearly onto the four surrounding pixels.
+fn add_height_bilinear(position: vec2<f32>, amount: f32) {
+    let coordinate = vec2<i32>(floor(position));
+    let local = fract(position);
+
+    add_height(coordinate,                     amount * (1.0 - local.x) * (1.0 - local.y));
+    add_height(coordinate + vec2<i32>(1, 0),   amount * local.x * (1.0 - local.y));
+    add_height(coordinate + vec2<i32>(0, 1),   amount * (1.0 - local.x) * local.y);
+    add_height(coordinate + vec2<i32>(1, 1),   amount * local.x * local.y);
+}
+
+fn height_and_gradient(position: vec2<f32>) -> HeightAndGradient {
+    let coordinate = vec2<i32>(floor(position));
+    let local = fract(position);
+
+    let h00 = load_height(coordinate);
+    let h10 = load_height(coordinate + vec2<i32>(1, 0));
+    let h01 = load_height(coordinate + vec2<i32>(0, 1));
+    let h11 = load_height(coordinate + vec2<i32>(1, 1));
+
+    let height = mix(mix(h00, h10, local.x), mix(h01, h11, local.x), local.y);
+    let gradient = vec2<f32>(mix(h10 - h00, h11 - h01, local.y), mix(h01 - h00, h11 - h10, local.x));
+
+    return HeightAndGradient(height, gradient);
+}
+
+// Simulates a single rain droplet, which erodes the terrain while flowing downhill
+// and deposits the sediment, once it slows down or evaporates.
+@compute @workgroup_size(64, 1, 1)
+fn hydraulic_erosion(
+    @builtin(global_invocation_id) invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let droplet = invocation_id.y * num_workgroups.x * 64u + invocation_id.x;
+
+    if (droplet >= parameters.droplet_count) {
+        return;
+    }
+
+    let size = vec2<f32>(parameters.size) - 1.0;
+    let seed = hash(parameters.seed) ^ (2u * droplet);
+
+    var position = vec2<f32>(random(seed), random(seed + 1u)) * size;
+    var direction = vec2<f32>(0.0);
+    var speed = 1.0;
+    var water = 1.0;
+    var sediment = 0.0;
+
+    for (var i = 0u; i < parameters.droplet_lifetime; i = i + 1u) {
+        let current = height_and_gradient(position);
+
+        direction = direction * parameters.inertia - current.gradient * (1.0 - parameters.inertia);
+
+        let direction_length = length(direction);
+
+        if (direction_length < 0.0001) {
+            break;
+        }
+
+        let previous_position = position;
+        direction = direction / direction_length;
+        position = position + direction;
+
+        if (any(position < vec2<f32>(0.0)) || any(position > size)) {
+            break;
+        }
+
+        let delta_height = height_and_gradient(position).height - current.height;
+        let capacity = max(-delta_height * speed * water * parameters.sediment_capacity,
+                           parameters.min_sediment_capacity);
+
+        if (delta_height > 0.0 || sediment > capacity) {
+            // fill up the pit when flowing uphill, otherwise deposit the surplus
+            var amount = (sediment - capacity) * parameters.deposition_rate;
+
+            if (delta_height > 0.0) {
+                amount = min(delta_height, sediment);
+            }
+
+            sediment = sediment - amount;
+            add_height_bilinear(previous_position, amount);
+        } else {
+            // never erode deeper than the height difference, to avoid digging holes
+            let amount = min((capacity - sediment) * parameters.erosion_rate, -delta_height);
+
+            sediment = sediment + amount;
+            add_height_bilinear(previous_position, -amount);
+        }
+
+        speed = sqrt(max(speed * speed - delta_height * parameters.gravity, 0.0));
+        water = water * (1.0 - parameters.evaporation_rate);
+    }
+}
+
+// Moves material, which exceeds the angle of repose, down to the lower neighbours.
+@compute @workgroup_size(8, 8, 1)
+fn thermal_erosion(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let coordinate = vec2<i32>(invocation_id.xy);
+
+    if (!inside(coordinate)) {
+        return;
+    }
+
+    let height = load_height(coordinate);
+
+    var excess: array<f32, 8>;
+    var total_excess = 0.0;
+    var max_excess = 0.0;
+
+    for (var i = 0; i < 8; i = i + 1) {
+        let neighbour = coordinate + NEIGHBOURS[i];
+        let distance = length(vec2<f32>(NEIGHBOURS[i]));
+
+        excess[i] = 0.0;
+
+        if (inside(neighbour)) {
+            excess[i] = max(height - load_height(neighbour) - parameters.talus * distance, 0.0);
+        }
+
+        total_excess = total_excess + excess[i];
+        max_excess = max(max_excess, excess[i]);
+    }
+
+    if (total_excess <= 0.0) {
+        return;
+    }
+
+    let amount = 0.5 * max_excess * parameters.thermal_rate;
+
+    for (var i = 0; i < 8; i = i + 1) {
+        add_height(coordinate + NEIGHBOURS[i], amount * excess[i] / total_excess);
+    }
+
+    add_height(coordinate, -amount);
+}
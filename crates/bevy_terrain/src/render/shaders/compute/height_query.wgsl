@@ -0,0 +1,102 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    attachment_sizes: vec4<f32>,
+    attachment_scales: vec4<f32>,
+    attachment_offsets: vec4<f32>,
+}
+
+struct CullingData {
+    world_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,
+    planes: array<vec4<f32>, 5>,
+    pixel_scale: f32,
+}
+
+struct HeightQueryPositions {
+    data: array<vec2<f32>>,
+}
+
+// The normal is stored in xyz and the height in w.
+struct HeightQueryResults {
+    data: array<vec4<f32>>,
+}
+
+@group(0) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(0) @binding(1)
+var quadtree: texture_2d_array<u32>;
+
+@group(1) @binding(0)
+var<uniform> view: CullingData;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+
+@group(3) @binding(0)
+var<storage> positions: HeightQueryPositions;
+@group(3) @binding(1)
+var<storage, read_write> results: HeightQueryResults;
+
+#import bevy_terrain::node
+
+// Samples the height at the node, offset by a number of pixels.
+fn sample_height(lookup: NodeLookup, offset: vec2<f32>) -> f32 {
+    let coords = lookup.atlas_coords * config.attachment_scales[0] + config.attachment_offsets[0]
+               + offset / config.attachment_sizes[0];
+
+    return config.height * textureSampleLevel(height_atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).x + view_config.elevation_offset;
+}
+
+// Selects the finest lod of the quadtree, which still covers the position.
+fn quadtree_lod(local_position: vec2<f32>) -> u32 {
+    var lod = 0u;
+    for (; lod < config.lod_count - 1u; lod = lod + 1u) {
+        let coordinate = local_position / node_size(lod);
+        let grid_coordinate = floor(view.world_position.xz / node_size(lod) + 0.5 - f32(view_config.node_count >> 1u));
+
+        let grid = step(grid_coordinate, coordinate) * (1.0 - step(grid_coordinate + f32(view_config.node_count), coordinate));
+
+        if (grid.x * grid.y == 1.0) {
+            break;
+        }
+    }
+
+    return lod;
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn query_height(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+
+    if (index >= arrayLength(&positions.data)) {
+        return;
+    }
+
+    let local_position = positions.data[index];
+    let lookup = lookup_node(quadtree_lod(local_position), local_position);
+
+    // the distance between two neighbouring pixels of the node
+    let pixel_size = node_size(lookup.atlas_lod) / (config.attachment_sizes[0] * config.attachment_scales[0]);
+
+    let height = sample_height(lookup, vec2<f32>(0.0, 0.0));
+    let left   = sample_height(lookup, vec2<f32>(-1.0, 0.0));
+    let right  = sample_height(lookup, vec2<f32>(1.0, 0.0));
+    let up     = sample_height(lookup, vec2<f32>(0.0, -1.0));
+    let down   = sample_height(lookup, vec2<f32>(0.0, 1.0));
+
+    let normal = normalize(vec3<f32>(left - right, 2.0 * pixel_size, up - down));
+
+    results.data[index] = vec4<f32>(normal, height);
+}
@@ -42,10 +42,32 @@ fn prepare_next() {
     indirect_buffer.workgroup_count.x = (parameters.tile_count + 63u) / 64u;
 }
 
+fn prepare_draw(tile_count: u32) {
+    if (view_config.patch_topology == INDEXED_STRIP) {
+        // draw one instance of the indexed tile grid per tile
+        indirect_buffer.workgroup_count = vec3<u32>(view_config.vertices_per_tile, tile_count, 0u);
+    }
+    else {
+        let vertex_count = view_config.vertices_per_tile * tile_count;
+        indirect_buffer.workgroup_count = vec3<u32>(vertex_count, 1u, 0u);
+    }
+}
+
 @compute @workgroup_size(1, 1, 1)
 fn prepare_render() {
-    let tile_count = u32(atomicExchange(&parameters.final_index, 0));
-    let vertex_count = view_config.vertices_per_tile * tile_count;
+    prepare_draw(u32(atomicExchange(&parameters.final_index, 0)));
+}
 
-    indirect_buffer.workgroup_count = vec3<u32>(vertex_count, 1u, 0u);
-}
\ No newline at end of file
+// Dependent views cull the final tiles of their primary view, before its draw is prepared.
+@compute @workgroup_size(1, 1, 1)
+fn prepare_cull() {
+    parameters.tile_count = u32(atomicLoad(&parameters.final_index));
+    atomicStore(&parameters.child_index, 0);
+
+    indirect_buffer.workgroup_count = vec3<u32>((parameters.tile_count + 63u) / 64u, 1u, 1u);
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn prepare_dependent_render() {
+    prepare_draw(u32(atomicExchange(&parameters.child_index, 0)));
+}
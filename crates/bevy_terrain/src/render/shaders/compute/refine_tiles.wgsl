@@ -26,6 +26,7 @@ struct CullingData {
     view_proj: mat4x4<f32>,
     model: mat4x4<f32>,
     planes: array<vec4<f32>, 5>,
+    pixel_scale: f32,
 }
 
 @group(0) @binding(0)
@@ -54,6 +55,7 @@ var minmax_atlas: texture_2d_array<f32>;
 
 #import bevy_terrain::node
 #import bevy_terrain::functions
+#import bevy_terrain::refinement
 
 fn child_index() -> i32 {
     return atomicAdd(&parameters.child_index, parameters.counter);
@@ -71,7 +73,7 @@ fn frustum_cull(tile: Tile) -> bool {
     let size = f32(tile.size) * view_config.tile_scale;
     let local_position = (vec2<f32>(tile.coords) + 0.5) * size;
 
-    let minmax = vec2<f32>(0.0, config.height); // 2D frustum culling
+    let minmax = vec2<f32>(0.0, config.height) + view_config.elevation_offset; // 2D frustum culling
     // Todo: enable this
     // let minmax = minmax(local_position, size); // 3D frustum culling
 
@@ -112,23 +114,20 @@ fn cull(tile: Tile) -> bool {
     return outside_cull(tile) || frustum_cull(tile);
 }
 
-fn should_be_divided(tile: Tile) -> bool {
-    if (tile.size == 1u) {
-        return false;
-    }
+// Checks whether the triangles of the subdivided tile would be smaller on screen than the minimum triangle size.
+fn below_min_triangle_size(tile: Tile) -> bool {
+    let size = f32(tile.size) * view_config.tile_scale;
+    let tile_min = vec2<f32>(tile.coords) * size;
 
-    var dist = 1000000000.0;
+    // the closest point of the tile to the viewer
+    let local_position = clamp(view.world_position.xz, tile_min, tile_min + size);
+    let world_position = approximate_world_position(local_position);
+    let dist = max(distance(world_position.xyz, view.world_position.xyz), 0.0001);
 
-    for (var i: u32 = 0u; i < 4u; i = i + 1u) {
-        let corner_coords = vec2<u32>(tile.coords.x + (i       & 1u),
-                                      tile.coords.y + (i >> 1u & 1u));
+    // the edge length of the triangles after the subdivision
+    let edge_length = size / (2.0 * view_config.grid_size);
 
-        let local_position = vec2<f32>(corner_coords * tile.size) * view_config.tile_scale;
-        let world_position = approximate_world_position(local_position);
-        dist = min(dist, distance(world_position.xyz, view.world_position.xyz));
-    }
-
-    return dist < view_config.morph_distance * f32(tile.size);
+    return edge_length / dist * view.pixel_scale < view_config.min_triangle_size;
 }
 
 fn subdivide(tile: Tile) {
@@ -154,10 +153,24 @@ fn refine_tiles(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
     let tile = temporary_tiles.data[parent_index(invocation_id.x)];
 
-    if (should_be_divided(tile)) {
+    if (should_be_divided(tile) && !below_min_triangle_size(tile)) {
         subdivide(tile);
     }
     else {
         final_tiles.data[final_index()] = tile;
     }
 }
+
+// Culls the final tiles of the primary view (bound as the temporary tiles) against the frustum of a dependent view.
+@compute @workgroup_size(64, 1, 1)
+fn cull_tiles(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= parameters.tile_count) {
+        return;
+    }
+
+    let tile = temporary_tiles.data[invocation_id.x];
+
+    if (!frustum_cull(tile)) {
+        final_tiles.data[atomicAdd(&parameters.child_index, 1)] = tile;
+    }
+}
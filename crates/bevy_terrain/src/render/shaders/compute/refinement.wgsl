@@ -0,0 +1,22 @@
+#define_import_path bevy_terrain::refinement
+
+// The default distance based refinement heuristic.
+// A tile is divided, if its closest corner is nearer to the viewer than the morph distance.
+fn should_be_divided(tile: Tile) -> bool {
+    if (tile.size == 1u) {
+        return false;
+    }
+
+    var dist = 1000000000.0;
+
+    for (var i: u32 = 0u; i < 4u; i = i + 1u) {
+        let corner_coords = vec2<u32>(tile.coords.x + (i       & 1u),
+                                      tile.coords.y + (i >> 1u & 1u));
+
+        let local_position = vec2<f32>(corner_coords * tile.size) * view_config.tile_scale;
+        let world_position = approximate_world_position(local_position);
+        dist = min(dist, distance(world_position.xyz, view.world_position.xyz));
+    }
+
+    return dist < view_config.morph_distance * f32(tile.size);
+}
@@ -0,0 +1,176 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    attachment_sizes: vec4<f32>,
+    attachment_scales: vec4<f32>,
+    attachment_offsets: vec4<f32>,
+}
+
+struct CullingData {
+    world_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,
+    planes: array<vec4<f32>, 5>,
+    pixel_scale: f32,
+}
+
+struct VegetationIndirect {
+    vertex_count: u32,
+    instance_count: atomic<u32>,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(0) @binding(1)
+var quadtree: texture_2d_array<u32>;
+
+@group(1) @binding(0)
+var<uniform> view: CullingData;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+#ifdef DENSITY_ATTACHMENT_2
+@group(2) @binding(4)
+var density_atlas: texture_2d_array<f32>;
+#endif
+#ifdef DENSITY_ATTACHMENT_3
+@group(2) @binding(5)
+var density_atlas: texture_2d_array<f32>;
+#endif
+
+@group(3) @binding(0)
+var<uniform> vegetation: VegetationConfig;
+@group(3) @binding(1)
+var<storage, read_write> instances: VegetationInstanceList;
+@group(3) @binding(2)
+var<storage, read_write> indirect: VegetationIndirect;
+
+#import bevy_terrain::node
+
+let MAX_INSTANCES: u32 = 262144u;
+
+// A cheap integer hash, which is used to jitter and thin out the instances deterministically.
+fn hash(cell: vec2<i32>, seed: u32) -> f32 {
+    var h = u32(cell.x) * 73856093u ^ u32(cell.y) * 19349663u ^ seed * 83492791u;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = (h ^ (h >> 16u)) * 0x45d9f3bu;
+    h = h ^ (h >> 16u);
+
+    return f32(h & 0xffffu) / 65535.0;
+}
+
+// Samples the attachment, offset by a number of pixels.
+fn sample_attachment(atlas: texture_2d_array<f32>, index: u32, lookup: NodeLookup, offset: vec2<f32>) -> f32 {
+    let coords = lookup.atlas_coords * config.attachment_scales[index] + config.attachment_offsets[index]
+               + offset / config.attachment_sizes[index];
+
+    return textureSampleLevel(atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).x;
+}
+
+fn frustum_cull(position: vec3<f32>, radius: f32) -> bool {
+    for (var i = 0; i < 5; i = i + 1) {
+        if (dot(view.planes[i], vec4<f32>(position, 1.0)) < -radius) {
+            return true;
+        }
+    }
+
+    return false;
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn prepare_vegetation() {
+    indirect.vertex_count = 4u;
+    atomicStore(&indirect.instance_count, 0u);
+    indirect.first_vertex = 0u;
+    indirect.first_instance = 0u;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn scatter_vegetation(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= vegetation.grid_size || invocation_id.y >= vegetation.grid_size) {
+        return;
+    }
+
+    // the grid is aligned to the world, so that the instances stay in place, when the viewer moves
+    let grid_origin = vec2<i32>(floor(view.world_position.xz / vegetation.spacing)) - i32(vegetation.grid_size / 2u);
+    let cell = grid_origin + vec2<i32>(invocation_id.xy);
+
+    let jitter = vec2<f32>(hash(cell, 0u), hash(cell, 1u));
+    let local_position = (vec2<f32>(cell) + jitter) * vegetation.spacing;
+
+    if (any(local_position < vec2<f32>(0.0)) || any(local_position > vec2<f32>(f32(config.terrain_size)))) {
+        return;
+    }
+
+    let viewer_distance = distance(local_position, view.world_position.xz);
+
+    if (viewer_distance > vegetation.view_distance) {
+        return;
+    }
+
+    let lookup = lookup_node(0u, local_position);
+
+    // halve the density with every lod, while doubling the size of the remaining instances
+    let lod = max(log2(viewer_distance / vegetation.lod_distance), 0.0);
+    var density = exp2(-2.0 * lod);
+
+#ifdef DENSITY_ATTACHMENT_2
+    density = density * sample_attachment(density_atlas, 2u, lookup, vec2<f32>(0.0));
+#endif
+#ifdef DENSITY_ATTACHMENT_3
+    density = density * sample_attachment(density_atlas, 3u, lookup, vec2<f32>(0.0));
+#endif
+
+    if (hash(cell, 2u) >= density) {
+        return;
+    }
+
+    let height = sample_attachment(height_atlas, 0u, lookup, vec2<f32>(0.0)) * config.height + view_config.elevation_offset;
+
+    if (height < vegetation.min_height || height > vegetation.max_height) {
+        return;
+    }
+
+    let pixel_size = f32(1u << lookup.atlas_lod);
+    let gradient = vec2<f32>(
+        sample_attachment(height_atlas, 0u, lookup, vec2<f32>(1.0, 0.0)) - sample_attachment(height_atlas, 0u, lookup, vec2<f32>(-1.0, 0.0)),
+        sample_attachment(height_atlas, 0u, lookup, vec2<f32>(0.0, 1.0)) - sample_attachment(height_atlas, 0u, lookup, vec2<f32>(0.0, -1.0))
+    ) * config.height / (2.0 * pixel_size);
+
+    if (length(gradient) > vegetation.max_slope) {
+        return;
+    }
+
+    // shrink the instances towards the view distance, to avoid popping
+    let fade = 1.0 - smoothstep(0.8 * vegetation.view_distance, vegetation.view_distance, viewer_distance);
+    let scale = exp2(lod) * fade * mix(0.75, 1.25, hash(cell, 3u));
+
+    let position = vec3<f32>(local_position.x, height, local_position.y);
+
+    if (frustum_cull(position, max(vegetation.size.x, vegetation.size.y) * scale)) {
+        return;
+    }
+
+    let index = atomicAdd(&indirect.instance_count, 1u);
+
+    if (index < MAX_INSTANCES) {
+        instances.data[index] = VegetationInstance(position, scale);
+    }
+}
+
+@compute @workgroup_size(1, 1, 1)
+fn finish_vegetation() {
+    atomicMin(&indirect.instance_count, MAX_INSTANCES);
+}
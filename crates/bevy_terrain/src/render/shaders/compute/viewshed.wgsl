@@ -0,0 +1,115 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    attachment_sizes: vec4<f32>,
+    attachment_scales: vec4<f32>,
+    attachment_offsets: vec4<f32>,
+}
+
+struct CullingData {
+    world_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    model: mat4x4<f32>,
+    planes: array<vec4<f32>, 5>,
+    pixel_scale: f32,
+}
+
+struct ViewshedParameters {
+    visible_color: vec4<f32>,
+    hidden_color: vec4<f32>,
+    observer: vec2<f32>,
+    observer_height: f32,
+    target_height: f32,
+    radius: f32,
+    sample_count: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(0) @binding(1)
+var quadtree: texture_2d_array<u32>;
+
+@group(1) @binding(0)
+var<uniform> view: CullingData;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+
+@group(3) @binding(0)
+var<uniform> parameters: ViewshedParameters;
+@group(3) @binding(1)
+var viewshed: texture_storage_2d<rgba8unorm, write>;
+
+#import bevy_terrain::node
+
+// Selects the finest lod of the quadtree, which still covers the position.
+fn quadtree_lod(local_position: vec2<f32>) -> u32 {
+    var lod = 0u;
+    for (; lod < config.lod_count - 1u; lod = lod + 1u) {
+        let coordinate = local_position / node_size(lod);
+        let grid_coordinate = floor(view.world_position.xz / node_size(lod) + 0.5 - f32(view_config.node_count >> 1u));
+
+        let grid = step(grid_coordinate, coordinate) * (1.0 - step(grid_coordinate + f32(view_config.node_count), coordinate));
+
+        if (grid.x * grid.y == 1.0) {
+            break;
+        }
+    }
+
+    return lod;
+}
+
+fn terrain_height(local_position: vec2<f32>) -> f32 {
+    let lookup = lookup_node(quadtree_lod(local_position), local_position);
+    let coords = lookup.atlas_coords * config.attachment_scales[0] + config.attachment_offsets[0];
+
+    return config.height * textureSampleLevel(height_atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).x + view_config.elevation_offset;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn compute_viewshed(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let resolution = textureDimensions(viewshed);
+    let coords = vec2<i32>(invocation_id.xy);
+
+    if (coords.x >= resolution.x || coords.y >= resolution.y) {
+        return;
+    }
+
+    let texel_size = f32(config.terrain_size) / f32(resolution.x);
+    let target_position = (vec2<f32>(coords) + 0.5) * texel_size;
+    let observer_distance = distance(parameters.observer, target_position);
+
+    if (observer_distance > parameters.radius) {
+        textureStore(viewshed, coords, vec4<f32>(0.0));
+        return;
+    }
+
+    let observer_height = terrain_height(parameters.observer) + parameters.observer_height;
+    let target_height = terrain_height(target_position) + parameters.target_height;
+
+    // sample the line of sight about once per texel
+    let sample_count = clamp(u32(observer_distance / texel_size), 1u, parameters.sample_count);
+
+    var visible = true;
+    for (var sample = 1u; sample < sample_count; sample = sample + 1u) {
+        let t = f32(sample) / f32(sample_count);
+        let position = mix(parameters.observer, target_position, t);
+
+        if (terrain_height(position) > mix(observer_height, target_height, t)) {
+            visible = false;
+            break;
+        }
+    }
+
+    textureStore(viewshed, coords, select(parameters.hidden_color, parameters.visible_color, visible));
+}
@@ -68,7 +68,7 @@ fn show_lod(lod: u32, world_position: vec3<f32>) -> vec4<f32> {
     var color = lod_color(lod);
 
     for (var i = 0u; i < config.lod_count; i = i + 1u) {
-        let viewer_distance = distance(view.world_position.xyz, world_position);
+        let viewer_distance = distance(view_config.viewer_position.xyz, world_position);
         let circle = f32(1u << i) * view_config.blend_distance;
 
         if (viewer_distance < circle && circle - f32(8 << i) < viewer_distance) {
@@ -77,7 +77,7 @@ fn show_lod(lod: u32, world_position: vec3<f32>) -> vec4<f32> {
 
 #ifdef SHOW_NODES
         let node_size = node_size(i);
-        let grid_position = floor(view.world_position.xz / node_size + 0.5 - f32(view_config.node_count >> 1u)) * node_size;
+        let grid_position = floor(view_config.viewer_position.xz / node_size + 0.5 - f32(view_config.node_count >> 1u)) * node_size;
         let grid_size = node_size * f32(view_config.node_count);
         let thickness = f32(8u << i);
 
@@ -91,3 +91,120 @@ fn show_lod(lod: u32, world_position: vec3<f32>) -> vec4<f32> {
 
     return color;
 }
+
+// Looks up the color of the normalized value in the color ramp.
+fn color_ramp(value: f32) -> vec4<f32> {
+    // sample at the texel centers, so that the first and last stop are reached exactly
+    let coordinate = (clamp(value, 0.0, 1.0) * 255.0 + 0.5) / 256.0;
+
+    return textureSampleLevel(color_ramp_texture, color_ramp_sampler, vec2<f32>(coordinate, 0.5), 0.0);
+}
+
+// Colors the normalized height using the color ramp.
+fn show_height(height: f32) -> vec4<f32> {
+    return color_ramp(height);
+}
+
+// Colors the slope angle from flat (0°) to vertical (90°) using the color ramp.
+fn show_slope(world_normal: vec3<f32>) -> vec4<f32> {
+    let slope = degrees(acos(clamp(world_normal.y, 0.0, 1.0)));
+
+    return color_ramp(slope / 90.0);
+}
+
+// Colors the compass direction the slope is facing as a hue (north red, east yellow-green,
+// south cyan, west purple). Flat areas without a distinct aspect are gray.
+fn show_aspect(world_normal: vec3<f32>) -> vec4<f32> {
+    let horizontal = vec2<f32>(world_normal.x, -world_normal.z);
+
+    if (length(horizontal) < 0.01) {
+        return vec4<f32>(0.5, 0.5, 0.5, 1.0);
+    }
+
+    // clockwise angle from north (negative z) in the range of [0, 1)
+    let aspect = fract(atan2(horizontal.x, horizontal.y) / 6.283185307 + 1.0);
+    let hue = clamp(abs(fract(vec3<f32>(aspect) + vec3<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    return vec4<f32>(hue, 1.0);
+}
+
+// Shades the terrain like a GIS hillshade, lit by the sun of the terrain view.
+fn show_hillshade(world_normal: vec3<f32>) -> vec4<f32> {
+    let azimuth = view_config.sun_azimuth;
+    let elevation = view_config.sun_elevation;
+    let sun_direction = vec3<f32>(sin(azimuth) * cos(elevation), sin(elevation), -cos(azimuth) * cos(elevation));
+
+    let shade = max(dot(world_normal, sun_direction), 0.0);
+
+    return vec4<f32>(vec3<f32>(shade), 1.0);
+}
+
+// Replaces the color with the enabled analysis views (height, slope, aspect and hillshade).
+// The hillshade is multiplied onto the other analysis colors, if they are enabled as well.
+fn show_analysis(base_color: vec4<f32>, world_normal: vec3<f32>, height: f32) -> vec4<f32> {
+    var color = base_color;
+    var shade = vec4<f32>(1.0);
+
+#ifdef SHOW_HEIGHT
+    color = show_height(height);
+#endif
+
+#ifdef SHOW_SLOPE
+    color = show_slope(world_normal);
+#endif
+
+#ifdef SHOW_ASPECT
+    color = show_aspect(world_normal);
+#endif
+
+#ifdef SHOW_HILLSHADE
+    shade = show_hillshade(world_normal);
+#ifndef SHOW_HEIGHT
+#ifndef SHOW_SLOPE
+#ifndef SHOW_ASPECT
+    color = vec4<f32>(1.0);
+#endif
+#endif
+#endif
+#endif
+
+    return color * shade;
+}
+
+// Colors the signed height difference with a diverging ramp, from cut (red) over
+// unchanged (white) to fill (blue). The ramp saturates at the difference range of the view.
+fn show_height_difference(difference: f32) -> vec4<f32> {
+    let ratio = clamp(difference / view_config.difference_range, -1.0, 1.0);
+
+    var color: vec3<f32>;
+
+    if (ratio < 0.0) {
+        color = mix(vec3<f32>(1.0), vec3<f32>(0.8, 0.1, 0.1), -ratio);
+    } else {
+        color = mix(vec3<f32>(1.0), vec3<f32>(0.1, 0.3, 0.8), ratio);
+    }
+
+    return vec4<f32>(color, 1.0);
+}
+
+// Draws the streams of the normalized flow accumulation in blue over the color.
+fn show_flow(color: vec4<f32>, accumulation: f32) -> vec4<f32> {
+    let stream = smoothstep(0.3, 0.8, accumulation);
+
+    return mix(color, vec4<f32>(0.1, 0.35, 0.9, 1.0), stream);
+}
+
+// Colors each watershed with a distinct hue. Pixels without a watershed are gray.
+fn show_watershed(watershed: f32) -> vec4<f32> {
+    let id = u32(round(watershed * 65535.0));
+
+    if (id == 0u) {
+        return vec4<f32>(0.5, 0.5, 0.5, 1.0);
+    }
+
+    // the golden ratio scatters the hues of neighbouring ids
+    let hue = fract(f32(id) * 0.618034);
+    let color = clamp(abs(fract(vec3<f32>(hue) + vec3<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0)) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    return vec4<f32>(mix(color, vec3<f32>(1.0), 0.3), 1.0);
+}
@@ -0,0 +1,30 @@
+#define_import_path bevy_terrain::decals
+
+// Has to match the DECAL_CLUSTER_COUNT.
+let DECAL_CLUSTER_COUNT: u32 = 32u;
+
+// Blends all decals, that cover the local position, on top of the color.
+fn apply_decals(base_color: vec4<f32>, local_position: vec2<f32>) -> vec4<f32> {
+    let cluster_size = f32(config.terrain_size) / f32(DECAL_CLUSTER_COUNT);
+    let cluster_coords = clamp(vec2<u32>(local_position / cluster_size), vec2<u32>(0u), vec2<u32>(DECAL_CLUSTER_COUNT - 1u));
+    let cluster = decal_clusters.data[cluster_coords.y * DECAL_CLUSTER_COUNT + cluster_coords.x];
+
+    var color = base_color;
+
+    for (var i: u32 = 0u; i < cluster.y; i = i + 1u) {
+        let decal = decals.data[decal_indices.data[cluster.x + i]];
+
+        // transform the position into the rotated decal space
+        let offset = local_position - decal.position;
+        let uv = vec2<f32>(dot(offset, decal.rotation), dot(offset, vec2<f32>(-decal.rotation.y, decal.rotation.x))) / decal.half_size * 0.5 + 0.5;
+
+        if (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+            continue;
+        }
+
+        let decal_color = textureSampleLevel(decal_atlas, decal_sampler, uv, i32(decal.layer), 0.0) * decal.color;
+        color = vec4<f32>(mix(color.rgb, decal_color.rgb, decal_color.a), color.a);
+    }
+
+    return color;
+}
@@ -3,6 +3,10 @@
 struct VertexInput {
     @builtin(instance_index) instance: u32,
     @builtin(vertex_index)   vertex_index: u32,
+#ifdef DOWNLEVEL
+    // the coordinates and the size of the tile, which are refined on the CPU
+    @location(0)             tile: vec3<u32>,
+#endif
 }
 
 struct VertexOutput {
@@ -10,6 +14,8 @@ struct VertexOutput {
     @location(0)             local_position: vec2<f32>,
     @location(1)             world_position: vec4<f32>,
     @location(2)             debug_color: vec4<f32>,
+    // greater than zero on the walls of the border skirt
+    @location(3)             skirt: f32,
 }
 
 fn vertex_output(local_position: vec2<f32>, height: f32) -> VertexOutput {
@@ -20,6 +26,7 @@ fn vertex_output(local_position: vec2<f32>, height: f32) -> VertexOutput {
     output.local_position = vec2<f32>(local_position);
     output.world_position = world_position;
     output.debug_color = vec4<f32>(0.0);
+    output.skirt = 0.0;
 
     return output;
 }
@@ -30,6 +37,7 @@ struct FragmentInput {
     @location(0)             local_position: vec2<f32>,
     @location(1)             world_position: vec4<f32>,
     @location(2)             debug_color: vec4<f32>,
+    @location(3)             skirt: f32,
 }
 
 struct FragmentOutput {
@@ -48,7 +56,7 @@ struct Blend {
 }
 
 fn calculate_blend(world_position: vec4<f32>) -> Blend {
-    let viewer_distance = distance(world_position.xyz, view.world_position.xyz);
+    let viewer_distance = distance(world_position.xyz, view_config.viewer_position.xyz);
     let log_distance = max(log2(2.0 * viewer_distance / view_config.blend_distance), 0.0);
     let ratio = (1.0 - log_distance % 1.0) / view_config.blend_range;
 
@@ -56,7 +64,7 @@ fn calculate_blend(world_position: vec4<f32>) -> Blend {
 }
 
 fn calculate_morph(tile: Tile, world_position: vec4<f32>) -> f32 {
-    let viewer_distance = distance(world_position.xyz, view.world_position.xyz);
+    let viewer_distance = distance(world_position.xyz, view_config.viewer_position.xyz);
     let morph_distance = view_config.morph_distance * f32(tile.size << 1u);
 
     return clamp(1.0 - (1.0 - viewer_distance / morph_distance) / view_config.morph_range, 0.0, 1.0);
@@ -70,6 +78,27 @@ fn calculate_grid_position(grid_index: u32) -> vec2<u32>{
     return vec2<u32>(column_index + (row_index & 1u), row_index >> 1u);
 }
 
+// Returns the index of the tile and the position inside its grid of the vertex.
+fn calculate_tile_vertex(in: VertexInput) -> vec3<u32> {
+#ifdef DOWNLEVEL
+    // each tile is an instance, regardless of the patch topology
+    if (view_config.patch_topology == STRIP) {
+        return vec3<u32>(in.instance, calculate_grid_position(in.vertex_index));
+    }
+#endif
+
+    if (view_config.patch_topology == INDEXED_STRIP) {
+        // each tile is an instance and the vertex index is the index into its grid
+        let vertices_per_row = u32(view_config.grid_size) + 1u;
+        return vec3<u32>(in.instance, in.vertex_index % vertices_per_row, in.vertex_index / vertices_per_row);
+    }
+
+    let tile_index = in.vertex_index / view_config.vertices_per_tile;
+    let grid_index = in.vertex_index % view_config.vertices_per_tile;
+
+    return vec3<u32>(tile_index, calculate_grid_position(grid_index));
+}
+
 fn calculate_local_position(tile: Tile, grid_position: vec2<u32>) -> vec2<f32> {
     let size = f32(tile.size) * view_config.tile_scale;
 
@@ -88,29 +117,174 @@ fn calculate_local_position(tile: Tile, grid_position: vec2<u32>) -> vec2<f32> {
     return local_position;
 }
 
+// Returns the position of the vertex along one axis and whether it forms the bottom of the border skirt (1.0) or not (0.0).
+// The outermost vertex of a tile on the border forms the bottom of the wall and its neighbour the top,
+// thus both are moved onto the border.
+fn border_skirt_axis(position: f32, tile_start: f32, grid_index: u32, spacing: f32) -> vec2<f32> {
+    let terrain_size = f32(config.terrain_size);
+    let last_index = u32(view_config.grid_size);
+
+    if (tile_start == 0.0 && grid_index <= 1u) {
+        return vec2<f32>(0.0, f32(grid_index == 0u));
+    }
+
+    // the first vertex at or beyond the upper border forms the top and all following ones the bottom,
+    // if it is the last vertex of the tile, the vertex before it forms the top instead
+    let crossing = u32(max(ceil((terrain_size - tile_start) / spacing), 0.0));
+
+    if (crossing == last_index && grid_index + 1u >= last_index) {
+        return vec2<f32>(terrain_size, f32(grid_index == last_index));
+    }
+    if (crossing < last_index && grid_index >= crossing) {
+        return vec2<f32>(terrain_size, f32(grid_index > crossing));
+    }
+
+    return vec2<f32>(position, 0.0);
+}
+
+// Moves the vertices of tiles on the border of the terrain onto it, so that they form vertical walls.
+// Returns the local position and whether the vertex forms the bottom of the wall (1.0) or not (0.0).
+fn border_skirt(tile: Tile, grid_position: vec2<u32>, local_position: vec2<f32>) -> vec3<f32> {
+    let size = f32(tile.size) * view_config.tile_scale;
+    let spacing = size / view_config.grid_size;
+    let tile_start = vec2<f32>(tile.coords) * size;
+
+    let x = border_skirt_axis(local_position.x, tile_start.x, grid_position.x, spacing);
+    let y = border_skirt_axis(local_position.y, tile_start.y, grid_position.y, spacing);
+
+    return vec3<f32>(x.x, y.x, max(x.y, y.y));
+}
+
+// Returns the normalized height of a sample of the height attachment.
+// With a residual, the second channel stores the remainder of the quantization of the first one.
+// 32 bit float heights are split into a 16 bit float value and its remainder as well,
+// while the second channel of all other formats is zero.
+fn height_value(sample: vec4<f32>) -> f32 {
+#ifdef HEIGHT_RESIDUAL
+    return sample.x + sample.y / 65535.0;
+#else
+    return sample.x + sample.y;
+#endif
+}
+
+fn height_tap(coords: vec2<f32>, atlas_index: i32) -> f32 {
+    return height_value(textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0));
+}
+
+// Samples the normalized height with a Catmull-Rom filter, which is smooth across the pixels.
+// The sixteen pixels of the filter are gathered with nine bilinear taps, by merging the weights of the inner two pixels.
+fn sample_height_catmull_rom(coords: vec2<f32>, atlas_index: i32) -> f32 {
+    let size = config.height_size;
+    let position = coords * size;
+    let center = floor(position - 0.5) + 0.5;
+    let f = position - center;
+
+    let w0 = f * (-0.5 + f * (1.0 - 0.5 * f));
+    let w1 = 1.0 + f * f * (-2.5 + 1.5 * f);
+    let w2 = f * (0.5 + f * (2.0 - 1.5 * f));
+    let w3 = f * f * (-0.5 + 0.5 * f);
+    let w12 = w1 + w2;
+
+    let coords0  = (center - 1.0) / size;
+    let coords12 = (center + w2 / w12) / size;
+    let coords3  = (center + 2.0) / size;
+
+    var height = 0.0;
+    height += height_tap(vec2<f32>( coords0.x,  coords0.y), atlas_index) *  w0.x *  w0.y;
+    height += height_tap(vec2<f32>(coords12.x,  coords0.y), atlas_index) * w12.x *  w0.y;
+    height += height_tap(vec2<f32>( coords3.x,  coords0.y), atlas_index) *  w3.x *  w0.y;
+    height += height_tap(vec2<f32>( coords0.x, coords12.y), atlas_index) *  w0.x * w12.y;
+    height += height_tap(vec2<f32>(coords12.x, coords12.y), atlas_index) * w12.x * w12.y;
+    height += height_tap(vec2<f32>( coords3.x, coords12.y), atlas_index) *  w3.x * w12.y;
+    height += height_tap(vec2<f32>( coords0.x,  coords3.y), atlas_index) *  w0.x *  w3.y;
+    height += height_tap(vec2<f32>(coords12.x,  coords3.y), atlas_index) * w12.x *  w3.y;
+    height += height_tap(vec2<f32>( coords3.x,  coords3.y), atlas_index) *  w3.x *  w3.y;
+
+    return height;
+}
+
+// Samples the normalized height with a cubic B-spline filter, which is smooth across the pixels, but does not pass through them.
+// Since all weights are positive, the sixteen pixels of the filter are gathered with four bilinear taps.
+fn sample_height_b_spline(coords: vec2<f32>, atlas_index: i32) -> f32 {
+    let size = config.height_size;
+    let position = coords * size;
+    let center = floor(position - 0.5) + 0.5;
+    let f = position - center;
+
+    let w0 = (1.0 - f) * (1.0 - f) * (1.0 - f) / 6.0;
+    let w1 = (4.0 + f * f * (-6.0 + 3.0 * f)) / 6.0;
+    let w2 = (1.0 + f * (3.0 + f * (3.0 - 3.0 * f))) / 6.0;
+    let w3 = f * f * f / 6.0;
+    let w01 = w0 + w1;
+    let w23 = w2 + w3;
+
+    let coords01 = (center - 1.0 + w1 / w01) / size;
+    let coords23 = (center + 1.0 + w3 / w23) / size;
+
+    var height = 0.0;
+    height += height_tap(vec2<f32>(coords01.x, coords01.y), atlas_index) * w01.x * w01.y;
+    height += height_tap(vec2<f32>(coords23.x, coords01.y), atlas_index) * w23.x * w01.y;
+    height += height_tap(vec2<f32>(coords01.x, coords23.y), atlas_index) * w01.x * w23.y;
+    height += height_tap(vec2<f32>(coords23.x, coords23.y), atlas_index) * w23.x * w23.y;
+
+    return height;
+}
+
+#ifdef BICUBIC_NORMALS
+// Samples the normalized height with the bicubic filter of the normals.
+fn normal_height(coords: vec2<f32>, atlas_index: i32) -> f32 {
+#ifdef B_SPLINE_NORMALS
+    return sample_height_b_spline(coords, atlas_index);
+#else
+    return sample_height_catmull_rom(coords, atlas_index);
+#endif
+}
+#endif
+
 fn calculate_normal(coords: vec2<f32>, atlas_index: i32, atlas_lod: u32, ddx: vec2<f32>, ddy: vec2<f32>) -> vec3<f32> {
+#ifdef BICUBIC_NORMALS
+    // the smooth reconstruction hides the stair steps of the quantized heights
+    let offset = 1.0 / config.height_size;
+    let left  = normal_height(coords + vec2<f32>(-offset,     0.0), atlas_index);
+    let up    = normal_height(coords + vec2<f32>(    0.0, -offset), atlas_index);
+    let right = normal_height(coords + vec2<f32>( offset,     0.0), atlas_index);
+    let down  = normal_height(coords + vec2<f32>(    0.0,  offset), atlas_index);
+#else
 #ifdef SAMPLE_GRAD
     let offset = 1.0 / config.height_size;
-    let left  = textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(-offset,     0.0), atlas_index, ddx, ddy).x;
-    let up    = textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(    0.0, -offset), atlas_index, ddx, ddy).x;
-    let right = textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>( offset,     0.0), atlas_index, ddx, ddy).x;
-    let down  = textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(    0.0,  offset), atlas_index, ddx, ddy).x;
+    let left  = height_value(textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(-offset,     0.0), atlas_index, ddx, ddy));
+    let up    = height_value(textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(    0.0, -offset), atlas_index, ddx, ddy));
+    let right = height_value(textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>( offset,     0.0), atlas_index, ddx, ddy));
+    let down  = height_value(textureSampleGrad(height_atlas, atlas_sampler, coords + vec2<f32>(    0.0,  offset), atlas_index, ddx, ddy));
 #else
-    let left  = textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>(-1,  0)).x;
-    let up    = textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 0, -1)).x;
-    let right = textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 1,  0)).x;
-    let down  = textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 0,  1)).x;
-
+    let left  = height_value(textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>(-1,  0)));
+    let up    = height_value(textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 0, -1)));
+    let right = height_value(textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 1,  0)));
+    let down  = height_value(textureSampleLevel(height_atlas, atlas_sampler, coords, atlas_index, 0.0, vec2<i32>( 0,  1)));
+#endif
 #endif
 
     return normalize(vec3<f32>(right - left, f32(2u << atlas_lod) / config.height, down - up));
 }
 
+// Interleaved gradient noise in the range of zero to one, as described by Jimenez (2014).
+fn interleaved_gradient_noise(frag_coord: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(frag_coord, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// Adds triangular noise of up to one 8 bit quantization step to the color,
+// which breaks up the banding of smooth gradients.
+fn deband_dither(color: vec4<f32>, frag_coord: vec2<f32>) -> vec4<f32> {
+    let noise = interleaved_gradient_noise(frag_coord) + interleaved_gradient_noise(frag_coord + vec2<f32>(47.0, 17.0)) - 1.0;
+
+    return vec4<f32>(color.rgb + noise / 255.0, color.a);
+}
+
 fn minmax(local_position: vec2<f32>, size: f32) -> vec2<f32> {
     let lod = u32(ceil(log2(size))) + 1u;
 
     if (lod >= config.lod_count) {
-        return vec2<f32>(0.0, config.height);
+        return vec2<f32>(0.0, config.height) + view_config.elevation_offset;
     }
 
     let lookup = lookup_node(lod, local_position);
@@ -123,5 +297,5 @@ fn minmax(local_position: vec2<f32>, size: f32) -> vec2<f32> {
     var min_height = min(min(min_gather.x, min_gather.y), min(min_gather.z, min_gather.w));
     var max_height = max(max(max_gather.x, max_gather.y), max(max_gather.z, max_gather.w));
 
-    return vec2(min_height, max_height) * config.height;
+    return vec2(min_height, max_height) * config.height + view_config.elevation_offset;
 }
@@ -1,4 +1,4 @@
-use bevy::{prelude::*, reflect::TypeUuid};
+use bevy::{asset::load_internal_asset, prelude::*, reflect::TypeUuid};
 
 const TYPES_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 907665645684322571);
@@ -10,6 +10,22 @@ const FUNCTIONS_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 234313897973543254);
 const DEBUG_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 513467378691355413);
+const DECALS_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 845123675420986531);
+const ATMOSPHERE_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 463092817365012894);
+const TINT_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 758206139472015683);
+const WEATHER_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 381920475163028749);
+const VIEWSHED_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 629471058316204957);
+const VIRTUAL_TEXTURE_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 503826194731650842);
+const ATLAS_BINDINGS_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 952731604817263409);
+const ATLAS_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 316048259173620584);
 const MINMAX_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 705341350987806053);
 const VERTEX_SHADER: HandleUntyped =
@@ -21,49 +37,161 @@ pub(crate) const PREPARE_INDIRECT_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 242384313596767307);
 pub(crate) const REFINE_TILES_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 938732132468373352);
+pub(crate) const REFINEMENT_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 411372458120657943);
+pub(crate) const SCATTER_VEGETATION_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 907135462801934571);
+pub(crate) const HEIGHT_QUERY_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 473910285617340926);
+pub(crate) const EROSION_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 690157342810596823);
+pub(crate) const COMPUTE_VIEWSHED_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 148206937524180637);
 
 pub(crate) const DEFAULT_SHADER: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 556563744564564658);
+pub(crate) const STANDARD_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 590218734612098354);
+pub(crate) const VECTOR_LAYER_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 634178902346512879);
+pub(crate) const VEGETATION_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 278450913672309415);
+pub(crate) const WATER_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 861523094718263540);
+pub(crate) const FALLBACK_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 327409861253470918);
+pub(crate) const DEFORMATION_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 815320746921538064);
+pub(crate) const SOFT_INTERSECTION_SHADER: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 407218965340172853);
 
+/// Registers the terrain shaders.
+///
+/// With the `debug_asset_server` feature enabled, the shaders are loaded from the source directory
+/// and hot reloaded, whenever they change.
 pub(crate) fn add_shader(app: &mut App) {
-    let mut assets = app.world.resource_mut::<Assets<_>>();
+    load_internal_asset!(app, TYPES_SHADER, "types.wgsl", Shader::from_wgsl);
 
-    assets.set_untracked(TYPES_SHADER, Shader::from_wgsl(include_str!("types.wgsl")));
-
-    assets.set_untracked(
+    load_internal_asset!(
+        app,
         PARAMETERS_SHADER,
-        Shader::from_wgsl(include_str!("compute/parameters.wgsl")),
-    );
-    assets.set_untracked(NODE_SHADER, Shader::from_wgsl(include_str!("node.wgsl")));
-    assets.set_untracked(
-        FUNCTIONS_SHADER,
-        Shader::from_wgsl(include_str!("functions.wgsl")),
+        "compute/parameters.wgsl",
+        Shader::from_wgsl
     );
-    assets.set_untracked(DEBUG_SHADER, Shader::from_wgsl(include_str!("debug.wgsl")));
-
-    assets.set_untracked(
-        MINMAX_SHADER,
-        Shader::from_wgsl(include_str!("render/minmax.wgsl")),
+    load_internal_asset!(app, NODE_SHADER, "node.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, FUNCTIONS_SHADER, "functions.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, DEBUG_SHADER, "debug.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, DECALS_SHADER, "decals.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, ATMOSPHERE_SHADER, "atmosphere.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, TINT_SHADER, "tint.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, WEATHER_SHADER, "weather.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, VIEWSHED_SHADER, "viewshed.wgsl", Shader::from_wgsl);
+    load_internal_asset!(
+        app,
+        VIRTUAL_TEXTURE_SHADER,
+        "virtual_texture.wgsl",
+        Shader::from_wgsl
     );
-    assets.set_untracked(
-        VERTEX_SHADER,
-        Shader::from_wgsl(include_str!("render/vertex.wgsl")),
+    load_internal_asset!(
+        app,
+        ATLAS_BINDINGS_SHADER,
+        "atlas_bindings.wgsl",
+        Shader::from_wgsl
     );
-    assets.set_untracked(
+    load_internal_asset!(app, ATLAS_SHADER, "atlas.wgsl", Shader::from_wgsl);
+
+    load_internal_asset!(app, MINMAX_SHADER, "render/minmax.wgsl", Shader::from_wgsl);
+    load_internal_asset!(app, VERTEX_SHADER, "render/vertex.wgsl", Shader::from_wgsl);
+    load_internal_asset!(
+        app,
         FRAGMENT_SHADER,
-        Shader::from_wgsl(include_str!("render/fragment.wgsl")),
+        "render/fragment.wgsl",
+        Shader::from_wgsl
     );
-    assets.set_untracked(
+    load_internal_asset!(
+        app,
         DEFAULT_SHADER,
-        Shader::from_wgsl(include_str!("render/default.wgsl")),
+        "render/default.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        STANDARD_SHADER,
+        "render/standard.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        VECTOR_LAYER_SHADER,
+        "render/vector_layer.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        VEGETATION_SHADER,
+        "render/vegetation.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(app, WATER_SHADER, "render/water.wgsl", Shader::from_wgsl);
+    load_internal_asset!(
+        app,
+        FALLBACK_SHADER,
+        "render/fallback.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        DEFORMATION_SHADER,
+        "render/deformation.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        SOFT_INTERSECTION_SHADER,
+        "render/soft_intersection.wgsl",
+        Shader::from_wgsl
     );
 
-    assets.set_untracked(
+    load_internal_asset!(
+        app,
         PREPARE_INDIRECT_SHADER,
-        Shader::from_wgsl(include_str!("compute/prepare_indirect.wgsl")),
+        "compute/prepare_indirect.wgsl",
+        Shader::from_wgsl
     );
-    assets.set_untracked(
+    load_internal_asset!(
+        app,
         REFINE_TILES_SHADER,
-        Shader::from_wgsl(include_str!("compute/refine_tiles.wgsl")),
+        "compute/refine_tiles.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        REFINEMENT_SHADER,
+        "compute/refinement.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        SCATTER_VEGETATION_SHADER,
+        "compute/scatter_vegetation.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        HEIGHT_QUERY_SHADER,
+        "compute/height_query.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        EROSION_SHADER,
+        "compute/erosion.wgsl",
+        Shader::from_wgsl
+    );
+    load_internal_asset!(
+        app,
+        COMPUTE_VIEWSHED_SHADER,
+        "compute/viewshed.wgsl",
+        Shader::from_wgsl
     );
 }
@@ -5,8 +5,15 @@ struct NodeLookup {
     atlas_lod: u32,
     atlas_index: i32,
     atlas_coords: vec2<f32>,
+    // whether the position is covered by the data of a sparse terrain
+    covered: bool,
 }
 
+// The atlas index of quadtree entries, for which no node is available.
+let INVALID_ATLAS_INDEX: u32 = 65535u;
+// The atlas lod of quadtree entries, whose node lies outside of the coverage of the terrain.
+let UNCOVERED_LOD: u32 = 65534u;
+
 fn approximate_world_position(local_position: vec2<f32>) -> vec4<f32> {
     return vec4<f32>(local_position.x, view_config.approximate_height, local_position.y, 1.0);
 }
@@ -15,6 +22,37 @@ fn node_size(lod: u32) -> f32 {
     return f32(config.leaf_node_size * (1u << lod));
 }
 
+// Returns the bounds (min and max) of the coarsest quadtree layer, beyond which no nodes are available.
+fn quadtree_bounds() -> vec4<f32> {
+    let size = node_size(config.lod_count - 1u);
+    let grid_min = floor(view_config.viewer_position.xz / size + 0.5 - f32(view_config.node_count >> 1u)) * size;
+
+    return vec4<f32>(grid_min, grid_min + f32(view_config.node_count) * size);
+}
+
+// Whether the position lies beyond the coarsest quadtree layer, where the far field of the terrain is rendered instead.
+fn inside_far_field(local_position: vec2<f32>) -> bool {
+    if (view_config.far_field == 0u) {
+        return false;
+    }
+
+    let bounds = quadtree_bounds();
+
+    return any(local_position < bounds.xy) || any(local_position >= bounds.zw);
+}
+
+// Clamps the position into the coarsest quadtree layer, if the terrain has a far field,
+// so that vertices beyond it do not sample the nodes of the wrapped around quadtree.
+fn clamp_to_quadtree(local_position: vec2<f32>) -> vec2<f32> {
+    if (view_config.far_field == 0u) {
+        return local_position;
+    }
+
+    let bounds = quadtree_bounds();
+
+    return clamp(local_position, bounds.xy, bounds.zw - 0.5 * f32(config.leaf_node_size));
+}
+
 // Looks up the best availale node in the node atlas from the viewers point of view.
 // This is done by sampling the viewers quadtree at the caluclated coordinate.
 fn lookup_node(lod: u32, local_position: vec2<f32>) -> NodeLookup {
@@ -22,7 +60,7 @@ fn lookup_node(lod: u32, local_position: vec2<f32>) -> NodeLookup {
     var quadtree_lod = 0u;
     for (; quadtree_lod < config.lod_count; quadtree_lod = quadtree_lod + 1u) {
         let coordinate = local_position / node_size(quadtree_lod);
-        let grid_coordinate = floor(view.world_position.xz / node_size(quadtree_lod) + 0.5 - f32(view_config.node_count >> 1u));
+        let grid_coordinate = floor(view_config.viewer_position.xz / node_size(quadtree_lod) + 0.5 - f32(view_config.node_count >> 1u));
 
         let grid = step(grid_coordinate, coordinate) * (1.0 - step(grid_coordinate + f32(view_config.node_count), coordinate));
 
@@ -31,15 +69,52 @@ fn lookup_node(lod: u32, local_position: vec2<f32>) -> NodeLookup {
         }
     }
 #else
-    let quadtree_lod = min(lod, config.lod_count - 1u);
+    var quadtree_lod = min(lod, config.lod_count - 1u);
 #endif
 
-    let quadtree_coords = vec2<i32>((local_position / node_size(quadtree_lod)) % f32(view_config.node_count));
-    let lookup = textureLoad(quadtree, quadtree_coords, i32(quadtree_lod), 0);
+    var quadtree_coords = vec2<i32>((local_position / node_size(quadtree_lod)) % f32(view_config.node_count));
+    var lookup = textureLoad(quadtree, quadtree_coords, i32(quadtree_lod), 0);
+
+    // the parents of covered nodes are covered as well, thus this has to be checked only once
+    let covered = lookup.y != UNCOVERED_LOD;
 
-    let atlas_index = i32(lookup.x);
-    let atlas_lod   = lookup.y;
+    // nodes missing from sparse datasets are not available, fall back to the next coarser quadtree layer
+    loop {
+        if (!covered || lookup.x != INVALID_ATLAS_INDEX || quadtree_lod >= config.lod_count - 1u) {
+            break;
+        }
+
+        quadtree_lod = quadtree_lod + 1u;
+        quadtree_coords = vec2<i32>((local_position / node_size(quadtree_lod)) % f32(view_config.node_count));
+        lookup = textureLoad(quadtree, quadtree_coords, i32(quadtree_lod), 0);
+    }
+
+    var atlas_index = i32(lookup.x);
+    var atlas_lod   = lookup.y;
+
+    // no ancestor is available either or the position is not covered, sample the first atlas layer instead of reading out of bounds
+    if (lookup.x == INVALID_ATLAS_INDEX) {
+        atlas_index = 0;
+        atlas_lod   = config.lod_count - 1u;
+    }
     let atlas_coords = (local_position / node_size(atlas_lod)) % 1.0;
 
-    return NodeLookup(atlas_lod, atlas_index, atlas_coords);
+    return NodeLookup(atlas_lod, atlas_index, atlas_coords, covered);
 }
+
+#ifdef NODE_FADE
+// The progress of the cross-fade of a newly loaded node, from zero (only the coarser data) to one (only its own data).
+fn node_fade(lookup: NodeLookup) -> f32 {
+    return textureLoad(node_fade_texture, vec2<i32>(lookup.atlas_index, 0), 0).x;
+}
+
+// The next coarser data, which a fading node is blended with.
+fn fade_lookup(lookup: NodeLookup, local_position: vec2<f32>) -> NodeLookup {
+    return lookup_node(lookup.atlas_lod + 1u, local_position);
+}
+
+// Whether or not the node is still fading in and has any coarser data to fade from.
+fn is_fading(lookup: NodeLookup, fade: f32) -> bool {
+    return fade < 1.0 && lookup.covered && lookup.atlas_lod + 1u < config.lod_count;
+}
+#endif
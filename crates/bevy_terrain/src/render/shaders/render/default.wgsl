@@ -8,16 +8,24 @@ struct TerrainConfig {
 
     height_size: f32,
     minmax_size: f32,
-    _empty: u32,
-    _empty: u32,
+    mask_size: f32,
+    reference_size: f32,
     height_scale: f32,
     minmax_scale: f32,
-    _empty: u32,
-    _empty: u32,
+    mask_scale: f32,
+    reference_scale: f32,
     height_offset: f32,
     minmax_offset: f32,
-    _empty: u32,
-    _empty: u32,
+    mask_offset: f32,
+    reference_offset: f32,
+
+    // the attachments four to seven are not used by the default shader
+    extra_sizes: vec4<f32>,
+    extra_scales: vec4<f32>,
+    extra_offsets: vec4<f32>,
+
+    attachment_count: u32,
+    biome_colors: array<vec4<f32>, 16>,
 }
 
 // view bindings
@@ -28,8 +36,42 @@ struct TerrainConfig {
 var<uniform> view_config: TerrainViewConfig;
 @group(1) @binding(1)
 var quadtree: texture_2d_array<u32>;
+#ifndef DOWNLEVEL
 @group(1) @binding(2)
 var<storage> tiles: TileList;
+@group(1) @binding(3)
+var<storage> decals: DecalList;
+@group(1) @binding(4)
+var<storage> decal_clusters: DecalClusterList;
+@group(1) @binding(5)
+var<storage> decal_indices: DecalIndexList;
+#endif
+@group(1) @binding(6)
+var decal_atlas: texture_2d_array<f32>;
+@group(1) @binding(7)
+var decal_sampler: sampler;
+@group(1) @binding(8)
+var color_ramp_texture: texture_2d<f32>;
+@group(1) @binding(9)
+var color_ramp_sampler: sampler;
+@group(1) @binding(10)
+var<uniform> atmosphere: TerrainAtmosphere;
+@group(1) @binding(11)
+var tint_texture: texture_2d<f32>;
+@group(1) @binding(12)
+var weather_texture: texture_2d<f32>;
+@group(1) @binding(13)
+var viewshed_texture: texture_2d<f32>;
+#ifdef VIRTUAL_TEXTURE
+@group(1) @binding(14)
+var<uniform> virtual_texture: VirtualTexture;
+@group(1) @binding(15)
+var page_table: texture_2d_array<u32>;
+@group(1) @binding(16)
+var page_cache: texture_2d<f32>;
+@group(1) @binding(17)
+var<storage, read_write> page_feedback: PageFeedback;
+#endif
 
 // terrain bindings
 @group(2) @binding(0)
@@ -38,8 +80,76 @@ var<uniform> config: TerrainConfig;
 var atlas_sampler: sampler;
 @group(2) @binding(2)
 var height_atlas: texture_2d_array<f32>;
+@group(2) @binding(11)
+var height_sampler: sampler;
 @group(2) @binding(3)
 var minmax_atlas: texture_2d_array<f32>;
+#ifdef MASK
+@group(2) @binding(4)
+var mask_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var mask_sampler: sampler;
+#endif
+#ifdef REFERENCE_ATTACHMENT_2
+@group(2) @binding(4)
+var reference_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var reference_sampler: sampler;
+#endif
+#ifdef REFERENCE_ATTACHMENT_3
+@group(2) @binding(5)
+var reference_atlas: texture_2d_array<f32>;
+@group(2) @binding(14)
+var reference_sampler: sampler;
+#endif
+#ifdef BATHYMETRY_ATTACHMENT_2
+@group(2) @binding(4)
+var bathymetry_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var bathymetry_sampler: sampler;
+#endif
+#ifdef BATHYMETRY_ATTACHMENT_3
+@group(2) @binding(5)
+var bathymetry_atlas: texture_2d_array<f32>;
+@group(2) @binding(14)
+var bathymetry_sampler: sampler;
+#endif
+#ifdef FLOW_ATTACHMENT_2
+@group(2) @binding(4)
+var flow_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var flow_sampler: sampler;
+#endif
+#ifdef FLOW_ATTACHMENT_3
+@group(2) @binding(5)
+var flow_atlas: texture_2d_array<f32>;
+@group(2) @binding(14)
+var flow_sampler: sampler;
+#endif
+#ifdef CEILING_ATTACHMENT_2
+@group(2) @binding(4)
+var ceiling_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var ceiling_sampler: sampler;
+#endif
+#ifdef CEILING_ATTACHMENT_3
+@group(2) @binding(5)
+var ceiling_atlas: texture_2d_array<f32>;
+@group(2) @binding(14)
+var ceiling_sampler: sampler;
+#endif
+#ifdef BIOME_ATTACHMENT_2
+@group(2) @binding(4)
+var biome_atlas: texture_2d_array<f32>;
+#endif
+#ifdef BIOME_ATTACHMENT_3
+@group(2) @binding(5)
+var biome_atlas: texture_2d_array<f32>;
+#endif
+#ifdef NODE_FADE
+@group(2) @binding(10)
+var node_fade_texture: texture_2d<f32>;
+#endif
 
 #import bevy_pbr::mesh_types
 #import bevy_pbr::pbr_types
@@ -52,19 +162,236 @@ var minmax_atlas: texture_2d_array<f32>;
 #import bevy_terrain::node
 #import bevy_terrain::functions
 #import bevy_terrain::debug
+#import bevy_terrain::atmosphere
+#import bevy_terrain::tint
+#import bevy_terrain::weather
+#import bevy_terrain::viewshed
+#ifdef VIRTUAL_TEXTURE
+#import bevy_terrain::virtual_texture
+#endif
+#ifndef DOWNLEVEL
+#import bevy_terrain::decals
+#endif
 
 struct FragmentData {
     world_normal: vec3<f32>,
     debug_color: vec4<f32>,
+    mask: f32,
+}
+
+#ifdef BATHYMETRY
+// Returns the atlas coordinates of the bathymetry attachment and the size of one of its pixels.
+fn bathymetry_coords(atlas_coords: vec2<f32>) -> vec3<f32> {
+    var coords = vec3<f32>(atlas_coords, 0.0);
+
+#ifdef BATHYMETRY_ATTACHMENT_2
+    // the bathymetry occupies the slot of the mask
+    coords = vec3<f32>(atlas_coords * config.mask_scale + config.mask_offset, 1.0 / config.mask_size);
+#endif
+#ifdef BATHYMETRY_ATTACHMENT_3
+    coords = vec3<f32>(atlas_coords * config.reference_scale + config.reference_offset, 1.0 / config.reference_size);
+#endif
+
+    return coords;
+}
+
+// Returns the height of the seabed in world units.
+fn seabed_height(coords: vec2<f32>, atlas_index: i32) -> f32 {
+    let height = textureSampleLevel(bathymetry_atlas, bathymetry_sampler, coords, atlas_index, 0.0).x;
+
+    return height * config.height + view_config.elevation_offset;
+}
+
+fn seabed_normal(atlas_coords: vec2<f32>, atlas_index: i32, atlas_lod: u32) -> vec3<f32> {
+    let coords = bathymetry_coords(atlas_coords);
+    let offset = coords.z;
+
+    let left  = seabed_height(coords.xy + vec2<f32>(-offset,     0.0), atlas_index);
+    let up    = seabed_height(coords.xy + vec2<f32>(    0.0, -offset), atlas_index);
+    let right = seabed_height(coords.xy + vec2<f32>( offset,     0.0), atlas_index);
+    let down  = seabed_height(coords.xy + vec2<f32>(    0.0,  offset), atlas_index);
+
+    return normalize(vec3<f32>(right - left, f32(2u << atlas_lod), down - up));
+}
+
+// Tints the seabed from light to dark blue by its depth below the sea level.
+fn bathymetric_tint(color: vec4<f32>, height: f32) -> vec4<f32> {
+    let depth = (view_config.sea_level - height) / max(view_config.sea_level - view_config.elevation_offset, 1.0);
+    let tint = mix(vec4<f32>(0.55, 0.8, 0.9, 1.0), vec4<f32>(0.02, 0.1, 0.35, 1.0), sqrt(clamp(depth, 0.0, 1.0)));
+
+    return color * tint;
+}
+
+// Whether the seabed is rendered at the height instead of the topography.
+fn shows_seabed(height: f32) -> bool {
+    return view_config.bathymetry_mode == BATHYMETRY_TOPO_BATHY && height <= view_config.sea_level;
+}
+#endif
+
+#ifdef CEILING
+// the minimal height difference in world units, above which the ceiling is rendered
+let CEILING_THRESHOLD: f32 = 0.05;
+
+// Returns the atlas coordinates of the ceiling attachment and the size of one of its pixels.
+fn ceiling_coords(atlas_coords: vec2<f32>) -> vec3<f32> {
+    var coords = vec3<f32>(atlas_coords, 0.0);
+
+#ifdef CEILING_ATTACHMENT_2
+    // the ceiling occupies the slot of the mask
+    coords = vec3<f32>(atlas_coords * config.mask_scale + config.mask_offset, 1.0 / config.mask_size);
+#endif
+#ifdef CEILING_ATTACHMENT_3
+    coords = vec3<f32>(atlas_coords * config.reference_scale + config.reference_offset, 1.0 / config.reference_size);
+#endif
+
+    return coords;
+}
+
+// Returns the height of the ceiling in world units.
+fn ceiling_height(coords: vec2<f32>, atlas_index: i32) -> f32 {
+    let height = textureSampleLevel(ceiling_atlas, ceiling_sampler, coords, atlas_index, 0.0).x;
+
+    return height * config.height + view_config.elevation_offset;
+}
+
+fn ceiling_normal(atlas_coords: vec2<f32>, atlas_index: i32, atlas_lod: u32) -> vec3<f32> {
+    let coords = ceiling_coords(atlas_coords);
+    let offset = coords.z;
+
+    let left  = ceiling_height(coords.xy + vec2<f32>(-offset,     0.0), atlas_index);
+    let up    = ceiling_height(coords.xy + vec2<f32>(    0.0, -offset), atlas_index);
+    let right = ceiling_height(coords.xy + vec2<f32>( offset,     0.0), atlas_index);
+    let down  = ceiling_height(coords.xy + vec2<f32>(    0.0,  offset), atlas_index);
+
+    return normalize(vec3<f32>(right - left, f32(2u << atlas_lod), down - up));
+}
+
+// Whether the ceiling diverges from the height, i.e. whether there is an overhang.
+fn has_overhang(lookup: NodeLookup) -> bool {
+    let height_coords = lookup.atlas_coords * config.height_scale + config.height_offset;
+    let height = textureSampleLevel(height_atlas, height_sampler, height_coords, lookup.atlas_index, 0.0).x;
+    let ceiling = ceiling_height(ceiling_coords(lookup.atlas_coords).xy, lookup.atlas_index);
+
+    return ceiling - (height * config.height + view_config.elevation_offset) > CEILING_THRESHOLD;
 }
+#endif
 
 fn vertex_height(lookup: NodeLookup) -> f32 {
+#ifdef CEILING_LAYER
+    // the second layer is displaced by the ceiling instead
+    return ceiling_height(ceiling_coords(lookup.atlas_coords).xy, lookup.atlas_index);
+#else
     let height_coords = lookup.atlas_coords * config.height_scale + config.height_offset;
-    let height = textureSampleLevel(height_atlas, atlas_sampler, height_coords, lookup.atlas_index, 0.0).x;
+#ifdef BICUBIC_VERTICES
+    // the bicubic filters are smooth across the pixels, thus the surface is not faceted at close range
+#ifdef B_SPLINE_VERTICES
+    var height = sample_height_b_spline(height_coords, lookup.atlas_index);
+#else
+    var height = sample_height_catmull_rom(height_coords, lookup.atlas_index);
+#endif
+#else
+    var height = height_value(textureSampleLevel(height_atlas, height_sampler, height_coords, lookup.atlas_index, 0.0));
+#endif
+    height = height * config.height + view_config.elevation_offset;
+
+#ifdef BATHYMETRY
+    // below the sea level, the topography is replaced by the seabed
+    if (shows_seabed(height)) {
+        height = min(height, seabed_height(bathymetry_coords(lookup.atlas_coords).xy, lookup.atlas_index));
+    }
+#endif
 
-    return height * config.height;
+    return height;
+#endif
 }
 
+// Returns the signed difference between the height and the reference height in world units.
+fn height_difference(lookup: NodeLookup) -> f32 {
+    var difference = 0.0;
+
+#ifdef REFERENCE_ATTACHMENT_2
+    // the reference occupies the slot of the mask
+    let reference_coords = lookup.atlas_coords * config.mask_scale + config.mask_offset;
+#endif
+#ifdef REFERENCE_ATTACHMENT_3
+    let reference_coords = lookup.atlas_coords * config.reference_scale + config.reference_offset;
+#endif
+
+#ifdef REFERENCE
+    let height = textureSampleLevel(height_atlas, height_sampler, lookup.atlas_coords * config.height_scale + config.height_offset, lookup.atlas_index, 0.0).x;
+    let reference = textureSampleLevel(reference_atlas, reference_sampler, reference_coords, lookup.atlas_index, 0.0).x;
+
+    difference = (height - reference) * config.height;
+#endif
+
+    return difference;
+}
+
+#ifdef FLOW
+// Returns the normalized flow accumulation and the watershed id of the flow attachment.
+fn flow_data(lookup: NodeLookup) -> vec2<f32> {
+#ifdef FLOW_ATTACHMENT_2
+    // the flow occupies the slot of the mask
+    let flow_coords = lookup.atlas_coords * config.mask_scale + config.mask_offset;
+    let flow_size = config.mask_size;
+#endif
+#ifdef FLOW_ATTACHMENT_3
+    let flow_coords = lookup.atlas_coords * config.reference_scale + config.reference_offset;
+    let flow_size = config.reference_size;
+#endif
+
+    let accumulation = textureSampleLevel(flow_atlas, flow_sampler, flow_coords, lookup.atlas_index, 0.0).x;
+    // the watershed ids must not be interpolated
+    let watershed = textureLoad(flow_atlas, vec2<i32>(flow_coords * flow_size), lookup.atlas_index, 0).y;
+
+    return vec2<f32>(accumulation, watershed);
+}
+#endif
+
+#ifdef BIOME
+// Returns the color of the biome id, where the id zero (no biome) is transparent.
+fn biome_id_color(id: f32) -> vec4<f32> {
+    let id = u32(round(id * 65535.0));
+
+    if (id == 0u || id > 16u) {
+        return vec4<f32>(0.0);
+    }
+
+    return config.biome_colors[id - 1u];
+}
+
+// Returns the color of the biomes around the lookup, which are blended bilinearly,
+// since the ids themselves must not be interpolated.
+fn biome_color(lookup: NodeLookup) -> vec4<f32> {
+#ifdef BIOME_ATTACHMENT_2
+    // the biomes occupy the slot of the mask
+    let biome_coords = lookup.atlas_coords * config.mask_scale + config.mask_offset;
+    let biome_size = config.mask_size;
+#endif
+#ifdef BIOME_ATTACHMENT_3
+    let biome_coords = lookup.atlas_coords * config.reference_scale + config.reference_offset;
+    let biome_size = config.reference_size;
+#endif
+
+    let position = biome_coords * biome_size - 0.5;
+    let origin = vec2<i32>(floor(position));
+    let weight = fract(position);
+    let max_coords = vec2<i32>(i32(biome_size) - 1);
+
+    let id = vec4<f32>(
+        textureLoad(biome_atlas, clamp(origin, vec2<i32>(0), max_coords), lookup.atlas_index, 0).x,
+        textureLoad(biome_atlas, clamp(origin + vec2<i32>(1, 0), vec2<i32>(0), max_coords), lookup.atlas_index, 0).x,
+        textureLoad(biome_atlas, clamp(origin + vec2<i32>(0, 1), vec2<i32>(0), max_coords), lookup.atlas_index, 0).x,
+        textureLoad(biome_atlas, clamp(origin + vec2<i32>(1, 1), vec2<i32>(0), max_coords), lookup.atlas_index, 0).x,
+    );
+
+    let top = mix(biome_id_color(id.x), biome_id_color(id.y), weight.x);
+    let bottom = mix(biome_id_color(id.z), biome_id_color(id.w), weight.x);
+
+    return mix(top, bottom, weight.y);
+}
+#endif
+
 fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>, ddy: vec2<f32>) -> FragmentData {
     let atlas_lod = lookup.atlas_lod;
     let atlas_index = lookup.atlas_index;
@@ -76,10 +403,25 @@ fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>
     let height_ddx = ddx / 512.0;
     let height_ddy = ddy / 512.0;
 
-    let world_normal = calculate_normal(height_coords, atlas_index, atlas_lod, height_ddx, height_ddy);
+    var world_normal = calculate_normal(height_coords, atlas_index, atlas_lod, height_ddx, height_ddy);
+
+#ifdef BATHYMETRY
+    if (shows_seabed(input.world_position.y)) {
+        world_normal = seabed_normal(atlas_coords, atlas_index, atlas_lod);
+    }
+#endif
+
+#ifdef CEILING_LAYER
+    world_normal = ceiling_normal(atlas_coords, atlas_index, atlas_lod);
+#endif
 
     var debug_color = vec4<f32>(0.5);
 
+#ifdef BIOME
+    let biome = biome_color(lookup);
+    debug_color = mix(debug_color, vec4<f32>(biome.xyz, 1.0), biome.w);
+#endif
+
 #ifdef SHOW_LOD
     debug_color = mix(debug_color, show_lod(atlas_lod, input.world_position.xyz), 0.4);
 #endif
@@ -88,37 +430,113 @@ fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>
     debug_color = mix(debug_color, vec4<f32>(atlas_coords.x, atlas_coords.y, 0.0, 1.0), 0.5);
 #endif
 
-    return FragmentData(world_normal, debug_color);
+#ifdef SHOW_HEIGHT_DIFFERENCE
+    debug_color = show_height_difference(height_difference(lookup));
+#endif
+
+#ifdef FLOW
+    let flow = flow_data(lookup);
+#ifdef SHOW_WATERSHEDS
+    debug_color = show_watershed(flow.y);
+#endif
+#ifdef SHOW_FLOW
+    debug_color = show_flow(debug_color, flow.x);
+#endif
+#endif
+
+    var mask = 0.0;
+
+#ifdef MASK
+    let mask_coords = atlas_coords * config.mask_scale + config.mask_offset;
+    mask = textureSampleLevel(mask_atlas, mask_sampler, mask_coords, atlas_index, 0.0).x;
+#endif
+
+#ifdef CEILING_LAYER
+    // the ceiling is only rendered, where it diverges from the height
+    if (!has_overhang(lookup)) {
+        mask = 1.0;
+    }
+#endif
+
+    return FragmentData(world_normal, debug_color, mask);
 }
 
 fn blend_fragment_data(data1: FragmentData, data2: FragmentData, blend_ratio: f32) -> FragmentData {
     let world_normal = mix(data2.world_normal, data1.world_normal, blend_ratio);
     let debug_color = mix(data2.debug_color, data1.debug_color, blend_ratio);
+    let mask = mix(data2.mask, data1.mask, blend_ratio);
 
-    return FragmentData(world_normal, debug_color);
+    return FragmentData(world_normal, debug_color, mask);
 }
 
 fn process_fragment(input: FragmentInput, data: FragmentData) -> Fragment {
-    let do_discard = input.local_position.x < 2.0 || input.local_position.x > f32(config.terrain_size) - 2.0 ||
-                     input.local_position.y < 2.0 || input.local_position.y > f32(config.terrain_size) - 2.0;
+    let outside = input.local_position.x < 2.0 || input.local_position.x > f32(config.terrain_size) - 2.0 ||
+                  input.local_position.y < 2.0 || input.local_position.y > f32(config.terrain_size) - 2.0;
+
+#ifdef ALPHA_TO_COVERAGE
+    // the edge of the mask is antialiased by the share of the covered samples
+    let coverage = clamp((0.5 - data.mask) / max(fwidth(data.mask), 0.0001) + 0.5, 0.0, 1.0);
+    let do_discard = outside || coverage == 0.0;
+#else
+    let do_discard = outside || data.mask > 0.5;
+#endif
+
+    var world_normal = data.world_normal;
+
+#ifdef CEILING_LAYER
+    // the ceiling is double-sided, so its normal has to face the viewer
+    if (dot(world_normal, view.world_position - input.world_position.xyz) < 0.0) {
+        world_normal = -world_normal;
+    }
+#endif
 
     var color = mix(data.debug_color, vec4<f32>(input.debug_color.xyz, 1.0), input.debug_color.w);
+#ifdef VIRTUAL_TEXTURE
+    let imagery = virtual_texture_color(input.local_position, input.frag_coord);
+    color = vec4<f32>(mix(color.rgb, imagery.rgb, imagery.a), color.a);
+#endif
+#ifndef DOWNLEVEL
+    color = apply_decals(color, input.local_position);
+#endif
+
+    color = apply_tint(color, input.world_position);
+    color = apply_viewshed(color, input.world_position);
+
+    let weather = apply_weather(color, 1.0, input.world_position);
+    color = weather.color;
+
+#ifdef BATHYMETRY
+    if (shows_seabed(input.world_position.y)) {
+        color = bathymetric_tint(color, input.world_position.y);
+    }
+#endif
 
 #ifdef LIGHTING
     var pbr_input: PbrInput = pbr_input_new();
     pbr_input.material.base_color = color;
-    pbr_input.material.perceptual_roughness = 1.0;
+    pbr_input.material.perceptual_roughness = weather.perceptual_roughness;
     pbr_input.material.reflectance = 0.0;
     pbr_input.frag_coord = input.frag_coord;
     pbr_input.world_position = input.world_position;
-    pbr_input.world_normal = data.world_normal;
+    pbr_input.world_normal = world_normal;
     pbr_input.is_orthographic = view.projection[3].w == 1.0;
-    pbr_input.N = data.world_normal;
+    pbr_input.N = world_normal;
     pbr_input.V = calculate_view(input.world_position, pbr_input.is_orthographic);
 
     color = tone_mapping(pbr(pbr_input));
 #endif
 
+    color = apply_atmosphere(color, input.world_position);
+    color = show_analysis(color, world_normal, (input.world_position.y - view_config.elevation_offset) / config.height);
+
+#ifdef DEBAND_DITHER
+    color = deband_dither(color, input.frag_coord.xy);
+#endif
+
+#ifdef ALPHA_TO_COVERAGE
+    color.a = coverage;
+#endif
+
     return Fragment(color, do_discard);
 }
 
@@ -0,0 +1,55 @@
+// Renders the deformed data of a node into one mip level of an atlas attachment.
+
+struct Deformation {
+    center: vec2<f32>,
+    origin: vec2<f32>,
+    radius: f32,
+    texel_size: f32,
+    profile: u32,
+    amplitude: f32,
+    rim_height: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> deformation: Deformation;
+@group(0) @binding(1)
+var original: texture_2d<f32>;
+
+// Returns the normalized height difference at the distance from the center.
+fn height_difference(center_distance: f32) -> f32 {
+    let r = center_distance / deformation.radius;
+
+    // crater
+    if (deformation.profile == 0u) {
+        let bowl = -deformation.amplitude * max(1.0 - r * r, 0.0);
+        let rim = deformation.rim_height * pow(max(1.0 - 4.0 * (r - 1.0) * (r - 1.0), 0.0), 2.0);
+
+        return bowl + rim;
+    }
+
+    // mound
+    return deformation.amplitude * (1.0 - smoothstep(0.0, 1.0, r));
+}
+
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // a single triangle covering the whole target
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fragment(@builtin(position) frag_coord: vec4<f32>) -> @location(0) vec4<f32> {
+    let value = textureLoad(original, vec2<i32>(frag_coord.xy), 0);
+
+    let position = deformation.origin + frag_coord.xy * deformation.texel_size;
+    let delta = height_difference(distance(position, deformation.center));
+
+#ifdef MINMAX
+    // the bounds are only ever extended, so that they stay conservative
+    return vec4<f32>(value.x + min(delta, 0.0), value.y + max(delta, 0.0), value.zw);
+#else
+    return vec4<f32>(value.x + delta, value.yzw);
+#endif
+}
@@ -0,0 +1,63 @@
+// The fallback shader, which renders the terrain magenta, whenever the shader of the terrain material fails to compile.
+
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    height_size: f32,
+    minmax_size: f32,
+    _empty: f32,
+    _empty: f32,
+    height_scale: f32,
+    minmax_scale: f32,
+    _empty: f32,
+    _empty: f32,
+    height_offset: f32,
+    minmax_offset: f32,
+    _empty: f32,
+    _empty: f32,
+}
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+// terrain view bindings
+@group(1) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(1) @binding(1)
+var quadtree: texture_2d_array<u32>;
+#ifndef DOWNLEVEL
+@group(1) @binding(2)
+var<storage> tiles: TileList;
+#endif
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+@group(2) @binding(3)
+var minmax_atlas: texture_2d_array<f32>;
+
+#import bevy_terrain::node
+#import bevy_terrain::functions
+
+fn vertex_height(lookup: NodeLookup) -> f32 {
+    let height_coords = lookup.atlas_coords * config.height_scale + config.height_offset;
+    let height = textureSampleLevel(height_atlas, atlas_sampler, height_coords, lookup.atlas_index, 0.0).x;
+
+    return height * config.height + view_config.elevation_offset;
+}
+
+#import bevy_terrain::vertex
+
+@fragment
+fn fragment(input: FragmentInput) -> FragmentOutput {
+    return FragmentOutput(vec4<f32>(1.0, 0.0, 1.0, 1.0));
+}
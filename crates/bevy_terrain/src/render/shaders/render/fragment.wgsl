@@ -17,6 +17,22 @@
 // blended fragment data.
 // fn process_fragment(input: FragmentInput, data: FragmentData) -> Fragment;
 
+// Lookup the terrain data, while newly loaded nodes are cross-faded with their coarser data.
+fn lookup_faded_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>, ddy: vec2<f32>) -> FragmentData {
+    var data = lookup_fragment_data(input, lookup, ddx, ddy);
+
+#ifdef NODE_FADE
+    let fade = node_fade(lookup);
+
+    if (is_fading(lookup, fade)) {
+        let coarse_data = lookup_fragment_data(input, fade_lookup(lookup, input.local_position), ddx, ddy);
+        data = blend_fragment_data(data, coarse_data, fade);
+    }
+#endif
+
+    return data;
+}
+
 // The default fragment entry point, which blends the terrain data at the fringe between two lods.
 @fragment
 fn fragment(input: FragmentInput) -> FragmentOutput {
@@ -25,19 +41,26 @@ fn fragment(input: FragmentInput) -> FragmentOutput {
     let blend = calculate_blend(input.world_position);
 
     let lookup = lookup_node(blend.lod, input.local_position);
-    var data   = lookup_fragment_data(input, lookup, ddx, ddy);
+    var data   = lookup_faded_fragment_data(input, lookup, ddx, ddy);
 
     if (blend.ratio < 1.0) {
         let lookup2 = lookup_node(blend.lod + 1u, input.local_position);
-        let data2   = lookup_fragment_data(input, lookup2, ddx, ddy);
+        let data2   = lookup_faded_fragment_data(input, lookup2, ddx, ddy);
         data        = blend_fragment_data(data, data2, blend.ratio);
     }
 
     let fragment = process_fragment(input, data);
 
-    if (fragment.do_discard) {
+    let uncovered = !lookup.covered && view_config.coverage_fill == COVERAGE_SKIP;
+
+    if (fragment.do_discard || uncovered || inside_far_field(input.local_position)) {
         discard;
     }
 
+    // the border walls are filled with the skirt color
+    if (input.skirt > 0.0) {
+        return FragmentOutput(view_config.skirt_color);
+    }
+
     return FragmentOutput(fragment.color);
 }
\ No newline at end of file
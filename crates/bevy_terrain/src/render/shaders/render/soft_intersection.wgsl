@@ -0,0 +1,104 @@
+#import bevy_terrain::types
+
+struct SoftIntersection {
+    model: mat4x4<f32>,
+    color: vec4<f32>,
+    blend_height: f32,
+}
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+#import bevy_terrain::atlas_bindings
+
+#ifdef ALBEDO_ATTACHMENT_2
+@group(2) @binding(4)
+var albedo_atlas: texture_2d_array<f32>;
+#endif
+#ifdef ALBEDO_ATTACHMENT_3
+@group(2) @binding(5)
+var albedo_atlas: texture_2d_array<f32>;
+#endif
+
+// soft intersection bindings
+@group(3) @binding(0)
+var<uniform> soft_intersection: SoftIntersection;
+
+#import bevy_pbr::mesh_types
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import bevy_terrain::node
+#import bevy_terrain::atlas
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+}
+
+fn terrain_color(lookup: NodeLookup) -> vec4<f32> {
+#ifdef ALBEDO_ATTACHMENT_2
+    let coords = attachment_coords(lookup, 2u);
+    return vec4<f32>(textureSampleLevel(albedo_atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).rgb, 1.0);
+#else
+#ifdef ALBEDO_ATTACHMENT_3
+    let coords = attachment_coords(lookup, 3u);
+    return vec4<f32>(textureSampleLevel(albedo_atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).rgb, 1.0);
+#else
+    return soft_intersection.color;
+#endif
+#endif
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let world_position = soft_intersection.model * vec4<f32>(vertex.position, 1.0);
+
+    var output: VertexOutput;
+    output.clip_position = view.view_proj * world_position;
+    output.world_position = world_position;
+
+    return output;
+}
+
+@fragment
+fn fragment(input: VertexOutput) -> @location(0) vec4<f32> {
+    let local_position = input.world_position.xz;
+    let lookup = lookup_terrain(local_position);
+
+    if (!lookup.covered) {
+        discard;
+    }
+
+    // the mesh fades into the terrain with the height above it
+    let height_above_terrain = input.world_position.y - terrain_height(lookup, vec2<f32>(0.0));
+    let blend = 1.0 - smoothstep(0.0, soft_intersection.blend_height, height_above_terrain);
+
+    if (blend <= 0.0) {
+        discard;
+    }
+
+    let normal = terrain_normal(lookup);
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = terrain_color(lookup);
+    pbr_input.material.reflectance = 0.0;
+    pbr_input.frag_coord = input.clip_position;
+    pbr_input.world_position = input.world_position;
+    pbr_input.world_normal = normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normal;
+    pbr_input.V = calculate_view(input.world_position, pbr_input.is_orthographic);
+
+    let color = tone_mapping(pbr(pbr_input));
+
+    return vec4<f32>(color.rgb, blend);
+}
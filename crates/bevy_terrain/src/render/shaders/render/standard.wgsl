@@ -0,0 +1,429 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    height_size: f32,
+    minmax_size: f32,
+    normal_size: f32,
+    material_size: f32,
+    height_scale: f32,
+    minmax_scale: f32,
+    normal_scale: f32,
+    material_scale: f32,
+    height_offset: f32,
+    minmax_offset: f32,
+    normal_offset: f32,
+    material_offset: f32,
+
+    albedo_size: f32,
+    splat_size: f32,
+    winter_albedo_size: f32,
+    occlusion_size: f32,
+    albedo_scale: f32,
+    splat_scale: f32,
+    winter_albedo_scale: f32,
+    occlusion_scale: f32,
+    albedo_offset: f32,
+    splat_offset: f32,
+    winter_albedo_offset: f32,
+    occlusion_offset: f32,
+
+    attachment_count: u32,
+    biome_colors: array<vec4<f32>, 16>,
+
+    height_mip_bias: f32,
+    minmax_mip_bias: f32,
+    normal_mip_bias: f32,
+    material_mip_bias: f32,
+    albedo_mip_bias: f32,
+    splat_mip_bias: f32,
+    winter_albedo_mip_bias: f32,
+    occlusion_mip_bias: f32,
+}
+
+struct DetailLayer {
+    scale: f32,
+    fade_start: f32,
+    fade_end: f32,
+    strength: f32,
+}
+
+struct SnowLine {
+    summer_altitude: f32,
+    winter_altitude: f32,
+    transition: f32,
+    max_slope: f32,
+}
+
+struct HorizonShadows {
+    strength: f32,
+    softness: f32,
+}
+
+struct StandardTerrainMaterial {
+    base_color: vec4<f32>,
+    perceptual_roughness: f32,
+    reflectance: f32,
+    occlusion_strength: f32,
+    flags: u32,
+    detail_layers: array<DetailLayer, 4>,
+    snow_color: vec4<f32>,
+    snow_line: SnowLine,
+    season: f32,
+    horizon_shadows: HorizonShadows,
+}
+
+let DETAIL_ALBEDO_FLAG: u32 = 1u;
+let DETAIL_NORMAL_FLAG: u32 = 2u;
+let SNOW_LINE_FLAG: u32 = 4u;
+let HORIZON_SHADOWS_FLAG: u32 = 8u;
+let DETAIL_LAYER_COUNT: u32 = 4u;
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+// terrain view bindings
+@group(1) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(1) @binding(1)
+var quadtree: texture_2d_array<u32>;
+#ifndef DOWNLEVEL
+@group(1) @binding(2)
+var<storage> tiles: TileList;
+#endif
+@group(1) @binding(8)
+var color_ramp_texture: texture_2d<f32>;
+@group(1) @binding(9)
+var color_ramp_sampler: sampler;
+@group(1) @binding(10)
+var<uniform> atmosphere: TerrainAtmosphere;
+@group(1) @binding(11)
+var tint_texture: texture_2d<f32>;
+@group(1) @binding(12)
+var weather_texture: texture_2d<f32>;
+@group(1) @binding(13)
+var viewshed_texture: texture_2d<f32>;
+#ifdef VIRTUAL_TEXTURE
+@group(1) @binding(14)
+var<uniform> virtual_texture: VirtualTexture;
+@group(1) @binding(15)
+var page_table: texture_2d_array<u32>;
+@group(1) @binding(16)
+var page_cache: texture_2d<f32>;
+@group(1) @binding(17)
+var<storage, read_write> page_feedback: PageFeedback;
+#endif
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+@group(2) @binding(3)
+var minmax_atlas: texture_2d_array<f32>;
+// attachment slots without an attachment are bound to an empty placeholder
+@group(2) @binding(4)
+var normal_atlas: texture_2d_array<f32>;
+@group(2) @binding(13)
+var normal_sampler: sampler;
+@group(2) @binding(5)
+var material_atlas: texture_2d_array<f32>;
+@group(2) @binding(14)
+var material_sampler: sampler;
+@group(2) @binding(6)
+var albedo_atlas: texture_2d_array<f32>;
+@group(2) @binding(15)
+var albedo_sampler: sampler;
+@group(2) @binding(7)
+var splat_atlas: texture_2d_array<f32>;
+@group(2) @binding(16)
+var splat_sampler: sampler;
+@group(2) @binding(8)
+var winter_albedo_atlas: texture_2d_array<f32>;
+@group(2) @binding(17)
+var winter_albedo_sampler: sampler;
+@group(2) @binding(9)
+var occlusion_atlas: texture_2d_array<f32>;
+@group(2) @binding(18)
+var occlusion_sampler: sampler;
+#ifdef NODE_FADE
+@group(2) @binding(10)
+var node_fade_texture: texture_2d<f32>;
+#endif
+
+// material bindings
+@group(3) @binding(0)
+var<uniform> material: StandardTerrainMaterial;
+@group(3) @binding(1)
+var detail_albedo_texture: texture_2d<f32>;
+@group(3) @binding(2)
+var detail_albedo_sampler: sampler;
+@group(3) @binding(3)
+var detail_normal_texture: texture_2d<f32>;
+@group(3) @binding(4)
+var detail_normal_sampler: sampler;
+
+#import bevy_pbr::mesh_types
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import bevy_terrain::node
+#import bevy_terrain::functions
+#import bevy_terrain::debug
+#import bevy_terrain::atmosphere
+#import bevy_terrain::tint
+#import bevy_terrain::weather
+#import bevy_terrain::viewshed
+#ifdef VIRTUAL_TEXTURE
+#import bevy_terrain::virtual_texture
+#endif
+
+struct FragmentData {
+    world_normal: vec3<f32>,
+    roughness: f32,
+    occlusion: f32,
+    albedo: vec4<f32>,
+    splat: vec4<f32>,
+    horizon: vec4<f32>,
+}
+
+// Samples the attachment with its own sampler, whose mip bias scales the gradients.
+fn sample_attachment(atlas: texture_2d_array<f32>, attachment_sampler: sampler, coords: vec2<f32>, atlas_index: i32, ddx: vec2<f32>, ddy: vec2<f32>, mip_bias: f32) -> vec4<f32> {
+#ifdef SAMPLE_GRAD
+    let scale = exp2(mip_bias);
+    return textureSampleGrad(atlas, attachment_sampler, coords, atlas_index, ddx * scale, ddy * scale);
+#else
+    return textureSampleLevel(atlas, attachment_sampler, coords, atlas_index, 0.0);
+#endif
+}
+
+fn lookup_fragment_data(input: FragmentInput, lookup: NodeLookup, ddx: vec2<f32>, ddy: vec2<f32>) -> FragmentData {
+    let atlas_lod = lookup.atlas_lod;
+    let atlas_index = lookup.atlas_index;
+    let atlas_coords = lookup.atlas_coords;
+    let ddx = ddx / f32(1u << atlas_lod);
+    let ddy = ddy / f32(1u << atlas_lod);
+
+    // The normal attachment stores the x and z component of the world normal, remapped to [0, 1].
+    var world_normal: vec3<f32>;
+    if (config.attachment_count > 2u) {
+        let normal_coords = atlas_coords * config.normal_scale + config.normal_offset;
+        let encoded = sample_attachment(normal_atlas, normal_sampler, normal_coords, atlas_index, ddx / config.normal_size, ddy / config.normal_size, config.normal_mip_bias).xy * 2.0 - 1.0;
+        world_normal = vec3<f32>(encoded.x, sqrt(max(1.0 - dot(encoded, encoded), 0.0)), encoded.y);
+    }
+    else {
+        let height_coords = atlas_coords * config.height_scale + config.height_offset;
+        world_normal = calculate_normal(height_coords, atlas_index, atlas_lod, ddx / config.height_size, ddy / config.height_size);
+    }
+
+    // The material attachment stores the roughness in the red and the ambient occlusion in the green channel.
+    var roughness = 1.0;
+    var occlusion = 1.0;
+    if (config.attachment_count > 3u) {
+        let material_coords = atlas_coords * config.material_scale + config.material_offset;
+        let material_data = sample_attachment(material_atlas, material_sampler, material_coords, atlas_index, ddx / config.material_size, ddy / config.material_size, config.material_mip_bias);
+        roughness = material_data.x;
+        occlusion = material_data.y;
+    }
+
+    // The baked ambient occlusion attachment stores the visible fraction of the sky.
+    // The horizon attachment instead stores the sine of the horizon angle towards east, south, west and north.
+    // It is sampled as sRGB, thus the sines are approximately converted back.
+    var horizon = vec4<f32>(0.0);
+    if (config.attachment_count > 7u) {
+        let occlusion_coords = atlas_coords * config.occlusion_scale + config.occlusion_offset;
+        let occlusion_data = sample_attachment(occlusion_atlas, occlusion_sampler, occlusion_coords, atlas_index, ddx / config.occlusion_size, ddy / config.occlusion_size, config.occlusion_mip_bias);
+
+        if ((material.flags & HORIZON_SHADOWS_FLAG) != 0u) {
+            horizon = pow(occlusion_data, vec4<f32>(1.0 / 2.2));
+            occlusion = occlusion * (1.0 - dot(horizon, vec4<f32>(0.25)));
+        }
+        else {
+            occlusion = occlusion * occlusion_data.x;
+        }
+    }
+
+    var albedo = vec4<f32>(1.0);
+    if (config.attachment_count > 4u) {
+        let albedo_coords = atlas_coords * config.albedo_scale + config.albedo_offset;
+        albedo = sample_attachment(albedo_atlas, albedo_sampler, albedo_coords, atlas_index, ddx / config.albedo_size, ddy / config.albedo_size, config.albedo_mip_bias);
+    }
+
+    // The winter albedo attachment is blended in according to the season.
+    if (config.attachment_count > 6u && material.season > 0.0) {
+        let winter_albedo_coords = atlas_coords * config.winter_albedo_scale + config.winter_albedo_offset;
+        let winter_albedo = sample_attachment(winter_albedo_atlas, winter_albedo_sampler, winter_albedo_coords, atlas_index, ddx / config.winter_albedo_size, ddy / config.winter_albedo_size, config.winter_albedo_mip_bias);
+        albedo = mix(albedo, winter_albedo, material.season);
+    }
+
+    // The splat attachment stores the weight of each detail layer in one channel.
+    // The attachment is sampled as sRGB, thus the weights are approximately converted back.
+    var splat = vec4<f32>(1.0, 0.0, 0.0, 0.0);
+    if (config.attachment_count > 5u) {
+        let splat_coords = atlas_coords * config.splat_scale + config.splat_offset;
+        splat = pow(sample_attachment(splat_atlas, splat_sampler, splat_coords, atlas_index, ddx / config.splat_size, ddy / config.splat_size, config.splat_mip_bias), vec4<f32>(1.0 / 2.2));
+    }
+
+    return FragmentData(world_normal, roughness, occlusion, albedo, splat, horizon);
+}
+
+fn blend_fragment_data(data1: FragmentData, data2: FragmentData, blend_ratio: f32) -> FragmentData {
+    let world_normal = mix(data2.world_normal, data1.world_normal, blend_ratio);
+    let roughness = mix(data2.roughness, data1.roughness, blend_ratio);
+    let occlusion = mix(data2.occlusion, data1.occlusion, blend_ratio);
+    let albedo = mix(data2.albedo, data1.albedo, blend_ratio);
+    let splat = mix(data2.splat, data1.splat, blend_ratio);
+    let horizon = mix(data2.horizon, data1.horizon, blend_ratio);
+
+    return FragmentData(world_normal, roughness, occlusion, albedo, splat, horizon);
+}
+
+// Applies the detail layers, which fade out with the distance to the camera.
+fn apply_detail(world_position: vec4<f32>, data: FragmentData) -> FragmentData {
+    var result = data;
+
+    let view_distance = distance(view.world_position.xyz, world_position.xyz);
+    var normal_offset = vec2<f32>(0.0);
+
+    // The derivatives have to be computed in uniform control flow, before the loop.
+    let position_ddx = dpdx(world_position.xz);
+    let position_ddy = dpdy(world_position.xz);
+
+    for (var layer_index = 0u; layer_index < DETAIL_LAYER_COUNT; layer_index = layer_index + 1u) {
+        let layer = material.detail_layers[layer_index];
+        let fade = 1.0 - smoothstep(layer.fade_start, layer.fade_end, view_distance);
+        let weight = data.splat[layer_index] * layer.strength * fade;
+
+        if (weight <= 0.0) {
+            continue;
+        }
+
+        // The tiles of the layers are placed next to each other horizontally.
+        // The gradients of the unwrapped coordinates avoid seams at the tile borders.
+        let tile_coords = world_position.xz / layer.scale;
+        let coords = vec2<f32>((f32(layer_index) + fract(tile_coords.x)) / f32(DETAIL_LAYER_COUNT), fract(tile_coords.y));
+        let tile_scale = vec2<f32>(1.0 / f32(DETAIL_LAYER_COUNT), 1.0);
+        let ddx = position_ddx / layer.scale * tile_scale;
+        let ddy = position_ddy / layer.scale * tile_scale;
+
+        if ((material.flags & DETAIL_ALBEDO_FLAG) != 0u) {
+            let detail = textureSampleGrad(detail_albedo_texture, detail_albedo_sampler, coords, ddx, ddy).rgb;
+            result.albedo = vec4<f32>(mix(result.albedo.rgb, result.albedo.rgb * detail * 2.0, weight), result.albedo.a);
+        }
+
+        if ((material.flags & DETAIL_NORMAL_FLAG) != 0u) {
+            let detail = textureSampleGrad(detail_normal_texture, detail_normal_sampler, coords, ddx, ddy).xy * 2.0 - 1.0;
+            normal_offset = normal_offset + detail * weight;
+        }
+    }
+
+    // The tangent space of the detail normals is aligned with the x and z axis.
+    let normal = normalize(data.world_normal);
+    let tangent = normalize(vec3<f32>(1.0, 0.0, 0.0) - normal * normal.x);
+    let bitangent = cross(tangent, normal);
+    result.world_normal = normal + tangent * normal_offset.x + bitangent * normal_offset.y;
+
+    return result;
+}
+
+// Covers the flat terrain above the snow line, which descends towards winter, in snow.
+fn apply_snow_line(color: vec4<f32>, world_position: vec4<f32>, world_normal: vec3<f32>) -> vec4<f32> {
+    if ((material.flags & SNOW_LINE_FLAG) == 0u) {
+        return color;
+    }
+
+    let snow_line = material.snow_line;
+    let altitude = mix(snow_line.summer_altitude, snow_line.winter_altitude, material.season);
+    let slope = degrees(acos(clamp(world_normal.y, -1.0, 1.0)));
+
+    let altitude_coverage = smoothstep(altitude - 0.5 * snow_line.transition, altitude + 0.5 * snow_line.transition, world_position.y);
+    let slope_coverage = 1.0 - smoothstep(snow_line.max_slope - 5.0, snow_line.max_slope + 5.0, slope);
+
+    return vec4<f32>(mix(color.rgb, material.snow_color.rgb, altitude_coverage * slope_coverage), color.a);
+}
+
+// Returns the fraction of the sun of the first directional light, which is visible above the horizon.
+fn horizon_visibility(horizon: vec4<f32>) -> f32 {
+    if ((material.flags & HORIZON_SHADOWS_FLAG) == 0u || lights.n_directional_lights == 0u) {
+        return 1.0;
+    }
+
+    let sun = lights.directional_lights[0].direction_to_light;
+
+    // Interpolates the horizon linearly between the two directions enclosing the sun azimuth.
+    let quarter = 0.5 * PI;
+    let azimuth = atan2(sun.z, sun.x);
+    var difference = azimuth - vec4<f32>(0.0, quarter, 2.0 * quarter, 3.0 * quarter);
+    difference = difference - 4.0 * quarter * round(difference / (4.0 * quarter));
+    let weights = max(1.0 - abs(difference) / quarter, vec4<f32>(0.0));
+    let horizon_sine = dot(horizon, weights);
+
+    let softness = material.horizon_shadows.softness;
+    let visibility = smoothstep(horizon_sine - softness, horizon_sine + softness, sun.y);
+
+    return mix(1.0, visibility, material.horizon_shadows.strength);
+}
+
+fn process_fragment(input: FragmentInput, fragment_data: FragmentData) -> Fragment {
+    let data = apply_detail(input.world_position, fragment_data);
+    let world_normal = normalize(data.world_normal);
+    var color = material.base_color * data.albedo;
+
+#ifdef VIRTUAL_TEXTURE
+    // the imagery replaces the albedo of the attachments
+    let imagery = virtual_texture_color(input.local_position, input.frag_coord);
+    color = mix(color, material.base_color * vec4<f32>(imagery.rgb, 1.0), imagery.a);
+#endif
+
+    color = apply_snow_line(color, input.world_position, world_normal);
+
+#ifdef SHOW_LOD
+    color = mix(color, show_lod(calculate_blend(input.world_position).lod, input.world_position.xyz), 0.4);
+#endif
+
+    color = apply_tint(color, input.world_position);
+    color = apply_viewshed(color, input.world_position);
+
+    let weather = apply_weather(color, material.perceptual_roughness * data.roughness, input.world_position);
+    color = weather.color;
+
+#ifdef LIGHTING
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.perceptual_roughness = weather.perceptual_roughness;
+    pbr_input.material.reflectance = material.reflectance;
+    pbr_input.occlusion = mix(1.0, data.occlusion, material.occlusion_strength);
+    pbr_input.frag_coord = input.frag_coord;
+    pbr_input.world_position = input.world_position;
+    pbr_input.world_normal = world_normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = world_normal;
+    pbr_input.V = calculate_view(input.world_position, pbr_input.is_orthographic);
+
+    // Terrain in the shadow of the horizon only receives the ambient light.
+    let lit_color = pbr(pbr_input);
+    let ambient_color = vec4<f32>(color.rgb * lights.ambient_color.rgb * pbr_input.occlusion, lit_color.a);
+    color = tone_mapping(mix(ambient_color, lit_color, horizon_visibility(data.horizon)));
+#endif
+
+    color = apply_atmosphere(color, input.world_position);
+    color = show_analysis(color, world_normal, (input.world_position.y - view_config.elevation_offset) / config.height);
+
+#ifdef DEBAND_DITHER
+    color = deband_dither(color, input.frag_coord.xy);
+#endif
+
+    return Fragment(color, false);
+}
+
+#import bevy_terrain::fragment
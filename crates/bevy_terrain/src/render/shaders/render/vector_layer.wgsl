@@ -0,0 +1,95 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    height_size: f32,
+    minmax_size: f32,
+    mask_size: f32,
+    _empty: u32,
+    height_scale: f32,
+    minmax_scale: f32,
+    mask_scale: f32,
+    _empty: u32,
+    height_offset: f32,
+    minmax_offset: f32,
+    mask_offset: f32,
+    _empty: u32,
+}
+
+struct VectorLayer {
+    color: vec4<f32>,
+    depth_offset: f32,
+}
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+// terrain view bindings
+@group(1) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(1) @binding(1)
+var quadtree: texture_2d_array<u32>;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+@group(2) @binding(3)
+var minmax_atlas: texture_2d_array<f32>;
+
+// vector layer bindings
+@group(3) @binding(0)
+var<uniform> layer: VectorLayer;
+
+#import bevy_terrain::node
+#import bevy_terrain::functions
+
+fn sample_height(lod: u32, local_position: vec2<f32>) -> f32 {
+    let lookup = lookup_node(lod, local_position);
+    let height_coords = lookup.atlas_coords * config.height_scale + config.height_offset;
+
+    return textureSampleLevel(height_atlas, atlas_sampler, height_coords, lookup.atlas_index, 0.0).x * config.height + view_config.elevation_offset;
+}
+
+struct LineVertexInput {
+    @location(0) local_position: vec2<f32>,
+}
+
+struct LineVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// Drapes the vertex onto the terrain surface, using the same lod blending as the terrain geometry.
+@vertex
+fn vertex(input: LineVertexInput) -> LineVertexOutput {
+    let local_position = clamp(input.local_position, vec2<f32>(0.0), vec2<f32>(f32(config.terrain_size)));
+    let blend = calculate_blend(approximate_world_position(local_position));
+
+    var height = sample_height(blend.lod, local_position);
+
+    if (blend.ratio < 1.0) {
+        height = mix(sample_height(blend.lod + 1u, local_position), height, blend.ratio);
+    }
+
+    var world_position = vec3<f32>(local_position.x, height, local_position.y);
+
+    // move the vertex towards the viewer to avoid z-fighting with the terrain
+    world_position = world_position + normalize(view.world_position.xyz - world_position) * layer.depth_offset;
+
+    var output: LineVertexOutput;
+    output.clip_position = view.view_proj * vec4<f32>(world_position, 1.0);
+
+    return output;
+}
+
+@fragment
+fn fragment() -> @location(0) vec4<f32> {
+    return layer.color;
+}
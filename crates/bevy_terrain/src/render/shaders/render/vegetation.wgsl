@@ -0,0 +1,63 @@
+#import bevy_terrain::types
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+// vegetation bindings
+@group(1) @binding(0)
+var<uniform> vegetation: VegetationConfig;
+@group(1) @binding(1)
+var<storage> instances: VegetationInstanceList;
+
+struct VertexInput {
+    @builtin(instance_index) instance_index: u32,
+    @builtin(vertex_index)   vertex_index: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0)       uv: vec2<f32>,
+}
+
+// Expands each instance into a billboard, which rotates around its vertical axis to face the viewer.
+@vertex
+fn vertex(input: VertexInput) -> VertexOutput {
+    let instance = instances.data[input.instance_index];
+    let uv = vec2<f32>(f32(input.vertex_index & 1u), f32(input.vertex_index >> 1u));
+
+    let to_viewer = view.world_position.xz - instance.position.xz;
+    let right = normalize(vec2<f32>(to_viewer.y, -to_viewer.x) + vec2<f32>(0.0001, 0.0));
+    let offset = vec2<f32>(uv.x - 0.5, uv.y) * vegetation.size * instance.scale;
+
+    let world_position = instance.position + vec3<f32>(right.x * offset.x, offset.y, right.y * offset.x);
+
+    var output: VertexOutput;
+    output.clip_position = view.view_proj * vec4<f32>(world_position, 1.0);
+    output.uv = uv;
+
+    return output;
+}
+
+@fragment
+fn fragment(input: VertexOutput) -> @location(0) vec4<f32> {
+    // cut out a tapered blade shape
+    let edge = 1.0 - input.uv.y - abs(input.uv.x - 0.5) * 2.0;
+
+#ifdef ALPHA_TO_COVERAGE
+    // the edges of the blade are antialiased by the share of the covered samples
+    let alpha = clamp(edge / max(fwidth(edge), 0.0001) + 0.5, 0.0, 1.0);
+
+    if (alpha == 0.0) {
+        discard;
+    }
+#else
+    let alpha = vegetation.color.a;
+
+    if (edge < 0.0) {
+        discard;
+    }
+#endif
+
+    // darken the base of the billboard to fake ambient occlusion
+    return vec4<f32>(vegetation.color.rgb * mix(0.4, 1.0, input.uv.y), alpha);
+}
@@ -4,30 +4,71 @@
 // This will happen once or twice (lod fringe).
 // fn vertex_height(lookup: AtlasLookup) -> f32;
 
+// Evaluates the height of the vertex, while newly loaded nodes are cross-faded with their coarser data.
+fn faded_vertex_height(lookup: NodeLookup, local_position: vec2<f32>) -> f32 {
+    var height = vertex_height(lookup);
+
+#ifdef NODE_FADE
+    let fade = node_fade(lookup);
+
+    if (is_fading(lookup, fade)) {
+        height = mix(vertex_height(fade_lookup(lookup, local_position)), height, fade);
+    }
+#endif
+
+    return height;
+}
+
 // The default vertex entry point, which blends the height at the fringe between two lods.
 @vertex
 fn vertex(in: VertexInput) -> VertexOutput {
-    let tile_index = in.vertex_index / view_config.vertices_per_tile;
-    let grid_index = in.vertex_index % view_config.vertices_per_tile;
+    let tile_vertex = calculate_tile_vertex(in);
+    let tile_index = tile_vertex.x;
+    let grid_position = tile_vertex.yz;
 
+#ifdef DOWNLEVEL
+    let tile = Tile(in.tile.xy, in.tile.z);
+#else
     let tile = tiles.data[tile_index];
-    let grid_position = calculate_grid_position(grid_index);
+#endif
+
+    var local_position = calculate_local_position(tile, grid_position);
+    var skirt = 0.0;
+
+    if (view_config.border_skirt == 1u) {
+        let skirt_vertex = border_skirt(tile, grid_position, local_position);
+        local_position = skirt_vertex.xy;
+        skirt = skirt_vertex.z;
+    }
 
-    let local_position = calculate_local_position(tile, grid_position);
     let world_position = approximate_world_position(local_position);
 
     let blend = calculate_blend(world_position);
 
-    let lookup = lookup_node(blend.lod, local_position);
-    var height = vertex_height(lookup);
+    // vertices beyond the quadtree continue the height of its border, the far field is rendered there instead
+    let sample_position = clamp_to_quadtree(local_position);
+
+    let lookup = lookup_node(blend.lod, sample_position);
+    var height = faded_vertex_height(lookup, sample_position);
 
     if (blend.ratio < 1.0) {
-        let lookup2 = lookup_node(blend.lod + 1u, local_position);
-        let height2 = vertex_height(lookup2);
+        let lookup2 = lookup_node(blend.lod + 1u, sample_position);
+        let height2 = faded_vertex_height(lookup2, sample_position);
         height      = mix(height2, height, blend.ratio);
     }
 
+    // the area outside of the coverage is either filled or discarded in the fragment shader
+    if (!lookup.covered) {
+        height = view_config.fill_height;
+    }
+
+    // the bottom of the border walls
+    if (skirt == 1.0) {
+        height = view_config.skirt_height;
+    }
+
     var output = vertex_output(local_position, height);
+    output.skirt = skirt;
 
 #ifdef SHOW_TILES
     output.debug_color = show_tiles(tile, output.world_position);
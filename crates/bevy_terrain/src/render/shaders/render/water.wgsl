@@ -0,0 +1,140 @@
+#import bevy_terrain::types
+
+struct TerrainConfig {
+    lod_count: u32,
+    height: f32,
+    leaf_node_size: u32,
+    terrain_size: u32,
+
+    attachment_sizes: vec4<f32>,
+    attachment_scales: vec4<f32>,
+    attachment_offsets: vec4<f32>,
+}
+
+struct Water {
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    level: f32,
+    depth_scale: f32,
+    foam_depth: f32,
+    wave_length: f32,
+    wave_speed: f32,
+    wave_strength: f32,
+    time: f32,
+}
+
+// view bindings
+#import bevy_pbr::mesh_view_bindings
+
+// terrain view bindings
+@group(1) @binding(0)
+var<uniform> view_config: TerrainViewConfig;
+@group(1) @binding(1)
+var quadtree: texture_2d_array<u32>;
+@group(1) @binding(2)
+var<storage> tiles: TileList;
+
+// terrain bindings
+@group(2) @binding(0)
+var<uniform> config: TerrainConfig;
+@group(2) @binding(1)
+var atlas_sampler: sampler;
+@group(2) @binding(2)
+var height_atlas: texture_2d_array<f32>;
+#ifdef WATER_ATTACHMENT_2
+@group(2) @binding(4)
+var water_atlas: texture_2d_array<f32>;
+#endif
+#ifdef WATER_ATTACHMENT_3
+@group(2) @binding(5)
+var water_atlas: texture_2d_array<f32>;
+#endif
+
+// water bindings
+@group(3) @binding(0)
+var<uniform> water: Water;
+
+#import bevy_pbr::mesh_types
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import bevy_terrain::node
+#import bevy_terrain::functions
+
+fn sample_attachment(atlas: texture_2d_array<f32>, index: u32, lookup: NodeLookup) -> f32 {
+    let coords = lookup.atlas_coords * config.attachment_scales[index] + config.attachment_offsets[index];
+
+    return textureSampleLevel(atlas, atlas_sampler, coords, lookup.atlas_index, 0.0).x * config.height + view_config.elevation_offset;
+}
+
+// Places the vertices of the terrain tiles at the water level.
+fn vertex_height(lookup: NodeLookup) -> f32 {
+#ifdef WATER_ATTACHMENT_2
+    return sample_attachment(water_atlas, 2u, lookup);
+#else
+#ifdef WATER_ATTACHMENT_3
+    return sample_attachment(water_atlas, 3u, lookup);
+#else
+    return water.level;
+#endif
+#endif
+}
+
+// Sums up a few sine waves travelling in different directions and returns the resulting normal.
+fn wave_normal(local_position: vec2<f32>) -> vec3<f32> {
+    let position = local_position / water.wave_length;
+    let time = water.time * water.wave_speed;
+
+    let direction1 = vec2<f32>(0.8, 0.6);
+    let direction2 = vec2<f32>(-0.45, 0.9);
+    let direction3 = vec2<f32>(0.2, -1.0);
+
+    let gradient = direction1 * 1.0 * cos(dot(position, direction1) * 1.0 + time) * 1.0
+                 + direction2 * 1.7 * cos(dot(position, direction2) * 1.7 + time * 1.3) * 0.5
+                 + direction3 * 3.1 * cos(dot(position, direction3) * 3.1 + time * 2.1) * 0.25;
+
+    return normalize(vec3<f32>(-gradient.x * water.wave_strength, 1.0, -gradient.y * water.wave_strength));
+}
+
+#import bevy_terrain::vertex
+
+@fragment
+fn fragment(input: FragmentInput) -> FragmentOutput {
+    let blend = calculate_blend(input.world_position);
+    let lookup = lookup_node(blend.lod, input.local_position);
+    let terrain_height = sample_attachment(height_atlas, 0u, lookup);
+
+    let depth = input.world_position.y - terrain_height;
+
+    if (depth <= 0.0) {
+        discard;
+    }
+
+    let absorption = 1.0 - exp(-depth / water.depth_scale);
+    var color = mix(water.shallow_color, water.deep_color, absorption);
+
+    // the foam fades out with the depth and is broken up by the waves
+    let ripple = 0.5 + 0.5 * sin(depth * 6.0 - water.time * water.wave_speed * 2.0);
+    let foam = (1.0 - smoothstep(0.0, water.foam_depth, depth)) * mix(0.5, 1.0, ripple);
+    color = mix(color, vec4<f32>(1.0), foam);
+
+    let normal = wave_normal(input.local_position);
+
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = color;
+    pbr_input.material.perceptual_roughness = mix(0.05, 0.8, foam);
+    pbr_input.material.reflectance = 0.5;
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND;
+    pbr_input.frag_coord = input.frag_coord;
+    pbr_input.world_position = input.world_position;
+    pbr_input.world_normal = normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normal;
+    pbr_input.V = calculate_view(input.world_position, pbr_input.is_orthographic);
+
+    return FragmentOutput(tone_mapping(pbr(pbr_input)));
+}
@@ -0,0 +1,16 @@
+#define_import_path bevy_terrain::tint
+
+// Returns the tint of the terrain at the world position, whose alpha is the strength of the tint.
+fn terrain_tint(world_position: vec4<f32>) -> vec4<f32> {
+    let resolution = textureDimensions(tint_texture);
+    let cell = vec2<i32>(world_position.xz / f32(config.terrain_size) * vec2<f32>(resolution));
+
+    return textureLoad(tint_texture, clamp(cell, vec2<i32>(0), resolution - 1), 0);
+}
+
+// Blends the tint of the terrain over the color.
+fn apply_tint(base_color: vec4<f32>, world_position: vec4<f32>) -> vec4<f32> {
+    let tint = terrain_tint(world_position);
+
+    return vec4<f32>(mix(base_color.rgb, tint.rgb, tint.a), base_color.a);
+}
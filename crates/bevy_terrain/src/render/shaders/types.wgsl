@@ -2,6 +2,18 @@
 
 struct Mesh { flags: u32 }; let mesh = Mesh(0u); // hack for the pbr shaders
 
+// The patch topologies of the tile grid.
+let STRIP: u32 = 0u;
+let INDEXED_STRIP: u32 = 1u;
+
+// how the area outside of the coverage of sparse terrains is rendered
+let COVERAGE_SKIP: u32 = 0u;
+let COVERAGE_FILL: u32 = 1u;
+
+// whether the seabed of the bathymetry attachment is rendered below the sea level
+let BATHYMETRY_TOPOGRAPHY: u32 = 0u;
+let BATHYMETRY_TOPO_BATHY: u32 = 1u;
+
 struct TerrainViewConfig {
     approximate_height: f32,
     node_count: u32,
@@ -16,6 +28,30 @@ struct TerrainViewConfig {
     blend_distance: f32,
     morph_range: f32,
     blend_range: f32,
+    min_triangle_size: f32,
+    sun_azimuth: f32,
+    sun_elevation: f32,
+    difference_range: f32,
+    patch_topology: u32,
+    coverage_fill: u32,
+    fill_height: f32,
+    elevation_offset: f32,
+    bathymetry_mode: u32,
+    sea_level: f32,
+    far_field: u32,
+    border_skirt: u32,
+    skirt_height: f32,
+    viewer_position: vec4<f32>,
+    skirt_color: vec4<f32>,
+}
+
+struct TerrainAtmosphere {
+    fog_color: vec4<f32>,
+    sun_color: vec4<f32>,
+    sun_direction: vec4<f32>,
+    fog_density: f32,
+    height_falloff: f32,
+    sun_exponent: f32,
 }
 
 struct Tile {
@@ -26,3 +62,62 @@ struct Tile {
 struct TileList {
     data: array<Tile>,
 }
+
+struct Decal {
+    position: vec2<f32>,
+    half_size: vec2<f32>,
+    rotation: vec2<f32>,
+    layer: u32,
+    _padding: u32,
+    color: vec4<f32>,
+}
+
+struct DecalList {
+    data: array<Decal>,
+}
+
+struct DecalClusterList {
+    data: array<vec2<u32>>,
+}
+
+struct DecalIndexList {
+    data: array<u32>,
+}
+
+struct VirtualTexture {
+    lod_count: u32,
+    page_table_size: u32,
+    cache_size: u32,
+    feedback_slot: u32,
+    page_size: f32,
+    border_size: f32,
+    leaf_page_size: f32,
+    lod_bias: f32,
+    // the page coordinate of the first page of the page table per lod
+    origins: array<vec4<i32>, 16>,
+}
+
+struct PageFeedback {
+    data: array<atomic<u32>>,
+}
+
+struct VegetationConfig {
+    color: vec4<f32>,
+    size: vec2<f32>,
+    spacing: f32,
+    grid_size: u32,
+    view_distance: f32,
+    lod_distance: f32,
+    min_height: f32,
+    max_height: f32,
+    max_slope: f32,
+}
+
+struct VegetationInstance {
+    position: vec3<f32>,
+    scale: f32,
+}
+
+struct VegetationInstanceList {
+    data: array<VegetationInstance>,
+}
@@ -0,0 +1,17 @@
+#define_import_path bevy_terrain::viewshed
+
+// Returns the overlay color of the viewshed at the world position, whose alpha is the strength of the overlay.
+// Regions outside of the radius of the viewshed are transparent.
+fn terrain_viewshed(world_position: vec4<f32>) -> vec4<f32> {
+    let resolution = textureDimensions(viewshed_texture);
+    let texel = vec2<i32>(world_position.xz / f32(config.terrain_size) * vec2<f32>(resolution));
+
+    return textureLoad(viewshed_texture, clamp(texel, vec2<i32>(0), resolution - 1), 0);
+}
+
+// Blends the viewshed of the terrain over the color.
+fn apply_viewshed(base_color: vec4<f32>, world_position: vec4<f32>) -> vec4<f32> {
+    let viewshed = terrain_viewshed(world_position);
+
+    return vec4<f32>(mix(base_color.rgb, viewshed.rgb, viewshed.a), base_color.a);
+}
@@ -0,0 +1,110 @@
+#define_import_path bevy_terrain::virtual_texture
+
+struct PageLookup {
+    coords: vec2<f32>,
+    resident: bool,
+}
+
+// Returns the world size of a page of the lod.
+fn page_world_size(lod: u32) -> f32 {
+    return virtual_texture.leaf_page_size * exp2(f32(lod));
+}
+
+// Returns the entry of the page table, which covers the page, or -1 if the page lies outside of the page table.
+fn page_table_cell(lod: u32, page: vec2<i32>) -> vec2<i32> {
+    let size = i32(virtual_texture.page_table_size);
+    let offset = page - virtual_texture.origins[lod].xy;
+
+    if (any(offset < vec2<i32>(0)) || any(offset >= vec2<i32>(size))) {
+        return vec2<i32>(-1);
+    }
+
+    // the page table wraps around, so that only the new pages have to be updated, when it moves
+    return (page % size + size) % size;
+}
+
+// Selects the lod of the virtual texture, whose texels match the size of the pixels on screen.
+fn virtual_texture_lod(local_position: vec2<f32>) -> f32 {
+    let texel_size = virtual_texture.leaf_page_size / virtual_texture.page_size;
+    let pixel_size = max(length(dpdx(local_position)), length(dpdy(local_position)));
+    let lod = log2(max(pixel_size / texel_size, 1e-6)) + virtual_texture.lod_bias;
+
+    return clamp(lod, 0.0, f32(virtual_texture.lod_count - 1u));
+}
+
+// Looks up the position in the page cache of the best resident page, starting at the lod.
+fn lookup_page(lod: u32, local_position: vec2<f32>) -> PageLookup {
+    for (var level = lod; level < virtual_texture.lod_count; level = level + 1u) {
+        let cell = page_table_cell(level, vec2<i32>(floor(local_position / page_world_size(level))));
+
+        if (cell.x < 0) {
+            continue;
+        }
+
+        let entry = textureLoad(page_table, cell, i32(level), 0).xy;
+
+        if (entry.x == 0xFFFFu) {
+            break;
+        }
+
+        let page_texture_size = virtual_texture.page_size + 2.0 * virtual_texture.border_size;
+        let slot = vec2<f32>(vec2<u32>(entry.x % virtual_texture.cache_size, entry.x / virtual_texture.cache_size));
+        let page_coords = fract(local_position / page_world_size(entry.y));
+        let coords = slot * page_texture_size + virtual_texture.border_size + page_coords * virtual_texture.page_size;
+
+        return PageLookup(coords / (f32(virtual_texture.cache_size) * page_texture_size), true);
+    }
+
+    return PageLookup(vec2<f32>(0.0), false);
+}
+
+// Requests the page of the lod at the position, if this fragment writes the feedback during the current frame.
+// Pages outside of the page table are requested at the first coarser lod, which covers them.
+fn request_page(lod: u32, local_position: vec2<f32>, frag_coord: vec4<f32>) {
+    let pattern = vec2<u32>(frag_coord.xy) % 4u;
+
+    if (pattern.y * 4u + pattern.x != virtual_texture.feedback_slot) {
+        return;
+    }
+
+    for (var level = lod; level < virtual_texture.lod_count; level = level + 1u) {
+        let cell = page_table_cell(level, vec2<i32>(floor(local_position / page_world_size(level))));
+
+        if (cell.x < 0) {
+            continue;
+        }
+
+        let size = virtual_texture.page_table_size;
+        let index = (level * size + u32(cell.y)) * size + u32(cell.x);
+
+        atomicOr(&page_feedback.data[index / 32u], 1u << (index % 32u));
+        return;
+    }
+}
+
+// Returns the color of the virtual texture at the position, whose alpha is zero, if no page is resident yet.
+fn virtual_texture_color(local_position: vec2<f32>, frag_coord: vec4<f32>) -> vec4<f32> {
+    let lod = virtual_texture_lod(local_position);
+    let fine_lod = u32(lod);
+    let coarse_lod = min(fine_lod + 1u, virtual_texture.lod_count - 1u);
+
+    request_page(fine_lod, local_position, frag_coord);
+
+    let fine = lookup_page(fine_lod, local_position);
+    let coarse = lookup_page(coarse_lod, local_position);
+
+    // the lookup falls back to coarser pages, thus none of them is resident
+    if (!fine.resident) {
+        return vec4<f32>(0.0);
+    }
+
+    let fine_color = textureSampleLevel(page_cache, atlas_sampler, fine.coords, 0.0);
+
+    if (!coarse.resident) {
+        return fine_color;
+    }
+
+    let coarse_color = textureSampleLevel(page_cache, atlas_sampler, coarse.coords, 0.0);
+
+    return mix(fine_color, coarse_color, fract(lod));
+}
@@ -0,0 +1,39 @@
+#define_import_path bevy_terrain::weather
+
+struct WeatheredSurface {
+    color: vec4<f32>,
+    perceptual_roughness: f32,
+}
+
+// Returns the snow (x) and the wetness (y) of the terrain at the world position.
+// The cells of the weather layer are interpolated bilinearly.
+fn terrain_weather(world_position: vec4<f32>) -> vec2<f32> {
+    let resolution = textureDimensions(weather_texture);
+    let coords = world_position.xz / f32(config.terrain_size) * vec2<f32>(resolution) - 0.5;
+    let cell = vec2<i32>(floor(coords));
+    let ratio = fract(coords);
+    let max_cell = resolution - 1;
+
+    let a = textureLoad(weather_texture, clamp(cell, vec2<i32>(0), max_cell), 0).xy;
+    let b = textureLoad(weather_texture, clamp(cell + vec2<i32>(1, 0), vec2<i32>(0), max_cell), 0).xy;
+    let c = textureLoad(weather_texture, clamp(cell + vec2<i32>(0, 1), vec2<i32>(0), max_cell), 0).xy;
+    let d = textureLoad(weather_texture, clamp(cell + vec2<i32>(1, 1), vec2<i32>(0), max_cell), 0).xy;
+
+    return mix(mix(a, b, ratio.x), mix(c, d, ratio.x), ratio.y);
+}
+
+// Darkens and smooths wet terrain and covers snowy terrain in snow.
+fn apply_weather(base_color: vec4<f32>, perceptual_roughness: f32, world_position: vec4<f32>) -> WeatheredSurface {
+    let weather = terrain_weather(world_position);
+    let snow = weather.x;
+    let wetness = weather.y;
+
+    // wet surfaces absorb more light and reflect specularly
+    var color = base_color.rgb * mix(1.0, 0.5, wetness);
+    var roughness = mix(perceptual_roughness, 0.15, wetness);
+
+    color = mix(color, vec3<f32>(0.95, 0.95, 0.97), snow);
+    roughness = mix(roughness, 0.7, snow);
+
+    return WeatheredSurface(vec4<f32>(color, base_color.a), roughness);
+}
@@ -0,0 +1,428 @@
+//! Soft intersections between the terrain and large static meshes (e.g. cliff props or
+//! building foundations), which otherwise cut hard lines into the terrain.
+//!
+//! The meshes with a [`TerrainSoftIntersection`] are drawn a second time on top of themselves.
+//! This pass reconstructs the height of the terrain beneath each fragment from the node atlas,
+//! using the quadtree of the view, and fades the base of the mesh into the terrain depending on
+//! its height above the terrain.
+//!
+//! The bind groups of the terrain can be bound for any other entity as well, by adding a
+//! [`TerrainBinding`] to it in the render world and drawing it with the
+//! [`SetTerrainViewBindGroupOf`] and [`SetTerrainBindGroupOf`] render commands.
+//! See [`terrain_textures`](crate::render::terrain_textures) for the matching layouts.
+
+use crate::{
+    render::{
+        shaders::SOFT_INTERSECTION_SHADER, terrain_data::TerrainData,
+        terrain_textures::TerrainBindGroupLayout, terrain_view_data::TerrainViewData,
+    },
+    skip_none,
+    terrain::TerrainComponents,
+    terrain_data::AttachmentIndex,
+    terrain_view::{TerrainView, TerrainViewComponents},
+};
+use bevy::{
+    core_pipeline::core_3d::Transparent3d,
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem,
+    },
+    pbr::{DrawMesh, MeshPipeline, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
+        render_phase::{
+            DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::BevyDefault,
+        view::ExtractedView,
+        Extract,
+    },
+};
+
+/// Blends the base of the mesh of this entity into the terrain.
+///
+/// Add this component to an entity with a mesh (e.g. a [`PbrBundle`]), which intersects the
+/// terrain. Only the position attribute of the mesh is used.
+#[derive(Clone, Component)]
+pub struct TerrainSoftIntersection {
+    /// The terrain, the mesh is blended into.
+    pub terrain: Entity,
+    /// The height above the terrain, up to which the mesh is blended.
+    pub blend_height: f32,
+    /// The color of the terrain, which the mesh is blended with.
+    pub color: Color,
+    /// The attachment, whose color replaces the color of the terrain, if any.
+    ///
+    /// Only the attachments with the index two and three are supported.
+    pub albedo_attachment: Option<AttachmentIndex>,
+}
+
+impl TerrainSoftIntersection {
+    pub fn new(terrain: Entity) -> Self {
+        Self {
+            terrain,
+            blend_height: 1.0,
+            color: Color::rgb(0.5, 0.5, 0.5),
+            albedo_attachment: None,
+        }
+    }
+}
+
+/// Binds the bind groups of the terrain to an entity of the render world, which is not a terrain.
+#[derive(Clone, Copy, Component)]
+pub struct TerrainBinding {
+    /// The terrain, whose bind groups are bound.
+    pub terrain: Entity,
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct SoftIntersectionUniform {
+    model: Mat4,
+    color: Vec4,
+    blend_height: f32,
+}
+
+/// The render world side of the [`TerrainSoftIntersection`].
+#[derive(Component)]
+pub(crate) struct ExtractedSoftIntersection {
+    uniform: SoftIntersectionUniform,
+    albedo_attachment: Option<AttachmentIndex>,
+}
+
+#[derive(Component)]
+pub(crate) struct SoftIntersectionBindGroup(BindGroup);
+
+/// The pipeline used to blend the meshes into the terrain.
+#[derive(Resource)]
+pub struct SoftIntersectionPipeline {
+    view_layout: BindGroupLayout,
+    terrain_view_layout: BindGroupLayout,
+    terrain_layout: BindGroupLayout,
+    soft_intersection_layout: BindGroupLayout,
+}
+
+impl FromWorld for SoftIntersectionPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let mesh_pipeline = world.resource::<MeshPipeline>();
+        let terrain_layout = world.resource::<TerrainBindGroupLayout>();
+
+        let soft_intersection_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: "soft_intersection_layout".into(),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX_FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SoftIntersectionUniform::min_size()),
+                    },
+                    count: None,
+                }],
+            });
+
+        Self {
+            view_layout: mesh_pipeline.view_layout.clone(),
+            terrain_view_layout: terrain_layout.terrain_view.clone(),
+            terrain_layout: terrain_layout.terrain.clone(),
+            soft_intersection_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for SoftIntersectionPipeline {
+    /// The number of msaa samples, the albedo attachment and the topology of the mesh.
+    type Key = (u32, Option<AttachmentIndex>, PrimitiveTopology);
+
+    fn specialize(
+        &self,
+        (msaa_samples, albedo_attachment, topology): Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut shader_defs = vec!["TONEMAP_IN_SHADER".to_string()];
+
+        match albedo_attachment {
+            Some(2) => shader_defs.push("ALBEDO_ATTACHMENT_2".to_string()),
+            Some(3) => shader_defs.push("ALBEDO_ATTACHMENT_3".to_string()),
+            _ => {}
+        }
+
+        let vertex_layout = layout.get_layout(&[Mesh::ATTRIBUTE_POSITION.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            label: "soft_intersection_pipeline".into(),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.terrain_view_layout.clone(),
+                self.terrain_layout.clone(),
+                self.soft_intersection_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: SOFT_INTERSECTION_SHADER.typed(),
+                entry_point: "vertex".into(),
+                shader_defs: shader_defs.clone(),
+                buffers: vec![vertex_layout],
+            },
+            primitive: PrimitiveState {
+                topology,
+                cull_mode: Some(Face::Back),
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: SOFT_INTERSECTION_SHADER.typed(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            // the mesh is drawn on top of its own surface
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: msaa_samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+}
+
+pub(crate) fn extract_soft_intersections(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    soft_intersection_query: Extract<
+        Query<(
+            Entity,
+            &TerrainSoftIntersection,
+            &GlobalTransform,
+            &ComputedVisibility,
+        )>,
+    >,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+
+    for (entity, soft_intersection, transform, visibility) in soft_intersection_query.iter() {
+        if !visibility.is_visible() {
+            continue;
+        }
+
+        if let Some(attachment) = soft_intersection.albedo_attachment {
+            if !(2..=3).contains(&attachment) {
+                warn!("The albedo attachment has to be the attachment two or three.");
+            }
+        }
+
+        values.push((
+            entity,
+            (
+                TerrainBinding {
+                    terrain: soft_intersection.terrain,
+                },
+                ExtractedSoftIntersection {
+                    uniform: SoftIntersectionUniform {
+                        model: transform.compute_matrix(),
+                        color: soft_intersection.color.as_linear_rgba_f32().into(),
+                        blend_height: soft_intersection.blend_height.max(f32::EPSILON),
+                    },
+                    albedo_attachment: soft_intersection.albedo_attachment,
+                },
+            ),
+        ));
+    }
+
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
+}
+
+/// Creates the bind groups of the soft intersections and queues them for all terrain views,
+/// which render their terrain.
+pub(crate) fn queue_soft_intersections(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    soft_intersection_pipeline: Res<SoftIntersectionPipeline>,
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    msaa: Res<Msaa>,
+    meshes: Res<RenderAssets<Mesh>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<SoftIntersectionPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    soft_intersection_query: Query<(
+        Entity,
+        &TerrainBinding,
+        &ExtractedSoftIntersection,
+        &Handle<Mesh>,
+    )>,
+    mut view_query: Query<
+        (Entity, &ExtractedView, &mut RenderPhase<Transparent3d>),
+        With<TerrainView>,
+    >,
+) {
+    let draw_function = draw_functions
+        .read()
+        .get_id::<DrawSoftIntersection>()
+        .unwrap();
+
+    for (entity, binding, soft_intersection, mesh) in soft_intersection_query.iter() {
+        let mesh = skip_none!(meshes.get(mesh));
+
+        let pipeline = match pipelines.specialize(
+            &mut pipeline_cache,
+            &soft_intersection_pipeline,
+            (
+                msaa.samples,
+                soft_intersection.albedo_attachment,
+                mesh.primitive_topology,
+            ),
+            &mesh.layout,
+        ) {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                error!("{error}");
+                continue;
+            }
+        };
+
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&soft_intersection.uniform).unwrap();
+
+        let uniform_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "soft_intersection_uniform_buffer".into(),
+            usage: BufferUsages::UNIFORM,
+            contents: &buffer.into_inner(),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "soft_intersection_bind_group".into(),
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            layout: &soft_intersection_pipeline.soft_intersection_layout,
+        });
+
+        commands
+            .entity(entity)
+            .insert(SoftIntersectionBindGroup(bind_group));
+
+        let translation = soft_intersection.uniform.model.col(3);
+
+        for (view, extracted_view, mut transparent_phase) in view_query.iter_mut() {
+            if terrain_view_data.get(&(binding.terrain, view)).is_none() {
+                continue;
+            }
+
+            let inverse_view_row_2 = extracted_view.transform.compute_matrix().inverse().row(2);
+
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function,
+                distance: inverse_view_row_2.dot(translation),
+            });
+        }
+    }
+}
+
+/// Binds the terrain view bind group of the terrain of the [`TerrainBinding`] of the item.
+pub struct SetTerrainViewBindGroupOf<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetTerrainViewBindGroupOf<I> {
+    type Param = (
+        SRes<TerrainViewComponents<TerrainViewData>>,
+        SQuery<Read<TerrainBinding>>,
+    );
+
+    #[inline]
+    fn render<'w>(
+        view: Entity,
+        item: Entity,
+        (terrain_view_data, binding_query): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let binding = match binding_query.get(item) {
+            Ok(binding) => binding,
+            Err(_) => return RenderCommandResult::Failure,
+        };
+
+        match terrain_view_data.into_inner().get(&(binding.terrain, view)) {
+            Some(data) => {
+                pass.set_bind_group(I, &data.terrain_view_bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
+
+/// Binds the terrain bind group of the terrain of the [`TerrainBinding`] of the item.
+pub struct SetTerrainBindGroupOf<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetTerrainBindGroupOf<I> {
+    type Param = (
+        SRes<TerrainComponents<TerrainData>>,
+        SQuery<Read<TerrainBinding>>,
+    );
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        (terrain_data, binding_query): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let binding = match binding_query.get(item) {
+            Ok(binding) => binding,
+            Err(_) => return RenderCommandResult::Failure,
+        };
+
+        match terrain_data.into_inner().get(&binding.terrain) {
+            Some(data) => {
+                pass.set_bind_group(I, &data.terrain_bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
+    }
+}
+
+pub struct SetSoftIntersectionBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetSoftIntersectionBindGroup<I> {
+    type Param = SQuery<Read<SoftIntersectionBindGroup>>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        bind_group_query: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let bind_group = bind_group_query.get_inner(item).unwrap();
+        pass.set_bind_group(I, &bind_group.0, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// The draw function of the soft intersections.
+pub(crate) type DrawSoftIntersection = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetTerrainViewBindGroupOf<1>,
+    SetTerrainBindGroupOf<2>,
+    SetSoftIntersectionBindGroup<3>,
+    DrawMesh,
+);
@@ -0,0 +1,283 @@
+//! A standard terrain material, which lights the terrain with the PBR functions of Bevy,
+//! without having to write a custom shader.
+//!
+//! The material expects the following attachment layout:
+//! * 0 - the height attachment
+//! * 1 - the minmax attachment
+//! * 2 - (optional) the normal attachment (`Rg16`), which stores the x and z component
+//!   of the world normal remapped to [0, 1]
+//! * 3 - (optional) the material attachment (`Rg16`), which stores the perceptual roughness
+//!   in the red and the ambient occlusion in the green channel
+//! * 4 - (optional) the albedo attachment (`Rgb8`), e.g. an orthophoto
+//! * 5 - (optional) the splat attachment (`Rgba8`), which stores the weight of each detail layer
+//!   in one channel
+//! * 6 - (optional) the winter albedo attachment (`Rgb8`), e.g. an orthophoto captured in winter
+//! * 7 - (optional) the baked ambient occlusion attachment (`R16`), see
+//!   [`Derivative::AmbientOcclusion`](crate::preprocess::derivative::Derivative::AmbientOcclusion),
+//!   which is combined with the ambient occlusion of the material attachment,
+//!   or the horizon attachment (`Rgba8`), see
+//!   [`Derivative::Horizon`](crate::preprocess::derivative::Derivative::Horizon),
+//!   if the material has [`HorizonShadows`]
+//!
+//! Without the normal attachment, the normals are computed from the height attachment instead.
+//! Without the splat attachment, only the first detail layer is applied.
+//!
+//! Aerial imagery is usually too coarse, when the camera is close to the ground.
+//! Therefore tiling detail textures are blended over the albedo up close and fade out
+//! with the distance to the camera. The detail textures contain one tile per [`DetailLayer`],
+//! which are placed next to each other horizontally. The detail albedo is applied as a
+//! modulation, where a linear value of 0.5 leaves the albedo unchanged, so that it only adds
+//! high frequency variation.
+//! The detail normal is a tangent space normal map, which perturbs the terrain normal.
+//!
+//! The horizon attachment stores the horizon angle of each pixel in four directions.
+//! The terrain is shadowed, where the sun of the first directional light lies below the horizon,
+//! and the ambient occlusion is approximated by the average horizon.
+//!
+//! The global [`Season`] blends between the albedo and the winter albedo attachment and lowers
+//! the optional [`SnowLine`], above which flat terrain is covered in snow.
+
+use crate::render::shaders::STANDARD_SHADER;
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{render_asset::RenderAssets, render_resource::*},
+};
+
+/// The number of detail layers of the [`StandardTerrainMaterial`].
+pub const DETAIL_LAYER_COUNT: usize = 4;
+
+const DETAIL_ALBEDO_FLAG: u32 = 1 << 0;
+const DETAIL_NORMAL_FLAG: u32 = 1 << 1;
+const SNOW_LINE_FLAG: u32 = 1 << 2;
+const HORIZON_SHADOWS_FLAG: u32 = 1 << 3;
+
+/// The season of all [`StandardTerrainMaterial`]s.
+///
+/// Change this resource to transition the terrains between the seasons.
+#[derive(Clone, Copy, Debug, Default, Resource)]
+pub struct Season {
+    /// The progress from summer (0) to winter (1).
+    pub winter: f32,
+}
+
+/// The altitude above which the terrain is covered in snow.
+///
+/// The snow line lies between the summer and the winter altitude, depending on the [`Season`].
+#[derive(Clone, Copy, Debug, ShaderType)]
+pub struct SnowLine {
+    /// The altitude of the snow line in summer.
+    pub summer_altitude: f32,
+    /// The altitude of the snow line in winter.
+    pub winter_altitude: f32,
+    /// The altitude range over which the snow fades in around the snow line.
+    pub transition: f32,
+    /// The slope in degrees above which no snow lies.
+    pub max_slope: f32,
+}
+
+impl Default for SnowLine {
+    fn default() -> Self {
+        Self {
+            summer_altitude: 2500.0,
+            winter_altitude: 500.0,
+            transition: 100.0,
+            max_slope: 45.0,
+        }
+    }
+}
+
+/// The soft self-shadows cast by the terrain, computed from the horizon attachment.
+///
+/// Shadowed regions only receive the ambient light.
+#[derive(Clone, Copy, Debug, ShaderType)]
+pub struct HorizonShadows {
+    /// How strongly the shadows darken the terrain, in the range of [0, 1].
+    pub strength: f32,
+    /// The range of the sine of the sun elevation, over which the shadow fades in
+    /// around the horizon. Larger values produce softer shadows.
+    pub softness: f32,
+}
+
+impl Default for HorizonShadows {
+    fn default() -> Self {
+        Self {
+            strength: 1.0,
+            softness: 0.05,
+        }
+    }
+}
+
+/// The tiling and fade settings of a single detail layer.
+#[derive(Clone, Copy, Debug, ShaderType)]
+pub struct DetailLayer {
+    /// The size of one repetition of the detail texture in world units.
+    pub scale: f32,
+    /// The distance to the camera at which the detail starts to fade out.
+    pub fade_start: f32,
+    /// The distance to the camera at which the detail has faded out completely.
+    pub fade_end: f32,
+    /// How strongly the detail is applied, in the range of [0, 1].
+    pub strength: f32,
+}
+
+impl Default for DetailLayer {
+    fn default() -> Self {
+        Self {
+            scale: 4.0,
+            fade_start: 50.0,
+            fade_end: 200.0,
+            strength: 1.0,
+        }
+    }
+}
+
+/// A lit terrain material, which consumes normal, roughness and ambient occlusion attachments.
+///
+/// Use it together with the `TerrainMaterialPlugin::<StandardTerrainMaterial>`.
+/// The terrain is lit by all directional and point lights, as well as the ambient light.
+#[derive(AsBindGroup, TypeUuid, Clone, Debug)]
+#[uuid = "8f0b3b5e-3c1d-4f0a-9a47-2d6e5c8b1f73"]
+#[uniform(0, StandardTerrainMaterialUniform)]
+pub struct StandardTerrainMaterial {
+    /// The color of the terrain surface.
+    pub base_color: Color,
+    /// The perceptual roughness, which is multiplied with the one of the material attachment.
+    pub perceptual_roughness: f32,
+    /// The specular reflectance of the terrain surface.
+    pub reflectance: f32,
+    /// How strongly the ambient occlusion of the material and the baked ambient occlusion
+    /// attachments is applied, in the range of [0, 1].
+    pub occlusion_strength: f32,
+    /// The tiling albedo textures of the detail layers, placed next to each other horizontally.
+    #[texture(1)]
+    #[sampler(2)]
+    pub detail_albedo: Option<Handle<Image>>,
+    /// The tiling tangent space normal maps of the detail layers, placed next to each other
+    /// horizontally. This texture has to be stored in a linear format.
+    #[texture(3)]
+    #[sampler(4)]
+    pub detail_normal: Option<Handle<Image>>,
+    /// The settings of the detail layers, selected by the channels of the splat attachment.
+    pub detail_layers: [DetailLayer; DETAIL_LAYER_COUNT],
+    /// The color of the snow above the snow line.
+    pub snow_color: Color,
+    /// The snow line, or `None` to disable the snow overlay.
+    pub snow_line: Option<SnowLine>,
+    /// The progress from summer (0) to winter (1), which is synchronized with the [`Season`].
+    pub season: f32,
+    /// The self-shadows of the terrain, or `None` to use the attachment seven as baked
+    /// ambient occlusion instead.
+    pub horizon_shadows: Option<HorizonShadows>,
+}
+
+impl Default for StandardTerrainMaterial {
+    fn default() -> Self {
+        Self {
+            base_color: Color::rgb(0.5, 0.5, 0.5),
+            perceptual_roughness: 1.0,
+            reflectance: 0.0,
+            occlusion_strength: 1.0,
+            detail_albedo: None,
+            detail_normal: None,
+            detail_layers: default(),
+            snow_color: Color::rgb(0.95, 0.95, 0.97),
+            snow_line: None,
+            season: 0.0,
+            horizon_shadows: None,
+        }
+    }
+}
+
+impl Material for StandardTerrainMaterial {
+    fn fragment_shader() -> ShaderRef {
+        STANDARD_SHADER.typed().into()
+    }
+}
+
+/// The GPU representation of the [`StandardTerrainMaterial`].
+#[derive(Clone, Default, ShaderType)]
+pub struct StandardTerrainMaterialUniform {
+    pub base_color: Vec4,
+    pub perceptual_roughness: f32,
+    pub reflectance: f32,
+    pub occlusion_strength: f32,
+    pub flags: u32,
+    pub detail_layers: [DetailLayer; DETAIL_LAYER_COUNT],
+    pub snow_color: Vec4,
+    pub snow_line: SnowLine,
+    pub season: f32,
+    pub horizon_shadows: HorizonShadows,
+}
+
+impl AsBindGroupShaderType<StandardTerrainMaterialUniform> for StandardTerrainMaterial {
+    fn as_bind_group_shader_type(
+        &self,
+        _images: &RenderAssets<Image>,
+    ) -> StandardTerrainMaterialUniform {
+        let mut flags = 0;
+        if self.detail_albedo.is_some() {
+            flags |= DETAIL_ALBEDO_FLAG;
+        }
+        if self.detail_normal.is_some() {
+            flags |= DETAIL_NORMAL_FLAG;
+        }
+        if self.snow_line.is_some() {
+            flags |= SNOW_LINE_FLAG;
+        }
+        if self.horizon_shadows.is_some() {
+            flags |= HORIZON_SHADOWS_FLAG;
+        }
+
+        let detail_layers = self.detail_layers.map(|layer| DetailLayer {
+            strength: layer.strength.clamp(0.0, 1.0),
+            fade_end: layer.fade_end.max(layer.fade_start + f32::EPSILON),
+            ..layer
+        });
+
+        StandardTerrainMaterialUniform {
+            base_color: self.base_color.as_linear_rgba_f32().into(),
+            perceptual_roughness: self.perceptual_roughness,
+            reflectance: self.reflectance,
+            occlusion_strength: self.occlusion_strength.clamp(0.0, 1.0),
+            flags,
+            detail_layers,
+            snow_color: self.snow_color.as_linear_rgba_f32().into(),
+            snow_line: self.snow_line.map_or(default(), |snow_line| SnowLine {
+                transition: snow_line.transition.max(f32::EPSILON),
+                ..snow_line
+            }),
+            season: self.season.clamp(0.0, 1.0),
+            horizon_shadows: self.horizon_shadows.map_or(default(), |horizon_shadows| {
+                HorizonShadows {
+                    strength: horizon_shadows.strength.clamp(0.0, 1.0),
+                    softness: horizon_shadows.softness.max(f32::EPSILON),
+                }
+            }),
+        }
+    }
+}
+
+/// Synchronizes the season of all [`StandardTerrainMaterial`]s with the [`Season`].
+///
+/// Only materials with an outdated season are modified, to avoid preparing them each frame.
+pub(crate) fn apply_season(
+    season: Res<Season>,
+    materials: Option<ResMut<Assets<StandardTerrainMaterial>>>,
+) {
+    let mut materials = match materials {
+        Some(materials) => materials,
+        None => return, // the standard material is not used
+    };
+
+    let outdated: Vec<HandleId> = materials
+        .iter()
+        .filter(|(_, material)| material.season != season.winter)
+        .map(|(id, _)| id)
+        .collect();
+
+    for id in outdated {
+        materials.get_mut(id).unwrap().season = season.winter;
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{
+    biome::MAX_BIOMES,
     render::TERRAIN_CONFIG_SIZE,
-    terrain::{Terrain, TerrainComponents},
+    terrain::{HeightFilter, Terrain, TerrainComponents},
+    terrain_data::{gpu_node_atlas::GpuNodeAtlas, AttachmentIndex, AttachmentSampler},
     TerrainConfig,
 };
 use bevy::{
@@ -14,7 +16,38 @@ use bevy::{
         Extract,
     },
 };
-use std::num::NonZeroU8;
+
+/// The maximum number of attachments per terrain.
+///
+/// The terrain bind group always contains this many attachment slots, so that the layout and
+/// the pipelines do not depend on the attachments of the individual terrains.
+/// Slots without an attachment are bound to an empty placeholder.
+/// Adding more attachments to a terrain panics.
+pub const MAX_ATTACHMENTS: usize = 8;
+
+/// The sampled textures per shader stage, which the terrain pipelines bind besides the
+/// attachments: the two shadow maps of the view, the eight textures of the terrain view
+/// and the node fade texture.
+const RESERVED_SAMPLED_TEXTURES: usize = 11;
+
+/// Returns the number of attachment slots, which are bound on the device.
+///
+/// WebGPU only guarantees 16 sampled textures per shader stage, which does not suffice for all
+/// [`MAX_ATTACHMENTS`] slots next to the other textures of the terrain pipelines.
+/// On such devices the trailing slots are left out of the layout, so that the pipelines
+/// of the default shader, which only uses the first four slots, can still be created.
+pub(crate) fn attachment_slot_count(device: &RenderDevice) -> usize {
+    let limit = device.limits().max_sampled_textures_per_shader_stage as usize;
+
+    MAX_ATTACHMENTS.min(limit.saturating_sub(RESERVED_SAMPLED_TEXTURES))
+}
+
+/// The binding of the texture, which stores the fade progress of the node atlas slots.
+pub const NODE_FADE_BINDING: u32 = MAX_ATTACHMENTS as u32 + 2;
+
+/// The binding of the sampler of the first attachment slot.
+/// The samplers of the other slots follow consecutively.
+pub const ATTACHMENT_SAMPLER_BINDING: u32 = NODE_FADE_BINDING + 1;
 
 /// The terrain config data that is available in shaders.
 #[derive(Clone, Default, ShaderType)]
@@ -26,19 +59,39 @@ pub(crate) struct TerrainConfigUniform {
     attachment_sizes: Vec4,
     attachment_scales: Vec4,
     attachment_offsets: Vec4,
+    /// The sizes, scales and offsets of the attachments four to seven.
+    /// They are stored at the end, so that shaders, which only use the first
+    /// four attachments, can declare the shorter struct.
+    extra_attachment_sizes: Vec4,
+    extra_attachment_scales: Vec4,
+    extra_attachment_offsets: Vec4,
+    /// The number of attachments of the terrain, which lets shaders skip empty attachment slots.
+    attachment_count: u32,
+    /// The colors of the biomes in linear space, indexed by the biome id minus one.
+    biome_colors: [Vec4; MAX_BIOMES],
+    /// The mip biases of the samplers of the attachments.
+    attachment_mip_biases: Vec4,
+    extra_attachment_mip_biases: Vec4,
 }
 
 impl From<&TerrainConfig> for TerrainConfigUniform {
     fn from(config: &TerrainConfig) -> Self {
-        // Todo: figure out a better way to store data for more than four attachments
-        let mut sizes = [0.0; 4];
-        let mut scales = [1.0; 4];
-        let mut offsets = [0.0; 4];
+        let mut sizes = [0.0; MAX_ATTACHMENTS];
+        let mut scales = [1.0; MAX_ATTACHMENTS];
+        let mut offsets = [0.0; MAX_ATTACHMENTS];
+        let mut mip_biases = [0.0; MAX_ATTACHMENTS];
 
-        for (i, attachment) in config.attachments.iter().enumerate() {
+        for (i, attachment) in config.attachments.iter().enumerate().take(MAX_ATTACHMENTS) {
             sizes[i] = attachment.texture_size as f32;
             scales[i] = attachment.center_size as f32 / attachment.texture_size as f32;
             offsets[i] = attachment.border_size as f32 / attachment.texture_size as f32;
+            mip_biases[i] = attachment.sampler.mip_bias;
+        }
+
+        let mut biome_colors = [Vec4::ZERO; MAX_BIOMES];
+
+        for (color, biome) in biome_colors.iter_mut().zip(&config.biomes) {
+            *color = Vec4::from(biome.color.as_linear_rgba_f32());
         }
 
         Self {
@@ -46,17 +99,23 @@ impl From<&TerrainConfig> for TerrainConfigUniform {
             height: config.height,
             chunk_size: config.leaf_node_size,
             terrain_size: config.terrain_size,
-            attachment_sizes: Vec4::from_array(sizes),
-            attachment_scales: Vec4::from_array(scales),
-            attachment_offsets: Vec4::from_array(offsets),
+            attachment_sizes: Vec4::from_slice(&sizes[..4]),
+            attachment_scales: Vec4::from_slice(&scales[..4]),
+            attachment_offsets: Vec4::from_slice(&offsets[..4]),
+            extra_attachment_sizes: Vec4::from_slice(&sizes[4..]),
+            extra_attachment_scales: Vec4::from_slice(&scales[4..]),
+            extra_attachment_offsets: Vec4::from_slice(&offsets[4..]),
+            attachment_count: config.attachments.len().min(MAX_ATTACHMENTS) as u32,
+            biome_colors,
+            attachment_mip_biases: Vec4::from_slice(&mip_biases[..4]),
+            extra_attachment_mip_biases: Vec4::from_slice(&mip_biases[4..]),
         }
     }
 }
 
-pub fn terrain_bind_group_layout(
-    device: &RenderDevice,
-    attachment_count: usize,
-) -> BindGroupLayout {
+pub fn terrain_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    let slot_count = attachment_slot_count(device);
+
     let mut entries = vec![
         BindGroupLayoutEntry {
             binding: 0,
@@ -76,7 +135,7 @@ pub fn terrain_bind_group_layout(
         },
     ];
 
-    entries.extend((0..attachment_count).map(|binding| BindGroupLayoutEntry {
+    entries.extend((0..slot_count).map(|binding| BindGroupLayoutEntry {
         binding: binding as u32 + 2,
         visibility: ShaderStages::all(),
         ty: BindingType::Texture {
@@ -87,6 +146,24 @@ pub fn terrain_bind_group_layout(
         count: None,
     }));
 
+    entries.push(BindGroupLayoutEntry {
+        binding: NODE_FADE_BINDING,
+        visibility: ShaderStages::all(),
+        ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    });
+
+    entries.extend((0..slot_count).map(|binding| BindGroupLayoutEntry {
+        binding: binding as u32 + ATTACHMENT_SAMPLER_BINDING,
+        visibility: ShaderStages::all(),
+        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+        count: None,
+    }));
+
     device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         label: "terrain_layout".into(),
         entries: &entries,
@@ -95,6 +172,29 @@ pub fn terrain_bind_group_layout(
 
 pub struct TerrainData {
     pub(crate) terrain_bind_group: BindGroup,
+    /// Whether or not the terrain has a hole mask attachment.
+    pub(crate) mask: bool,
+    /// The reference height attachment of the terrain, if any.
+    pub(crate) reference: Option<AttachmentIndex>,
+    /// The bathymetry attachment of the terrain, if any.
+    pub(crate) bathymetry: Option<AttachmentIndex>,
+    /// The flow attachment of the terrain, if any.
+    pub(crate) flow: Option<AttachmentIndex>,
+    /// The ceiling attachment of the terrain, if any.
+    pub(crate) ceiling: Option<AttachmentIndex>,
+    /// The biome attachment of the terrain, if any.
+    pub(crate) biome: Option<AttachmentIndex>,
+    /// Whether or not newly loaded nodes are cross-faded.
+    pub(crate) node_fade: bool,
+    /// The filter, with which the normals are reconstructed from the height attachment.
+    pub(crate) normal_filter: HeightFilter,
+    /// The filter, with which the heights of the vertices are sampled.
+    pub(crate) vertex_filter: HeightFilter,
+    /// Whether or not the shaded color is dithered.
+    pub(crate) deband_dither: bool,
+    /// Whether or not the height attachment stores the residual of the quantized height
+    /// in its second channel.
+    pub(crate) height_residual: bool,
 }
 
 impl TerrainData {
@@ -102,8 +202,18 @@ impl TerrainData {
         device: &RenderDevice,
         images: &RenderAssets<Image>,
         config: &TerrainConfig,
+        gpu_node_atlas: &GpuNodeAtlas,
+        placeholder: &PlaceholderAttachment,
     ) -> Self {
-        let layout = terrain_bind_group_layout(&device, config.attachments.len());
+        let layout = terrain_bind_group_layout(device);
+        let slot_count = attachment_slot_count(device);
+
+        if config.attachments.len() > slot_count {
+            error!(
+                "The terrain has {} attachments, but the device only supports {slot_count} attachment slots, thus the remaining attachments can not be sampled.",
+                config.attachments.len()
+            );
+        }
 
         let mut buffer = encase::UniformBuffer::new(Vec::new());
         buffer.write(&TerrainConfigUniform::from(config)).unwrap();
@@ -114,18 +224,8 @@ impl TerrainData {
             contents: &buffer.into_inner(),
         });
 
-        let sampler_descriptor = SamplerDescriptor {
-            address_mode_u: Default::default(),
-            address_mode_v: Default::default(),
-            address_mode_w: Default::default(),
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Linear,
-            anisotropy_clamp: NonZeroU8::new(16),
-            ..default()
-        };
-
-        let sampler = device.create_sampler(&sampler_descriptor);
+        // the shared sampler of shaders, which do not use the samplers of the attachments
+        let sampler = device.create_sampler(&AttachmentSampler::default().descriptor());
 
         let mut entries = vec![
             BindGroupEntry {
@@ -138,18 +238,46 @@ impl TerrainData {
             },
         ];
 
+        entries.extend((0..slot_count).map(|binding| {
+            let texture_view = match config.attachments.get(binding) {
+                // the attachment slots can only hold filterable float textures, thus integer
+                // attachments (e.g. categories) are only accessible via the terrain textures
+                Some(attachment)
+                    if !matches!(
+                        attachment.format.describe().sample_type,
+                        TextureSampleType::Uint | TextureSampleType::Sint
+                    ) =>
+                {
+                    &images.get(&attachment.handle).unwrap().texture_view
+                }
+                _ => &placeholder.0,
+            };
+
+            BindGroupEntry {
+                binding: binding as u32 + 2,
+                resource: BindingResource::TextureView(texture_view),
+            }
+        }));
+
+        entries.push(BindGroupEntry {
+            binding: NODE_FADE_BINDING,
+            resource: BindingResource::TextureView(&gpu_node_atlas.fade_view),
+        });
+
+        let samplers: Vec<Sampler> = (0..slot_count)
+            .map(|binding| match config.attachments.get(binding) {
+                Some(attachment) => device.create_sampler(&attachment.sampler.descriptor()),
+                None => sampler.clone(),
+            })
+            .collect();
+
         entries.extend(
-            config
-                .attachments
+            samplers
                 .iter()
                 .enumerate()
-                .map(|(binding, attachment)| {
-                    let attachment = images.get(&attachment.handle).unwrap();
-
-                    BindGroupEntry {
-                        binding: binding as u32 + 2,
-                        resource: BindingResource::TextureView(&attachment.texture_view),
-                    }
+                .map(|(binding, sampler)| BindGroupEntry {
+                    binding: binding as u32 + ATTACHMENT_SAMPLER_BINDING,
+                    resource: BindingResource::Sampler(sampler),
                 }),
         );
 
@@ -159,18 +287,79 @@ impl TerrainData {
             layout: &layout,
         });
 
-        Self { terrain_bind_group }
+        Self {
+            terrain_bind_group,
+            mask: config.mask_attachment.is_some(),
+            reference: config.reference_attachment,
+            bathymetry: config.bathymetry_attachment,
+            flow: config.flow_attachment,
+            ceiling: config.ceiling_attachment,
+            biome: config.biome_attachment,
+            node_fade: config.node_fade_frames > 0,
+            normal_filter: config.normal_filter,
+            vertex_filter: config.vertex_filter,
+            deband_dither: config.deband_dither,
+            height_residual: config
+                .attachments
+                .first()
+                .map_or(false, |height| height.format == TextureFormat::Rg16Unorm),
+        }
     }
 }
 
+/// The empty attachment, which is bound to the unused attachment slots of all terrains.
+#[derive(Resource)]
+pub(crate) struct PlaceholderAttachment(TextureView);
+
+impl FromWorld for PlaceholderAttachment {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let view = device
+            .create_texture(&TextureDescriptor {
+                label: "placeholder_attachment".into(),
+                size: Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING,
+            })
+            .create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2Array),
+                ..default()
+            });
+
+        Self(view)
+    }
+}
+
+/// Creates the [`TerrainData`] of new terrains and recreates it, once the attachments
+/// of a terrain have changed.
 pub(crate) fn initialize_terrain_data(
     device: Res<RenderDevice>,
     images: Res<RenderAssets<Image>>,
+    placeholder: Res<PlaceholderAttachment>,
+    gpu_node_atlases: Res<TerrainComponents<GpuNodeAtlas>>,
     mut terrain_data: ResMut<TerrainComponents<TerrainData>>,
-    terrain_query: Extract<Query<(Entity, &TerrainConfig), Added<Terrain>>>,
+    terrain_query: Extract<
+        Query<
+            (Entity, &TerrainConfig),
+            (With<Terrain>, Or<(Added<Terrain>, Changed<TerrainConfig>)>),
+        >,
+    >,
 ) {
     for (terrain, config) in terrain_query.iter() {
-        terrain_data.insert(terrain, TerrainData::new(&device, &images, config));
+        let gpu_node_atlas = gpu_node_atlases.get(&terrain).unwrap();
+
+        terrain_data.insert(
+            terrain,
+            TerrainData::new(&device, &images, config, gpu_node_atlas, &placeholder),
+        );
     }
 }
 
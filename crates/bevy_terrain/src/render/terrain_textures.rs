@@ -0,0 +1,159 @@
+//! The interface for render passes of other plugins (e.g. grass, water or volumetric clouds),
+//! which sample the node atlas of a terrain.
+//!
+//! The [`TerrainBindGroupLayout`] contains the layouts of the terrain view and terrain bind
+//! groups, which these pipelines can include at the group indices one and two.
+//! The [`TerrainTextures`] are refreshed during the extraction of each frame and contain the
+//! bind groups, as well as the texture views of the attachments and quadtrees, for custom
+//! bind groups.
+//!
+//! The `bevy_terrain::atlas_bindings` and `bevy_terrain::atlas` shader imports declare the
+//! matching bindings and the functions to look up and sample the terrain in WGSL.
+
+use crate::{
+    render::{
+        downlevel::TerrainRenderMode,
+        terrain_data::{terrain_bind_group_layout, TerrainData},
+        terrain_view_data::{terrain_view_layout, TerrainViewData},
+    },
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_view::{TerrainViewComponents, TerrainViewConfig},
+};
+use bevy::{
+    prelude::*,
+    render::{render_asset::RenderAssets, render_resource::*, renderer::RenderDevice, Extract},
+    utils::HashMap,
+};
+
+/// The layouts of the bind groups of the terrain, which are shared by all terrains and views.
+#[derive(Clone, Resource)]
+pub struct TerrainBindGroupLayout {
+    /// The layout of the terrain view bind group, which contains the view config (binding zero)
+    /// and the quadtree (binding one).
+    pub terrain_view: BindGroupLayout,
+    /// The layout of the terrain bind group, which contains the config (binding zero),
+    /// the atlas sampler (binding one), the attachments (starting at binding two) and the
+    /// samplers of the attachments (starting at
+    /// [`ATTACHMENT_SAMPLER_BINDING`](crate::render::terrain_data::ATTACHMENT_SAMPLER_BINDING)).
+    pub terrain: BindGroupLayout,
+}
+
+impl FromWorld for TerrainBindGroupLayout {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let mode = *world.resource::<TerrainRenderMode>();
+
+        Self {
+            terrain_view: terrain_view_layout(device, mode),
+            terrain: terrain_bind_group_layout(device),
+        }
+    }
+}
+
+/// The node atlas of a terrain in the current frame.
+#[derive(Clone)]
+pub struct TerrainAtlasTextures {
+    /// The names and texture views of the attachments in the order of the terrain config.
+    /// The first attachment stores the height.
+    pub attachments: Vec<(String, TextureView)>,
+    /// The terrain bind group, which matches the [`TerrainBindGroupLayout::terrain`].
+    pub bind_group: BindGroup,
+}
+
+impl TerrainAtlasTextures {
+    /// Returns the texture view of the height attachment.
+    pub fn height_atlas(&self) -> Option<&TextureView> {
+        self.attachments.first().map(|(_, view)| view)
+    }
+
+    /// Returns the texture view of the attachment with the name.
+    pub fn attachment(&self, name: &str) -> Option<&TextureView> {
+        self.attachments
+            .iter()
+            .find(|(attachment, _)| attachment == name)
+            .map(|(_, view)| view)
+    }
+}
+
+/// The node lookup table (quadtree) of a terrain view in the current frame.
+#[derive(Clone)]
+pub struct TerrainViewTextures {
+    /// The texture view of the quadtree, which maps the nodes around the viewer to their
+    /// atlas indices.
+    pub quadtree: TextureView,
+    /// The terrain view bind group, which matches the [`TerrainBindGroupLayout::terrain_view`].
+    pub bind_group: BindGroup,
+}
+
+/// The textures and bind groups of all terrains and terrain views in the current frame.
+///
+/// The bind groups are recreated, whenever the attachments of a terrain change, thus they should
+/// be looked up each frame, instead of being stored by other plugins.
+#[derive(Default, Resource)]
+pub struct TerrainTextures {
+    terrains: HashMap<Entity, TerrainAtlasTextures>,
+    views: HashMap<(Entity, Entity), TerrainViewTextures>,
+}
+
+impl TerrainTextures {
+    /// Returns the node atlas of the terrain.
+    pub fn terrain(&self, terrain: Entity) -> Option<&TerrainAtlasTextures> {
+        self.terrains.get(&terrain)
+    }
+
+    /// Returns the quadtree of the terrain for the view.
+    pub fn view(&self, terrain: Entity, view: Entity) -> Option<&TerrainViewTextures> {
+        self.views.get(&(terrain, view))
+    }
+}
+
+/// Collects the [`TerrainTextures`] of this frame, after the terrain and terrain view data
+/// has been initialized.
+pub(crate) fn extract_terrain_textures(
+    images: Res<RenderAssets<Image>>,
+    terrain_data: Res<TerrainComponents<TerrainData>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    mut terrain_textures: ResMut<TerrainTextures>,
+    terrain_query: Extract<Query<(Entity, &TerrainConfig), With<Terrain>>>,
+    view_configs: Extract<Res<TerrainViewComponents<TerrainViewConfig>>>,
+) {
+    terrain_textures.terrains.clear();
+    terrain_textures.views.clear();
+
+    'terrains: for (terrain, config) in terrain_query.iter() {
+        let data = skip_none!(terrain_data.get(&terrain));
+        let mut attachments = Vec::with_capacity(config.attachments.len());
+
+        for attachment in &config.attachments {
+            // the attachments of new terrains are available from the next frame on
+            let image = match images.get(&attachment.handle) {
+                Some(image) => image,
+                None => continue 'terrains,
+            };
+
+            attachments.push((attachment.name.clone(), image.texture_view.clone()));
+        }
+
+        terrain_textures.terrains.insert(
+            terrain,
+            TerrainAtlasTextures {
+                attachments,
+                bind_group: data.terrain_bind_group.clone(),
+            },
+        );
+    }
+
+    for (&(terrain, view), view_config) in &view_configs.0 {
+        let data = skip_none!(terrain_view_data.get(&(terrain, view)));
+        let quadtree = skip_none!(images.get(&view_config.quadtree_handle));
+
+        terrain_textures.views.insert(
+            (terrain, view),
+            TerrainViewTextures {
+                quadtree: quadtree.texture_view.clone(),
+                bind_group: data.terrain_view_bind_group.clone(),
+            },
+        );
+    }
+}
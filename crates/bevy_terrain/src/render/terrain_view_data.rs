@@ -1,10 +1,24 @@
 use crate::{
+    far_field::TerrainFarField,
     render::{
-        INDIRECT_BUFFER_SIZE, PARAMETER_BUFFER_SIZE, PREPARE_INDIRECT_LAYOUT, REFINE_TILES_LAYOUT,
+        atmosphere::GpuTerrainAtmosphere,
+        color_ramp::GpuColorRamp,
+        decals::{GpuDecalAtlas, DECAL_CLUSTER_COUNT, MAX_DECALS, MAX_DECAL_INDICES},
+        downlevel::TerrainRenderMode,
+        tint::GpuTerrainTint,
+        viewshed::GpuTerrainViewshed,
+        weather::GpuTerrainWeather,
+        DECAL_CLUSTER_SIZE, DECAL_INDEX_SIZE, DECAL_SIZE, INDIRECT_BUFFER_SIZE,
+        PARAMETER_BUFFER_SIZE, PREPARE_INDIRECT_LAYOUT, REFINE_TILES_LAYOUT,
         TERRAIN_VIEW_CONFIG_SIZE, TERRAIN_VIEW_LAYOUT, TILE_SIZE,
     },
-    terrain::{Terrain, TerrainConfig},
-    terrain_view::{TerrainView, TerrainViewConfig},
+    skip_none,
+    terrain::{BorderSkirt, CoverageFill, TerrainComponents, TerrainConfig},
+    terrain_data::gpu_virtual_texture::GpuVirtualTexture,
+    terrain_view::{
+        root_primary_view, DependentRefinement, DependentTerrainView, PatchTopology,
+        TerrainViewConfig,
+    },
     TerrainViewComponents,
 };
 use bevy::{
@@ -17,27 +31,66 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
         Extract,
     },
+    utils::HashMap,
 };
+use bytemuck::cast_slice;
 
 #[derive(Clone, Default, ShaderType)]
 pub(crate) struct TerrainViewConfigUniform {
-    height_under_viewer: f32,
+    pub(crate) height_under_viewer: f32,
     node_count: u32,
-    tile_count: u32,
+    pub(crate) tile_count: u32,
     pub(crate) refinement_count: u32,
-    tile_scale: f32,
-    grid_size: f32,
+    pub(crate) tile_scale: f32,
+    pub(crate) grid_size: f32,
     vertices_per_row: u32,
-    vertices_per_tile: u32,
-    morph_distance: f32,
+    pub(crate) vertices_per_tile: u32,
+    pub(crate) morph_distance: f32,
     blend_distance: f32,
     morph_range: f32,
     blend_range: f32,
+    pub(crate) min_triangle_size: f32,
+    sun_azimuth: f32,
+    sun_elevation: f32,
+    difference_range: f32,
+    pub(crate) patch_topology: u32,
+    /// Whether the area outside of the coverage is filled (1) or skipped (0).
+    coverage_fill: u32,
+    fill_height: f32,
+    elevation_offset: f32,
+    /// Whether the seabed is rendered below the sea level (1) or not (0).
+    bathymetry_mode: u32,
+    sea_level: f32,
+    /// Whether the terrain has a far field (1), which is rendered beyond the quadtree, or not (0).
+    far_field: u32,
+    /// Whether the border of the terrain is closed by walls (1) or not (0).
+    border_skirt: u32,
+    /// The height of the bottom of the border walls.
+    skirt_height: f32,
+    pub(crate) viewer_position: Vec4,
+    skirt_color: Vec4,
 }
 
 impl TerrainViewConfigUniform {
-    fn new(config: &TerrainConfig, view_config: &TerrainViewConfig) -> Self {
+    pub(crate) fn new(
+        config: &TerrainConfig,
+        view_config: &TerrainViewConfig,
+        far_field: bool,
+    ) -> Self {
         let view_distance = view_config.view_distance * config.leaf_node_size as f32;
+        let grid_size = view_config.grid_size;
+
+        // the strip counts all vertices including the degenerate ones,
+        // while the indexed strip counts the indices of a single tile including the restarts
+        let vertices_per_tile = match view_config.patch_topology {
+            PatchTopology::Strip => 2 * grid_size * (grid_size + 2),
+            PatchTopology::IndexedStrip => grid_size * (2 * (grid_size + 1) + 1),
+        };
+
+        let (skirt_height, skirt_color) = match config.border_skirt {
+            BorderSkirt::None => (0.0, Color::NONE),
+            BorderSkirt::Wall { depth, color } => (config.elevation_offset() - depth, color),
+        };
 
         TerrainViewConfigUniform {
             height_under_viewer: view_config.height_under_viewer,
@@ -45,99 +98,409 @@ impl TerrainViewConfigUniform {
             tile_count: view_config.tile_count,
             refinement_count: view_config.refinement_count,
             tile_scale: view_config.tile_scale,
-            grid_size: view_config.grid_size as f32,
-            vertices_per_row: 2 * (view_config.grid_size + 2),
-            vertices_per_tile: 2 * view_config.grid_size * (view_config.grid_size + 2),
+            grid_size: grid_size as f32,
+            vertices_per_row: 2 * (grid_size + 2),
+            vertices_per_tile,
             morph_distance: view_distance
                 / 2.0_f32.powf(view_config.additional_refinement as f32 + 1.0),
             blend_distance: view_distance,
             morph_range: view_config.morph_range,
             blend_range: view_config.blend_range,
+            min_triangle_size: view_config.min_triangle_size,
+            sun_azimuth: view_config.sun_azimuth.to_radians(),
+            sun_elevation: view_config.sun_elevation.to_radians(),
+            difference_range: view_config.difference_range,
+            patch_topology: view_config.patch_topology as u32,
+            coverage_fill: matches!(config.coverage_fill, CoverageFill::Fill { .. }) as u32,
+            fill_height: match config.coverage_fill {
+                CoverageFill::Skip => 0.0,
+                CoverageFill::Fill { height } => height,
+            },
+            elevation_offset: config.elevation_offset(),
+            bathymetry_mode: view_config.bathymetry_mode as u32,
+            sea_level: config.sea_level,
+            far_field: far_field as u32,
+            border_skirt: matches!(config.border_skirt, BorderSkirt::Wall { .. }) as u32,
+            skirt_height,
+            viewer_position: view_config.viewer_position.extend(1.0),
+            skirt_color: skirt_color.as_linear_rgba_f32().into(),
+        }
+    }
+
+    pub(crate) fn is_indexed(&self) -> bool {
+        self.patch_topology == PatchTopology::IndexedStrip as u32
+    }
+
+    /// Returns the arguments of the indirect draw call, which draws the tiles.
+    pub(crate) fn draw_arguments(&self, tile_count: u32) -> [u32; 4] {
+        if self.is_indexed() {
+            [self.vertices_per_tile, tile_count, 0, 0]
+        } else {
+            [tile_count * self.vertices_per_tile, 1, 0, 0]
+        }
+    }
+}
+
+/// The maximum amount of terrain views, whose draw commands are stored in the shared draw command buffer.
+const MAX_DRAW_COMMANDS: u32 = 64;
+/// The distance between the draw commands of two views, which satisfies the
+/// minimum storage buffer offset alignment.
+const DRAW_COMMAND_STRIDE: BufferAddress = 256;
+
+/// The render resources, which do not depend on a single terrain view and are thus
+/// shared by all terrains and views.
+///
+/// Since the views are refined one after another, they can all use the same scratch buffers.
+/// The indirect draw commands written by the refinement of all views are stored in a single buffer.
+/// Each terrain still binds its own node atlas and material, so the commands are drawn one by one.
+#[derive(Default, Resource)]
+pub(crate) struct SharedTerrainViewResources {
+    /// The index buffers of a single tile keyed by their grid size.
+    index_buffers: HashMap<u32, Buffer>,
+    /// The temporary tile buffers used during refinement keyed by their tile count.
+    temporary_tile_buffers: HashMap<u32, Buffer>,
+    parameter_buffer: Option<Buffer>,
+    draw_command_buffer: Option<Buffer>,
+    free_draw_commands: Vec<u32>,
+    draw_command_count: u32,
+}
+
+impl SharedTerrainViewResources {
+    fn index_buffer(&mut self, device: &RenderDevice, grid_size: u32) -> Buffer {
+        self.index_buffers
+            .entry(grid_size)
+            .or_insert_with(|| TerrainViewData::create_index_buffer(device, grid_size))
+            .clone()
+    }
+
+    fn temporary_tile_buffer(&mut self, device: &RenderDevice, tile_count: u32) -> Buffer {
+        self.temporary_tile_buffers
+            .entry(tile_count)
+            .or_insert_with(|| TerrainViewData::create_tile_buffer(device, tile_count))
+            .clone()
+    }
+
+    fn parameter_buffer(&mut self, device: &RenderDevice) -> Buffer {
+        self.parameter_buffer
+            .get_or_insert_with(|| TerrainViewData::create_parameter_buffer(device))
+            .clone()
+    }
+
+    /// Allocates a slot inside the draw command buffer and returns the buffer and its offset.
+    /// Once all slots are taken, a separate buffer is created and a warning is logged.
+    fn draw_command(&mut self, device: &RenderDevice) -> (Buffer, Option<u32>) {
+        let slot = self.free_draw_commands.pop().or_else(|| {
+            (self.draw_command_count < MAX_DRAW_COMMANDS).then(|| {
+                self.draw_command_count += 1;
+                self.draw_command_count - 1
+            })
+        });
+
+        match slot {
+            Some(slot) => {
+                let buffer = self
+                    .draw_command_buffer
+                    .get_or_insert_with(|| {
+                        TerrainViewData::create_indirect_buffer(
+                            device,
+                            DRAW_COMMAND_STRIDE * MAX_DRAW_COMMANDS as BufferAddress,
+                        )
+                    })
+                    .clone();
+
+                (buffer, Some(slot))
+            }
+            None => {
+                warn!(
+                    "All {MAX_DRAW_COMMANDS} shared draw commands are in use, thus the terrain view uses its own indirect buffer."
+                );
+
+                (
+                    TerrainViewData::create_indirect_buffer(device, INDIRECT_BUFFER_SIZE),
+                    None,
+                )
+            }
+        }
+    }
+
+    fn free_draw_command(&mut self, slot: Option<u32>) {
+        self.free_draw_commands.extend(slot);
+    }
+
+    /// Releases the buffers, which are no longer used by any view.
+    fn retain(&mut self, terrain_view_data: &TerrainViewComponents<TerrainViewData>) {
+        self.index_buffers.retain(|&grid_size, _| {
+            terrain_view_data
+                .0
+                .values()
+                .any(|data| data.index_grid_size == grid_size)
+        });
+        self.temporary_tile_buffers.retain(|&tile_count, _| {
+            terrain_view_data
+                .0
+                .values()
+                .any(|data| data.tile_count == tile_count)
+        });
+
+        if terrain_view_data.0.is_empty() {
+            self.parameter_buffer = None;
         }
     }
 }
 
+/// Creates the layout of the terrain view bind group, which omits the storage buffers in
+/// [`TerrainRenderMode::Downlevel`].
+pub(crate) fn terrain_view_layout(
+    device: &RenderDevice,
+    mode: TerrainRenderMode,
+) -> BindGroupLayout {
+    let entries: Vec<BindGroupLayoutEntry> = TERRAIN_VIEW_LAYOUT
+        .entries
+        .iter()
+        .filter(|entry| mode.supports_binding(entry.binding))
+        .copied()
+        .collect();
+
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: TERRAIN_VIEW_LAYOUT.label,
+        entries: &entries,
+    })
+}
+
 pub struct TerrainViewData {
     pub(crate) indirect_buffer: Buffer,
+    /// The offset of the draw command of this view inside the indirect buffer.
+    pub(crate) indirect_offset: BufferAddress,
+    draw_command_slot: Option<u32>,
+    pub(crate) final_tile_buffer: Buffer,
     pub(crate) view_config_buffer: Buffer,
-    pub(crate) prepare_indirect_bind_group: BindGroup,
-    pub(crate) refine_tiles_bind_group: BindGroup,
+    /// The bind groups of the refinement, which are only available in [`TerrainRenderMode::Compute`].
+    /// The refinement bind group of a dependent view reads the final tiles of its primary view
+    /// as its temporary tiles, which are then culled into its own final tiles.
+    pub(crate) prepare_indirect_bind_group: Option<BindGroup>,
+    pub(crate) refine_tiles_bind_group: Option<BindGroup>,
+    /// The primary view, whose tiles are culled instead of refining the quadtree,
+    /// if this is a [`DependentTerrainView`] in [`TerrainRenderMode::Compute`].
+    pub(crate) primary: Option<Entity>,
     pub(crate) terrain_view_bind_group: BindGroup,
+    pub(crate) decal_buffer: Buffer,
+    pub(crate) decal_cluster_buffer: Buffer,
+    pub(crate) decal_index_buffer: Buffer,
+    pub(crate) index_buffer: Buffer,
+    /// The grid size, the index buffer has been generated for.
+    index_grid_size: u32,
+    tile_count: u32,
+    indexed: bool,
+    vertices_per_tile: u32,
+    /// The amount of tiles refined on the CPU, if the view is rendered in [`TerrainRenderMode::Downlevel`].
+    downlevel_tile_count: Option<u32>,
 }
 
 impl TerrainViewData {
     fn new(
         device: &RenderDevice,
+        mode: TerrainRenderMode,
+        shared: &mut SharedTerrainViewResources,
         images: &RenderAssets<Image>,
+        decal_atlas: &GpuDecalAtlas,
+        color_ramp: &GpuColorRamp,
+        atmosphere: &GpuTerrainAtmosphere,
+        tint: &GpuTerrainTint,
+        weather: &GpuTerrainWeather,
+        viewshed: &GpuTerrainViewshed,
+        virtual_texture: &GpuVirtualTexture,
         view_config: &TerrainViewConfig,
+        primary: Option<(Entity, &TerrainViewData)>,
     ) -> Self {
-        let indirect_buffer = Self::create_indirect_buffer(device);
-        let view_config_buffer = Self::create_view_config_buffer(device);
-        let parameter_buffer = Self::create_parameter_buffer(device);
-        let (temporary_tile_buffer, final_tile_buffer) =
-            Self::create_tile_buffers(device, view_config);
+        let (indirect_buffer, draw_command_slot) = shared.draw_command(device);
+        let indirect_offset =
+            draw_command_slot.map_or(0, |slot| slot as BufferAddress * DRAW_COMMAND_STRIDE);
+        let final_tile_buffer = Self::create_tile_buffer(device, view_config.tile_count);
+
+        // dependent views share the view config and the decals with their primary view,
+        // since the view configs are copied and the decals are the same for all views
+        let (view_config_buffer, decal_buffer, decal_cluster_buffer, decal_index_buffer) =
+            match primary {
+                Some((_, primary)) => (
+                    primary.view_config_buffer.clone(),
+                    primary.decal_buffer.clone(),
+                    primary.decal_cluster_buffer.clone(),
+                    primary.decal_index_buffer.clone(),
+                ),
+                None => {
+                    let (decal_buffer, decal_cluster_buffer, decal_index_buffer) =
+                        Self::create_decal_buffers(device);
+
+                    (
+                        Self::create_view_config_buffer(device),
+                        decal_buffer,
+                        decal_cluster_buffer,
+                        decal_index_buffer,
+                    )
+                }
+            };
+
+        let index_buffer = shared.index_buffer(device, view_config.grid_size);
 
         let quadtree = images.get(&view_config.quadtree_handle).unwrap();
 
-        let prepare_indirect_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: "prepare_indirect_bind_group".into(),
-            entries: &[BindGroupEntry {
+        // the refinement is performed on the CPU in downlevel mode
+        let (prepare_indirect_bind_group, refine_tiles_bind_group) = match mode {
+            TerrainRenderMode::Compute => {
+                let parameter_buffer = shared.parameter_buffer(device);
+                let temporary_tile_buffer = match primary {
+                    Some((_, primary)) => primary.final_tile_buffer.clone(),
+                    None => shared.temporary_tile_buffer(device, view_config.tile_count),
+                };
+
+                let prepare_indirect_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: "prepare_indirect_bind_group".into(),
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: &indirect_buffer,
+                            offset: indirect_offset,
+                            size: BufferSize::new(INDIRECT_BUFFER_SIZE),
+                        }),
+                    }],
+                    layout: &device.create_bind_group_layout(&PREPARE_INDIRECT_LAYOUT),
+                });
+                let refine_tiles_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: "refine_tiles_bind_group".into(),
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: view_config_buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::TextureView(&quadtree.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: final_tile_buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: temporary_tile_buffer.as_entire_binding(),
+                        },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: parameter_buffer.as_entire_binding(),
+                        },
+                    ],
+                    layout: &device.create_bind_group_layout(&REFINE_TILES_LAYOUT),
+                });
+
+                (
+                    Some(prepare_indirect_bind_group),
+                    Some(refine_tiles_bind_group),
+                )
+            }
+            TerrainRenderMode::Downlevel => (None, None),
+        };
+
+        let mut entries = vec![
+            BindGroupEntry {
                 binding: 0,
-                resource: indirect_buffer.as_entire_binding(),
-            }],
-            layout: &device.create_bind_group_layout(&PREPARE_INDIRECT_LAYOUT),
-        });
-        let refine_tiles_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: "refine_tiles_bind_group".into(),
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: view_config_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&quadtree.texture_view),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: final_tile_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: temporary_tile_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 4,
-                    resource: parameter_buffer.as_entire_binding(),
-                },
-            ],
-            layout: &device.create_bind_group_layout(&REFINE_TILES_LAYOUT),
-        });
+                resource: view_config_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&quadtree.texture_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: final_tile_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: decal_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: decal_cluster_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: decal_index_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(&decal_atlas.texture_view),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::Sampler(&decal_atlas.sampler),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::TextureView(&color_ramp.texture_view),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: BindingResource::Sampler(&color_ramp.sampler),
+            },
+            BindGroupEntry {
+                binding: 10,
+                resource: atmosphere.buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 11,
+                resource: BindingResource::TextureView(&tint.texture_view),
+            },
+            BindGroupEntry {
+                binding: 12,
+                resource: BindingResource::TextureView(&weather.texture_view),
+            },
+            BindGroupEntry {
+                binding: 13,
+                resource: BindingResource::TextureView(&viewshed.texture_view),
+            },
+            BindGroupEntry {
+                binding: 14,
+                resource: virtual_texture.uniform_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 15,
+                resource: BindingResource::TextureView(&virtual_texture.page_table_view),
+            },
+            BindGroupEntry {
+                binding: 16,
+                resource: BindingResource::TextureView(&virtual_texture.page_cache_view),
+            },
+            BindGroupEntry {
+                binding: 17,
+                resource: virtual_texture.feedback_buffer.as_entire_binding(),
+            },
+        ];
+        entries.retain(|entry| mode.supports_binding(entry.binding));
+
         let terrain_view_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: "terrain_view_bind_group".into(),
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: view_config_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(&quadtree.texture_view),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: final_tile_buffer.as_entire_binding(),
-                },
-            ],
-            layout: &device.create_bind_group_layout(&TERRAIN_VIEW_LAYOUT),
+            entries: &entries,
+            layout: &terrain_view_layout(device, mode),
         });
 
         Self {
             indirect_buffer,
+            indirect_offset,
+            draw_command_slot,
+            final_tile_buffer,
             view_config_buffer,
             prepare_indirect_bind_group,
             refine_tiles_bind_group,
+            primary: primary.map(|(primary, _)| primary),
             terrain_view_bind_group,
+            decal_buffer,
+            decal_cluster_buffer,
+            decal_index_buffer,
+            index_buffer,
+            index_grid_size: view_config.grid_size,
+            tile_count: view_config.tile_count,
+            indexed: view_config.patch_topology == PatchTopology::IndexedStrip,
+            vertices_per_tile: 0,
+            downlevel_tile_count: (mode == TerrainRenderMode::Downlevel).then_some(0),
         }
     }
 
@@ -150,13 +513,42 @@ impl TerrainViewData {
         })
     }
 
-    fn create_indirect_buffer(device: &RenderDevice) -> Buffer {
+    fn create_indirect_buffer(device: &RenderDevice, size: BufferAddress) -> Buffer {
         device.create_buffer_with_data(&BufferInitDescriptor {
             label: "indirect_buffer".into(),
-            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
-            contents: &[0; INDIRECT_BUFFER_SIZE as usize],
+            usage: BufferUsages::STORAGE
+                | BufferUsages::INDIRECT
+                | BufferUsages::COPY_SRC
+                | BufferUsages::COPY_DST,
+            contents: &vec![0; size as usize],
+        })
+    }
+
+    /// Creates the indices of a single tile, which form one triangle strip per column of the grid.
+    /// The strips are separated by primitive restarts.
+    fn create_index_buffer(device: &RenderDevice, grid_size: u32) -> Buffer {
+        let vertices_per_row = grid_size + 1;
+
+        let indices = (0..grid_size)
+            .flat_map(|column| {
+                (0..=grid_size)
+                    .flat_map(move |row| {
+                        [
+                            row * vertices_per_row + column,
+                            row * vertices_per_row + column + 1,
+                        ]
+                    })
+                    .chain([u32::MAX])
+            })
+            .collect::<Vec<_>>();
+
+        device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "index_buffer".into(),
+            usage: BufferUsages::INDEX,
+            contents: cast_slice(&indices),
         })
     }
+
     fn create_parameter_buffer(device: &RenderDevice) -> Buffer {
         device.create_buffer(&BufferDescriptor {
             label: "parameter_buffer".into(),
@@ -166,77 +558,185 @@ impl TerrainViewData {
         })
     }
 
-    fn create_tile_buffers(
-        device: &RenderDevice,
-        view_config: &TerrainViewConfig,
-    ) -> (Buffer, Buffer) {
-        let buffer_descriptor = BufferDescriptor {
+    fn create_tile_buffer(device: &RenderDevice, tile_count: u32) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
             label: "tile_buffer".into(),
-            size: TILE_SIZE * view_config.tile_count as BufferAddress, // Todo: figure out a better tile buffer size limit
-            usage: BufferUsages::STORAGE,
+            size: TILE_SIZE * tile_count as BufferAddress, // Todo: figure out a better tile buffer size limit
+            // the final tiles are bound as instances in downlevel mode
+            usage: BufferUsages::STORAGE
+                | BufferUsages::VERTEX
+                | BufferUsages::COPY_SRC
+                | BufferUsages::COPY_DST,
             mapped_at_creation: false,
+        })
+    }
+
+    fn create_decal_buffers(device: &RenderDevice) -> (Buffer, Buffer, Buffer) {
+        let create_buffer = |label: &str, size: BufferAddress| {
+            device.create_buffer(&BufferDescriptor {
+                label: label.into(),
+                size,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
         };
 
         (
-            device.create_buffer(&buffer_descriptor),
-            device.create_buffer(&buffer_descriptor),
+            create_buffer("decal_buffer", DECAL_SIZE * MAX_DECALS as BufferAddress),
+            create_buffer(
+                "decal_cluster_buffer",
+                DECAL_CLUSTER_SIZE * (DECAL_CLUSTER_COUNT * DECAL_CLUSTER_COUNT) as BufferAddress,
+            ),
+            create_buffer(
+                "decal_index_buffer",
+                DECAL_INDEX_SIZE * MAX_DECAL_INDICES as BufferAddress,
+            ),
         )
     }
 
     pub(crate) fn update(
-        &self,
+        &mut self,
+        device: &RenderDevice,
         queue: &RenderQueue,
+        shared: &mut SharedTerrainViewResources,
         view_config_uniform: &TerrainViewConfigUniform,
     ) {
+        let grid_size = view_config_uniform.grid_size as u32;
+
+        if self.index_grid_size != grid_size {
+            self.index_buffer = shared.index_buffer(device, grid_size);
+            self.index_grid_size = grid_size;
+        }
+
+        self.indexed = view_config_uniform.is_indexed();
+        self.vertices_per_tile = view_config_uniform.vertices_per_tile;
+
         let mut buffer = encase::UniformBuffer::new(Vec::new());
         buffer.write(view_config_uniform).unwrap();
         queue.write_buffer(&self.view_config_buffer, 0, &buffer.into_inner());
     }
+
+    /// Uploads the tiles, which have been refined on the CPU in downlevel mode.
+    pub(crate) fn write_downlevel_tiles(&mut self, queue: &RenderQueue, tiles: &[[u32; 4]]) {
+        if !tiles.is_empty() {
+            queue.write_buffer(&self.final_tile_buffer, 0, cast_slice(tiles));
+        }
+
+        self.downlevel_tile_count = Some(tiles.len() as u32);
+    }
 }
 
+/// Initializes the [`TerrainViewData`] of newly created terrain views
+/// and removes the one of despawned views.
 pub(crate) fn initialize_terrain_view_data(
     device: Res<RenderDevice>,
+    mode: Res<TerrainRenderMode>,
     images: Res<RenderAssets<Image>>,
+    decal_atlas: Res<GpuDecalAtlas>,
+    color_ramp: Res<GpuColorRamp>,
+    atmosphere: Res<GpuTerrainAtmosphere>,
+    tints: Res<TerrainComponents<GpuTerrainTint>>,
+    weathers: Res<TerrainComponents<GpuTerrainWeather>>,
+    viewsheds: Res<TerrainComponents<GpuTerrainViewshed>>,
+    virtual_textures: Res<TerrainComponents<GpuVirtualTexture>>,
+    mut shared: ResMut<SharedTerrainViewResources>,
     mut terrain_view_data: ResMut<TerrainViewComponents<TerrainViewData>>,
     view_configs: Extract<Res<TerrainViewComponents<TerrainViewConfig>>>,
-    view_query: Extract<Query<Entity, With<TerrainView>>>,
-    terrain_query: Extract<Query<Entity, Added<Terrain>>>,
+    dependent_views: Extract<Query<&DependentTerrainView>>,
 ) {
-    for terrain in terrain_query.iter() {
-        for view in view_query.iter() {
-            let view_config = view_configs.get(&(terrain, view)).unwrap();
-
-            terrain_view_data.insert(
-                (terrain, view),
-                TerrainViewData::new(&device, &images, view_config),
-            );
+    terrain_view_data.0.retain(|key, data| {
+        let retain = view_configs.0.contains_key(key);
+
+        if !retain {
+            shared.free_draw_command(data.draw_command_slot);
+        }
+
+        retain
+    });
+
+    for (&(terrain, view), view_config) in &view_configs.0 {
+        let tint = skip_none!(tints.get(&terrain));
+        let weather = skip_none!(weathers.get(&terrain));
+        let viewshed = skip_none!(viewsheds.get(&terrain));
+        let virtual_texture = skip_none!(virtual_textures.get(&terrain));
+
+        if terrain_view_data.get(&(terrain, view)).is_some() {
+            continue;
         }
+
+        // dependent views may cull the tiles of their primary view, which requires a compute device,
+        // otherwise they refine the quadtree of their primary view on their own
+        let primary = match dependent_views.get(view) {
+            Ok(dependency)
+                if *mode == TerrainRenderMode::Compute
+                    && dependency.refinement == DependentRefinement::CullPrimaryTiles =>
+            {
+                // cyclic views have no view configs, thus the chain always ends in a view,
+                // which refines its own tiles
+                let root = skip_none!(root_primary_view(view, |view| {
+                    dependent_views
+                        .get(view)
+                        .ok()
+                        .map(|dependency| dependency.primary)
+                }));
+                let primary = skip_none!(terrain_view_data.get(&(terrain, root)));
+
+                Some((root, primary))
+            }
+            _ => None,
+        };
+
+        let data = TerrainViewData::new(
+            &device,
+            *mode,
+            &mut shared,
+            &images,
+            &decal_atlas,
+            &color_ramp,
+            &atmosphere,
+            tint,
+            weather,
+            viewshed,
+            virtual_texture,
+            view_config,
+            primary,
+        );
+
+        terrain_view_data.insert((terrain, view), data);
     }
 }
 
 pub(crate) fn extract_terrain_view_config(
     mut view_config_uniforms: ResMut<TerrainViewComponents<TerrainViewConfigUniform>>,
-    configs: Extract<Query<&TerrainConfig>>,
+    configs: Extract<Query<(&TerrainConfig, Option<&TerrainFarField>)>>,
     view_configs: Extract<Res<TerrainViewComponents<TerrainViewConfig>>>,
 ) {
+    view_config_uniforms
+        .0
+        .retain(|key, _| view_configs.0.contains_key(key));
+
     for (&(terrain, view), view_config) in &view_configs.0 {
-        let config = configs.get(terrain).unwrap();
+        let (config, far_field) = configs.get(terrain).unwrap();
         view_config_uniforms.insert(
             (terrain, view),
-            TerrainViewConfigUniform::new(config, view_config),
+            TerrainViewConfigUniform::new(config, view_config, far_field.is_some()),
         )
     }
 }
 
 pub(crate) fn queue_terrain_view_config(
+    device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
+    mut shared: ResMut<SharedTerrainViewResources>,
     mut terrain_view_data: ResMut<TerrainViewComponents<TerrainViewData>>,
     view_config_uniforms: Res<TerrainViewComponents<TerrainViewConfigUniform>>,
 ) {
     for (&(terrain, view), data) in &mut terrain_view_data.0 {
         let view_config_uniform = view_config_uniforms.get(&(terrain, view)).unwrap();
-        data.update(&queue, view_config_uniform)
+        data.update(&device, &queue, &mut shared, view_config_uniform)
     }
+
+    shared.retain(&terrain_view_data);
 }
 
 pub struct SetTerrainViewBindGroup<const I: usize>;
@@ -278,7 +778,23 @@ impl EntityRenderCommand for DrawTerrainCommand {
             .get(&(terrain, view))
             .unwrap();
 
-        pass.draw_indirect(&data.indirect_buffer, 0);
+        if data.indexed {
+            pass.set_index_buffer(data.index_buffer.slice(..), 0, IndexFormat::Uint32);
+        }
+
+        match (data.downlevel_tile_count, data.indexed) {
+            // each tile refined on the CPU is drawn as an instance
+            (Some(tile_count), true) => {
+                pass.set_vertex_buffer(0, data.final_tile_buffer.slice(..));
+                pass.draw_indexed(0..data.vertices_per_tile, 0, 0..tile_count);
+            }
+            (Some(tile_count), false) => {
+                pass.set_vertex_buffer(0, data.final_tile_buffer.slice(..));
+                pass.draw(0..data.vertices_per_tile, 0..tile_count);
+            }
+            (None, true) => pass.draw_indexed_indirect(&data.indirect_buffer, data.indirect_offset),
+            (None, false) => pass.draw_indirect(&data.indirect_buffer, data.indirect_offset),
+        }
         RenderCommandResult::Success
     }
 }
@@ -0,0 +1,218 @@
+//! A per terrain tint, which colors regions of the terrain (e.g. the territories of the players
+//! in a strategy game or the biomes while debugging).
+//!
+//! The tint is a grid of colors covering the whole terrain, which can be modified from the CPU
+//! every frame. Each cell either corresponds to a node of a certain lod or to an arbitrary
+//! patch of the terrain. The grid is uploaded into a small texture, whenever the [`TerrainTint`]
+//! changes, and can be read in the fragment shader via the `terrain_tint` function of the
+//! `bevy_terrain::tint` shader import.
+
+use crate::{
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_data::NodeCoordinate,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use std::num::NonZeroU32;
+
+/// Tints the cells of a terrain with a color, whose alpha is the strength of the tint.
+///
+/// Add this component to a terrain entity, when spawning it.
+/// The resolution can not be changed afterwards.
+#[derive(Clone, Component)]
+pub struct TerrainTint {
+    resolution: u32,
+    colors: Vec<Color>,
+}
+
+impl TerrainTint {
+    /// Creates a transparent tint with `resolution` x `resolution` cells.
+    pub fn new(resolution: u32) -> Self {
+        Self {
+            resolution,
+            colors: vec![Color::NONE; (resolution * resolution) as usize],
+        }
+    }
+
+    /// Creates a transparent tint with one cell per node of the lod.
+    pub fn per_node(config: &TerrainConfig, lod: u32) -> Self {
+        Self::new((config.terrain_size / (config.leaf_node_size << lod)).max(1))
+    }
+
+    /// Returns the number of cells per side.
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Returns the color of the cell.
+    pub fn get(&self, cell: UVec2) -> Color {
+        self.colors[(cell.y * self.resolution + cell.x) as usize]
+    }
+
+    /// Sets the color of the cell.
+    pub fn set(&mut self, cell: UVec2, color: Color) {
+        self.colors[(cell.y * self.resolution + cell.x) as usize] = color;
+    }
+
+    /// Sets the color of all cells.
+    pub fn fill(&mut self, color: Color) {
+        self.colors.fill(color);
+    }
+
+    /// Sets the color of all cells covered by the node.
+    pub fn set_node(&mut self, config: &TerrainConfig, node: &NodeCoordinate, color: Color) {
+        let node_size = config.leaf_node_size as f64 * (1u64 << node.lod) as f64;
+        let cell_size = config.terrain_size as f64 / self.resolution as f64;
+
+        let start = (UVec2::new(node.x, node.y).as_dvec2() * node_size / cell_size).as_uvec2();
+        let end = ((UVec2::new(node.x + 1, node.y + 1).as_dvec2() * node_size / cell_size)
+            .ceil()
+            .as_uvec2())
+        .min(UVec2::splat(self.resolution));
+
+        for y in start.y..end.y {
+            for x in start.x..end.x {
+                self.set(UVec2::new(x, y), color);
+            }
+        }
+    }
+
+    /// Sets the color of the cell containing the horizontal position (x, z) of the terrain.
+    pub fn set_at(&mut self, config: &TerrainConfig, position: Vec2, color: Color) {
+        let cell = position / config.terrain_size as f32 * self.resolution as f32;
+
+        if cell.cmpge(Vec2::ZERO).all() && cell.cmplt(Vec2::splat(self.resolution as f32)).all() {
+            self.set(cell.as_uvec2(), color);
+        }
+    }
+
+    fn texels(&self) -> Vec<u8> {
+        self.colors
+            .iter()
+            .flat_map(|color| {
+                color
+                    .as_rgba_f32()
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    }
+}
+
+/// Stores the texture of the [`TerrainTint`] of a terrain.
+pub struct GpuTerrainTint {
+    pub(crate) texture_view: TextureView,
+    texture: Texture,
+    resolution: u32,
+    /// The texels, which have been extracted this frame, but not yet written into the texture.
+    pending_texels: Option<Vec<u8>>,
+}
+
+impl GpuTerrainTint {
+    fn new(device: &RenderDevice, tint: Option<&TerrainTint>) -> Self {
+        // terrains without a tint are covered by a single transparent cell
+        let resolution = tint.map_or(1, |tint| tint.resolution);
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: "terrain_tint".into(),
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8UnormSrgb,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let texture_view = texture.create_view(&default());
+
+        Self {
+            texture_view,
+            texture,
+            resolution,
+            pending_texels: Some(tint.map_or_else(|| vec![0; 4], TerrainTint::texels)),
+        }
+    }
+}
+
+/// Initializes the [`GpuTerrainTint`] of newly created terrains and updates the changed ones.
+pub(crate) fn extract_terrain_tint(
+    device: Res<RenderDevice>,
+    mut gpu_tints: ResMut<TerrainComponents<GpuTerrainTint>>,
+    terrain_query: Extract<
+        Query<
+            (
+                Entity,
+                Option<&TerrainTint>,
+                Option<ChangeTrackers<TerrainTint>>,
+            ),
+            With<Terrain>,
+        >,
+    >,
+) {
+    gpu_tints
+        .0
+        .retain(|&terrain, _| terrain_query.contains(terrain));
+
+    for (terrain, tint, tint_trackers) in terrain_query.iter() {
+        if gpu_tints.get(&terrain).is_none() {
+            gpu_tints.insert(terrain, GpuTerrainTint::new(&device, tint));
+            continue;
+        }
+
+        let (tint, tint_trackers) = match (tint, tint_trackers) {
+            (Some(tint), Some(tint_trackers)) => (tint, tint_trackers),
+            _ => continue,
+        };
+
+        if !tint_trackers.is_changed() {
+            continue;
+        }
+
+        let gpu_tint = gpu_tints.get_mut(&terrain).unwrap();
+
+        if tint.resolution != gpu_tint.resolution {
+            warn!("The tint of a terrain has to be added when spawning the terrain and its resolution can not be changed.");
+            continue;
+        }
+
+        gpu_tint.pending_texels = Some(tint.texels());
+    }
+}
+
+pub(crate) fn queue_terrain_tint(
+    queue: Res<RenderQueue>,
+    mut gpu_tints: ResMut<TerrainComponents<GpuTerrainTint>>,
+) {
+    for gpu_tint in gpu_tints.0.values_mut() {
+        if let Some(texels) = gpu_tint.pending_texels.take() {
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &gpu_tint.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                &texels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(gpu_tint.resolution * 4),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width: gpu_tint.resolution,
+                    height: gpu_tint.resolution,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,463 @@
+//! Vector layers, which drape 2D linework (e.g. boundaries, rivers or trails) over the terrain.
+//!
+//! The polylines and polygons are tessellated on the CPU into a flat triangle list in the
+//! horizontal plane of the terrain. The height of each vertex is then sampled from the
+//! height attachment in the vertex shader, so that the layer follows the currently loaded terrain.
+
+use crate::{
+    render::{
+        shaders::VECTOR_LAYER_SHADER,
+        terrain_data::{terrain_bind_group_layout, TerrainData},
+        terrain_view_data::TerrainViewData,
+        TERRAIN_VIEW_LAYOUT,
+    },
+    terrain::TerrainComponents,
+    terrain_view::{TerrainView, TerrainViewComponents},
+};
+use bevy::{
+    core_pipeline::core_3d::Transparent3d,
+    ecs::{
+        query::QueryItem,
+        system::{
+            lifetimeless::{Read, SQuery, SRes},
+            SystemParamItem,
+        },
+    },
+    pbr::{MeshPipeline, SetMeshViewBindGroup},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        extract_component::ExtractComponent,
+        render_asset::{PrepareAssetError, RenderAsset, RenderAssets},
+        render_phase::{
+            DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::BevyDefault,
+    },
+};
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+/// A set of polylines and polygons, which is draped over the terrain.
+///
+/// All coordinates are specified in the horizontal (x, z) plane of the terrain.
+/// Use [`Georeference::to_local`](crate::georeference::Georeference::to_local) to convert
+/// projected coordinates (e.g. from GeoJSON or shapefiles) into this plane.
+#[derive(Clone, TypeUuid)]
+#[uuid = "4b0c2f6e-3a5d-4e1b-9f7a-8d2c6e1b5a93"]
+pub struct TerrainVectorLayer {
+    /// The polylines, which are rendered as strips with the width.
+    pub lines: Vec<Vec<Vec2>>,
+    /// The outer rings of the polygons, which are filled.
+    pub polygons: Vec<Vec<Vec2>>,
+    /// The width of the lines in world units.
+    pub width: f32,
+    /// The color of the lines and polygons, whose alpha scales the opacity.
+    pub color: Color,
+    /// The distance by which the layer is moved towards the viewer to avoid z-fighting.
+    pub depth_offset: f32,
+    /// The maximum edge length of the tessellated triangles.
+    /// Longer edges are subdivided, so that the layer does not cut through the terrain.
+    pub segment_length: f32,
+}
+
+impl Default for TerrainVectorLayer {
+    fn default() -> Self {
+        Self {
+            lines: default(),
+            polygons: default(),
+            width: 1.0,
+            color: Color::WHITE,
+            depth_offset: 0.5,
+            segment_length: 2.0,
+        }
+    }
+}
+
+impl TerrainVectorLayer {
+    /// Tessellates the lines and polygons into a triangle list.
+    fn tessellate(&self) -> Vec<Vec2> {
+        let mut triangles = Vec::new();
+
+        for line in &self.lines {
+            for segment in line.windows(2) {
+                let direction = (segment[1] - segment[0]).normalize_or_zero();
+
+                if direction == Vec2::ZERO {
+                    continue;
+                }
+
+                // extend each segment by half the width, to close the gaps at the joints
+                let extent = direction * self.width / 2.0;
+                let normal = extent.perp();
+                let (start, end) = (segment[0] - extent, segment[1] + extent);
+
+                triangles.extend([
+                    start - normal,
+                    start + normal,
+                    end + normal,
+                    start - normal,
+                    end + normal,
+                    end - normal,
+                ]);
+            }
+        }
+
+        for polygon in &self.polygons {
+            triangles.extend(triangulate_polygon(polygon));
+        }
+
+        triangles
+            .chunks_exact(3)
+            .flat_map(|triangle| {
+                subdivide_triangle([triangle[0], triangle[1], triangle[2]], self.segment_length)
+            })
+            .collect()
+    }
+}
+
+/// Triangulates a simple polygon using ear clipping.
+fn triangulate_polygon(polygon: &[Vec2]) -> Vec<Vec2> {
+    let mut polygon = polygon.to_vec();
+
+    // remove the closing vertex of closed rings
+    if polygon.len() > 1 && polygon.first() == polygon.last() {
+        polygon.pop();
+    }
+
+    // ensure a consistent winding order
+    let area: f32 = (0..polygon.len())
+        .map(|i| polygon[i].perp_dot(polygon[(i + 1) % polygon.len()]))
+        .sum();
+
+    if area < 0.0 {
+        polygon.reverse();
+    }
+
+    let mut indices: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = Vec::new();
+
+    while indices.len() > 3 {
+        let count = indices.len();
+
+        let ear = (0..count).find(|&i| {
+            let a = polygon[indices[(i + count - 1) % count]];
+            let b = polygon[indices[i]];
+            let c = polygon[indices[(i + 1) % count]];
+
+            (b - a).perp_dot(c - b) > 0.0
+                && indices.iter().all(|&j| {
+                    let p = polygon[j];
+                    p == a || p == b || p == c || !point_in_triangle(p, a, b, c)
+                })
+        });
+
+        // the remaining polygon is degenerate
+        let i = match ear {
+            Some(i) => i,
+            None => break,
+        };
+
+        triangles.extend([
+            polygon[indices[(i + count - 1) % count]],
+            polygon[indices[i]],
+            polygon[indices[(i + 1) % count]],
+        ]);
+        indices.remove(i);
+    }
+
+    if indices.len() == 3 {
+        triangles.extend(indices.iter().map(|&i| polygon[i]));
+    }
+
+    triangles
+}
+
+fn point_in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    (b - a).perp_dot(p - a) >= 0.0
+        && (c - b).perp_dot(p - b) >= 0.0
+        && (a - c).perp_dot(p - c) >= 0.0
+}
+
+/// Splits the triangle into four, until none of its edges is longer than the segment length.
+fn subdivide_triangle(triangle: [Vec2; 3], segment_length: f32) -> Vec<Vec2> {
+    let [a, b, c] = triangle;
+    let max_length = a.distance(b).max(b.distance(c)).max(c.distance(a));
+
+    if max_length <= segment_length.max(0.01) {
+        return triangle.to_vec();
+    }
+
+    let (ab, bc, ca) = ((a + b) / 2.0, (b + c) / 2.0, (c + a) / 2.0);
+
+    [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+        .into_iter()
+        .flat_map(|triangle| subdivide_triangle(triangle, segment_length))
+        .collect()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+struct VectorLayerUniform {
+    color: [f32; 4],
+    depth_offset: f32,
+    _padding: [f32; 3],
+}
+
+/// The GPU representation of a [`TerrainVectorLayer`].
+pub struct GpuVectorLayer {
+    vertex_buffer: Buffer,
+    vertex_count: u32,
+    bind_group: BindGroup,
+}
+
+impl RenderAsset for TerrainVectorLayer {
+    type ExtractedAsset = TerrainVectorLayer;
+    type PreparedAsset = GpuVectorLayer;
+    type Param = (SRes<RenderDevice>, SRes<VectorLayerPipeline>);
+
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        self.clone()
+    }
+
+    fn prepare_asset(
+        layer: Self::ExtractedAsset,
+        (device, pipeline): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let vertices = layer.tessellate();
+
+        let vertex_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "vector_layer_vertex_buffer".into(),
+            contents: cast_slice(&vertices),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let uniform = VectorLayerUniform {
+            color: layer.color.as_linear_rgba_f32(),
+            depth_offset: layer.depth_offset,
+            _padding: default(),
+        };
+
+        let uniform_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "vector_layer_uniform_buffer".into(),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "vector_layer_bind_group".into(),
+            layout: &pipeline.layer_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        Ok(GpuVectorLayer {
+            vertex_buffer,
+            vertex_count: vertices.len() as u32,
+            bind_group,
+        })
+    }
+}
+
+/// Drapes the vector layer over the terrain.
+#[derive(Clone, Component)]
+pub struct DrapedVectorLayer {
+    pub layer: Handle<TerrainVectorLayer>,
+    /// The terrain entity the layer is draped over.
+    pub terrain: Entity,
+}
+
+impl ExtractComponent for DrapedVectorLayer {
+    type Query = Read<Self>;
+    type Filter = ();
+
+    #[inline]
+    fn extract_component(item: QueryItem<Self::Query>) -> Self {
+        item.clone()
+    }
+}
+
+/// The pipeline used to render the vector layers.
+#[derive(Resource)]
+pub struct VectorLayerPipeline {
+    view_layout: BindGroupLayout,
+    terrain_view_layout: BindGroupLayout,
+    terrain_layout: BindGroupLayout,
+    layer_layout: BindGroupLayout,
+}
+
+impl FromWorld for VectorLayerPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let mesh_pipeline = world.resource::<MeshPipeline>();
+
+        let layer_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: "vector_layer_layout".into(),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(
+                        std::mem::size_of::<VectorLayerUniform>() as BufferAddress
+                    ),
+                },
+                count: None,
+            }],
+        });
+
+        Self {
+            view_layout: mesh_pipeline.view_layout.clone(),
+            terrain_view_layout: device.create_bind_group_layout(&TERRAIN_VIEW_LAYOUT),
+            terrain_layout: terrain_bind_group_layout(device),
+            layer_layout,
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for VectorLayerPipeline {
+    /// The number of msaa samples.
+    type Key = u32;
+
+    fn specialize(&self, msaa_samples: Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: "vector_layer_pipeline".into(),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.terrain_view_layout.clone(),
+                self.terrain_layout.clone(),
+                self.layer_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: VECTOR_LAYER_SHADER.typed(),
+                entry_point: "vertex".into(),
+                shader_defs: Vec::new(),
+                buffers: vec![VertexBufferLayout::from_vertex_formats(
+                    VertexStepMode::Vertex,
+                    [VertexFormat::Float32x2],
+                )],
+            },
+            primitive: PrimitiveState {
+                cull_mode: None,
+                topology: PrimitiveTopology::TriangleList,
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: VECTOR_LAYER_SHADER.typed(),
+                shader_defs: Vec::new(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Greater,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: msaa_samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        }
+    }
+}
+
+/// Sets the terrain view, terrain and layer bind groups and draws the layer.
+pub(crate) struct DrawVectorLayerCommand;
+
+impl EntityRenderCommand for DrawVectorLayerCommand {
+    type Param = (
+        SRes<TerrainViewComponents<TerrainViewData>>,
+        SRes<TerrainComponents<TerrainData>>,
+        SRes<RenderAssets<TerrainVectorLayer>>,
+        SQuery<Read<DrapedVectorLayer>>,
+    );
+
+    #[inline]
+    fn render<'w>(
+        view: Entity,
+        item: Entity,
+        (terrain_view_data, terrain_data, layers, layer_query): SystemParamItem<
+            'w,
+            '_,
+            Self::Param,
+        >,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let draped_layer = layer_query.get_inner(item).unwrap();
+
+        let (terrain_view_data, terrain_data, layer) = match (
+            terrain_view_data
+                .into_inner()
+                .get(&(draped_layer.terrain, view)),
+            terrain_data.into_inner().get(&draped_layer.terrain),
+            layers.into_inner().get(&draped_layer.layer),
+        ) {
+            (Some(terrain_view_data), Some(terrain_data), Some(layer)) => {
+                (terrain_view_data, terrain_data, layer)
+            }
+            _ => return RenderCommandResult::Failure,
+        };
+
+        pass.set_bind_group(1, &terrain_view_data.terrain_view_bind_group, &[]);
+        pass.set_bind_group(2, &terrain_data.terrain_bind_group, &[]);
+        pass.set_bind_group(3, &layer.bind_group, &[]);
+        pass.set_vertex_buffer(0, layer.vertex_buffer.slice(..));
+        pass.draw(0..layer.vertex_count, 0..1);
+
+        RenderCommandResult::Success
+    }
+}
+
+/// The draw function of the vector layers.
+pub(crate) type DrawVectorLayer = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    DrawVectorLayerCommand,
+);
+
+/// Queues all draped vector layers of the terrain views for rendering.
+pub(crate) fn queue_vector_layers(
+    pipeline: Res<VectorLayerPipeline>,
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    msaa: Res<Msaa>,
+    layers: Res<RenderAssets<TerrainVectorLayer>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<VectorLayerPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut view_query: Query<(Entity, &mut RenderPhase<Transparent3d>), With<TerrainView>>,
+    layer_query: Query<(Entity, &DrapedVectorLayer)>,
+) {
+    let draw_function = draw_functions.read().get_id::<DrawVectorLayer>().unwrap();
+    let pipeline = pipelines.specialize(&mut pipeline_cache, &pipeline, msaa.samples);
+
+    for (view, mut transparent_phase) in view_query.iter_mut() {
+        for (entity, draped_layer) in layer_query.iter() {
+            if !layers.contains_key(&draped_layer.layer)
+                || terrain_view_data
+                    .get(&(draped_layer.terrain, view))
+                    .is_none()
+            {
+                continue;
+            }
+
+            transparent_phase.add(Transparent3d {
+                entity,
+                pipeline,
+                draw_function,
+                distance: 0.0,
+            });
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! GPU driven scattering of vegetation billboards (e.g. grass) on top of the terrain.
+//!
+//! Each frame a grid of cells around the viewer is evaluated in the terrain compute pass.
+//! Every cell places at most one instance at a jittered position, depending on the density
+//! attachment, the height and slope rules and the distance to the viewer.
+//! The surviving instances are appended to an instance buffer, which is then drawn using a single
+//! instanced draw indirect call.
+
+use crate::{
+    render::{
+        shaders::{SCATTER_VEGETATION_SHADER, VEGETATION_SHADER},
+        terrain_data::terrain_bind_group_layout,
+        CULL_DATA_LAYOUT, REFINE_TILES_LAYOUT, SCATTER_VEGETATION_LAYOUT,
+        VEGETATION_INDIRECT_BUFFER_SIZE, VEGETATION_INSTANCE_SIZE, VEGETATION_LAYOUT,
+    },
+    terrain_data::AttachmentIndex,
+    terrain_view::{TerrainView, TerrainViewComponents, TerrainViewConfig},
+};
+use bevy::{
+    core_pipeline::core_3d::Opaque3d,
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    pbr::{MeshPipeline, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        render_phase::{
+            DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        Extract,
+    },
+};
+use strum::{EnumCount, IntoEnumIterator};
+use strum_macros::{EnumCount, EnumIter};
+
+/// The maximum number of vegetation instances per terrain and view. Has to match the shader.
+pub const MAX_VEGETATION_INSTANCES: u32 = 1 << 18;
+/// The maximum number of cells in x and y direction, that are evaluated each frame.
+pub const MAX_VEGETATION_GRID_SIZE: u32 = 1024;
+
+/// Scatters vegetation billboards on the terrain.
+///
+/// Add this component to a terrain entity to enable vegetation for all of its views.
+#[derive(Clone, Component)]
+pub struct TerrainVegetation {
+    /// The attachment, whose first channel scales the density (0 to 1), if any.
+    ///
+    /// Only the attachments with the index two and three are supported.
+    pub density_attachment: Option<AttachmentIndex>,
+    /// The distance between neighbouring instances at full density.
+    pub spacing: f32,
+    /// The distance up to which vegetation is scattered.
+    pub view_distance: f32,
+    /// The distance after which the density is quartered (and the size doubled) with every lod.
+    pub lod_distance: f32,
+    /// The minimum terrain height, at which vegetation is placed.
+    pub min_height: f32,
+    /// The maximum terrain height, at which vegetation is placed.
+    pub max_height: f32,
+    /// The maximum terrain slope in degrees, at which vegetation is placed.
+    pub max_slope: f32,
+    /// The width and height of the billboards.
+    pub size: Vec2,
+    /// The color of the billboards.
+    pub color: Color,
+}
+
+impl Default for TerrainVegetation {
+    fn default() -> Self {
+        Self {
+            density_attachment: None,
+            spacing: 0.5,
+            view_distance: 200.0,
+            lod_distance: 25.0,
+            min_height: f32::MIN,
+            max_height: f32::MAX,
+            max_slope: 35.0,
+            size: Vec2::new(0.3, 0.8),
+            color: Color::rgb(0.25, 0.45, 0.1),
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct VegetationUniform {
+    color: Vec4,
+    size: Vec2,
+    spacing: f32,
+    grid_size: u32,
+    view_distance: f32,
+    lod_distance: f32,
+    min_height: f32,
+    max_height: f32,
+    max_slope: f32,
+}
+
+impl From<&TerrainVegetation> for VegetationUniform {
+    fn from(vegetation: &TerrainVegetation) -> Self {
+        Self {
+            color: vegetation.color.as_linear_rgba_f32().into(),
+            size: vegetation.size,
+            spacing: vegetation.spacing,
+            grid_size: grid_size(vegetation),
+            view_distance: vegetation.view_distance,
+            lod_distance: vegetation.lod_distance,
+            min_height: vegetation.min_height,
+            max_height: vegetation.max_height,
+            max_slope: vegetation.max_slope.to_radians().tan(),
+        }
+    }
+}
+
+/// The number of cells in x and y direction, required to cover the view distance.
+fn grid_size(vegetation: &TerrainVegetation) -> u32 {
+    let grid_size = (2.0 * vegetation.view_distance / vegetation.spacing).ceil() as u32;
+    grid_size.clamp(1, MAX_VEGETATION_GRID_SIZE)
+}
+
+#[derive(Copy, Clone, Hash, PartialEq, Eq, EnumIter, EnumCount)]
+pub enum VegetationComputePipelineId {
+    Prepare,
+    Scatter,
+    Finish,
+}
+
+/// The pipelines used to scatter and render the vegetation.
+#[derive(Resource)]
+pub struct VegetationPipelines {
+    refine_tiles_layout: BindGroupLayout,
+    cull_data_layout: BindGroupLayout,
+    terrain_layout: BindGroupLayout,
+    scatter_layout: BindGroupLayout,
+    view_layout: BindGroupLayout,
+    vegetation_layout: BindGroupLayout,
+}
+
+impl FromWorld for VegetationPipelines {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let mesh_pipeline = world.resource::<MeshPipeline>();
+
+        Self {
+            refine_tiles_layout: device.create_bind_group_layout(&REFINE_TILES_LAYOUT),
+            cull_data_layout: device.create_bind_group_layout(&CULL_DATA_LAYOUT),
+            terrain_layout: terrain_bind_group_layout(device),
+            scatter_layout: device.create_bind_group_layout(&SCATTER_VEGETATION_LAYOUT),
+            view_layout: mesh_pipeline.view_layout.clone(),
+            vegetation_layout: device.create_bind_group_layout(&VEGETATION_LAYOUT),
+        }
+    }
+}
+
+impl SpecializedComputePipeline for VegetationPipelines {
+    type Key = (VegetationComputePipelineId, Option<AttachmentIndex>);
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        let mut shader_defs = Vec::new();
+
+        match key.1 {
+            Some(2) => shader_defs.push("DENSITY_ATTACHMENT_2".to_string()),
+            Some(3) => shader_defs.push("DENSITY_ATTACHMENT_3".to_string()),
+            _ => {}
+        }
+
+        let entry_point = match key.0 {
+            VegetationComputePipelineId::Prepare => "prepare_vegetation",
+            VegetationComputePipelineId::Scatter => "scatter_vegetation",
+            VegetationComputePipelineId::Finish => "finish_vegetation",
+        };
+
+        ComputePipelineDescriptor {
+            label: Some("vegetation_compute_pipeline".into()),
+            layout: Some(vec![
+                self.refine_tiles_layout.clone(),
+                self.cull_data_layout.clone(),
+                self.terrain_layout.clone(),
+                self.scatter_layout.clone(),
+            ]),
+            shader: SCATTER_VEGETATION_SHADER.typed(),
+            shader_defs,
+            entry_point: entry_point.into(),
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for VegetationPipelines {
+    /// The number of msaa samples.
+    type Key = u32;
+
+    fn specialize(&self, msaa_samples: Self::Key) -> RenderPipelineDescriptor {
+        // the edges of the blades are antialiased with alpha to coverage, when multisampling
+        let alpha_to_coverage = msaa_samples > 1;
+
+        let mut shader_defs = Vec::new();
+
+        if alpha_to_coverage {
+            shader_defs.push("ALPHA_TO_COVERAGE".to_string());
+        }
+
+        RenderPipelineDescriptor {
+            label: Some("vegetation_render_pipeline".into()),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.vegetation_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: VEGETATION_SHADER.typed(),
+                entry_point: "vertex".into(),
+                shader_defs: shader_defs.clone(),
+                buffers: Vec::new(),
+            },
+            primitive: PrimitiveState {
+                cull_mode: None,
+                topology: PrimitiveTopology::TriangleStrip,
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: VEGETATION_SHADER.typed(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Greater,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: msaa_samples,
+                mask: !0,
+                alpha_to_coverage_enabled: alpha_to_coverage,
+            },
+        }
+    }
+}
+
+/// The vegetation buffers and pipelines of a terrain view.
+pub struct VegetationViewData {
+    pub(crate) indirect_buffer: Buffer,
+    pub(crate) scatter_bind_group: BindGroup,
+    pub(crate) grid_size: u32,
+    pub(crate) compute_pipelines: [CachedComputePipelineId; VegetationComputePipelineId::COUNT],
+    uniform: VegetationUniform,
+    density_attachment: Option<AttachmentIndex>,
+    uniform_buffer: Buffer,
+    vegetation_bind_group: BindGroup,
+    render_pipeline: CachedRenderPipelineId,
+}
+
+impl VegetationViewData {
+    fn new(device: &RenderDevice) -> Self {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: "vegetation_uniform_buffer".into(),
+            size: VegetationUniform::min_size().get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let instance_buffer = device.create_buffer(&BufferDescriptor {
+            label: "vegetation_instance_buffer".into(),
+            size: VEGETATION_INSTANCE_SIZE * MAX_VEGETATION_INSTANCES as BufferAddress,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let indirect_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "vegetation_indirect_buffer".into(),
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            contents: &[0; VEGETATION_INDIRECT_BUFFER_SIZE as usize],
+        });
+
+        let scatter_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "scatter_vegetation_bind_group".into(),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+            ],
+            layout: &device.create_bind_group_layout(&SCATTER_VEGETATION_LAYOUT),
+        });
+        let vegetation_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "vegetation_bind_group".into(),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: instance_buffer.as_entire_binding(),
+                },
+            ],
+            layout: &device.create_bind_group_layout(&VEGETATION_LAYOUT),
+        });
+
+        Self {
+            indirect_buffer,
+            scatter_bind_group,
+            grid_size: 0,
+            compute_pipelines: [CachedComputePipelineId::INVALID;
+                VegetationComputePipelineId::COUNT],
+            uniform: default(),
+            density_attachment: None,
+            uniform_buffer,
+            vegetation_bind_group,
+            render_pipeline: CachedRenderPipelineId::INVALID,
+        }
+    }
+}
+
+pub(crate) fn initialize_vegetation_view_data(
+    device: Res<RenderDevice>,
+    mut vegetation_view_data: ResMut<TerrainViewComponents<VegetationViewData>>,
+    view_configs: Extract<Res<TerrainViewComponents<TerrainViewConfig>>>,
+    terrain_query: Extract<Query<(), With<TerrainVegetation>>>,
+) {
+    vegetation_view_data.0.retain(|&(terrain, view), _| {
+        view_configs.0.contains_key(&(terrain, view)) && terrain_query.contains(terrain)
+    });
+
+    for &(terrain, view) in view_configs.0.keys() {
+        if terrain_query.contains(terrain) && vegetation_view_data.get(&(terrain, view)).is_none() {
+            vegetation_view_data.insert((terrain, view), VegetationViewData::new(&device));
+        }
+    }
+}
+
+pub(crate) fn extract_vegetation(
+    mut vegetation_view_data: ResMut<TerrainViewComponents<VegetationViewData>>,
+    terrain_query: Extract<Query<&TerrainVegetation>>,
+) {
+    for (&(terrain, _), data) in &mut vegetation_view_data.0 {
+        if let Ok(vegetation) = terrain_query.get(terrain) {
+            if let Some(attachment) = vegetation.density_attachment {
+                if !(2..=3).contains(&attachment) {
+                    warn!("The density attachment has to be the attachment two or three.");
+                }
+            }
+
+            data.uniform = vegetation.into();
+            data.grid_size = data.uniform.grid_size;
+            data.density_attachment = vegetation.density_attachment;
+        }
+    }
+}
+
+/// Updates the vegetation uniforms and queues the vegetation of all terrain views for rendering.
+pub(crate) fn queue_vegetation(
+    queue: Res<RenderQueue>,
+    vegetation_pipelines: Res<VegetationPipelines>,
+    draw_functions: Res<DrawFunctions<Opaque3d>>,
+    msaa: Res<Msaa>,
+    mut compute_pipelines: ResMut<SpecializedComputePipelines<VegetationPipelines>>,
+    mut render_pipelines: ResMut<SpecializedRenderPipelines<VegetationPipelines>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut vegetation_view_data: ResMut<TerrainViewComponents<VegetationViewData>>,
+    mut view_query: Query<&mut RenderPhase<Opaque3d>, With<TerrainView>>,
+) {
+    let draw_function = draw_functions.read().get_id::<DrawVegetation>().unwrap();
+
+    for (&(terrain, view), data) in &mut vegetation_view_data.0 {
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&data.uniform).unwrap();
+        queue.write_buffer(&data.uniform_buffer, 0, &buffer.into_inner());
+
+        for id in VegetationComputePipelineId::iter() {
+            data.compute_pipelines[id as usize] = compute_pipelines.specialize(
+                &mut pipeline_cache,
+                &vegetation_pipelines,
+                (id, data.density_attachment),
+            );
+        }
+
+        data.render_pipeline =
+            render_pipelines.specialize(&mut pipeline_cache, &vegetation_pipelines, msaa.samples);
+
+        if let Ok(mut opaque_phase) = view_query.get_mut(view) {
+            opaque_phase.add(Opaque3d {
+                entity: terrain,
+                pipeline: data.render_pipeline,
+                draw_function,
+                distance: 0.0,
+            });
+        }
+    }
+}
+
+pub(crate) struct DrawVegetationCommand;
+
+impl EntityRenderCommand for DrawVegetationCommand {
+    type Param = SRes<TerrainViewComponents<VegetationViewData>>;
+
+    #[inline]
+    fn render<'w>(
+        view: Entity,
+        terrain: Entity,
+        vegetation_view_data: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let data = vegetation_view_data
+            .into_inner()
+            .get(&(terrain, view))
+            .unwrap();
+
+        pass.set_bind_group(1, &data.vegetation_bind_group, &[]);
+        pass.draw_indirect(&data.indirect_buffer, 0);
+        RenderCommandResult::Success
+    }
+}
+
+/// The draw function of the vegetation.
+pub(crate) type DrawVegetation = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    DrawVegetationCommand,
+);
@@ -0,0 +1,386 @@
+//! A per terrain viewshed, which marks the regions of the terrain that are visible from an
+//! observer (e.g. the coverage of a radar or the view of a watchtower).
+//!
+//! The viewshed is computed on the GPU by marching the line of sight from the observer to each
+//! texel of a visibility mask covering the whole terrain through the height attachment.
+//! The heights are looked up using the quadtree of a view, thus regions far away from the viewer
+//! are tested against coarser data. The mask is recomputed, whenever the [`TerrainViewshed`]
+//! changes or new nodes of the terrain have finished loading.
+//! It is overlaid by the built-in materials and can be read in the fragment shader of custom
+//! materials via the `terrain_viewshed` and `apply_viewshed` functions of the
+//! `bevy_terrain::viewshed` shader import.
+
+use crate::{
+    render::{
+        culling::CullingBindGroup,
+        downlevel::TerrainRenderMode,
+        shaders::COMPUTE_VIEWSHED_SHADER,
+        terrain_data::{terrain_bind_group_layout, TerrainData},
+        terrain_view_data::TerrainViewData,
+        CULL_DATA_LAYOUT, REFINE_TILES_LAYOUT,
+    },
+    skip_none,
+    terrain::{Terrain, TerrainComponents},
+    terrain_data::node_atlas::TerrainEvent,
+    terrain_view::TerrainViewComponents,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+
+/// Computes the regions of a terrain, which are visible from an observer.
+///
+/// Add this component to a terrain entity, when spawning it.
+/// The resolution can not be changed afterwards.
+/// The viewshed is only computed in [`TerrainRenderMode::Compute`].
+#[derive(Clone, Component)]
+pub struct TerrainViewshed {
+    /// The view, whose quadtree is used to look up the heights.
+    pub view: Entity,
+    /// The horizontal position (x, z) of the observer.
+    pub observer: Vec2,
+    /// The height of the observer above the ground (e.g. the height of the watchtower).
+    pub observer_height: f32,
+    /// The height of the targets above the ground, which have to be visible
+    /// (e.g. the flight altitude covered by a radar).
+    pub target_height: f32,
+    /// The distance from the observer up to which the viewshed is computed.
+    pub radius: f32,
+    /// The maximum number of heights sampled along each line of sight.
+    pub sample_count: u32,
+    /// The overlay color of the visible regions, whose alpha is the strength of the overlay.
+    pub visible_color: Color,
+    /// The overlay color of the hidden regions, whose alpha is the strength of the overlay.
+    pub hidden_color: Color,
+    resolution: u32,
+}
+
+impl TerrainViewshed {
+    /// Creates a viewshed with `resolution` x `resolution` texels, whose heights are looked up
+    /// using the quadtree of the view.
+    pub fn new(view: Entity, resolution: u32) -> Self {
+        Self {
+            view,
+            observer: Vec2::ZERO,
+            observer_height: 10.0,
+            target_height: 0.0,
+            radius: 1000.0,
+            sample_count: 256,
+            visible_color: Color::rgba(0.0, 1.0, 0.0, 0.4),
+            hidden_color: Color::rgba(1.0, 0.0, 0.0, 0.4),
+            resolution,
+        }
+    }
+
+    /// Returns the number of texels per side.
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct ViewshedParameters {
+    visible_color: Vec4,
+    hidden_color: Vec4,
+    observer: Vec2,
+    observer_height: f32,
+    target_height: f32,
+    radius: f32,
+    sample_count: u32,
+}
+
+impl ViewshedParameters {
+    fn new(viewshed: &TerrainViewshed) -> Self {
+        Self {
+            visible_color: viewshed.visible_color.as_linear_rgba_f32().into(),
+            hidden_color: viewshed.hidden_color.as_linear_rgba_f32().into(),
+            observer: viewshed.observer,
+            observer_height: viewshed.observer_height,
+            target_height: viewshed.target_height,
+            radius: viewshed.radius,
+            sample_count: viewshed.sample_count.max(1),
+        }
+    }
+}
+
+/// Recomputes the viewsheds of the terrains, whose nodes have finished loading.
+pub(crate) fn refresh_viewsheds(
+    mut events: EventReader<TerrainEvent>,
+    mut terrain_query: Query<(Entity, &mut TerrainViewshed), With<Terrain>>,
+) {
+    let loaded_terrains: Vec<Entity> = events
+        .iter()
+        .filter_map(|event| match event {
+            TerrainEvent::NodeLoaded { terrain, .. } => Some(*terrain),
+            _ => None,
+        })
+        .collect();
+
+    for (terrain, mut viewshed) in terrain_query.iter_mut() {
+        if loaded_terrains.contains(&terrain) {
+            viewshed.set_changed();
+        }
+    }
+}
+
+fn viewshed_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: "viewshed_layout".into(),
+        entries: &[
+            // parameters
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(ViewshedParameters::min_size()),
+                },
+                count: None,
+            },
+            // visibility mask
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// The pipeline used to compute the viewsheds.
+#[derive(Resource)]
+pub struct ViewshedPipeline {
+    viewshed_layout: BindGroupLayout,
+    pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for ViewshedPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let refine_tiles_layout = device.create_bind_group_layout(&REFINE_TILES_LAYOUT);
+        let cull_data_layout = device.create_bind_group_layout(&CULL_DATA_LAYOUT);
+        let terrain_layout = terrain_bind_group_layout(device);
+        let viewshed_layout = viewshed_bind_group_layout(device);
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let pipeline_id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("viewshed_pipeline".into()),
+            layout: Some(vec![
+                refine_tiles_layout,
+                cull_data_layout,
+                terrain_layout,
+                viewshed_layout.clone(),
+            ]),
+            shader: COMPUTE_VIEWSHED_SHADER.typed(),
+            shader_defs: vec![],
+            entry_point: "compute_viewshed".into(),
+        });
+
+        Self {
+            viewshed_layout,
+            pipeline_id,
+        }
+    }
+}
+
+/// Stores the visibility mask of the [`TerrainViewshed`] of a terrain.
+pub struct GpuTerrainViewshed {
+    pub(crate) texture_view: TextureView,
+    resolution: u32,
+    view: Option<Entity>,
+    /// The parameters, which have been extracted, but whose viewshed has not been computed yet.
+    pending_parameters: Option<ViewshedParameters>,
+}
+
+impl GpuTerrainViewshed {
+    fn new(
+        device: &RenderDevice,
+        mode: TerrainRenderMode,
+        viewshed: Option<&TerrainViewshed>,
+    ) -> Self {
+        // terrains without a viewshed are covered by a single transparent texel
+        let resolution = viewshed.map_or(1, |viewshed| viewshed.resolution);
+
+        // storage textures are not available on downlevel devices
+        let usage = match mode {
+            TerrainRenderMode::Compute => {
+                TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING
+            }
+            TerrainRenderMode::Downlevel => TextureUsages::TEXTURE_BINDING,
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: "terrain_viewshed".into(),
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage,
+        });
+
+        Self {
+            texture_view: texture.create_view(&default()),
+            resolution,
+            view: viewshed.map(|viewshed| viewshed.view),
+            pending_parameters: viewshed.map(ViewshedParameters::new),
+        }
+    }
+
+    /// Records the computation of the visibility mask.
+    fn compute(
+        &self,
+        device: &RenderDevice,
+        command_encoder: &mut CommandEncoder,
+        pipeline: &ComputePipeline,
+        viewshed_layout: &BindGroupLayout,
+        parameters: &ViewshedParameters,
+        bind_groups: (&BindGroup, &BindGroup, &BindGroup),
+    ) {
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(parameters).unwrap();
+
+        let parameter_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "viewshed_parameter_buffer".into(),
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let viewshed_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: parameter_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&self.texture_view),
+                },
+            ],
+            layout: viewshed_layout,
+        });
+
+        let (refine_tiles_bind_group, culling_bind_group, terrain_bind_group) = bind_groups;
+        let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, refine_tiles_bind_group, &[]);
+        pass.set_bind_group(1, culling_bind_group, &[]);
+        pass.set_bind_group(2, terrain_bind_group, &[]);
+        pass.set_bind_group(3, &viewshed_bind_group, &[]);
+        pass.dispatch_workgroups((self.resolution + 7) / 8, (self.resolution + 7) / 8, 1);
+    }
+}
+
+/// Initializes the [`GpuTerrainViewshed`] of newly created terrains and updates the changed ones.
+pub(crate) fn extract_terrain_viewshed(
+    device: Res<RenderDevice>,
+    mode: Res<TerrainRenderMode>,
+    mut gpu_viewsheds: ResMut<TerrainComponents<GpuTerrainViewshed>>,
+    terrain_query: Extract<
+        Query<
+            (
+                Entity,
+                Option<&TerrainViewshed>,
+                Option<ChangeTrackers<TerrainViewshed>>,
+            ),
+            With<Terrain>,
+        >,
+    >,
+) {
+    gpu_viewsheds
+        .0
+        .retain(|&terrain, _| terrain_query.contains(terrain));
+
+    for (terrain, viewshed, viewshed_trackers) in terrain_query.iter() {
+        if gpu_viewsheds.get(&terrain).is_none() {
+            gpu_viewsheds.insert(terrain, GpuTerrainViewshed::new(&device, *mode, viewshed));
+            continue;
+        }
+
+        let (viewshed, viewshed_trackers) = match (viewshed, viewshed_trackers) {
+            (Some(viewshed), Some(viewshed_trackers)) => (viewshed, viewshed_trackers),
+            _ => continue,
+        };
+
+        if !viewshed_trackers.is_changed() {
+            continue;
+        }
+
+        let gpu_viewshed = gpu_viewsheds.get_mut(&terrain).unwrap();
+
+        if viewshed.resolution != gpu_viewshed.resolution {
+            warn!("The viewshed of a terrain has to be added when spawning the terrain and its resolution can not be changed.");
+            continue;
+        }
+
+        gpu_viewshed.view = Some(viewshed.view);
+        gpu_viewshed.pending_parameters = Some(ViewshedParameters::new(viewshed));
+    }
+}
+
+/// Queues the computation of the changed viewsheds.
+pub(crate) fn queue_terrain_viewshed(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    viewshed_pipeline: Res<ViewshedPipeline>,
+    terrain_data: Res<TerrainComponents<TerrainData>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    culling_bind_groups: Res<TerrainViewComponents<CullingBindGroup>>,
+    mut gpu_viewsheds: ResMut<TerrainComponents<GpuTerrainViewshed>>,
+) {
+    let pipeline = match pipeline_cache.get_compute_pipeline(viewshed_pipeline.pipeline_id) {
+        Some(pipeline) => pipeline,
+        None => return, // the pipeline is not loaded yet
+    };
+
+    for (&terrain, gpu_viewshed) in &mut gpu_viewsheds.0 {
+        let view = skip_none!(gpu_viewshed.view);
+        let parameters = skip_none!(&gpu_viewshed.pending_parameters);
+
+        // keep the parameters pending, until the view has been prepared
+        let terrain_data = skip_none!(terrain_data.get(&terrain));
+        let view_data = skip_none!(terrain_view_data.get(&(terrain, view)));
+        let refine_tiles_bind_group = skip_none!(&view_data.refine_tiles_bind_group);
+        let culling_bind_group = skip_none!(culling_bind_groups.get(&(terrain, view)));
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        gpu_viewshed.compute(
+            &device,
+            &mut command_encoder,
+            pipeline,
+            &viewshed_pipeline.viewshed_layout,
+            parameters,
+            (
+                refine_tiles_bind_group,
+                &culling_bind_group.value,
+                &terrain_data.terrain_bind_group,
+            ),
+        );
+
+        queue.submit(vec![command_encoder.finish()]);
+
+        gpu_viewshed.pending_parameters = None;
+    }
+}
@@ -0,0 +1,335 @@
+//! Water surfaces (e.g. oceans and lakes), which are rendered on top of the terrain.
+//!
+//! The water is drawn using the same tiles as the terrain, which have been refined by the
+//! quadtree of the view. Instead of the terrain height, each vertex is placed at the water level.
+//! The fragment shader then compares the water level with the terrain height to compute the
+//! water depth, which is used for the color, the transparency and the shoreline foam.
+
+use crate::{
+    render::{
+        shaders::WATER_SHADER,
+        terrain_data::{terrain_bind_group_layout, SetTerrainBindGroup},
+        terrain_view_data::{DrawTerrainCommand, SetTerrainViewBindGroup, TerrainViewData},
+        TERRAIN_VIEW_LAYOUT,
+    },
+    terrain::TerrainComponents,
+    terrain_data::AttachmentIndex,
+    terrain_view::{TerrainView, TerrainViewComponents},
+};
+use bevy::{
+    core_pipeline::core_3d::Transparent3d,
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    pbr::{MeshPipeline, SetMeshViewBindGroup},
+    prelude::*,
+    render::{
+        render_phase::{
+            DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        Extract,
+    },
+};
+
+/// Renders water on top of the terrain.
+///
+/// Add this component to a terrain entity to enable water for all of its views.
+/// Because the water is drawn on the tiles of the terrain, it is culled together with the
+/// terrain beneath it.
+#[derive(Clone, Component)]
+pub struct TerrainWater {
+    /// The height of the water surface (e.g. the sea level).
+    pub level: f32,
+    /// The attachment, whose first channel stores the height of the water surface
+    /// (scaled like the terrain height), if any. Overrides the level.
+    ///
+    /// Only the attachments with the index two and three are supported.
+    pub water_attachment: Option<AttachmentIndex>,
+    /// The color of shallow water, whose alpha scales the opacity.
+    pub shallow_color: Color,
+    /// The color of deep water, whose alpha scales the opacity.
+    pub deep_color: Color,
+    /// The depth, at which the water is mostly colored in the deep color.
+    pub depth_scale: f32,
+    /// The depth, up to which foam appears along the shoreline.
+    pub foam_depth: f32,
+    /// The wavelength of the largest waves.
+    pub wave_length: f32,
+    /// The speed of the waves.
+    pub wave_speed: f32,
+    /// The steepness of the waves.
+    pub wave_strength: f32,
+}
+
+impl Default for TerrainWater {
+    fn default() -> Self {
+        Self {
+            level: 0.0,
+            water_attachment: None,
+            shallow_color: Color::rgba(0.1, 0.45, 0.5, 0.4),
+            deep_color: Color::rgba(0.02, 0.08, 0.2, 0.95),
+            depth_scale: 20.0,
+            foam_depth: 1.5,
+            wave_length: 8.0,
+            wave_speed: 1.0,
+            wave_strength: 0.15,
+        }
+    }
+}
+
+#[derive(Clone, Default, ShaderType)]
+struct WaterUniform {
+    shallow_color: Vec4,
+    deep_color: Vec4,
+    level: f32,
+    depth_scale: f32,
+    foam_depth: f32,
+    wave_length: f32,
+    wave_speed: f32,
+    wave_strength: f32,
+    time: f32,
+}
+
+impl From<&TerrainWater> for WaterUniform {
+    fn from(water: &TerrainWater) -> Self {
+        Self {
+            shallow_color: water.shallow_color.as_linear_rgba_f32().into(),
+            deep_color: water.deep_color.as_linear_rgba_f32().into(),
+            level: water.level,
+            depth_scale: water.depth_scale,
+            foam_depth: water.foam_depth,
+            wave_length: water.wave_length,
+            wave_speed: water.wave_speed,
+            wave_strength: water.wave_strength,
+            time: 0.0,
+        }
+    }
+}
+
+/// The pipeline used to render the water.
+#[derive(Resource)]
+pub struct WaterPipeline {
+    view_layout: BindGroupLayout,
+    terrain_view_layout: BindGroupLayout,
+    terrain_layout: BindGroupLayout,
+    water_layout: BindGroupLayout,
+}
+
+impl FromWorld for WaterPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let mesh_pipeline = world.resource::<MeshPipeline>();
+
+        Self {
+            view_layout: mesh_pipeline.view_layout.clone(),
+            terrain_view_layout: device.create_bind_group_layout(&TERRAIN_VIEW_LAYOUT),
+            terrain_layout: terrain_bind_group_layout(device),
+            water_layout: water_bind_group_layout(device),
+        }
+    }
+}
+
+fn water_bind_group_layout(device: &RenderDevice) -> BindGroupLayout {
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: "water_layout".into(),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX_FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(WaterUniform::min_size()),
+            },
+            count: None,
+        }],
+    })
+}
+
+impl SpecializedRenderPipeline for WaterPipeline {
+    /// The number of msaa samples and the water attachment.
+    type Key = (u32, Option<AttachmentIndex>);
+
+    fn specialize(&self, (msaa_samples, water_attachment): Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = vec!["TONEMAP_IN_SHADER".to_string()];
+
+        match water_attachment {
+            Some(2) => shader_defs.push("WATER_ATTACHMENT_2".to_string()),
+            Some(3) => shader_defs.push("WATER_ATTACHMENT_3".to_string()),
+            _ => {}
+        }
+
+        RenderPipelineDescriptor {
+            label: "water_pipeline".into(),
+            layout: Some(vec![
+                self.view_layout.clone(),
+                self.terrain_view_layout.clone(),
+                self.terrain_layout.clone(),
+                self.water_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: WATER_SHADER.typed(),
+                entry_point: "vertex".into(),
+                shader_defs: shader_defs.clone(),
+                buffers: Vec::new(),
+            },
+            primitive: PrimitiveState {
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: Some(IndexFormat::Uint32),
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: WATER_SHADER.typed(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Greater,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: msaa_samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        }
+    }
+}
+
+/// Stores the render world side of the [`TerrainWater`].
+pub struct WaterData {
+    uniform: WaterUniform,
+    water_attachment: Option<AttachmentIndex>,
+    uniform_buffer: Buffer,
+    water_bind_group: BindGroup,
+}
+
+impl WaterData {
+    fn new(device: &RenderDevice) -> Self {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: "water_uniform_buffer".into(),
+            size: WaterUniform::min_size().get(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let water_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: "water_bind_group".into(),
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            layout: &water_bind_group_layout(device),
+        });
+
+        Self {
+            uniform: default(),
+            water_attachment: None,
+            uniform_buffer,
+            water_bind_group,
+        }
+    }
+}
+
+pub(crate) fn extract_water(
+    device: Res<RenderDevice>,
+    time: Extract<Res<Time>>,
+    mut water_data: ResMut<TerrainComponents<WaterData>>,
+    terrain_query: Extract<Query<(Entity, &TerrainWater)>>,
+) {
+    for (terrain, water) in terrain_query.iter() {
+        if let Some(attachment) = water.water_attachment {
+            if !(2..=3).contains(&attachment) {
+                warn!("The water attachment has to be the attachment two or three.");
+            }
+        }
+
+        let data = water_data
+            .0
+            .entry(terrain)
+            .or_insert_with(|| WaterData::new(&device));
+
+        data.uniform = water.into();
+        data.uniform.time = time.elapsed_seconds_wrapped();
+        data.water_attachment = water.water_attachment;
+    }
+}
+
+/// Updates the water uniforms and queues the water of all terrain views for rendering.
+pub(crate) fn queue_water(
+    queue: Res<RenderQueue>,
+    water_pipeline: Res<WaterPipeline>,
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    msaa: Res<Msaa>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    water_data: Res<TerrainComponents<WaterData>>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<WaterPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut view_query: Query<(Entity, &mut RenderPhase<Transparent3d>), With<TerrainView>>,
+) {
+    let draw_function = draw_functions.read().get_id::<DrawWater>().unwrap();
+
+    for (&terrain, data) in &water_data.0 {
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&data.uniform).unwrap();
+        queue.write_buffer(&data.uniform_buffer, 0, &buffer.into_inner());
+
+        let pipeline = pipelines.specialize(
+            &mut pipeline_cache,
+            &water_pipeline,
+            (msaa.samples, data.water_attachment),
+        );
+
+        for (view, mut transparent_phase) in view_query.iter_mut() {
+            if terrain_view_data.get(&(terrain, view)).is_none() {
+                continue;
+            }
+
+            transparent_phase.add(Transparent3d {
+                entity: terrain,
+                pipeline,
+                draw_function,
+                distance: 0.0,
+            });
+        }
+    }
+}
+
+pub struct SetWaterBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetWaterBindGroup<I> {
+    type Param = SRes<TerrainComponents<WaterData>>;
+
+    #[inline]
+    fn render<'w>(
+        _view: Entity,
+        terrain: Entity,
+        water_data: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let data = water_data.into_inner().get(&terrain).unwrap();
+        pass.set_bind_group(I, &data.water_bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// The draw function of the water. It reuses the tiles of the terrain.
+pub(crate) type DrawWater = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetTerrainViewBindGroup<1>,
+    SetTerrainBindGroup<2>,
+    SetWaterBindGroup<3>,
+    DrawTerrainCommand,
+);
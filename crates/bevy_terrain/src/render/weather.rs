@@ -0,0 +1,213 @@
+//! A per terrain weather layer, which stores the amount of snow and wetness covering the terrain
+//! (e.g. driven by a weather system).
+//!
+//! The layer is a grid of cells covering the whole terrain, which can be painted from the CPU
+//! every frame. It is uploaded into a small texture, whenever the [`TerrainWeather`] changes.
+//! The built-in materials cover snowy regions in snow and darken and smooth wet regions.
+//! Custom materials can read it in the fragment shader via the `terrain_weather` and
+//! `apply_weather` functions of the `bevy_terrain::weather` shader import.
+
+use crate::terrain::{Terrain, TerrainComponents, TerrainConfig};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+use std::num::NonZeroU32;
+
+/// Stores the amount of snow and wetness, each in the range of [0, 1], of the cells of a terrain.
+///
+/// Add this component to a terrain entity, when spawning it.
+/// The resolution can not be changed afterwards.
+#[derive(Clone, Component)]
+pub struct TerrainWeather {
+    resolution: u32,
+    cells: Vec<Vec2>,
+}
+
+impl TerrainWeather {
+    /// Creates a dry weather layer without snow with `resolution` x `resolution` cells.
+    pub fn new(resolution: u32) -> Self {
+        Self {
+            resolution,
+            cells: vec![Vec2::ZERO; (resolution * resolution) as usize],
+        }
+    }
+
+    /// Returns the number of cells per side.
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    /// Returns the snow (x) and the wetness (y) of the cell.
+    pub fn get(&self, cell: UVec2) -> Vec2 {
+        self.cells[(cell.y * self.resolution + cell.x) as usize]
+    }
+
+    /// Sets the snow (x) and the wetness (y) of the cell.
+    pub fn set(&mut self, cell: UVec2, amount: Vec2) {
+        self.cells[(cell.y * self.resolution + cell.x) as usize] =
+            amount.clamp(Vec2::ZERO, Vec2::ONE);
+    }
+
+    /// Sets the snow (x) and the wetness (y) of all cells.
+    pub fn fill(&mut self, amount: Vec2) {
+        self.cells.fill(amount.clamp(Vec2::ZERO, Vec2::ONE));
+    }
+
+    /// Adds the snow (x) and the wetness (y) to all cells within the radius around the
+    /// horizontal position (x, z) of the terrain. Negative amounts melt or dry the cells.
+    ///
+    /// The amount falls off smoothly towards the border of the brush.
+    pub fn paint(&mut self, config: &TerrainConfig, position: Vec2, radius: f32, amount: Vec2) {
+        let cell_size = config.terrain_size as f32 / self.resolution as f32;
+        let center = position / cell_size;
+        let radius = (radius / cell_size).max(0.5);
+
+        let start = (center - radius).floor().max(Vec2::ZERO).as_uvec2();
+        let end = (center + radius)
+            .ceil()
+            .min(Vec2::splat(self.resolution as f32))
+            .max(Vec2::ZERO)
+            .as_uvec2();
+
+        for y in start.y..end.y {
+            for x in start.x..end.x {
+                let cell = UVec2::new(x, y);
+                let distance = (cell.as_vec2() + 0.5).distance(center) / radius;
+
+                if distance < 1.0 {
+                    let falloff = 1.0 - distance * distance;
+                    self.set(cell, self.get(cell) + amount * falloff);
+                }
+            }
+        }
+    }
+
+    fn texels(&self) -> Vec<u8> {
+        self.cells
+            .iter()
+            .flat_map(|amount| {
+                amount
+                    .to_array()
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    }
+}
+
+/// Stores the texture of the [`TerrainWeather`] of a terrain.
+pub struct GpuTerrainWeather {
+    pub(crate) texture_view: TextureView,
+    texture: Texture,
+    resolution: u32,
+    /// The texels, which have been extracted this frame, but not yet written into the texture.
+    pending_texels: Option<Vec<u8>>,
+}
+
+impl GpuTerrainWeather {
+    fn new(device: &RenderDevice, weather: Option<&TerrainWeather>) -> Self {
+        // terrains without a weather layer are covered by a single dry cell
+        let resolution = weather.map_or(1, |weather| weather.resolution);
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: "terrain_weather".into(),
+            size: Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rg8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let texture_view = texture.create_view(&default());
+
+        Self {
+            texture_view,
+            texture,
+            resolution,
+            pending_texels: Some(weather.map_or_else(|| vec![0; 2], TerrainWeather::texels)),
+        }
+    }
+}
+
+/// Initializes the [`GpuTerrainWeather`] of newly created terrains and updates the changed ones.
+pub(crate) fn extract_terrain_weather(
+    device: Res<RenderDevice>,
+    mut gpu_weathers: ResMut<TerrainComponents<GpuTerrainWeather>>,
+    terrain_query: Extract<
+        Query<
+            (
+                Entity,
+                Option<&TerrainWeather>,
+                Option<ChangeTrackers<TerrainWeather>>,
+            ),
+            With<Terrain>,
+        >,
+    >,
+) {
+    gpu_weathers
+        .0
+        .retain(|&terrain, _| terrain_query.contains(terrain));
+
+    for (terrain, weather, weather_trackers) in terrain_query.iter() {
+        if gpu_weathers.get(&terrain).is_none() {
+            gpu_weathers.insert(terrain, GpuTerrainWeather::new(&device, weather));
+            continue;
+        }
+
+        let (weather, weather_trackers) = match (weather, weather_trackers) {
+            (Some(weather), Some(weather_trackers)) => (weather, weather_trackers),
+            _ => continue,
+        };
+
+        if !weather_trackers.is_changed() {
+            continue;
+        }
+
+        let gpu_weather = gpu_weathers.get_mut(&terrain).unwrap();
+
+        if weather.resolution != gpu_weather.resolution {
+            warn!("The weather layer of a terrain has to be added when spawning the terrain and its resolution can not be changed.");
+            continue;
+        }
+
+        gpu_weather.pending_texels = Some(weather.texels());
+    }
+}
+
+pub(crate) fn queue_terrain_weather(
+    queue: Res<RenderQueue>,
+    mut gpu_weathers: ResMut<TerrainComponents<GpuTerrainWeather>>,
+) {
+    for gpu_weather in gpu_weathers.0.values_mut() {
+        if let Some(texels) = gpu_weather.pending_texels.take() {
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &gpu_weather.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                &texels,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(gpu_weather.resolution * 2),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width: gpu_weather.resolution,
+                    height: gpu_weather.resolution,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Couples the tiles of a terrain to the shadow projection of a directional light.
+//!
+//! Shadows are cast by the terrain within the whole shadow projection of the light, which
+//! usually reaches far beyond the frustum of the camera. A shadow view is a
+//! [`DependentTerrainView`] of the camera, which follows the orthographic shadow projection
+//! of the light. It refines the quadtree of the camera against the shadow projection
+//! ([`DependentRefinement::Refine`]), so it neither maintains a quadtree of its own
+//! nor requests any nodes. Since the level of detail is still determined from the position
+//! of the camera, distant mountains are covered by the coarse nodes the camera already keeps
+//! resident, without blowing up the node atlas.
+//!
+//! Directional lights only have a single shadow projection in this version of Bevy, which
+//! corresponds to the far cascade of a cascaded shadow map.
+
+use crate::{
+    terrain_view::{DependentRefinement, DependentTerrainView, TerrainView},
+    TerrainViewComponents, TerrainViewConfig,
+};
+use bevy::{
+    pbr::DirectionalLightShadowMap,
+    prelude::*,
+    render::{camera::CameraProjection, view::ExtractedView, Extract},
+};
+
+/// The settings of a [`TerrainShadowView`].
+#[derive(Clone)]
+pub struct ShadowViewSettings {
+    /// The factor, by which the tiles of the shadow view are larger than the ones of the camera.
+    ///
+    /// Shadow maps have a lower resolution than the screen, so coarser tiles suffice.
+    pub tile_scale_factor: f32,
+}
+
+impl Default for ShadowViewSettings {
+    fn default() -> Self {
+        Self {
+            tile_scale_factor: 4.0,
+        }
+    }
+}
+
+/// A terrain view, which follows the shadow projection of a directional light.
+///
+/// Use [`spawn_terrain_shadow_view`] to create it.
+#[derive(Clone, Component)]
+pub struct TerrainShadowView {
+    /// The directional light, whose shadow projection is followed.
+    pub light: Entity,
+    /// The settings of the shadow view.
+    pub settings: ShadowViewSettings,
+}
+
+/// Spawns a shadow view for the directional light and returns it.
+///
+/// The shadow view depends on the `camera` terrain view and covers all terrains, which are
+/// rendered into the camera.
+pub fn spawn_terrain_shadow_view(
+    commands: &mut Commands,
+    camera: Entity,
+    light: Entity,
+    settings: ShadowViewSettings,
+) -> Entity {
+    commands
+        .spawn((
+            TransformBundle::default(),
+            Projection::Orthographic(default()),
+            TerrainView,
+            DependentTerrainView {
+                primary: camera,
+                refinement: DependentRefinement::Refine,
+            },
+            TerrainShadowView { light, settings },
+        ))
+        .id()
+}
+
+/// Matches the shadow views with the shadow projections of their lights.
+pub(crate) fn update_shadow_views(
+    mut shadow_view_query: Query<(&TerrainShadowView, &mut Transform, &mut Projection)>,
+    light_query: Query<(&GlobalTransform, &DirectionalLight)>,
+) {
+    for (shadow_view, mut transform, mut projection) in &mut shadow_view_query {
+        if let Ok((light_transform, light)) = light_query.get(shadow_view.light) {
+            // the shadow projection of a directional light is centered on the world origin
+            *transform = Transform::from_rotation(light_transform.compute_transform().rotation);
+            *projection = Projection::Orthographic(light.shadow_projection.clone());
+        }
+    }
+}
+
+/// Coarsens the view configs, which the shadow views copied from their cameras.
+pub(crate) fn update_shadow_view_configs(
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    shadow_view_query: Query<&TerrainShadowView>,
+) {
+    for (&(_, view), view_config) in view_configs.0.iter_mut() {
+        if let Ok(shadow_view) = shadow_view_query.get(view) {
+            view_config.tile_scale *= shadow_view.settings.tile_scale_factor;
+        }
+    }
+}
+
+/// Extracts the shadow views as views of the size of the shadow map,
+/// so that their tiles are culled against the shadow projection.
+pub(crate) fn extract_shadow_views(
+    mut commands: Commands,
+    shadow_map: Extract<Res<DirectionalLightShadowMap>>,
+    shadow_view_query: Extract<
+        Query<(Entity, &GlobalTransform, &Projection), With<TerrainShadowView>>,
+    >,
+) {
+    let size = shadow_map.size as u32;
+
+    let values = shadow_view_query
+        .iter()
+        .map(|(view, transform, projection)| {
+            (
+                view,
+                ExtractedView {
+                    projection: projection.get_projection_matrix(),
+                    transform: *transform,
+                    hdr: false,
+                    viewport: UVec4::new(0, 0, size, size),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    commands.insert_or_spawn_batch(values);
+}
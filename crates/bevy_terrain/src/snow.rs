@@ -0,0 +1,175 @@
+//! A simple simulation of seasonal snow cover, which accumulates and melts over time.
+//!
+//! The snow cover of the entire terrain is stored in a single `R8Unorm` image, which is updated
+//! on the CPU in fixed time steps. It can be bound by a terrain material and blended into the
+//! albedo in the fragment shader (see the advanced example).
+
+use crate::{
+    terrain::{Terrain, TerrainConfig},
+    terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree},
+    terrain_view::TerrainViewComponents,
+};
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+/// Simulates the snow cover of a terrain.
+///
+/// Add this component to a terrain entity and bind the [`image`](Self::image) in the material.
+/// The snow accumulates above the snow line, while it is snowing, slides off steep slopes and
+/// melts in warm weather and in direct sunlight.
+///
+/// The sun direction is taken from the first [`DirectionalLight`] in the scene.
+/// The sun exposure only considers the orientation of the terrain surface, shadows cast by
+/// surrounding terrain are ignored.
+#[derive(Component)]
+pub struct SnowSimulation {
+    /// The image, which stores the snow cover (0 to 1) of the terrain.
+    pub image: Handle<Image>,
+    /// The number of pixels of the image in x and y direction.
+    pub resolution: u32,
+    /// The height above which snow starts to accumulate.
+    pub snow_line: f32,
+    /// The height range over which the accumulation fades in around the snow line.
+    pub snow_line_range: f32,
+    /// The slope in degrees above which snow slides off.
+    pub max_slope: f32,
+    /// The snow cover gained per second during heavy snowfall.
+    pub accumulation_rate: f32,
+    /// The snow cover lost per second in warm weather or in full sunlight.
+    pub melt_rate: f32,
+    /// The current weather from -1 (warm) over 0 (neutral) to 1 (heavy snowfall).
+    pub weather: f32,
+    /// The duration of a simulation step in seconds.
+    pub time_step: f32,
+    cover: Vec<f32>,
+    elapsed: f32,
+}
+
+impl SnowSimulation {
+    /// Creates a new snow simulation and its image, without any initial snow cover.
+    pub fn new(images: &mut Assets<Image>, resolution: u32) -> Self {
+        let image = Image::new(
+            Extent3d {
+                width: resolution,
+                height: resolution,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![0; (resolution * resolution) as usize],
+            TextureFormat::R8Unorm,
+        );
+
+        Self {
+            image: images.add(image),
+            resolution,
+            snow_line: 0.0,
+            snow_line_range: 50.0,
+            max_slope: 40.0,
+            accumulation_rate: 0.05,
+            melt_rate: 0.02,
+            weather: 0.0,
+            time_step: 0.25,
+            cover: vec![0.0; (resolution * resolution) as usize],
+            elapsed: 0.0,
+        }
+    }
+
+    /// Advances the snow cover of the pixel by one time step.
+    fn step(&self, cover: f32, height: f32, normal: Vec3, sun_direction: Vec3) -> f32 {
+        let slope = normal.y.acos().to_degrees();
+        let slope_factor = 1.0 - smoothstep(0.75 * self.max_slope, self.max_slope, slope);
+        let altitude_factor = smoothstep(
+            self.snow_line - self.snow_line_range,
+            self.snow_line + self.snow_line_range,
+            height,
+        );
+        let sun_exposure = normal.dot(sun_direction).max(0.0) * sun_direction.y.max(0.0).sqrt();
+
+        let accumulation = self.accumulation_rate * self.weather.max(0.0) * altitude_factor;
+        let melt = self.melt_rate
+            * ((-self.weather).max(0.0) + sun_exposure)
+            * (1.0 - 0.5 * altitude_factor);
+
+        ((cover + (accumulation - melt) * self.time_step) * slope_factor).clamp(0.0, 1.0)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub(crate) fn simulate_snow(
+    time: Res<Time>,
+    mut images: ResMut<Assets<Image>>,
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    light_query: Query<&GlobalTransform, With<DirectionalLight>>,
+    mut terrain_query: Query<
+        (Entity, &TerrainConfig, &NodeAtlas, &mut SnowSimulation),
+        With<Terrain>,
+    >,
+) {
+    let sun_direction = light_query
+        .iter()
+        .next()
+        .map_or(Vec3::Y, |transform| transform.back());
+
+    for (terrain, config, node_atlas, mut simulation) in terrain_query.iter_mut() {
+        simulation.elapsed += time.delta_seconds();
+
+        if simulation.elapsed < simulation.time_step {
+            continue;
+        }
+
+        simulation.elapsed -= simulation.time_step;
+
+        // the heights are sampled from any view of the terrain
+        let quadtree = match quadtrees
+            .0
+            .iter()
+            .find(|(&(quadtree_terrain, _), _)| quadtree_terrain == terrain)
+        {
+            Some((_, quadtree)) => quadtree,
+            None => continue,
+        };
+
+        let resolution = simulation.resolution;
+        let pixel_size = config.terrain_size as f32 / resolution as f32;
+
+        let height = |position: Vec2| quadtree.sample_height(node_atlas, &images, position);
+
+        let cover: Vec<f32> = (0..resolution * resolution)
+            .map(|index| {
+                let cover = simulation.cover[index as usize];
+                let position = (UVec2::new(index % resolution, index / resolution).as_vec2() + 0.5)
+                    * pixel_size;
+
+                let heights = (
+                    height(position),
+                    height(position - Vec2::X * pixel_size),
+                    height(position + Vec2::X * pixel_size),
+                    height(position - Vec2::Y * pixel_size),
+                    height(position + Vec2::Y * pixel_size),
+                );
+
+                // keep the current cover, where the terrain is not loaded
+                match heights {
+                    (Some(center), Some(left), Some(right), Some(up), Some(down)) => {
+                        let normal =
+                            Vec3::new(left - right, 2.0 * pixel_size, up - down).normalize();
+                        simulation.step(cover, center, normal, sun_direction)
+                    }
+                    _ => cover,
+                }
+            })
+            .collect();
+
+        if let Some(image) = images.get_mut(&simulation.image) {
+            image.data = cover.iter().map(|&cover| (cover * 255.0) as u8).collect();
+        }
+
+        simulation.cover = cover;
+    }
+}
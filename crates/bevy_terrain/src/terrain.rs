@@ -3,8 +3,21 @@
 use crate::terrain_data::NodeId;
 use crate::{
     attachment_loader::{AttachmentFromDisk, AttachmentFromDiskLoader},
-    preprocess::{BaseConfig, Preprocessor, TileConfig},
-    terrain_data::{AtlasAttachment, AttachmentConfig, AttachmentIndex},
+    biome::Biome,
+    error::{TerrainError, TerrainResult},
+    georeference::{Georeference, VerticalDatum},
+    preprocess::BaseConfig,
+    procedural_loader::ProceduralAttachmentLoader,
+    render::terrain_data::MAX_ATTACHMENTS,
+    terrain_data::{
+        node_atlas::NodeAtlas, node_readback::NodeReadback, shift_attachment_index,
+        AtlasAttachment, AttachmentConfig, AttachmentFormat, AttachmentIndex,
+    },
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{
+    biome::BiomeClassification,
+    preprocess::{derivative::Derivative, flow::FlowAnalysis, Preprocessor, TileConfig},
 };
 use bevy::utils::HashSet;
 use bevy::{
@@ -54,6 +67,46 @@ impl ExtractComponent for Terrain {
     }
 }
 
+/// How the area of a sparse terrain, which is not covered by its data, is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CoverageFill {
+    /// The uncovered area is not rendered.
+    #[default]
+    Skip,
+    /// The uncovered area is filled with a flat surface at the height (e.g. the sea level).
+    Fill { height: f32 },
+}
+
+/// The filter, with which the heights of the height attachment are reconstructed
+/// between its pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HeightFilter {
+    /// The heights are interpolated bilinearly by the sampler, which leaves visible creases
+    /// along the pixels.
+    #[default]
+    Linear,
+    /// The heights are interpolated by a Catmull-Rom spline through the surrounding four by four
+    /// pixels, which is smooth across the pixels, at the cost of nine texture fetches instead of one.
+    /// The spline passes through the pixels, but may overshoot them at sharp edges.
+    CatmullRom,
+    /// The heights are approximated by a cubic B-spline of the surrounding four by four pixels,
+    /// at the cost of four texture fetches instead of one.
+    /// The B-spline is smoother than the Catmull-Rom spline and never overshoots the pixels,
+    /// but it slightly flattens peaks and ridges, since it does not pass through the pixels.
+    BSpline,
+}
+
+/// How the border of the terrain is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BorderSkirt {
+    /// The terrain ends at its border, thus its underside is visible from the side.
+    #[default]
+    None,
+    /// The border is closed by vertical walls of the color, which extend from the surface down
+    /// to the depth below the lowest possible height of the terrain.
+    Wall { depth: f32, color: Color },
+}
+
 /// The configuration of a terrain.
 ///
 /// Here you can define all fundamental parameters of the terrain.
@@ -62,18 +115,71 @@ pub struct TerrainConfig {
     /// The count of level of detail layers.
     pub lod_count: u32,
     /// The maximum height of the terrain. // Todo: reconsider this
+    ///
+    /// The values of the height attachment are scaled by this height.
     pub height: f32,
+    /// The height, to which a value of zero of the height attachment corresponds, measured
+    /// relative to the vertical datum (e.g. -430 for the shore of the Dead Sea or the depth of
+    /// the deepest point of bathymetry data).
+    /// The heights span from this offset up to the offset plus the height.
+    pub height_offset: f32,
+    /// The vertical datum, relative to which the heights of the terrain are measured.
+    pub vertical_datum: VerticalDatum,
     /// The size of the smallest nodes (with lod 0).
     pub leaf_node_size: u32, // Todo: reconsider this
     /// The size of the terrain.
     pub terrain_size: u32, // Todo: reconsider this
     /// The amount of nodes the can be loaded simultaneously in the node atlas.
     pub node_atlas_size: u32,
+    /// The number of frames, over which newly loaded nodes are cross-faded with the coarser
+    /// data they replace, instead of popping in. Zero disables the cross-fade.
+    ///
+    /// Custom shaders have to declare the `node_fade_texture` binding, if this is enabled.
+    pub node_fade_frames: u32,
     /// The path to the terrain folder inside the assets directory.
     pub path: String,
     /// The attachments of the terrain.
     pub attachments: Vec<AtlasAttachment>,
+    /// The nodes covered by the data of the terrain.
+    /// Nodes outside of this coverage are never requested.
     pub nodes: HashSet<NodeId>,
+    /// How the area, which is not covered by the nodes, is rendered.
+    pub coverage_fill: CoverageFill,
+    /// Whether the border of the terrain is closed by walls.
+    pub border_skirt: BorderSkirt,
+    /// The filter, with which the normals are reconstructed from the height attachment.
+    ///
+    /// The Catmull-Rom filter smooths the stair steps of quantized (`R16`) heights, which are
+    /// visible as banding on flat areas under low-angle light.
+    pub normal_filter: HeightFilter,
+    /// The filter, with which the heights of the vertices are sampled from the height attachment.
+    ///
+    /// The bicubic filters remove the faceted look of the bilinear filter at close range.
+    pub vertex_filter: HeightFilter,
+    /// Whether the shaded color is dithered, which breaks up the remaining banding of
+    /// smooth gradients.
+    pub deband_dither: bool,
+    /// The placement of the terrain inside a real-world coordinate reference system, if any.
+    pub georeference: Option<Georeference>,
+    /// The attachment, which masks out holes (e.g. tunnels, cellars) in the terrain, if any.
+    pub mask_attachment: Option<AttachmentIndex>,
+    /// The attachment, which stores the reference heights (e.g. a DTM or the original terrain),
+    /// the heights are compared against, if any.
+    pub reference_attachment: Option<AttachmentIndex>,
+    /// The attachment, which stores the signed heights of the seabed (scaled like the terrain
+    /// height), if any.
+    pub bathymetry_attachment: Option<AttachmentIndex>,
+    /// The height of the sea surface, below which the seabed of the bathymetry attachment is shown.
+    pub sea_level: f32,
+    /// The attachment, which stores the flow accumulation and the watersheds, if any.
+    pub flow_attachment: Option<AttachmentIndex>,
+    /// The attachment, which stores the heights of the ceiling of overhangs (scaled like the
+    /// terrain height), if any.
+    pub ceiling_attachment: Option<AttachmentIndex>,
+    /// The attachment, which stores the biome ids, if any.
+    pub biome_attachment: Option<AttachmentIndex>,
+    /// The biomes, which the ids of the biome attachment refer to.
+    pub biomes: Vec<Biome>,
 }
 
 impl TerrainConfig {
@@ -87,33 +193,71 @@ impl TerrainConfig {
         Self {
             lod_count,
             height,
+            height_offset: 0.0,
+            vertical_datum: default(),
             leaf_node_size: 0,
             terrain_size,
             node_atlas_size,
+            node_fade_frames: 0,
             path,
             attachments: vec![],
             nodes: HashSet::new(),
+            coverage_fill: default(),
+            border_skirt: default(),
+            normal_filter: default(),
+            vertex_filter: default(),
+            deband_dither: false,
+            georeference: None,
+            mask_attachment: None,
+            reference_attachment: None,
+            bathymetry_attachment: None,
+            sea_level: 0.0,
+            flow_attachment: None,
+            ceiling_attachment: None,
+            biome_attachment: None,
+            biomes: Vec::new(),
         }
     }
 }
 
 impl TerrainConfig {
+    /// Returns the absolute world height, to which a value of zero of the height attachment
+    /// corresponds.
+    ///
+    /// The offset of the vertical datum is converted into world units using the georeference,
+    /// if any.
+    pub fn elevation_offset(&self) -> f32 {
+        let unit_size = self
+            .georeference
+            .map_or(1.0, |georeference| georeference.unit_size);
+
+        self.height_offset + (self.vertical_datum.offset() / unit_size) as f32
+    }
+
     /// Adds an attachment to the terrain.
     ///
     /// The attachment will not be loaded automatically, but the caller has to handle the loading instead.
+    /// Panics, if the terrain already has [`MAX_ATTACHMENTS`] attachments.
     pub fn add_attachment(&mut self, attachment: AttachmentConfig) -> AttachmentIndex {
+        assert!(
+            self.attachments.len() < MAX_ATTACHMENTS,
+            "The attachment {} can not be added, since a terrain can not have more than {MAX_ATTACHMENTS} attachments.",
+            attachment.name
+        );
+
         self.attachments.push(attachment.into());
         self.attachments.len() - 1
     }
 
-    /// Adds an attachment to the terrain, which will be loaded from disk automatically.
-    pub fn add_attachment_from_disk(
+    /// Adds an already preprocessed attachment to the terrain, which will be loaded from disk
+    /// automatically.
+    ///
+    /// Unlike `add_attachment_from_disk`, this is available on the web as well.
+    pub fn load_attachment_from_disk(
         &mut self,
-        preprocessor: &mut Preprocessor,
         loader: &mut AttachmentFromDiskLoader,
         attachment: AttachmentConfig,
-        tile: TileConfig,
-    ) {
+    ) -> AttachmentIndex {
         let attachment_index = self.add_attachment(attachment.clone());
 
         loader.attachments.insert(
@@ -121,6 +265,41 @@ impl TerrainConfig {
             AttachmentFromDisk::new(&attachment, &self.path),
         );
 
+        attachment_index
+    }
+
+    /// Adds the already preprocessed base attachment to the terrain, which will be loaded from
+    /// disk automatically.
+    pub fn load_base_attachment_from_disk(
+        &mut self,
+        loader: &mut AttachmentFromDiskLoader,
+        base: BaseConfig,
+    ) {
+        self.leaf_node_size = base.texture_size - 2 * base.border_size;
+
+        loader.attachments.insert(
+            self.attachments.len(),
+            AttachmentFromDisk::new(&base.stored_height_attachment(), &self.path),
+        );
+        loader.attachments.insert(
+            self.attachments.len() + 1,
+            AttachmentFromDisk::new(&base.minmax_attachment(), &self.path),
+        );
+
+        self.add_base_attachment(base);
+    }
+
+    /// Adds an attachment to the terrain, which will be loaded from disk automatically.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        attachment: AttachmentConfig,
+        tile: TileConfig,
+    ) {
+        self.load_attachment_from_disk(loader, attachment.clone());
+
         preprocessor.attachments.push((tile, attachment));
     }
 
@@ -128,10 +307,11 @@ impl TerrainConfig {
     ///
     /// This is required by terrains, that use the default render pipeline.
     pub fn add_base_attachment(&mut self, base: BaseConfig) {
-        self.add_attachment(base.height_attachment());
+        self.add_attachment(base.stored_height_attachment());
         self.add_attachment(base.minmax_attachment());
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn add_base_attachment_from_disk(
         &mut self,
         preprocessor: &mut Preprocessor,
@@ -139,19 +319,289 @@ impl TerrainConfig {
         base: BaseConfig,
         tile: TileConfig,
     ) {
-        self.leaf_node_size = base.texture_size - 2 * base.border_size;
+        self.load_base_attachment_from_disk(loader, base);
 
-        loader.attachments.insert(
-            self.attachments.len(),
-            AttachmentFromDisk::new(&base.height_attachment(), &self.path),
-        );
-        loader.attachments.insert(
-            self.attachments.len() + 1,
-            AttachmentFromDisk::new(&base.minmax_attachment(), &self.path),
-        );
+        preprocessor.base = Some((tile, base));
+    }
 
-        self.add_base_attachment(base);
+    /// Adds a hole mask attachment, which will be loaded from disk automatically.
+    ///
+    /// Pixels with a mask value above one half are treated as holes. They are discarded by
+    /// the default shader and ignored by CPU height queries.
+    /// The default shader expects the mask to be the first attachment after the base attachment
+    /// and the mask has to store a single channel (`R16`, `R16F` or `R32F`).
+    /// Otherwise an error is returned and the attachment is not added.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_mask_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        attachment: AttachmentConfig,
+        tile: TileConfig,
+    ) -> TerrainResult<()> {
+        let attachment_index = self.attachments.len();
 
-        preprocessor.base = Some((tile, base));
+        if attachment_index != 2 {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The mask attachment {} has to be the first attachment after the base attachment.",
+                attachment.name
+            )));
+        }
+
+        if !matches!(
+            attachment.format,
+            AttachmentFormat::R16 | AttachmentFormat::R16F | AttachmentFormat::R32F
+        ) {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The mask attachment {} stores {:?} instead of a single channel.",
+                attachment.name, attachment.format
+            )));
+        }
+
+        self.add_attachment_from_disk(preprocessor, loader, attachment, tile);
+        self.mask_attachment = Some(attachment_index);
+
+        Ok(())
+    }
+
+    /// Adds a reference height attachment, which will be loaded from disk automatically.
+    ///
+    /// The default shader visualizes the signed difference between the height and the reference
+    /// height, if the `show_height_difference` debug view is enabled.
+    /// The default shader expects the reference to be the first attachment after the base
+    /// attachment and the mask attachment (if any).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_reference_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        attachment: AttachmentConfig,
+        tile: TileConfig,
+    ) {
+        self.add_attachment_from_disk(preprocessor, loader, attachment, tile);
+        self.reference_attachment = Some(self.attachments.len() - 1);
+    }
+
+    /// Adds a bathymetry attachment, which stores the heights of the seabed and will be loaded
+    /// from disk automatically.
+    ///
+    /// The default shader replaces the topography below the sea level with the seabed,
+    /// if the [`BathymetryMode`](crate::terrain_view::BathymetryMode) of the view is `TopoBathy`.
+    /// Only the attachments with the index two and three are supported by the default shader.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_bathymetry_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        attachment: AttachmentConfig,
+        tile: TileConfig,
+    ) {
+        self.add_attachment_from_disk(preprocessor, loader, attachment, tile);
+        self.bathymetry_attachment = Some(self.attachments.len() - 1);
+    }
+
+    /// Adds an experimental ceiling attachment, which stores a second height layer
+    /// (e.g. the underside of overhangs, arches or quarry walls) and will be loaded from disk
+    /// automatically.
+    ///
+    /// Where there is no overhang, the ceiling has to be equal to the height.
+    /// The default shader renders the ceiling as a second, double-sided surface wherever it lies
+    /// above the height. Only the attachments with the index two and three are supported by the
+    /// default shader.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_ceiling_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        attachment: AttachmentConfig,
+        tile: TileConfig,
+    ) {
+        self.add_attachment_from_disk(preprocessor, loader, attachment, tile);
+        self.ceiling_attachment = Some(self.attachments.len() - 1);
+    }
+
+    /// Adds an attachment derived from the height data, which will be loaded from disk automatically.
+    ///
+    /// The attachment is generated by the preprocessor after the base attachment.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_derivative_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        base: BaseConfig,
+        derivative: Derivative,
+    ) {
+        self.load_attachment_from_disk(loader, derivative.attachment(&base));
+
+        preprocessor.derivatives.push((base, derivative));
+    }
+
+    /// Adds the flow attachment, which stores the flow accumulation and the watersheds of the
+    /// terrain and will be loaded from disk automatically.
+    ///
+    /// The attachment is generated by the preprocessor after the derivatives.
+    /// The default shader visualizes it, if the `show_flow` or `show_watersheds` debug view is
+    /// enabled. Only the attachments with the index two and three are supported by the default
+    /// shader.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_flow_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        base: BaseConfig,
+        analysis: FlowAnalysis,
+    ) {
+        self.load_attachment_from_disk(loader, analysis.attachment(&base));
+        self.flow_attachment = Some(self.attachments.len() - 1);
+
+        preprocessor.flow = Some((base, analysis));
+    }
+
+    /// Adds the biome attachment, which stores the biome of each pixel of the terrain
+    /// and will be loaded from disk automatically.
+    ///
+    /// The attachment is generated by the preprocessor after the flow attachment.
+    /// The default shader colors the terrain by its biomes. Only the attachments with the
+    /// index two and three are supported by the default shader.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn add_biome_attachment_from_disk(
+        &mut self,
+        preprocessor: &mut Preprocessor,
+        loader: &mut AttachmentFromDiskLoader,
+        base: BaseConfig,
+        classification: BiomeClassification,
+    ) {
+        self.load_attachment_from_disk(loader, classification.attachment(&base));
+        self.biome_attachment = Some(self.attachments.len() - 1);
+        self.biomes = classification.biomes.clone();
+
+        preprocessor.biomes = Some((base, classification));
+    }
+
+    /// Removes an attachment from the terrain and shifts the indices of the following attachments.
+    pub(crate) fn remove_attachment(&mut self, attachment_index: AttachmentIndex) {
+        self.attachments.remove(attachment_index);
+
+        self.mask_attachment = self
+            .mask_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+        self.reference_attachment = self
+            .reference_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+        self.bathymetry_attachment = self
+            .bathymetry_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+        self.flow_attachment = self
+            .flow_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+        self.ceiling_attachment = self
+            .ceiling_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+        self.biome_attachment = self
+            .biome_attachment
+            .and_then(|index| shift_attachment_index(index, attachment_index));
+    }
+}
+
+/// An event, which adds an attachment to an already spawned terrain.
+///
+/// The attachment is loaded from the data directory of the terrain by the
+/// [`AttachmentFromDiskLoader`] for all nodes, that are currently present in the node atlas,
+/// as well as for all nodes, that are requested later on.
+/// Until the attachment of a node has finished loading, the node keeps being rendered with
+/// its previous attachments.
+/// The attachment is appended after the existing ones, so its index equals the previous
+/// attachment count.
+pub struct AddTerrainAttachment {
+    pub terrain: Entity,
+    pub attachment: AttachmentConfig,
+}
+
+/// An event, which removes an attachment from an already spawned terrain.
+///
+/// The indices of all following attachments are shifted down by one.
+/// Attachments at or before the attachment of a [`NodeReadback`] can not be removed.
+pub struct RemoveTerrainAttachment {
+    pub terrain: Entity,
+    pub attachment_index: AttachmentIndex,
+}
+
+/// Adds and removes the attachments of the terrains according to the
+/// [`AddTerrainAttachment`] and [`RemoveTerrainAttachment`] events.
+///
+/// The render world picks up the changed [`TerrainConfig`] and recreates the atlas textures
+/// and the bind group of the terrain.
+pub(crate) fn update_terrain_attachments(
+    mut add_events: EventReader<AddTerrainAttachment>,
+    mut remove_events: EventReader<RemoveTerrainAttachment>,
+    mut terrain_query: Query<(
+        &mut TerrainConfig,
+        &mut NodeAtlas,
+        Option<&mut AttachmentFromDiskLoader>,
+        Option<&mut ProceduralAttachmentLoader>,
+        Option<&NodeReadback>,
+    )>,
+) {
+    for AddTerrainAttachment {
+        terrain,
+        attachment,
+    } in add_events.iter()
+    {
+        let (mut config, mut node_atlas, mut loader) = match terrain_query.get_mut(*terrain) {
+            Ok((config, node_atlas, Some(loader), _, _)) => (config, node_atlas, loader),
+            _ => {
+                warn!("Can not add an attachment to {terrain:?}, since it is not a terrain loaded from disk.");
+                continue;
+            }
+        };
+
+        if config.attachments.len() >= MAX_ATTACHMENTS {
+            error!(
+                "Can not add the attachment {} to {terrain:?}, since it already has {MAX_ATTACHMENTS} attachments.",
+                attachment.name
+            );
+            continue;
+        }
+
+        let attachment_index = config.add_attachment(attachment.clone());
+        loader.insert_attachment(attachment_index, attachment, &config.path);
+        node_atlas.add_attachment(config.attachments[attachment_index].clone());
+    }
+
+    for &RemoveTerrainAttachment {
+        terrain,
+        attachment_index,
+    } in remove_events.iter()
+    {
+        let (mut config, mut node_atlas, disk_loader, procedural_loader, readback) =
+            match terrain_query.get_mut(terrain) {
+                Ok(components) => components,
+                Err(_) => {
+                    warn!(
+                        "Can not remove an attachment from {terrain:?}, since it is not a terrain."
+                    );
+                    continue;
+                }
+            };
+
+        if attachment_index >= config.attachments.len() {
+            warn!("Can not remove the attachment {attachment_index} from {terrain:?}, since it does not exist.");
+            continue;
+        }
+
+        if matches!(readback, Some(readback) if readback.attachment_index >= attachment_index) {
+            warn!("Can not remove the attachment {attachment_index} from {terrain:?}, since it is read back.");
+            continue;
+        }
+
+        config.remove_attachment(attachment_index);
+        node_atlas.remove_attachment(attachment_index);
+
+        if let Some(mut loader) = disk_loader {
+            loader.remove_attachment(attachment_index);
+        }
+        if let Some(mut loader) = procedural_loader {
+            loader.remove_attachment(attachment_index);
+        }
     }
 }
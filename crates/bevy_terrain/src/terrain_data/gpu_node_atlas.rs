@@ -1,5 +1,5 @@
 use crate::{
-    terrain::{Terrain, TerrainComponents},
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
     terrain_data::{
         node_atlas::{LoadingNode, NodeAtlas},
         AtlasAttachment, AtlasIndex,
@@ -15,7 +15,7 @@ use bevy::{
         Extract, MainWorld,
     },
 };
-use std::mem;
+use std::{mem, num::NonZeroU32};
 
 impl AtlasAttachment {
     /// Creates the attachment from its config.
@@ -36,7 +36,10 @@ impl AtlasAttachment {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: self.format,
-            usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+            usage: TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::RENDER_ATTACHMENT,
         });
 
         images.insert(
@@ -58,12 +61,27 @@ impl AtlasAttachment {
 /// alongside the data to update it.
 ///
 /// All attachments of newly loaded nodes are copied into their according atlas attachment.
+///
+/// Additionally the frame, in which each slot of the atlas has been uploaded, is tracked,
+/// so that newly loaded nodes can be cross-faded with the coarser data they replace.
+/// The progress of this fade is stored per slot in the fade texture.
 #[derive(Component)]
 pub struct GpuNodeAtlas {
     /// Stores the atlas attachments of the terrain.
     pub(crate) attachments: Vec<(AtlasAttachment, Handle<Image>)>,
     /// Stores the nodes, that have finished loading this frame.
     pub(crate) loaded_nodes: Vec<LoadingNode>,
+    /// The view of the fade texture, which stores the fade progress of each slot.
+    pub(crate) fade_view: TextureView,
+    fade_texture: Texture,
+    /// The number of frames, over which newly uploaded nodes are faded in.
+    fade_frames: u32,
+    /// The frame, in which each slot of the atlas has been uploaded last, if any.
+    upload_frames: Vec<Option<u32>>,
+    /// The number of frames, which have been rendered so far.
+    frame: u32,
+    /// Whether or not the fade texture has to be rewritten this frame.
+    fading: bool,
 }
 
 impl GpuNodeAtlas {
@@ -84,25 +102,95 @@ impl GpuNodeAtlas {
             })
             .collect();
 
+        let fade_texture = device.create_texture(&TextureDescriptor {
+            label: "node_fade_texture".into(),
+            size: Extent3d {
+                width: node_atlas.size as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
         Self {
             attachments,
             loaded_nodes: Vec::new(),
+            fade_view: fade_texture.create_view(&default()),
+            fade_texture,
+            fade_frames: 0,
+            upload_frames: vec![None; node_atlas.size as usize],
+            frame: 0,
+            // slots, which have never been uploaded, are fully faded in
+            fading: true,
+        }
+    }
+
+    /// Creates the textures of newly added attachments and releases the ones of removed attachments.
+    fn update_attachments(
+        &mut self,
+        device: &RenderDevice,
+        images: &mut RenderAssets<Image>,
+        node_atlas: &NodeAtlas,
+    ) {
+        let unchanged = self.attachments.len() == node_atlas.attachments.len()
+            && self
+                .attachments
+                .iter()
+                .zip(&node_atlas.attachments)
+                .all(|((_, handle), attachment)| *handle == attachment.handle);
+
+        if unchanged {
+            return;
+        }
+
+        let mut previous = mem::take(&mut self.attachments);
+
+        self.attachments = node_atlas
+            .attachments
+            .iter()
+            .map(|attachment| {
+                match previous
+                    .iter()
+                    .position(|(_, handle)| *handle == attachment.handle)
+                {
+                    Some(index) => previous.swap_remove(index),
+                    None => (
+                        attachment.clone(),
+                        attachment.create(device, images, node_atlas.size),
+                    ),
+                }
+            })
+            .collect();
+
+        for (_, handle) in previous {
+            images.remove(&handle);
         }
     }
 
     /// Updates the atlas attachments, by copying over the data of the nodes that have
     /// finished loading this frame.
     fn update(&mut self, command_encoder: &mut CommandEncoder, images: &RenderAssets<Image>) {
+        self.frame += 1;
+
         for node in self.loaded_nodes.drain(..) {
+            if self.fade_frames > 0 {
+                self.upload_frames[node.atlas_index as usize] = Some(self.frame);
+                self.fading = true;
+            }
+
+            // nodes, which have been reloaded for a new attachment, may only contain some attachments
             for (attachment, node_handle, atlas_handle) in
-                self.attachments
-                    .iter()
-                    .enumerate()
-                    .map(|(index, (attachment, atlas_handle))| {
-                        let node_handle = node.attachments.get(&index).unwrap();
+                self.attachments.iter().enumerate().filter_map(
+                    |(index, (attachment, atlas_handle))| {
+                        let node_handle = node.attachments.get(&index)?;
 
-                        (attachment, node_handle, atlas_handle)
-                    })
+                        Some((attachment, node_handle, atlas_handle))
+                    },
+                )
             {
                 if let (Some(node_attachment), Some(atlas_attachment)) =
                     (images.get(node_handle), images.get(atlas_handle))
@@ -139,6 +227,52 @@ impl GpuNodeAtlas {
             }
         }
     }
+
+    /// Writes the fade progress of all slots into the fade texture, while any of them is fading.
+    fn update_fade(&mut self, queue: &RenderQueue) {
+        if !self.fading {
+            return;
+        }
+
+        let mut fading = false;
+
+        let texels: Vec<u8> = self
+            .upload_frames
+            .iter()
+            .map(|upload_frame| {
+                let fade = upload_frame.map_or(1.0, |upload_frame| {
+                    (self.frame - upload_frame) as f32 / self.fade_frames as f32
+                });
+
+                fading |= fade < 1.0;
+
+                (fade.min(1.0) * 255.0).round() as u8
+            })
+            .collect();
+
+        // the fully faded in texels are written once more, before the updates stop
+        self.fading = fading;
+
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.fade_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &texels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(texels.len() as u32),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: texels.len() as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 }
 
 /// Initializes the [`GpuNodeAtlas`] of newly created terrains.
@@ -157,15 +291,19 @@ pub(crate) fn initialize_gpu_node_atlas(
 }
 
 /// Extracts the nodes that have finished loading from all [`NodeAtlas`]es into the
-/// corresponding [`GpuNodeAtlas`]es.
+/// corresponding [`GpuNodeAtlas`]es and synchronizes their attachments.
 pub(crate) fn extract_node_atlas(
+    device: Res<RenderDevice>,
+    mut images: ResMut<RenderAssets<Image>>,
     mut main_world: ResMut<MainWorld>,
     mut gpu_node_atlases: ResMut<TerrainComponents<GpuNodeAtlas>>,
 ) {
-    let mut terrain_query = main_world.query::<(Entity, &mut NodeAtlas)>();
+    let mut terrain_query = main_world.query::<(Entity, &mut NodeAtlas, &TerrainConfig)>();
 
-    for (terrain, mut node_atlas) in terrain_query.iter_mut(&mut main_world) {
+    for (terrain, mut node_atlas, config) in terrain_query.iter_mut(&mut main_world) {
         let gpu_node_atlas = gpu_node_atlases.get_mut(&terrain).unwrap();
+        gpu_node_atlas.update_attachments(&device, &mut images, &node_atlas);
+        gpu_node_atlas.fade_frames = config.node_fade_frames;
         mem::swap(
             &mut node_atlas.loaded_nodes,
             &mut gpu_node_atlas.loaded_nodes,
@@ -174,7 +312,7 @@ pub(crate) fn extract_node_atlas(
 }
 
 /// Queues the attachments of the nodes that have finished loading to be copied into the
-/// corresponding atlas attachments.
+/// corresponding atlas attachments and updates the fade progress of the atlas slots.
 pub(crate) fn queue_node_atlas_updates(
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
@@ -187,6 +325,7 @@ pub(crate) fn queue_node_atlas_updates(
     for terrain in terrain_query.iter() {
         let gpu_node_atlas = gpu_node_atlases.get_mut(&terrain).unwrap();
         gpu_node_atlas.update(&mut command_encoder, &images);
+        gpu_node_atlas.update_fade(&queue);
     }
 
     queue.submit(vec![command_encoder.finish()]);
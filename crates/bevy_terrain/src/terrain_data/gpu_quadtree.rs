@@ -1,7 +1,5 @@
 use crate::{
-    terrain::Terrain,
     terrain_data::quadtree::{Quadtree, QuadtreeEntry},
-    terrain_view::TerrainView,
     TerrainViewComponents,
 };
 use bevy::{
@@ -101,23 +99,27 @@ impl GpuQuadtree {
     }
 }
 
-/// Initializes the [`GpuQuadtree`] of newly created terrains.
+/// Initializes the [`GpuQuadtree`]s of newly created terrain views
+/// and removes the ones of despawned views.
 pub(crate) fn initialize_gpu_quadtree(
     device: Res<RenderDevice>,
     mut images: ResMut<RenderAssets<Image>>,
     mut gpu_quadtrees: ResMut<TerrainViewComponents<GpuQuadtree>>,
     quadtrees: Extract<Res<TerrainViewComponents<Quadtree>>>,
-    view_query: Extract<Query<Entity, With<TerrainView>>>,
-    terrain_query: Extract<Query<Entity, Added<Terrain>>>,
 ) {
-    for terrain in terrain_query.iter() {
-        for view in view_query.iter() {
-            let quadtree = quadtrees.get(&(terrain, view)).unwrap();
+    gpu_quadtrees.0.retain(|key, gpu_quadtree| {
+        let retain = quadtrees.0.contains_key(key);
 
-            gpu_quadtrees.insert(
-                (terrain, view),
-                GpuQuadtree::new(&device, &mut images, &quadtree),
-            );
+        if !retain {
+            images.remove(&gpu_quadtree.handle);
+        }
+
+        retain
+    });
+
+    for (&key, quadtree) in &quadtrees.0 {
+        if gpu_quadtrees.get(&key).is_none() {
+            gpu_quadtrees.insert(key, GpuQuadtree::new(&device, &mut images, quadtree));
         }
     }
 }
@@ -126,18 +128,13 @@ pub(crate) fn initialize_gpu_quadtree(
 pub(crate) fn extract_quadtree(
     mut gpu_quadtrees: ResMut<TerrainViewComponents<GpuQuadtree>>,
     quadtrees: Extract<Res<TerrainViewComponents<Quadtree>>>,
-    view_query: Extract<Query<Entity, With<TerrainView>>>,
-    terrain_query: Extract<Query<Entity, With<Terrain>>>,
 ) {
-    for terrain in terrain_query.iter() {
-        for view in view_query.iter() {
-            let quadtree = quadtrees.get(&(terrain, view)).unwrap();
-            let gpu_quadtree = gpu_quadtrees.get_mut(&(terrain, view)).unwrap();
+    for (key, gpu_quadtree) in &mut gpu_quadtrees.0 {
+        let quadtree = quadtrees.get(key).unwrap();
 
-            // Todo: enable this again once mutable access to the main world in extract is less painful
-            // mem::swap(&mut gpu_quadtree.data, &mut gpu_gpu_quadtree.data);
-            gpu_quadtree.data = quadtree.data.clone();
-        }
+        // Todo: enable this again once mutable access to the main world in extract is less painful
+        // mem::swap(&mut gpu_quadtree.data, &mut gpu_gpu_quadtree.data);
+        gpu_quadtree.data = quadtree.data.clone();
     }
 }
 
@@ -146,13 +143,8 @@ pub(crate) fn queue_quadtree_update(
     queue: Res<RenderQueue>,
     images: Res<RenderAssets<Image>>,
     mut gpu_quadtrees: ResMut<TerrainViewComponents<GpuQuadtree>>,
-    view_query: Query<Entity, With<TerrainView>>,
-    terrain_query: Query<Entity, With<Terrain>>,
 ) {
-    for terrain in terrain_query.iter() {
-        for view in view_query.iter() {
-            let gpu_quadtree = gpu_quadtrees.get_mut(&(terrain, view)).unwrap();
-            gpu_quadtree.update(&queue, &images);
-        }
+    for gpu_quadtree in gpu_quadtrees.0.values_mut() {
+        gpu_quadtree.update(&queue, &images);
     }
 }
@@ -0,0 +1,474 @@
+use crate::{
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_data::{
+        calc_node_id,
+        virtual_texture::{
+            PageId, PageTableEntry, PageUpload, TerrainVirtualTexture, MAX_VIRTUAL_TEXTURE_LODS,
+        },
+    },
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        MainWorld,
+    },
+};
+use bytemuck::cast_slice;
+use ndarray::Array3;
+use std::{
+    mem,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use wgpu::Maintain;
+
+/// The number of frames, after which every fragment has written its page request once.
+/// Each frame only one fragment of each 4x4 block of pixels writes into the feedback buffer.
+const FEEDBACK_SLOTS: u32 = 16;
+
+#[derive(Clone, Default, ShaderType)]
+struct VirtualTextureUniform {
+    lod_count: u32,
+    page_table_size: u32,
+    cache_size: u32,
+    feedback_slot: u32,
+    page_size: f32,
+    border_size: f32,
+    leaf_page_size: f32,
+    lod_bias: f32,
+    /// The page coordinate of the first page of the page table per lod.
+    origins: [IVec4; MAX_VIRTUAL_TEXTURE_LODS],
+}
+
+/// A copy of the feedback buffer, which is read back to the CPU.
+struct FeedbackReadback {
+    /// The origins of the page table, with which the feedback has been written.
+    origins: Vec<IVec2>,
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+}
+
+impl FeedbackReadback {
+    /// Starts mapping the staging buffer, after the copy has been submitted.
+    fn map(&self) {
+        let mapped = self.mapped.clone();
+
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+/// Stores the GPU representation of the [`TerrainVirtualTexture`] (page table, page cache and
+/// feedback buffer) alongside the data to update it.
+///
+/// Terrains without a virtual texture are bound to tiny placeholder resources.
+pub struct GpuVirtualTexture {
+    pub(crate) uniform_buffer: Buffer,
+    pub(crate) page_table_view: TextureView,
+    pub(crate) page_cache_view: TextureView,
+    pub(crate) feedback_buffer: Buffer,
+    page_table_texture: Texture,
+    page_cache_texture: Texture,
+    /// Whether the terrain has a virtual texture, or only the placeholder resources.
+    pub(crate) active: bool,
+    uniform: VirtualTextureUniform,
+    /// The current cpu page table. This is synced each frame with the page table of the
+    /// [`TerrainVirtualTexture`].
+    page_table: Array3<PageTableEntry>,
+    /// The pages, which still have to be copied into the page cache.
+    uploads: Vec<PageUpload>,
+    page_texture_size: u32,
+    feedback_size: BufferAddress,
+    origins: Vec<IVec2>,
+    /// The origins of the page table, with which the feedback buffer has been written,
+    /// since it has been cleared.
+    recorded_origins: Option<Vec<IVec2>>,
+    readback: Option<FeedbackReadback>,
+    feedback: Arc<Mutex<Option<Vec<PageId>>>>,
+    frame: u32,
+}
+
+impl GpuVirtualTexture {
+    fn new(
+        device: &RenderDevice,
+        virtual_texture: Option<(&TerrainVirtualTexture, &TerrainConfig)>,
+    ) -> Self {
+        let max_size = device.limits().max_texture_dimension_2d;
+
+        // the page cache has to fit into a single texture
+        let virtual_texture = virtual_texture.filter(|(virtual_texture, _)| {
+            let page_attachment = virtual_texture.config.page_attachment();
+            let fits = virtual_texture.cache_size * page_attachment.texture_size <= max_size;
+
+            if !fits {
+                warn!("The page cache of the virtual texture exceeds the maximum texture size of the device.");
+            }
+
+            fits
+        });
+
+        // terrains without a virtual texture are covered by a single empty page
+        let (lod_count, page_table_size, cache_size, page_texture_size, format) =
+            match virtual_texture {
+                Some((virtual_texture, config)) => (
+                    config.lod_count.min(MAX_VIRTUAL_TEXTURE_LODS as u32),
+                    virtual_texture.page_table_size,
+                    virtual_texture.cache_size,
+                    virtual_texture.config.page_attachment().texture_size,
+                    virtual_texture.format(),
+                ),
+                None => (1, 1, 1, 1, TextureFormat::Rgba8UnormSrgb),
+            };
+
+        let page_table_texture = device.create_texture(&TextureDescriptor {
+            label: "page_table_texture".into(),
+            size: Extent3d {
+                width: page_table_size,
+                height: page_table_size,
+                depth_or_array_layers: lod_count,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rg16Uint,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        let page_cache_texture = device.create_texture(&TextureDescriptor {
+            label: "page_cache_texture".into(),
+            size: Extent3d {
+                width: cache_size * page_texture_size,
+                height: cache_size * page_texture_size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        });
+
+        // one bit per entry of the page table
+        let feedback_size =
+            ((lod_count * page_table_size * page_table_size + 31) / 32 * 4) as BufferAddress;
+
+        let feedback_buffer = device.create_buffer(&BufferDescriptor {
+            label: "page_feedback_buffer".into(),
+            size: feedback_size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform = match virtual_texture {
+            Some((virtual_texture, _)) => VirtualTextureUniform {
+                lod_count,
+                page_table_size,
+                cache_size,
+                page_size: virtual_texture.config.page_size as f32,
+                border_size: virtual_texture.config.attachment.border_size as f32,
+                ..default()
+            },
+            None => default(),
+        };
+
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&uniform).unwrap();
+
+        let uniform_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "virtual_texture_buffer".into(),
+            contents: &buffer.into_inner(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        Self {
+            uniform_buffer,
+            // a single layer would be viewed as a regular texture otherwise
+            page_table_view: page_table_texture.create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2Array),
+                ..default()
+            }),
+            page_cache_view: page_cache_texture.create_view(&default()),
+            feedback_buffer,
+            page_table_texture,
+            page_cache_texture,
+            active: virtual_texture.is_some(),
+            uniform,
+            page_table: default(),
+            uploads: Vec::new(),
+            page_texture_size,
+            feedback_size,
+            origins: Vec::new(),
+            recorded_origins: None,
+            readback: None,
+            feedback: virtual_texture.map_or_else(default, |(virtual_texture, _)| {
+                virtual_texture.feedback.clone()
+            }),
+            frame: 0,
+        }
+    }
+
+    /// Takes over the pages, which have finished loading, and the current page table.
+    fn extract(&mut self, virtual_texture: &mut TerrainVirtualTexture) {
+        let uploads = mem::take(&mut virtual_texture.uploads);
+
+        // pending uploads of evicted pages must not overwrite their successors
+        self.uploads
+            .retain(|pending| uploads.iter().all(|upload| upload.slot != pending.slot));
+        self.uploads.extend(uploads);
+
+        self.page_table = virtual_texture.page_table.clone();
+        self.origins = virtual_texture.origins.clone();
+        self.uniform.leaf_page_size = virtual_texture.leaf_page_size;
+        self.uniform.lod_bias = virtual_texture.lod_bias;
+
+        for (origin, &position) in self.uniform.origins.iter_mut().zip(&self.origins) {
+            *origin = position.extend(0).extend(0);
+        }
+    }
+
+    /// Passes the pages of the mapped readback on to the [`TerrainVirtualTexture`].
+    fn finish(&mut self) {
+        let readback = match &self.readback {
+            Some(readback) if readback.mapped.load(Ordering::Acquire) => readback,
+            _ => return,
+        };
+
+        let size = self.uniform.page_table_size;
+        let layer_size = size * size;
+
+        let requests: Vec<PageId> =
+            cast_slice::<u8, u32>(&readback.buffer.slice(..).get_mapped_range())
+                .iter()
+                .enumerate()
+                .flat_map(|(index, &word)| {
+                    (0..32)
+                        .filter(move |bit| word & (1 << bit) != 0)
+                        .map(move |bit| index as u32 * 32 + bit)
+                })
+                .filter_map(|index| {
+                    let lod = index / layer_size;
+                    let origin = *readback.origins.get(lod as usize)?;
+
+                    // the page table wraps around, thus the entry belongs to the page,
+                    // which lies inside of the page table
+                    let x = origin.x + ((index % size) as i32 - origin.x).rem_euclid(size as i32);
+                    let y = origin.y
+                        + ((index % layer_size / size) as i32 - origin.y).rem_euclid(size as i32);
+
+                    (x >= 0 && y >= 0).then(|| calc_node_id(lod, x as u32, y as u32))
+                })
+                .collect();
+
+        readback.buffer.unmap();
+
+        self.feedback
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Vec::new)
+            .extend(requests);
+        self.readback = None;
+    }
+
+    /// Copies the loaded pages into the page cache and starts reading back the feedback,
+    /// which has been written during the previous frame.
+    fn update(&mut self, device: &RenderDevice, queue: &RenderQueue, images: &RenderAssets<Image>) {
+        self.finish();
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+        let mut readback = None;
+
+        // only one readback is in flight at a time
+        if self.readback.is_none() {
+            if let Some(origins) = self.recorded_origins.take() {
+                let buffer = device.create_buffer(&BufferDescriptor {
+                    label: "page_feedback_staging_buffer".into(),
+                    size: self.feedback_size,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                });
+
+                command_encoder.copy_buffer_to_buffer(
+                    &self.feedback_buffer,
+                    0,
+                    &buffer,
+                    0,
+                    self.feedback_size,
+                );
+                command_encoder.clear_buffer(&self.feedback_buffer, 0, None);
+
+                readback = Some(FeedbackReadback {
+                    origins,
+                    buffer,
+                    mapped: default(),
+                });
+            }
+        } else if self.recorded_origins.as_ref() != Some(&self.origins) {
+            // the requests can only be decoded with the origins they have been written with
+            command_encoder.clear_buffer(&self.feedback_buffer, 0, None);
+        }
+
+        self.recorded_origins = Some(self.origins.clone());
+
+        let cache_size = self.uniform.cache_size;
+        let page_texture_size = self.page_texture_size;
+        let page_cache_texture = &self.page_cache_texture;
+
+        self.uploads.retain(|upload| {
+            // the page has not been prepared yet
+            let page = match images.get(&upload.handle) {
+                Some(page) => page,
+                None => return true,
+            };
+
+            command_encoder.copy_texture_to_texture(
+                ImageCopyTexture {
+                    texture: &page.texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyTexture {
+                    texture: page_cache_texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: upload.slot as u32 % cache_size * page_texture_size,
+                        y: upload.slot as u32 / cache_size * page_texture_size,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                Extent3d {
+                    width: page_texture_size,
+                    height: page_texture_size,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            false
+        });
+
+        queue.submit(vec![command_encoder.finish()]);
+
+        if let Some(readback) = readback {
+            readback.map();
+            self.readback = Some(readback);
+        }
+
+        let size = self.uniform.page_table_size;
+
+        if self.page_table.dim()
+            == (
+                self.uniform.lod_count as usize,
+                size as usize,
+                size as usize,
+            )
+        {
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.page_table_texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                cast_slice(self.page_table.as_slice().unwrap()),
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(size * 4),
+                    rows_per_image: NonZeroU32::new(size),
+                },
+                Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: self.uniform.lod_count,
+                },
+            );
+        }
+
+        // the fragments, which write their requests, rotate each frame
+        self.uniform.feedback_slot = self.frame % FEEDBACK_SLOTS;
+        self.frame += 1;
+
+        let mut buffer = encase::UniformBuffer::new(Vec::new());
+        buffer.write(&self.uniform).unwrap();
+        queue.write_buffer(&self.uniform_buffer, 0, &buffer.into_inner());
+    }
+}
+
+/// Initializes the [`GpuVirtualTexture`]s of newly created terrains, removes the ones of
+/// despawned terrains and extracts the loaded pages and page tables of the
+/// [`TerrainVirtualTexture`]s.
+pub(crate) fn extract_virtual_textures(
+    device: Res<RenderDevice>,
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_virtual_textures: ResMut<TerrainComponents<GpuVirtualTexture>>,
+) {
+    let mut terrain_query = main_world.query_filtered::<(
+        Entity,
+        &TerrainConfig,
+        Option<&mut TerrainVirtualTexture>,
+    ), With<Terrain>>();
+
+    let mut terrains = Vec::new();
+
+    for (terrain, config, virtual_texture) in terrain_query.iter_mut(&mut main_world) {
+        terrains.push(terrain);
+
+        if gpu_virtual_textures.get(&terrain).is_none() {
+            let gpu_virtual_texture = GpuVirtualTexture::new(
+                &device,
+                virtual_texture
+                    .as_deref()
+                    .map(|virtual_texture| (virtual_texture, config)),
+            );
+            gpu_virtual_textures.insert(terrain, gpu_virtual_texture);
+        }
+
+        let mut virtual_texture = skip_none!(virtual_texture);
+        let gpu_virtual_texture = gpu_virtual_textures.get_mut(&terrain).unwrap();
+
+        if !gpu_virtual_texture.active {
+            if virtual_texture.is_added() {
+                warn!(
+                    "The virtual texture of a terrain has to be added when spawning the terrain."
+                );
+            }
+            continue;
+        }
+
+        gpu_virtual_texture.extract(&mut virtual_texture);
+    }
+
+    gpu_virtual_textures
+        .0
+        .retain(|terrain, _| terrains.contains(terrain));
+}
+
+/// Queues the loaded pages to be copied into the page caches, the readback of the feedback
+/// and the update of the page tables.
+pub(crate) fn queue_virtual_textures(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    images: Res<RenderAssets<Image>>,
+    mut gpu_virtual_textures: ResMut<TerrainComponents<GpuVirtualTexture>>,
+) {
+    device.wgpu_device().poll(Maintain::Poll);
+
+    for gpu_virtual_texture in gpu_virtual_textures.0.values_mut() {
+        if gpu_virtual_texture.active {
+            gpu_virtual_texture.update(&device, &queue, &images);
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Bulk height and normal queries, which are evaluated on the GPU from the [`GpuNodeAtlas`].
+//!
+//! Systems that require thousands of height lookups each frame (crowds, foliage physics)
+//! can submit all of their positions at once, instead of sampling the CPU copy of the terrain
+//! one by one. The results become available one frame after they have been submitted.
+//!
+//! [`GpuNodeAtlas`]: crate::terrain_data::gpu_node_atlas::GpuNodeAtlas
+
+use crate::{
+    render::{
+        culling::CullingBindGroup,
+        shaders::HEIGHT_QUERY_SHADER,
+        terrain_data::{terrain_bind_group_layout, TerrainData},
+        terrain_view_data::TerrainViewData,
+        CULL_DATA_LAYOUT, HEIGHT_QUERY_LAYOUT, HEIGHT_QUERY_RESULT_SIZE, REFINE_TILES_LAYOUT,
+    },
+    skip_none,
+    terrain::{Terrain, TerrainComponents},
+    terrain_view::TerrainViewComponents,
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        MainWorld,
+    },
+};
+use bytemuck::cast_slice;
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use wgpu::Maintain;
+
+/// The height and normal of the terrain at a queried position.
+#[derive(Clone, Copy, Debug)]
+pub struct HeightQueryResult {
+    /// The queried position in the horizontal (x, z) plane.
+    pub position: Vec2,
+    /// The height of the terrain at the position.
+    pub height: f32,
+    /// The normal of the terrain at the position.
+    pub normal: Vec3,
+}
+
+/// Queries the height and normal of the terrain at many positions at once on the GPU.
+///
+/// Add this component to a terrain entity and [`submit`](Self::submit) the positions each frame.
+/// The heights are looked up using the quadtree of the view, thus positions far away from
+/// the viewer are answered with coarser data.
+/// While a batch is still being read back, only the most recent submission is kept.
+#[derive(Component)]
+pub struct TerrainHeightQuery {
+    /// The view, whose quadtree is used to look up the nodes.
+    pub view: Entity,
+    /// The positions submitted since the last extraction.
+    pub(crate) pending_positions: Option<Vec<Vec2>>,
+    /// The results of the most recently finished batch.
+    results: Vec<HeightQueryResult>,
+    /// Shared with the render world, which stores the finished batch.
+    pub(crate) finished: Arc<Mutex<Option<Vec<HeightQueryResult>>>>,
+}
+
+impl TerrainHeightQuery {
+    /// Creates a new height query, which looks up the heights using the quadtree of the view.
+    pub fn new(view: Entity) -> Self {
+        Self {
+            view,
+            pending_positions: None,
+            results: default(),
+            finished: default(),
+        }
+    }
+
+    /// Submits the positions in the horizontal (x, z) plane, whose heights will be queried.
+    pub fn submit(&mut self, positions: Vec<Vec2>) {
+        self.pending_positions = Some(positions);
+    }
+
+    /// Returns the results of the most recently finished batch.
+    pub fn results(&self) -> &[HeightQueryResult] {
+        &self.results
+    }
+}
+
+/// Collects the results, that have been read back in the meantime.
+pub(crate) fn update_height_query(
+    mut terrain_query: Query<&mut TerrainHeightQuery, With<Terrain>>,
+) {
+    for mut height_query in terrain_query.iter_mut() {
+        let finished = height_query.finished.lock().unwrap().take();
+
+        if let Some(results) = finished {
+            height_query.results = results;
+        }
+    }
+}
+
+/// The pipeline used to evaluate the height queries.
+#[derive(Resource)]
+pub struct HeightQueryPipeline {
+    query_layout: BindGroupLayout,
+    pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for HeightQueryPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let refine_tiles_layout = device.create_bind_group_layout(&REFINE_TILES_LAYOUT);
+        let cull_data_layout = device.create_bind_group_layout(&CULL_DATA_LAYOUT);
+        let terrain_layout = terrain_bind_group_layout(device);
+        let query_layout = device.create_bind_group_layout(&HEIGHT_QUERY_LAYOUT);
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let pipeline_id = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("height_query_pipeline".into()),
+            layout: Some(vec![
+                refine_tiles_layout,
+                cull_data_layout,
+                terrain_layout,
+                query_layout.clone(),
+            ]),
+            shader: HEIGHT_QUERY_SHADER.typed(),
+            shader_defs: vec![],
+            entry_point: "query_height".into(),
+        });
+
+        Self {
+            query_layout,
+            pipeline_id,
+        }
+    }
+}
+
+/// A batch of queries, whose results are waiting to be mapped.
+struct HeightQueryBatch {
+    positions: Vec<Vec2>,
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+}
+
+impl HeightQueryBatch {
+    /// Starts mapping the staging buffer, after the queries have been submitted.
+    fn map(&self) {
+        let mapped = self.mapped.clone();
+
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+/// Stores the render world side of the [`TerrainHeightQuery`].
+pub struct GpuHeightQuery {
+    view: Entity,
+    pending_positions: Option<Vec<Vec2>>,
+    batch: Option<HeightQueryBatch>,
+    finished: Arc<Mutex<Option<Vec<HeightQueryResult>>>>,
+}
+
+impl GpuHeightQuery {
+    /// Records the evaluation of the pending positions and the copy of the results
+    /// into a staging buffer.
+    fn query(
+        &mut self,
+        device: &RenderDevice,
+        command_encoder: &mut CommandEncoder,
+        pipeline: &ComputePipeline,
+        query_layout: &BindGroupLayout,
+        bind_groups: (&BindGroup, &BindGroup, &BindGroup),
+    ) -> Option<HeightQueryBatch> {
+        let positions = self.pending_positions.take()?;
+
+        if positions.is_empty() {
+            return None;
+        }
+
+        let size = positions.len() as BufferAddress * HEIGHT_QUERY_RESULT_SIZE;
+
+        let position_buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: "height_query_position_buffer".into(),
+            contents: cast_slice(&positions),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let result_buffer = device.create_buffer(&BufferDescriptor {
+            label: "height_query_result_buffer".into(),
+            size,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let staging_buffer = device.create_buffer(&BufferDescriptor {
+            label: "height_query_staging_buffer".into(),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let query_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: position_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: result_buffer.as_entire_binding(),
+                },
+            ],
+            layout: query_layout,
+        });
+
+        {
+            let (refine_tiles_bind_group, culling_bind_group, terrain_bind_group) = bind_groups;
+            let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
+
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, refine_tiles_bind_group, &[]);
+            pass.set_bind_group(1, culling_bind_group, &[]);
+            pass.set_bind_group(2, terrain_bind_group, &[]);
+            pass.set_bind_group(3, &query_bind_group, &[]);
+            pass.dispatch_workgroups((positions.len() as u32 + 63) / 64, 1, 1);
+        }
+
+        command_encoder.copy_buffer_to_buffer(&result_buffer, 0, &staging_buffer, 0, size);
+
+        Some(HeightQueryBatch {
+            positions,
+            buffer: staging_buffer,
+            mapped: default(),
+        })
+    }
+
+    /// Moves the results of the mapped batch into the CPU results.
+    fn finish(&mut self) {
+        let batch = match &self.batch {
+            Some(batch) if batch.mapped.load(Ordering::Acquire) => batch,
+            _ => return,
+        };
+
+        let results = cast_slice::<u8, [f32; 4]>(&batch.buffer.slice(..).get_mapped_range())
+            .iter()
+            .zip(&batch.positions)
+            .map(|(&[x, y, z, height], &position)| HeightQueryResult {
+                position,
+                height,
+                normal: Vec3::new(x, y, z),
+            })
+            .collect();
+
+        batch.buffer.unmap();
+
+        *self.finished.lock().unwrap() = Some(results);
+        self.batch = None;
+    }
+}
+
+/// Extracts the submitted positions into the [`GpuHeightQuery`]s.
+pub(crate) fn extract_height_query(
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_height_queries: ResMut<TerrainComponents<GpuHeightQuery>>,
+) {
+    let mut terrain_query = main_world.query::<(Entity, &mut TerrainHeightQuery)>();
+
+    for (terrain, mut height_query) in terrain_query.iter_mut(&mut main_world) {
+        let pending_positions = mem::take(&mut height_query.pending_positions);
+
+        if let Some(gpu_height_query) = gpu_height_queries.get_mut(&terrain) {
+            gpu_height_query.view = height_query.view;
+
+            if pending_positions.is_some() {
+                gpu_height_query.pending_positions = pending_positions;
+            }
+        } else {
+            gpu_height_queries.insert(
+                terrain,
+                GpuHeightQuery {
+                    view: height_query.view,
+                    pending_positions,
+                    batch: None,
+                    finished: height_query.finished.clone(),
+                },
+            );
+        }
+    }
+}
+
+/// Queues the evaluation of the pending positions and collects the finished results.
+pub(crate) fn queue_height_query(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline_cache: Res<PipelineCache>,
+    height_query_pipeline: Res<HeightQueryPipeline>,
+    terrain_data: Res<TerrainComponents<TerrainData>>,
+    terrain_view_data: Res<TerrainViewComponents<TerrainViewData>>,
+    culling_bind_groups: Res<TerrainViewComponents<CullingBindGroup>>,
+    mut gpu_height_queries: ResMut<TerrainComponents<GpuHeightQuery>>,
+) {
+    device.wgpu_device().poll(Maintain::Poll);
+
+    let pipeline = match pipeline_cache.get_compute_pipeline(height_query_pipeline.pipeline_id) {
+        Some(pipeline) => pipeline,
+        None => return, // the pipeline is not loaded yet
+    };
+
+    for (&terrain, gpu_height_query) in &mut gpu_height_queries.0 {
+        gpu_height_query.finish();
+
+        // only one batch is in flight at a time
+        if gpu_height_query.batch.is_some() || gpu_height_query.pending_positions.is_none() {
+            continue;
+        }
+
+        let view = gpu_height_query.view;
+        let terrain_data = skip_none!(terrain_data.get(&terrain));
+        let view_data = skip_none!(terrain_view_data.get(&(terrain, view)));
+        let refine_tiles_bind_group = skip_none!(&view_data.refine_tiles_bind_group);
+        let culling_bind_group = skip_none!(culling_bind_groups.get(&(terrain, view)));
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        let batch = gpu_height_query.query(
+            &device,
+            &mut command_encoder,
+            pipeline,
+            &height_query_pipeline.query_layout,
+            (
+                refine_tiles_bind_group,
+                &culling_bind_group.value,
+                &terrain_data.terrain_bind_group,
+            ),
+        );
+
+        queue.submit(vec![command_encoder.finish()]);
+
+        if let Some(batch) = batch {
+            batch.map();
+            gpu_height_query.batch = Some(batch);
+        }
+    }
+}
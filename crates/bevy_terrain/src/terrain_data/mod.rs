@@ -16,31 +16,62 @@
 //! which can be used to access the terrain data in shaders.
 
 use bevy::{prelude::*, render::render_resource::*, utils::Uuid};
-use bincode::{Decode, Encode};
-use std::str::FromStr;
+use bincode::{
+    de::{BorrowDecoder, Decoder},
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    BorrowDecode, Decode, Encode,
+};
+use std::{cmp::Ordering, num::NonZeroU8, str::FromStr};
 
 pub mod gpu_node_atlas;
 pub mod gpu_quadtree;
+pub mod gpu_virtual_texture;
+pub mod height_query;
 pub mod node_atlas;
+pub mod node_readback;
 pub mod quadtree;
+pub mod refinement;
+pub mod sampler;
+pub mod virtual_texture;
 
-// Todo: may be swap to u64 for giant terrains
 // Todo: consider 3 bit face data, for cube sphere
 /// A globally unique identifier of a node.
 /// lod |  x |  y
-///   6 | 13 | 13
-pub type NodeId = u32;
+///   6 | 29 | 29
+///
+/// The coordinates support up to 2^29 nodes per side and lod,
+/// which is sufficient for Earth-scale terrains at centimeter resolution.
+pub type NodeId = u64;
 pub const INVALID_NODE_ID: NodeId = NodeId::MAX;
 
+/// The largest x or y coordinate of a node, that can be encoded in a [`NodeId`].
+pub const MAX_NODE_COORDINATE: u32 = (1 << 29) - 1;
+
 /// Identifier of a node (and its attachments) inside the node atlas.
 pub type AtlasIndex = u16;
 pub const INVALID_ATLAS_INDEX: AtlasIndex = AtlasIndex::MAX;
 
 pub const INVALID_LOD: u16 = u16::MAX;
+/// The atlas lod of quadtree entries, whose node lies outside of the coverage of the terrain.
+pub const UNCOVERED_LOD: u16 = u16::MAX - 1;
 
 /// Identifier of an attachment inside the node atlas.
 pub type AttachmentIndex = usize;
 
+/// Returns the index of the attachment after the attachment at the removed index has been removed,
+/// or `None` if it is the removed attachment.
+pub(crate) fn shift_attachment_index(
+    attachment_index: AttachmentIndex,
+    removed_index: AttachmentIndex,
+) -> Option<AttachmentIndex> {
+    match attachment_index.cmp(&removed_index) {
+        Ordering::Less => Some(attachment_index),
+        Ordering::Equal => None,
+        Ordering::Greater => Some(attachment_index - 1),
+    }
+}
+
 /// The global coordinate of a node.
 pub struct NodeCoordinate {
     /// The lod of the node, where 0 is the highest level of detail with the smallest size
@@ -57,9 +88,9 @@ impl From<NodeId> for NodeCoordinate {
     #[inline]
     fn from(id: NodeId) -> Self {
         Self {
-            lod: ((id >> 26) & 0x3F) as u32,
-            x: ((id >> 13) & 0x1FFF) as u32,
-            y: (id & 0x1FFF) as u32,
+            lod: ((id >> 58) & 0x3F) as u32,
+            x: ((id >> 29) & 0x1FFF_FFFF) as u32,
+            y: (id & 0x1FFF_FFFF) as u32,
         }
     }
 }
@@ -67,7 +98,7 @@ impl From<NodeId> for NodeCoordinate {
 /// Calculates the node identifier from the node coordinate.
 #[inline]
 pub fn calc_node_id(lod: u32, x: u32, y: u32) -> NodeId {
-    (lod as NodeId & 0x3F) << 26 | (x as NodeId & 0x1FFF) << 13 | y as NodeId & 0x1FFF
+    (lod as NodeId & 0x3F) << 58 | (x as NodeId & 0x1FFF_FFFF) << 29 | y as NodeId & 0x1FFF_FFFF
 }
 
 /// The data format of an attachment.
@@ -81,6 +112,31 @@ pub enum AttachmentFormat {
     R16,
     /// Two   channels 16 bit
     Rg16,
+    /// One   channel  16 bit float
+    R16F,
+    /// One   channel  32 bit float, which is split into a 16 bit float value and its 16 bit float
+    /// remainder on the GPU, since 32 bit floats are not filterable on all devices
+    R32F,
+    /// Two   channels 32 bit float, which are rounded outwards to 16 bit floats on the GPU
+    /// (the minimum and maximum height)
+    Rg32F,
+    /// One   channel   8 bit unsigned integer, which stores categories (e.g. landcover classes)
+    R8Uint,
+}
+
+impl AttachmentFormat {
+    /// Whether the format stores floating point values, which are not normalized.
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::R16F | Self::R32F | Self::Rg32F)
+    }
+
+    /// Whether the format stores categories, which must never be blended.
+    ///
+    /// Categorical attachments are resampled with the nearest neighbor and down sampled
+    /// by the majority of the pixels, for the nodes of coarser lods as well as for their mip maps.
+    pub fn is_categorical(&self) -> bool {
+        matches!(self, Self::R8Uint)
+    }
 }
 
 impl From<AttachmentFormat> for TextureFormat {
@@ -90,6 +146,10 @@ impl From<AttachmentFormat> for TextureFormat {
             AttachmentFormat::Rgba8 => TextureFormat::Rgba8UnormSrgb,
             AttachmentFormat::R16 => TextureFormat::R16Unorm,
             AttachmentFormat::Rg16 => TextureFormat::Rg16Unorm,
+            AttachmentFormat::R16F => TextureFormat::R16Float,
+            AttachmentFormat::R32F => TextureFormat::Rg16Float,
+            AttachmentFormat::Rg32F => TextureFormat::Rg16Float,
+            AttachmentFormat::R8Uint => TextureFormat::R8Uint,
         }
     }
 }
@@ -122,6 +182,83 @@ impl FileFormat {
     }
 }
 
+/// Configures how an attachment is filtered, when it is sampled from the node atlas.
+///
+/// Imagery (e.g. orthophotos) benefits from anisotropic filtering, while height data should
+/// stay linearly filtered and clamped.
+/// Samplers only affect the rendering and not the preprocessed data, thus they are not stored
+/// in the manifest of the terrain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AttachmentSampler {
+    /// The filter used for magnification, minification and between the mip levels.
+    pub filter: FilterMode,
+    /// The maximum anisotropy (1, 2, 4, 8 or 16), which requires linear filtering.
+    pub anisotropy: u8,
+    /// The address mode used for all directions.
+    pub address_mode: AddressMode,
+    /// Shifts the sampled mip level, positive values select coarser mip levels.
+    /// It is only applied by shaders, which sample the attachment with gradients.
+    pub mip_bias: f32,
+}
+
+impl Default for AttachmentSampler {
+    fn default() -> Self {
+        Self {
+            filter: FilterMode::Linear,
+            anisotropy: 16,
+            address_mode: AddressMode::ClampToEdge,
+            mip_bias: 0.0,
+        }
+    }
+}
+
+impl AttachmentSampler {
+    /// A linearly filtered and clamped sampler without anisotropic filtering (e.g. for heights).
+    pub fn linear() -> Self {
+        Self {
+            anisotropy: 1,
+            ..default()
+        }
+    }
+
+    pub(crate) fn descriptor(&self) -> SamplerDescriptor<'static> {
+        // wgpu only accepts powers of two up to 16 and requires linear filtering for anisotropy
+        let anisotropy = match self.filter {
+            FilterMode::Linear => 1 << (7 - self.anisotropy.clamp(1, 16).leading_zeros()),
+            FilterMode::Nearest => 1,
+        };
+
+        SamplerDescriptor {
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.filter,
+            min_filter: self.filter,
+            mipmap_filter: self.filter,
+            anisotropy_clamp: NonZeroU8::new(anisotropy).filter(|anisotropy| anisotropy.get() > 1),
+            ..default()
+        }
+    }
+}
+
+impl Encode for AttachmentSampler {
+    fn encode<E: Encoder>(&self, _encoder: &mut E) -> Result<(), EncodeError> {
+        Ok(())
+    }
+}
+
+impl Decode for AttachmentSampler {
+    fn decode<D: Decoder>(_decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(default())
+    }
+}
+
+impl<'de> BorrowDecode<'de> for AttachmentSampler {
+    fn borrow_decode<D: BorrowDecoder<'de>>(_decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(default())
+    }
+}
+
 /// Configures an attachment.
 #[derive(Encode, Decode, Clone, Debug)]
 pub struct AttachmentConfig {
@@ -137,6 +274,8 @@ pub struct AttachmentConfig {
     pub format: AttachmentFormat,
     /// The file format of the attachment.
     pub file_format: FileFormat,
+    /// The sampler of the attachment.
+    pub sampler: AttachmentSampler,
 }
 
 impl AttachmentConfig {
@@ -157,6 +296,7 @@ impl AttachmentConfig {
             mip_level_count,
             format,
             file_format: FileFormat::TDF,
+            sampler: default(),
         }
     }
 }
@@ -176,6 +316,8 @@ pub struct AtlasAttachment {
     pub mip_level_count: u32,
     /// The format of the attachment.
     pub(crate) format: TextureFormat,
+    /// The sampler of the attachment.
+    pub(crate) sampler: AttachmentSampler,
 }
 
 impl From<AttachmentConfig> for AtlasAttachment {
@@ -195,6 +337,7 @@ impl From<AttachmentConfig> for AtlasAttachment {
             border_size: config.border_size,
             mip_level_count: config.mip_level_count,
             format: config.format.into(),
+            sampler: config.sampler,
         }
     }
 }
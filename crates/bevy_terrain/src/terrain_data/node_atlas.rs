@@ -1,7 +1,8 @@
 use crate::{
     terrain::{Terrain, TerrainConfig},
     terrain_data::{
-        quadtree::Quadtree, AtlasAttachment, AtlasIndex, AttachmentIndex, NodeId, INVALID_NODE_ID,
+        quadtree::Quadtree, shift_attachment_index, AtlasAttachment, AtlasIndex, AttachmentIndex,
+        NodeId, INVALID_NODE_ID,
     },
     TerrainView, TerrainViewComponents,
 };
@@ -9,7 +10,18 @@ use bevy::{
     prelude::*,
     utils::{HashMap, HashSet},
 };
-use std::collections::VecDeque;
+use std::{collections::VecDeque, mem};
+
+/// Sent, whenever the streaming of a terrain makes progress, e.g. to show a loading screen,
+/// until the terrain under the camera is ready.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerrainEvent {
+    /// All nodes required by the view are resident in the node atlas for the first time.
+    /// This is sent once per terrain and view.
+    InitialLoadComplete { terrain: Entity, view: Entity },
+    /// All attachments of the node have finished loading and it is used from now on.
+    NodeLoaded { terrain: Entity, node_id: NodeId },
+}
 
 /// Stores all of the attachments of the node, alongside their loading state.
 #[derive(Clone)]
@@ -34,6 +46,20 @@ impl LoadingNode {
         self.loading_attachments.remove(&attachment_index);
     }
 
+    /// Removes the attachment and shifts the indices of the following attachments.
+    fn remove_attachment(&mut self, removed_index: AttachmentIndex) {
+        self.attachments = mem::take(&mut self.attachments)
+            .into_iter()
+            .filter_map(|(index, handle)| {
+                shift_attachment_index(index, removed_index).map(|index| (index, handle))
+            })
+            .collect();
+        self.loading_attachments = mem::take(&mut self.loading_attachments)
+            .into_iter()
+            .filter_map(|index| shift_attachment_index(index, removed_index))
+            .collect();
+    }
+
     /// Returns whether all node attachments of the node have finished loading.
     fn finished_loading(&self) -> bool {
         self.loading_attachments.is_empty()
@@ -75,6 +101,27 @@ struct UnusedNode {
     atlas_index: AtlasIndex,
 }
 
+/// The occupancy of the slots of a [`NodeAtlas`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NodeAtlasStatistics {
+    /// The amount of slots of the node atlas.
+    pub size: u32,
+    /// The amount of slots used by nodes, which are requested by any quadtree.
+    pub used: u32,
+    /// The amount of slots used by cached nodes, which are not requested anymore.
+    pub cached: u32,
+    /// The amount of slots, which have never been used.
+    pub free: u32,
+    /// The amount of nodes, which are currently loading.
+    pub loading: u32,
+    /// The amount of nodes, which have started loading this frame.
+    pub started: u32,
+    /// The amount of nodes, which have finished loading this frame.
+    pub finished: u32,
+    /// The amount of cached nodes, which have been evicted this frame to make room for new ones.
+    pub evicted: u32,
+}
+
 /// A sparse storage of all terrain attachments, which streams data in and out of memory
 /// depending on the decisions of the corresponding [`Quadtree`]s.
 ///
@@ -108,6 +155,8 @@ pub struct NodeAtlas {
     pub(crate) existing_nodes: HashSet<NodeId>,
     /// Lists the unused nodes in least recently used order.
     unused_nodes: VecDeque<UnusedNode>,
+    /// The amount of cached nodes, which have been evicted this frame.
+    evicted_count: u32,
 }
 
 impl NodeAtlas {
@@ -137,6 +186,7 @@ impl NodeAtlas {
             size,
             unused_nodes,
             existing_nodes,
+            evicted_count: 0,
         }
     }
 
@@ -149,33 +199,173 @@ impl NodeAtlas {
         )
     }
 
+    /// Returns the current occupancy of the slots of the node atlas.
+    pub fn statistics(&self) -> NodeAtlasStatistics {
+        let free = self
+            .unused_nodes
+            .iter()
+            .filter(|node| node.node_id == INVALID_NODE_ID)
+            .count() as u32;
+        let unused = self.unused_nodes.len() as u32;
+
+        NodeAtlasStatistics {
+            size: self.size as u32,
+            used: self.size as u32 - unused,
+            cached: unused - free,
+            free,
+            loading: self.loading_nodes.len() as u32,
+            started: self.load_events.len() as u32,
+            finished: self.loaded_nodes.len() as u32,
+            evicted: self.evicted_count,
+        }
+    }
+
+    /// Starts loading all attachments of a present node again (e.g. after its data has changed).
+    ///
+    /// The node keeps being used with its current data, until the new data has finished loading.
+    pub(crate) fn reload_node(&mut self, node_id: NodeId) {
+        let node = match self.nodes.get(&node_id) {
+            Some(node) => node,
+            None => return,
+        };
+
+        self.load_events.push(node_id);
+        self.loading_nodes.insert(
+            node_id,
+            LoadingNode {
+                atlas_index: node.atlas_index,
+                loading_attachments: (0..self.attachments.len()).collect(),
+                attachments: default(),
+            },
+        );
+    }
+
+    /// Removes a node, whose data is missing, so that the quadtrees fall back to its parent.
+    ///
+    /// The node is not requested again and its atlas index is reused first.
+    pub(crate) fn remove_missing_node(&mut self, node_id: NodeId) {
+        self.existing_nodes.remove(&node_id);
+        self.loading_nodes.remove(&node_id);
+        self.load_events.retain(|&id| id != node_id);
+
+        if let Some(node) = self.nodes.remove(&node_id) {
+            self.unused_nodes
+                .retain(|unused_node| unused_node.atlas_index != node.atlas_index);
+            self.unused_nodes.push_front(UnusedNode {
+                node_id: INVALID_NODE_ID,
+                atlas_index: node.atlas_index,
+            });
+        }
+    }
+
+    /// Adds an attachment and starts loading it for all present nodes.
+    ///
+    /// The present nodes keep being used, until the attachment has finished loading.
+    pub(crate) fn add_attachment(&mut self, attachment: AtlasAttachment) {
+        let attachment_index = self.attachments.len();
+        self.attachments.push(attachment);
+
+        for (&node_id, node) in &self.nodes {
+            if !self.load_events.contains(&node_id) {
+                self.load_events.push(node_id);
+            }
+
+            self.loading_nodes
+                .entry(node_id)
+                .or_insert_with(|| LoadingNode {
+                    atlas_index: node.atlas_index,
+                    loading_attachments: default(),
+                    attachments: default(),
+                })
+                .loading_attachments
+                .insert(attachment_index);
+        }
+    }
+
+    /// Removes an attachment and shifts the indices of the following attachments.
+    pub(crate) fn remove_attachment(&mut self, attachment_index: AttachmentIndex) {
+        self.attachments.remove(attachment_index);
+
+        for node in self
+            .loading_nodes
+            .values_mut()
+            .chain(self.loaded_nodes.iter_mut())
+        {
+            node.remove_attachment(attachment_index);
+        }
+    }
+
+    /// Assigns the least recently used unused slot to the node and starts loading it.
+    ///
+    /// Nodes without any requests are only cached and thus stay evictable.
+    /// Returns false, if all slots are used by requested nodes.
+    fn start_loading(&mut self, node_id: NodeId, requests: u32) -> bool {
+        let unused_node = match self.unused_nodes.pop_front() {
+            Some(unused_node) => unused_node,
+            None => return false,
+        };
+
+        if unused_node.node_id != INVALID_NODE_ID {
+            self.evicted_count += 1;
+
+            // the evicted node may still be loading
+            self.loading_nodes.remove(&unused_node.node_id);
+            self.load_events.retain(|&id| id != unused_node.node_id);
+        }
+
+        self.nodes.remove(&unused_node.node_id);
+        self.nodes.insert(
+            node_id,
+            AtlasNode {
+                requests,
+                state: LoadingState::Loading,
+                atlas_index: unused_node.atlas_index,
+            },
+        );
+
+        if requests == 0 {
+            self.unused_nodes.push_back(UnusedNode {
+                node_id,
+                atlas_index: unused_node.atlas_index,
+            });
+        }
+
+        // start loading the node
+        self.load_events.push(node_id);
+        self.loading_nodes.insert(
+            node_id,
+            LoadingNode {
+                atlas_index: unused_node.atlas_index,
+                loading_attachments: (0..self.attachments.len()).collect(),
+                attachments: default(),
+            },
+        );
+
+        true
+    }
+
     /// Adjusts the node atlas according to the requested and released nodes of the [`Quadtree`]
     /// and starts loading not already present nodes.
+    ///
+    /// The nodes, which the quadtree wants to have cached (prefetched and warm start nodes),
+    /// are loaded into unused slots without being requested, so that they never pin their slots
+    /// and are evicted like any other cached node, whenever a requested node needs the slot.
     fn fulfill_request(&mut self, quadtree: &mut Quadtree) {
-        let NodeAtlas {
-            attachments,
-            unused_nodes,
-            nodes,
-            loading_nodes,
-            load_events,
-            existing_nodes,
-            ..
-        } = self;
-
         // release nodes that are on longer required
         for node_id in quadtree.released_nodes.drain(..) {
-            if !existing_nodes.contains(&node_id) {
+            if !self.existing_nodes.contains(&node_id) {
                 continue;
             }
 
-            let node = nodes
+            let node = self
+                .nodes
                 .get_mut(&node_id)
                 .expect("Tried releasing a node, which is not present.");
             node.requests -= 1;
 
             if node.requests == 0 {
                 // the node is not used anymore
-                unused_nodes.push_back(UnusedNode {
+                self.unused_nodes.push_back(UnusedNode {
                     node_id,
                     atlas_index: node.atlas_index,
                 });
@@ -184,65 +374,74 @@ impl NodeAtlas {
 
         // load nodes that are requested
         for node_id in quadtree.requested_nodes.drain(..) {
-            if !existing_nodes.contains(&node_id) {
+            if !self.existing_nodes.contains(&node_id) {
                 continue;
             }
 
             // check if the node is already present else start loading it
-            if let Some(node) = nodes.get_mut(&node_id) {
+            if let Some(node) = self.nodes.get_mut(&node_id) {
                 if node.requests == 0 {
                     // the node is now used again
-                    unused_nodes.retain(|unused_node| node.atlas_index != unused_node.atlas_index);
+                    let atlas_index = node.atlas_index;
+                    self.unused_nodes
+                        .retain(|unused_node| atlas_index != unused_node.atlas_index);
                 }
 
                 node.requests += 1;
             } else {
                 // Todo: implement better loading strategy
                 // remove least recently used node and reuse its atlas index
-                let unused_node = unused_nodes.pop_front().expect("Atlas out of indices");
-
-                nodes.remove(&unused_node.node_id);
-                nodes.insert(
-                    node_id,
-                    AtlasNode {
-                        requests: 1,
-                        state: LoadingState::Loading,
-                        atlas_index: unused_node.atlas_index,
-                    },
-                );
-
-                // start loading the node
-                load_events.push(node_id);
-                loading_nodes.insert(
-                    node_id,
-                    LoadingNode {
-                        atlas_index: unused_node.atlas_index,
-                        loading_attachments: (0..attachments.len()).collect(),
-                        attachments: default(),
-                    },
-                );
+                let started = self.start_loading(node_id, 1);
+                assert!(started, "Atlas out of indices");
             }
         }
 
-        // println!(
-        //     "Currently there are {} nodes in use.",
-        //     self.size as usize - self.unused_nodes.len()
-        // );
+        // load nodes into the cache, as long as there are unused slots
+        for node_id in quadtree.cached_nodes.drain(..) {
+            if !self.existing_nodes.contains(&node_id) {
+                continue;
+            }
+
+            match self.nodes.get(&node_id) {
+                Some(node) if node.requests == 0 => {
+                    // the node is already cached, mark it as recently used
+                    let atlas_index = node.atlas_index;
+                    self.unused_nodes
+                        .retain(|unused_node| atlas_index != unused_node.atlas_index);
+                    self.unused_nodes.push_back(UnusedNode {
+                        node_id,
+                        atlas_index,
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    if !self.start_loading(node_id, 0) {
+                        break;
+                    }
+                }
+            }
+        }
     }
 
     /// Checks all nodes that have finished loading, marks them accordingly and prepares the data
     /// to be send to the gpu by the [`GpuNodeAtlas`](super::gpu_node_atlas::GpuNodeAtlas).
-    fn update_loaded_nodes(&mut self) {
+    ///
+    /// Returns the ids of the nodes, which have finished loading.
+    fn update_loaded_nodes(&mut self) -> Vec<NodeId> {
         let NodeAtlas {
             ref mut data,
             ref mut load_events,
             ref mut nodes,
             ref mut loading_nodes,
             ref mut loaded_nodes,
+            ref mut evicted_count,
             ..
         } = self;
 
         load_events.clear();
+        *evicted_count = 0;
+
+        let mut finished = Vec::new();
 
         // update all nodes that have finished loading
         for (node_id, loading_node) in loading_nodes.drain_filter(|_, node| node.finished_loading())
@@ -256,22 +455,46 @@ impl NodeAtlas {
                 };
 
                 loaded_nodes.push(loading_node);
+                finished.push(node_id);
             } else {
                 dbg!("Dropped node after loading.");
                 // node no longer required, can safely be ignored
             }
         }
+
+        finished
+    }
+
+    /// Returns whether or not the node is loaded and can be used.
+    pub fn is_loaded(&self, node_id: NodeId) -> bool {
+        self.nodes
+            .get(&node_id)
+            .map_or(false, |node| node.state == LoadingState::Loaded)
+    }
+
+    /// Returns whether or not the node is loaded and currently requested by any quadtree.
+    pub fn is_requested(&self, node_id: NodeId) -> bool {
+        self.nodes.get(&node_id).map_or(false, |node| {
+            node.state == LoadingState::Loaded && node.requests > 0
+        })
     }
 }
 
-/// Updates the node atlas according to all corresponding quadtrees.
+/// Updates the node atlas according to all corresponding quadtrees
+/// and sends a [`TerrainEvent::NodeLoaded`] for each node, that has finished loading.
 pub(crate) fn update_node_atlas(
     mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut terrain_events: EventWriter<TerrainEvent>,
     view_query: Query<Entity, With<TerrainView>>,
     mut terrain_query: Query<(Entity, &mut NodeAtlas), With<Terrain>>,
 ) {
     for (terrain, mut node_atlas) in terrain_query.iter_mut() {
-        node_atlas.update_loaded_nodes();
+        terrain_events.send_batch(
+            node_atlas
+                .update_loaded_nodes()
+                .into_iter()
+                .map(|node_id| TerrainEvent::NodeLoaded { terrain, node_id }),
+        );
 
         for view in view_query.iter() {
             if let Some(quadtree) = quadtrees.get_mut(&(terrain, view)) {
@@ -0,0 +1,448 @@
+//! An optional readback path, which copies node attachments from the [`GpuNodeAtlas`]
+//! back to the CPU.
+//!
+//! This is useful for terrains whose data only lives on the GPU (e.g. generated by a compute
+//! shader), but which still require CPU-side height queries (gameplay, physics).
+//! The data becomes available a frame or two after it has been requested.
+//! Once a node has been read back, its data replaces the CPU copy of the attachment, so that
+//! the height queries of the [`Quadtree`](super::quadtree::Quadtree) (e.g.
+//! [`sample_height`](super::quadtree::Quadtree::sample_height)) see the data of the GPU.
+
+use crate::{
+    error::{TerrainError, TerrainResult},
+    skip_none,
+    terrain::{Terrain, TerrainComponents, TerrainConfig},
+    terrain_data::{
+        gpu_node_atlas::GpuNodeAtlas,
+        node_atlas::{LoadingState, NodeAtlas},
+        AtlasIndex, AttachmentIndex, NodeId,
+    },
+};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_resource::*,
+        renderer::{RenderDevice, RenderQueue},
+        MainWorld,
+    },
+    utils::{HashMap, HashSet},
+};
+use std::{
+    mem,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+use wgpu::{Maintain, COPY_BYTES_PER_ROW_ALIGNMENT};
+
+/// The CPU copy of a node attachment, which has been read back from the GPU.
+#[derive(Clone)]
+pub struct ReadbackData {
+    /// The size of the attachment in pixels.
+    pub size: u32,
+    /// The size of a single pixel in bytes.
+    pub pixel_size: u32,
+    /// The format of the attachment.
+    pub format: TextureFormat,
+    /// The tightly packed pixel data of the first mip level.
+    pub data: Vec<u8>,
+}
+
+impl ReadbackData {
+    /// Returns the raw bytes of the pixel at the coordinate.
+    pub fn pixel(&self, coordinate: UVec2) -> &[u8] {
+        let coordinate = coordinate.min(UVec2::splat(self.size - 1));
+        let start = ((coordinate.y * self.size + coordinate.x) * self.pixel_size) as usize;
+
+        &self.data[start..start + self.pixel_size as usize]
+    }
+
+    /// Converts the data into an image, which is sampled like the attachments loaded on the CPU.
+    fn to_image(&self) -> Image {
+        Image::new(
+            Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            self.data.clone(),
+            self.format,
+        )
+    }
+}
+
+/// The result of a copy, which is `None` if the attachment can not be read back.
+type ReadbackResult = (NodeId, ReadbackTicket, Option<ReadbackData>);
+
+/// Identifies a single copy of a node, so that the results of released nodes can be discarded.
+type ReadbackTicket = u64;
+
+/// Requests node attachments to be read back from the GPU into a CPU cache.
+///
+/// Add this component to a terrain entity to opt into the readback path.
+/// Requested nodes are copied once they are loaded into the [`NodeAtlas`].
+#[derive(Component)]
+pub struct NodeReadback {
+    /// The attachment, which will be read back.
+    pub attachment_index: AttachmentIndex,
+    /// Nodes requested by the user, which have not been copied yet.
+    requested_nodes: HashSet<NodeId>,
+    /// Nodes that are loaded and can be copied this frame.
+    pub(crate) pending_nodes: Vec<(NodeId, AtlasIndex, ReadbackTicket)>,
+    /// The ticket of the latest copy of each node, which has not finished yet.
+    in_flight_nodes: HashMap<NodeId, ReadbackTicket>,
+    next_ticket: ReadbackTicket,
+    /// The data of the nodes, that have finished reading back.
+    data: HashMap<NodeId, ReadbackData>,
+    /// Shared with the render world, which pushes finished and rejected readbacks.
+    pub(crate) finished: Arc<Mutex<Vec<ReadbackResult>>>,
+}
+
+impl NodeReadback {
+    /// Creates a new readback for the attachment of the terrain.
+    ///
+    /// Returns an error, if the terrain has no attachment at the index or if the attachment is
+    /// block-compressed and thus can not be read back.
+    pub fn new(config: &TerrainConfig, attachment_index: AttachmentIndex) -> TerrainResult<Self> {
+        let attachment = config.attachments.get(attachment_index).ok_or_else(|| {
+            TerrainError::InvalidConfig(format!(
+                "The terrain has no attachment {attachment_index}, which could be read back."
+            ))
+        })?;
+
+        if attachment.format.describe().block_dimensions != (1, 1) {
+            return Err(TerrainError::InvalidConfig(format!(
+                "The attachment {} is block-compressed and can not be read back.",
+                attachment.name
+            )));
+        }
+
+        Ok(Self {
+            attachment_index,
+            requested_nodes: default(),
+            pending_nodes: default(),
+            in_flight_nodes: default(),
+            next_ticket: 0,
+            data: default(),
+            finished: default(),
+        })
+    }
+
+    /// Requests the node to be read back, once it is loaded.
+    pub fn request(&mut self, node_id: NodeId) {
+        if !self.data.contains_key(&node_id) && !self.in_flight_nodes.contains_key(&node_id) {
+            self.requested_nodes.insert(node_id);
+        }
+    }
+
+    /// Removes the node from the cache.
+    ///
+    /// Copies of the node, which are still in flight, are discarded once they finish.
+    pub fn release(&mut self, node_id: NodeId) {
+        self.requested_nodes.remove(&node_id);
+        self.pending_nodes.retain(|&(id, _, _)| id != node_id);
+        self.in_flight_nodes.remove(&node_id);
+        self.data.remove(&node_id);
+    }
+
+    /// Returns the cached data of the node, if it has been read back already.
+    pub fn get(&self, node_id: NodeId) -> Option<&ReadbackData> {
+        self.data.get(&node_id)
+    }
+}
+
+/// Resolves the requested nodes to their atlas indices and collects
+/// the data that has been read back in the meantime.
+///
+/// The data of each finished node replaces the CPU copy of the attachment inside of the
+/// [`NodeAtlas`], which is sampled by the height queries.
+pub(crate) fn update_node_readback(
+    mut images: ResMut<Assets<Image>>,
+    mut terrain_query: Query<(&mut NodeAtlas, &mut NodeReadback), With<Terrain>>,
+) {
+    for (mut node_atlas, mut readback) in terrain_query.iter_mut() {
+        let NodeReadback {
+            attachment_index,
+            ref mut requested_nodes,
+            ref mut pending_nodes,
+            ref mut in_flight_nodes,
+            ref mut next_ticket,
+            ref mut data,
+            ref finished,
+            ..
+        } = readback.as_mut();
+
+        for (node_id, ticket, node_data) in finished.lock().unwrap().drain(..) {
+            // the node has been released (and maybe requested again) since the copy was queued
+            if in_flight_nodes.get(&node_id) != Some(&ticket) {
+                continue;
+            }
+
+            in_flight_nodes.remove(&node_id);
+
+            // the attachment can not be read back, the node may be requested again though
+            let node_data = skip_none!(node_data);
+
+            let atlas_index = node_atlas.nodes.get(&node_id).map(|node| node.atlas_index);
+
+            if let Some(atlas_index) = atlas_index {
+                node_atlas.data[atlas_index as usize]
+                    ._attachments
+                    .insert(*attachment_index, images.add(node_data.to_image()));
+            }
+
+            data.insert(node_id, node_data);
+        }
+
+        requested_nodes.retain(|node_id| match node_atlas.nodes.get(node_id) {
+            Some(node) if node.state == LoadingState::Loaded => {
+                pending_nodes.push((*node_id, node.atlas_index, *next_ticket));
+                in_flight_nodes.insert(*node_id, *next_ticket);
+                *next_ticket += 1;
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+/// A copy from the atlas into a staging buffer, which is waiting to be mapped.
+struct ReadbackCopy {
+    node_id: NodeId,
+    ticket: ReadbackTicket,
+    buffer: Buffer,
+    mapped: Arc<AtomicBool>,
+}
+
+impl ReadbackCopy {
+    /// Starts mapping the staging buffer, after the copy has been submitted.
+    fn map(&self) {
+        let mapped = self.mapped.clone();
+
+        self.buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if result.is_ok() {
+                    mapped.store(true, Ordering::Release);
+                }
+            });
+    }
+}
+
+/// Stores the render world side of the [`NodeReadback`].
+pub struct GpuNodeReadback {
+    attachment_index: AttachmentIndex,
+    pending_nodes: Vec<(NodeId, AtlasIndex, ReadbackTicket)>,
+    copies: Vec<ReadbackCopy>,
+    finished: Arc<Mutex<Vec<ReadbackResult>>>,
+    /// Whether the attachment can not be read back, because it is missing or block-compressed.
+    rejected: bool,
+}
+
+impl GpuNodeReadback {
+    /// Checks, whether the attachment exists and stores uncompressed pixels, which can be read back.
+    fn supported(gpu_node_atlas: &GpuNodeAtlas, attachment_index: AttachmentIndex) -> bool {
+        gpu_node_atlas
+            .attachments
+            .get(attachment_index)
+            .map_or(false, |(attachment, _)| {
+                attachment.format.describe().block_dimensions == (1, 1)
+            })
+    }
+
+    /// Reports the pending nodes as rejected, so that they are no longer in flight.
+    fn reject(&mut self) {
+        self.finished.lock().unwrap().extend(
+            self.pending_nodes
+                .drain(..)
+                .map(|(node_id, _, ticket)| (node_id, ticket, None)),
+        );
+    }
+
+    fn size(gpu_node_atlas: &GpuNodeAtlas, attachment_index: AttachmentIndex) -> (u32, u32, u32) {
+        // the attachment exists, since only supported attachments are read back
+        let (attachment, _) = &gpu_node_atlas.attachments[attachment_index];
+        let size = attachment.texture_size;
+        // the block size equals the pixel size, since only uncompressed formats are supported
+        let pixel_size = attachment.format.describe().block_size as u32;
+        let padded_row_size = (size * pixel_size + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            / COPY_BYTES_PER_ROW_ALIGNMENT
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        (size, pixel_size, padded_row_size)
+    }
+
+    /// Copies the pending nodes from the atlas attachment into staging buffers.
+    fn copy(
+        &mut self,
+        device: &RenderDevice,
+        command_encoder: &mut CommandEncoder,
+        images: &RenderAssets<Image>,
+        gpu_node_atlas: &GpuNodeAtlas,
+    ) -> Vec<ReadbackCopy> {
+        let (size, _, padded_row_size) = Self::size(gpu_node_atlas, self.attachment_index);
+        let (_, atlas_handle) = &gpu_node_atlas.attachments[self.attachment_index];
+        let atlas_attachment = images.get(atlas_handle).unwrap();
+
+        self.pending_nodes
+            .drain(..)
+            .map(|(node_id, atlas_index, ticket)| {
+                let buffer = device.create_buffer(&BufferDescriptor {
+                    label: "readback_buffer".into(),
+                    size: (padded_row_size * size) as BufferAddress,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                });
+
+                command_encoder.copy_texture_to_buffer(
+                    ImageCopyTexture {
+                        texture: &atlas_attachment.texture,
+                        mip_level: 0,
+                        origin: Origin3d {
+                            x: 0,
+                            y: 0,
+                            z: atlas_index as u32,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    ImageCopyBuffer {
+                        buffer: &buffer,
+                        layout: ImageDataLayout {
+                            offset: 0,
+                            bytes_per_row: NonZeroU32::new(padded_row_size),
+                            rows_per_image: NonZeroU32::new(size),
+                        },
+                    },
+                    Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers: 1,
+                    },
+                );
+
+                ReadbackCopy {
+                    node_id,
+                    ticket,
+                    buffer,
+                    mapped: default(),
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the data of all mapped staging buffers into the CPU cache.
+    fn finish(&mut self, gpu_node_atlas: &GpuNodeAtlas) {
+        let (size, pixel_size, padded_row_size) = Self::size(gpu_node_atlas, self.attachment_index);
+        let (attachment, _) = &gpu_node_atlas.attachments[self.attachment_index];
+        let row_size = (size * pixel_size) as usize;
+
+        let mut finished = self.finished.lock().unwrap();
+
+        self.copies.retain(|copy| {
+            if !copy.mapped.load(Ordering::Acquire) {
+                return true;
+            }
+
+            let data = copy
+                .buffer
+                .slice(..)
+                .get_mapped_range()
+                .chunks_exact(padded_row_size as usize)
+                .flat_map(|row| row[..row_size].iter().copied())
+                .collect();
+
+            copy.buffer.unmap();
+
+            finished.push((
+                copy.node_id,
+                copy.ticket,
+                Some(ReadbackData {
+                    size,
+                    pixel_size,
+                    format: attachment.format,
+                    data,
+                }),
+            ));
+
+            false
+        });
+    }
+}
+
+/// Extracts the nodes, that are ready to be read back, into the [`GpuNodeReadback`]s.
+pub(crate) fn extract_node_readback(
+    mut main_world: ResMut<MainWorld>,
+    mut gpu_readbacks: ResMut<TerrainComponents<GpuNodeReadback>>,
+) {
+    let mut terrain_query = main_world.query::<(Entity, &mut NodeReadback)>();
+
+    for (terrain, mut readback) in terrain_query.iter_mut(&mut main_world) {
+        let pending_nodes = mem::take(&mut readback.pending_nodes);
+
+        if let Some(gpu_readback) = gpu_readbacks.get_mut(&terrain) {
+            gpu_readback.pending_nodes.extend(pending_nodes);
+        } else {
+            gpu_readbacks.insert(
+                terrain,
+                GpuNodeReadback {
+                    attachment_index: readback.attachment_index,
+                    pending_nodes,
+                    copies: default(),
+                    finished: readback.finished.clone(),
+                    rejected: false,
+                },
+            );
+        }
+    }
+}
+
+/// Queues the copies of the pending nodes and collects the finished ones.
+pub(crate) fn queue_node_readback(
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    images: Res<RenderAssets<Image>>,
+    gpu_node_atlases: Res<TerrainComponents<GpuNodeAtlas>>,
+    mut gpu_readbacks: ResMut<TerrainComponents<GpuNodeReadback>>,
+) {
+    device.wgpu_device().poll(Maintain::Poll);
+
+    for (terrain, gpu_readback) in &mut gpu_readbacks.0 {
+        let gpu_node_atlas = skip_none!(gpu_node_atlases.get(terrain));
+
+        if gpu_readback.rejected {
+            gpu_readback.reject();
+            continue;
+        }
+
+        if !GpuNodeReadback::supported(gpu_node_atlas, gpu_readback.attachment_index) {
+            error!(
+                "The attachment {} of the terrain {terrain:?} is missing or block-compressed and can not be read back.",
+                gpu_readback.attachment_index
+            );
+            gpu_readback.rejected = true;
+            gpu_readback.reject();
+            continue;
+        }
+
+        gpu_readback.finish(gpu_node_atlas);
+
+        if gpu_readback.pending_nodes.is_empty() {
+            continue;
+        }
+
+        let mut command_encoder =
+            device.create_command_encoder(&CommandEncoderDescriptor::default());
+
+        let copies = gpu_readback.copy(&device, &mut command_encoder, &images, gpu_node_atlas);
+        queue.submit(vec![command_encoder.finish()]);
+
+        for copy in copies {
+            copy.map();
+            gpu_readback.copies.push(copy);
+        }
+    }
+}
@@ -1,16 +1,51 @@
 use crate::{
+    debug::DebugTerrain,
+    formats::streaming_state::StreamingState,
+    skip_none,
     terrain::{Terrain, TerrainConfig},
     terrain_data::{
         calc_node_id,
-        node_atlas::{LoadingState, NodeAtlas},
-        AtlasIndex, NodeCoordinate, NodeId, INVALID_ATLAS_INDEX, INVALID_LOD, INVALID_NODE_ID,
+        node_atlas::{LoadingState, NodeAtlas, TerrainEvent},
+        refinement::{RefinementContext, RefinementHeuristic, TerrainRefinement},
+        AtlasIndex, AttachmentIndex, NodeCoordinate, NodeId, INVALID_ATLAS_INDEX, INVALID_LOD,
+        INVALID_NODE_ID, MAX_NODE_COORDINATE, UNCOVERED_LOD,
     },
+    terrain_view::{viewer_position, DependentTerrainView},
     TerrainView, TerrainViewComponents, TerrainViewConfig,
 };
-use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy::{
+    math::{DVec2, DVec3, Vec3Swizzles},
+    prelude::*,
+    render::render_resource::TextureFormat,
+    utils::HashSet,
+};
 use bytemuck::{Pod, Zeroable};
+use half::f16;
 use itertools::iproduct;
 use ndarray::Array3;
+use std::cmp::Reverse;
+
+/// Samples the first channel of a 16 bit, floating point or categorical attachment image at the
+/// coordinates, which range from zero to one across the whole image (including its border).
+/// Categories are returned as they are, instead of being normalized.
+pub(crate) fn sample_image(image: &Image, coords: Vec2) -> f32 {
+    let format = image.texture_descriptor.format;
+    let size = image.size().as_uvec2();
+    let position = (image.size() * coords).as_uvec2().min(size - 1);
+    let index = format.describe().block_size as usize * (position.x + position.y * size.x) as usize;
+    let bytes = &image.data[index..];
+
+    match format {
+        TextureFormat::R16Float => f16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+        // the value and the remainder of a 32 bit float
+        TextureFormat::Rg16Float => {
+            f16::from_le_bytes([bytes[0], bytes[1]]).to_f32()
+                + f16::from_le_bytes([bytes[2], bytes[3]]).to_f32()
+        }
+        TextureFormat::R8Uint => bytes[0] as f32,
+        _ => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / u16::MAX as f32,
+    }
+}
 
 /// The current state of a node of a [`Quadtree`].
 ///
@@ -63,6 +98,27 @@ impl Default for QuadtreeEntry {
     }
 }
 
+/// The nodes, which have been newly requested and released by a single update of a [`Quadtree`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeRequests {
+    /// Nodes that should be loaded from now on.
+    pub requested: Vec<NodeId>,
+    /// Nodes that are no longer required.
+    pub released: Vec<NodeId>,
+}
+
+/// Returns whether or not the bounding box of the node intersects the frustum.
+fn intersects_frustum(planes: &[Vec4; 5], context: &RefinementContext) -> bool {
+    let (aabb_min, aabb_max) = (context.min.as_vec3(), context.max.as_vec3());
+
+    planes.iter().all(|plane| {
+        // the corner, which is furthest along the plane normal
+        let corner = Vec3::select(plane.truncate().cmpge(Vec3::ZERO), aabb_max, aabb_min);
+
+        plane.dot(corner.extend(1.0)) >= 0.0
+    })
+}
+
 /// A quadtree-like view of a terrain, that requests and releases nodes from the [`NodeAtlas`]
 /// depending on the distance to the viewer.
 ///
@@ -80,10 +136,14 @@ impl Default for QuadtreeEntry {
 /// in each direction.
 ///
 /// Each frame the quadtree determines the state of each node via the
-/// `compute_requests` methode.
+/// `update_for_view` methode.
 /// After the [`NodeAtlas`] has adjusted to these requests, the quadtree retrieves the best
 /// currently loaded nodes from the node atlas via the
 /// `adjust` methode, which can later be used to access the terrain data.
+///
+/// The nodes are addressed using 64 bit [`NodeId`]s and double precision positions,
+/// so that Earth-scale terrains do not overflow. Only the wrapped quadtree layers are sent to the
+/// GPU, which thus never sees global node coordinates.
 #[derive(Default, Component)]
 pub struct Quadtree {
     /// The handle of the quadtree texture.
@@ -94,6 +154,8 @@ pub struct Quadtree {
     pub(crate) released_nodes: Vec<NodeId>,
     /// Nodes that are requested to be loaded by this quadtree.
     pub(crate) requested_nodes: Vec<NodeId>,
+    /// Nodes that should be loaded into the cache of the node atlas, without being requested.
+    pub(crate) cached_nodes: Vec<NodeId>,
     /// The count of level of detail layers.
     pub(crate) lod_count: u32,
     /// The count of nodes in x and y direction per layer.
@@ -101,11 +163,18 @@ pub struct Quadtree {
     /// The size of the smallest nodes (with lod 0).
     leaf_node_size: u32,
     /// The distance (measured in node sizes) until which to request nodes to be loaded.
-    load_distance: f32,
+    pub(crate) load_distance: f32,
     height: f32,
+    elevation_offset: f32,
     height_under_viewer: f32,
+    /// The attachment, which masks out holes in the terrain.
+    mask_attachment: Option<AttachmentIndex>,
     /// The internal node states of the quadtree.
     nodes: Array3<TreeNode>,
+    /// The nodes, which are currently prefetched around the predicted position of the viewer.
+    prefetched_nodes: HashSet<NodeId>,
+    /// The nodes of a streaming state, which have not been cached yet.
+    pending_warm_start: Vec<NodeId>,
 }
 
 impl Quadtree {
@@ -132,102 +201,527 @@ impl Quadtree {
             leaf_node_size,
             load_distance,
             height,
+            elevation_offset: 0.0,
             height_under_viewer: height / 2.0,
+            mask_attachment: None,
             data: Array3::default((lod_count as usize, node_count as usize, node_count as usize)),
             nodes: Array3::default((lod_count as usize, node_count as usize, node_count as usize)),
             released_nodes: default(),
             requested_nodes: default(),
+            cached_nodes: default(),
+            prefetched_nodes: default(),
+            pending_warm_start: default(),
         }
     }
 
     /// Creates a new quadtree from a terrain and a terrain view config.
     pub fn from_configs(config: &TerrainConfig, view_config: &TerrainViewConfig) -> Self {
-        Self::new(
-            view_config.quadtree_handle.clone(),
-            config.lod_count,
-            view_config.node_count,
-            config.leaf_node_size,
-            view_config.load_distance,
-            config.height,
-        )
+        Self {
+            mask_attachment: config.mask_attachment,
+            elevation_offset: config.elevation_offset(),
+            height_under_viewer: config.elevation_offset() + config.height / 2.0,
+            ..Self::new(
+                view_config.quadtree_handle.clone(),
+                config.lod_count,
+                view_config.node_count,
+                config.leaf_node_size,
+                view_config.load_distance,
+                config.height,
+            )
+        }
+    }
+
+    /// Returns the entry of the finest currently loaded node, which covers the position,
+    /// and the coordinates of the position inside of this node.
+    fn lookup(&self, position: DVec2) -> Option<(QuadtreeEntry, Vec2)> {
+        // positions outside of the range of node coordinates would saturate to its border nodes
+        let range = 0.0..self.node_size(0) * (MAX_NODE_COORDINATE as f64 + 1.0);
+        if !range.contains(&position.x) || !range.contains(&position.y) {
+            return None;
+        }
+
+        // find the finest lod, whose quadtree layer currently covers the position
+        (0..self.lod_count).find_map(|lod| {
+            let coordinate = (position / self.node_size(lod)).as_uvec2();
+            let index = coordinate % self.node_count;
+
+            // the node states are stored as [lod, x, y], while the entries are stored as [lod, y, x]
+            let node = &self.nodes[[lod as usize, index.x as usize, index.y as usize]];
+            let entry = self.data[[lod as usize, index.y as usize, index.x as usize]];
+
+            if node.node_id != calc_node_id(lod, coordinate.x, coordinate.y) {
+                return None;
+            }
+
+            if entry.atlas_lod == UNCOVERED_LOD {
+                return Some(None);
+            }
+
+            if entry.atlas_index == INVALID_ATLAS_INDEX {
+                return None;
+            }
+
+            let atlas_size = self.node_size(entry.atlas_lod as u32);
+            Some(Some((entry, ((position / atlas_size) % 1.0).as_vec2())))
+        })?
+    }
+
+    /// Returns the lod of the finest currently loaded node, which covers the position, if any.
+    pub fn loaded_lod(&self, position: Vec2) -> Option<u32> {
+        self.lookup(position.as_dvec2())
+            .map(|(entry, _)| entry.atlas_lod as u32)
+    }
+
+    /// Samples the height of the terrain at the position, using the finest currently loaded node.
+    ///
+    /// Returns `None` if no node is loaded or if the position lies inside a hole of the mask
+    /// or outside of the coverage of the terrain.
+    pub fn sample_height(
+        &self,
+        node_atlas: &NodeAtlas,
+        images: &Assets<Image>,
+        position: Vec2,
+    ) -> Option<f32> {
+        self.sample_global_height(node_atlas, images, position.as_dvec2())
+    }
+
+    /// Samples the height of the terrain like [`Self::sample_height`], but at a position
+    /// in double precision, e.g. the [`global_viewer_position`](TerrainViewConfig::global_viewer_position).
+    pub fn sample_global_height(
+        &self,
+        node_atlas: &NodeAtlas,
+        images: &Assets<Image>,
+        position: DVec2,
+    ) -> Option<f32> {
+        let (entry, atlas_coords) = self.lookup(position)?;
+
+        let attachments = &node_atlas.data[entry.atlas_index as usize]._attachments;
+
+        let sample = |attachment_index: AttachmentIndex| {
+            let image = images.get(attachments.get(&attachment_index)?)?;
+
+            Some(sample_image(image, atlas_coords))
+        };
+
+        if let Some(mask_attachment) = self.mask_attachment {
+            if sample(mask_attachment)? > 0.5 {
+                return None;
+            }
+        }
+
+        Some(sample(0)? * self.height + self.elevation_offset)
+    }
+
+    /// Samples the raw value of the attachment at the position, using the finest currently
+    /// loaded node and the nearest pixel.
+    ///
+    /// Returns `None` if no node is loaded or if the position lies outside of the coverage
+    /// of the terrain.
+    pub fn sample_attachment(
+        &self,
+        node_atlas: &NodeAtlas,
+        images: &Assets<Image>,
+        attachment_index: AttachmentIndex,
+        position: Vec2,
+    ) -> Option<f32> {
+        let (entry, atlas_coords) = self.lookup(position.as_dvec2())?;
+
+        let attachments = &node_atlas.data[entry.atlas_index as usize]._attachments;
+        let image = images.get(attachments.get(&attachment_index)?)?;
+
+        Some(sample_image(image, atlas_coords))
+    }
+
+    /// Samples the normal of the terrain at the position, using the finest currently loaded node.
+    ///
+    /// The normal is approximated by the central differences of the surrounding heights.
+    /// The distance between the samples is one pixel of the height attachment of the node,
+    /// so that the normal matches the level of detail, which is loaded at the position.
+    /// Neighbouring heights, which are not available (e.g. inside of holes), are replaced by the
+    /// height at the position.
+    ///
+    /// Returns `None` if the height at the position is not available (see [`Self::sample_height`]).
+    pub fn sample_normal(
+        &self,
+        node_atlas: &NodeAtlas,
+        images: &Assets<Image>,
+        position: Vec2,
+    ) -> Option<Vec3> {
+        let (entry, _) = self.lookup(position.as_dvec2())?;
+
+        let height_image = node_atlas.data[entry.atlas_index as usize]
+            ._attachments
+            .get(&0)
+            .and_then(|handle| images.get(handle))?;
+        let step = self.node_size(entry.atlas_lod as u32) as f32 / height_image.size().x;
+
+        let height = |offset: Vec2| self.sample_height(node_atlas, images, position + offset);
+
+        let center = height(Vec2::ZERO)?;
+        let left = height(Vec2::new(-step, 0.0)).unwrap_or(center);
+        let right = height(Vec2::new(step, 0.0)).unwrap_or(center);
+        let back = height(Vec2::new(0.0, -step)).unwrap_or(center);
+        let front = height(Vec2::new(0.0, step)).unwrap_or(center);
+
+        Some(Vec3::new(left - right, 2.0 * step, back - front).normalize())
+    }
+
+    /// Samples the slope of the terrain in degrees (0 flat to 90 vertical) at the position,
+    /// using the finest currently loaded node.
+    ///
+    /// Returns `None` if the height at the position is not available (see [`Self::sample_height`]).
+    pub fn sample_slope(
+        &self,
+        node_atlas: &NodeAtlas,
+        images: &Assets<Image>,
+        position: Vec2,
+    ) -> Option<f32> {
+        let normal = self.sample_normal(node_atlas, images, position)?;
+
+        Some(normal.angle_between(Vec3::Y).to_degrees())
+    }
+
+    /// Returns whether or not all nodes, which are requested by the quadtree, are loaded.
+    ///
+    /// Nodes missing from sparse terrains are never loaded and thus ignored.
+    /// This is false, as long as the quadtree has not requested any nodes yet.
+    pub fn is_loaded(&self, node_atlas: &NodeAtlas) -> bool {
+        let mut requested = self
+            .nodes
+            .iter()
+            .filter(|node| node.state == RequestState::Requested)
+            .filter(|node| node_atlas.existing_nodes.contains(&node.node_id))
+            .peekable();
+
+        requested.peek().is_some() && requested.all(|node| node_atlas.is_loaded(node.node_id))
+    }
+
+    /// Returns the nodes, which are requested by the quadtree and loaded, so that the
+    /// streaming can be resumed from them (see [`Self::warm_start`]).
+    pub fn streaming_state(&self, node_atlas: &NodeAtlas) -> StreamingState {
+        let mut nodes: Vec<NodeId> = self
+            .nodes
+            .iter()
+            .filter(|node| node.state == RequestState::Requested)
+            .map(|node| node.node_id)
+            .filter(|&node_id| node_atlas.is_loaded(node_id))
+            .collect();
+
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        StreamingState { nodes }
+    }
+
+    /// Starts loading all nodes of a previously saved streaming state at once, so that a
+    /// frequently used viewpoint comes up instantly, instead of being refined step by step.
+    ///
+    /// The nodes are loaded into the cache of the node atlas, coarse ones first and only as long
+    /// as it has unused slots. Since they are not requested, they never pin their slots and are
+    /// evicted like any other cached node, once the view requires the slots for its own nodes.
+    pub fn warm_start(&mut self, state: &StreamingState) {
+        self.pending_warm_start = state.nodes.clone();
+        self.pending_warm_start
+            .sort_by_key(|&node_id| NodeCoordinate::from(node_id).lod);
+    }
+
+    /// Caches the pending nodes of the warm start, as long as the budget allows.
+    fn update_warm_start(&mut self, budget: &mut usize) {
+        // the nodes are sorted from fine to coarse, so the coarse ones are cached first
+        let count = self.pending_warm_start.len().min(*budget);
+        let nodes = self
+            .pending_warm_start
+            .split_off(self.pending_warm_start.len() - count);
+
+        *budget -= count;
+        self.cached_nodes.extend(nodes);
+    }
+
+    /// Returns the slot, whether the node is requested and the lod of the best loaded node,
+    /// for each slot of the quadtree layer of the lod.
+    pub(crate) fn layer_states(
+        &self,
+        lod: u32,
+    ) -> impl Iterator<Item = (UVec2, bool, Option<u32>)> + '_ {
+        iproduct!(0..self.node_count, 0..self.node_count).map(move |(x, y)| {
+            let node = &self.nodes[[lod as usize, x as usize, y as usize]];
+            let entry = self.data[[lod as usize, y as usize, x as usize]];
+
+            let loaded_lod =
+                (entry.atlas_index != INVALID_ATLAS_INDEX).then_some(entry.atlas_lod as u32);
+
+            (
+                UVec2::new(x, y),
+                node.state == RequestState::Requested,
+                loaded_lod,
+            )
+        })
     }
 
     /// Calculates the size of a node.
     #[inline]
-    fn node_size(&self, lod: u32) -> u32 {
-        self.leaf_node_size * (1 << lod)
+    fn node_size(&self, lod: u32) -> f64 {
+        self.leaf_node_size as f64 * (1u64 << lod) as f64
     }
 
     /// Traverses the quadtree and updates the node states,
-    /// while selecting newly requested and released nodes.
-    pub(crate) fn compute_requests(&mut self, viewer_position: Vec3) {
+    /// while selecting newly requested and released nodes based on the heuristic.
+    ///
+    /// The selected nodes are queued for the [`NodeAtlas`].
+    pub(crate) fn compute_requests(
+        &mut self,
+        viewer_position: DVec3,
+        heuristic: &dyn RefinementHeuristic,
+        coverage: &HashSet<NodeId>,
+    ) {
+        let requests = self.update_for_view(viewer_position, None, heuristic, coverage);
+
+        self.requested_nodes.extend(requests.requested);
+        self.released_nodes.extend(requests.released);
+    }
+
+    /// Traverses the quadtree for the view and updates the node states.
+    /// Returns the nodes, which have been newly requested and released by this update.
+    ///
+    /// This is independent of the ECS and the [`NodeAtlas`], so that the refinement decisions
+    /// can be tested for any viewer position (e.g. with a [`DistanceHeuristic`](super::refinement::DistanceHeuristic)
+    /// and the nodes of a [`TerrainConfig`]).
+    ///
+    /// * `viewer_position` - The position from which the level of detail is determined.
+    /// * `frustum` - The planes of the view frustum (see [`planes`](crate::render::culling::planes)), if any.
+    ///   Nodes outside of the frustum are not requested, except for the ones of the coarsest lod.
+    /// * `heuristic` - Decides whether or not a node should be loaded.
+    /// * `coverage` - The nodes covered by the data of the terrain. Nodes outside of the
+    ///   coverage are never requested, so that the empty branches of sparse terrains are not
+    ///   traversed by the node atlas.
+    pub fn update_for_view(
+        &mut self,
+        viewer_position: DVec3,
+        frustum: Option<&[Vec4; 5]>,
+        heuristic: &dyn RefinementHeuristic,
+        coverage: &HashSet<NodeId>,
+    ) -> NodeRequests {
+        let mut requests = NodeRequests::default();
+
         for lod in 0..self.lod_count {
             let node_size = self.node_size(lod);
 
             // bottom left position of grid in node coordinates
-            let grid_coordinate: IVec2 = (viewer_position.xz() / node_size as f32 + 0.5
-                - (self.node_count >> 1) as f32)
-                .as_ivec2();
-
-            for coordinate in iproduct!(0..self.node_count as i32, 0..self.node_count as i32)
-                .filter_map(|(x, y)| {
-                    let coordinate = grid_coordinate + IVec2::new(x, y);
-
-                    if coordinate.x < 0 || coordinate.y < 0 {
-                        None
-                    } else {
-                        Some(coordinate.as_uvec2())
-                    }
-                })
-            {
-                let node_id = calc_node_id(lod, coordinate.x, coordinate.y);
-                let node = &mut self.nodes[[
+            let grid_coordinate =
+                viewer_position.xz() / node_size + 0.5 - (self.node_count >> 1) as f64;
+            let (grid_x, grid_y) = (grid_coordinate.x as i64, grid_coordinate.y as i64);
+
+            for (x, y) in iproduct!(0..self.node_count as i64, 0..self.node_count as i64) {
+                let (x, y) = (grid_x + x, grid_y + y);
+                let range = 0..=MAX_NODE_COORDINATE as i64;
+
+                // slots of coordinates outside of the valid range do not refer to any node
+                let coordinate = (range.contains(&x) && range.contains(&y))
+                    .then(|| UVec2::new(x as u32, y as u32));
+                let node_id = coordinate.map_or(INVALID_NODE_ID, |coordinate| {
+                    calc_node_id(lod, coordinate.x, coordinate.y)
+                });
+
+                let index = [
                     lod as usize,
-                    (coordinate.x % self.node_count) as usize,
-                    (coordinate.y % self.node_count) as usize,
-                ]];
+                    x.rem_euclid(self.node_count as i64) as usize,
+                    y.rem_euclid(self.node_count as i64) as usize,
+                ];
+                let node = &mut self.nodes[index];
 
                 // quadtree slot refers to a new node
                 if node_id != node.node_id {
                     // release old node
                     if node.state == RequestState::Requested {
-                        self.released_nodes.push(node.node_id);
+                        requests.released.push(node.node_id);
                         node.state = RequestState::Released;
                     }
 
                     node.node_id = node_id;
                 }
 
-                let node_position = (coordinate.as_vec2() + 0.5) * node_size as f32;
-                let world_position =
-                    Vec3::new(node_position.x, self.height_under_viewer, node_position.y);
-                let distance = viewer_position.xyz().distance(world_position);
-                let mut demanded = distance < self.load_distance * node_size as f32;
+                let coordinate = skip_none!(coordinate);
+                let previously_requested = node.state == RequestState::Requested;
+
+                let context = self.refinement_context(
+                    viewer_position,
+                    lod,
+                    coordinate,
+                    self.load_distance,
+                    previously_requested,
+                );
+                let node = &mut self.nodes[index];
+
+                let mut demanded = heuristic.request_node(&context);
                 demanded |= lod == self.lod_count - 1; // always request highest lod
+                demanded &= coverage.contains(&node_id); // never request empty branches
+                demanded &= lod == self.lod_count - 1 // never request nodes outside of the frustum
+                    || frustum.map_or(true, |planes| intersects_frustum(planes, &context));
 
                 // request or release node based on their distance to the viewer
                 match (node.state, demanded) {
                     (RequestState::Released, true) => {
-                        self.requested_nodes.push(node.node_id);
+                        requests.requested.push(node.node_id);
                         node.state = RequestState::Requested;
                     }
                     (RequestState::Requested, false) => {
-                        self.released_nodes.push(node.node_id);
+                        requests.released.push(node.node_id);
                         node.state = RequestState::Released;
                     }
                     (_, _) => {}
                 }
             }
         }
+
+        requests
+    }
+
+    /// Returns the context, with which the heuristic decides whether or not the node is loaded.
+    fn refinement_context(
+        &self,
+        viewer_position: DVec3,
+        lod: u32,
+        coordinate: UVec2,
+        load_distance: f32,
+        previously_requested: bool,
+    ) -> RefinementContext {
+        let (min, max) = self.node_bounds(lod, coordinate);
+
+        RefinementContext {
+            viewer_position,
+            height_under_viewer: self.height_under_viewer,
+            lod,
+            lod_count: self.lod_count,
+            coordinate,
+            node_size: self.node_size(lod),
+            min,
+            max,
+            load_distance,
+            previously_requested,
+        }
+    }
+
+    /// Returns the minimum and the maximum corner of the bounding box of the node,
+    /// which spans the whole height range of the terrain.
+    fn node_bounds(&self, lod: u32, coordinate: UVec2) -> (DVec3, DVec3) {
+        let node_size = self.node_size(lod);
+        let node_min = coordinate.as_dvec2() * node_size;
+        let node_max = node_min + node_size;
+
+        (
+            DVec3::new(node_min.x, self.elevation_offset as f64, node_min.y),
+            DVec3::new(
+                node_max.x,
+                (self.elevation_offset + self.height) as f64,
+                node_max.y,
+            ),
+        )
+    }
+
+    /// Returns the lods and the bounding boxes (minimum and maximum corner) of the nodes,
+    /// which are currently requested by the quadtree.
+    pub fn requested_node_bounds(&self) -> impl Iterator<Item = (u32, DVec3, DVec3)> + '_ {
+        self.nodes
+            .iter()
+            .filter(|node| node.state == RequestState::Requested)
+            .map(|node| {
+                let coordinate = NodeCoordinate::from(node.node_id);
+                let (min, max) =
+                    self.node_bounds(coordinate.lod, UVec2::new(coordinate.x, coordinate.y));
+
+                (coordinate.lod, min, max)
+            })
+    }
+
+    /// Caches the nodes around the predicted position of the viewer, so that they are
+    /// already loaded, once the viewer arrives there.
+    ///
+    /// These nodes have a lower priority than the ones of the view itself. They are not requested,
+    /// so that they stay evictable and never take the slots required by the view.
+    /// At most `budget` nodes are newly cached, which is decreased accordingly.
+    ///
+    /// * `predicted_position` - The extrapolated position of the viewer, if prefetching is enabled.
+    /// * `load_distance` - The distance (measured in node sizes) around the predicted position,
+    ///   until which to request nodes to be loaded.
+    pub(crate) fn compute_prefetch_requests(
+        &mut self,
+        predicted_position: Option<DVec3>,
+        load_distance: f32,
+        heuristic: &dyn RefinementHeuristic,
+        node_atlas: &NodeAtlas,
+        budget: &mut usize,
+    ) {
+        let coverage = &node_atlas.existing_nodes;
+        let mut predicted_nodes = HashSet::new();
+
+        if let Some(predicted_position) = predicted_position {
+            for lod in 0..self.lod_count {
+                let node_size = self.node_size(lod);
+                let count = (load_distance.ceil() as i64 + 1).max(1);
+                let center = (predicted_position.xz() / node_size).floor();
+
+                for (x, y) in iproduct!(-count..=count, -count..=count) {
+                    let (x, y) = (center.x as i64 + x, center.y as i64 + y);
+                    let range = 0..=MAX_NODE_COORDINATE as i64;
+
+                    if !range.contains(&x) || !range.contains(&y) {
+                        continue;
+                    }
+
+                    let coordinate = UVec2::new(x as u32, y as u32);
+                    let node_id = calc_node_id(lod, coordinate.x, coordinate.y);
+
+                    if !coverage.contains(&node_id) {
+                        continue;
+                    }
+
+                    let context = self.refinement_context(
+                        predicted_position,
+                        lod,
+                        coordinate,
+                        load_distance,
+                        self.prefetched_nodes.contains(&node_id),
+                    );
+
+                    if heuristic.request_node(&context) {
+                        predicted_nodes.insert(node_id);
+                    }
+                }
+            }
+        }
+
+        // forget nodes that are no longer predicted or have been evicted in the meantime
+        self.prefetched_nodes.retain(|node_id| {
+            predicted_nodes.contains(node_id) && node_atlas.nodes.contains_key(node_id)
+        });
+
+        // cache newly predicted nodes, coarse ones first, as long as the budget allows
+        let mut cached: Vec<NodeId> = predicted_nodes
+            .into_iter()
+            .filter(|node_id| !self.prefetched_nodes.contains(node_id))
+            .collect();
+        cached.sort_by_key(|&node_id| Reverse(NodeCoordinate::from(node_id).lod));
+        cached.truncate(*budget);
+
+        *budget -= cached.len();
+        self.prefetched_nodes.extend(&cached);
+        self.cached_nodes.extend(cached);
     }
 
     /// Adjusts the quadtree to the node atlas by updating the entries with the best available nodes.
-    fn adjust(&mut self, node_atlas: &NodeAtlas) {
+    ///
+    /// Entries of nodes outside of the coverage are marked as uncovered instead.
+    fn adjust(&mut self, node_atlas: &NodeAtlas, coverage: &HashSet<NodeId>) {
         for ((lod, x, y), node) in self.nodes.indexed_iter_mut() {
             let mut node_id = node.node_id;
             let mut coordinate = NodeCoordinate::from(node_id);
 
             let (atlas_index, atlas_lod) = loop {
+                if node_id != INVALID_NODE_ID && !coverage.contains(&node_id) {
+                    // node lies outside of the coverage of the terrain
+                    break (INVALID_ATLAS_INDEX, UNCOVERED_LOD);
+                }
+
                 if coordinate.lod == self.lod_count || node_id == INVALID_NODE_ID {
                     // highest lod is not loaded
                     break (INVALID_ATLAS_INDEX, u16::MAX);
@@ -255,20 +749,113 @@ impl Quadtree {
     }
 }
 
+/// The weight of the velocity of the current frame in the smoothed viewer velocity.
+const VELOCITY_SMOOTHING: f32 = 0.1;
+
+/// Updates the positions from which the level of detail of the terrain views is determined,
+/// alongside their velocities.
+pub(crate) fn update_viewer_position(
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    view_query: Query<
+        (Entity, &GlobalTransform, Option<&Projection>),
+        (With<TerrainView>, Without<DependentTerrainView>),
+    >,
+    terrain_query: Query<Entity, With<Terrain>>,
+    time: Res<Time>,
+    debug: Option<Res<DebugTerrain>>,
+) {
+    if let Some(debug) = debug {
+        if debug.freeze_view {
+            return;
+        }
+    }
+
+    let delta_time = time.delta_seconds();
+
+    for terrain in terrain_query.iter() {
+        for (view, view_transform, projection) in view_query.iter() {
+            if let Some(view_config) = view_configs.get_mut(&(terrain, view)) {
+                let position =
+                    viewer_position(view_transform, projection, view_config.height_under_viewer);
+
+                // the velocity is smoothed, so that single jittery frames do not trigger prefetching
+                if delta_time > 0.0 {
+                    let velocity = (position - view_config.viewer_position) / delta_time;
+                    view_config.viewer_velocity = view_config
+                        .viewer_velocity
+                        .lerp(velocity, VELOCITY_SMOOTHING);
+                }
+
+                view_config.viewer_position = position;
+            }
+        }
+    }
+}
+
 /// Traverses all quadtrees and updates the node states,
 /// while selecting newly requested and released nodes.
+///
+/// The nodes of warm starts are cached into the unused slots of the node atlas.
+/// The nodes along the predicted motion of the views are only prefetched, while the node atlas
+/// is idle (no node is loading or newly requested), and only into its unused slots.
+/// Neither is requested, so they never pin slots required by the views.
 pub(crate) fn compute_quadtree_request(
+    refinement: Res<TerrainRefinement>,
     mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
-    view_query: Query<(Entity, &GlobalTransform), With<TerrainView>>,
-    terrain_query: Query<(Entity, &GlobalTransform), With<Terrain>>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+    view_query: Query<Entity, With<TerrainView>>,
+    terrain_query: Query<(Entity, &TerrainConfig, &NodeAtlas, &GlobalTransform), With<Terrain>>,
 ) {
     // Todo: properly take the terrain transform into account
-    for (terrain, _terrain_transform) in terrain_query.iter() {
-        for (view, view_transform) in view_query.iter() {
-            let view_position = view_transform.translation();
-            let quadtree = quadtrees.get_mut(&(terrain, view)).unwrap();
+    for (terrain, config, node_atlas, _terrain_transform) in terrain_query.iter() {
+        let mut idle = true;
 
-            quadtree.compute_requests(view_position);
+        for view in view_query.iter() {
+            if let (Some(view_config), Some(quadtree)) = (
+                view_configs.get(&(terrain, view)),
+                quadtrees.get_mut(&(terrain, view)),
+            ) {
+                quadtree.compute_requests(
+                    view_config.global_viewer_position(),
+                    refinement.0.as_ref(),
+                    &config.nodes,
+                );
+
+                idle &= quadtree.requested_nodes.is_empty();
+            }
+        }
+
+        let statistics = node_atlas.statistics();
+        let mut unused_slots = (statistics.free + statistics.cached) as usize;
+
+        for view in view_query.iter() {
+            if let Some(quadtree) = quadtrees.get_mut(&(terrain, view)) {
+                unused_slots = unused_slots.saturating_sub(quadtree.requested_nodes.len());
+                quadtree.update_warm_start(&mut unused_slots);
+            }
+        }
+
+        let mut budget = if idle && statistics.loading == 0 {
+            unused_slots
+        } else {
+            0
+        };
+
+        for view in view_query.iter() {
+            if let (Some(view_config), Some(quadtree)) = (
+                view_configs.get(&(terrain, view)),
+                quadtrees.get_mut(&(terrain, view)),
+            ) {
+                quadtree.compute_prefetch_requests(
+                    view_config
+                        .predicted_viewer_position()
+                        .map(|position| view_config.viewer_origin + position.as_dvec3()),
+                    view_config.prefetch_distance,
+                    refinement.0.as_ref(),
+                    node_atlas,
+                    &mut budget,
+                );
+            }
         }
     }
 }
@@ -278,13 +865,37 @@ pub(crate) fn compute_quadtree_request(
 pub(crate) fn adjust_quadtree(
     mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
     view_query: Query<Entity, With<TerrainView>>,
-    mut terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
+    mut terrain_query: Query<(Entity, &NodeAtlas, &TerrainConfig), With<Terrain>>,
 ) {
-    for (terrain, mut node_atlas) in terrain_query.iter_mut() {
+    for (terrain, mut node_atlas, config) in terrain_query.iter_mut() {
         for view in view_query.iter() {
-            let quadtree = quadtrees.get_mut(&(terrain, view)).unwrap();
+            if let Some(quadtree) = quadtrees.get_mut(&(terrain, view)) {
+                quadtree.adjust(&mut node_atlas, &config.nodes);
+            }
+        }
+    }
+}
+
+/// Sends a [`TerrainEvent::InitialLoadComplete`], once all nodes required by a terrain view
+/// have finished loading for the first time.
+pub(crate) fn send_initial_load_events(
+    quadtrees: Res<TerrainViewComponents<Quadtree>>,
+    mut terrain_events: EventWriter<TerrainEvent>,
+    mut completed: Local<HashSet<(Entity, Entity)>>,
+    terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
+) {
+    completed.retain(|key| quadtrees.0.contains_key(key));
+
+    for (&(terrain, view), quadtree) in &quadtrees.0 {
+        if completed.contains(&(terrain, view)) {
+            continue;
+        }
 
-            quadtree.adjust(&mut node_atlas);
+        let (_, node_atlas) = skip_none!(terrain_query.get(terrain).ok());
+
+        if quadtree.is_loaded(node_atlas) {
+            completed.insert((terrain, view));
+            terrain_events.send(TerrainEvent::InitialLoadComplete { terrain, view });
         }
     }
 }
@@ -293,57 +904,25 @@ pub(crate) fn update_height_under_viewer(
     images: Res<Assets<Image>>,
     mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
     mut terrain_view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
-    view_query: Query<(Entity, &GlobalTransform), With<TerrainView>>,
+    view_query: Query<Entity, With<TerrainView>>,
     mut terrain_query: Query<(Entity, &NodeAtlas), With<Terrain>>,
 ) {
     for (terrain, node_atlas) in terrain_query.iter_mut() {
-        for (view, view_transform) in view_query.iter() {
+        for view in view_query.iter() {
             if let Some(quadtree) = quadtrees.get_mut(&(terrain, view)) {
-                quadtree.height_under_viewer = height_under_viewer(
-                    quadtree,
-                    &node_atlas,
-                    &images,
-                    view_transform.translation().xz(),
-                );
-
-                terrain_view_configs
-                    .get_mut(&(terrain, view))
-                    .unwrap()
-                    .height_under_viewer = quadtree.height_under_viewer;
+                let view_config = terrain_view_configs.get_mut(&(terrain, view)).unwrap();
+
+                // sample at the same position, from which the nodes are selected
+                quadtree.height_under_viewer = quadtree
+                    .sample_global_height(
+                        &node_atlas,
+                        &images,
+                        view_config.global_viewer_position().xz(),
+                    )
+                    .unwrap_or(quadtree.height_under_viewer);
+
+                view_config.height_under_viewer = quadtree.height_under_viewer;
             }
         }
     }
 }
-
-fn height_under_viewer(
-    quadtree: &Quadtree,
-    node_atlas: &NodeAtlas,
-    images: &Assets<Image>,
-    viewer_position: Vec2,
-) -> f32 {
-    let coordinate =
-        (viewer_position / quadtree.leaf_node_size as f32).as_uvec2() % quadtree.node_count;
-
-    let node = &quadtree.data[[0, coordinate.y as usize, coordinate.x as usize]];
-    let atlas_coords = (viewer_position / quadtree.leaf_node_size as f32) % 1.0;
-
-    if node.atlas_index == INVALID_ATLAS_INDEX {
-        return 0.0;
-    }
-
-    if let Some(node) = node_atlas.data[node.atlas_index as usize]
-        ._attachments
-        .get(&0)
-    {
-        if let Some(image) = images.get(node) {
-            let position = (image.size() * atlas_coords).as_uvec2();
-            let index = 2 * (position.x + position.y * image.size().x as u32) as usize;
-            let height = ((image.data[index + 1] as u16) << 8) + image.data[index] as u16;
-            let height = height as f32 / u16::MAX as f32 * quadtree.height;
-
-            return height;
-        }
-    }
-
-    return quadtree.height_under_viewer;
-}
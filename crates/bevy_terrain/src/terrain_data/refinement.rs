@@ -0,0 +1,258 @@
+//! The refinement heuristic decides which level of detail is selected for each part of the terrain.
+//!
+//! The decision is made in two places: on the CPU by the [`Quadtree`](super::quadtree::Quadtree),
+//! which requests the nodes that should be loaded, and on the GPU by the tile refinement
+//! compute shader, which selects the geometry.
+//! A [`RefinementHeuristic`] bundles both halves, so that they can be swapped out together.
+
+use crate::{
+    render::{
+        diagnostics::{TERRAIN_DRAW_MS, TERRAIN_REFINE_MS},
+        shaders::REFINEMENT_SHADER,
+    },
+    terrain_data::quadtree::Quadtree,
+    TerrainViewComponents, TerrainViewConfig,
+};
+use bevy::{
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    math::{DVec2, DVec3},
+    prelude::*,
+};
+use std::sync::Arc;
+
+/// The data available to the CPU side of a [`RefinementHeuristic`] for a single node.
+///
+/// The positions and sizes use double precision, so that they remain exact for Earth-scale terrains.
+pub struct RefinementContext {
+    /// The world position of the viewer (camera, light, etc.).
+    pub viewer_position: DVec3,
+    /// The current height of the terrain under the viewer.
+    pub height_under_viewer: f32,
+    /// The lod of the node.
+    pub lod: u32,
+    /// The count of level of detail layers.
+    pub lod_count: u32,
+    /// The x and y position of the node in node sizes.
+    pub coordinate: UVec2,
+    /// The size of the node.
+    pub node_size: f64,
+    /// The minimum corner of the bounding box of the node.
+    pub min: DVec3,
+    /// The maximum corner of the bounding box of the node.
+    pub max: DVec3,
+    /// The distance (measured in node sizes) until which to request nodes to be loaded.
+    pub load_distance: f32,
+    /// Whether or not the node has been requested in the previous frame.
+    pub previously_requested: bool,
+}
+
+/// A level of detail selection metric.
+///
+/// The CPU side is evaluated by the quadtree for every node each frame.
+/// The GPU side is a shader with the `bevy_terrain::refinement` import path, which has to define
+/// the `fn should_be_divided(tile: Tile) -> bool` function used by the refinement compute shader.
+/// It has access to the `view_config`, the culling `view` and all of the terrain bindings.
+pub trait RefinementHeuristic: Send + Sync + 'static {
+    /// Decides whether or not the node should be loaded.
+    fn request_node(&self, context: &RefinementContext) -> bool;
+
+    /// The shader implementing the GPU side of the heuristic.
+    fn shader() -> Shader
+    where
+        Self: Sized;
+}
+
+/// The built-in heuristic, which requests and divides based on the distance to the viewer.
+#[derive(Clone, Copy, Default)]
+pub struct DistanceHeuristic;
+
+impl RefinementHeuristic for DistanceHeuristic {
+    fn request_node(&self, context: &RefinementContext) -> bool {
+        let node_position = (context.coordinate.as_dvec2() + DVec2::splat(0.5)) * context.node_size;
+        let world_position = DVec3::new(
+            node_position.x,
+            context.height_under_viewer as f64,
+            node_position.y,
+        );
+
+        context.viewer_position.distance(world_position)
+            < context.load_distance as f64 * context.node_size
+    }
+
+    fn shader() -> Shader {
+        Shader::from_wgsl(include_str!("../render/shaders/compute/refinement.wgsl"))
+    }
+}
+
+/// Stores the [`RefinementHeuristic`] used by all quadtrees.
+#[derive(Clone, Resource)]
+pub struct TerrainRefinement(pub(crate) Arc<dyn RefinementHeuristic>);
+
+impl Default for TerrainRefinement {
+    fn default() -> Self {
+        Self(Arc::new(DistanceHeuristic))
+    }
+}
+
+/// Replaces the default [`DistanceHeuristic`] with a custom [`RefinementHeuristic`].
+///
+/// Has to be added after the [`TerrainPlugin`](crate::TerrainPlugin).
+pub struct RefinementHeuristicPlugin<H: RefinementHeuristic + Clone>(pub H);
+
+impl<H: RefinementHeuristic + Clone> Plugin for RefinementHeuristicPlugin<H> {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerrainRefinement(Arc::new(self.0.clone())));
+
+        app.world
+            .resource_mut::<Assets<Shader>>()
+            .set_untracked(REFINEMENT_SHADER, H::shader());
+    }
+}
+
+/// Adapts the refinement of all terrain views to hold a target frame time.
+///
+/// The frame time is measured on the GPU, if possible: the sum of the `terrain/refine_ms` and
+/// `terrain/draw_ms` timings of the
+/// [`TerrainDiagnosticsPlugin`](crate::render::diagnostics::TerrainDiagnosticsPlugin),
+/// which require timestamp queries (`WgpuFeatures::TIMESTAMP_QUERY`).
+/// Since the draw timing covers the whole main pass, this is close to the GPU time of the frame.
+/// Otherwise the CPU frame time of the [`FrameTimeDiagnosticsPlugin`] is used,
+/// which includes the time waiting for the GPU, but also the time of all other systems.
+/// Either plugin has to be added as well.
+///
+/// If the frame time exceeds the target, the tiles are enlarged, fewer additional levels of
+/// detail are refined and fewer nodes are loaded around the viewer (coarser terrain),
+/// if it drops below the target, the adjustments are reverted step by step (finer terrain).
+#[derive(Clone, Resource)]
+pub struct AdaptiveRefinement {
+    /// Whether or not the refinement is currently adapted.
+    pub enabled: bool,
+    /// The frame time to hold in milliseconds.
+    pub target_frame_time: f32,
+    /// The relative deviation from the target frame time, which is tolerated without adjustments.
+    pub tolerance: f32,
+    /// The smallest (finest) tile scale.
+    pub min_tile_scale: f32,
+    /// The largest (coarsest) tile scale.
+    pub max_tile_scale: f32,
+    /// The smallest `load_distance`, to which the views are reduced.
+    pub min_load_distance: f32,
+    /// The largest `load_distance`, to which the views are extended.
+    pub max_load_distance: f32,
+    /// The largest `additional_refinement` (amount of additional levels of detail).
+    pub max_additional_refinement: u32,
+    /// The factor the tile scale and the load distance are multiplied or divided by
+    /// in each adjustment.
+    pub step: f32,
+    /// The time in seconds between two adjustments, which allows the frame time to settle.
+    pub interval: f32,
+    elapsed: f32,
+}
+
+impl Default for AdaptiveRefinement {
+    fn default() -> Self {
+        Self::from_frame_rate(60.0)
+    }
+}
+
+impl AdaptiveRefinement {
+    /// Creates a new adaptive refinement, which holds the frame rate (frames per second).
+    pub fn from_frame_rate(frame_rate: f32) -> Self {
+        Self {
+            enabled: true,
+            target_frame_time: 1000.0 / frame_rate,
+            tolerance: 0.1,
+            min_tile_scale: 1.0,
+            max_tile_scale: 64.0,
+            min_load_distance: 2.0,
+            max_load_distance: 8.0,
+            max_additional_refinement: 2,
+            step: 1.2,
+            interval: 0.5,
+            elapsed: 0.0,
+        }
+    }
+}
+
+/// Adapts the refinement of all terrain views to the frame time.
+///
+/// Has to be added after the [`TerrainPlugin`](crate::TerrainPlugin).
+pub struct AdaptiveRefinementPlugin(pub AdaptiveRefinement);
+
+impl Plugin for AdaptiveRefinementPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.0.clone())
+            .add_system(adapt_refinement);
+    }
+}
+
+/// Returns the smoothed GPU frame time, if timestamp queries are measured,
+/// and the CPU frame time otherwise.
+fn frame_time(diagnostics: &Diagnostics) -> Option<f32> {
+    let smoothed = |id| {
+        diagnostics
+            .get(id)
+            .and_then(|diagnostic| diagnostic.smoothed())
+    };
+
+    smoothed(TERRAIN_DRAW_MS)
+        .map(|draw_ms| draw_ms + smoothed(TERRAIN_REFINE_MS).unwrap_or(0.0))
+        .or_else(|| smoothed(FrameTimeDiagnosticsPlugin::FRAME_TIME))
+        .map(|frame_time| frame_time as f32)
+}
+
+fn adapt_refinement(
+    time: Res<Time>,
+    diagnostics: Res<Diagnostics>,
+    mut adaptive: ResMut<AdaptiveRefinement>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+) {
+    if !adaptive.enabled {
+        return;
+    }
+
+    adaptive.elapsed += time.delta_seconds();
+
+    if adaptive.elapsed < adaptive.interval {
+        return;
+    }
+
+    adaptive.elapsed = 0.0;
+
+    let frame_time = match frame_time(&diagnostics) {
+        Some(frame_time) => frame_time,
+        None => return,
+    };
+
+    let coarser = if frame_time > adaptive.target_frame_time * (1.0 + adaptive.tolerance) {
+        true
+    } else if frame_time < adaptive.target_frame_time * (1.0 - adaptive.tolerance) {
+        false
+    } else {
+        return;
+    };
+
+    let factor = if coarser {
+        adaptive.step
+    } else {
+        adaptive.step.recip()
+    };
+
+    for (key, view_config) in view_configs.0.iter_mut() {
+        view_config.tile_scale = (view_config.tile_scale * factor)
+            .clamp(adaptive.min_tile_scale, adaptive.max_tile_scale);
+        view_config.load_distance = (view_config.load_distance / factor)
+            .clamp(adaptive.min_load_distance, adaptive.max_load_distance);
+        view_config.additional_refinement = if coarser {
+            view_config.additional_refinement.saturating_sub(1)
+        } else {
+            (view_config.additional_refinement + 1).min(adaptive.max_additional_refinement)
+        };
+
+        // dependent views have no quadtree of their own
+        if let Some(quadtree) = quadtrees.get_mut(key) {
+            quadtree.load_distance = view_config.load_distance;
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! CPU access to the currently loaded terrain data, e.g. for placing objects on the terrain.
+
+use crate::terrain_data::{node_atlas::NodeAtlas, quadtree::Quadtree, AttachmentIndex};
+use bevy::{math::Rect, prelude::*};
+
+/// The rules, which decide where objects may be placed by [`TerrainSampler::scatter`].
+#[derive(Clone, Debug)]
+pub struct ScatterRules {
+    /// The seed of the placement. The same seed always produces the same placements.
+    pub seed: u64,
+    /// The maximum slope of the terrain in degrees.
+    pub max_slope: f32,
+    /// The minimum height of the terrain.
+    pub min_height: f32,
+    /// The maximum height of the terrain.
+    pub max_height: f32,
+    /// Whether the objects are tilted to align with the terrain normal, instead of staying upright.
+    pub align_to_normal: bool,
+}
+
+impl Default for ScatterRules {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            max_slope: 90.0,
+            min_height: f32::MIN,
+            max_height: f32::MAX,
+            align_to_normal: false,
+        }
+    }
+}
+
+/// Samples the height and slope of the terrain from the nodes currently loaded by a quadtree.
+///
+/// Positions, whose nodes are not loaded yet or which lie inside holes of the mask, yield `None`.
+pub struct TerrainSampler<'a> {
+    quadtree: &'a Quadtree,
+    node_atlas: &'a NodeAtlas,
+    images: &'a Assets<Image>,
+}
+
+impl<'a> TerrainSampler<'a> {
+    pub fn new(
+        quadtree: &'a Quadtree,
+        node_atlas: &'a NodeAtlas,
+        images: &'a Assets<Image>,
+    ) -> Self {
+        Self {
+            quadtree,
+            node_atlas,
+            images,
+        }
+    }
+
+    /// Returns the height of the terrain at the position in the horizontal (x, z) plane.
+    pub fn height(&self, position: Vec2) -> Option<f32> {
+        self.quadtree
+            .sample_height(self.node_atlas, self.images, position)
+    }
+
+    /// Returns the raw value of the attachment at the position in the horizontal (x, z) plane.
+    pub fn attachment(&self, attachment_index: AttachmentIndex, position: Vec2) -> Option<f32> {
+        self.quadtree
+            .sample_attachment(self.node_atlas, self.images, attachment_index, position)
+    }
+
+    /// Returns the normal of the terrain at the position, approximated using central differences.
+    pub fn normal(&self, position: Vec2) -> Option<Vec3> {
+        let left = self.height(position - Vec2::X)?;
+        let right = self.height(position + Vec2::X)?;
+        let up = self.height(position - Vec2::Y)?;
+        let down = self.height(position + Vec2::Y)?;
+
+        Some(Vec3::new(left - right, 2.0, up - down).normalize())
+    }
+
+    /// Scatters objects inside the region with the density (objects per square unit) and
+    /// returns their positions and rotations.
+    ///
+    /// The region is divided into a grid aligned to the terrain origin, where each cell contains
+    /// at most one object at a random position. Because the randomness only depends on the seed
+    /// and the cell, the placements are deterministic and overlapping regions agree with each other.
+    /// Cells, whose terrain data is not loaded yet, are skipped.
+    pub fn scatter(&self, region: Rect, density: f32, rules: &ScatterRules) -> Vec<(Vec3, Quat)> {
+        if density <= 0.0 {
+            return Vec::new();
+        }
+
+        let cell_size = density.recip().sqrt();
+        let max_slope = rules.max_slope.to_radians().cos();
+
+        let first = (region.min / cell_size).floor().as_ivec2();
+        let last = (region.max / cell_size).ceil().as_ivec2();
+
+        let mut placements = Vec::new();
+
+        for y in first.y..last.y {
+            for x in first.x..last.x {
+                let mut rng = fastrand::Rng::with_seed(cell_seed(rules.seed, x, y));
+
+                let offset = Vec2::new(rng.f32(), rng.f32());
+                let position = (IVec2::new(x, y).as_vec2() + offset) * cell_size;
+
+                if position.cmplt(region.min).any() || position.cmpge(region.max).any() {
+                    continue;
+                }
+
+                let (height, normal) = match (self.height(position), self.normal(position)) {
+                    (Some(height), Some(normal)) => (height, normal),
+                    _ => continue,
+                };
+
+                if height < rules.min_height || height > rules.max_height || normal.y < max_slope {
+                    continue;
+                }
+
+                let mut rotation = Quat::from_rotation_y(rng.f32() * std::f32::consts::TAU);
+
+                if rules.align_to_normal {
+                    rotation = Quat::from_rotation_arc(Vec3::Y, normal) * rotation;
+                }
+
+                placements.push((Vec3::new(position.x, height, position.y), rotation));
+            }
+        }
+
+        placements
+    }
+}
+
+/// Combines the seed with the cell coordinate into the seed of the cell.
+fn cell_seed(seed: u64, x: i32, y: i32) -> u64 {
+    let cell = (x as u32 as u64) << 32 | y as u32 as u64;
+    (seed ^ cell.wrapping_mul(0x9E37_79B9_7F4A_7C15)).wrapping_mul(0xBF58_476D_1CE4_E5B9)
+}
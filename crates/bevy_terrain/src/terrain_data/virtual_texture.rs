@@ -0,0 +1,476 @@
+//! Virtual texturing of imagery attachments, which are too large to fit into the node atlas
+//! (e.g. orthophotos with a resolution of a few centimeters covering a whole country).
+//!
+//! Instead of whole nodes, the imagery is split into small pages by the preprocessor, which are
+//! streamed into a physical page cache. Only the pages, which are required at the current screen
+//! resolution, are resident. They are determined by a feedback pass: while shading the terrain,
+//! a sparse, rotating subset of the fragments marks the page it would like to sample in a
+//! feedback buffer, which is read back to the CPU a frame later.
+//!
+//! The page table is a clipmap, which is centered under a view like the
+//! [`Quadtree`](super::quadtree::Quadtree). For each lod it stores the best resident page
+//! for `page_table_size` x `page_table_size` pages around the viewer, which falls back to coarser
+//! pages, until the requested ones have been loaded. The pages of the coarsest lod are always
+//! kept resident, while the least recently requested pages are evicted from the page cache.
+//!
+//! Virtual textures are only available in [`TerrainRenderMode::Compute`], since the feedback
+//! is written into a storage buffer.
+//!
+//! [`TerrainRenderMode::Compute`]: crate::render::downlevel::TerrainRenderMode::Compute
+
+use crate::{
+    skip_none,
+    terrain::{Terrain, TerrainConfig},
+    terrain_data::{calc_node_id, AttachmentConfig, NodeCoordinate, NodeId, INVALID_LOD},
+    terrain_view::{TerrainViewComponents, TerrainViewConfig},
+};
+use bevy::{
+    asset::LoadState,
+    math::Vec3Swizzles,
+    prelude::*,
+    render::render_resource::{TextureFormat, TextureUsages},
+    utils::{HashMap, HashSet},
+};
+use bytemuck::{Pod, Zeroable};
+use itertools::iproduct;
+use ndarray::Array3;
+use std::{
+    cmp::Reverse,
+    sync::{Arc, Mutex},
+};
+
+/// A globally unique identifier of a page, which is encoded like a [`NodeId`].
+pub type PageId = NodeId;
+
+/// Identifier of a page inside the page cache.
+pub type PageSlot = u16;
+
+/// The maximum number of lods of a virtual texture.
+pub const MAX_VIRTUAL_TEXTURE_LODS: usize = 16;
+
+/// Configures the pages of a virtual texture.
+#[derive(Clone, Debug)]
+pub struct VirtualTextureConfig {
+    /// The imagery attachment, which is split into pages.
+    /// It is preprocessed like any other attachment, but never loaded into the node atlas.
+    pub attachment: AttachmentConfig,
+    /// The none overlapping center size of a page in pixels,
+    /// which has to divide the center size of the attachment.
+    pub page_size: u32,
+}
+
+impl VirtualTextureConfig {
+    pub fn new(attachment: AttachmentConfig, page_size: u32) -> Self {
+        let page_size = if page_size > 0 && attachment.center_size % page_size == 0 {
+            page_size
+        } else {
+            warn!(
+                "The page size of the virtual texture {} does not divide the center size of the attachment, thus each node is stored as a single page.",
+                attachment.name
+            );
+            attachment.center_size
+        };
+
+        Self {
+            attachment,
+            page_size,
+        }
+    }
+
+    /// The number of pages along each side of a node.
+    pub(crate) fn pages_per_node(&self) -> u32 {
+        self.attachment.center_size / self.page_size
+    }
+
+    /// The attachment, which stores the pages.
+    ///
+    /// Each page keeps the border size of the imagery attachment, so that it can be
+    /// filtered without sampling the neighbouring pages of the page cache.
+    pub(crate) fn page_attachment(&self) -> AttachmentConfig {
+        let mut attachment = AttachmentConfig::new(
+            format!("{}_pages", self.attachment.name),
+            self.page_size + 2 * self.attachment.border_size,
+            self.attachment.border_size,
+            1,
+            self.attachment.format,
+        );
+
+        attachment.file_format = self.attachment.file_format;
+        attachment
+    }
+}
+
+/// An entry of the page table, which references the best resident page.
+///
+/// These entries are synced each frame with the page table texture for access on the GPU.
+#[repr(C)]
+#[derive(Clone, Copy, Zeroable, Pod)]
+pub(crate) struct PageTableEntry {
+    /// The slot of the page inside the page cache.
+    slot: PageSlot,
+    /// The lod of the page.
+    lod: u16,
+}
+
+impl Default for PageTableEntry {
+    fn default() -> Self {
+        Self {
+            slot: PageSlot::MAX,
+            lod: INVALID_LOD,
+        }
+    }
+}
+
+/// A page, which has finished loading and has to be copied into its slot of the page cache.
+pub(crate) struct PageUpload {
+    pub(crate) slot: PageSlot,
+    pub(crate) handle: Handle<Image>,
+}
+
+struct ResidentPage {
+    slot: PageSlot,
+    /// The frame, in which the page has been requested last.
+    last_used: u32,
+}
+
+/// Streams the pages of a virtual texture, which are required at the current screen resolution,
+/// into a page cache.
+///
+/// Add this component to a terrain entity, when spawning it.
+/// The pages have to be generated by the preprocessor beforehand
+/// (see [`Preprocessor::add_virtual_texture`](crate::preprocess::Preprocessor::add_virtual_texture)).
+/// The built-in materials replace their color with the virtual texture, where it is resident.
+/// Custom materials can sample it via the `virtual_texture_color` function of the
+/// `bevy_terrain::virtual_texture` shader import.
+#[derive(Component)]
+pub struct TerrainVirtualTexture {
+    /// The view, around which the page table is centered.
+    pub view: Entity,
+    /// Shifts the lod of the requested pages. Positive values request coarser pages.
+    pub lod_bias: f32,
+    /// The maximum number of pages, which are loaded at the same time.
+    pub max_loading_pages: usize,
+    pub(crate) config: VirtualTextureConfig,
+    /// The number of pages along each side of the page cache.
+    pub(crate) cache_size: u32,
+    /// The number of pages along each side of each lod of the page table.
+    pub(crate) page_table_size: u32,
+    pub(crate) lod_count: u32,
+    /// The world size of a page of lod zero.
+    pub(crate) leaf_page_size: f32,
+    /// The page coordinate of the first page of the page table per lod.
+    pub(crate) origins: Vec<IVec2>,
+    pub(crate) page_table: Array3<PageTableEntry>,
+    /// The pages, which have finished loading this frame.
+    pub(crate) uploads: Vec<PageUpload>,
+    /// Shared with the render world, which stores the pages requested by the feedback pass.
+    pub(crate) feedback: Arc<Mutex<Option<Vec<PageId>>>>,
+    resident_pages: HashMap<PageId, ResidentPage>,
+    loading_pages: HashMap<PageId, Handle<Image>>,
+    /// The pages, which do not exist (e.g. in empty regions of sparse terrains).
+    missing_pages: HashSet<PageId>,
+    free_slots: Vec<PageSlot>,
+    frame: u32,
+}
+
+impl TerrainVirtualTexture {
+    /// Creates a virtual texture, whose page table is centered around the view.
+    ///
+    /// The page cache stores `cache_size` x `cache_size` pages
+    /// and the page table covers `page_table_size` x `page_table_size` pages per lod.
+    pub fn new(
+        view: Entity,
+        config: VirtualTextureConfig,
+        cache_size: u32,
+        page_table_size: u32,
+    ) -> Self {
+        // the slots have to be addressable by a page table entry
+        let cache_size = cache_size.clamp(1, 255);
+
+        if config.attachment.format.is_categorical() {
+            warn!(
+                "Categorical attachments can not be virtualized, since they can not be filtered."
+            );
+        }
+
+        Self {
+            view,
+            lod_bias: 0.0,
+            max_loading_pages: 16,
+            config,
+            cache_size,
+            page_table_size: page_table_size.max(2),
+            lod_count: 0,
+            leaf_page_size: 0.0,
+            origins: Vec::new(),
+            page_table: default(),
+            uploads: Vec::new(),
+            feedback: default(),
+            resident_pages: default(),
+            loading_pages: default(),
+            missing_pages: default(),
+            free_slots: (0..(cache_size * cache_size) as PageSlot).rev().collect(),
+            frame: 0,
+        }
+    }
+
+    /// Returns the number of pages, which are currently stored in the page cache.
+    pub fn resident_page_count(&self) -> usize {
+        self.resident_pages.len()
+    }
+
+    /// Returns whether the page is currently stored in the page cache.
+    pub fn is_resident(&self, page_id: PageId) -> bool {
+        self.resident_pages.contains_key(&page_id)
+    }
+
+    pub(crate) fn format(&self) -> TextureFormat {
+        self.config.attachment.format.into()
+    }
+
+    /// Returns the number of pages along each side of the terrain at the lod.
+    fn page_count(&self, config: &TerrainConfig, lod: u32) -> i32 {
+        (config.terrain_size as f32 / (self.leaf_page_size * (1 << lod) as f32)).ceil() as i32
+    }
+
+    /// Returns whether the page lies inside of the page table and the terrain.
+    fn is_covered(&self, config: &TerrainConfig, page_id: PageId) -> bool {
+        let coordinate = NodeCoordinate::from(page_id);
+
+        if coordinate.lod >= self.lod_count {
+            return false;
+        }
+
+        let page_count = self.page_count(config, coordinate.lod);
+        let position = IVec2::new(coordinate.x as i32, coordinate.y as i32);
+        let offset = position - self.origins[coordinate.lod as usize];
+
+        position.cmplt(IVec2::splat(page_count)).all()
+            && offset.cmpge(IVec2::ZERO).all()
+            && offset
+                .cmplt(IVec2::splat(self.page_table_size as i32))
+                .all()
+    }
+
+    /// Centers the page table of each lod under the viewer.
+    fn update_origins(&mut self, config: &TerrainConfig, viewer_position: Vec3) {
+        self.lod_count = config.lod_count.min(MAX_VIRTUAL_TEXTURE_LODS as u32);
+        self.leaf_page_size = config.leaf_node_size as f32 / self.config.pages_per_node() as f32;
+
+        let size = self.page_table_size as usize;
+
+        if self.page_table.dim() != (self.lod_count as usize, size, size) {
+            self.page_table = Array3::default((self.lod_count as usize, size, size));
+        }
+
+        self.origins = (0..self.lod_count)
+            .map(|lod| {
+                let page_size = self.leaf_page_size * (1 << lod) as f32;
+
+                (viewer_position.xz() / page_size).round().as_ivec2()
+                    - (self.page_table_size / 2) as i32
+            })
+            .collect();
+    }
+
+    /// Returns the first free slot of the page cache or evicts the least recently used page,
+    /// which has not been requested this frame.
+    fn allocate_slot(&mut self) -> Option<PageSlot> {
+        if let Some(slot) = self.free_slots.pop() {
+            return Some(slot);
+        }
+
+        let (&page_id, _) = self
+            .resident_pages
+            .iter()
+            .filter(|(_, page)| page.last_used < self.frame)
+            .min_by_key(|(_, page)| page.last_used)?;
+
+        self.resident_pages.remove(&page_id).map(|page| page.slot)
+    }
+
+    /// Starts loading the requested pages, which are neither resident nor loading.
+    fn request_pages(
+        &mut self,
+        asset_server: &AssetServer,
+        config: &TerrainConfig,
+        requested: Vec<PageId>,
+    ) {
+        let mut pages = Vec::new();
+
+        for page_id in requested {
+            if let Some(page) = self.resident_pages.get_mut(&page_id) {
+                page.last_used = self.frame;
+            } else if !self.loading_pages.contains_key(&page_id)
+                && !self.missing_pages.contains(&page_id)
+                && self.is_covered(config, page_id)
+            {
+                pages.push(page_id);
+            }
+        }
+
+        // the coarser pages are loaded first, since they are the fallback of the finer ones
+        pages
+            .sort_unstable_by_key(|&page_id| (Reverse(NodeCoordinate::from(page_id).lod), page_id));
+        pages.dedup();
+
+        let directory = format!(
+            "{}/data/{}",
+            config.path,
+            self.config.page_attachment().name
+        );
+        let extension = self.config.attachment.file_format.extension();
+        let count = self
+            .max_loading_pages
+            .saturating_sub(self.loading_pages.len());
+
+        for page_id in pages.into_iter().take(count) {
+            let handle = asset_server.load(&format!("{directory}/{page_id}.{extension}"));
+            self.loading_pages.insert(page_id, handle);
+        }
+    }
+
+    /// Moves the pages, which have finished loading, into the page cache.
+    fn finish_loading(&mut self, asset_server: &AssetServer, images: &mut Assets<Image>) {
+        let mut loaded = Vec::new();
+        let missing_pages = &mut self.missing_pages;
+
+        self.loading_pages.retain(
+            |&page_id, handle| match asset_server.get_load_state(&*handle) {
+                LoadState::Loaded => {
+                    loaded.push((page_id, handle.clone()));
+                    false
+                }
+                LoadState::Failed => {
+                    missing_pages.insert(page_id);
+                    false
+                }
+                _ => true,
+            },
+        );
+
+        let format = self.format();
+
+        for (page_id, handle) in loaded {
+            // the page is requested again by the feedback, once a slot is available
+            let slot = skip_none!(self.allocate_slot());
+
+            if let Some(image) = images.get_mut(&handle) {
+                image.texture_descriptor.format = format;
+                image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+            }
+
+            self.resident_pages.insert(
+                page_id,
+                ResidentPage {
+                    slot,
+                    last_used: self.frame,
+                },
+            );
+            self.uploads.push(PageUpload { slot, handle });
+        }
+    }
+
+    /// Returns the entry of the page table at the page coordinate,
+    /// or an invalid one, if it lies outside of the page table.
+    fn entry(&self, lod: u32, position: IVec2) -> PageTableEntry {
+        let size = self.page_table_size as i32;
+        let offset = position - self.origins[lod as usize];
+
+        if offset.cmplt(IVec2::ZERO).any() || offset.cmpge(IVec2::splat(size)).any() {
+            return default();
+        }
+
+        self.page_table[[
+            lod as usize,
+            position.y.rem_euclid(size) as usize,
+            position.x.rem_euclid(size) as usize,
+        ]]
+    }
+
+    /// Stores the best resident page for each entry of the page table,
+    /// starting at the coarsest lod, so that missing pages fall back to their parents.
+    fn update_page_table(&mut self) {
+        let size = self.page_table_size as i32;
+
+        for lod in (0..self.lod_count).rev() {
+            let origin = self.origins[lod as usize];
+
+            for (y, x) in iproduct!(origin.y..origin.y + size, origin.x..origin.x + size) {
+                let page = (x >= 0 && y >= 0)
+                    .then(|| calc_node_id(lod, x as u32, y as u32))
+                    .and_then(|page_id| self.resident_pages.get(&page_id));
+
+                let entry = match page {
+                    Some(page) => PageTableEntry {
+                        slot: page.slot,
+                        lod: lod as u16,
+                    },
+                    None if lod + 1 < self.lod_count => {
+                        self.entry(lod + 1, IVec2::new(x.div_euclid(2), y.div_euclid(2)))
+                    }
+                    None => default(),
+                };
+
+                self.page_table[[
+                    lod as usize,
+                    y.rem_euclid(size) as usize,
+                    x.rem_euclid(size) as usize,
+                ]] = entry;
+            }
+        }
+    }
+
+    /// Streams the requested pages into the page cache and updates the page table.
+    fn update(
+        &mut self,
+        asset_server: &AssetServer,
+        images: &mut Assets<Image>,
+        config: &TerrainConfig,
+        viewer_position: Vec3,
+    ) {
+        self.frame += 1;
+
+        // the uploads have been extracted into the render world by now,
+        // apps without a renderer drop them
+        self.uploads.clear();
+        self.update_origins(config, viewer_position);
+
+        let mut requested = self.feedback.lock().unwrap().take().unwrap_or_default();
+
+        // the pages of the coarsest lod are always resident, so that there is a fallback everywhere
+        let lod = self.lod_count - 1;
+        let origin = self.origins[lod as usize];
+        let size = self.page_table_size as i32;
+
+        requested.extend(
+            iproduct!(origin.x..origin.x + size, origin.y..origin.y + size)
+                .filter(|&(x, y)| x >= 0 && y >= 0)
+                .map(|(x, y)| calc_node_id(lod, x as u32, y as u32)),
+        );
+
+        self.request_pages(asset_server, config, requested);
+        self.finish_loading(asset_server, images);
+        self.update_page_table();
+    }
+}
+
+/// Streams the pages of the [`TerrainVirtualTexture`]s, which have been requested by the
+/// feedback pass, and updates their page tables.
+pub(crate) fn update_virtual_textures(
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    view_configs: Res<TerrainViewComponents<TerrainViewConfig>>,
+    mut terrain_query: Query<(Entity, &TerrainConfig, &mut TerrainVirtualTexture), With<Terrain>>,
+) {
+    for (terrain, config, mut virtual_texture) in terrain_query.iter_mut() {
+        let view_config = skip_none!(view_configs.get(&(terrain, virtual_texture.view)));
+
+        virtual_texture.update(
+            &asset_server,
+            &mut images,
+            config,
+            view_config.viewer_position,
+        );
+    }
+}
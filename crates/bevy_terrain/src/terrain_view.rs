@@ -1,12 +1,17 @@
 //! Types for configuring terrain views.
 
+use crate::terrain_data::quadtree::Quadtree;
 use bevy::{
     ecs::{query::QueryItem, system::lifetimeless::Read},
+    math::DVec3,
     prelude::*,
     render::extract_component::ExtractComponent,
-    utils::{HashMap, Uuid},
+    utils::{HashMap, HashSet, Uuid},
 };
-use std::str::FromStr;
+use std::{f32::consts::FRAC_PI_4, str::FromStr};
+
+/// The vertical field of view of the perspective view, which orthographic views are refined like.
+pub(crate) const ORTHOGRAPHIC_REFERENCE_FOV: f32 = FRAC_PI_4;
 
 /// Resource that stores components that are associated to a terrain entity and a view entity.
 #[derive(Clone, Resource)]
@@ -24,6 +29,11 @@ impl<C> TerrainViewComponents<C> {
     pub fn insert(&mut self, k: (Entity, Entity), v: C) {
         self.0.insert(k, v);
     }
+
+    /// Removes the components of all terrains associated to the view.
+    pub fn remove_view(&mut self, view: Entity) {
+        self.0.retain(|&(_, v), _| v != view);
+    }
 }
 
 impl<C> FromWorld for TerrainViewComponents<C> {
@@ -46,6 +56,101 @@ impl ExtractComponent for TerrainView {
     }
 }
 
+/// Turns a terrain view into a lightweight view, which depends on a primary terrain view.
+///
+/// Dependent views are intended for secondary cameras, like water reflections, which see the
+/// same terrain from roughly the same position. Instead of maintaining their own [`Quadtree`],
+/// they reuse the node residency and the level of detail selection of the primary view and
+/// only perform their own frustum culling.
+///
+/// The [`DependentRefinement`] determines, whether the tiles of the primary view are culled
+/// or the quadtree of the primary view is refined again for the dependent view.
+/// The downlevel mode always refines each view on its own.
+/// A chain of dependent views depends on the view at its end, which refines its own quadtree.
+/// Views inside of a cycle of dependent views are not rendered.
+///
+/// Spawn the camera with a [`TerrainView`] and this component. The view configs are copied
+/// from the primary view every frame, so they do not have to be inserted manually.
+#[derive(Clone, Copy, Component)]
+pub struct DependentTerrainView {
+    /// The terrain view, whose quadtree and view configs are reused.
+    pub primary: Entity,
+    /// How the tiles of the dependent view are selected.
+    /// Changing it after the view has been rendered once has no effect.
+    pub refinement: DependentRefinement,
+}
+
+impl DependentTerrainView {
+    /// Creates a dependent view, which culls the tiles of the primary view.
+    pub fn new(primary: Entity) -> Self {
+        Self {
+            primary,
+            refinement: default(),
+        }
+    }
+}
+
+/// How a [`DependentTerrainView`] selects its tiles in
+/// [`TerrainRenderMode::Compute`](crate::render::downlevel::TerrainRenderMode::Compute).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DependentRefinement {
+    /// Culls the final tiles of the primary view against the frustum of the dependent view.
+    /// This is the cheapest option, but only tiles inside the frustum of the primary view are
+    /// visible (e.g. sufficient for water reflections).
+    #[default]
+    CullPrimaryTiles,
+    /// Refines the quadtree of the primary view against the frustum of the dependent view,
+    /// so that the terrain outside of the frustum of the primary view is visible as well
+    /// (e.g. for shadow maps).
+    Refine,
+}
+
+/// Follows a chain of dependent views to the view, which refines its own quadtree.
+///
+/// The `primary` function returns the primary view of a dependent view.
+/// Returns `None`, if the chain is cyclic.
+pub(crate) fn root_primary_view(
+    view: Entity,
+    primary: impl Fn(Entity) -> Option<Entity>,
+) -> Option<Entity> {
+    let mut chain = vec![view];
+
+    while let Some(next) = primary(*chain.last().unwrap()) {
+        if chain.contains(&next) {
+            return None;
+        }
+
+        chain.push(next);
+    }
+
+    chain.last().copied()
+}
+
+/// Whether the seabed of the bathymetry attachment is rendered below the sea level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BathymetryMode {
+    /// Renders only the topography (e.g. with a flat surface at the sea level).
+    #[default]
+    Topography,
+    /// Replaces the topography below the sea level with the seabed and tints it by its depth.
+    TopoBathy,
+}
+
+/// The order, in which the vertices of the tile grid are drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PatchTopology {
+    /// Draws all tiles as a single non-indexed triangle strip,
+    /// whose rows are connected by degenerate triangles.
+    /// Each inner vertex of the grid is processed twice.
+    #[default]
+    Strip,
+    /// Draws each tile as an instance of an indexed triangle strip, whose columns are separated
+    /// by primitive restarts.
+    /// Vertices shared by adjacent columns are reused from the vertex cache.
+    /// The minmax debug view is not supported by this topology.
+    IndexedStrip,
+}
+
 /// The configuration of a terrain view.
 ///
 /// A terrain view describes the quality settings the corresponding terrain will be rendered with.
@@ -55,8 +160,32 @@ pub struct TerrainViewConfig {
     pub quadtree_handle: Handle<Image>,
     /// The current height under the viewer.
     pub height_under_viewer: f32,
+    /// The position from which the level of detail is determined.
+    ///
+    /// For perspective views this is the camera position. Orthographic views have a constant
+    /// screen-space scale, so a virtual viewer is placed behind the point the camera looks at,
+    /// at the distance from which a perspective view would cover the same area.
+    pub viewer_position: Vec3,
+    /// The offset of the render space of the view to the terrain in double precision.
+    ///
+    /// Earth-scale scenes keep the camera close to the render origin (a floating origin) and
+    /// move this offset instead, since the `viewer_position` is only single precision.
+    /// The quadtree selects the nodes from the sum of both in double precision.
+    pub viewer_origin: DVec3,
+    /// The current smoothed velocity of the viewer position in units per second.
+    pub viewer_velocity: Vec3,
     /// The distance (measured in multiples of the node size) until which to request nodes to be loaded.
     pub load_distance: f32,
+    /// The time in seconds, over which the viewer position is extrapolated by its velocity,
+    /// to prefetch the nodes along the predicted motion. Zero disables prefetching.
+    ///
+    /// The prefetched nodes only occupy unused slots of the node atlas and are evicted,
+    /// whenever a view requires these slots for its own nodes.
+    pub prefetch_horizon: f32,
+    /// The distance (measured in multiples of the node size) around the predicted viewer
+    /// position, until which to prefetch nodes.
+    /// This should be smaller than the `load_distance`, to only prefetch the coarser nodes.
+    pub prefetch_distance: f32,
     /// The count of nodes in x and y direction per quadtree layer.
     pub node_count: u32,
     /// The size of the tile buffer.
@@ -69,12 +198,27 @@ pub struct TerrainViewConfig {
     pub tile_scale: f32,
     /// The number of rows and columns of the tile grid.
     pub grid_size: u32,
+    /// The order, in which the vertices of the tile grid are drawn.
+    pub patch_topology: PatchTopology,
     /// The distance (measured in multiples of the node size) at which the LOD changes.
     pub view_distance: f32,
     /// The morph percentage of the mesh.
     pub morph_range: f32,
     /// The blend percentage in the vertex and fragment shader.
     pub blend_range: f32,
+    /// The minimum projected edge length of the triangles in pixels.
+    /// Tiles are not subdivided any further, once their triangles would become smaller than this,
+    /// regardless of the refinement heuristic and the `additional_refinement`.
+    pub min_triangle_size: f32,
+    /// The compass direction of the sun used by the hillshade view in degrees,
+    /// measured clockwise from north (negative z).
+    pub sun_azimuth: f32,
+    /// The angle of the sun above the horizon used by the hillshade view in degrees.
+    pub sun_elevation: f32,
+    /// The height difference in world units, at which the height difference view is fully saturated.
+    pub difference_range: f32,
+    /// Whether the seabed of the bathymetry attachment is rendered, if the terrain has one.
+    pub bathymetry_mode: BathymetryMode,
 }
 
 impl Default for TerrainViewConfig {
@@ -86,16 +230,120 @@ impl Default for TerrainViewConfig {
             )
             .typed(), // Todo: fix this awful hack
             height_under_viewer: 0.0,
+            viewer_position: Vec3::ZERO,
+            viewer_origin: DVec3::ZERO,
+            viewer_velocity: Vec3::ZERO,
             load_distance: 5.0,
+            prefetch_horizon: 0.0,
+            prefetch_distance: 2.0,
             node_count: 10,
             tile_count: 1000000,
             refinement_count: 20,
             additional_refinement: 0,
             tile_scale: 32.0,
             grid_size: 8,
+            patch_topology: PatchTopology::Strip,
             view_distance: 4.0,
             morph_range: 0.2,
             blend_range: 0.2,
+            min_triangle_size: 2.0,
+            sun_azimuth: 315.0,
+            sun_elevation: 45.0,
+            difference_range: 10.0,
+            bathymetry_mode: default(),
         }
     }
 }
+
+impl TerrainViewConfig {
+    /// Returns the position of the viewer relative to the terrain in double precision.
+    pub fn global_viewer_position(&self) -> DVec3 {
+        self.viewer_origin + self.viewer_position.as_dvec3()
+    }
+
+    /// Returns the position of the viewer extrapolated by its velocity over the
+    /// `prefetch_horizon`, if prefetching is enabled.
+    pub fn predicted_viewer_position(&self) -> Option<Vec3> {
+        (self.prefetch_horizon > 0.0)
+            .then(|| self.viewer_position + self.viewer_velocity * self.prefetch_horizon)
+    }
+}
+
+/// Computes the position from which the level of detail of a view is determined.
+///
+/// See [`TerrainViewConfig::viewer_position`].
+pub(crate) fn viewer_position(
+    transform: &GlobalTransform,
+    projection: Option<&Projection>,
+    height_under_viewer: f32,
+) -> Vec3 {
+    let position = transform.translation();
+
+    let projection = match projection {
+        Some(Projection::Orthographic(projection)) => projection,
+        _ => return position,
+    };
+
+    let forward = transform.forward();
+    let area_height = (projection.top - projection.bottom) * projection.scale;
+
+    // the point on the terrain the camera looks at
+    let focus = if forward.y < -0.001 {
+        position + forward * ((position.y - height_under_viewer) / -forward.y).max(0.0)
+    } else {
+        position
+    };
+
+    focus - forward * area_height / (2.0 * (ORTHOGRAPHIC_REFERENCE_FOV / 2.0).tan())
+}
+
+/// Copies the view configs of the primary views to their dependent views.
+///
+/// Chains of dependent views are resolved to the view at their end.
+pub(crate) fn update_dependent_views(
+    mut cyclic_views: Local<HashSet<Entity>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+    view_query: Query<(Entity, &DependentTerrainView), With<TerrainView>>,
+) {
+    for (view, _) in view_query.iter() {
+        view_configs.remove_view(view);
+
+        let primary = match root_primary_view(view, |view| {
+            view_query
+                .get(view)
+                .ok()
+                .map(|(_, dependency)| dependency.primary)
+        }) {
+            Some(primary) => primary,
+            None => {
+                if cyclic_views.insert(view) {
+                    warn!("The terrain view {view:?} is part of a cycle of dependent views, thus it is not rendered.");
+                }
+                continue;
+            }
+        };
+
+        let primary_configs = view_configs
+            .0
+            .iter()
+            .filter(|(&(_, other), _)| other == primary)
+            .map(|(&(terrain, _), view_config)| ((terrain, view), view_config.clone()))
+            .collect::<Vec<_>>();
+
+        view_configs.0.extend(primary_configs);
+    }
+}
+
+/// Removes the quadtrees and view configs of despawned terrain views.
+///
+/// The render world releases the corresponding GPU resources, once the entries are gone.
+pub(crate) fn remove_terrain_views(
+    removed_views: RemovedComponents<TerrainView>,
+    mut quadtrees: ResMut<TerrainViewComponents<Quadtree>>,
+    mut view_configs: ResMut<TerrainViewComponents<TerrainViewConfig>>,
+) {
+    for view in removed_views.iter() {
+        quadtrees.remove_view(view);
+        view_configs.remove_view(view);
+    }
+}
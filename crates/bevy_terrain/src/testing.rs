@@ -0,0 +1,345 @@
+//! A harness for integration tests of the terrain streaming, which runs without a window or a GPU.
+//!
+//! A [`SyntheticTerrain`] procedurally generates a tiny deterministic dataset at test time and
+//! preprocesses it into a temporary directory.
+//! A [`TerrainTestApp`] then spins up a headless app with the [`TerrainPlugin`], moves its view
+//! along a scripted path and exposes the residency of the node atlas and the requests of the
+//! quadtree for assertions.
+//!
+//! Without a renderer only the CPU side of the plugin (quadtree, node atlas and loaders) runs,
+//! which is exactly the streaming logic, that should be covered by CI.
+//! The tiles of the view are refined on the CPU, like in the downlevel mode.
+//!
+//! The harness is only available with the `testing` feature.
+
+use crate::{
+    attachment_loader::AttachmentFromDiskLoader,
+    error::{TerrainError, TerrainResult},
+    formats::streaming_state::StreamingState,
+    noise::{sample_layers, NoiseLayer},
+    preprocess::{config::load_node_config, BaseConfig, Preprocessor, R16Image, TileConfig},
+    render::downlevel::refine_view,
+    terrain::TerrainConfig,
+    terrain_data::{
+        node_atlas::{LoadingState, NodeAtlas, NodeAtlasStatistics},
+        quadtree::Quadtree,
+        FileFormat, NodeId,
+    },
+    terrain_view::{TerrainView, TerrainViewComponents, TerrainViewConfig},
+    TerrainBundle, TerrainPlugin,
+};
+use bevy::{
+    asset::AssetPlugin,
+    prelude::*,
+    render::{camera::CameraProjection, texture::ImagePlugin},
+};
+use image::Luma;
+use std::{env, fs, path::PathBuf, thread, time::Duration};
+
+/// A tiny terrain, whose height data is generated from noise layers.
+///
+/// The same parameters always produce the same dataset.
+#[derive(Clone, Debug)]
+pub struct SyntheticTerrain {
+    /// The absolute path of the terrain directory.
+    pub path: String,
+    /// The size of the terrain in pixels.
+    pub terrain_size: u32,
+    /// The size of the node textures, including the border.
+    pub texture_size: u32,
+    /// The count of level of detail layers.
+    pub lod_count: u32,
+    /// The maximum height of the terrain.
+    pub height: f32,
+    /// The amount of nodes the can be loaded simultaneously in the node atlas.
+    pub node_atlas_size: u32,
+    /// The noise layers, which are combined into the terrain height.
+    pub layers: Vec<NoiseLayer>,
+}
+
+impl SyntheticTerrain {
+    /// Creates a terrain of 4x4 leaf nodes with three lods inside the temporary directory.
+    ///
+    /// Each test should use a unique name, since the tests run in parallel.
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join("bevy_terrain_fixtures").join(name);
+
+        Self {
+            path: path.to_string_lossy().into_owned(),
+            terrain_size: 256,
+            texture_size: 68,
+            lod_count: 3,
+            height: 100.0,
+            node_atlas_size: 64,
+            layers: vec![NoiseLayer {
+                frequency: 0.02,
+                octaves: 4,
+                ..default()
+            }],
+        }
+    }
+
+    /// Returns the configuration of the base attachment.
+    pub fn base(&self) -> BaseConfig {
+        BaseConfig::new(self.texture_size, 1)
+    }
+
+    /// Returns the size of the leaf nodes (with lod 0).
+    pub fn leaf_node_size(&self) -> u32 {
+        self.base().height_attachment().center_size
+    }
+
+    /// Writes the source tile, preprocesses it and loads the node config.
+    ///
+    /// Returns the config and the loader of the terrain, ready to be spawned.
+    pub fn generate(&self) -> TerrainResult<(TerrainConfig, AttachmentFromDiskLoader)> {
+        let source_path = PathBuf::from(&self.path).join("source");
+        let tile_path = source_path.join("height.png");
+
+        let _ = fs::remove_dir_all(&self.path);
+        fs::create_dir_all(&source_path)
+            .map_err(TerrainError::io(source_path.to_string_lossy()))?;
+
+        let tile = R16Image::from_fn(self.terrain_size, self.terrain_size, |x, y| {
+            let height = sample_layers(&self.layers, Vec2::new(x as f32, y as f32));
+
+            Luma([(height * u16::MAX as f32) as u16])
+        });
+
+        tile.save(&tile_path)
+            .map_err(|error| TerrainError::malformed(tile_path.to_string_lossy(), error))?;
+
+        let mut preprocessor = Preprocessor::default();
+        let mut loader = AttachmentFromDiskLoader::default();
+        let mut config = TerrainConfig::new(
+            self.terrain_size,
+            self.lod_count,
+            self.height,
+            self.node_atlas_size,
+            self.path.clone(),
+        );
+
+        config.add_base_attachment_from_disk(
+            &mut preprocessor,
+            &mut loader,
+            self.base(),
+            TileConfig {
+                path: tile_path.to_string_lossy().into_owned(),
+                size: self.terrain_size,
+                file_format: FileFormat::PNG,
+                ..default()
+            },
+        );
+
+        preprocessor.preprocess(&config)?;
+        load_node_config(&mut config)?;
+
+        Ok((config, loader))
+    }
+}
+
+/// Creates an app with the [`TerrainPlugin`], which runs without a window and without a renderer.
+pub fn headless_terrain_app() -> App {
+    let mut app = App::new();
+
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(ImagePlugin::default())
+        .add_asset::<Shader>()
+        .add_plugin(TerrainPlugin);
+
+    app
+}
+
+/// The height of the viewport in pixels, for which the tiles of the view are refined.
+const VIEWPORT_HEIGHT: f32 = 1080.0;
+
+/// A headless app streaming a single terrain for a single view.
+pub struct TerrainTestApp {
+    pub app: App,
+    pub terrain: Entity,
+    pub view: Entity,
+}
+
+impl TerrainTestApp {
+    /// Spawns the terrain and a view at the origin inside a [`headless_terrain_app`].
+    pub fn new(
+        config: TerrainConfig,
+        loader: AttachmentFromDiskLoader,
+        view_config: TerrainViewConfig,
+    ) -> Self {
+        let mut app = headless_terrain_app();
+
+        let terrain = app
+            .world
+            .spawn((TerrainBundle::new(config.clone()), loader))
+            .id();
+        let view = app
+            .world
+            .spawn((TerrainView, TransformBundle::default()))
+            .id();
+
+        let quadtree = Quadtree::from_configs(&config, &view_config);
+        app.world
+            .resource_mut::<TerrainViewComponents<TerrainViewConfig>>()
+            .insert((terrain, view), view_config);
+        app.world
+            .resource_mut::<TerrainViewComponents<Quadtree>>()
+            .insert((terrain, view), quadtree);
+
+        Self { app, terrain, view }
+    }
+
+    /// Moves the view to the position.
+    pub fn move_view(&mut self, position: Vec3) {
+        self.app
+            .world
+            .get_mut::<Transform>(self.view)
+            .unwrap()
+            .translation = position;
+    }
+
+    /// Moves the view to the position and turns it towards the target.
+    pub fn look_at(&mut self, position: Vec3, target: Vec3, up: Vec3) {
+        *self.app.world.get_mut::<Transform>(self.view).unwrap() =
+            Transform::from_translation(position).looking_at(target, up);
+    }
+
+    /// Runs a single frame.
+    pub fn update(&mut self) {
+        self.app.update();
+    }
+
+    /// Runs frames, until all requested nodes have finished loading.
+    ///
+    /// Since the nodes are loaded asynchronously, the frames are spaced out a little.
+    /// Returns whether or not the node atlas settled within the maximum amount of frames.
+    pub fn run_until_loaded(&mut self, max_frames: u32) -> bool {
+        for _ in 0..max_frames {
+            self.update();
+
+            if self.node_atlas().loading_nodes.is_empty() {
+                return true;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        false
+    }
+
+    /// Returns the node atlas of the terrain.
+    pub fn node_atlas(&self) -> &NodeAtlas {
+        self.app.world.get::<NodeAtlas>(self.terrain).unwrap()
+    }
+
+    /// Returns the current occupancy of the slots of the node atlas.
+    pub fn statistics(&self) -> NodeAtlasStatistics {
+        self.node_atlas().statistics()
+    }
+
+    /// Returns whether or not the node is loaded into the node atlas.
+    pub fn is_resident(&self, node_id: NodeId) -> bool {
+        self.node_atlas()
+            .nodes
+            .get(&node_id)
+            .map_or(false, |node| node.state == LoadingState::Loaded)
+    }
+
+    /// Returns whether or not the node is loading or loaded in the node atlas.
+    pub fn is_present(&self, node_id: NodeId) -> bool {
+        self.node_atlas().nodes.contains_key(&node_id)
+    }
+
+    /// Returns the loaded nodes of the node atlas in ascending order.
+    pub fn resident_nodes(&self) -> Vec<NodeId> {
+        let mut nodes: Vec<NodeId> = self
+            .node_atlas()
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.state == LoadingState::Loaded)
+            .map(|(&node_id, _)| node_id)
+            .collect();
+
+        nodes.sort_unstable();
+        nodes
+    }
+
+    /// Returns the quadtree of the view.
+    pub fn quadtree(&self) -> &Quadtree {
+        self.app
+            .world
+            .resource::<TerrainViewComponents<Quadtree>>()
+            .get(&(self.terrain, self.view))
+            .unwrap()
+    }
+
+    /// Returns the quadtree of the view mutably.
+    pub fn quadtree_mut(&mut self) -> &mut Quadtree {
+        let key = (self.terrain, self.view);
+
+        self.app
+            .world
+            .resource_mut::<TerrainViewComponents<Quadtree>>()
+            .into_inner()
+            .get_mut(&key)
+            .unwrap()
+    }
+
+    /// Samples the height of the terrain at the position, using the quadtree of the view.
+    pub fn sample_height(&self, position: Vec2) -> Option<f32> {
+        let images = self.app.world.resource::<Assets<Image>>();
+
+        self.quadtree()
+            .sample_height(self.node_atlas(), images, position)
+    }
+
+    /// Returns the view config of the view.
+    pub fn view_config(&self) -> &TerrainViewConfig {
+        self.app
+            .world
+            .resource::<TerrainViewComponents<TerrainViewConfig>>()
+            .get(&(self.terrain, self.view))
+            .unwrap()
+    }
+
+    /// Refines the tiles of the view on the CPU, like the downlevel mode does.
+    ///
+    /// The view is treated as a camera with the default perspective projection and a square
+    /// viewport. Each tile is drawn as a single patch, so the tiles are the patches of the view.
+    /// Returns the coordinates and the size of each tile.
+    pub fn refine_tiles(&self) -> Vec<UVec3> {
+        let config = self.app.world.get::<TerrainConfig>(self.terrain).unwrap();
+        let transform = self.app.world.get::<GlobalTransform>(self.view).unwrap();
+
+        let projection = PerspectiveProjection::default().get_projection_matrix();
+        let view_proj = projection * transform.compute_matrix().inverse();
+        let pixel_scale = VIEWPORT_HEIGHT / 2.0 * projection.y_axis.y;
+
+        refine_view(config, self.view_config(), view_proj, pixel_scale)
+            .into_iter()
+            .map(|[x, y, size, _]| UVec3::new(x, y, size))
+            .collect()
+    }
+
+    /// Returns the nodes, which are requested by the quadtree of the view and loaded.
+    pub fn streaming_state(&self) -> StreamingState {
+        self.quadtree().streaming_state(self.node_atlas())
+    }
+
+    /// Returns the amount of nodes, that are requested by the quadtree of the view, for each lod.
+    pub fn requested_node_counts(&self) -> Vec<u32> {
+        let quadtree = self.quadtree();
+
+        (0..quadtree.lod_count)
+            .map(|lod| {
+                quadtree
+                    .layer_states(lod)
+                    .filter(|&(_, requested, _)| requested)
+                    .count() as u32
+            })
+            .collect()
+    }
+}
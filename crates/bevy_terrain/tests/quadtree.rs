@@ -0,0 +1,175 @@
+use bevy::{
+    math::DVec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use bevy_terrain::{
+    prelude::*,
+    render::culling::planes,
+    terrain_data::{calc_node_id, NodeCoordinate, NodeId},
+};
+
+const LOD_COUNT: u32 = 5;
+const NODE_COUNT: u32 = 8;
+const LEAF_NODE_SIZE: u32 = 64;
+const HEIGHT: f32 = 200.0;
+
+fn quadtree(load_distance: f32) -> Quadtree {
+    Quadtree::new(
+        Handle::default(),
+        LOD_COUNT,
+        NODE_COUNT,
+        LEAF_NODE_SIZE,
+        load_distance,
+        HEIGHT,
+    )
+}
+
+/// All nodes of a square terrain with a side length of `node_count` leaf nodes.
+fn coverage(node_count: u32) -> HashSet<NodeId> {
+    (0..LOD_COUNT)
+        .flat_map(|lod| {
+            let count = (node_count >> lod).max(1);
+
+            (0..count).flat_map(move |x| (0..count).map(move |y| calc_node_id(lod, x, y)))
+        })
+        .collect()
+}
+
+fn parent(node_id: NodeId) -> NodeId {
+    let coordinate = NodeCoordinate::from(node_id);
+
+    calc_node_id(coordinate.lod + 1, coordinate.x >> 1, coordinate.y >> 1)
+}
+
+/// A deterministic pseudo random walk over the terrain.
+fn camera_path(seed: u64, steps: usize, extent: f64) -> Vec<DVec3> {
+    let mut state = seed;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+
+    (0..steps)
+        .map(|_| DVec3::new(next() * extent, next() * HEIGHT as f64, next() * extent))
+        .collect()
+}
+
+/// Applies the requests to the set of requested nodes and checks that no node is requested
+/// or released twice.
+fn apply(requested: &mut HashSet<NodeId>, requests: NodeRequests) {
+    let unique: HashSet<NodeId> = requests.requested.iter().copied().collect();
+    assert_eq!(unique.len(), requests.requested.len(), "duplicate requests");
+
+    for node_id in requests.released {
+        assert!(requested.remove(&node_id), "released a node twice");
+    }
+
+    for node_id in requests.requested {
+        assert!(requested.insert(node_id), "requested a node twice");
+    }
+}
+
+#[test]
+fn deterministic_traversal() {
+    let coverage = coverage(32);
+
+    let run = || {
+        let mut quadtree = quadtree(2.0);
+
+        camera_path(7, 50, 32.0 * LEAF_NODE_SIZE as f64)
+            .into_iter()
+            .map(|position| quadtree.update_for_view(position, None, &DistanceHeuristic, &coverage))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(run(), run());
+}
+
+#[test]
+fn requests_are_unique_and_monotonic() {
+    let coverage = coverage(32);
+
+    for (seed, load_distance) in [(1, 1.0), (2, 2.0), (3, 4.0)] {
+        let mut quadtree = quadtree(load_distance);
+        let mut requested = HashSet::default();
+
+        for position in camera_path(seed, 200, 32.0 * LEAF_NODE_SIZE as f64) {
+            let requests = quadtree.update_for_view(position, None, &DistanceHeuristic, &coverage);
+            apply(&mut requested, requests);
+
+            let coarsest = requested
+                .iter()
+                .filter(|&&node_id| NodeCoordinate::from(node_id).lod == LOD_COUNT - 1)
+                .count();
+            assert!(coarsest > 0, "the coarsest lod is always requested");
+
+            // a node is only requested, if its parent is requested as well
+            for &node_id in &requested {
+                assert!(coverage.contains(&node_id), "requested an uncovered node");
+
+                if NodeCoordinate::from(node_id).lod < LOD_COUNT - 1 {
+                    assert!(
+                        requested.contains(&parent(node_id)),
+                        "requested a node without its parent"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn never_requests_uncovered_nodes() {
+    // a sparse terrain, that only covers the first leaf node and its parents
+    let coverage: HashSet<NodeId> = (0..LOD_COUNT).map(|lod| calc_node_id(lod, 0, 0)).collect();
+    let mut quadtree = quadtree(4.0);
+    let mut requested = HashSet::default();
+
+    for position in camera_path(4, 50, 8.0 * LEAF_NODE_SIZE as f64) {
+        apply(
+            &mut requested,
+            quadtree.update_for_view(position, None, &DistanceHeuristic, &coverage),
+        );
+
+        assert!(requested.is_subset(&coverage));
+    }
+}
+
+#[test]
+fn frustum_limits_requests() {
+    let coverage = coverage(32);
+    let position = DVec3::new(16.0, 0.5, 16.0) * LEAF_NODE_SIZE as f64;
+
+    // looking along the negative x axis
+    let view = Transform::from_translation(position.as_vec3())
+        .looking_at(position.as_vec3() - Vec3::X, Vec3::Y);
+    let projection = Mat4::perspective_infinite_reverse_rh(45f32.to_radians(), 1.0, 0.1);
+    let frustum = planes(&(projection * view.compute_matrix().inverse()));
+
+    let mut culled = quadtree(4.0);
+    let mut unculled = quadtree(4.0);
+
+    let culled_requests =
+        culled.update_for_view(position, Some(&frustum), &DistanceHeuristic, &coverage);
+    let unculled_requests = unculled.update_for_view(position, None, &DistanceHeuristic, &coverage);
+
+    assert!(culled_requests.requested.len() < unculled_requests.requested.len());
+
+    let mut counts = HashMap::<u32, usize>::default();
+
+    for &node_id in &culled_requests.requested {
+        let coordinate = NodeCoordinate::from(node_id);
+        *counts.entry(coordinate.lod).or_default() += 1;
+
+        // no fine nodes behind the viewer
+        if coordinate.lod < LOD_COUNT - 1 {
+            let node_min = coordinate.x as f64 * (LEAF_NODE_SIZE << coordinate.lod) as f64;
+            assert!(node_min <= position.x);
+        }
+    }
+
+    assert!(counts.get(&(LOD_COUNT - 1)).copied().unwrap_or(0) > 0);
+}
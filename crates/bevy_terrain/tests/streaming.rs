@@ -0,0 +1,350 @@
+use bevy::prelude::*;
+use bevy_terrain::{
+    prelude::*,
+    terrain_data::calc_node_id,
+    testing::{SyntheticTerrain, TerrainTestApp},
+};
+
+const MAX_FRAMES: u32 = 500;
+
+fn view_config() -> TerrainViewConfig {
+    TerrainViewConfig {
+        node_count: 4,
+        load_distance: 1.5,
+        ..default()
+    }
+}
+
+#[test]
+fn preprocesses_all_nodes() {
+    let terrain = SyntheticTerrain::new("preprocesses_all_nodes");
+    let (config, _) = terrain.generate().expect("Could not generate the terrain.");
+
+    // 4x4 + 2x2 + 1x1 nodes
+    assert_eq!(config.nodes.len(), 21);
+    assert_eq!(config.leaf_node_size, terrain.leaf_node_size());
+
+    for lod in 0..terrain.lod_count {
+        let node_count = terrain.terrain_size / (terrain.leaf_node_size() << lod);
+
+        for (x, y) in (0..node_count).flat_map(|x| (0..node_count).map(move |y| (x, y))) {
+            assert!(config.nodes.contains(&calc_node_id(lod, x, y)));
+        }
+    }
+}
+
+#[test]
+fn streams_nodes_along_camera_move() {
+    let terrain = SyntheticTerrain::new("streams_nodes_along_camera_move");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let leaf_node_size = terrain.leaf_node_size() as f32;
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+
+    // start above the first leaf node
+    app.move_view(Vec3::new(0.5, 0.5, 0.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+
+    let requested = app.requested_node_counts();
+    let statistics = app.statistics();
+
+    assert_eq!(requested[2], 1, "the coarsest lod is always requested");
+    assert_eq!(statistics.used, requested.iter().sum::<u32>());
+    assert_eq!(statistics.loading, 0);
+    assert_eq!(app.resident_nodes().len() as u32, statistics.used);
+    assert!(app.is_resident(calc_node_id(0, 0, 0)));
+    assert!(!app.is_resident(calc_node_id(0, 3, 3)));
+
+    // move above the opposite leaf node
+    app.move_view(Vec3::new(3.5, 0.5, 3.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+
+    let requested = app.requested_node_counts();
+    let statistics = app.statistics();
+
+    assert_eq!(statistics.used, requested.iter().sum::<u32>());
+    assert!(statistics.cached > 0, "released nodes stay cached");
+    assert!(app.is_resident(calc_node_id(0, 3, 3)));
+    assert!(app.is_resident(calc_node_id(2, 0, 0)));
+}
+
+#[test]
+fn samples_heights_off_the_diagonal() {
+    let terrain = SyntheticTerrain::new("samples_heights_off_the_diagonal");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let leaf_node_size = terrain.leaf_node_size() as f32;
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+
+    // the x and y coordinates differ, so that swapped quadtree indices miss the leaf nodes
+    for position in [
+        Vec2::new(3.5, 0.5),
+        Vec2::new(0.5, 2.5),
+        Vec2::new(1.5, 3.5),
+    ] {
+        let position = position * leaf_node_size;
+
+        app.move_view(Vec3::new(position.x, 0.5 * leaf_node_size, position.y));
+        assert!(app.run_until_loaded(MAX_FRAMES));
+        app.update();
+
+        assert_eq!(app.quadtree().loaded_lod(position), Some(0));
+
+        let height = app
+            .sample_height(position)
+            .expect("The height is not available.");
+        assert!((0.0..=terrain.height).contains(&height));
+    }
+
+    // positions outside of the terrain do not saturate to its border nodes
+    let outside = terrain.terrain_size as f32 + leaf_node_size;
+
+    for position in [
+        Vec2::new(-1.0, 0.5 * leaf_node_size),
+        Vec2::new(0.5 * leaf_node_size, -1.0),
+        Vec2::new(outside, 0.5 * leaf_node_size),
+        Vec2::splat(outside),
+    ] {
+        assert_eq!(app.sample_height(position), None);
+    }
+}
+
+/// Returns whether the tiles overlap each other.
+fn overlapping_tiles(tiles: &[UVec3]) -> bool {
+    tiles.iter().enumerate().any(|(i, a)| {
+        tiles[i + 1..].iter().any(|b| {
+            let (a_min, a_max) = (a.truncate() * a.z, (a.truncate() + 1) * a.z);
+            let (b_min, b_max) = (b.truncate() * b.z, (b.truncate() + 1) * b.z);
+
+            a_min.cmplt(b_max).all() && b_min.cmplt(a_max).all()
+        })
+    })
+}
+
+#[test]
+fn refines_patches_for_the_view() {
+    let terrain = SyntheticTerrain::new("refines_patches_for_the_view");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let terrain_size = terrain.terrain_size as f32;
+    let center = Vec3::new(0.5, 0.0, 0.5) * terrain_size;
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    let tile_scale = app.view_config().tile_scale;
+
+    let refine_from = |app: &mut TerrainTestApp, height: f32| {
+        // look straight down at the center of the terrain
+        app.look_at(center + Vec3::Y * height, center, Vec3::NEG_Z);
+        assert!(app.run_until_loaded(MAX_FRAMES));
+        app.update();
+
+        let tiles = app.refine_tiles();
+
+        assert!(!tiles.is_empty(), "the terrain below the view is drawn");
+        assert!(tiles.len() as u32 <= app.view_config().tile_count);
+        assert!(!overlapping_tiles(&tiles), "each patch is drawn once");
+        assert!(tiles.iter().all(|tile| {
+            (tile.truncate() * tile.z).as_vec2().max_element() * tile_scale <= terrain_size
+        }));
+
+        tiles
+    };
+
+    let close = refine_from(&mut app, 1.5 * terrain.height);
+    let far = refine_from(&mut app, 6.0 * terrain.height);
+
+    let finest_size = |tiles: &[UVec3]| tiles.iter().map(|tile| tile.z).min().unwrap();
+    assert_eq!(
+        finest_size(&close),
+        1,
+        "the patches below the view are refined fully"
+    );
+    assert!(finest_size(&far) > 1, "distant patches stay coarse");
+
+    // the amount of patches is capped by the tile count of the view
+    let key = (app.terrain, app.view);
+    app.app
+        .world
+        .resource_mut::<TerrainViewComponents<TerrainViewConfig>>()
+        .get_mut(&key)
+        .unwrap()
+        .tile_count = 2;
+    assert!(close.len() > 2);
+    assert_eq!(refine_from(&mut app, 1.5 * terrain.height).len(), 2);
+}
+
+#[test]
+fn sends_loading_events() {
+    let terrain = SyntheticTerrain::new("sends_loading_events");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    let mut reader = app
+        .app
+        .world
+        .resource::<Events<TerrainEvent>>()
+        .get_reader();
+    let mut loaded_nodes = Vec::new();
+    let mut completed = 0;
+
+    for _ in 0..MAX_FRAMES {
+        app.update();
+
+        for event in reader.iter(app.app.world.resource::<Events<TerrainEvent>>()) {
+            match *event {
+                TerrainEvent::NodeLoaded { terrain, node_id } => {
+                    assert_eq!(terrain, app.terrain);
+                    loaded_nodes.push(node_id);
+                }
+                TerrainEvent::InitialLoadComplete { terrain, view } => {
+                    assert_eq!((terrain, view), (app.terrain, app.view));
+                    completed += 1;
+                }
+            }
+        }
+
+        if completed > 0 && app.statistics().loading == 0 {
+            break;
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    assert_eq!(completed, 1, "the initial load completes exactly once");
+
+    loaded_nodes.sort_unstable();
+    assert_eq!(loaded_nodes, app.resident_nodes());
+}
+
+#[test]
+fn resumes_streaming_state() {
+    let terrain = SyntheticTerrain::new("resumes_streaming_state");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let position = Vec3::new(0.5, 0.5, 0.5) * terrain.leaf_node_size() as f32;
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    app.move_view(position);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+
+    let state = app.streaming_state();
+    let encoded = state.encode_alloc().unwrap();
+    assert_eq!(StreamingState::decode_alloc(&encoded).unwrap(), state);
+    assert!(StreamingState::decode_alloc(&encoded[4..]).is_err());
+
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    app.move_view(position);
+    app.quadtree_mut().warm_start(&state);
+    app.update();
+
+    // all nodes of the saved state start loading in the first frame
+    for &node_id in &state.nodes {
+        assert!(app.is_present(node_id));
+    }
+
+    assert!(app.run_until_loaded(MAX_FRAMES));
+    assert_eq!(app.streaming_state(), state);
+}
+
+#[test]
+fn warm_start_never_pins_the_node_atlas() {
+    let mut terrain = SyntheticTerrain::new("warm_start_never_pins_the_node_atlas");
+    terrain.node_atlas_size = 16;
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let leaf_node_size = terrain.leaf_node_size() as f32;
+
+    // a state containing all nodes of the terrain does not fit into the node atlas
+    let state = StreamingState {
+        nodes: config.nodes.iter().copied().collect(),
+    };
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    app.quadtree_mut().warm_start(&state);
+    app.move_view(Vec3::new(0.5, 0.5, 0.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+
+    // the cached nodes of the warm start are evicted for the nodes required by the view
+    app.move_view(Vec3::new(3.5, 0.5, 3.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+
+    let requested = app.requested_node_counts();
+    let statistics = app.statistics();
+
+    assert_eq!(statistics.used, requested.iter().sum::<u32>());
+    assert!(app.is_resident(calc_node_id(0, 3, 3)));
+}
+
+#[test]
+fn streams_mesh_inserts_with_leaf_nodes() {
+    let terrain = SyntheticTerrain::new("streams_mesh_inserts_with_leaf_nodes");
+    let (mut config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let leaf_node_size = terrain.leaf_node_size() as f32;
+
+    // headless apps do not carve the mask, so any attachment provides the node layout
+    config.mask_attachment = Some(0);
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    let insert = app
+        .app
+        .world
+        .spawn((
+            TerrainMeshInsert::new(
+                app.terrain,
+                Vec2::splat(0.25 * leaf_node_size),
+                Vec2::splat(0.75 * leaf_node_size),
+            ),
+            Visibility::default(),
+        ))
+        .id();
+    let is_visible =
+        |app: &TerrainTestApp| app.app.world.get::<Visibility>(insert).unwrap().is_visible;
+
+    // start above the leaf node of the insert
+    app.move_view(Vec3::new(0.5, 0.5, 0.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+    app.update();
+    assert!(is_visible(&app));
+
+    // the leaf node is released, once the view has moved away
+    app.move_view(Vec3::new(3.5, 0.5, 3.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+    app.update();
+    assert!(!is_visible(&app));
+}
+
+#[test]
+fn mirrors_requested_leaf_nodes_as_entities() {
+    let terrain = SyntheticTerrain::new("mirrors_requested_leaf_nodes_as_entities");
+    let (config, loader) = terrain.generate().expect("Could not generate the terrain.");
+    let leaf_node_size = terrain.leaf_node_size() as f32;
+
+    let mut app = TerrainTestApp::new(config, loader, view_config());
+    app.app
+        .world
+        .entity_mut(app.terrain)
+        .insert(TerrainNodeEntities::default());
+
+    let node_entity = |app: &TerrainTestApp| {
+        app.app
+            .world
+            .get::<TerrainNodeEntities>(app.terrain)
+            .unwrap()
+            .get(calc_node_id(0, 0, 0))
+    };
+
+    app.move_view(Vec3::new(0.5, 0.5, 0.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+    app.update();
+
+    let entity = node_entity(&app).expect("The leaf node has no entity.");
+    let node = app.app.world.get::<TerrainNode>(entity).unwrap();
+    assert_eq!(node.coordinate, UVec2::ZERO);
+    assert_eq!(node.aabb.min().x, 0.0);
+    assert_eq!(node.aabb.max().x, leaf_node_size);
+
+    // the entity is despawned, once the view has moved away
+    app.move_view(Vec3::new(3.5, 0.5, 3.5) * leaf_node_size);
+    assert!(app.run_until_loaded(MAX_FRAMES));
+    app.update();
+    assert!(node_entity(&app).is_none());
+    assert!(app.app.world.get_entity(entity).is_none());
+}
@@ -111,6 +111,24 @@ impl TryFrom<TerrainSettings> for Settings {
 }
 
 pub fn load_settings() -> Result<Settings> {
+    let settings = read_settings()?;
+    let settings = settings.try_into()?;
+
+    Ok(settings)
+}
+
+/// Loads the settings of the terrain with the name, which is stored inside the directory,
+/// instead of the one selected in the config.
+pub fn load_dataset_settings(terrain_dir: &str, name: &str) -> Result<Settings> {
+    let mut settings = read_settings()?;
+    settings.terrain_dir = terrain_dir.to_string();
+    settings.terrain = name.to_string();
+    let settings = settings.try_into()?;
+
+    Ok(settings)
+}
+
+fn read_settings() -> Result<TerrainSettings> {
     let mut path = env::current_exe()?;
     path.pop();
     path.push("config.toml");
@@ -126,8 +144,7 @@ pub fn load_settings() -> Result<Settings> {
 
     let contents = contents?;
 
-    let settings: TerrainSettings = toml::from_str(&contents)?;
-    let settings = settings.try_into()?;
+    let settings = toml::from_str(&contents)?;
 
     Ok(settings)
 }